@@ -0,0 +1,77 @@
+// How fast the CPU runs a small loop of common instructions, both on its
+// own on a flat bus and through the whole emulator.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use snesemu::bus::FlatBus;
+use snesemu::cpu::{Cpu, CpuState};
+use snesemu::emulator::Emulator;
+use snesemu::mmu::MapMode;
+
+// How many instructions each iteration runs.
+const INSTRUCTIONS: u64 = 10_000;
+
+// Sets up native mode and 16-bit registers, then adds, stores, loads,
+// compares and branches over and over.
+#[rustfmt::skip]
+const CODE: [u8; 25] = [
+    0x18,             // CLC
+    0xFB,             // XCE
+    0xC2, 0x30,       // REP #$30
+    0xA2, 0x00, 0x00, // LDX #$0000
+    // loop:
+    0x69, 0x01, 0x00, // ADC #$0001
+    0x9D, 0x00, 0x01, // STA $0100,X
+    0xBD, 0x00, 0x01, // LDA $0100,X
+    0xE8,             // INX
+    0xE8,             // INX
+    0xE0, 0x00, 0x01, // CPX #$0100
+    0xD0, 0xF0,       // BNE loop
+    0x80, 0xEB,       // BRA $8004
+];
+
+fn tick(c: &mut Criterion) {
+    let mut bus = FlatBus::new();
+    bus.load(0x00_8000, &CODE);
+
+    let mut cpu = Cpu::new();
+    cpu.set_state(&CpuState {
+        pc: 0x8000,
+        sp: 0x01FF,
+        emulation: true,
+        ..CpuState::default()
+    });
+
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("tick", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                cpu.tick(&mut bus);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn step(c: &mut Criterion) {
+    let mut rom = vec![0; 0x8000];
+    rom[..CODE.len()].copy_from_slice(&CODE);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+
+    let mut group = c.benchmark_group("emulator");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("step", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                emulator.step();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, tick, step);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+// How fast the Mmu reads memory that's on the page table, against SRAM,
+// which is plain memory too but goes through the full address decoding.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use snesemu::mmu::{MapMode, Mmu};
+
+// How many reads each iteration does.
+const READS: u32 = 4096;
+
+fn read_u8(c: &mut Criterion) {
+    // A 1MB LoROM with 8KB of SRAM.
+    let mut cartridge = vec![0; 0x10_0000];
+    cartridge[0x7FD8] = 3;
+
+    let mut mmu = Mmu::new(cartridge, Some(MapMode::LoRom));
+
+    let mut group = c.benchmark_group("mmu");
+    group.throughput(Throughput::Elements(READS as u64));
+
+    for (name, start) in [("wram", 0x7E_0000), ("rom", 0x80_8000), ("sram", 0x70_0000)] {
+        group.bench_with_input(BenchmarkId::new("read_u8", name), &start, |b, &start| {
+            b.iter(|| {
+                let mut sum = 0u8;
+
+                for i in 0..READS {
+                    sum = sum.wrapping_add(mmu.read_u8(start + i));
+                }
+
+                sum
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, read_u8);
+criterion_main!(benches);
@@ -0,0 +1,70 @@
+// What tracing costs per instruction: a million instructions of a small
+// loop with tracing off, as --trace-mode off runs them, against capturing a
+// record for each and formatting it into one reused line, as stream mode
+// does.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use snesemu::asm::{self, Asm};
+use snesemu::emulator::Emulator;
+use snesemu::mmu::MapMode;
+use snesemu::symbols::Symbols;
+use snesemu::trace::TraceFormat;
+
+// How many instructions each iteration runs.
+const INSTRUCTIONS: u64 = 1_000_000;
+
+// Adds, stores, loads, compares and branches over and over, with 16-bit
+// registers.
+fn emulator() -> Emulator {
+    #[rustfmt::skip]
+    let code = Asm::at(0x8000)
+        .clc().xce().rep(0x30)
+        .label("start").ldx_imm16(0x0000)
+        .label("loop")
+        .adc_imm16(0x0001).sta_abs_x(0x0100).lda_abs_x(0x0100)
+        .inx().inx().cpx_imm16(0x0100).bne("loop")
+        .bra("start")
+        .assemble()
+        .unwrap();
+
+    Emulator::new(asm::lorom(&code), Some(MapMode::LoRom)).unwrap()
+}
+
+fn trace(c: &mut Criterion) {
+    let mut group = c.benchmark_group("trace");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.sample_size(10);
+
+    let mut off = emulator();
+
+    group.bench_function("off", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                off.step();
+            }
+        })
+    });
+
+    let mut stream = emulator();
+    let symbols = Symbols::default();
+    let mut line = String::new();
+
+    group.bench_function("stream", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                let (record, _) = stream.step_traced();
+
+                line.clear();
+                TraceFormat::Default.write(&mut line, &record, &symbols);
+            }
+
+            line.len()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, trace);
+criterion_main!(benches);
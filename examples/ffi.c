@@ -0,0 +1,103 @@
+/*
+ * Drives the core through the C interface, to check that the ABI works:
+ *
+ *   cargo build --features ffi
+ *   cc -Iinclude examples/ffi.c -Ltarget/debug -lsnesemu -o target/ffi
+ *   LD_LIBRARY_PATH=target/debug target/ffi game.sfc
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "snesemu.h"
+
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *file = fopen(path, "rb");
+
+    if (!file) {
+        return NULL;
+    }
+
+    fseek(file, 0, SEEK_END);
+    *len = (size_t)ftell(file);
+    fseek(file, 0, SEEK_SET);
+
+    uint8_t *bytes = malloc(*len);
+
+    if (fread(bytes, 1, *len, file) != *len) {
+        free(bytes);
+        bytes = NULL;
+    }
+
+    fclose(file);
+    return bytes;
+}
+
+static void check(int32_t result, const char *what) {
+    if (result != SNES_OK) {
+        fprintf(stderr, "%s failed: %d\n", what, result);
+        exit(1);
+    }
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <rom>\n", argv[0]);
+        return 1;
+    }
+
+    size_t rom_len;
+    uint8_t *rom = read_file(argv[1], &rom_len);
+
+    if (!rom) {
+        fprintf(stderr, "couldn't read %s\n", argv[1]);
+        return 1;
+    }
+
+    SnesEmulator *snes = snes_create(rom, rom_len);
+    free(rom);
+
+    if (!snes) {
+        fprintf(stderr, "couldn't load %s\n", argv[1]);
+        return 1;
+    }
+
+    check(snes_set_input(snes, 0, 0x1000), "snes_set_input");
+
+    for (int i = 0; i < 60; i++) {
+        check(snes_run_frame(snes), "snes_run_frame");
+    }
+
+    uint32_t width, height;
+    const uint8_t *pixels = snes_framebuffer(snes, &width, &height);
+    printf("frame: %ux%u, first pixel %02X%02X%02X\n", width, height, pixels[0], pixels[1], pixels[2]);
+
+    /* Ask how big the state is, then save it for real. */
+    size_t state_len = 0;
+
+    if (snes_save_state(snes, NULL, 0, &state_len) != SNES_ERROR_BUFFER_TOO_SMALL) {
+        fprintf(stderr, "expected the size of the state\n");
+        return 1;
+    }
+
+    uint8_t *state = malloc(state_len);
+    check(snes_save_state(snes, state, state_len, &state_len), "snes_save_state");
+
+    uint8_t before, after;
+    check(snes_read_memory(snes, 0x7E0000, &before), "snes_read_memory");
+    check(snes_write_memory(snes, 0x7E0000, before ^ 0xFF), "snes_write_memory");
+    check(snes_load_state(snes, state, state_len), "snes_load_state");
+    check(snes_read_memory(snes, 0x7E0000, &after), "snes_read_memory");
+
+    if (before != after) {
+        fprintf(stderr, "loading the state didn't undo the write\n");
+        return 1;
+    }
+
+    printf("state: %zu bytes, restored 7E0000 = %02X\n", state_len, after);
+
+    free(state);
+    snes_destroy(snes);
+
+    return 0;
+}
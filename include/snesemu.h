@@ -0,0 +1,52 @@
+#ifndef SNESEMU_H
+#define SNESEMU_H
+
+/* Generated with cbindgen from src/ffi.rs - don't edit this by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define SNES_OK 0
+
+#define SNES_ERROR_NULL -1
+
+#define SNES_ERROR_PANIC -2
+
+#define SNES_ERROR_INVALID_ARGUMENT -3
+
+#define SNES_ERROR_BUFFER_TOO_SMALL -4
+
+#define SNES_ERROR_INVALID_STATE -5
+
+typedef struct SnesEmulator SnesEmulator;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+SnesEmulator *snes_create(const uint8_t *rom, size_t len);
+
+void snes_destroy(SnesEmulator *snes);
+
+int32_t snes_run_frame(SnesEmulator *snes);
+
+const uint8_t *snes_framebuffer(const SnesEmulator *snes, uint32_t *width, uint32_t *height);
+
+int32_t snes_set_input(SnesEmulator *snes, uint32_t pad, uint16_t buttons);
+
+int32_t snes_save_state(SnesEmulator *snes, uint8_t *buf, size_t len, size_t *written);
+
+int32_t snes_load_state(SnesEmulator *snes, const uint8_t *buf, size_t len);
+
+int32_t snes_read_memory(const SnesEmulator *snes, uint32_t addr, uint8_t *value);
+
+int32_t snes_write_memory(SnesEmulator *snes, uint32_t addr, uint8_t value);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SNESEMU_H */
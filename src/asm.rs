@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+// A tiny assembler for the instructions the CPU implements, for putting
+// together test programs without working out the opcodes by hand:
+//
+//     let code = Asm::at(0x8000)
+//         .rep(0x30)
+//         .ldx_imm16(0x0010)
+//         .label("loop")
+//         .stz_abs_x(0x0100)
+//         .dex()
+//         .bne("loop")
+//         .rts()
+//         .assemble()?;
+//
+// Methods are named after the mnemonic and the addressing mode. Immediates
+// come in 8 and 16-bit versions, as the assembler doesn't follow REP and
+// SEP to know which one the CPU will expect.
+//
+// It's only built with the test-support feature, which the tests turn on
+// for themselves.
+pub struct Asm {
+    origin: u32,
+    bytes: Vec<u8>,
+    labels: HashMap<String, usize>,
+    fixups: Vec<Fixup>,
+}
+
+// A reference to a label that can't be filled in until the end, as the
+// label may come later.
+struct Fixup {
+    offset: usize,
+    label: String,
+    kind: FixupKind,
+}
+
+enum FixupKind {
+    // An 8-bit displacement from the end of a branch.
+    Relative,
+
+    // The label's address within the bank, for JMP and JSR.
+    Absolute,
+}
+
+impl Asm {
+    // Starts a program that will be loaded at origin, which is only needed
+    // for jumping to labels.
+    pub fn at(origin: u32) -> Asm {
+        Asm {
+            origin,
+            bytes: Vec::new(),
+            labels: HashMap::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    // Fills in the references to labels, failing if one is missing or a
+    // branch can't reach.
+    pub fn assemble(mut self) -> Result<Vec<u8>, String> {
+        for fixup in &self.fixups {
+            let target = *self
+                .labels
+                .get(&fixup.label)
+                .ok_or_else(|| format!("unknown label: {}", fixup.label))?;
+
+            match fixup.kind {
+                FixupKind::Relative => {
+                    let distance = target as i64 - (fixup.offset as i64 + 1);
+
+                    let distance = i8::try_from(distance).map_err(|_| {
+                        format!(
+                            "branch to {} is out of range ({} bytes)",
+                            fixup.label, distance
+                        )
+                    })?;
+
+                    self.bytes[fixup.offset] = distance as u8;
+                }
+                FixupKind::Absolute => {
+                    let addr = (self.origin as usize + target) as u16;
+                    self.bytes[fixup.offset..][..2].copy_from_slice(&addr.to_le_bytes());
+                }
+            }
+        }
+
+        Ok(self.bytes)
+    }
+
+    // Names the address of whatever is assembled next.
+    pub fn label(mut self, name: &str) -> Asm {
+        self.labels.insert(name.to_string(), self.bytes.len());
+        self
+    }
+
+    // Raw bytes, e.g. for data or for an opcode that isn't covered.
+    pub fn bytes(mut self, bytes: &[u8]) -> Asm {
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn byte(mut self, value: u8) -> Asm {
+        self.bytes.push(value);
+        self
+    }
+
+    pub fn word(self, value: u16) -> Asm {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub fn long(self, value: u32) -> Asm {
+        self.bytes(&value.to_le_bytes()[..3])
+    }
+
+    fn fixup(mut self, opcode: u8, label: &str, kind: FixupKind, len: usize) -> Asm {
+        self.bytes.push(opcode);
+
+        self.fixups.push(Fixup {
+            offset: self.bytes.len(),
+            label: label.to_string(),
+            kind,
+        });
+
+        self.bytes.resize(self.bytes.len() + len, 0);
+        self
+    }
+
+    // MVN takes the destination bank first, but is written source first.
+    pub fn mvn(self, src: u8, dest: u8) -> Asm {
+        self.byte(0x54).byte(dest).byte(src)
+    }
+
+    pub fn jmp_to(self, label: &str) -> Asm {
+        self.fixup(0x4C, label, FixupKind::Absolute, 2)
+    }
+
+    pub fn jsr_to(self, label: &str) -> Asm {
+        self.fixup(0x20, label, FixupKind::Absolute, 2)
+    }
+}
+
+macro_rules! instructions {
+    (implied: $($name:ident = $opcode:literal),* $(,)?) => {
+        impl Asm {
+            $(pub fn $name(self) -> Asm {
+                self.byte($opcode)
+            })*
+        }
+    };
+
+    (byte: $($name:ident = $opcode:literal),* $(,)?) => {
+        impl Asm {
+            $(pub fn $name(self, operand: u8) -> Asm {
+                self.byte($opcode).byte(operand)
+            })*
+        }
+    };
+
+    (word: $($name:ident = $opcode:literal),* $(,)?) => {
+        impl Asm {
+            $(pub fn $name(self, operand: u16) -> Asm {
+                self.byte($opcode).word(operand)
+            })*
+        }
+    };
+
+    (long: $($name:ident = $opcode:literal),* $(,)?) => {
+        impl Asm {
+            $(pub fn $name(self, operand: u32) -> Asm {
+                self.byte($opcode).long(operand)
+            })*
+        }
+    };
+
+    (branch: $($name:ident = $opcode:literal),* $(,)?) => {
+        impl Asm {
+            $(pub fn $name(self, label: &str) -> Asm {
+                self.fixup($opcode, label, FixupKind::Relative, 1)
+            })*
+        }
+    };
+}
+
+instructions! {
+    implied:
+    php = 0x08, asl_a = 0x0A, phd = 0x0B, clc = 0x18, inc_a = 0x1A, plp = 0x28,
+    pld = 0x2B, rti = 0x40, pha = 0x48, cli = 0x58, phy = 0x5A, rts = 0x60,
+    pla = 0x68, rtl = 0x6B, sei = 0x78, ply = 0x7A, tdc = 0x7B, dey = 0x88,
+    phb = 0x8B, tya = 0x98, txs = 0x9A, tay = 0xA8, tax = 0xAA, plb = 0xAB,
+    iny = 0xC8, dex = 0xCA, wai = 0xCB, phx = 0xDA, inx = 0xE8, xba = 0xEB,
+    plx = 0xFA, xce = 0xFB,
+}
+
+instructions! {
+    byte:
+    brk = 0x00, adc_dp = 0x65, stz_dp = 0x64, adc_imm8 = 0x69, stz_dp_x = 0x74,
+    adc_dp_x = 0x75, sty_dp = 0x84, sta_dp = 0x85, stx_dp = 0x86, sta_dp_x = 0x95,
+    ldy_imm8 = 0xA0, ldx_imm8 = 0xA2, ldy_dp = 0xA4, lda_dp = 0xA5, ldx_dp = 0xA6,
+    lda_dp_indirect_long = 0xA7, lda_imm8 = 0xA9, cpy_imm8 = 0xC0, rep = 0xC2,
+    cmp_dp = 0xC5, cmp_imm8 = 0xC9, cmp_dp_x = 0xD5, cpx_imm8 = 0xE0, sep = 0xE2,
+    inc_dp = 0xE6,
+}
+
+instructions! {
+    word:
+    jsr = 0x20, jmp = 0x4C, adc_imm16 = 0x69, adc_abs = 0x6D, adc_abs_y = 0x79,
+    sta_abs = 0x8D, stx_abs = 0x8E, sta_abs_y = 0x99, stz_abs = 0x9C,
+    sta_abs_x = 0x9D, stz_abs_x = 0x9E, ldy_imm16 = 0xA0, ldx_imm16 = 0xA2,
+    lda_imm16 = 0xA9, lda_abs = 0xAD, lda_abs_y = 0xB9, lda_abs_x = 0xBD,
+    cpy_imm16 = 0xC0, cmp_imm16 = 0xC9, cmp_abs = 0xCD, cpx_imm16 = 0xE0,
+    pea = 0xF4,
+}
+
+instructions! {
+    long:
+    jsl = 0x22, sta_long_x = 0x9F, lda_long_x = 0xBF, cmp_long_x = 0xDF,
+}
+
+instructions! {
+    branch:
+    bra = 0x80, bcc = 0x90, bcs = 0xB0, bne = 0xD0, beq = 0xF0,
+}
+
+// Wraps code in a 32KB LoROM image, with the code at the start of bank 0's
+// ROM and the reset vector pointing at it. The code has to leave room for
+// the vectors at the end.
+pub fn lorom(code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+
+    rom[..code.len()].copy_from_slice(code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&0x8000u16.to_le_bytes());
+
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodings() {
+        // Encodings from the 65816 data sheet.
+        #[rustfmt::skip]
+        let cases: Vec<(Asm, &[u8])> = vec![
+            (Asm::at(0).clc().xce(),               &[0x18, 0xFB]),
+            (Asm::at(0).rep(0x30),                 &[0xC2, 0x30]),
+            (Asm::at(0).sep(0x20),                 &[0xE2, 0x20]),
+            (Asm::at(0).lda_imm8(0x12),            &[0xA9, 0x12]),
+            (Asm::at(0).lda_imm16(0x1234),         &[0xA9, 0x34, 0x12]),
+            (Asm::at(0).ldx_imm16(0x0010),         &[0xA2, 0x10, 0x00]),
+            (Asm::at(0).sta_abs(0x2100),           &[0x8D, 0x00, 0x21]),
+            (Asm::at(0).stz_abs_x(0x0100),         &[0x9E, 0x00, 0x01]),
+            (Asm::at(0).lda_dp_indirect_long(0x10), &[0xA7, 0x10]),
+            (Asm::at(0).sta_long_x(0x7E_1234),     &[0x9F, 0x34, 0x12, 0x7E]),
+            (Asm::at(0).jsl(0x12_3456),            &[0x22, 0x56, 0x34, 0x12]),
+            (Asm::at(0).jsr(0x8123),               &[0x20, 0x23, 0x81]),
+            (Asm::at(0).pea(0xBEEF),               &[0xF4, 0xEF, 0xBE]),
+            (Asm::at(0).mvn(0x12, 0x7F),           &[0x54, 0x7F, 0x12]),
+            (Asm::at(0).brk(0x00),                 &[0x00, 0x00]),
+            (Asm::at(0).pha().plx().rts().rtl(),   &[0x48, 0xFA, 0x60, 0x6B]),
+            (Asm::at(0).word(0x1234).long(0x56_789A), &[0x34, 0x12, 0x9A, 0x78, 0x56]),
+        ];
+
+        for (asm, bytes) in cases {
+            assert_eq!(asm.assemble().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn branches_to_labels() {
+        // The example at the top of the file.
+        let code = Asm::at(0x8000)
+            .rep(0x30)
+            .ldx_imm16(0x0010)
+            .label("loop")
+            .stz_abs_x(0x0100)
+            .dex()
+            .bne("loop")
+            .rts()
+            .assemble()
+            .unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(code, [
+            0xC2, 0x30,       // REP #$30
+            0xA2, 0x10, 0x00, // LDX #$0010
+            // loop:
+            0x9E, 0x00, 0x01, // STZ $0100,X
+            0xCA,             // DEX
+            0xD0, 0xFA,       // BNE loop
+            0x60,             // RTS
+        ]);
+
+        // Forwards, and as far as a branch can go either way.
+        let code = Asm::at(0x8000)
+            .label("back")
+            .bytes(&[0xEA; 126])
+            .bra("back")
+            .beq("ahead")
+            .bytes(&[0xEA; 127])
+            .label("ahead")
+            .assemble()
+            .unwrap();
+
+        assert_eq!(code[126..128], [0x80, 0x80]);
+        assert_eq!(code[128..130], [0xF0, 0x7F]);
+    }
+
+    #[test]
+    fn jumps_to_labels() {
+        let code = Asm::at(0x12_9000)
+            .jsr_to("sub")
+            .jmp_to("start")
+            .label("sub")
+            .rts()
+            .label("start")
+            .assemble()
+            .unwrap();
+
+        // Only the address within the bank is used.
+        assert_eq!(code, [0x20, 0x06, 0x90, 0x4C, 0x07, 0x90, 0x60]);
+    }
+
+    #[test]
+    fn label_errors() {
+        let error = Asm::at(0x8000).bne("nowhere").assemble().unwrap_err();
+        assert_eq!(error, "unknown label: nowhere");
+
+        let error = Asm::at(0x8000)
+            .beq("far")
+            .bytes(&[0xEA; 128])
+            .label("far")
+            .assemble()
+            .unwrap_err();
+        assert_eq!(error, "branch to far is out of range (128 bytes)");
+    }
+
+    #[test]
+    fn lorom_image() {
+        let rom = lorom(&[0x18, 0xFB]);
+
+        assert_eq!(rom.len(), 0x8000);
+        assert_eq!(rom[..3], [0x18, 0xFB, 0x00]);
+        assert_eq!(rom[0x7FFC..0x7FFE], [0x00, 0x80]);
+    }
+}
@@ -0,0 +1,15 @@
+use std::io;
+
+use crate::mmu::Mmu;
+
+// Somewhere to keep a cartridge's battery-backed SRAM. The core doesn't deal
+// with files, so the frontend supplies one of these, and the emulator calls
+// it as the game runs.
+pub trait Battery {
+    // Called at the end of each frame. It's up to the battery how often SRAM
+    // actually gets written.
+    fn frame(&mut self, mmu: &mut Mmu) -> io::Result<()>;
+
+    // Writes the save now if SRAM has changed since the last time.
+    fn flush(&mut self, mmu: &mut Mmu) -> io::Result<()>;
+}
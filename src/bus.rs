@@ -0,0 +1,179 @@
+use crate::mmu::Mmu;
+
+// Everything the CPU can see of the rest of the system. The CPU is generic
+// over this rather than using a trait object, so the Mmu version compiles to
+// the same code as calling the Mmu directly.
+pub trait Bus {
+    fn read_u8(&mut self, addr: u32) -> u8;
+    fn store_u8(&mut self, addr: u32, value: u8);
+
+    // Reads without any side effects, for the debugger and the trace.
+    fn peek_u8(&self, addr: u32) -> u8;
+
+    // How many master cycles an access to addr takes.
+    fn access_cycles(&self, _addr: u32) -> u64 {
+        8
+    }
+
+    // Records how the CPU used a byte, for the code/data log.
+    fn log_access(&mut self, _addr: u32, _flags: u8) {}
+
+    // Multi-byte values are little endian, and wrap around at the end of
+    // the 24-bit address space.
+    fn read_u16(&mut self, addr: u32) -> u16 {
+        u16::from_le_bytes([self.read_u8(addr), self.read_u8(next(addr, 1))])
+    }
+
+    fn read_long(&mut self, addr: u32) -> u32 {
+        self.read_u16(addr) as u32 | (self.read_u8(next(addr, 2)) as u32) << 16
+    }
+
+    fn store_u16(&mut self, addr: u32, value: u16) {
+        let [low, high] = value.to_le_bytes();
+
+        self.store_u8(addr, low);
+        self.store_u8(next(addr, 1), high);
+    }
+
+    fn peek_u16(&self, addr: u32) -> u16 {
+        u16::from_le_bytes([self.peek_u8(addr), self.peek_u8(next(addr, 1))])
+    }
+
+    fn peek_long(&self, addr: u32) -> u32 {
+        self.peek_u16(addr) as u32 | (self.peek_u8(next(addr, 2)) as u32) << 16
+    }
+}
+
+fn next(addr: u32, offset: u32) -> u32 {
+    addr.wrapping_add(offset) & 0xFF_FFFF
+}
+
+impl Bus for Mmu {
+    fn read_u8(&mut self, addr: u32) -> u8 {
+        Mmu::read_u8(self, addr)
+    }
+
+    fn store_u8(&mut self, addr: u32, value: u8) {
+        Mmu::store_u8(self, addr, value)
+    }
+
+    fn peek_u8(&self, addr: u32) -> u8 {
+        Mmu::peek_u8(self, addr)
+    }
+
+    fn access_cycles(&self, addr: u32) -> u64 {
+        Mmu::access_cycles(self, addr)
+    }
+
+    fn log_access(&mut self, addr: u32, flags: u8) {
+        Mmu::log_access(self, addr, flags)
+    }
+}
+
+// 16MB of plain memory, with no mapping, mirroring or I/O, and every access
+// taking the same time. This is for running the CPU on its own, e.g. for
+// single-step test suites, which expect exactly this.
+#[derive(Clone)]
+pub struct FlatBus {
+    memory: Vec<u8>,
+}
+
+impl FlatBus {
+    pub fn new() -> FlatBus {
+        FlatBus {
+            memory: vec![0; 0x100_0000],
+        }
+    }
+
+    pub fn load(&mut self, addr: u32, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.memory[next(addr, i as u32) as usize] = byte;
+        }
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> FlatBus {
+        FlatBus::new()
+    }
+}
+
+impl Bus for FlatBus {
+    fn read_u8(&mut self, addr: u32) -> u8 {
+        self.memory[addr as usize & 0xFF_FFFF]
+    }
+
+    fn store_u8(&mut self, addr: u32, value: u8) {
+        self.memory[addr as usize & 0xFF_FFFF] = value;
+    }
+
+    fn peek_u8(&self, addr: u32) -> u8 {
+        self.memory[addr as usize & 0xFF_FFFF]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mmu::MapMode;
+
+    #[test]
+    fn words_are_little_endian_and_wrap() {
+        let mut bus = FlatBus::new();
+        bus.load(0x12_3456, &[0x78, 0x9A, 0xBC]);
+
+        assert_eq!(bus.read_u16(0x12_3456), 0x9A78);
+        assert_eq!(bus.read_long(0x12_3456), 0xBC9A78);
+        assert_eq!(bus.peek_long(0x12_3456), 0xBC9A78);
+
+        // Off the end of the address space and back to the start.
+        bus.load(0xFF_FFFF, &[0x11, 0x22, 0x33]);
+        assert_eq!(bus.memory()[..2], [0x22, 0x33]);
+        assert_eq!(bus.read_long(0xFF_FFFF), 0x332211);
+
+        bus.store_u16(0xFF_FFFF, 0x4455);
+        assert_eq!(
+            (bus.peek_u8(0xFF_FFFF), bus.peek_u8(0x00_0000)),
+            (0x55, 0x44)
+        );
+        assert_eq!(bus.peek_u16(0xFF_FFFF), 0x4455);
+
+        // Anything above 24 bits is dropped.
+        assert_eq!(bus.read_u8(0x0100_0000), 0x44);
+    }
+
+    #[test]
+    fn flat_bus_has_no_mapping() {
+        let mut bus = FlatBus::new();
+        bus.store_u8(0x00_0000, 0x12);
+        bus.store_u8(0x00_8000, 0x34);
+
+        // Nothing is mirrored, and everything is writable.
+        assert_eq!(bus.read_u8(0x7E_0000), 0x00);
+        assert_eq!(bus.read_u8(0x80_8000), 0x00);
+        assert_eq!(bus.read_u8(0x00_8000), 0x34);
+        assert_eq!(bus.access_cycles(0x00_4016), 8);
+    }
+
+    #[test]
+    fn mmu_is_a_bus() {
+        let mut cartridge = vec![0; 0x8000];
+        cartridge[0x0000..0x0003].copy_from_slice(&[0x01, 0x02, 0x03]);
+
+        let mut mmu = Mmu::new(cartridge, Some(MapMode::LoRom));
+
+        // The helpers go through the Mmu's mapping, so WRAM is mirrored and
+        // ROM is read-only.
+        Bus::store_u16(&mut mmu, 0x00_0010, 0x1234);
+        assert_eq!(Bus::read_u16(&mut mmu, 0x7E_0010), 0x1234);
+
+        Bus::store_u16(&mut mmu, 0x00_8000, 0xFFFF);
+        assert_eq!(Bus::read_long(&mut mmu, 0x80_8000), 0x030201);
+        assert_eq!(Bus::access_cycles(&mmu, 0x00_4016), 12);
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Flags for each byte of the ROM, laid out the same way as Mesen-S's CDL
+// files so that they can be loaded into its debugger.
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+pub const INDEX_8: u8 = 0x10;
+pub const MEMORY_8: u8 = 0x20;
+
+// Only used internally, to tell opcodes apart from their operands. This isn't
+// part of the file format, so it gets stripped when saving.
+pub const OPCODE: u8 = 0x80;
+
+pub struct BankCoverage {
+    pub bank: usize,
+    pub size: usize,
+    pub opcodes: usize,
+    pub operands: usize,
+    pub data: usize,
+    pub touched: usize,
+}
+
+// A code/data log - a record of how every byte of the ROM has been accessed
+// over the course of a run.
+pub struct CodeDataLog {
+    flags: Vec<u8>,
+}
+
+impl CodeDataLog {
+    pub fn new(rom_size: usize) -> CodeDataLog {
+        CodeDataLog {
+            flags: vec![0; rom_size],
+        }
+    }
+
+    pub fn mark(&mut self, offset: usize, flags: u8) {
+        self.flags[offset] |= flags;
+    }
+
+    pub fn flags(&self, offset: usize) -> u8 {
+        self.flags[offset]
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let flags: Vec<u8> = self.flags.iter().map(|f| f & !OPCODE).collect();
+        writer.write_all(&flags)?;
+
+        writer.flush()
+    }
+
+    // Counts how many bytes of each bank have been touched. A byte can count
+    // as both code and data, if it was executed and read.
+    pub fn coverage(&self, bank_size: usize) -> Vec<BankCoverage> {
+        self.flags
+            .chunks(bank_size)
+            .enumerate()
+            .map(|(bank, flags)| {
+                let count =
+                    |mask: u8, value: u8| flags.iter().filter(|&&f| f & mask == value).count();
+
+                BankCoverage {
+                    bank,
+                    size: flags.len(),
+                    opcodes: count(OPCODE, OPCODE),
+                    operands: count(CODE | OPCODE, CODE),
+                    data: count(DATA, DATA),
+                    touched: flags.iter().filter(|&&f| f != 0).count(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::mmu::MapMode;
+
+    #[test]
+    fn marks_each_byte() {
+        #[rustfmt::skip]
+        let code = [
+            0xAD, 0x10, 0x80, // LDA $8010
+            0xA2, 0x05,       // LDX #$05
+            0x80, 0xFE,       // BRA *
+        ];
+
+        let mut rom = vec![0; 0x8000];
+        rom[..code.len()].copy_from_slice(&code);
+        rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+        let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+        emulator.mmu.cdl = Some(CodeDataLog::new(0x8000));
+
+        for _ in 0..4 {
+            emulator.step();
+        }
+
+        let cdl = emulator.mmu.cdl.as_ref().unwrap();
+        let flags: Vec<u8> = (0..0x12).map(|offset| cdl.flags(offset)).collect();
+
+        // Everything runs in emulation mode, so the opcodes are all marked
+        // as having 8-bit registers.
+        let opcode = CODE | OPCODE | MEMORY_8 | INDEX_8;
+
+        #[rustfmt::skip]
+        let expected = [
+            opcode, CODE, CODE,
+            // Immediates are read through the data path too.
+            opcode, CODE | DATA,
+            opcode, CODE,
+            0, 0, 0, 0, 0, 0, 0, 0, 0,
+            DATA,
+            0,
+        ];
+
+        assert_eq!(flags, expected);
+
+        let coverage = &cdl.coverage(0x8000)[0];
+        assert_eq!(
+            (
+                coverage.opcodes,
+                coverage.operands,
+                coverage.data,
+                coverage.touched
+            ),
+            (3, 4, 2, 8)
+        );
+
+        // The opcode flag is left out of the file.
+        let path = std::env::temp_dir().join(format!("snesemu-cdl-{}.cdl", std::process::id()));
+        cdl.save(&path).unwrap();
+
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved.len(), 0x8000);
+        assert_eq!(saved[..3], [CODE | MEMORY_8 | INDEX_8, CODE, CODE]);
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+
+// The Game Genie's letters, in the order of the nibbles they stand for.
+const GAME_GENIE_LETTERS: &str = "DF4709156BC8A2E3";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    // Replaces a byte of ROM, wherever it's read from.
+    GameGenie,
+
+    // Writes a byte of RAM at the start of every vblank, so that whatever
+    // the game put there is overwritten.
+    ProActionReplay,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: String,
+    pub kind: CheatKind,
+    pub addr: u32,
+    pub value: u8,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    // Game Genie codes are written with a dash in the middle, e.g.
+    // `DD62-6DA4`, and Pro Action Replay codes as eight hex digits of
+    // address and value, e.g. `7E0DBEFF`, optionally with a colon before the
+    // value.
+    pub fn parse(code: &str) -> Result<Cheat, String> {
+        let code = code.trim().to_ascii_uppercase();
+
+        let (kind, addr, value) = match code.split_once('-') {
+            Some((first, second)) => {
+                let (addr, value) = decode_game_genie(first, second)
+                    .ok_or_else(|| format!("invalid Game Genie code: {}", code))?;
+
+                (CheatKind::GameGenie, addr, value)
+            }
+            None => {
+                let digits = code.replace(':', "");
+
+                let raw = (digits.len() == 8)
+                    .then(|| u32::from_str_radix(&digits, 16).ok())
+                    .flatten()
+                    .ok_or_else(|| format!("invalid Pro Action Replay code: {}", code))?;
+
+                (CheatKind::ProActionReplay, raw >> 8, raw as u8)
+            }
+        };
+
+        Ok(Cheat {
+            code,
+            kind,
+            addr,
+            value,
+            enabled: true,
+        })
+    }
+}
+
+// e.g. `DD62-6DA4 (ROM 00:8000 = 00)`
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let target = match self.kind {
+            CheatKind::GameGenie => "ROM",
+            CheatKind::ProActionReplay => "RAM",
+        };
+
+        write!(
+            f,
+            "{} ({} {:02X}:{:04X} = {:02X})",
+            self.code,
+            target,
+            self.addr >> 16,
+            self.addr & 0xFFFF,
+            self.value
+        )?;
+
+        if !self.enabled {
+            f.write_str(", off")?;
+        }
+
+        Ok(())
+    }
+}
+
+// The letters stand for the nibbles of the value and a CPU address, but the
+// address's bits are shuffled, so they have to be put back in order.
+fn decode_game_genie(first: &str, second: &str) -> Option<(u32, u8)> {
+    if first.len() != 4 || second.len() != 4 {
+        return None;
+    }
+
+    let mut raw = 0u32;
+
+    for letter in first.chars().chain(second.chars()) {
+        raw = raw << 4 | GAME_GENIE_LETTERS.find(letter)? as u32;
+    }
+
+    let value = (raw >> 24) as u8;
+    let bits = raw & 0xFF_FFFF;
+
+    let addr = (bits & 0x00_3C00) << 10
+        | (bits & 0x00_003C) << 14
+        | (bits & 0xF0_0000) >> 8
+        | (bits & 0x00_0003) << 10
+        | (bits & 0x00_C000) >> 6
+        | (bits & 0x0F_0000) >> 12
+        | (bits & 0x00_03C0) >> 6;
+
+    Some((addr, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Puts a code together the other way round, following the bit layout
+    // in the Game Genie's documentation: the letters' 24 address bits are
+    // ijklqrstopabcduvwxefghmn, for an address of abcdefghijklmnopqrstuvwx.
+    fn encode_game_genie(addr: u32, value: u8) -> String {
+        let bits = |low: u32, len: u32| (addr >> low) & ((1 << len) - 1);
+
+        let raw = (value as u32) << 24
+            | bits(12, 4) << 20
+            | bits(4, 4) << 16
+            | bits(8, 2) << 14
+            | bits(20, 4) << 10
+            | bits(0, 4) << 6
+            | bits(16, 4) << 2
+            | bits(10, 2);
+
+        let letters: String = (0..8)
+            .map(|i| GAME_GENIE_LETTERS.as_bytes()[(raw >> (28 - i * 4)) as usize & 0xF] as char)
+            .collect();
+
+        format!("{}-{}", &letters[..4], &letters[4..])
+    }
+
+    #[test]
+    fn game_genie_codes() {
+        // Worked out by hand from the letter table and the bit layout.
+        let cases = [
+            ("DD62-6DA4", 0x00_8AD3, 0x00),
+            ("3364-0D3D", 0x0C_8123, 0xFF),
+            ("0464-5DAD", 0xC0_8123, 0x42),
+            ("DD33-E7D7", 0x80_FFFC, 0x00),
+        ];
+
+        for (code, addr, value) in cases {
+            let cheat = Cheat::parse(code).unwrap();
+
+            assert_eq!(cheat.kind, CheatKind::GameGenie);
+            assert_eq!((cheat.addr, cheat.value), (addr, value), "{}", code);
+            assert_eq!(encode_game_genie(addr, value), code);
+        }
+
+        // Case and surrounding space don't matter.
+        assert_eq!(Cheat::parse(" dd62-6da4 ").unwrap().code, "DD62-6DA4");
+    }
+
+    #[test]
+    fn game_genie_round_trips() {
+        let mut state = 0x1234_5678u32;
+
+        for _ in 0..1000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+
+            let (addr, value) = (state & 0xFF_FFFF, (state >> 24) as u8);
+            let cheat = Cheat::parse(&encode_game_genie(addr, value)).unwrap();
+
+            assert_eq!((cheat.addr, cheat.value), (addr, value));
+        }
+    }
+
+    #[test]
+    fn pro_action_replay_codes() {
+        for code in ["7E0DBEFF", "7e0dbe:ff"] {
+            let cheat = Cheat::parse(code).unwrap();
+
+            assert_eq!(cheat.kind, CheatKind::ProActionReplay);
+            assert_eq!((cheat.addr, cheat.value), (0x7E_0DBE, 0xFF));
+        }
+
+        let mut cheat = Cheat::parse("7E0DBE:FF").unwrap();
+        assert_eq!(cheat.to_string(), "7E0DBE:FF (RAM 7E:0DBE = FF)");
+
+        cheat.enabled = false;
+        assert_eq!(cheat.to_string(), "7E0DBE:FF (RAM 7E:0DBE = FF), off");
+    }
+
+    #[test]
+    fn invalid_codes() {
+        let cases = [
+            ("DD62-6DA", "invalid Game Genie code: DD62-6DA"),
+            ("DD62-6DAZ", "invalid Game Genie code: DD62-6DAZ"),
+            ("7E0DBE", "invalid Pro Action Replay code: 7E0DBE"),
+            ("7E0DBEGG", "invalid Pro Action Replay code: 7E0DBEGG"),
+        ];
+
+        for (code, error) in cases {
+            assert_eq!(Cheat::parse(code), Err(error.to_string()));
+        }
+    }
+}
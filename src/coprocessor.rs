@@ -0,0 +1,112 @@
+pub mod dsp1;
+
+use crate::mmu::MapMode;
+
+use self::dsp1::Dsp1;
+
+// A chip on the cartridge that the CPU talks to through registers mapped
+// into the address space. The MMU asks it about every access to a slow
+// page before anything else, so it can claim addresses that would
+// otherwise be ROM.
+pub trait Coprocessor {
+    fn name(&self) -> &'static str;
+
+    // Whether the chip responds at an address.
+    fn maps(&self, bank: u8, offset: u16) -> bool;
+
+    fn read(&mut self, bank: u8, offset: u16) -> u8;
+
+    // What the next read would return, without changing any state.
+    fn peek(&self, bank: u8, offset: u16) -> u8;
+
+    fn write(&mut self, bank: u8, offset: u16, value: u8);
+}
+
+// The coprocessors that are emulated. This is an enum rather than a boxed
+// trait object so that it can be cloned and saved along with the rest of
+// the machine.
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub enum Chip {
+    Dsp1(Dsp1),
+}
+
+impl Chip {
+    // Goes by the chipset byte in the header. The DSP-1 to DSP-4 all share
+    // the same value, but the DSP-1 is far more common than the others.
+    pub fn detect(cartridge: &[u8], map_mode: MapMode) -> Option<Chip> {
+        let chipset = *cartridge.get(map_mode.header_offset() + 0x16)?;
+
+        match (chipset >> 4, chipset & 0x0F) {
+            (0x0, 0x3..=0x5) => Some(Chip::Dsp1(Dsp1::new(map_mode, cartridge.len()))),
+            _ => None,
+        }
+    }
+
+    fn inner(&self) -> &dyn Coprocessor {
+        match self {
+            Chip::Dsp1(dsp1) => dsp1,
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut dyn Coprocessor {
+        match self {
+            Chip::Dsp1(dsp1) => dsp1,
+        }
+    }
+}
+
+impl Coprocessor for Chip {
+    fn name(&self) -> &'static str {
+        self.inner().name()
+    }
+
+    fn maps(&self, bank: u8, offset: u16) -> bool {
+        self.inner().maps(bank, offset)
+    }
+
+    fn read(&mut self, bank: u8, offset: u16) -> u8 {
+        self.inner_mut().read(bank, offset)
+    }
+
+    fn peek(&self, bank: u8, offset: u16) -> u8 {
+        self.inner().peek(bank, offset)
+    }
+
+    fn write(&mut self, bank: u8, offset: u16, value: u8) {
+        self.inner_mut().write(bank, offset, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_dsp1() {
+        for (map_mode, size) in [(MapMode::LoRom, 0x8_0000), (MapMode::HiRom, 0x10_0000)] {
+            let mut cartridge = vec![0; size];
+
+            for (chipset, dsp) in [
+                (0x00, false),
+                (0x02, false),
+                (0x03, true),
+                (0x05, true),
+                (0x13, false),
+                (0x35, false),
+            ] {
+                cartridge[map_mode.header_offset() + 0x16] = chipset;
+                let chip = Chip::detect(&cartridge, map_mode);
+
+                assert_eq!(chip.is_some(), dsp, "chipset {:02X}", chipset);
+
+                if let Some(chip) = chip {
+                    assert_eq!(chip.name(), "DSP-1");
+                }
+            }
+        }
+
+        // Too short to have a header.
+        assert!(Chip::detect(&[0x03; 0x100], MapMode::LoRom).is_none());
+    }
+}
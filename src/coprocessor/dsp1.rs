@@ -0,0 +1,400 @@
+use std::collections::VecDeque;
+
+use super::Coprocessor;
+use crate::mmu::MapMode;
+
+// RQM, set when the chip is ready for the CPU to read or write the data
+// register. Commands finish instantly here, so it's always set.
+const STATUS_RQM: u8 = 0x80;
+
+// DRS, set between the two bytes of a word.
+const STATUS_DRS: u8 = 0x10;
+
+// Where the data and status registers are, which depends on the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+enum Mapping {
+    // $00-$1F:$6000-$7FFF, with the status register in the upper half.
+    HiRom,
+
+    // $30-$3F:$8000-$FFFF, for LoROM games up to 1MB.
+    LoRomSmall,
+
+    // $60-$6F:$0000-$7FFF, for bigger LoROM games.
+    LoRomLarge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    Data,
+    Status,
+}
+
+// The DSP-1 is an NEC uPD77C25 running a fixed program, which the CPU gives
+// a command byte followed by 16-bit parameters, then reads the results back
+// from the same data register. This is a high level version of that
+// program, so only the commands below are emulated, and the rest stop the
+// emulator rather than leaving the game waiting on results that never
+// come.
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dsp1 {
+    mapping: Mapping,
+
+    // The command whose parameters are being written, if any.
+    command: Option<u8>,
+    last_command: u8,
+    inputs: Vec<u16>,
+
+    // Words go through the data register a byte at a time, low byte first.
+    write_low: Option<u8>,
+    read_high: bool,
+
+    outputs: VecDeque<u16>,
+}
+
+impl Dsp1 {
+    pub fn new(map_mode: MapMode, rom_len: usize) -> Dsp1 {
+        let mapping = match map_mode {
+            MapMode::HiRom => Mapping::HiRom,
+            MapMode::LoRom if rom_len > 0x10_0000 => Mapping::LoRomLarge,
+            MapMode::LoRom => Mapping::LoRomSmall,
+        };
+
+        Dsp1 {
+            mapping,
+
+            command: None,
+            last_command: 0,
+            inputs: Vec::new(),
+
+            write_low: None,
+            read_high: false,
+
+            outputs: VecDeque::new(),
+        }
+    }
+
+    fn register(&self, bank: u8, offset: u16) -> Option<Register> {
+        let data = match (self.mapping, bank & 0x7F) {
+            (Mapping::HiRom, 0x00..=0x1F) if (0x6000..0x8000).contains(&offset) => offset < 0x7000,
+            (Mapping::LoRomSmall, 0x30..=0x3F) if offset >= 0x8000 => offset < 0xC000,
+            (Mapping::LoRomLarge, 0x60..=0x6F) if offset < 0x8000 => offset < 0x4000,
+            _ => return None,
+        };
+
+        Some(if data {
+            Register::Data
+        } else {
+            Register::Status
+        })
+    }
+
+    fn status(&self) -> u8 {
+        let drs = self.write_low.is_some() || self.read_high;
+
+        STATUS_RQM | if drs { STATUS_DRS } else { 0 }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let value = self.peek_data();
+
+        if !self.outputs.is_empty() {
+            if self.read_high {
+                self.outputs.pop_front();
+            }
+
+            self.read_high = !self.read_high;
+        }
+
+        value
+    }
+
+    // With nothing left to read, the last command is echoed back.
+    fn peek_data(&self) -> u8 {
+        match self.outputs.front() {
+            Some(&word) if self.read_high => (word >> 8) as u8,
+            Some(&word) => word as u8,
+            None => self.last_command,
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        let Some(command) = self.command else {
+            self.start(value);
+            return;
+        };
+
+        let Some(low) = self.write_low.take() else {
+            self.write_low = Some(value);
+            return;
+        };
+
+        self.inputs.push(u16::from_le_bytes([low, value]));
+
+        if Some(self.inputs.len()) == input_count(command) {
+            self.finish(command);
+        }
+    }
+
+    // Anything past $3F isn't a command, and is ignored.
+    fn start(&mut self, command: u8) {
+        if command > 0x3F {
+            return;
+        }
+
+        if input_count(command).is_none() {
+            panic!("DSP-1 command ${:02X} isn't emulated", command);
+        }
+
+        // Writing a command abandons any results that weren't read.
+        self.outputs.clear();
+        self.read_high = false;
+
+        self.command = Some(command);
+        self.last_command = command;
+        self.inputs.clear();
+    }
+
+    fn finish(&mut self, command: u8) {
+        self.outputs.extend(run(command, &self.inputs));
+        self.command = None;
+    }
+}
+
+impl Coprocessor for Dsp1 {
+    fn name(&self) -> &'static str {
+        "DSP-1"
+    }
+
+    fn maps(&self, bank: u8, offset: u16) -> bool {
+        self.register(bank, offset).is_some()
+    }
+
+    fn read(&mut self, bank: u8, offset: u16) -> u8 {
+        match self.register(bank, offset) {
+            Some(Register::Data) => self.read_data(),
+            _ => self.status(),
+        }
+    }
+
+    fn peek(&self, bank: u8, offset: u16) -> u8 {
+        match self.register(bank, offset) {
+            Some(Register::Data) => self.peek_data(),
+            _ => self.status(),
+        }
+    }
+
+    // The status register is read-only.
+    fn write(&mut self, bank: u8, offset: u16, value: u8) {
+        if self.register(bank, offset) == Some(Register::Data) {
+            self.write_data(value);
+        }
+    }
+}
+
+// How many parameters a command takes, or None if it isn't emulated.
+fn input_count(command: u8) -> Option<usize> {
+    match command {
+        0x00 | 0x20 => Some(2),
+        0x08 => Some(3),
+        0x18 | 0x38 => Some(4),
+        0x0F | 0x2F => Some(1),
+        _ => None,
+    }
+}
+
+// Runs a command on its parameters, giving the words to read back. The
+// arithmetic follows the chip's program, which works in signed 16-bit
+// fixed point, and is exact.
+fn run(command: u8, inputs: &[u16]) -> Vec<u16> {
+    let signed = |i: usize| inputs[i] as i16 as i32;
+
+    // The squared length of the vector in the first three parameters.
+    let squares = || (0..3).fold(0i32, |sum, i| sum.wrapping_add(signed(i) * signed(i)));
+
+    match command {
+        // Multiply, and the same plus one.
+        0x00 => vec![((signed(0) * signed(1)) >> 15) as u16],
+        0x20 => vec![(((signed(0) * signed(1)) >> 15) + 1) as u16],
+
+        // Radius, the squared length of a vector, doubled, as a 32-bit
+        // value.
+        0x08 => {
+            let size = squares().wrapping_shl(1);
+            vec![size as u16, (size >> 16) as u16]
+        }
+
+        // Range, the squared length of a vector minus a squared radius, and
+        // the same plus one.
+        0x18 => vec![(squares().wrapping_sub(signed(3) * signed(3)) >> 15) as u16],
+        0x38 => vec![((squares().wrapping_sub(signed(3) * signed(3)) >> 15) + 1) as u16],
+
+        // Memory Test, which always passes, and Memory Size.
+        0x0F => vec![0x0000],
+        0x2F => vec![0x0100],
+
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lorom() -> Dsp1 {
+        Dsp1::new(MapMode::LoRom, 0x8_0000)
+    }
+
+    fn status(dsp1: &mut Dsp1) -> u8 {
+        dsp1.read(0x30, 0xC000)
+    }
+
+    fn write_word(dsp1: &mut Dsp1, word: u16) {
+        for byte in word.to_le_bytes() {
+            dsp1.write(0x30, 0x8000, byte);
+        }
+    }
+
+    fn read_word(dsp1: &mut Dsp1) -> u16 {
+        u16::from_le_bytes([dsp1.read(0x30, 0x8000), dsp1.read(0x30, 0x8000)])
+    }
+
+    // Sends a command and its parameters, and reads back `outputs` words.
+    fn command(dsp1: &mut Dsp1, command: u8, inputs: &[u16], outputs: usize) -> Vec<u16> {
+        dsp1.write(0x30, 0x8000, command);
+
+        for &input in inputs {
+            write_word(dsp1, input);
+        }
+
+        (0..outputs).map(|_| read_word(dsp1)).collect()
+    }
+
+    #[test]
+    fn handshake() {
+        let mut dsp1 = lorom();
+        assert_eq!(status(&mut dsp1), STATUS_RQM);
+
+        // DRS is set between the two bytes of each word, both ways.
+        dsp1.write(0x30, 0x8000, 0x00);
+        assert_eq!(status(&mut dsp1), STATUS_RQM);
+
+        for word in [0x4000u16, 0x2000] {
+            dsp1.write(0x30, 0x8000, word as u8);
+            assert_eq!(status(&mut dsp1), STATUS_RQM | STATUS_DRS);
+            dsp1.write(0x30, 0x8000, (word >> 8) as u8);
+            assert_eq!(status(&mut dsp1), STATUS_RQM);
+        }
+
+        assert_eq!(dsp1.peek(0x30, 0x8000), 0x00);
+        assert_eq!(dsp1.read(0x30, 0x8000), 0x00);
+        assert_eq!(status(&mut dsp1), STATUS_RQM | STATUS_DRS);
+        assert_eq!(dsp1.read(0x30, 0x8000), 0x10);
+        assert_eq!(status(&mut dsp1), STATUS_RQM);
+
+        // With the result read, the command is echoed back.
+        assert_eq!(dsp1.read(0x30, 0x8000), 0x00);
+        assert_eq!(command(&mut dsp1, 0x2F, &[0], 1), [0x0100]);
+        assert_eq!(dsp1.read(0x30, 0x8000), 0x2F);
+        assert_eq!(dsp1.read(0x30, 0x8000), 0x2F);
+    }
+
+    #[test]
+    fn commands() {
+        // Inputs and outputs as worked out from the DSP-1 program, in
+        // signed Q15 fixed point where they're fractions.
+        #[rustfmt::skip]
+        let cases: &[(u8, &[u16], &[u16])] = &[
+            // 0.5 x 0.25 = 0.125, and -1.0 x 0.5 = -0.5.
+            (0x00, &[0x4000, 0x2000], &[0x1000]),
+            (0x00, &[0x8000, 0x4000], &[0xC000]),
+            (0x20, &[0x4000, 0x2000], &[0x1001]),
+
+            // 2(1 + 4 + 9), and 2 x 3 x 0x4000² as 32 bits.
+            (0x08, &[1, 2, 3], &[28, 0]),
+            (0x08, &[0x4000, 0x4000, 0x4000], &[0x0000, 0x6000]),
+
+            // 0.5² - 0.25² = 0.1875.
+            (0x18, &[0x4000, 0, 0, 0x2000], &[0x1800]),
+            (0x38, &[0x4000, 0, 0, 0x2000], &[0x1801]),
+
+            (0x0F, &[0x1234], &[0x0000]),
+            (0x2F, &[0x1234], &[0x0100]),
+        ];
+
+        let mut dsp1 = lorom();
+
+        for &(op, inputs, outputs) in cases {
+            assert_eq!(
+                command(&mut dsp1, op, inputs, outputs.len()),
+                outputs,
+                "command ${:02X} with {:04X?}",
+                op,
+                inputs
+            );
+        }
+    }
+
+    #[test]
+    fn new_command_drops_unread_results() {
+        let mut dsp1 = lorom();
+
+        command(&mut dsp1, 0x08, &[1, 2, 3], 0);
+        assert_eq!(dsp1.read(0x30, 0x8000), 28);
+
+        assert_eq!(command(&mut dsp1, 0x00, &[0x4000, 0x4000], 1), [0x2000]);
+
+        // Bytes past $3F aren't commands.
+        dsp1.write(0x30, 0x8000, 0x80);
+        assert_eq!(dsp1.command, None);
+        assert_eq!(dsp1.read(0x30, 0x8000), 0x00);
+    }
+
+    #[test]
+    #[should_panic(expected = "DSP-1 command $02 isn't emulated")]
+    fn unknown_commands_stop() {
+        lorom().write(0x30, 0x8000, 0x02);
+    }
+
+    #[test]
+    fn mappings() {
+        use Register::*;
+
+        #[rustfmt::skip]
+        let cases = [
+            (MapMode::HiRom, 0x20_0000, &[
+                (0x00, 0x6000, Some(Data)), (0x9F, 0x6FFF, Some(Data)),
+                (0x00, 0x7000, Some(Status)), (0x1F, 0x7FFF, Some(Status)),
+                (0x20, 0x6000, None), (0x00, 0x8000, None), (0x00, 0x5FFF, None),
+            ]),
+            (MapMode::LoRom, 0x10_0000, &[
+                (0x30, 0x8000, Some(Data)), (0xBF, 0xBFFF, Some(Data)),
+                (0x30, 0xC000, Some(Status)), (0x3F, 0xFFFF, Some(Status)),
+                (0x2F, 0x8000, None), (0x30, 0x7FFF, None), (0x60, 0x0000, None),
+            ]),
+            (MapMode::LoRom, 0x20_0000, &[
+                (0x60, 0x0000, Some(Data)), (0xEF, 0x3FFF, Some(Data)),
+                (0x60, 0x4000, Some(Status)), (0x6F, 0x7FFF, Some(Status)),
+                (0x70, 0x0000, None), (0x60, 0x8000, None), (0x30, 0x8000, None),
+            ]),
+        ];
+
+        for (map_mode, size, addresses) in cases {
+            let dsp1 = Dsp1::new(map_mode, size);
+
+            for &(bank, offset, register) in addresses {
+                assert_eq!(
+                    dsp1.register(bank, offset),
+                    register,
+                    "{:?} {:X} at {:02X}:{:04X}",
+                    map_mode,
+                    size,
+                    bank,
+                    offset
+                );
+                assert_eq!(dsp1.maps(bank, offset), register.is_some());
+            }
+        }
+    }
+}
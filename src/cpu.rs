@@ -1,9 +1,23 @@
-use std::fmt::Write;
+#[cfg(feature = "ops-audit")]
+mod audit;
+mod ops;
+
+use std::fmt;
+use std::marker::PhantomData;
 
 use bitflags::bitflags;
 
+use crate::bus::Bus;
+use crate::cdl;
+use crate::disasm::{self, Disassembly};
 use crate::inst::Instruction;
-use crate::mmu::Mmu;
+use crate::stack::{Stack, STACK_LIMIT};
+
+// With the ops-audit feature, every ALU operation is checked as it runs.
+#[cfg(feature = "ops-audit")]
+use audit as alu;
+#[cfg(not(feature = "ops-audit"))]
+use ops as alu;
 
 fn bank_addr(bank: u8, addr: u16) -> u32 {
     (bank as u32) << 16 | (addr as u32)
@@ -31,7 +45,8 @@ pub enum AddressingMode {
 }
 
 bitflags! {
-    #[derive(Clone)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
     pub struct Flags: u8 {
         const CARRY          = 0b00000001;
         const ZERO           = 0b00000010;
@@ -46,10 +61,177 @@ bitflags! {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    Break,
+    Irq,
+    Nmi,
+}
+
+impl Interrupt {
+    // Where the handler's address is read from, in bank 0. In emulation
+    // mode, BRK shares its vector with IRQ, so handlers have to check the
+    // pushed status byte to tell them apart.
+    pub fn vector(self, emulation: bool) -> u16 {
+        match (self, emulation) {
+            (Interrupt::Break, false) => 0xFFE6,
+            (Interrupt::Nmi, false) => 0xFFEA,
+            (Interrupt::Irq, false) => 0xFFEE,
+            (Interrupt::Nmi, true) => 0xFFFA,
+            (Interrupt::Break | Interrupt::Irq, true) => 0xFFFE,
+        }
+    }
+}
+
+// A subroutine call (or interrupt) that hasn't returned yet. `sp` is the stack pointer
+// from before the return address was pushed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    pub caller: u32,
+    pub target: u32,
+    pub sp: u16,
+    pub kind: CallKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallKind {
+    Subroutine,
+    Long,
+    Interrupt { emulation: bool },
+}
+
+impl CallKind {
+    // How many bytes the call pushed. Interrupts push the status along with
+    // the return address, and the program bank too outside emulation mode.
+    pub fn pushed(self) -> u16 {
+        match self {
+            CallKind::Subroutine => 2,
+            CallKind::Long => 3,
+            CallKind::Interrupt { emulation: true } => 3,
+            CallKind::Interrupt { emulation: false } => 4,
+        }
+    }
+}
+
+// The instruction at the current address. The raw bytes and the effective
+// address (where it can be worked out without touching anything) are in
+// the disassembly.
+#[derive(Clone, Copy)]
+pub struct DecodedInstruction {
+    pub instruction: Instruction,
+    pub disassembly: Disassembly,
+}
+
+impl DecodedInstruction {
+    pub fn bytes(&self) -> &[u8] {
+        self.disassembly.bytes()
+    }
+
+    pub fn effective_addr(&self) -> Option<u32> {
+        self.disassembly.effective_addr
+    }
+}
+
+// How an instruction used its memory operand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandAccess {
+    Read,
+    Write,
+
+    // Read, changed, then written back, e.g. INC.
+    Modify,
+}
+
+impl OperandAccess {
+    pub fn name(self) -> &'static str {
+        match self {
+            OperandAccess::Read => "read",
+            OperandAccess::Write => "write",
+            OperandAccess::Modify => "modify",
+        }
+    }
+}
+
+// The address an instruction's operand was actually accessed at, after
+// indexing and following any pointers. Unlike the disassembly's effective
+// address, this comes from running the instruction, so it's what the CPU
+// really did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Operand {
+    pub addr: u32,
+    pub access: OperandAccess,
+}
+
+// e.g. `write 7E0010`
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:06X}", self.access.name(), self.addr)
+    }
+}
+
+// The architectural registers, as plain values. This is what code outside
+// of the CPU should compare against, rather than poking at the Cpu itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub a: u16,
+    pub x: u16,
+    pub y: u16,
+    pub pc: u16,
+    pub sp: u16,
+    pub direct_page: u16,
+    pub program_bank: u8,
+    pub data_bank: u8,
+    pub status: u8,
+    pub emulation: bool,
+}
+
+impl CpuState {
+    pub fn current_addr(&self) -> u32 {
+        bank_addr(self.program_bank, self.pc)
+    }
+
+    pub fn register_debug_to(&self, output: &mut impl fmt::Write) -> fmt::Result {
+        fn flag_or_empty(flag: &str, value: bool) -> &str {
+            if value {
+                flag
+            } else {
+                ""
+            }
+        }
+
+        let status = Flags::from_bits_retain(self.status);
+
+        write!(
+            output,
+            "A: {:04X} | X: {:04X} | Y: {:04X} | SP: {:04X} | D: {:04X} | DB: {:02X} | PB: {:02X} | Flags: {}{}{}{}{}{}{}{}{}",
+            self.a,
+            self.x,
+            self.y,
+            self.sp,
+            self.direct_page,
+            self.data_bank,
+            self.program_bank,
+            flag_or_empty("N", status.contains(Flags::NEGATIVE)),
+            flag_or_empty("V", status.contains(Flags::OVERFLOW)),
+            flag_or_empty("M", status.contains(Flags::MEMORY_SELECT)),
+            flag_or_empty("X", status.contains(Flags::INDEX_REGISTER)),
+            flag_or_empty("D", status.contains(Flags::DECIMAL_MODE)),
+            flag_or_empty("I", status.contains(Flags::IRQ_DISABLE)),
+            flag_or_empty("Z", status.contains(Flags::ZERO)),
+            flag_or_empty("C", status.contains(Flags::CARRY)),
+            flag_or_empty("E", self.emulation),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
-    // Registers
-    a: u16,
+    // Registers. The accumulator is kept as two halves, so that 8-bit
+    // operations can only ever touch A and leave B alone.
+    a: u8,
+    b: u8,
     x: u16,
     y: u16,
     pc: u16,
@@ -60,14 +242,38 @@ pub struct Cpu {
     status: Flags,
     emulation: bool,
 
+    // Timing
+    cycles: u64,
+
+    // Interrupt lines. IRQ is level triggered, so it stays asserted until
+    // the source is acknowledged, while an NMI is taken once per raise.
+    irq: bool,
+    nmi: bool,
+
+    // Set by WAI until an interrupt arrives.
+    waiting: bool,
+
     // Debug info
     sp_base: u16,
+
+    // Calls are matched to returns by the stack pointer rather than by
+    // counting, as games often return with tricks like pushing an address
+    // and running RTS, or pop a return address and jump.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    call_stack: Vec<CallFrame>,
+
+    // The memory operand of the last instruction, or None if it didn't
+    // have one (implied and immediate modes, jumps, block moves and
+    // interrupts).
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    operand: Option<Operand>,
 }
 
 impl Cpu {
     pub fn new() -> Cpu {
         Cpu {
             a: 0,
+            b: 0,
             x: 0,
             y: 0,
 
@@ -78,11 +284,24 @@ impl Cpu {
             program_bank: 0,
             data_bank: 0,
 
-            status: Flags::empty(),
+            // M and X are always set in emulation mode, and stay set on the
+            // way out of it.
+            status: Flags::MEMORY_SELECT.union(Flags::INDEX_REGISTER),
 
             emulation: true,
 
+            cycles: 0,
+
+            irq: false,
+            nmi: false,
+
+            waiting: false,
+
             sp_base: 0x1FF,
+
+            call_stack: Vec::new(),
+
+            operand: None,
         }
     }
 
@@ -95,31 +314,99 @@ impl Cpu {
         self.pc = (addr & 0x0000FFFF) as u16;
     }
 
-    fn fetch_u8(&mut self, mmu: &Mmu) -> u8 {
-        let value = mmu.read_u8(self.current_addr());
+    fn read_u8(&mut self, mmu: &mut impl Bus, addr: u32) -> u8 {
+        self.cycles += mmu.access_cycles(addr);
+        mmu.log_access(addr, cdl::DATA);
+
+        mmu.read_u8(addr)
+    }
+
+    // Reads part of an instruction, rather than data.
+    fn read_code(&mut self, mmu: &mut impl Bus, addr: u32, flags: u8) -> u8 {
+        self.cycles += mmu.access_cycles(addr);
+        mmu.log_access(addr, flags);
+
+        mmu.read_u8(addr)
+    }
+
+    fn read_u16(&mut self, mmu: &mut impl Bus, addr: u32) -> u16 {
+        let byte0 = self.read_u8(mmu, addr);
+        let byte1 = self.read_u8(mmu, addr + 1);
+
+        u16::from_le_bytes([byte0, byte1])
+    }
+
+    fn read_long(&mut self, mmu: &mut impl Bus, addr: u32) -> u32 {
+        let byte0 = self.read_u8(mmu, addr);
+        let byte1 = self.read_u8(mmu, addr + 1);
+        let byte2 = self.read_u8(mmu, addr + 2);
+
+        u32::from_le_bytes([byte0, byte1, byte2, 0])
+    }
+
+    fn store_u8(&mut self, mmu: &mut impl Bus, addr: u32, value: u8) {
+        self.cycles += mmu.access_cycles(addr);
+
+        mmu.store_u8(addr, value);
+    }
+
+    fn store_u16(&mut self, mmu: &mut impl Bus, addr: u32, value: u16) {
+        let [byte0, byte1] = value.to_le_bytes();
+
+        self.store_u8(mmu, addr, byte0);
+        self.store_u8(mmu, addr + 1, byte1);
+    }
+
+    fn fetch_opcode(&mut self, mmu: &mut impl Bus) -> u8 {
+        let mut flags = cdl::CODE | cdl::OPCODE;
+
+        if self.is_eight_bit_mode(Register::A) {
+            flags |= cdl::MEMORY_8;
+        }
+
+        if self.is_eight_bit_mode(Register::X) {
+            flags |= cdl::INDEX_8;
+        }
+
+        let value = self.read_code(mmu, self.current_addr(), flags);
         self.pc += 1;
 
         value
     }
 
-    fn fetch_u16(&mut self, mmu: &Mmu) -> u16 {
-        let value = mmu.read_u16(self.current_addr());
-        self.pc += 2;
+    fn fetch_u8(&mut self, mmu: &mut impl Bus) -> u8 {
+        let value = self.read_code(mmu, self.current_addr(), cdl::CODE);
+        self.pc += 1;
 
         value
     }
 
-    fn fetch_long(&mut self, mmu: &Mmu) -> u32 {
-        let value = mmu.read_long(self.current_addr());
+    fn fetch_u16(&mut self, mmu: &mut impl Bus) -> u16 {
+        let addr = self.current_addr();
+        let byte0 = self.read_code(mmu, addr, cdl::CODE);
+        let byte1 = self.read_code(mmu, addr + 1, cdl::CODE);
+        self.pc += 2;
+
+        u16::from_le_bytes([byte0, byte1])
+    }
+
+    fn fetch_long(&mut self, mmu: &mut impl Bus) -> u32 {
+        let addr = self.current_addr();
+        let byte0 = self.read_code(mmu, addr, cdl::CODE);
+        let byte1 = self.read_code(mmu, addr + 1, cdl::CODE);
+        let byte2 = self.read_code(mmu, addr + 2, cdl::CODE);
         self.pc += 3;
 
-        value
+        u32::from_le_bytes([byte0, byte1, byte2, 0])
     }
 
-    fn fetch_addr(&mut self, mmu: &Mmu, addr_mode: AddressingMode) -> u32 {
+    fn fetch_addr(&mut self, mmu: &mut impl Bus, addr_mode: AddressingMode) -> u32 {
         match addr_mode {
+            // TODO: Immediates are read through the data path afterwards, so
+            // the code/data log marks them as data as well as code
             AddressingMode::Immediate8 => {
                 let addr = self.current_addr();
+                mmu.log_access(addr, cdl::CODE);
                 self.pc += 1;
 
                 addr
@@ -127,6 +414,8 @@ impl Cpu {
 
             AddressingMode::Immediate16 => {
                 let addr = self.current_addr();
+                mmu.log_access(addr, cdl::CODE);
+                mmu.log_access(addr + 1, cdl::CODE);
                 self.pc += 2;
 
                 addr
@@ -141,13 +430,14 @@ impl Cpu {
             AddressingMode::DirectPage => {
                 let addr = self.fetch_u8(mmu);
 
-                self.direct_page as u32 + addr as u32
+                // The direct page wraps around within bank 0
+                self.direct_page.wrapping_add(addr as u16) as u32
             }
 
             AddressingMode::DirectPageIndirectLong => {
                 let ptr = self.fetch_addr(mmu, AddressingMode::DirectPage);
 
-                mmu.read_long(ptr)
+                self.read_long(mmu, ptr)
             }
 
             AddressingMode::AbsoluteIndexedX => {
@@ -171,49 +461,356 @@ impl Cpu {
             AddressingMode::DirectPageIndexedX => {
                 let addr = self.fetch_u8(mmu);
 
-                self.direct_page as u32 + addr as u32 + self.x as u32
+                self.direct_page
+                    .wrapping_add(addr as u16)
+                    .wrapping_add(self.x) as u32
             }
         }
     }
 
-    fn push_u8(&mut self, mmu: &mut Mmu, value: u8) {
-        mmu.store_u8(self.sp as u32, value);
+    // Works out the address of an instruction's operand, and records it
+    // along with how it's used. Immediates are part of the instruction, so
+    // they don't count as a memory operand.
+    fn fetch_operand(
+        &mut self,
+        mmu: &mut impl Bus,
+        addr_mode: AddressingMode,
+        access: OperandAccess,
+    ) -> u32 {
+        let addr = self.fetch_addr(mmu, addr_mode);
+
+        if !matches!(
+            addr_mode,
+            AddressingMode::Immediate8 | AddressingMode::Immediate16
+        ) {
+            self.operand = Some(Operand {
+                addr: addr & 0xFF_FFFF,
+                access,
+            });
+        }
+
+        addr
+    }
+
+    fn push_u8(&mut self, mmu: &mut impl Bus, value: u8) {
+        self.store_u8(mmu, self.sp as u32, value);
         self.sp -= 1;
     }
 
-    fn push_u16(&mut self, mmu: &mut Mmu, value: u16) {
-        mmu.store_u16(self.sp as u32 - 1, value);
+    fn push_u16(&mut self, mmu: &mut impl Bus, value: u16) {
+        self.store_u16(mmu, self.sp as u32 - 1, value);
         self.sp -= 2;
     }
 
-    fn pull_u8(&mut self, mmu: &mut Mmu) -> u8 {
+    fn pull_u8(&mut self, mmu: &mut impl Bus) -> u8 {
         self.sp += 1;
-        mmu.read_u8(self.sp as u32)
+        self.read_u8(mmu, self.sp as u32)
     }
 
-    fn pull_u16(&mut self, mmu: &mut Mmu) -> u16 {
+    fn pull_u16(&mut self, mmu: &mut impl Bus) -> u16 {
         self.sp += 2;
-        mmu.read_u16(self.sp as u32 - 1)
+        self.read_u16(mmu, self.sp as u32 - 1)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn program_bank(&self) -> u8 {
+        self.program_bank
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    // Where TXS last put the stack.
+    pub fn sp_base(&self) -> u16 {
+        self.sp_base
+    }
+
+    pub fn set_sp(&mut self, value: u16) {
+        self.sp = value;
+    }
+
+    pub fn data_bank(&self) -> u8 {
+        self.data_bank
+    }
+
+    pub fn set_data_bank(&mut self, value: u8) {
+        self.data_bank = value;
+    }
+
+    pub fn status(&self) -> u8 {
+        self.status.bits()
+    }
+
+    pub fn set_status(&mut self, value: u8) {
+        self.status = Flags::from_bits_retain(value);
+    }
+
+    pub fn flag(&self, flag: Flags) -> bool {
+        self.status.contains(flag)
+    }
+
+    pub fn emulation(&self) -> bool {
+        self.emulation
+    }
+
+    // Unlike XCE, this doesn't touch any of the other registers.
+    pub fn set_emulation(&mut self, value: bool) {
+        self.emulation = value;
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            a: self.accumulator(),
+            x: self.x,
+            y: self.y,
+            pc: self.pc,
+            sp: self.sp,
+            direct_page: self.direct_page,
+            program_bank: self.program_bank,
+            data_bank: self.data_bank,
+            status: self.status.bits(),
+            emulation: self.emulation,
+        }
+    }
+
+    // Loads every register at once, e.g. to set up a test. As with the
+    // individual setters, no other state is adjusted to match.
+    pub fn set_state(&mut self, state: &CpuState) {
+        self.set_accumulator(state.a);
+        self.x = state.x;
+        self.y = state.y;
+        self.pc = state.pc;
+        self.sp = state.sp;
+        self.direct_page = state.direct_page;
+        self.program_bank = state.program_bank;
+        self.data_bank = state.data_bank;
+        self.status = Flags::from_bits_retain(state.status);
+        self.emulation = state.emulation;
+    }
+
+    // The complete architectural state, for saving and for test harnesses.
+    // This is the same as state(), but named to pair with load_state.
+    pub fn save_state(&self) -> CpuState {
+        self.state()
+    }
+
+    // Loads every register, then forces the result into something the
+    // hardware could actually be in. Any state that came from save_state
+    // already is, so the two round-trip exactly.
+    pub fn load_state(&mut self, state: &CpuState) {
+        self.set_state(state);
+        self.enforce_mode();
+    }
+
+    // Emulation mode pins M and X to 1 and the stack to page 1, and 8-bit
+    // index registers have no high byte. The high byte of the accumulator
+    // survives in B either way. Anything that changes the flags or the mode
+    // goes through this, as the hardware can't be in any other state.
+    fn enforce_mode(&mut self) {
+        if self.emulation {
+            self.status
+                .insert(Flags::MEMORY_SELECT | Flags::INDEX_REGISTER);
+            self.sp = 0x0100 | (self.sp & 0xFF);
+        }
+
+        if self.status.contains(Flags::INDEX_REGISTER) {
+            self.x &= 0xFF;
+            self.y &= 0xFF;
+        }
+    }
+
+    // The calls that are still in progress, outermost first. Frames whose
+    // return address has been pulled off the stack are left out, even if
+    // the return didn't go through RTS/RTL.
+    pub fn call_stack(&self) -> &[CallFrame] {
+        let live = self
+            .call_stack
+            .iter()
+            .take_while(|frame| frame.sp > self.sp)
+            .count();
+
+        &self.call_stack[..live]
+    }
+
+    // Takes the stack pointer from before anything was pushed for the call.
+    fn enter_call(&mut self, caller: u32, target: u32, sp: u16, kind: CallKind) {
+        self.leave_calls();
+
+        self.call_stack.push(CallFrame {
+            caller,
+            target,
+            sp,
+            kind,
+        });
+    }
+
+    // Drops any frames that the stack pointer has moved back past.
+    fn leave_calls(&mut self) {
+        while self
+            .call_stack
+            .last()
+            .is_some_and(|frame| frame.sp <= self.sp)
+        {
+            self.call_stack.pop();
+        }
+    }
+
+    pub fn operand(&self) -> Option<Operand> {
+        self.operand
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Lets time pass without running anything, e.g. while DMA has the bus.
+    pub fn stall(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+    }
+
+    pub fn raise_nmi(&mut self) {
+        self.nmi = true;
+    }
+
+    // The interrupt that will be taken instead of the next instruction, if
+    // any. IRQ is sampled between instructions, so it's taken straight after
+    // CLI (or PLP/RTI) clears the I flag, without the one instruction delay
+    // that the 6502 has, and an IRQ that was dropped before then is never
+    // taken. Setting the flag blocks an IRQ that's already pending.
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        if self.nmi {
+            Some(Interrupt::Nmi)
+        } else if self.irq && !self.status.contains(Flags::IRQ_DISABLE) {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
+    }
+
+    // True while WAI is waiting for an interrupt, in which case ticking the
+    // CPU doesn't run any instructions.
+    pub fn waiting(&self) -> bool {
+        self.waiting
+    }
+
+    // Pushes the return address and status, then jumps to the handler.
+    //
+    // In emulation mode, the program bank isn't pushed, bit 5 of the pushed
+    // status is always set, and bit 4 is set for BRK but clear for IRQ and
+    // NMI. That's the only way a handler can tell BRK and IRQ apart.
+    fn interrupt(&mut self, mmu: &mut impl Bus, interrupt: Interrupt) {
+        let caller = match interrupt {
+            Interrupt::Break => bank_addr(self.program_bank, self.pc.wrapping_sub(2)),
+            Interrupt::Irq | Interrupt::Nmi => self.current_addr(),
+        };
+
+        let sp = self.sp;
+
+        if !self.emulation {
+            self.push_u8(mmu, self.program_bank);
+        }
+
+        self.push_u16(mmu, self.pc);
+
+        let mut status = self.status.bits();
+
+        if self.emulation {
+            status |= Flags::UNUSED.bits();
+            status &= !Flags::BREAK_FLAG.bits();
+
+            if interrupt == Interrupt::Break {
+                status |= Flags::BREAK_FLAG.bits();
+            }
+        }
+
+        self.push_u8(mmu, status);
+
+        self.status.insert(Flags::IRQ_DISABLE);
+        self.status.remove(Flags::DECIMAL_MODE);
+
+        let vector = interrupt.vector(self.emulation);
+
+        self.program_bank = 0;
+        self.pc = self.read_u16(mmu, vector as u32);
+
+        let kind = CallKind::Interrupt {
+            emulation: self.emulation,
+        };
+
+        self.enter_call(caller, self.current_addr(), sp, kind);
+    }
+
+    // The high byte of the accumulator, which XBA swaps into A.
+    pub fn b(&self) -> u8 {
+        self.b
+    }
+
+    // The full 16-bit accumulator (C), regardless of the M flag.
+    fn accumulator(&self) -> u16 {
+        u16::from_le_bytes([self.a, self.b])
+    }
+
+    fn set_accumulator(&mut self, value: u16) {
+        [self.a, self.b] = value.to_le_bytes();
     }
 
     pub fn get_register(&self, register: Register) -> u16 {
         match register {
-            Register::A => self.a,
+            Register::A => self.accumulator(),
             Register::D => self.direct_page,
             Register::X => self.x,
             Register::Y => self.y,
         }
     }
 
+    // Sets all 16 bits of a register, whatever its current width.
     pub fn set_register(&mut self, register: Register, value: u16) {
         match register {
-            Register::A => self.a = value,
+            Register::A => self.set_accumulator(value),
             Register::D => self.direct_page = value,
             Register::X => self.x = value,
             Register::Y => self.y = value,
         }
     }
 
+    // Writes the result of an instruction to a register at its current
+    // width. In 8-bit mode, only A is written and B is kept, while the high
+    // byte of the index registers is always zero.
+    fn write_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::A if self.is_eight_bit_mode(Register::A) => self.a = value as u8,
+            Register::X | Register::Y if self.is_eight_bit_mode(register) => {
+                self.set_register(register, value & 0xFF)
+            }
+            _ => self.set_register(register, value),
+        }
+    }
+
+    // Sets N and Z for a value at the register's current width.
+    fn set_nz(&mut self, register: Register, value: u16) {
+        #[cfg(feature = "ops-audit")]
+        let before = self.status;
+
+        if self.is_eight_bit_mode(register) {
+            self.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
+            self.status.set(Flags::ZERO, value & 0xFF == 0);
+        } else {
+            self.status.set(Flags::NEGATIVE, (value >> 15) & 1 == 1);
+            self.status.set(Flags::ZERO, value == 0);
+        }
+
+        #[cfg(feature = "ops-audit")]
+        audit::inline_nz(before, self.status, value, self.is_eight_bit_mode(register));
+    }
+
     pub fn is_eight_bit_mode(&self, register: Register) -> bool {
         match register {
             Register::A => self.emulation || self.status.contains(Flags::MEMORY_SELECT),
@@ -224,668 +821,826 @@ impl Cpu {
         }
     }
 
-    pub fn tick(&mut self, mmu: &mut Mmu) {
-        let opcode = self.fetch_u8(mmu);
-        let inst = Instruction::from_opcode(opcode);
+    pub fn tick<B: Bus>(&mut self, mmu: &mut B) -> u64 {
+        let start_cycles = self.cycles;
+        self.operand = None;
 
-        match inst {
-            Instruction::Unknown { .. } => {}
-
-            Instruction::LoadAImmediate => {
-                if self.is_eight_bit_mode(Register::A) {
-                    self.load(mmu, Register::A, AddressingMode::Immediate8);
-                } else {
-                    self.load(mmu, Register::A, AddressingMode::Immediate16);
-                }
-            }
+        // TODO: Count internal operation cycles per instruction
+        self.cycles += 6;
 
-            Instruction::LoadAAbsolute => {
-                self.load(mmu, Register::A, AddressingMode::Absolute);
+        // Any interrupt wakes the CPU from WAI, even an IRQ while the I flag
+        // is set. In that case it carries on with the next instruction
+        // rather than vectoring, which games use to sync up with the IRQ
+        // without having to handle it.
+        if self.waiting {
+            if !self.nmi && !self.irq {
+                return self.cycles - start_cycles;
             }
 
-            Instruction::LoadADirectPage => {
-                self.load(mmu, Register::A, AddressingMode::DirectPage);
-            }
+            self.waiting = false;
+        }
 
-            Instruction::LoadADirectPageIndirectLong => {
-                self.load(mmu, Register::A, AddressingMode::DirectPageIndirectLong);
+        if let Some(interrupt) = self.pending_interrupt() {
+            if interrupt == Interrupt::Nmi {
+                self.nmi = false;
             }
 
-            Instruction::LoadAAbsoluteIndexedX => {
-                self.load(mmu, Register::A, AddressingMode::AbsoluteIndexedX);
-            }
+            self.interrupt(mmu, interrupt);
 
-            Instruction::LoadAAbsoluteLongIndexedX => {
-                self.load(mmu, Register::A, AddressingMode::AbsoluteLongIndexedX);
-            }
+            return self.cycles - start_cycles;
+        }
 
-            Instruction::LoadAAbsoluteIndexedY => {
-                self.load(mmu, Register::A, AddressingMode::AbsoluteIndexedY);
-            }
+        let opcode = self.fetch_opcode(mmu);
+        Dispatch::<B>::HANDLERS[opcode as usize](self, mmu);
 
-            Instruction::LoadXImmediate => {
-                if self.is_eight_bit_mode(Register::X) {
-                    self.load(mmu, Register::X, AddressingMode::Immediate8);
-                } else {
-                    self.load(mmu, Register::X, AddressingMode::Immediate16);
-                }
-            }
+        self.cycles - start_cycles
+    }
 
-            Instruction::LoadXDirectPage => {
-                self.load(mmu, Register::X, AddressingMode::DirectPage);
-            }
+    fn load(&mut self, mmu: &mut impl Bus, register: Register, addr_mode: AddressingMode) {
+        let addr = self.fetch_operand(mmu, addr_mode, OperandAccess::Read);
 
-            Instruction::LoadYImmediate => {
-                if self.is_eight_bit_mode(Register::Y) {
-                    self.load(mmu, Register::Y, AddressingMode::Immediate8);
-                } else {
-                    self.load(mmu, Register::Y, AddressingMode::Immediate16);
-                }
-            }
+        let value = if self.is_eight_bit_mode(register) {
+            self.read_u8(mmu, addr) as u16
+        } else {
+            self.read_u16(mmu, addr)
+        };
 
-            Instruction::LoadYDirectPage => {
-                self.load(mmu, Register::Y, AddressingMode::DirectPage);
-            }
+        self.write_register(register, value);
+        self.set_nz(register, value);
+    }
 
-            Instruction::StoreAAbsolute => {
-                self.store(mmu, Register::A, AddressingMode::Absolute);
-            }
+    fn pull(&mut self, mmu: &mut impl Bus, register: Register) {
+        let value = if self.is_eight_bit_mode(register) {
+            self.pull_u8(mmu) as u16
+        } else {
+            self.pull_u16(mmu)
+        };
 
-            Instruction::StoreADirectPage => {
-                self.store(mmu, Register::A, AddressingMode::DirectPage);
-            }
+        self.write_register(register, value);
+        self.set_nz(register, value);
+    }
 
-            Instruction::StoreAAbsoluteIndexedX => {
-                self.store(mmu, Register::A, AddressingMode::AbsoluteIndexedX);
-            }
+    fn transfer(&mut self, from: Register, to: Register) {
+        let value = self.get_register(from);
 
-            Instruction::StoreAAbsoluteLongIndexedX => {
-                self.store(mmu, Register::A, AddressingMode::AbsoluteLongIndexedX);
-            }
+        self.write_register(to, value);
+        self.set_nz(to, value);
+    }
 
-            Instruction::StoreAAbsoluteIndexedY => {
-                self.store(mmu, Register::A, AddressingMode::AbsoluteIndexedY);
-            }
+    fn store(&mut self, mmu: &mut impl Bus, register: Register, addr_mode: AddressingMode) {
+        let addr = self.fetch_operand(mmu, addr_mode, OperandAccess::Write);
 
-            Instruction::StoreADirectPageIndexedX => {
-                self.store(mmu, Register::A, AddressingMode::DirectPageIndexedX);
-            }
+        if self.is_eight_bit_mode(register) {
+            self.store_u8(mmu, addr, self.get_register(register) as u8);
+        } else {
+            self.store_u16(mmu, addr, self.get_register(register));
+        }
+    }
 
-            Instruction::StoreXAbsolute => {
-                self.store(mmu, Register::X, AddressingMode::Absolute);
-            }
+    fn store_zero(&mut self, mmu: &mut impl Bus, addr_mode: AddressingMode) {
+        let addr = self.fetch_operand(mmu, addr_mode, OperandAccess::Write);
 
-            Instruction::StoreXDirectPage => {
-                self.store(mmu, Register::X, AddressingMode::DirectPage);
-            }
+        if self.is_eight_bit_mode(Register::A) {
+            self.store_u8(mmu, addr, 0);
+        } else {
+            self.store_u16(mmu, addr, 0);
+        }
+    }
 
-            Instruction::StoreYDirectPage => {
-                self.store(mmu, Register::Y, AddressingMode::DirectPage);
-            }
+    // Runs an ALU operation on A and a value from memory, at the width of A,
+    // and puts the result back in A.
+    fn accumulate(
+        &mut self,
+        mmu: &mut impl Bus,
+        addr_mode: AddressingMode,
+        op8: fn(&mut Flags, u8, u8) -> u8,
+        op16: fn(&mut Flags, u16, u16) -> u16,
+    ) {
+        let addr = self.fetch_operand(mmu, addr_mode, OperandAccess::Read);
 
-            Instruction::StoreZeroAbsolute => {
-                self.store_zero(mmu, AddressingMode::Absolute);
-            }
+        if self.is_eight_bit_mode(Register::A) {
+            let value = self.read_u8(mmu, addr);
+            self.a = op8(&mut self.status, self.a, value);
+        } else {
+            let lhs = self.accumulator();
+            let rhs = self.read_u16(mmu, addr);
+            let result = op16(&mut self.status, lhs, rhs);
 
-            Instruction::StoreZeroDirectPage => {
-                self.store_zero(mmu, AddressingMode::DirectPage);
-            }
+            self.set_accumulator(result);
+        }
+    }
 
-            Instruction::StoreZeroAbsoluteIndexedX => {
-                self.store_zero(mmu, AddressingMode::AbsoluteIndexedX);
-            }
+    // Like accumulate, but the immediate operand's size follows M.
+    fn accumulate_immediate(
+        &mut self,
+        mmu: &mut impl Bus,
+        op8: fn(&mut Flags, u8, u8) -> u8,
+        op16: fn(&mut Flags, u16, u16) -> u16,
+    ) {
+        if self.is_eight_bit_mode(Register::A) {
+            self.accumulate(mmu, AddressingMode::Immediate8, op8, op16);
+        } else {
+            self.accumulate(mmu, AddressingMode::Immediate16, op8, op16);
+        }
+    }
 
-            Instruction::StoreZeroDirectPageIndexedX => {
-                self.store_zero(mmu, AddressingMode::DirectPageIndexedX);
-            }
+    // Shifts and rotates of A, at its width.
+    fn shift_a(&mut self, op8: fn(&mut Flags, u8) -> u8, op16: fn(&mut Flags, u16) -> u16) {
+        if self.is_eight_bit_mode(Register::A) {
+            self.a = op8(&mut self.status, self.a);
+        } else {
+            let value = self.accumulator();
+            let result = op16(&mut self.status, value);
 
-            Instruction::AddWithCarryImmediate => {
-                if self.is_eight_bit_mode(Register::A) {
-                    self.add_with_carry(mmu, AddressingMode::Immediate8);
-                } else {
-                    self.add_with_carry(mmu, AddressingMode::Immediate16);
-                }
-            }
+            self.set_accumulator(result);
+        }
+    }
 
-            Instruction::AddWithCarryAbsolute => {
-                self.add_with_carry(mmu, AddressingMode::Absolute);
-            }
+    fn test_bits(&mut self, mmu: &mut impl Bus, addr_mode: AddressingMode) {
+        let addr = self.fetch_operand(mmu, addr_mode, OperandAccess::Read);
 
-            Instruction::AddWithCarryDirectPage => {
-                self.add_with_carry(mmu, AddressingMode::DirectPage);
-            }
+        if self.is_eight_bit_mode(Register::A) {
+            let rhs = self.read_u8(mmu, addr);
+            alu::bit8(&mut self.status, self.a, rhs);
+        } else {
+            let lhs = self.accumulator();
+            let rhs = self.read_u16(mmu, addr);
 
-            Instruction::AddWithCarryAbsoluteIndexedY => {
-                self.add_with_carry(mmu, AddressingMode::AbsoluteIndexedY);
-            }
+            alu::bit16(&mut self.status, lhs, rhs);
+        }
+    }
 
-            Instruction::AddWithCarryDirectPageIndexedX => {
-                self.add_with_carry(mmu, AddressingMode::DirectPageIndexedX);
-            }
+    fn inc_dec_register(&mut self, register: Register, amount: i8) {
+        if self.is_eight_bit_mode(register) {
+            let value = self.get_register(register) as u8;
 
-            Instruction::IncrementDirectPage => {
-                self.inc_dec_memory(mmu, AddressingMode::DirectPage, 1);
-            }
+            let value = match amount {
+                1 => alu::inc8(&mut self.status, value),
+                _ => alu::dec8(&mut self.status, value),
+            };
 
-            Instruction::IncrementA => {
-                self.inc_dec_register(Register::A, 1);
-            }
+            self.write_register(register, value as u16);
+        } else {
+            let value = self.get_register(register);
 
-            Instruction::IncrementX => {
-                self.inc_dec_register(Register::X, 1);
-            }
+            let value = match amount {
+                1 => alu::inc16(&mut self.status, value),
+                _ => alu::dec16(&mut self.status, value),
+            };
 
-            Instruction::IncrementY => {
-                self.inc_dec_register(Register::Y, 1);
-            }
+            self.set_register(register, value);
+        }
+    }
 
-            Instruction::DecrementX => {
-                self.inc_dec_register(Register::X, -1);
-            }
+    fn inc_dec_memory(&mut self, mmu: &mut impl Bus, addr_mode: AddressingMode, amount: i8) {
+        // TODO: Can this be 16-bit?
 
-            Instruction::DecrementY => {
-                self.inc_dec_register(Register::Y, -1);
-            }
+        let addr = self.fetch_operand(mmu, addr_mode, OperandAccess::Modify);
+        let value = self.read_u8(mmu, addr);
 
-            Instruction::ShiftLeft => {
-                // TODO: 16 bit mode
-                let original = self.a as u8;
-                let value = original << 1;
+        let value = match amount {
+            1 => alu::inc8(&mut self.status, value),
+            _ => alu::dec8(&mut self.status, value),
+        };
 
-                self.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
-                self.status.set(Flags::ZERO, self.y == 0);
-                self.status.set(Flags::CARRY, (original >> 7) & 1 == 1);
+        self.store_u8(mmu, addr, value);
+    }
 
-                self.a = value as u16;
-            }
+    fn compare(&mut self, mmu: &mut impl Bus, register: Register, addr_mode: AddressingMode) {
+        let addr = self.fetch_operand(mmu, addr_mode, OperandAccess::Read);
 
-            Instruction::MoveAX => {
-                // TODO: 8 bit mode
-                self.x = self.a;
+        if self.is_eight_bit_mode(register) {
+            let lhs = self.get_register(register) as u8;
+            let rhs = self.read_u8(mmu, addr);
 
-                self.status.set(Flags::NEGATIVE, (self.x >> 15) & 1 == 1);
-                self.status.set(Flags::ZERO, self.x == 0);
-            }
+            alu::cmp8(&mut self.status, lhs, rhs);
+        } else {
+            let lhs = self.get_register(register);
+            let rhs = self.read_u16(mmu, addr);
 
-            Instruction::MoveAY => {
-                // TODO: 8 bit mode
-                self.y = self.a;
+            alu::cmp16(&mut self.status, lhs, rhs);
+        }
+    }
 
-                self.status.set(Flags::NEGATIVE, (self.y >> 15) & 1 == 1);
-                self.status.set(Flags::ZERO, self.y == 0);
-            }
+    fn branch(&mut self, mmu: &mut impl Bus, should_branch: bool) {
+        let offset = self.fetch_u8(mmu);
 
-            Instruction::MoveDA => {
-                // NOTE: This is always 16 bit, regardless of flags
-                self.a = self.direct_page;
+        if should_branch {
+            let sign_bit = offset >> 7;
 
-                self.status.set(Flags::NEGATIVE, (self.a >> 15) & 1 == 1);
-                self.status.set(Flags::ZERO, self.a == 0);
+            // TODO: Is this overflow behaviour right, or should it increment the bank?
+            if sign_bit == 1 {
+                self.pc = self.pc.wrapping_sub((!offset + 1) as u16);
+            } else {
+                self.pc = self.pc.wrapping_add(offset as u16);
             }
+        }
+    }
 
-            Instruction::MoveXSP => {
-                // TODO: Emulation mode
-                self.sp = self.x;
-                self.sp_base = self.x;
+    pub fn register_debug(&self) -> String {
+        let mut output = String::new();
+        let _ = self.register_debug_to(&mut output);
 
-                self.status.set(Flags::NEGATIVE, (self.sp >> 15) & 1 == 1);
-                self.status.set(Flags::ZERO, self.sp == 0);
-            }
+        output
+    }
 
-            Instruction::MoveYA => {
-                if self.is_eight_bit_mode(Register::A) || self.is_eight_bit_mode(Register::Y) {
-                    // TODO: This shouldn't wipe out the high byte when A is 8bit.
-                    self.a = self.y & 0x00FF;
-                } else {
-                    self.a = self.y;
-                }
-            }
+    // The same as register_debug, but written into an existing buffer, so
+    // that tracing doesn't have to allocate for every instruction.
+    pub fn register_debug_to(&self, output: &mut impl fmt::Write) -> fmt::Result {
+        self.state().register_debug_to(output)
+    }
 
-            Instruction::ExchangeBA => {
-                self.a = (self.a << 8) | (self.a >> 8);
+    // Captures the top of the stack, up to where TXS last put it. The stack
+    // is always in bank 0, and is kept to page 1 in emulation mode. If SP
+    // has been moved above that point, the rest of its page is shown
+    // instead.
+    pub fn stack(&self, mmu: &impl Bus) -> Stack {
+        let top = self.sp as u32 + 1;
 
-                // TODO: I don't think these are right
-                self.status.set(Flags::NEGATIVE, (self.a & 1) == 1);
-                self.status.set(Flags::ZERO, (self.a & 1) == 1);
-            }
+        let mut end = match self.sp < self.sp_base {
+            true => self.sp_base as u32,
+            false => self.sp as u32 | 0xFF,
+        };
 
-            Instruction::BlockMoveNext => {
-                // TODO: 8 bit index registers - tbh I'm not sure about this one
-                let dest = self.fetch_u8(mmu);
-                let src = self.fetch_u8(mmu);
+        if self.emulation {
+            end = end.min(0x1FF);
+        }
 
-                self.data_bank = dest;
+        let len = (end + 1).saturating_sub(top);
+        let captured = len.min(STACK_LIMIT as u32);
 
-                while self.a != 0xFFFF {
-                    // TODO: Add a way to break out of this if it gets stuck
+        let bytes = (top..top + captured)
+            .map(|addr| mmu.peek_u8(addr))
+            .collect();
 
-                    let value = mmu.read_u8(bank_addr(src, self.x));
-                    mmu.store_u8(bank_addr(self.data_bank, self.y), value);
+        let mut stack = Stack::new(self.sp, bytes, captured < len);
+        stack.find_returns(mmu, self.call_stack(), self.program_bank);
 
-                    self.a = self.a.wrapping_sub(1);
-                    self.x = self.x.wrapping_add(1);
-                    self.y = self.y.wrapping_add(1);
-                }
-            }
+        stack
+    }
 
-            Instruction::CompareImmediate => {
-                if self.is_eight_bit_mode(Register::A) {
-                    self.compare(mmu, Register::A, AddressingMode::Immediate8);
-                } else {
-                    self.compare(mmu, Register::A, AddressingMode::Immediate16);
-                }
-            }
+    // Decodes the instruction that will run next, sized by the current M and
+    // X flags. Nothing is read through the bus, so this can be called as
+    // often as needed without changing what the instruction then does.
+    pub fn peek_next(&self, mmu: &impl Bus) -> DecodedInstruction {
+        let disassembly = disasm::disassemble(self, mmu);
 
-            Instruction::CompareAbsolute => {
-                self.compare(mmu, Register::A, AddressingMode::Absolute);
-            }
-
-            Instruction::CompareDirectPage => {
-                self.compare(mmu, Register::A, AddressingMode::DirectPage);
-            }
+        DecodedInstruction {
+            instruction: Instruction::from_opcode(disassembly.opcode()),
+            disassembly,
+        }
+    }
+}
 
-            Instruction::CompareAbsoluteLongIndexedX => {
-                self.compare(mmu, Register::A, AddressingMode::AbsoluteLongIndexedX);
-            }
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
+}
 
-            Instruction::CompareDirectPageIndexedX => {
-                self.compare(mmu, Register::A, AddressingMode::DirectPageIndexedX);
-            }
+// Runs one instruction, once its opcode has been fetched.
+type Handler<B> = fn(&mut Cpu, &mut B);
 
-            Instruction::CompareXImmediate => {
-                if self.is_eight_bit_mode(Register::X) {
-                    self.compare(mmu, Register::X, AddressingMode::Immediate8);
-                } else {
-                    self.compare(mmu, Register::X, AddressingMode::Immediate16);
-                }
-            }
+// The handler for every opcode, built at compile time from the decode table,
+// so that running an instruction is a single lookup and call rather than
+// decoding it and then matching on the result. There's one table for each
+// kind of bus.
+struct Dispatch<B>(PhantomData<B>);
 
-            Instruction::CompareYImmediate => {
-                if self.is_eight_bit_mode(Register::Y) {
-                    self.compare(mmu, Register::Y, AddressingMode::Immediate8);
-                } else {
-                    self.compare(mmu, Register::Y, AddressingMode::Immediate16);
-                }
-            }
+impl<B: Bus> Dispatch<B> {
+    const HANDLERS: [Handler<B>; 256] = {
+        let mut handlers = [handler::<B>(Instruction::Unknown); 256];
+        let mut opcode = 0;
 
-            Instruction::BranchCarryClear => {
-                self.branch(mmu, !self.status.contains(Flags::CARRY));
-            }
+        while opcode < 256 {
+            handlers[opcode] = handler(Instruction::from_opcode(opcode as u8));
+            opcode += 1;
+        }
 
-            Instruction::BranchCarrySet => {
-                self.branch(mmu, self.status.contains(Flags::CARRY));
-            }
+        handlers
+    };
+}
 
-            Instruction::BranchNotEqual => {
-                self.branch(mmu, !self.status.contains(Flags::ZERO));
-            }
+// What each instruction does.
+const fn handler<B: Bus>(instruction: Instruction) -> Handler<B> {
+    match instruction {
+        Instruction::Unknown => |_, _| {},
 
-            Instruction::BranchEqual => {
-                self.branch(mmu, self.status.contains(Flags::ZERO));
+        Instruction::LoadAImmediate => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::A) {
+                cpu.load(mmu, Register::A, AddressingMode::Immediate8);
+            } else {
+                cpu.load(mmu, Register::A, AddressingMode::Immediate16);
             }
+        },
 
-            Instruction::BranchAlways => {
-                self.branch(mmu, true);
-            }
+        Instruction::LoadAAbsolute => |cpu, mmu| {
+            cpu.load(mmu, Register::A, AddressingMode::Absolute);
+        },
 
-            Instruction::PushA => {
-                if self.is_eight_bit_mode(Register::A) {
-                    self.push_u8(mmu, self.a as u8);
-                } else {
-                    self.push_u16(mmu, self.a);
-                }
-            }
+        Instruction::LoadADirectPage => |cpu, mmu| {
+            cpu.load(mmu, Register::A, AddressingMode::DirectPage);
+        },
 
-            Instruction::PushB => {
-                self.push_u8(mmu, self.data_bank);
-            }
+        Instruction::LoadADirectPageIndirectLong => |cpu, mmu| {
+            cpu.load(mmu, Register::A, AddressingMode::DirectPageIndirectLong);
+        },
 
-            Instruction::PushD => {
-                self.push_u16(mmu, self.direct_page);
-            }
+        Instruction::LoadAAbsoluteIndexedX => |cpu, mmu| {
+            cpu.load(mmu, Register::A, AddressingMode::AbsoluteIndexedX);
+        },
 
-            Instruction::PushX => {
-                if self.is_eight_bit_mode(Register::X) {
-                    self.push_u8(mmu, self.x as u8);
-                } else {
-                    self.push_u16(mmu, self.x);
-                }
-            }
+        Instruction::LoadAAbsoluteLongIndexedX => |cpu, mmu| {
+            cpu.load(mmu, Register::A, AddressingMode::AbsoluteLongIndexedX);
+        },
 
-            Instruction::PushY => {
-                if self.is_eight_bit_mode(Register::Y) {
-                    self.push_u8(mmu, self.y as u8);
-                } else {
-                    self.push_u16(mmu, self.y);
-                }
-            }
+        Instruction::LoadAAbsoluteIndexedY => |cpu, mmu| {
+            cpu.load(mmu, Register::A, AddressingMode::AbsoluteIndexedY);
+        },
 
-            Instruction::PushStatus => {
-                self.push_u8(mmu, self.status.bits());
+        Instruction::LoadXImmediate => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::X) {
+                cpu.load(mmu, Register::X, AddressingMode::Immediate8);
+            } else {
+                cpu.load(mmu, Register::X, AddressingMode::Immediate16);
             }
+        },
 
-            Instruction::PushAbsolute => {
-                let addr = self.fetch_u16(mmu);
+        Instruction::LoadXDirectPage => |cpu, mmu| {
+            cpu.load(mmu, Register::X, AddressingMode::DirectPage);
+        },
 
-                self.push_u16(mmu, addr);
-            }
-
-            Instruction::PullA => {
-                self.pull(mmu, Register::A);
+        Instruction::LoadYImmediate => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::Y) {
+                cpu.load(mmu, Register::Y, AddressingMode::Immediate8);
+            } else {
+                cpu.load(mmu, Register::Y, AddressingMode::Immediate16);
             }
+        },
+
+        Instruction::LoadYDirectPage => |cpu, mmu| {
+            cpu.load(mmu, Register::Y, AddressingMode::DirectPage);
+        },
+
+        Instruction::StoreAAbsolute => |cpu, mmu| {
+            cpu.store(mmu, Register::A, AddressingMode::Absolute);
+        },
+
+        Instruction::StoreADirectPage => |cpu, mmu| {
+            cpu.store(mmu, Register::A, AddressingMode::DirectPage);
+        },
+
+        Instruction::StoreAAbsoluteIndexedX => |cpu, mmu| {
+            cpu.store(mmu, Register::A, AddressingMode::AbsoluteIndexedX);
+        },
+
+        Instruction::StoreAAbsoluteLongIndexedX => |cpu, mmu| {
+            cpu.store(mmu, Register::A, AddressingMode::AbsoluteLongIndexedX);
+        },
+
+        Instruction::StoreAAbsoluteIndexedY => |cpu, mmu| {
+            cpu.store(mmu, Register::A, AddressingMode::AbsoluteIndexedY);
+        },
+
+        Instruction::StoreADirectPageIndexedX => |cpu, mmu| {
+            cpu.store(mmu, Register::A, AddressingMode::DirectPageIndexedX);
+        },
+
+        Instruction::StoreXAbsolute => |cpu, mmu| {
+            cpu.store(mmu, Register::X, AddressingMode::Absolute);
+        },
+
+        Instruction::StoreXDirectPage => |cpu, mmu| {
+            cpu.store(mmu, Register::X, AddressingMode::DirectPage);
+        },
+
+        Instruction::StoreYDirectPage => |cpu, mmu| {
+            cpu.store(mmu, Register::Y, AddressingMode::DirectPage);
+        },
+
+        Instruction::StoreZeroAbsolute => |cpu, mmu| {
+            cpu.store_zero(mmu, AddressingMode::Absolute);
+        },
+
+        Instruction::StoreZeroDirectPage => |cpu, mmu| {
+            cpu.store_zero(mmu, AddressingMode::DirectPage);
+        },
+
+        Instruction::StoreZeroAbsoluteIndexedX => |cpu, mmu| {
+            cpu.store_zero(mmu, AddressingMode::AbsoluteIndexedX);
+        },
+
+        Instruction::StoreZeroDirectPageIndexedX => |cpu, mmu| {
+            cpu.store_zero(mmu, AddressingMode::DirectPageIndexedX);
+        },
+
+        Instruction::AddWithCarryImmediate => |cpu, mmu| {
+            cpu.accumulate_immediate(mmu, alu::adc8, alu::adc16);
+        },
+
+        Instruction::AddWithCarryAbsolute => |cpu, mmu| {
+            cpu.accumulate(mmu, AddressingMode::Absolute, alu::adc8, alu::adc16);
+        },
+
+        Instruction::AddWithCarryDirectPage => |cpu, mmu| {
+            cpu.accumulate(mmu, AddressingMode::DirectPage, alu::adc8, alu::adc16);
+        },
+
+        Instruction::AddWithCarryAbsoluteIndexedY => |cpu, mmu| {
+            cpu.accumulate(mmu, AddressingMode::AbsoluteIndexedY, alu::adc8, alu::adc16);
+        },
+
+        Instruction::AddWithCarryDirectPageIndexedX => |cpu, mmu| {
+            cpu.accumulate(
+                mmu,
+                AddressingMode::DirectPageIndexedX,
+                alu::adc8,
+                alu::adc16,
+            );
+        },
+
+        Instruction::SubtractWithCarryImmediate => |cpu, mmu| {
+            cpu.accumulate_immediate(mmu, alu::sbc8, alu::sbc16);
+        },
+
+        Instruction::SubtractWithCarryDirectPage => |cpu, mmu| {
+            cpu.accumulate(mmu, AddressingMode::DirectPage, alu::sbc8, alu::sbc16);
+        },
 
-            Instruction::PullB => {
-                // TODO: Can't use helper function here because target is a u8
-                let value = self.pull_u8(mmu);
+        Instruction::IncrementDirectPage => |cpu, mmu| {
+            cpu.inc_dec_memory(mmu, AddressingMode::DirectPage, 1);
+        },
 
-                self.data_bank = value;
+        Instruction::IncrementA => |cpu, _| {
+            cpu.inc_dec_register(Register::A, 1);
+        },
 
-                self.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
-                self.status.set(Flags::ZERO, value == 0);
-            }
+        Instruction::IncrementX => |cpu, _| {
+            cpu.inc_dec_register(Register::X, 1);
+        },
+
+        Instruction::IncrementY => |cpu, _| {
+            cpu.inc_dec_register(Register::Y, 1);
+        },
+
+        Instruction::DecrementX => |cpu, _| {
+            cpu.inc_dec_register(Register::X, -1);
+        },
+
+        Instruction::DecrementY => |cpu, _| {
+            cpu.inc_dec_register(Register::Y, -1);
+        },
+
+        Instruction::ShiftLeft => |cpu, _| {
+            cpu.shift_a(alu::asl8, alu::asl16);
+        },
+
+        Instruction::ShiftRight => |cpu, _| {
+            cpu.shift_a(alu::lsr8, alu::lsr16);
+        },
+
+        Instruction::RotateLeft => |cpu, _| {
+            cpu.shift_a(alu::rol8, alu::rol16);
+        },
+
+        Instruction::RotateRight => |cpu, _| {
+            cpu.shift_a(alu::ror8, alu::ror16);
+        },
+
+        // Transfers are the width of the destination, so with 16-bit
+        // index registers, TAX/TAY copy B as well even if A is 8-bit.
+        Instruction::MoveAX => |cpu, _| {
+            cpu.transfer(Register::A, Register::X);
+        },
+
+        Instruction::MoveAY => |cpu, _| {
+            cpu.transfer(Register::A, Register::Y);
+        },
+
+        Instruction::MoveDA => |cpu, _| {
+            // NOTE: This is always 16 bit, regardless of flags
+            cpu.set_accumulator(cpu.direct_page);
+
+            cpu.set_nz(Register::D, cpu.direct_page);
+        },
+
+        Instruction::MoveXSP => |cpu, _| {
+            // TODO: Emulation mode
+            cpu.sp = cpu.x;
+            cpu.sp_base = cpu.x;
+
+            cpu.status.set(Flags::NEGATIVE, (cpu.sp >> 15) & 1 == 1);
+            cpu.status.set(Flags::ZERO, cpu.sp == 0);
+        },
+
+        Instruction::MoveYA => |cpu, _| {
+            cpu.transfer(Register::Y, Register::A);
+        },
+
+        Instruction::ExchangeBA => |cpu, _| {
+            std::mem::swap(&mut cpu.a, &mut cpu.b);
+
+            // The flags always reflect the new A, even with a 16-bit
+            // accumulator.
+            cpu.status.set(Flags::NEGATIVE, (cpu.a >> 7) & 1 == 1);
+            cpu.status.set(Flags::ZERO, cpu.a == 0);
+        },
+
+        Instruction::BlockMoveNext => |cpu, mmu| {
+            // TODO: 8 bit index registers - tbh I'm not sure about this one
+            let dest = cpu.fetch_u8(mmu);
+            let src = cpu.fetch_u8(mmu);
+
+            cpu.data_bank = dest;
+
+            // The count is always the full 16-bit accumulator.
+            while cpu.accumulator() != 0xFFFF {
+                // TODO: Add a way to break out of this if it gets stuck
 
-            Instruction::PullD => {
-                self.pull(mmu, Register::D);
-            }
+                let value = cpu.read_u8(mmu, bank_addr(src, cpu.x));
+                cpu.store_u8(mmu, bank_addr(cpu.data_bank, cpu.y), value);
 
-            Instruction::PullX => {
-                self.pull(mmu, Register::X);
+                cpu.set_accumulator(cpu.accumulator().wrapping_sub(1));
+                cpu.x = cpu.x.wrapping_add(1);
+                cpu.y = cpu.y.wrapping_add(1);
             }
+        },
 
-            Instruction::PullY => {
-                self.pull(mmu, Register::Y);
-            }
+        Instruction::AndImmediate => |cpu, mmu| {
+            cpu.accumulate_immediate(mmu, alu::and8, alu::and16);
+        },
 
-            Instruction::PullStatus => {
-                let value = self.pull_u8(mmu);
+        Instruction::AndDirectPage => |cpu, mmu| {
+            cpu.accumulate(mmu, AddressingMode::DirectPage, alu::and8, alu::and16);
+        },
 
-                self.status = Flags::from_bits_truncate(value);
+        Instruction::OrImmediate => |cpu, mmu| {
+            cpu.accumulate_immediate(mmu, alu::or8, alu::or16);
+        },
 
-                self.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
-                self.status.set(Flags::ZERO, value == 0);
-            }
+        Instruction::OrDirectPage => |cpu, mmu| {
+            cpu.accumulate(mmu, AddressingMode::DirectPage, alu::or8, alu::or16);
+        },
 
-            Instruction::JumpAbsolute => {
-                let addr = self.fetch_u16(mmu);
+        Instruction::ExclusiveOrImmediate => |cpu, mmu| {
+            cpu.accumulate_immediate(mmu, alu::eor8, alu::eor16);
+        },
 
-                self.pc = addr;
-            }
+        Instruction::ExclusiveOrDirectPage => |cpu, mmu| {
+            cpu.accumulate(mmu, AddressingMode::DirectPage, alu::eor8, alu::eor16);
+        },
 
-            Instruction::JumpSubRoutineAbsolute => {
-                let addr = self.fetch_u16(mmu);
+        Instruction::TestBitsDirectPage => |cpu, mmu| {
+            cpu.test_bits(mmu, AddressingMode::DirectPage);
+        },
 
-                self.push_u16(mmu, self.pc - 1); // TODO: bytes are reversed
+        Instruction::TestBitsAbsolute => |cpu, mmu| {
+            cpu.test_bits(mmu, AddressingMode::Absolute);
+        },
 
-                self.pc = addr;
+        Instruction::CompareImmediate => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::A) {
+                cpu.compare(mmu, Register::A, AddressingMode::Immediate8);
+            } else {
+                cpu.compare(mmu, Register::A, AddressingMode::Immediate16);
             }
+        },
 
-            Instruction::JumpSubRoutineAbsoluteLong => {
-                let addr = self.fetch_u16(mmu);
-                let bank = self.fetch_u8(mmu);
+        Instruction::CompareAbsolute => |cpu, mmu| {
+            cpu.compare(mmu, Register::A, AddressingMode::Absolute);
+        },
 
-                self.push_u16(mmu, self.pc - 1); // TODO: bytes are reversed
-                self.push_u8(mmu, self.program_bank);
+        Instruction::CompareDirectPage => |cpu, mmu| {
+            cpu.compare(mmu, Register::A, AddressingMode::DirectPage);
+        },
 
-                self.program_bank = bank;
-                self.pc = addr;
-            }
+        Instruction::CompareAbsoluteLongIndexedX => |cpu, mmu| {
+            cpu.compare(mmu, Register::A, AddressingMode::AbsoluteLongIndexedX);
+        },
 
-            Instruction::Return => {
-                let addr = self.pull_u16(mmu);
+        Instruction::CompareDirectPageIndexedX => |cpu, mmu| {
+            cpu.compare(mmu, Register::A, AddressingMode::DirectPageIndexedX);
+        },
 
-                self.pc = addr.wrapping_add(1);
+        Instruction::CompareXImmediate => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::X) {
+                cpu.compare(mmu, Register::X, AddressingMode::Immediate8);
+            } else {
+                cpu.compare(mmu, Register::X, AddressingMode::Immediate16);
             }
+        },
 
-            Instruction::ReturnLong => {
-                let bank = self.pull_u8(mmu);
-                let addr = self.pull_u16(mmu);
-
-                self.pc = addr.wrapping_add(1);
-                self.program_bank = bank;
+        Instruction::CompareYImmediate => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::Y) {
+                cpu.compare(mmu, Register::Y, AddressingMode::Immediate8);
+            } else {
+                cpu.compare(mmu, Register::Y, AddressingMode::Immediate16);
             }
+        },
 
-            Instruction::ClearCarry => {
-                self.status.remove(Flags::CARRY);
-            }
+        Instruction::BranchCarryClear => |cpu, mmu| {
+            cpu.branch(mmu, !cpu.status.contains(Flags::CARRY));
+        },
 
-            Instruction::SetIrqDisable => {
-                self.status.insert(Flags::IRQ_DISABLE);
-            }
+        Instruction::BranchCarrySet => |cpu, mmu| {
+            cpu.branch(mmu, cpu.status.contains(Flags::CARRY));
+        },
 
-            Instruction::ResetFlags => {
-                let mask = self.fetch_u8(mmu);
+        Instruction::BranchNotEqual => |cpu, mmu| {
+            cpu.branch(mmu, !cpu.status.contains(Flags::ZERO));
+        },
 
-                self.status &= !Flags::from_bits_truncate(mask);
-            }
+        Instruction::BranchEqual => |cpu, mmu| {
+            cpu.branch(mmu, cpu.status.contains(Flags::ZERO));
+        },
 
-            Instruction::SetFlags => {
-                let mask = self.fetch_u8(mmu);
+        Instruction::BranchAlways => |cpu, mmu| {
+            cpu.branch(mmu, true);
+        },
 
-                self.status |= Flags::from_bits_truncate(mask);
+        Instruction::PushA => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::A) {
+                cpu.push_u8(mmu, cpu.a);
+            } else {
+                cpu.push_u16(mmu, cpu.accumulator());
             }
+        },
 
-            Instruction::ExchangeCE => {
-                let carry = self.status.contains(Flags::CARRY);
+        Instruction::PushB => |cpu, mmu| {
+            cpu.push_u8(mmu, cpu.data_bank);
+        },
 
-                self.emulation = carry;
-                self.status.toggle(Flags::CARRY);
-            }
+        Instruction::PushD => |cpu, mmu| {
+            cpu.push_u16(mmu, cpu.direct_page);
+        },
 
-            Instruction::Break => {
-                // TODO: Probably not right but let's see if it works
+        Instruction::PushX => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::X) {
+                cpu.push_u8(mmu, cpu.x as u8);
+            } else {
+                cpu.push_u16(mmu, cpu.x);
             }
-        }
-    }
-
-    pub fn load(&mut self, mmu: &Mmu, register: Register, addr_mode: AddressingMode) {
-        let addr = self.fetch_addr(mmu, addr_mode);
-
-        if self.is_eight_bit_mode(register) {
-            let value = mmu.read_u8(addr);
-
-            self.set_register(register, value as u16);
+        },
 
-            self.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
-            self.status.set(Flags::ZERO, value == 0);
-        } else {
-            let value = mmu.read_u16(addr);
+        Instruction::PushY => |cpu, mmu| {
+            if cpu.is_eight_bit_mode(Register::Y) {
+                cpu.push_u8(mmu, cpu.y as u8);
+            } else {
+                cpu.push_u16(mmu, cpu.y);
+            }
+        },
 
-            self.set_register(register, value);
+        Instruction::PushStatus => |cpu, mmu| {
+            cpu.push_u8(mmu, cpu.status.bits());
+        },
 
-            self.status.set(Flags::NEGATIVE, (value >> 15) & 1 == 1);
-            self.status.set(Flags::ZERO, value == 0);
-        }
-    }
+        Instruction::PushAbsolute => |cpu, mmu| {
+            let addr = cpu.fetch_u16(mmu);
 
-    pub fn pull(&mut self, mmu: &mut Mmu, register: Register) {
-        if self.is_eight_bit_mode(register) {
-            let value = self.pull_u8(mmu);
+            cpu.push_u16(mmu, addr);
+        },
 
-            self.set_register(register, value as u16);
+        Instruction::PullA => |cpu, mmu| {
+            cpu.pull(mmu, Register::A);
+        },
 
-            self.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
-            self.status.set(Flags::ZERO, value == 0);
-        } else {
-            let value = self.pull_u16(mmu);
+        Instruction::PullB => |cpu, mmu| {
+            // TODO: Can't use helper function here because target is a u8
+            let value = cpu.pull_u8(mmu);
 
-            self.set_register(register, value);
+            cpu.data_bank = value;
 
-            self.status.set(Flags::NEGATIVE, (value >> 15) & 1 == 1);
-            self.status.set(Flags::ZERO, value == 0);
-        }
-    }
+            cpu.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
+            cpu.status.set(Flags::ZERO, value == 0);
+        },
 
-    pub fn store(&mut self, mmu: &mut Mmu, register: Register, addr_mode: AddressingMode) {
-        let addr = self.fetch_addr(mmu, addr_mode);
+        Instruction::PullD => |cpu, mmu| {
+            cpu.pull(mmu, Register::D);
+        },
 
-        if self.is_eight_bit_mode(register) {
-            mmu.store_u8(addr, self.get_register(register) as u8);
-        } else {
-            mmu.store_u16(addr, self.get_register(register));
-        }
-    }
+        Instruction::PullX => |cpu, mmu| {
+            cpu.pull(mmu, Register::X);
+        },
 
-    pub fn store_zero(&mut self, mmu: &mut Mmu, addr_mode: AddressingMode) {
-        let addr = self.fetch_addr(mmu, addr_mode);
+        Instruction::PullY => |cpu, mmu| {
+            cpu.pull(mmu, Register::Y);
+        },
 
-        if self.is_eight_bit_mode(Register::A) {
-            mmu.store_u8(addr, 0);
-        } else {
-            mmu.store_u16(addr, 0);
-        }
-    }
+        Instruction::PullStatus => |cpu, mmu| {
+            let value = cpu.pull_u8(mmu);
 
-    pub fn add_with_carry(&mut self, mmu: &Mmu, addr_mode: AddressingMode) {
-        let addr = self.fetch_addr(mmu, addr_mode);
+            cpu.status = Flags::from_bits_retain(value);
+            cpu.enforce_mode();
+        },
 
-        if self.is_eight_bit_mode(Register::A) {
-            let value = mmu.read_u8(addr);
+        Instruction::JumpAbsolute => |cpu, mmu| {
+            let addr = cpu.fetch_u16(mmu);
 
-            let result = (self.a as u8)
-                .wrapping_add(value)
-                .wrapping_add(self.status.contains(Flags::CARRY) as u8);
+            cpu.pc = addr;
+        },
 
-            self.status.set(Flags::NEGATIVE, (result >> 7) & 1 == 1);
-            self.status.set(Flags::ZERO, result == 0);
-            self.status.set(Flags::CARRY, result < self.a as u8);
+        Instruction::JumpSubRoutineAbsolute => |cpu, mmu| {
+            let addr = cpu.fetch_u16(mmu);
 
-            // TODO: Overflow flag
+            let caller = bank_addr(cpu.program_bank, cpu.pc.wrapping_sub(3));
+            let target = bank_addr(cpu.program_bank, addr);
+            cpu.enter_call(caller, target, cpu.sp, CallKind::Subroutine);
 
-            self.a = result as u16;
-        } else {
-            let value = mmu.read_u16(addr);
+            cpu.push_u16(mmu, cpu.pc - 1); // TODO: bytes are reversed
 
-            let result = self
-                .a
-                .wrapping_add(value)
-                .wrapping_add(self.status.contains(Flags::CARRY) as u16);
+            cpu.pc = addr;
+        },
 
-            self.status.set(Flags::NEGATIVE, (result >> 15) & 1 == 1);
-            self.status.set(Flags::ZERO, result == 0);
-            self.status.set(Flags::CARRY, result < self.a);
+        Instruction::JumpSubRoutineAbsoluteLong => |cpu, mmu| {
+            let addr = cpu.fetch_u16(mmu);
+            let bank = cpu.fetch_u8(mmu);
 
-            // TODO: Overflow flag
+            let caller = bank_addr(cpu.program_bank, cpu.pc.wrapping_sub(4));
+            let target = bank_addr(bank, addr);
+            cpu.enter_call(caller, target, cpu.sp, CallKind::Long);
 
-            self.a = result;
-        }
-    }
+            cpu.push_u16(mmu, cpu.pc - 1); // TODO: bytes are reversed
+            cpu.push_u8(mmu, cpu.program_bank);
 
-    pub fn inc_dec_register(&mut self, register: Register, amount: i8) {
-        if self.is_eight_bit_mode(register) {
-            let value = (self.get_register(register) as u8).wrapping_add_signed(amount);
+            cpu.program_bank = bank;
+            cpu.pc = addr;
+        },
 
-            // TODO: This shouldn't wipe out upper byte
-            self.set_register(register, value as u16);
+        Instruction::Return => |cpu, mmu| {
+            let addr = cpu.pull_u16(mmu);
+            cpu.leave_calls();
 
-            self.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
-            self.status.set(Flags::ZERO, value == 0);
-        } else {
-            let value = self
-                .get_register(register)
-                .wrapping_add_signed(amount.into());
+            cpu.pc = addr.wrapping_add(1);
+        },
 
-            self.set_register(register, value);
+        Instruction::ReturnLong => |cpu, mmu| {
+            let bank = cpu.pull_u8(mmu);
+            let addr = cpu.pull_u16(mmu);
+            cpu.leave_calls();
 
-            self.status.set(Flags::NEGATIVE, (value >> 15) & 1 == 1);
-            self.status.set(Flags::ZERO, value == 0);
-        }
-    }
+            cpu.pc = addr.wrapping_add(1);
+            cpu.program_bank = bank;
+        },
 
-    pub fn inc_dec_memory(&mut self, mmu: &mut Mmu, addr_mode: AddressingMode, amount: i8) {
-        // TODO: Can this be 16-bit?
+        Instruction::ClearCarry => |cpu, _| {
+            cpu.status.remove(Flags::CARRY);
+        },
 
-        let addr = self.fetch_addr(mmu, addr_mode);
-        let value = mmu.read_u8(addr).wrapping_add_signed(amount);
+        Instruction::ClearIrqDisable => |cpu, _| {
+            cpu.status.remove(Flags::IRQ_DISABLE);
+        },
 
-        mmu.store_u8(addr, value);
+        Instruction::SetIrqDisable => |cpu, _| {
+            cpu.status.insert(Flags::IRQ_DISABLE);
+        },
 
-        self.status.set(Flags::NEGATIVE, (value >> 7) & 1 == 1);
-        self.status.set(Flags::ZERO, value == 0);
-    }
+        Instruction::ResetFlags => |cpu, mmu| {
+            let mask = cpu.fetch_u8(mmu);
 
-    pub fn compare(&mut self, mmu: &Mmu, register: Register, addr_mode: AddressingMode) {
-        let addr = self.fetch_addr(mmu, addr_mode);
+            cpu.status &= !Flags::from_bits_truncate(mask);
+            cpu.enforce_mode();
+        },
 
-        if self.is_eight_bit_mode(register) {
-            let lhs = self.get_register(register) as u8;
-            let rhs = mmu.read_u8(addr);
+        Instruction::SetFlags => |cpu, mmu| {
+            let mask = cpu.fetch_u8(mmu);
 
-            let result = lhs.wrapping_sub(rhs);
+            cpu.status |= Flags::from_bits_truncate(mask);
+            cpu.enforce_mode();
+        },
 
-            self.status.set(Flags::NEGATIVE, (result >> 7) & 1 == 1);
-            self.status.set(Flags::ZERO, result == 0);
-            self.status.set(Flags::CARRY, lhs >= rhs);
-        } else {
-            let lhs = self.get_register(register);
-            let rhs = mmu.read_u16(addr);
+        Instruction::ExchangeCE => |cpu, _| {
+            let carry = cpu.status.contains(Flags::CARRY);
 
-            let result = lhs.wrapping_sub(rhs);
+            cpu.status.set(Flags::CARRY, cpu.emulation);
+            cpu.emulation = carry;
+            cpu.enforce_mode();
+        },
 
-            self.status.set(Flags::NEGATIVE, (result >> 15) & 1 == 1);
-            self.status.set(Flags::ZERO, result == 0);
-            self.status.set(Flags::CARRY, lhs >= rhs);
-        }
-    }
+        Instruction::Break => |cpu, mmu| {
+            // The byte after BRK is skipped over, so it can be used as
+            // a signature.
+            cpu.fetch_u8(mmu);
 
-    pub fn branch(&mut self, mmu: &Mmu, should_branch: bool) {
-        let offset = self.fetch_u8(mmu);
+            cpu.interrupt(mmu, Interrupt::Break);
+        },
 
-        if should_branch {
-            let sign_bit = offset >> 7;
+        Instruction::WaitForInterrupt => |cpu, _| {
+            cpu.waiting = true;
+        },
 
-            // TODO: Is this overflow behaviour right, or should it increment the bank?
-            if sign_bit == 1 {
-                self.pc = self.pc.wrapping_sub((!offset + 1) as u16);
-            } else {
-                self.pc = self.pc.wrapping_add(offset as u16);
-            }
-        }
-    }
+        Instruction::ReturnFromInterrupt => |cpu, mmu| {
+            let status = cpu.pull_u8(mmu);
+            let addr = cpu.pull_u16(mmu);
 
-    pub fn register_debug(&self) -> String {
-        fn flag_or_empty(flag: &str, value: bool) -> &str {
-            if value {
-                flag
-            } else {
-                ""
+            if !cpu.emulation {
+                cpu.program_bank = cpu.pull_u8(mmu);
             }
-        }
-
-        format!(
-            "A: {:04X} | X: {:04X} | Y: {:04X} | SP: {:04X} | D: {:04X} | DB: {:02X} | PB: {:02X} | Flags: {}{}{}{}{}{}{}{}{}",
-            self.a,
-            self.x,
-            self.y,
-            self.sp,
-            self.direct_page,
-            self.data_bank,
-            self.program_bank,
-            flag_or_empty("N", self.status.contains(Flags::NEGATIVE)),
-            flag_or_empty("V", self.status.contains(Flags::OVERFLOW)),
-            flag_or_empty("M", self.status.contains(Flags::MEMORY_SELECT)),
-            flag_or_empty("X", self.status.contains(Flags::INDEX_REGISTER)),
-            flag_or_empty("D", self.status.contains(Flags::DECIMAL_MODE)),
-            flag_or_empty("I", self.status.contains(Flags::IRQ_DISABLE)),
-            flag_or_empty("Z", self.status.contains(Flags::ZERO)),
-            flag_or_empty("C", self.status.contains(Flags::CARRY)),
-            flag_or_empty("E", self.emulation),
-        )
-    }
 
-    pub fn stack_debug(&self, mmu: &Mmu) -> String {
-        let mut output = String::new();
+            cpu.leave_calls();
 
-        for addr in (self.sp + 1..=self.sp_base).rev() {
-            // TODO: Is stack always zero paged?
-            write!(
-                &mut output,
-                "0x{:02X}{}",
-                mmu.read_u8(addr as u32),
-                if addr == self.sp + 1 { "" } else { ", " }
-            )
-            .unwrap();
-        }
-
-        output
+            cpu.status = Flags::from_bits_retain(status);
+            cpu.enforce_mode();
+            cpu.pc = addr;
+        },
     }
 }
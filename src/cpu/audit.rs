@@ -0,0 +1,626 @@
+// With the ops-audit feature, the CPU calls these instead of the functions
+// in ops.rs. Each one runs the real operation, then checks its result and
+// flags against a reference version written a different way, and panics if
+// they disagree. The reference versions go by the definitions rather than
+// bit tricks (e.g. overflow is the signed result not fitting), so that a
+// mistake copied between widths doesn't show up in both.
+
+#[cfg(test)]
+use std::cell::Cell;
+
+use super::ops;
+use super::Flags;
+
+// The flags each operation is allowed to change. Everything else has to
+// come out the way it went in.
+const NZ: Flags = Flags::NEGATIVE.union(Flags::ZERO);
+const NZC: Flags = NZ.union(Flags::CARRY);
+const NVZC: Flags = NZC.union(Flags::OVERFLOW);
+
+// Tests can name an operation here to have its carry flipped before it's
+// checked, as if ops.rs had got it wrong, to make sure the audit notices.
+#[cfg(test)]
+thread_local! {
+    static SABOTAGED: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+fn check<T: PartialEq + std::fmt::Debug>(
+    name: &str,
+    inputs: &[u16],
+    before: Flags,
+    actual: (T, Flags),
+    expected: (T, Flags),
+) {
+    #[cfg(test)]
+    let actual = match SABOTAGED.get() == Some(name) {
+        true => (actual.0, actual.1 ^ Flags::CARRY),
+        false => actual,
+    };
+
+    if actual != expected {
+        panic!(
+            "ops audit: {} diverged for inputs {:04X?} with flags {:08b}: got {:?} with flags \
+             {:08b}, expected {:?} with flags {:08b}",
+            name,
+            inputs,
+            before.bits(),
+            actual.0,
+            actual.1.bits(),
+            expected.0,
+            expected.1.bits()
+        );
+    }
+}
+
+// The flags as they should be after an operation that sets N and Z from its
+// result, plus whatever else it changes.
+fn expected_flags(before: Flags, changed: Flags, negative: bool, zero: bool) -> Flags {
+    let mut flags = before.difference(changed);
+
+    flags.set(Flags::NEGATIVE, negative);
+    flags.set(Flags::ZERO, zero);
+
+    flags
+}
+
+// Cpu::set_nz sets N and Z inline, as it works at either width, so it's
+// checked against ops.rs instead.
+pub fn inline_nz(before: Flags, after: Flags, value: u16, eight_bit: bool) {
+    let mut expected = before;
+
+    if eight_bit {
+        ops::set_nz8(&mut expected, value as u8);
+    } else {
+        ops::set_nz16(&mut expected, value);
+    }
+
+    check("set_nz", &[value], before, ((), after), ((), expected));
+}
+
+pub fn adc8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let before = *flags;
+    let result = ops::adc8(flags, lhs, rhs);
+
+    let carry = before.contains(Flags::CARRY) as u8;
+    let (sum, carry_a) = lhs.overflowing_add(rhs);
+    let (expected, carry_b) = sum.overflowing_add(carry);
+    let signed = lhs as i8 as i16 + rhs as i8 as i16 + carry as i16;
+
+    let mut expected_flags = expected_flags(before, NVZC, (expected as i8) < 0, expected == 0);
+    expected_flags.set(Flags::CARRY, carry_a || carry_b);
+    expected_flags.set(Flags::OVERFLOW, i8::try_from(signed).is_err());
+
+    check(
+        "adc8",
+        &[lhs as u16, rhs as u16],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn adc16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let before = *flags;
+    let result = ops::adc16(flags, lhs, rhs);
+
+    let carry = before.contains(Flags::CARRY) as u16;
+    let (sum, carry_a) = lhs.overflowing_add(rhs);
+    let (expected, carry_b) = sum.overflowing_add(carry);
+    let signed = lhs as i16 as i32 + rhs as i16 as i32 + carry as i32;
+
+    let mut expected_flags = expected_flags(before, NVZC, (expected as i16) < 0, expected == 0);
+    expected_flags.set(Flags::CARRY, carry_a || carry_b);
+    expected_flags.set(Flags::OVERFLOW, i16::try_from(signed).is_err());
+
+    check(
+        "adc16",
+        &[lhs, rhs],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn cmp8(flags: &mut Flags, lhs: u8, rhs: u8) {
+    let before = *flags;
+    ops::cmp8(flags, lhs, rhs);
+
+    let (difference, borrow) = lhs.overflowing_sub(rhs);
+
+    let mut expected = expected_flags(before, NZC, (difference as i8) < 0, lhs == rhs);
+    expected.set(Flags::CARRY, !borrow);
+
+    check(
+        "cmp8",
+        &[lhs as u16, rhs as u16],
+        before,
+        ((), *flags),
+        ((), expected),
+    );
+}
+
+pub fn cmp16(flags: &mut Flags, lhs: u16, rhs: u16) {
+    let before = *flags;
+    ops::cmp16(flags, lhs, rhs);
+
+    let (difference, borrow) = lhs.overflowing_sub(rhs);
+
+    let mut expected = expected_flags(before, NZC, (difference as i16) < 0, lhs == rhs);
+    expected.set(Flags::CARRY, !borrow);
+
+    check("cmp16", &[lhs, rhs], before, ((), *flags), ((), expected));
+}
+
+pub fn inc8(flags: &mut Flags, value: u8) -> u8 {
+    let before = *flags;
+    let result = ops::inc8(flags, value);
+
+    let expected = if value == u8::MAX { 0 } else { value + 1 };
+    let expected_flags = expected_flags(before, NZ, (expected as i8) < 0, expected == 0);
+
+    check(
+        "inc8",
+        &[value as u16],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn inc16(flags: &mut Flags, value: u16) -> u16 {
+    let before = *flags;
+    let result = ops::inc16(flags, value);
+
+    let expected = if value == u16::MAX { 0 } else { value + 1 };
+    let expected_flags = expected_flags(before, NZ, (expected as i16) < 0, expected == 0);
+
+    check(
+        "inc16",
+        &[value],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn dec8(flags: &mut Flags, value: u8) -> u8 {
+    let before = *flags;
+    let result = ops::dec8(flags, value);
+
+    let expected = if value == 0 { u8::MAX } else { value - 1 };
+    let expected_flags = expected_flags(before, NZ, (expected as i8) < 0, expected == 0);
+
+    check(
+        "dec8",
+        &[value as u16],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn dec16(flags: &mut Flags, value: u16) -> u16 {
+    let before = *flags;
+    let result = ops::dec16(flags, value);
+
+    let expected = if value == 0 { u16::MAX } else { value - 1 };
+    let expected_flags = expected_flags(before, NZ, (expected as i16) < 0, expected == 0);
+
+    check(
+        "dec16",
+        &[value],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn asl8(flags: &mut Flags, value: u8) -> u8 {
+    let before = *flags;
+    let result = ops::asl8(flags, value);
+
+    let expected = value.wrapping_mul(2);
+    let mut expected_flags = expected_flags(before, NZC, (expected as i8) < 0, expected == 0);
+    expected_flags.set(Flags::CARRY, value >= 0x80);
+
+    check(
+        "asl8",
+        &[value as u16],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn asl16(flags: &mut Flags, value: u16) -> u16 {
+    let before = *flags;
+    let result = ops::asl16(flags, value);
+
+    let expected = value.wrapping_mul(2);
+    let mut expected_flags = expected_flags(before, NZC, (expected as i16) < 0, expected == 0);
+    expected_flags.set(Flags::CARRY, value >= 0x8000);
+
+    check(
+        "asl16",
+        &[value],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn sbc8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let before = *flags;
+    let result = ops::sbc8(flags, lhs, rhs);
+
+    let borrow = !before.contains(Flags::CARRY) as i16;
+    let difference = lhs as i16 - rhs as i16 - borrow;
+    let expected = difference.rem_euclid(0x100) as u8;
+    let signed = lhs as i8 as i16 - rhs as i8 as i16 - borrow;
+
+    let mut expected_flags = expected_flags(before, NVZC, (expected as i8) < 0, expected == 0);
+    expected_flags.set(Flags::CARRY, difference >= 0);
+    expected_flags.set(Flags::OVERFLOW, i8::try_from(signed).is_err());
+
+    check(
+        "sbc8",
+        &[lhs as u16, rhs as u16],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+pub fn sbc16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let before = *flags;
+    let result = ops::sbc16(flags, lhs, rhs);
+
+    let borrow = !before.contains(Flags::CARRY) as i32;
+    let difference = lhs as i32 - rhs as i32 - borrow;
+    let expected = difference.rem_euclid(0x10000) as u16;
+    let signed = lhs as i16 as i32 - rhs as i16 as i32 - borrow;
+
+    let mut expected_flags = expected_flags(before, NVZC, (expected as i16) < 0, expected == 0);
+    expected_flags.set(Flags::CARRY, difference >= 0);
+    expected_flags.set(Flags::OVERFLOW, i16::try_from(signed).is_err());
+
+    check(
+        "sbc16",
+        &[lhs, rhs],
+        before,
+        (result, *flags),
+        (expected, expected_flags),
+    );
+
+    result
+}
+
+// Shifts and rotates at either width, as multiplication and division. `bits`
+// is the width, and `op` is the name of the operation.
+fn shift(before: Flags, op: &str, value: u16, bits: u32) -> (u16, Flags) {
+    let carry_in = before.contains(Flags::CARRY) as u32;
+    let value = value as u32;
+    let top = 1 << (bits - 1);
+
+    let (expected, carry) = match op {
+        "asl" => (value * 2, value >= top),
+        "lsr" => (value / 2, value % 2 == 1),
+        "rol" => (value * 2 + carry_in, value >= top),
+        "ror" => (value / 2 + carry_in * top, value % 2 == 1),
+        _ => unreachable!(),
+    };
+
+    let expected = expected % (1 << bits);
+
+    let mut flags = expected_flags(before, NZC, expected >= top, expected == 0);
+    flags.set(Flags::CARRY, carry);
+
+    (expected as u16, flags)
+}
+
+pub fn lsr8(flags: &mut Flags, value: u8) -> u8 {
+    let before = *flags;
+    let result = ops::lsr8(flags, value);
+
+    let expected = shift(before, "lsr", value as u16, 8);
+    check(
+        "lsr8",
+        &[value as u16],
+        before,
+        (result as u16, *flags),
+        expected,
+    );
+
+    result
+}
+
+pub fn lsr16(flags: &mut Flags, value: u16) -> u16 {
+    let before = *flags;
+    let result = ops::lsr16(flags, value);
+
+    let expected = shift(before, "lsr", value, 16);
+    check("lsr16", &[value], before, (result, *flags), expected);
+
+    result
+}
+
+pub fn rol8(flags: &mut Flags, value: u8) -> u8 {
+    let before = *flags;
+    let result = ops::rol8(flags, value);
+
+    let expected = shift(before, "rol", value as u16, 8);
+    check(
+        "rol8",
+        &[value as u16],
+        before,
+        (result as u16, *flags),
+        expected,
+    );
+
+    result
+}
+
+pub fn rol16(flags: &mut Flags, value: u16) -> u16 {
+    let before = *flags;
+    let result = ops::rol16(flags, value);
+
+    let expected = shift(before, "rol", value, 16);
+    check("rol16", &[value], before, (result, *flags), expected);
+
+    result
+}
+
+pub fn ror8(flags: &mut Flags, value: u8) -> u8 {
+    let before = *flags;
+    let result = ops::ror8(flags, value);
+
+    let expected = shift(before, "ror", value as u16, 8);
+    check(
+        "ror8",
+        &[value as u16],
+        before,
+        (result as u16, *flags),
+        expected,
+    );
+
+    result
+}
+
+pub fn ror16(flags: &mut Flags, value: u16) -> u16 {
+    let before = *flags;
+    let result = ops::ror16(flags, value);
+
+    let expected = shift(before, "ror", value, 16);
+    check("ror16", &[value], before, (result, *flags), expected);
+
+    result
+}
+
+// Combines the two a bit at a time.
+fn bitwise(lhs: u16, rhs: u16, bits: u32, op: fn(bool, bool) -> bool) -> u16 {
+    (0..bits)
+        .filter(|&bit| op(lhs >> bit & 1 == 1, rhs >> bit & 1 == 1))
+        .map(|bit| 1 << bit)
+        .sum()
+}
+
+// AND, OR and EOR at either width, named by `op`.
+fn logic(before: Flags, op: &str, lhs: u16, rhs: u16, bits: u32) -> (u16, Flags) {
+    let op: fn(bool, bool) -> bool = match op {
+        "and" => |a, b| a && b,
+        "or" => |a, b| a || b,
+        "eor" => |a, b| a != b,
+        _ => unreachable!(),
+    };
+
+    let expected = bitwise(lhs, rhs, bits, op);
+    let top = 1 << (bits - 1);
+
+    (
+        expected,
+        expected_flags(before, NZ, expected >= top, expected == 0),
+    )
+}
+
+pub fn and8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let before = *flags;
+    let result = ops::and8(flags, lhs, rhs);
+
+    let (lhs, rhs) = (lhs as u16, rhs as u16);
+    let expected = logic(before, "and", lhs, rhs, 8);
+    check(
+        "and8",
+        &[lhs, rhs],
+        before,
+        (result as u16, *flags),
+        expected,
+    );
+
+    result
+}
+
+pub fn and16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let before = *flags;
+    let result = ops::and16(flags, lhs, rhs);
+
+    let expected = logic(before, "and", lhs, rhs, 16);
+    check("and16", &[lhs, rhs], before, (result, *flags), expected);
+
+    result
+}
+
+pub fn or8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let before = *flags;
+    let result = ops::or8(flags, lhs, rhs);
+
+    let (lhs, rhs) = (lhs as u16, rhs as u16);
+    let expected = logic(before, "or", lhs, rhs, 8);
+    check(
+        "or8",
+        &[lhs, rhs],
+        before,
+        (result as u16, *flags),
+        expected,
+    );
+
+    result
+}
+
+pub fn or16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let before = *flags;
+    let result = ops::or16(flags, lhs, rhs);
+
+    let expected = logic(before, "or", lhs, rhs, 16);
+    check("or16", &[lhs, rhs], before, (result, *flags), expected);
+
+    result
+}
+
+pub fn eor8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let before = *flags;
+    let result = ops::eor8(flags, lhs, rhs);
+
+    let (lhs, rhs) = (lhs as u16, rhs as u16);
+    let expected = logic(before, "eor", lhs, rhs, 8);
+    check(
+        "eor8",
+        &[lhs, rhs],
+        before,
+        (result as u16, *flags),
+        expected,
+    );
+
+    result
+}
+
+pub fn eor16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let before = *flags;
+    let result = ops::eor16(flags, lhs, rhs);
+
+    let expected = logic(before, "eor", lhs, rhs, 16);
+    check("eor16", &[lhs, rhs], before, (result, *flags), expected);
+
+    result
+}
+
+// N and V are the top two bits of the memory operand, and Z is whether the
+// two have no bits in common.
+fn bit(name: &str, before: Flags, after: Flags, operands: [u16; 2], bits: u32) {
+    let [lhs, rhs] = operands;
+    let common = bitwise(lhs, rhs, bits, |a, b| a && b);
+
+    let mut expected = expected_flags(before, NZ, rhs >> (bits - 1) == 1, common == 0);
+    expected.set(Flags::OVERFLOW, rhs >> (bits - 2) & 1 == 1);
+
+    check(name, &operands, before, ((), after), ((), expected));
+}
+
+pub fn bit8(flags: &mut Flags, lhs: u8, rhs: u8) {
+    let before = *flags;
+    ops::bit8(flags, lhs, rhs);
+    bit("bit8", before, *flags, [lhs as u16, rhs as u16], 8);
+}
+
+pub fn bit16(flags: &mut Flags, lhs: u16, rhs: u16) {
+    let before = *flags;
+    ops::bit16(flags, lhs, rhs);
+    bit("bit16", before, *flags, [lhs, rhs], 16);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::*;
+    use crate::asm::{lorom, Asm};
+    use crate::emulator::Emulator;
+    use crate::mmu::MapMode;
+
+    // Every 8-bit input, and the 16-bit edge cases, with and without a carry
+    // in. None of them should panic.
+    #[test]
+    fn ops_pass() {
+        for carry in [Flags::empty(), Flags::CARRY] {
+            for lhs in 0..=u8::MAX {
+                for rhs in 0..=u8::MAX {
+                    adc8(&mut { carry }, lhs, rhs);
+                    sbc8(&mut { carry }, lhs, rhs);
+                    cmp8(&mut { carry }, lhs, rhs);
+                }
+
+                asl8(&mut { carry }, lhs);
+                lsr8(&mut { carry }, lhs);
+                rol8(&mut { carry }, lhs);
+                ror8(&mut { carry }, lhs);
+                inc8(&mut { carry }, lhs);
+                dec8(&mut { carry }, lhs);
+            }
+
+            for value in [0x0000, 0x0001, 0x7FFF, 0x8000, 0x8001, 0xFFFF] {
+                for rhs in [0x0000, 0x0001, 0x7FFF, 0x8000, 0xFFFF] {
+                    adc16(&mut { carry }, value, rhs);
+                    sbc16(&mut { carry }, value, rhs);
+                    cmp16(&mut { carry }, value, rhs);
+                }
+
+                asl16(&mut { carry }, value);
+                lsr16(&mut { carry }, value);
+                rol16(&mut { carry }, value);
+                ror16(&mut { carry }, value);
+                inc16(&mut { carry }, value);
+                dec16(&mut { carry }, value);
+            }
+        }
+    }
+
+    // Runs $40 + $40 through the CPU, with adc8 made to give the wrong
+    // carry, and returns what the audit said about it.
+    fn sabotaged_adc() -> String {
+        #[rustfmt::skip]
+        let code = Asm::at(0x8000)
+            .clc().xce().sep(0x20)
+            .lda_imm8(0x40).clc().adc_imm8(0x40)
+            .assemble()
+            .unwrap();
+
+        let mut emulator = Emulator::new(lorom(&code), Some(MapMode::LoRom)).unwrap();
+
+        for _ in 0..5 {
+            emulator.step_instruction();
+        }
+
+        SABOTAGED.set(Some("adc8"));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| emulator.step_instruction()));
+        SABOTAGED.set(None);
+
+        let payload = result.expect_err("the audit let a wrong carry through");
+        payload.downcast_ref::<String>().unwrap().clone()
+    }
+
+    #[test]
+    fn reports_divergence() {
+        assert_eq!(
+            sabotaged_adc(),
+            "ops audit: adc8 diverged for inputs [0040, 0040] with flags 00110000: got 128 with \
+             flags 11110001, expected 128 with flags 11110000"
+        );
+    }
+}
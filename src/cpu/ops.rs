@@ -0,0 +1,849 @@
+// The ALU operations, in both widths. These only deal in values and flags -
+// fetching the operand and storing the result is left to the CPU, so each
+// operation's flag behaviour lives in exactly one place.
+
+use super::Flags;
+
+pub fn set_nz8(flags: &mut Flags, value: u8) {
+    flags.set(Flags::NEGATIVE, value & 0x80 != 0);
+    flags.set(Flags::ZERO, value == 0);
+}
+
+pub fn set_nz16(flags: &mut Flags, value: u16) {
+    flags.set(Flags::NEGATIVE, value & 0x8000 != 0);
+    flags.set(Flags::ZERO, value == 0);
+}
+
+// TODO: Decimal mode
+pub fn adc8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let carry = flags.contains(Flags::CARRY) as u16;
+    let result = lhs as u16 + rhs as u16 + carry;
+
+    flags.set(Flags::CARRY, result > 0xFF);
+    flags.set(
+        Flags::OVERFLOW,
+        !(lhs ^ rhs) & (lhs ^ result as u8) & 0x80 != 0,
+    );
+    set_nz8(flags, result as u8);
+
+    result as u8
+}
+
+pub fn adc16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let carry = flags.contains(Flags::CARRY) as u32;
+    let result = lhs as u32 + rhs as u32 + carry;
+
+    flags.set(Flags::CARRY, result > 0xFFFF);
+    flags.set(
+        Flags::OVERFLOW,
+        !(lhs ^ rhs) & (lhs ^ result as u16) & 0x8000 != 0,
+    );
+    set_nz16(flags, result as u16);
+
+    result as u16
+}
+
+// SBC is ADC of the complement, with the carry standing for no borrow.
+pub fn sbc8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    adc8(flags, lhs, !rhs)
+}
+
+pub fn sbc16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    adc16(flags, lhs, !rhs)
+}
+
+pub fn cmp8(flags: &mut Flags, lhs: u8, rhs: u8) {
+    flags.set(Flags::CARRY, lhs >= rhs);
+    set_nz8(flags, lhs.wrapping_sub(rhs));
+}
+
+pub fn cmp16(flags: &mut Flags, lhs: u16, rhs: u16) {
+    flags.set(Flags::CARRY, lhs >= rhs);
+    set_nz16(flags, lhs.wrapping_sub(rhs));
+}
+
+pub fn inc8(flags: &mut Flags, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
+    set_nz8(flags, result);
+    result
+}
+
+pub fn inc16(flags: &mut Flags, value: u16) -> u16 {
+    let result = value.wrapping_add(1);
+    set_nz16(flags, result);
+    result
+}
+
+pub fn dec8(flags: &mut Flags, value: u8) -> u8 {
+    let result = value.wrapping_sub(1);
+    set_nz8(flags, result);
+    result
+}
+
+pub fn dec16(flags: &mut Flags, value: u16) -> u16 {
+    let result = value.wrapping_sub(1);
+    set_nz16(flags, result);
+    result
+}
+
+pub fn asl8(flags: &mut Flags, value: u8) -> u8 {
+    let result = value << 1;
+
+    flags.set(Flags::CARRY, value & 0x80 != 0);
+    set_nz8(flags, result);
+
+    result
+}
+
+pub fn asl16(flags: &mut Flags, value: u16) -> u16 {
+    let result = value << 1;
+
+    flags.set(Flags::CARRY, value & 0x8000 != 0);
+    set_nz16(flags, result);
+
+    result
+}
+
+pub fn lsr8(flags: &mut Flags, value: u8) -> u8 {
+    let result = value >> 1;
+
+    flags.set(Flags::CARRY, value & 1 != 0);
+    set_nz8(flags, result);
+
+    result
+}
+
+pub fn lsr16(flags: &mut Flags, value: u16) -> u16 {
+    let result = value >> 1;
+
+    flags.set(Flags::CARRY, value & 1 != 0);
+    set_nz16(flags, result);
+
+    result
+}
+
+pub fn rol8(flags: &mut Flags, value: u8) -> u8 {
+    let result = value << 1 | flags.contains(Flags::CARRY) as u8;
+
+    flags.set(Flags::CARRY, value & 0x80 != 0);
+    set_nz8(flags, result);
+
+    result
+}
+
+pub fn rol16(flags: &mut Flags, value: u16) -> u16 {
+    let result = value << 1 | flags.contains(Flags::CARRY) as u16;
+
+    flags.set(Flags::CARRY, value & 0x8000 != 0);
+    set_nz16(flags, result);
+
+    result
+}
+
+pub fn ror8(flags: &mut Flags, value: u8) -> u8 {
+    let result = value >> 1 | (flags.contains(Flags::CARRY) as u8) << 7;
+
+    flags.set(Flags::CARRY, value & 1 != 0);
+    set_nz8(flags, result);
+
+    result
+}
+
+pub fn ror16(flags: &mut Flags, value: u16) -> u16 {
+    let result = value >> 1 | (flags.contains(Flags::CARRY) as u16) << 15;
+
+    flags.set(Flags::CARRY, value & 1 != 0);
+    set_nz16(flags, result);
+
+    result
+}
+
+pub fn and8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let result = lhs & rhs;
+    set_nz8(flags, result);
+    result
+}
+
+pub fn and16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let result = lhs & rhs;
+    set_nz16(flags, result);
+    result
+}
+
+pub fn or8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let result = lhs | rhs;
+    set_nz8(flags, result);
+    result
+}
+
+pub fn or16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let result = lhs | rhs;
+    set_nz16(flags, result);
+    result
+}
+
+pub fn eor8(flags: &mut Flags, lhs: u8, rhs: u8) -> u8 {
+    let result = lhs ^ rhs;
+    set_nz8(flags, result);
+    result
+}
+
+pub fn eor16(flags: &mut Flags, lhs: u16, rhs: u16) -> u16 {
+    let result = lhs ^ rhs;
+    set_nz16(flags, result);
+    result
+}
+
+// Z comes from ANDing the two, but N and V are copied straight from the top
+// two bits of the memory operand. (The immediate form only sets Z, which is
+// up to the CPU.)
+pub fn bit8(flags: &mut Flags, lhs: u8, rhs: u8) {
+    flags.set(Flags::NEGATIVE, rhs & 0x80 != 0);
+    flags.set(Flags::OVERFLOW, rhs & 0x40 != 0);
+    flags.set(Flags::ZERO, lhs & rhs == 0);
+}
+
+pub fn bit16(flags: &mut Flags, lhs: u16, rhs: u16) {
+    flags.set(Flags::NEGATIVE, rhs & 0x8000 != 0);
+    flags.set(Flags::OVERFLOW, rhs & 0x4000 != 0);
+    flags.set(Flags::ZERO, lhs & rhs == 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flags written as the letters of the ones that are set, e.g. "NZ". I
+    // and D are there to check that the operations leave them alone.
+    fn flags(names: &str) -> Flags {
+        names.chars().fold(Flags::empty(), |flags, name| {
+            flags
+                | match name {
+                    'N' => Flags::NEGATIVE,
+                    'V' => Flags::OVERFLOW,
+                    'D' => Flags::DECIMAL_MODE,
+                    'I' => Flags::IRQ_DISABLE,
+                    'Z' => Flags::ZERO,
+                    'C' => Flags::CARRY,
+                    _ => panic!("unknown flag {}", name),
+                }
+        })
+    }
+
+    // Each case is the flags going in, the operands, then the result and the
+    // flags coming out.
+    type Case<T> = (&'static str, T, T, T, &'static str);
+
+    fn binary<T>(name: &str, op: fn(&mut Flags, T, T) -> T, cases: &[Case<T>])
+    where
+        T: Copy + PartialEq + std::fmt::Debug,
+    {
+        for &(before, lhs, rhs, result, after) in cases {
+            let mut actual = flags(before);
+
+            assert_eq!(
+                (op(&mut actual, lhs, rhs), actual),
+                (result, flags(after)),
+                "{} {:?} {:?} with {}",
+                name,
+                lhs,
+                rhs,
+                before
+            );
+        }
+    }
+
+    // The same for operations on a single value, where the second operand
+    // is left as zero.
+    fn unary<T>(name: &str, op: fn(&mut Flags, T) -> T, cases: &[Case<T>])
+    where
+        T: Copy + PartialEq + std::fmt::Debug,
+    {
+        for &(before, value, _, result, after) in cases {
+            let mut actual = flags(before);
+
+            assert_eq!(
+                (op(&mut actual, value), actual),
+                (result, flags(after)),
+                "{} {:?} with {}",
+                name,
+                value,
+                before
+            );
+        }
+    }
+
+    #[test]
+    fn adc() {
+        binary(
+            "adc8",
+            adc8,
+            &[
+                ("", 0x00, 0x00, 0x00, "Z"),
+                ("", 0x01, 0x01, 0x02, ""),
+                ("C", 0x01, 0x01, 0x03, ""),
+                ("", 0x7F, 0x01, 0x80, "NV"),
+                ("", 0x80, 0x80, 0x00, "VZC"),
+                ("", 0xFF, 0x01, 0x00, "ZC"),
+                ("C", 0x10, 0xFF, 0x10, "C"),
+                ("C", 0x7F, 0x00, 0x80, "NV"),
+                ("C", 0xFF, 0xFF, 0xFF, "NC"),
+                ("DI", 0x01, 0x01, 0x02, "DI"),
+            ],
+        );
+
+        binary(
+            "adc16",
+            adc16,
+            &[
+                ("", 0x0000, 0x0000, 0x0000, "Z"),
+                ("", 0x00FF, 0x0001, 0x0100, ""),
+                ("", 0x7FFF, 0x0001, 0x8000, "NV"),
+                ("", 0x8000, 0x8000, 0x0000, "VZC"),
+                ("C", 0xFFFF, 0x0000, 0x0000, "ZC"),
+                ("C", 0x1000, 0xFFFF, 0x1000, "C"),
+                ("I", 0x1234, 0x4321, 0x5555, "I"),
+            ],
+        );
+    }
+
+    #[test]
+    fn sbc() {
+        // Carry set means there's no borrow.
+        binary(
+            "sbc8",
+            sbc8,
+            &[
+                ("C", 0x05, 0x03, 0x02, "C"),
+                ("", 0x05, 0x03, 0x01, "C"),
+                ("C", 0x03, 0x03, 0x00, "ZC"),
+                ("C", 0x03, 0x05, 0xFE, "N"),
+                ("", 0x00, 0x00, 0xFF, "N"),
+                ("C", 0x80, 0x01, 0x7F, "VC"),
+                ("C", 0x7F, 0xFF, 0x80, "NV"),
+                ("DIC", 0x05, 0x03, 0x02, "DIC"),
+            ],
+        );
+
+        binary(
+            "sbc16",
+            sbc16,
+            &[
+                ("C", 0x1000, 0x0001, 0x0FFF, "C"),
+                ("C", 0x1234, 0x1234, 0x0000, "ZC"),
+                ("", 0x0000, 0x0000, 0xFFFF, "N"),
+                ("C", 0x8000, 0x0001, 0x7FFF, "VC"),
+                ("C", 0x7FFF, 0xFFFF, 0x8000, "NV"),
+            ],
+        );
+    }
+
+    #[test]
+    fn cmp() {
+        // Only N, Z and C change, and A is left alone.
+        binary(
+            "cmp8",
+            |flags, lhs, rhs| {
+                cmp8(flags, lhs, rhs);
+                lhs
+            },
+            &[
+                ("", 0x05, 0x03, 0x05, "C"),
+                ("V", 0x03, 0x03, 0x03, "VZC"),
+                ("C", 0x03, 0x05, 0x03, "N"),
+                ("", 0x80, 0x00, 0x80, "NC"),
+                ("", 0x00, 0x80, 0x00, "N"),
+                ("", 0x00, 0x01, 0x00, "N"),
+            ],
+        );
+
+        binary(
+            "cmp16",
+            |flags, lhs, rhs| {
+                cmp16(flags, lhs, rhs);
+                lhs
+            },
+            &[
+                ("", 0x1000, 0x0FFF, 0x1000, "C"),
+                ("", 0x8000, 0x8000, 0x8000, "ZC"),
+                ("", 0x0FFF, 0x1000, 0x0FFF, "N"),
+                ("", 0x0000, 0x8001, 0x0000, ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn inc_dec() {
+        unary(
+            "inc8",
+            inc8,
+            &[
+                ("", 0x00, 0, 0x01, ""),
+                ("", 0x7F, 0, 0x80, "N"),
+                ("C", 0xFF, 0, 0x00, "ZC"),
+            ],
+        );
+
+        unary(
+            "inc16",
+            inc16,
+            &[
+                ("", 0x00FF, 0, 0x0100, ""),
+                ("", 0x7FFF, 0, 0x8000, "N"),
+                ("V", 0xFFFF, 0, 0x0000, "VZ"),
+            ],
+        );
+
+        unary(
+            "dec8",
+            dec8,
+            &[
+                ("", 0x02, 0, 0x01, ""),
+                ("", 0x01, 0, 0x00, "Z"),
+                ("C", 0x00, 0, 0xFF, "NC"),
+            ],
+        );
+
+        unary(
+            "dec16",
+            dec16,
+            &[
+                ("", 0x0100, 0, 0x00FF, ""),
+                ("", 0x0001, 0, 0x0000, "Z"),
+                ("", 0x0000, 0, 0xFFFF, "N"),
+            ],
+        );
+    }
+
+    #[test]
+    fn shifts() {
+        unary(
+            "asl8",
+            asl8,
+            &[
+                ("", 0x01, 0, 0x02, ""),
+                ("C", 0x01, 0, 0x02, ""),
+                ("", 0x40, 0, 0x80, "N"),
+                ("", 0x80, 0, 0x00, "ZC"),
+                ("", 0xC0, 0, 0x80, "NC"),
+            ],
+        );
+
+        unary(
+            "asl16",
+            asl16,
+            &[
+                ("", 0x0080, 0, 0x0100, ""),
+                ("", 0x4000, 0, 0x8000, "N"),
+                ("", 0x8000, 0, 0x0000, "ZC"),
+            ],
+        );
+
+        unary(
+            "lsr8",
+            lsr8,
+            &[
+                ("", 0x02, 0, 0x01, ""),
+                ("C", 0x02, 0, 0x01, ""),
+                ("N", 0x80, 0, 0x40, ""),
+                ("", 0x01, 0, 0x00, "ZC"),
+                ("", 0xFF, 0, 0x7F, "C"),
+            ],
+        );
+
+        unary(
+            "lsr16",
+            lsr16,
+            &[
+                ("", 0x0100, 0, 0x0080, ""),
+                ("", 0x8000, 0, 0x4000, ""),
+                ("", 0x0001, 0, 0x0000, "ZC"),
+            ],
+        );
+
+        unary(
+            "rol8",
+            rol8,
+            &[
+                ("", 0x01, 0, 0x02, ""),
+                ("C", 0x01, 0, 0x03, ""),
+                ("", 0x80, 0, 0x00, "ZC"),
+                ("C", 0x80, 0, 0x01, "C"),
+                ("", 0x40, 0, 0x80, "N"),
+            ],
+        );
+
+        unary(
+            "rol16",
+            rol16,
+            &[
+                ("C", 0x0080, 0, 0x0101, ""),
+                ("", 0x8000, 0, 0x0000, "ZC"),
+                ("C", 0xC000, 0, 0x8001, "NC"),
+            ],
+        );
+
+        unary(
+            "ror8",
+            ror8,
+            &[
+                ("", 0x02, 0, 0x01, ""),
+                ("C", 0x02, 0, 0x81, "N"),
+                ("", 0x01, 0, 0x00, "ZC"),
+                ("C", 0x01, 0, 0x80, "NC"),
+            ],
+        );
+
+        unary(
+            "ror16",
+            ror16,
+            &[
+                ("C", 0x0100, 0, 0x8080, "N"),
+                ("", 0x0001, 0, 0x0000, "ZC"),
+                ("", 0x8000, 0, 0x4000, ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn logic() {
+        binary(
+            "and8",
+            and8,
+            &[
+                ("", 0xF0, 0x3C, 0x30, ""),
+                ("C", 0xF0, 0x0F, 0x00, "ZC"),
+                ("Z", 0x80, 0xFF, 0x80, "N"),
+            ],
+        );
+
+        binary(
+            "and16",
+            and16,
+            &[
+                ("", 0xFF00, 0x0FF0, 0x0F00, ""),
+                ("", 0x8000, 0x8001, 0x8000, "N"),
+                ("V", 0xFF00, 0x00FF, 0x0000, "VZ"),
+            ],
+        );
+
+        binary(
+            "or8",
+            or8,
+            &[
+                ("", 0xF0, 0x0F, 0xFF, "N"),
+                ("Z", 0x01, 0x02, 0x03, ""),
+                ("C", 0x00, 0x00, 0x00, "ZC"),
+            ],
+        );
+
+        binary(
+            "or16",
+            or16,
+            &[
+                ("", 0x0F00, 0x00F0, 0x0FF0, ""),
+                ("", 0x8000, 0x0000, 0x8000, "N"),
+                ("", 0x0000, 0x0000, 0x0000, "Z"),
+            ],
+        );
+
+        binary(
+            "eor8",
+            eor8,
+            &[
+                ("", 0xFF, 0x0F, 0xF0, "N"),
+                ("", 0x5A, 0x5A, 0x00, "Z"),
+                ("NV", 0x01, 0x03, 0x02, "V"),
+            ],
+        );
+
+        binary(
+            "eor16",
+            eor16,
+            &[
+                ("", 0xFFFF, 0x00FF, 0xFF00, "N"),
+                ("", 0x1234, 0x1234, 0x0000, "Z"),
+            ],
+        );
+    }
+
+    #[test]
+    fn bit() {
+        // N and V come from memory, whatever A is, and C is left alone.
+        binary(
+            "bit8",
+            |flags, lhs, rhs| {
+                bit8(flags, lhs, rhs);
+                lhs
+            },
+            &[
+                ("", 0x01, 0x01, 0x01, ""),
+                ("C", 0x01, 0x02, 0x01, "ZC"),
+                ("", 0x00, 0xC0, 0x00, "NVZ"),
+                ("NV", 0xFF, 0x3F, 0xFF, ""),
+                ("", 0x40, 0x40, 0x40, "V"),
+            ],
+        );
+
+        binary(
+            "bit16",
+            |flags, lhs, rhs| {
+                bit16(flags, lhs, rhs);
+                lhs
+            },
+            &[
+                ("", 0x0001, 0x8001, 0x0001, "N"),
+                ("", 0xFFFF, 0x4000, 0xFFFF, "V"),
+                ("", 0x00FF, 0xC000, 0x00FF, "NVZ"),
+                ("NVZ", 0x0080, 0x0080, 0x0080, ""),
+            ],
+        );
+    }
+}
+
+// Random inputs checked against a reference written straight from the data
+// sheet's descriptions, in plain arithmetic rather than bit tricks, and kept
+// apart from the code above so the two can't share a mistake.
+#[cfg(test)]
+mod properties {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    mod reference {
+        // What an operation should give, at a width of `bits`.
+        #[derive(Debug, PartialEq)]
+        pub struct Outcome {
+            pub result: u32,
+            pub negative: bool,
+            pub overflow: bool,
+            pub zero: bool,
+            pub carry: bool,
+        }
+
+        fn outcome(result: u32, bits: u32, overflow: bool, carry: bool) -> Outcome {
+            Outcome {
+                result,
+                negative: result >= 2u32.pow(bits - 1),
+                overflow,
+                zero: result == 0,
+                carry,
+            }
+        }
+
+        // The value as a two's complement number.
+        fn signed(value: u32, bits: u32) -> i64 {
+            if value >= 2u32.pow(bits - 1) {
+                value as i64 - 2i64.pow(bits)
+            } else {
+                value as i64
+            }
+        }
+
+        // Whether a signed result doesn't fit in the width.
+        fn overflows(value: i64, bits: u32) -> bool {
+            value < -(2i64.pow(bits - 1)) || value >= 2i64.pow(bits - 1)
+        }
+
+        pub fn adc(lhs: u32, rhs: u32, carry: bool, bits: u32) -> Outcome {
+            let sum = lhs + rhs + carry as u32;
+            let signed_sum = signed(lhs, bits) + signed(rhs, bits) + carry as i64;
+
+            outcome(
+                sum % 2u32.pow(bits),
+                bits,
+                overflows(signed_sum, bits),
+                sum >= 2u32.pow(bits),
+            )
+        }
+
+        // The carry going in means there's no borrow, and the carry coming
+        // out means there wasn't one.
+        pub fn sbc(lhs: u32, rhs: u32, carry: bool, bits: u32) -> Outcome {
+            let borrow = if carry { 0 } else { 1 };
+            let difference = lhs as i64 - rhs as i64 - borrow;
+            let signed_difference = signed(lhs, bits) - signed(rhs, bits) - borrow;
+
+            outcome(
+                difference.rem_euclid(2i64.pow(bits)) as u32,
+                bits,
+                overflows(signed_difference, bits),
+                difference >= 0,
+            )
+        }
+
+        // The difference CMP sets N and Z from, with no borrow going in.
+        pub fn difference(lhs: u32, rhs: u32, bits: u32) -> u32 {
+            (lhs as i64 - rhs as i64).rem_euclid(2i64.pow(bits)) as u32
+        }
+
+        // Doubling the value, with the bit that falls off the top in C. V is
+        // whatever it was before.
+        pub fn asl(value: u32, overflow: bool, bits: u32) -> Outcome {
+            let doubled = value * 2;
+
+            outcome(
+                doubled % 2u32.pow(bits),
+                bits,
+                overflow,
+                doubled >= 2u32.pow(bits),
+            )
+        }
+    }
+
+    use reference::Outcome;
+
+    // Everything but the flags the operations change, so that each case runs
+    // with a random mix of the rest.
+    fn other_flags() -> impl Strategy<Value = Flags> {
+        any::<u8>().prop_map(|bits| {
+            Flags::from_bits_truncate(bits)
+                .difference(Flags::NEGATIVE | Flags::OVERFLOW | Flags::ZERO | Flags::CARRY)
+        })
+    }
+
+    fn with(flags: Flags, flag: Flags, set: bool) -> Flags {
+        match set {
+            true => flags | flag,
+            false => flags,
+        }
+    }
+
+    // Checks the result and the flags against the reference, and that the
+    // flags it doesn't cover were left alone.
+    fn check(before: Flags, after: Flags, result: u32, expected: Outcome) {
+        let actual = Outcome {
+            result,
+            negative: after.contains(Flags::NEGATIVE),
+            overflow: after.contains(Flags::OVERFLOW),
+            zero: after.contains(Flags::ZERO),
+            carry: after.contains(Flags::CARRY),
+        };
+
+        assert_eq!(actual, expected);
+
+        let covered = Flags::NEGATIVE | Flags::OVERFLOW | Flags::ZERO | Flags::CARRY;
+        assert_eq!(after.difference(covered), before.difference(covered));
+    }
+
+    proptest! {
+        // Decimal mode isn't implemented yet, so D is kept clear.
+        #[test]
+        fn adc_binary(lhs: u16, rhs: u16, carry: bool, flags in other_flags()) {
+            let flags = with(flags - Flags::DECIMAL_MODE, Flags::CARRY, carry);
+
+            let mut after = flags;
+            let result = adc8(&mut after, lhs as u8, rhs as u8);
+            check(flags, after, result as u32, reference::adc(lhs as u32 & 0xFF, rhs as u32 & 0xFF, carry, 8));
+
+            let mut after = flags;
+            let result = adc16(&mut after, lhs, rhs);
+            check(flags, after, result as u32, reference::adc(lhs as u32, rhs as u32, carry, 16));
+        }
+
+        #[test]
+        fn sbc_binary(lhs: u16, rhs: u16, carry: bool, flags in other_flags()) {
+            let flags = with(flags - Flags::DECIMAL_MODE, Flags::CARRY, carry);
+
+            let mut after = flags;
+            let result = sbc8(&mut after, lhs as u8, rhs as u8);
+            check(flags, after, result as u32, reference::sbc(lhs as u32 & 0xFF, rhs as u32 & 0xFF, carry, 8));
+
+            let mut after = flags;
+            let result = sbc16(&mut after, lhs, rhs);
+            check(flags, after, result as u32, reference::sbc(lhs as u32, rhs as u32, carry, 16));
+        }
+
+        // Exactly one of less, equal and greater holds, and the flags say
+        // which. N is the top bit of the difference, and V is left alone.
+        #[test]
+        fn cmp_trichotomy(lhs: u16, rhs: u16, carry: bool, overflow: bool, flags in other_flags()) {
+            let flags = with(with(flags, Flags::CARRY, carry), Flags::OVERFLOW, overflow);
+
+            for (lhs, rhs, bits) in [(lhs & 0xFF, rhs & 0xFF, 8), (lhs, rhs, 16)] {
+                let mut after = flags;
+
+                match bits {
+                    8 => cmp8(&mut after, lhs as u8, rhs as u8),
+                    _ => cmp16(&mut after, lhs, rhs),
+                }
+
+                let zero = after.contains(Flags::ZERO);
+                let carry = after.contains(Flags::CARRY);
+
+                prop_assert_eq!(lhs == rhs, zero && carry);
+                prop_assert_eq!(lhs > rhs, carry && !zero);
+                prop_assert_eq!(lhs < rhs, !carry && !zero);
+
+                let difference = reference::difference(lhs as u32, rhs as u32, bits);
+                prop_assert_eq!(after.contains(Flags::NEGATIVE), difference >= 2u32.pow(bits - 1));
+                prop_assert_eq!(after.contains(Flags::OVERFLOW), overflow);
+                prop_assert_eq!(after.difference(Flags::NEGATIVE | Flags::ZERO | Flags::CARRY), flags - Flags::CARRY);
+            }
+        }
+
+        // A shift left is a doubling, whatever the carry was going in.
+        #[test]
+        fn asl_doubles(value: u16, carry: bool, overflow: bool, flags in other_flags()) {
+            let flags = with(with(flags, Flags::CARRY, carry), Flags::OVERFLOW, overflow);
+
+            let mut after = flags;
+            let result = asl8(&mut after, value as u8);
+            check(flags, after, result as u32, reference::asl(value as u32 & 0xFF, overflow, 8));
+
+            let mut after = flags;
+            let result = asl16(&mut after, value);
+            check(flags, after, result as u32, reference::asl(value as u32, overflow, 16));
+        }
+
+        // A rotate undoes the rotate the other way, carry and all, and a
+        // shift is a rotate with the carry clear.
+        #[test]
+        fn shifts_round_trip(value: u16, carry: bool, flags in other_flags()) {
+            let flags = with(flags, Flags::CARRY, carry);
+
+            let mut after = flags;
+            let rotated = rol8(&mut after, value as u8);
+            prop_assert_eq!(after.contains(Flags::CARRY), value & 0x80 != 0);
+            prop_assert_eq!(ror8(&mut after, rotated), value as u8);
+            prop_assert_eq!(after, flags.difference(Flags::NEGATIVE | Flags::ZERO) | nz(value as u8 as u16, 8));
+
+            let mut after = flags;
+            let rotated = ror16(&mut after, value);
+            prop_assert_eq!(after.contains(Flags::CARRY), value & 1 != 0);
+            prop_assert_eq!(rol16(&mut after, rotated), value);
+            prop_assert_eq!(after, flags.difference(Flags::NEGATIVE | Flags::ZERO) | nz(value, 16));
+
+            let clear = flags - Flags::CARRY;
+            let (mut shifted, mut rotated) = (flags, clear);
+            prop_assert_eq!(asl8(&mut shifted, value as u8), rol8(&mut rotated, value as u8));
+            prop_assert_eq!(shifted, rotated);
+
+            let (mut shifted, mut rotated) = (flags, clear);
+            prop_assert_eq!(lsr16(&mut shifted, value), ror16(&mut rotated, value));
+            prop_assert_eq!(shifted, rotated);
+
+            // Shifting left then right loses only the top bit.
+            let mut after = flags;
+            let shifted = asl16(&mut after, value);
+            prop_assert_eq!(lsr16(&mut after, shifted), value & 0x7FFF);
+            prop_assert!(!after.contains(Flags::CARRY));
+
+            let mut after = flags;
+            let shifted = lsr8(&mut after, value as u8);
+            prop_assert_eq!(asl8(&mut after, shifted), value as u8 & 0xFE);
+            prop_assert!(!after.contains(Flags::CARRY));
+        }
+    }
+
+    // N and Z for a value, as the reference sees them.
+    fn nz(value: u16, bits: u32) -> Flags {
+        let outcome = reference::adc(value as u32, 0, false, bits);
+        let mut flags = Flags::empty();
+
+        flags.set(Flags::NEGATIVE, outcome.negative);
+        flags.set(Flags::ZERO, outcome.zero);
+
+        flags
+    }
+}
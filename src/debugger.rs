@@ -0,0 +1,887 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::cdl::BankCoverage;
+use crate::cheat::Cheat;
+use crate::cpu::{CallFrame, Cpu, DecodedInstruction, Flags, Register};
+use crate::emulator::Stats;
+use crate::inst::Instruction;
+use crate::mmu::Mmu;
+use crate::ram_search::{Filter, Width};
+use crate::symbols::Symbols;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(u64),
+
+    // Runs until the instruction after the next one, so that a subroutine
+    // call runs to completion.
+    StepOver,
+
+    // Runs until the current subroutine returns.
+    Finish,
+    Continue,
+    Registers,
+    Disassemble(Option<u32>),
+    Memory(u32, usize),
+    Break(u32, Option<Condition>),
+    ClearBreak(u32),
+    SaveState(String),
+    Rewind,
+    Profile(usize),
+    Coverage,
+    Io(usize),
+    Events(usize),
+    Writes(u32, u32),
+    Palette(Option<String>),
+    Tiles(u8, u8, String),
+    Tilemap(usize, String),
+    SearchStart(Width),
+    SearchFilter(Filter),
+    SearchList,
+    Cheats,
+    AddCheat(Cheat),
+    EnableCheat(usize, bool),
+    Backtrace,
+    Stats,
+    Quit,
+}
+
+pub const HELP: &str = "\
+s [n]        step n instructions (default: 1)
+n            step over the next instruction, running any call it makes until it returns
+fin          run until the current subroutine returns
+c            continue until a breakpoint or watchpoint
+r            show registers, and the last instruction's operand
+bt           show the subroutine calls that led to PC
+d [addr]     disassemble at addr (default: PC)
+m addr len   dump memory
+b addr       set a breakpoint
+b addr if c  only stop at addr when c holds, e.g. A==0x4F && [7E0100]>0x10
+bc addr      clear a breakpoint
+save path    write a save state to path
+rw           rewind to the last snapshot (needs --rewind)
+p [n]        show the n hottest addresses and opcodes (needs --profile)
+stats        show how much the emulator has run, and how fast
+io [n]       show the last n register accesses (default: 20, needs --io-ring)
+ev [n]       show the last n hardware events (default: 20, needs --events)
+w addr [len] show the remembered writes to len bytes from addr (default: 1, needs
+             --write-ring)
+pal [path]   show CGRAM, and write it to path as a PNG if given
+tiles d p path
+             write VRAM to path as a sheet of d bpp tiles, colored with palette p
+tilemap n path
+             write BG n's whole tilemap to path
+search start [8|16]
+             start a search of WRAM for a variable, as bytes or words (default: 8)
+search eq v  keep the addresses that now hold v
+search gt|lt|changed|unchanged
+             keep the addresses that went up, down, changed or stayed the same since
+             the last search
+search list  show the addresses left, and what last wrote them (with --write-ring)
+cheat        list the cheats, numbered
+cheat add code
+             add a Game Genie (XXXX-XXXX) or Pro Action Replay (AAAAAAVV) code
+cheat on|off n
+             turn cheat n on or off
+cdl          show how much of each ROM bank has been run or read (needs --cdl)
+q            quit";
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let mut parts = line.split_whitespace();
+
+    let Some(name) = parts.next() else {
+        return Err("no command given".into());
+    };
+
+    let args: Vec<&str> = parts.collect();
+
+    let address = |arg: &str| parse_address(arg).ok_or(format!("invalid address: {}", arg));
+
+    let command = match (name, args.as_slice()) {
+        ("s" | "step", []) => Command::Step(1),
+        ("s" | "step", [n]) => {
+            Command::Step(n.parse().map_err(|_| format!("invalid count: {}", n))?)
+        }
+        ("n" | "next", []) => Command::StepOver,
+        ("fin" | "finish", []) => Command::Finish,
+        ("c" | "continue", []) => Command::Continue,
+        ("r" | "registers", []) => Command::Registers,
+        ("bt" | "backtrace", []) => Command::Backtrace,
+        ("d" | "disassemble", []) => Command::Disassemble(None),
+        ("d" | "disassemble", [addr]) => Command::Disassemble(Some(address(addr)?)),
+        ("m" | "memory", [addr, len]) => Command::Memory(
+            address(addr)?,
+            len.parse()
+                .map_err(|_| format!("invalid length: {}", len))?,
+        ),
+        ("b" | "break", [_, ..]) => {
+            let (addr, condition) = parse_breakpoint(&args.join(" "))?;
+            Command::Break(addr, condition)
+        }
+        ("bc", [addr]) => Command::ClearBreak(address(addr)?),
+        ("save", [path]) => Command::SaveState(path.to_string()),
+        ("rw" | "rewind", []) => Command::Rewind,
+        ("cdl", []) => Command::Coverage,
+        ("io", []) => Command::Io(20),
+        ("ev" | "events", []) => Command::Events(20),
+        ("pal" | "palette", []) => Command::Palette(None),
+        ("pal" | "palette", [path]) => Command::Palette(Some(path.to_string())),
+        ("tiles", [depth, palette, path]) => Command::Tiles(
+            match *depth {
+                "2" | "4" | "8" => depth.parse().unwrap(),
+                _ => return Err(format!("invalid bit depth: {}", depth)),
+            },
+            palette
+                .parse()
+                .map_err(|_| format!("invalid palette: {}", palette))?,
+            path.to_string(),
+        ),
+        ("tilemap", [bg, path]) => Command::Tilemap(
+            match bg.parse() {
+                Ok(bg @ 1..=4) => bg - 1,
+                _ => return Err(format!("invalid BG: {}", bg)),
+            },
+            path.to_string(),
+        ),
+        ("w" | "writes", [addr]) => Command::Writes(address(addr)?, 1),
+        ("w" | "writes", [addr, len]) => Command::Writes(
+            address(addr)?,
+            match len.parse() {
+                Ok(len @ 1..) => len,
+                _ => return Err(format!("invalid length: {}", len)),
+            },
+        ),
+        ("io", [n]) => Command::Io(n.parse().map_err(|_| format!("invalid count: {}", n))?),
+        ("ev" | "events", [n]) => {
+            Command::Events(n.parse().map_err(|_| format!("invalid count: {}", n))?)
+        }
+        ("search", ["start"]) => Command::SearchStart(Width::Byte),
+        ("search", ["start", width]) => Command::SearchStart(match *width {
+            "8" => Width::Byte,
+            "16" => Width::Word,
+            _ => return Err(format!("invalid width: {}", width)),
+        }),
+        ("search", ["eq", value]) => Command::SearchFilter(Filter::Equal(
+            parse_number(value)
+                .and_then(|value| u16::try_from(value).ok())
+                .ok_or(format!("invalid value: {}", value))?,
+        )),
+        ("search", ["gt"]) => Command::SearchFilter(Filter::Greater),
+        ("search", ["lt"]) => Command::SearchFilter(Filter::Less),
+        ("search", ["changed"]) => Command::SearchFilter(Filter::Changed),
+        ("search", ["unchanged"]) => Command::SearchFilter(Filter::Unchanged),
+        ("search", ["list"]) => Command::SearchList,
+        ("cheat", []) => Command::Cheats,
+        ("cheat", ["add", code]) => Command::AddCheat(Cheat::parse(code)?),
+        ("cheat", [state @ ("on" | "off"), n]) => Command::EnableCheat(
+            n.parse().map_err(|_| format!("invalid cheat: {}", n))?,
+            *state == "on",
+        ),
+        ("stats", []) => Command::Stats,
+        ("p" | "profile", []) => Command::Profile(20),
+        ("p" | "profile", [n]) => {
+            Command::Profile(n.parse().map_err(|_| format!("invalid count: {}", n))?)
+        }
+        ("q" | "quit", []) => Command::Quit,
+        _ => return Err(format!("unknown command: {}", line.trim())),
+    };
+
+    Ok(command)
+}
+
+// Accepts either `bank:addr` or a full 24-bit address, in hex.
+pub fn parse_address(value: &str) -> Option<u32> {
+    let digits = value.trim_start_matches('$');
+
+    match digits.split_once(':') {
+        Some((bank, addr)) => {
+            let bank = u8::from_str_radix(bank, 16).ok()?;
+            let addr = u16::from_str_radix(addr, 16).ok()?;
+
+            Some((bank as u32) << 16 | addr as u32)
+        }
+
+        None => u32::from_str_radix(digits, 16)
+            .ok()
+            .filter(|&addr| addr <= 0xFF_FFFF),
+    }
+}
+
+// Accepts an address, optionally followed by `if` and a condition.
+pub fn parse_breakpoint(value: &str) -> Result<(u32, Option<Condition>), String> {
+    let value = value.trim();
+
+    let (addr, condition) = match value.split_once(char::is_whitespace) {
+        Some((addr, rest)) => {
+            let condition = rest
+                .trim_start()
+                .strip_prefix("if")
+                .filter(|condition| condition.starts_with(char::is_whitespace))
+                .ok_or(format!(
+                    "expected `if` after the address, got {}",
+                    rest.trim()
+                ))?;
+
+            (addr, Some(parse_condition(condition)?))
+        }
+
+        None => (value, None),
+    };
+
+    let addr = parse_address(addr).ok_or(format!("invalid address: {}", addr))?;
+
+    Ok((addr, condition))
+}
+
+// Something a breakpoint condition can look at. Registers are compared at
+// their current width, so in 8-bit mode `A` is only the low byte, the same
+// as the instructions at the breakpoint would see it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    A,
+    X,
+    Y,
+    DataBank,
+    DirectPage,
+    StackPointer,
+    Flag(Flags),
+    Emulation,
+    Byte(u32),
+    Word(u32),
+    Constant(u32),
+}
+
+impl Value {
+    fn eval(self, cpu: &Cpu, mmu: &Mmu) -> u32 {
+        let register = |register: Register| {
+            let value = cpu.get_register(register) as u32;
+
+            if cpu.is_eight_bit_mode(register) {
+                value & 0xFF
+            } else {
+                value
+            }
+        };
+
+        match self {
+            Value::A => register(Register::A),
+            Value::X => register(Register::X),
+            Value::Y => register(Register::Y),
+            Value::DataBank => cpu.data_bank() as u32,
+            Value::DirectPage => cpu.get_register(Register::D) as u32,
+            Value::StackPointer => cpu.sp() as u32,
+            Value::Flag(flag) => cpu.flag(flag) as u32,
+            Value::Emulation => cpu.emulation() as u32,
+            Value::Byte(addr) => mmu.peek_u8(addr) as u32,
+            Value::Word(addr) => {
+                let high = mmu.peek_u8(addr.wrapping_add(1) & 0xFF_FFFF);
+                u16::from_le_bytes([mmu.peek_u8(addr), high]) as u32
+            }
+            Value::Constant(value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+// A parsed breakpoint condition. This is checked every time the breakpoint's
+// address is reached, so it's kept as a tree that can be evaluated without
+// allocating, and memory is peeked rather than read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Compare(Value, Comparison, Value),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    pub fn eval(&self, cpu: &Cpu, mmu: &Mmu) -> bool {
+        match self {
+            Condition::Compare(lhs, comparison, rhs) => {
+                let (lhs, rhs) = (lhs.eval(cpu, mmu), rhs.eval(cpu, mmu));
+
+                match comparison {
+                    Comparison::Equal => lhs == rhs,
+                    Comparison::NotEqual => lhs != rhs,
+                    Comparison::Less => lhs < rhs,
+                    Comparison::LessOrEqual => lhs <= rhs,
+                    Comparison::Greater => lhs > rhs,
+                    Comparison::GreaterOrEqual => lhs >= rhs,
+                }
+            }
+
+            Condition::And(lhs, rhs) => lhs.eval(cpu, mmu) && rhs.eval(cpu, mmu),
+            Condition::Or(lhs, rhs) => lhs.eval(cpu, mmu) || rhs.eval(cpu, mmu),
+        }
+    }
+}
+
+// Parses conditions like `A==0x004F && [7E0100]>0x10`. `&&` binds tighter
+// than `||`, and parentheses can be used to group. The operands are:
+//
+// * A, X, Y, DB, D and SP
+// * P.N, P.V, P.M, P.X, P.D, P.I, P.Z and P.C for the flags, and E for the
+//   emulation bit
+// * [addr] for the byte at addr, or [addr].w for the word
+// * numbers, in hex with a 0x or $ prefix, or in decimal otherwise
+//
+// An operand on its own is true if it's non-zero, so `P.Z` works as a
+// condition too.
+pub fn parse_condition(input: &str) -> Result<Condition, String> {
+    let mut parser = ConditionParser { input, pos: 0 };
+    let condition = parser.or()?;
+
+    parser.skip_whitespace();
+
+    if parser.pos < input.len() {
+        return Err(format!(
+            "unexpected `{}` in condition",
+            &input[parser.pos..]
+        ));
+    }
+
+    Ok(condition)
+}
+
+struct ConditionParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl ConditionParser<'_> {
+    fn rest(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.and()?;
+
+        while self.eat("||") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.comparison()?;
+
+        while self.eat("&&") {
+            condition = Condition::And(Box::new(condition), Box::new(self.comparison()?));
+        }
+
+        Ok(condition)
+    }
+
+    fn comparison(&mut self) -> Result<Condition, String> {
+        if self.eat("(") {
+            let condition = self.or()?;
+
+            if !self.eat(")") {
+                return Err("missing `)` in condition".into());
+            }
+
+            return Ok(condition);
+        }
+
+        let lhs = self.value()?;
+
+        // The two character operators have to be tried first.
+        let comparison = [
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.eat(token));
+
+        match comparison {
+            Some((_, comparison)) => Ok(Condition::Compare(lhs, comparison, self.value()?)),
+            None => Ok(Condition::Compare(
+                lhs,
+                Comparison::NotEqual,
+                Value::Constant(0),
+            )),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if self.eat("[") {
+            let Some(len) = self.rest().find(']') else {
+                return Err("missing `]` in condition".into());
+            };
+
+            let addr = self.rest()[..len].trim();
+            let addr = parse_address(addr).ok_or(format!("invalid address: {}", addr))?;
+            self.pos += len + 1;
+
+            return Ok(if self.rest().starts_with(".w") {
+                self.pos += 2;
+                Value::Word(addr)
+            } else {
+                Value::Byte(addr)
+            });
+        }
+
+        self.skip_whitespace();
+
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '$'))
+            .unwrap_or(self.rest().len());
+
+        let input = self.input;
+        let word = &input[self.pos..self.pos + len];
+        self.pos += len;
+
+        let value = match word.to_ascii_uppercase().as_str() {
+            "" => return Err("expected a value in condition".into()),
+            "A" => Value::A,
+            "X" => Value::X,
+            "Y" => Value::Y,
+            "DB" => Value::DataBank,
+            "D" => Value::DirectPage,
+            "SP" => Value::StackPointer,
+            "E" => Value::Emulation,
+            "P.N" => Value::Flag(Flags::NEGATIVE),
+            "P.V" => Value::Flag(Flags::OVERFLOW),
+            "P.M" => Value::Flag(Flags::MEMORY_SELECT),
+            "P.X" => Value::Flag(Flags::INDEX_REGISTER),
+            "P.D" => Value::Flag(Flags::DECIMAL_MODE),
+            "P.I" => Value::Flag(Flags::IRQ_DISABLE),
+            "P.Z" => Value::Flag(Flags::ZERO),
+            "P.C" => Value::Flag(Flags::CARRY),
+            _ => Value::Constant(parse_number(word).ok_or(format!("invalid value: {}", word))?),
+        };
+
+        Ok(value)
+    }
+}
+
+fn parse_number(value: &str) -> Option<u32> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .or_else(|| value.strip_prefix('$'));
+
+    match hex {
+        Some(digits) => u32::from_str_radix(digits, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// Lists the calls in progress, innermost first, one per line.
+pub fn format_backtrace(frames: &[CallFrame], symbols: &Symbols) -> String {
+    let describe = |addr: u32| match symbols.describe(addr) {
+        Some(location) => format!("{:06X} ({})", addr, location),
+        None => format!("{:06X}", addr),
+    };
+
+    let mut output = String::new();
+
+    for (i, frame) in frames.iter().rev().enumerate() {
+        let _ = writeln!(
+            output,
+            "#{:<2} {}, called from {}",
+            i,
+            describe(frame.target),
+            describe(frame.caller)
+        );
+    }
+
+    output
+}
+
+pub fn format_instruction(addr: u32, opcode: u8) -> String {
+    let instruction = Instruction::from_opcode(opcode);
+
+    format!(
+        "[{:>06X}] {:02X} {} {}",
+        addr,
+        opcode,
+        instruction,
+        instruction.addressing_mode()
+    )
+    .trim_end()
+    .to_string()
+}
+
+// The same as format_instruction, but with the operand bytes and the
+// address the instruction will access.
+pub fn format_decoded(decoded: &DecodedInstruction) -> String {
+    let bytes: Vec<String> = decoded
+        .bytes()
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+
+    let mut output = format!(
+        "[{:>06X}] {:<11} {} {}",
+        decoded.disassembly.pc,
+        bytes.join(" "),
+        decoded.instruction,
+        decoded.instruction.addressing_mode()
+    )
+    .trim_end()
+    .to_string();
+
+    if let Some(addr) = decoded.effective_addr() {
+        let _ = write!(output, " [{:06X}]", addr);
+    }
+
+    output
+}
+
+// Formats memory 16 bytes to a line, with the printable characters
+// alongside.
+pub fn hexdump(start: u32, bytes: &[u8]) -> String {
+    let mut output = String::new();
+
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let _ = write!(
+            output,
+            "{:06X}:",
+            start.wrapping_add(i as u32 * 16) & 0xFF_FFFF
+        );
+
+        for byte in line {
+            let _ = write!(output, " {:02X}", byte);
+        }
+
+        // Keep the text column aligned on a short final line.
+        for _ in line.len()..16 {
+            output.push_str("   ");
+        }
+
+        output.push_str("  |");
+
+        for &byte in line {
+            output.push(if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            });
+        }
+
+        output.push_str("|\n");
+    }
+
+    output
+}
+
+// Summarizes the emulator's counters. The elapsed time should only cover
+// time spent emulating, so that the speed isn't thrown off by pausing.
+pub fn format_stats(stats: &Stats, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+
+    let per_frame = match stats.frames {
+        0 => String::from("-"),
+        frames => format!("{:.2}ms", seconds * 1000.0 / frames as f64),
+    };
+
+    let speed = match seconds {
+        0.0 => String::from("-"),
+        _ => format!("{:.2}MHz", stats.cycles as f64 / seconds / 1_000_000.0),
+    };
+
+    format!(
+        concat!(
+            "Instructions: {}\n",
+            "Steps:        {}\n",
+            "Cycles:       {} ({} effective)\n",
+            "Frames:       {} ({} per frame)\n",
+            "NMIs:         {}\n",
+            "IRQs:         {}\n",
+            "DMA bytes:    {}\n",
+            "Time:         {:.2}s\n",
+        ),
+        stats.instructions,
+        stats.steps,
+        stats.cycles,
+        speed,
+        stats.frames,
+        per_frame,
+        stats.nmis,
+        stats.irqs,
+        stats.dma_bytes,
+        seconds,
+    )
+}
+
+// Lists the banks of the ROM that have been touched, with how many bytes of
+// each were run as code or read as data.
+pub fn format_coverage(coverage: &[BankCoverage]) -> String {
+    let mut output = String::from("Bank   Opcodes  Operands      Data  Coverage\n");
+
+    for bank in coverage {
+        if bank.touched == 0 {
+            continue;
+        }
+
+        let _ = writeln!(
+            output,
+            "{:>4X} {:>9} {:>9} {:>9} {:>8.2}%",
+            bank.bank,
+            bank.opcodes,
+            bank.operands,
+            bank.data,
+            bank.touched as f64 * 100.0 / bank.size as f64
+        );
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CpuState;
+
+    #[test]
+    fn commands() {
+        let cases = [
+            ("s", Command::Step(1)),
+            ("step 25", Command::Step(25)),
+            ("c", Command::Continue),
+            ("r", Command::Registers),
+            ("d", Command::Disassemble(None)),
+            ("d 00:8000", Command::Disassemble(Some(0x00_8000))),
+            ("m 7E:0100 32", Command::Memory(0x7E_0100, 32)),
+            ("m $7E0100 4", Command::Memory(0x7E_0100, 4)),
+            ("b 80:8123", Command::Break(0x80_8123, None)),
+            ("bc 80:8123", Command::ClearBreak(0x80_8123)),
+            ("w 7E:0010", Command::Writes(0x7E_0010, 1)),
+            ("writes 00:0010 4", Command::Writes(0x00_0010, 4)),
+            ("ev", Command::Events(20)),
+            ("events 5", Command::Events(5)),
+            ("search start", Command::SearchStart(Width::Byte)),
+            ("search start 16", Command::SearchStart(Width::Word)),
+            (
+                "search eq $1F4",
+                Command::SearchFilter(Filter::Equal(0x1F4)),
+            ),
+            ("search gt", Command::SearchFilter(Filter::Greater)),
+            ("search unchanged", Command::SearchFilter(Filter::Unchanged)),
+            ("search list", Command::SearchList),
+            ("cheat", Command::Cheats),
+            (
+                "cheat add 7E0DBE:FF",
+                Command::AddCheat(Cheat::parse("7E0DBE:FF").unwrap()),
+            ),
+            ("cheat off 2", Command::EnableCheat(2, false)),
+            ("  q  \n", Command::Quit),
+        ];
+
+        for (line, command) in cases {
+            assert_eq!(parse_command(line), Ok(command), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn command_errors() {
+        let cases = [
+            ("", "no command given"),
+            ("s lots", "invalid count: lots"),
+            ("d zz:8000", "invalid address: zz:8000"),
+            ("m 7E:0100", "unknown command: m 7E:0100"),
+            ("m 7E:0100 -1", "invalid length: -1"),
+            ("w 7E:0010 0", "invalid length: 0"),
+            ("ev all", "invalid count: all"),
+            ("search start 32", "invalid width: 32"),
+            ("search eq 70000", "invalid value: 70000"),
+            ("cheat add ZZZZ-ZZZZ", "invalid Game Genie code: ZZZZ-ZZZZ"),
+            ("cheat on x", "invalid cheat: x"),
+            ("b 1000000", "invalid address: 1000000"),
+            (
+                "b 00:8000 when A==0",
+                "expected `if` after the address, got when A==0",
+            ),
+            ("step 1 2", "unknown command: step 1 2"),
+            ("jump", "unknown command: jump"),
+        ];
+
+        for (line, error) in cases {
+            assert_eq!(parse_command(line), Err(error.to_string()), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(parse_address("7E:0100"), Some(0x7E_0100));
+        assert_eq!(parse_address("$00:FFFC"), Some(0x00_FFFC));
+        assert_eq!(parse_address("C08000"), Some(0xC0_8000));
+        assert_eq!(parse_address("100:0000"), None);
+        assert_eq!(parse_address("00:10000"), None);
+        assert_eq!(parse_address("1000000"), None);
+    }
+
+    #[test]
+    fn conditions() {
+        let compare = |lhs, comparison, rhs| Condition::Compare(lhs, comparison, rhs);
+        let a = compare(Value::A, Comparison::Equal, Value::Constant(1));
+        let x = compare(Value::X, Comparison::Equal, Value::Constant(2));
+        let y = compare(Value::Y, Comparison::Equal, Value::Constant(3));
+
+        // && binds tighter than ||, unless there are parentheses.
+        assert_eq!(
+            parse_condition("A==1 || X==2 && Y==3"),
+            Ok(Condition::Or(
+                Box::new(a.clone()),
+                Box::new(Condition::And(Box::new(x.clone()), Box::new(y.clone())))
+            ))
+        );
+        assert_eq!(
+            parse_condition("(a == 1 || x == 2) && y == 3"),
+            Ok(Condition::And(
+                Box::new(Condition::Or(Box::new(a), Box::new(x))),
+                Box::new(y)
+            ))
+        );
+
+        assert_eq!(
+            parse_condition("P.Z"),
+            Ok(compare(
+                Value::Flag(Flags::ZERO),
+                Comparison::NotEqual,
+                Value::Constant(0)
+            ))
+        );
+        assert_eq!(
+            parse_condition("[7E:0100].w >= $10"),
+            Ok(compare(
+                Value::Word(0x7E_0100),
+                Comparison::GreaterOrEqual,
+                Value::Constant(0x10)
+            ))
+        );
+    }
+
+    #[test]
+    fn condition_errors() {
+        let cases = [
+            ("", "expected a value in condition"),
+            ("A == ", "expected a value in condition"),
+            ("(A==1", "missing `)` in condition"),
+            ("[7E0100 == 1", "missing `]` in condition"),
+            ("[zz] == 1", "invalid address: zz"),
+            ("A == 0xZZ", "invalid value: 0xZZ"),
+            ("A = 1", "unexpected `= 1` in condition"),
+            ("A==1 X==2", "unexpected `X==2` in condition"),
+        ];
+
+        for (input, error) in cases {
+            assert_eq!(
+                parse_condition(input),
+                Err(error.to_string()),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn conditions_see_registers_at_their_width() {
+        let mut mmu = Mmu::new(vec![0; 0x8000], None);
+        mmu.store_u8(0x7E_0100, 0x20);
+        mmu.store_u8(0x7E_0101, 0x01);
+
+        let eval = |flags: Flags, condition: &str| {
+            let mut cpu = Cpu::new();
+            cpu.set_state(&CpuState {
+                a: 0x1234,
+                x: 0x1234,
+                y: 0xABCD,
+                status: flags.bits(),
+                ..CpuState::default()
+            });
+
+            parse_condition(condition).unwrap().eval(&cpu, &mmu)
+        };
+
+        let wide = Flags::empty();
+        assert!(eval(wide, "A == 0x1234 && X == 0x1234 && Y == 0xABCD"));
+        assert!(!eval(wide, "A == 0x34"));
+
+        assert!(eval(Flags::MEMORY_SELECT, "A == 0x34 && X == 0x1234"));
+        assert!(!eval(Flags::MEMORY_SELECT, "A == 0x1234"));
+
+        assert!(eval(
+            Flags::INDEX_REGISTER,
+            "A == 0x1234 && X == 0x34 && Y == 0xCD"
+        ));
+        assert!(!eval(Flags::INDEX_REGISTER, "X > 0xFF || Y > 0xFF"));
+
+        // Evaluated the way it's grouped, whichever side comes first.
+        assert!(eval(wide, "A == 0 && X == 0 || Y == 0xABCD"));
+        assert!(!eval(wide, "A == 0 && (X == 0 || Y == 0xABCD)"));
+
+        assert!(eval(wide, "[7E0100] > 0x10 && [7E0100].w == 0x0120"));
+        assert!(eval(Flags::CARRY, "P.C && P.Z == 0"));
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(format_instruction(0x00_8000, 0xBD), "[008000] BD LDA abs,X");
+        assert_eq!(
+            format_instruction(0x00_8000, 0x54),
+            "[008000] 54 MVN src,dest"
+        );
+
+        // Nothing trails implied instructions.
+        assert_eq!(format_instruction(0xC0_1234, 0x18), "[C01234] 18 CLC");
+        assert_eq!(format_instruction(0xC0_1234, 0xDB), "[C01234] DB ???");
+    }
+
+    #[test]
+    fn hexdump_lines() {
+        let bytes: Vec<u8> = (0x3C..0x54).collect();
+
+        assert_eq!(
+            hexdump(0x7E_0100, &bytes),
+            "7E0100: 3C 3D 3E 3F 40 41 42 43 44 45 46 47 48 49 4A 4B  |<=>?@ABCDEFGHIJK|\n\
+             7E0110: 4C 4D 4E 4F 50 51 52 53                          |LMNOPQRS|\n"
+        );
+    }
+
+    #[test]
+    fn hexdump_unprintable_and_wrapping() {
+        assert_eq!(
+            hexdump(0xFF_FFF8, &[0x00, 0x20, 0x7F, 0xFF]),
+            "FFFFF8: 00 20 7F FF                                      |. ..|\n"
+        );
+
+        // The address wraps around the 24-bit address space.
+        let dump = hexdump(0xFF_FFF8, &[0; 24]);
+        assert!(dump.lines().nth(1).unwrap().starts_with("000008:"));
+
+        assert_eq!(hexdump(0, &[]), "");
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt::Write;
+
+use crate::bus::Bus;
+use crate::cpu::{Cpu, Register};
+use crate::inst::{Mode, Opcode};
+
+use self::Mode::*;
+
+// An instruction along with the address it will access. The text is only
+// worked out when it's needed, as disassembly happens for every instruction
+// that's traced.
+#[derive(Clone, Copy)]
+pub struct Disassembly {
+    pub pc: u32,
+
+    // The address the instruction will access, where that can be worked
+    // out from the current CPU state.
+    pub effective_addr: Option<u32>,
+
+    bytes: [u8; 4],
+    len: u8,
+}
+
+impl Disassembly {
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn opcode(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    pub fn text(&self) -> String {
+        let mut output = String::new();
+        self.write_text(&mut output);
+
+        output
+    }
+
+    // Everything needed for the text is in the instruction itself, as the
+    // immediate sizes were fixed when the operand was read.
+    pub fn write_text(&self, output: &mut String) {
+        let Opcode { mnemonic, mode, .. } = *Opcode::get(self.opcode());
+        let operand_len = self.len as usize - 1;
+        let operand = self.operand();
+
+        let next_pc = (self.pc as u16).wrapping_add(self.len as u16);
+
+        output.push_str(mnemonic);
+
+        if !matches!(mode, Implied) {
+            output.push(' ');
+        }
+
+        let _ = match mode {
+            Implied => Ok(()),
+            Accumulator => write!(output, "a"),
+            Immediate8 | Immediate16 | ImmediateM | ImmediateX => {
+                write!(output, "#${:0width$x}", operand, width = operand_len * 2)
+            }
+
+            Direct => write!(output, "${:02x}", operand),
+            DirectX => write!(output, "${:02x},x", operand),
+            DirectY => write!(output, "${:02x},y", operand),
+            DirectIndirect => write!(output, "(${:02x})", operand),
+            DirectIndirectX => write!(output, "(${:02x},x)", operand),
+            DirectIndirectY => write!(output, "(${:02x}),y", operand),
+            DirectIndirectLong => write!(output, "[${:02x}]", operand),
+            DirectIndirectLongY => write!(output, "[${:02x}],y", operand),
+
+            Absolute | AbsoluteJump => write!(output, "${:04x}", operand),
+            AbsoluteX => write!(output, "${:04x},x", operand),
+            AbsoluteY => write!(output, "${:04x},y", operand),
+            AbsoluteLong => write!(output, "${:06x}", operand),
+            AbsoluteLongX => write!(output, "${:06x},x", operand),
+            AbsoluteIndirect => write!(output, "(${:04x})", operand),
+            AbsoluteIndirectX => write!(output, "(${:04x},x)", operand),
+            AbsoluteIndirectLong => write!(output, "[${:04x}]", operand),
+
+            StackRelative => write!(output, "${:02x},s", operand),
+            StackRelativeIndirectY => write!(output, "(${:02x},s),y", operand),
+
+            Relative | RelativeLong => {
+                write!(output, "${:04x}", relative_target(mode, next_pc, operand))
+            }
+
+            // The destination bank comes first in the encoding, but the source
+            // is written first.
+            BlockMove => write!(output, "${:02x},${:02x}", self.bytes[2], self.bytes[1]),
+        };
+    }
+
+    fn operand(&self) -> u32 {
+        self.bytes[1..self.len as usize]
+            .iter()
+            .rev()
+            .fold(0u32, |acc, &b| acc << 8 | b as u32)
+    }
+}
+
+fn relative_target(mode: Mode, next_pc: u16, operand: u32) -> u16 {
+    match mode {
+        RelativeLong => next_pc.wrapping_add(operand as u16),
+        _ => next_pc.wrapping_add_signed(operand as u8 as i8 as i16),
+    }
+}
+
+fn bank_addr(bank: u8, addr: u16) -> u32 {
+    (bank as u32) << 16 | addr as u32
+}
+
+pub fn mnemonic(opcode: u8) -> &'static str {
+    Opcode::get(opcode).mnemonic
+}
+
+// Disassembles the instruction at the CPU's current address, using the
+// register state to size immediates and resolve effective addresses.
+pub fn disassemble(cpu: &Cpu, mmu: &impl Bus) -> Disassembly {
+    disassemble_at(cpu, mmu, cpu.current_addr())
+}
+
+// The same as disassemble, but for an instruction somewhere other than the
+// current address. The register state might not match what it will be by
+// the time the instruction runs, so the sizes and addresses are a guess.
+//
+// Everything is read with peek, so that disassembling never counts as an
+// access (e.g. clearing a latch or tripping a watchpoint).
+pub fn disassemble_at(cpu: &Cpu, mmu: &impl Bus, pc: u32) -> Disassembly {
+    let opcode = mmu.peek_u8(pc);
+    let mode = Opcode::get(opcode).mode;
+    let operand_len = mode.operand_len(
+        cpu.is_eight_bit_mode(Register::A),
+        cpu.is_eight_bit_mode(Register::X),
+    );
+
+    let mut bytes = [opcode, 0, 0, 0];
+
+    for (i, byte) in bytes[1..=operand_len].iter_mut().enumerate() {
+        // Operands wrap within the program bank.
+        let addr = (pc & 0xFF_0000) | (pc.wrapping_add(i as u32 + 1) & 0xFFFF);
+        *byte = mmu.peek_u8(addr);
+    }
+
+    let mut disassembly = Disassembly {
+        pc,
+        effective_addr: None,
+        bytes,
+        len: 1 + operand_len as u8,
+    };
+
+    let operand = disassembly.operand();
+
+    let program_bank = (pc >> 16) as u8;
+    let next_pc = (pc as u16).wrapping_add(1 + operand_len as u16);
+
+    let d = cpu.get_register(Register::D);
+    let x = cpu.get_register(Register::X);
+    let y = cpu.get_register(Register::Y);
+    let data = |addr: u16| bank_addr(cpu.data_bank(), addr);
+    let direct = |offset: u16| d.wrapping_add(operand as u16).wrapping_add(offset) as u32;
+    let stack = cpu.sp().wrapping_add(operand as u16) as u32;
+
+    disassembly.effective_addr = match mode {
+        Implied | Accumulator | BlockMove => None,
+        Immediate8 | Immediate16 | ImmediateM | ImmediateX => None,
+
+        Direct => Some(direct(0)),
+        DirectX => Some(direct(x)),
+        DirectY => Some(direct(y)),
+        DirectIndirect => Some(data(mmu.peek_u16(direct(0)))),
+        DirectIndirectX => Some(data(mmu.peek_u16(direct(x)))),
+        DirectIndirectY => Some(data(mmu.peek_u16(direct(0))).wrapping_add(y as u32) & 0xFF_FFFF),
+        DirectIndirectLong => Some(mmu.peek_long(direct(0))),
+        DirectIndirectLongY => Some(mmu.peek_long(direct(0)).wrapping_add(y as u32) & 0xFF_FFFF),
+
+        Absolute => Some(data(operand as u16)),
+        AbsoluteJump => Some(bank_addr(program_bank, operand as u16)),
+        AbsoluteX => Some(data(operand as u16).wrapping_add(x as u32) & 0xFF_FFFF),
+        AbsoluteY => Some(data(operand as u16).wrapping_add(y as u32) & 0xFF_FFFF),
+        AbsoluteLong => Some(operand),
+        AbsoluteLongX => Some(operand.wrapping_add(x as u32) & 0xFF_FFFF),
+        AbsoluteIndirect => Some(bank_addr(program_bank, mmu.peek_u16(operand))),
+        AbsoluteIndirectX => {
+            let pointer = bank_addr(program_bank, (operand as u16).wrapping_add(x));
+            Some(bank_addr(program_bank, mmu.peek_u16(pointer)))
+        }
+        AbsoluteIndirectLong => Some(mmu.peek_long(operand)),
+
+        StackRelative => Some(stack),
+        StackRelativeIndirectY => {
+            Some(data(mmu.peek_u16(stack)).wrapping_add(y as u32) & 0xFF_FFFF)
+        }
+
+        Relative | RelativeLong => Some(bank_addr(
+            program_bank,
+            relative_target(mode, next_pc, operand),
+        )),
+    };
+
+    disassembly
+}
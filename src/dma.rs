@@ -0,0 +1,138 @@
+// Master cycles per byte transferred, and the overheads on top of that.
+pub const CYCLES_PER_BYTE: u64 = 8;
+pub const CYCLES_PER_CHANNEL: u64 = 8;
+pub const CYCLES_PER_TRANSFER: u64 = 18;
+
+// HDMA's overheads: once per line that any channel is active, per active
+// channel, and for fetching the data address of an indirect table entry.
+pub const HDMA_CYCLES_PER_LINE: u64 = 18;
+pub const HDMA_CYCLES_PER_CHANNEL: u64 = 8;
+pub const HDMA_CYCLES_PER_INDIRECT: u64 = 16;
+
+// The B-bus address offsets that each transfer mode cycles through.
+const PATTERNS: [&[u8]; 8] = [
+    &[0],
+    &[0, 1],
+    &[0, 0],
+    &[0, 0, 1, 1],
+    &[0, 1, 2, 3],
+    &[0, 1, 0, 1],
+    &[0, 0],
+    &[0, 0, 1, 1],
+];
+
+// One of the eight DMA channels, as seen through its registers at
+// $43x0-$43xF.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmaChannel {
+    // DMAPx
+    pub params: u8,
+
+    // BBADx
+    pub b_addr: u8,
+
+    // A1TxL/H, A1Bx
+    pub a_addr: u16,
+    pub a_bank: u8,
+
+    // DASxL/H. A count of zero transfers 64KB.
+    pub count: u16,
+
+    // DASBx, A2AxL/H and NTRLx are only used by HDMA. In indirect mode, DAS
+    // holds the address of the data for the current table entry.
+    pub indirect_bank: u8,
+    pub table_addr: u16,
+    pub line_counter: u8,
+
+    // HDMA state that isn't visible through the registers. A channel is
+    // terminated once it reaches the end of its table, until the next frame
+    // starts, and only transfers on the lines that do_transfer is set for.
+    pub hdma_terminated: bool,
+    pub hdma_do_transfer: bool,
+
+    // The unused byte at $43xB, which is also mirrored at $43xF.
+    pub unused: u8,
+}
+
+impl DmaChannel {
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0x0 => self.params,
+            0x1 => self.b_addr,
+            0x2 => self.a_addr as u8,
+            0x3 => (self.a_addr >> 8) as u8,
+            0x4 => self.a_bank,
+            0x5 => self.count as u8,
+            0x6 => (self.count >> 8) as u8,
+            0x7 => self.indirect_bank,
+            0x8 => self.table_addr as u8,
+            0x9 => (self.table_addr >> 8) as u8,
+            0xA => self.line_counter,
+            _ => self.unused,
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, value: u8) {
+        match reg {
+            0x0 => self.params = value,
+            0x1 => self.b_addr = value,
+            0x2 => self.a_addr = (self.a_addr & 0xFF00) | value as u16,
+            0x3 => self.a_addr = (self.a_addr & 0x00FF) | (value as u16) << 8,
+            0x4 => self.a_bank = value,
+            0x5 => self.count = (self.count & 0xFF00) | value as u16,
+            0x6 => self.count = (self.count & 0x00FF) | (value as u16) << 8,
+            0x7 => self.indirect_bank = value,
+            0x8 => self.table_addr = (self.table_addr & 0xFF00) | value as u16,
+            0x9 => self.table_addr = (self.table_addr & 0x00FF) | (value as u16) << 8,
+            0xA => self.line_counter = value,
+            _ => self.unused = value,
+        }
+    }
+
+    // True if the transfer goes from the B-bus to the A-bus.
+    pub fn b_to_a(&self) -> bool {
+        self.params & 0x80 != 0
+    }
+
+    pub fn pattern(&self) -> &'static [u8] {
+        PATTERNS[(self.params & 0x07) as usize]
+    }
+
+    // True if HDMA table entries point to the data, rather than holding it.
+    pub fn indirect(&self) -> bool {
+        self.params & 0x40 != 0
+    }
+
+    // Where HDMA reads its next table byte from.
+    pub fn table_bus_addr(&self) -> u32 {
+        (self.a_bank as u32) << 16 | self.table_addr as u32
+    }
+
+    // Where indirect HDMA reads its next data byte from.
+    pub fn indirect_bus_addr(&self) -> u32 {
+        (self.indirect_bank as u32) << 16 | self.count as u32
+    }
+
+    pub fn a_bus_addr(&self) -> u32 {
+        (self.a_bank as u32) << 16 | self.a_addr as u32
+    }
+
+    // Moves the A-bus address on after a byte, unless it's fixed. It wraps
+    // within the bank.
+    pub fn step_a_addr(&mut self) {
+        match self.params & 0x18 {
+            0x00 => self.a_addr = self.a_addr.wrapping_add(1),
+            0x10 => self.a_addr = self.a_addr.wrapping_sub(1),
+            _ => {}
+        }
+    }
+
+    // How many bytes the next transfer will move.
+    pub fn transfer_len(&self) -> u32 {
+        match self.count {
+            0 => 0x10000,
+            count => count as u32,
+        }
+    }
+}
@@ -0,0 +1,655 @@
+use std::io::{self, Write};
+
+use crate::battery::Battery;
+use crate::cpu::{Cpu, CpuState, Interrupt, Operand};
+use crate::disasm;
+use crate::events::EventKind;
+use crate::input::Buttons;
+use crate::inst::Instruction;
+use crate::mmu::{self, MapMode, Mmu};
+use crate::trace::TraceRecord;
+
+// The SPC700 runs at 1.024MHz, while the NTSC master clock runs at
+// 236.25MHz / 11. Reduced, that's 5632 APU cycles per 118125 master cycles.
+const APU_CLOCK_NUMERATOR: u64 = 5632;
+const APU_CLOCK_DENOMINATOR: u64 = 118125;
+
+// Converts cycles of one clock into another, carrying the fractional
+// remainder over so the two never drift apart.
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClockRatio {
+    numerator: u64,
+    denominator: u64,
+    remainder: u64,
+}
+
+impl ClockRatio {
+    pub fn new(numerator: u64, denominator: u64) -> ClockRatio {
+        ClockRatio {
+            numerator,
+            denominator,
+            remainder: 0,
+        }
+    }
+
+    pub fn advance(&mut self, cycles: u64) -> u64 {
+        let total = cycles * self.numerator + self.remainder;
+
+        self.remainder = total % self.denominator;
+
+        total / self.denominator
+    }
+}
+
+pub type InstructionHook = Box<dyn FnMut(&mut Emulator, &CpuState, u32)>;
+pub type FrameHook = Box<dyn FnMut(&mut Emulator)>;
+pub type WriteHook = Box<dyn FnMut(&mut Emulator, u32, u8)>;
+
+// Callbacks for automating the emulator. Each kind is only checked for if
+// at least one is registered, so they cost nothing otherwise.
+#[derive(Default)]
+pub struct Hooks {
+    instruction: Vec<InstructionHook>,
+    frame: Vec<FrameHook>,
+    write: Vec<WriteHook>,
+}
+
+// What happened while running a single instruction.
+#[derive(Clone, Copy, Debug)]
+pub struct StepResult {
+    pub cycles: u64,
+    pub frame_complete: bool,
+
+    // Set if the CPU didn't know how to run the instruction, in which case
+    // it only moved past the opcode. An interrupt being taken instead of the
+    // instruction, or the CPU waiting for one, doesn't count.
+    pub unknown_opcode: Option<u8>,
+
+    // The interrupt the CPU took instead of running an instruction.
+    pub interrupt: Option<Interrupt>,
+
+    // Where the instruction's operand was, if it had one.
+    pub operand: Option<Operand>,
+}
+
+// The instruction the CPU will run next, as far as can be told without
+// running it.
+#[derive(Clone, Copy, Debug)]
+pub struct NextInstruction {
+    pub addr: u32,
+    pub opcode: u8,
+
+    // False if an interrupt will be taken instead, or the CPU is waiting for
+    // one.
+    pub runs: bool,
+}
+
+impl NextInstruction {
+    pub fn instruction(self) -> Instruction {
+        Instruction::from_opcode(self.opcode)
+    }
+
+    pub fn unknown(self) -> bool {
+        self.runs && matches!(self.instruction(), Instruction::Unknown)
+    }
+}
+
+// How much work the emulator has done since it started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    // Every step of the CPU, which includes the ones that took an interrupt
+    // or waited in WAI rather than running an instruction.
+    pub steps: u64,
+    pub instructions: u64,
+    pub cycles: u64,
+    pub frames: u64,
+    pub nmis: u64,
+    pub irqs: u64,
+    pub dma_bytes: u64,
+}
+
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Emulator {
+    pub cpu: Cpu,
+    pub mmu: Mmu,
+
+    // If set, every SPC700 instruction is logged here.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub spc_trace: Option<Box<dyn Write>>,
+
+    // If set, SRAM is saved here as the game runs, and when the emulator is
+    // dropped.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub battery: Option<Box<dyn Battery>>,
+
+    // The last time the battery save couldn't be written as the game ran,
+    // until it's taken.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    battery_error: Option<io::Error>,
+
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    hooks: Hooks,
+
+    apu_clock: ClockRatio,
+
+    // How many cycles the SPC700 is behind the main CPU. This can go
+    // negative, as SPC700 instructions take multiple cycles.
+    apu_debt: i64,
+
+    steps: u64,
+    instructions: u64,
+    cycles: u64,
+
+    nmis: u64,
+    irqs: u64,
+    dma_bytes: u64,
+}
+
+impl Emulator {
+    // Fails if the ROM is too small to run, which would otherwise only show
+    // up as a panic on the first read from it.
+    pub fn new(mut cartridge: Vec<u8>, map_mode: Option<MapMode>) -> Result<Emulator, String> {
+        mmu::strip_copier_header(&mut cartridge);
+
+        if cartridge.len() < mmu::MIN_ROM_SIZE {
+            return Err(format!("too small to be a ROM ({} bytes)", cartridge.len()));
+        }
+
+        let mmu = Mmu::new(cartridge, map_mode);
+        let mut cpu = Cpu::new();
+        cpu.set_current_addr(mmu.reset_vector() as u32);
+
+        Ok(Emulator {
+            cpu,
+            mmu,
+
+            spc_trace: None,
+            battery: None,
+            battery_error: None,
+            hooks: Hooks::default(),
+
+            apu_clock: ClockRatio::new(APU_CLOCK_NUMERATOR, APU_CLOCK_DENOMINATOR),
+            apu_debt: 0,
+
+            steps: 0,
+            instructions: 0,
+            cycles: 0,
+
+            nmis: 0,
+            irqs: 0,
+            dma_bytes: 0,
+        })
+    }
+
+    // Runs a single CPU instruction, then catches the rest of the system up
+    // to it. Returns true if the PPU finished a frame.
+    pub fn step(&mut self) -> bool {
+        self.run_instruction().1
+    }
+
+    // Like step, but also reports on the instruction that ran.
+    pub fn step_instruction(&mut self) -> StepResult {
+        let next = self.next_instruction();
+        let (cycles, frame_complete, interrupt) = self.run_instruction();
+
+        StepResult {
+            cycles,
+            frame_complete,
+            unknown_opcode: next.unknown().then_some(next.opcode),
+            interrupt,
+            operand: self.cpu.operand(),
+        }
+    }
+
+    // Like step_instruction, but also returns a trace record of the
+    // instruction: the state from before it ran, with its operand filled in
+    // from after.
+    pub fn step_traced(&mut self) -> (TraceRecord, StepResult) {
+        let mut record = self.trace_record();
+        let result = self.step_instruction();
+        record.operand = result.operand;
+
+        (record, result)
+    }
+
+    pub fn next_instruction(&self) -> NextInstruction {
+        let addr = self.cpu.current_addr();
+
+        NextInstruction {
+            addr,
+            opcode: self.mmu.peek_u8(addr),
+            runs: self.cpu.pending_interrupt().is_none() && !self.cpu.waiting(),
+        }
+    }
+
+    // The state before the next instruction runs. Its operand isn't known
+    // until it has.
+    pub fn trace_record(&self) -> TraceRecord {
+        TraceRecord::capture(&self.cpu, &self.mmu)
+    }
+
+    // Moves past the operand of the unknown instruction at `addr` too, as the
+    // CPU only moves past the opcode. The length goes by the M and X flags,
+    // which the instruction can't have changed, and it stays within the
+    // program bank.
+    pub fn skip_operand(&mut self, addr: u32) {
+        let len = disasm::disassemble_at(&self.cpu, &self.mmu, addr)
+            .bytes()
+            .len() as u16;
+        let next = (addr & 0xFF_0000) | (addr as u16).wrapping_add(len) as u32;
+
+        self.cpu.set_current_addr(next);
+    }
+
+    fn run_instruction(&mut self) -> (u64, bool, Option<Interrupt>) {
+        if !self.hooks.instruction.is_empty() {
+            self.run_instruction_hooks();
+        }
+
+        // Anything accessed before this point was the debugger or a hook
+        // looking at memory, not the CPU.
+        self.mmu.take_watch_hits();
+        self.mmu.clear_accesses();
+        self.mmu.clear_rom_writes();
+        self.mmu.clear_io_accesses();
+        self.mmu.clear_writes();
+
+        if let Some(events) = &mut self.mmu.events {
+            events.instruction = self.steps;
+        }
+
+        // Steps that take an interrupt or wait in WAI don't run an
+        // instruction.
+        let interrupt = self.cpu.pending_interrupt();
+        let runs = interrupt.is_none() && !self.cpu.waiting();
+
+        match interrupt {
+            Some(Interrupt::Nmi) => self.nmis += 1,
+            // Nothing on the board drives the IRQ line yet, so the event is
+            // logged when an IRQ is taken rather than when it's raised.
+            Some(Interrupt::Irq) => {
+                self.irqs += 1;
+                self.mmu.push_event(EventKind::IrqAsserted);
+            }
+            Some(Interrupt::Break) | None => {}
+        }
+
+        let mut cycles = self.cpu.tick(&mut self.mmu);
+
+        // DMA starts straight after the write to MDMAEN, and the CPU is
+        // paused until it finishes, so the rest of the system has to be run
+        // for that long too.
+        let (dma_cycles, dma_bytes) = self.mmu.run_dma();
+        self.cpu.stall(dma_cycles);
+        self.dma_bytes += dma_bytes;
+        cycles += dma_cycles;
+
+        let (frame_complete, hdma_cycles) = self.mmu.step_ppu(cycles);
+        self.cpu.stall(hdma_cycles);
+        cycles += hdma_cycles;
+
+        self.steps += 1;
+        self.instructions += runs as u64;
+        self.cycles += cycles;
+
+        // A frame is complete at the start of vblank.
+        if frame_complete {
+            self.mmu.start_vblank();
+            self.mmu.apply_ram_cheats();
+
+            if let Some(battery) = &mut self.battery {
+                if let Err(e) = battery.frame(&mut self.mmu) {
+                    self.battery_error = Some(e);
+                }
+            }
+        } else if !self.mmu.ppu.in_vblank() {
+            self.mmu.end_vblank();
+        }
+
+        if !self.hooks.write.is_empty() {
+            self.run_write_hooks();
+        }
+
+        if frame_complete && !self.hooks.frame.is_empty() {
+            self.run_frame_hooks();
+        }
+
+        if self.mmu.take_nmi_edge() {
+            self.cpu.raise_nmi();
+            self.mmu.push_event(EventKind::NmiAsserted);
+        }
+
+        self.apu_debt += self.apu_clock.advance(cycles) as i64;
+
+        while self.apu_debt > 0 {
+            if let Some(trace) = &mut self.spc_trace {
+                let _ = writeln!(trace, "{}", self.mmu.spc.trace_line());
+            }
+
+            self.apu_debt -= self.mmu.spc.tick() as i64;
+        }
+
+        (cycles, frame_complete, interrupt)
+    }
+
+    // Called before each instruction runs, with the address it's at. The
+    // hook can change the machine state, e.g. to poke memory, and the
+    // instruction will see the change.
+    pub fn on_instruction(&mut self, hook: impl FnMut(&mut Emulator, &CpuState, u32) + 'static) {
+        self.hooks.instruction.push(Box::new(hook));
+    }
+
+    // Called once a frame has finished drawing, at the start of vblank.
+    pub fn on_frame(&mut self, hook: impl FnMut(&mut Emulator) + 'static) {
+        self.hooks.frame.push(Box::new(hook));
+    }
+
+    // Called after each instruction for every byte it wrote, including by
+    // DMA, with the address as the CPU saw it.
+    pub fn on_memory_write(&mut self, hook: impl FnMut(&mut Emulator, u32, u8) + 'static) {
+        self.hooks.write.push(Box::new(hook));
+        self.mmu.log_writes = true;
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks = Hooks::default();
+        self.mmu.log_writes = false;
+    }
+
+    // The hooks are taken out while they run, so that they can be given the
+    // emulator. Any that they register in the meantime are kept.
+    fn run_instruction_hooks(&mut self) {
+        let mut hooks = std::mem::take(&mut self.hooks.instruction);
+        let state = self.cpu.state();
+        let addr = self.cpu.current_addr();
+
+        for hook in &mut hooks {
+            hook(self, &state, addr);
+        }
+
+        hooks.append(&mut self.hooks.instruction);
+        self.hooks.instruction = hooks;
+    }
+
+    fn run_frame_hooks(&mut self) {
+        let mut hooks = std::mem::take(&mut self.hooks.frame);
+
+        for hook in &mut hooks {
+            hook(self);
+        }
+
+        hooks.append(&mut self.hooks.frame);
+        self.hooks.frame = hooks;
+    }
+
+    fn run_write_hooks(&mut self) {
+        let mut hooks = std::mem::take(&mut self.hooks.write);
+        let writes = self.mmu.writes().to_vec();
+
+        for &(addr, value) in &writes {
+            for hook in &mut hooks {
+                hook(self, addr, value);
+            }
+        }
+
+        hooks.append(&mut self.hooks.write);
+        self.hooks.write = hooks;
+    }
+
+    // Runs until the PPU finishes a frame, returning the number of
+    // instructions that took. Unknown opcodes are skipped over, the same as
+    // the CPU does on its own.
+    pub fn run_frame(&mut self) -> u64 {
+        let start = self.instructions;
+
+        while !self.step_instruction().frame_complete {}
+
+        self.instructions - start
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.mmu.ppu.frame()
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        self.mmu.ppu.framebuffer()
+    }
+
+    // The buttons held on pad 1.
+    pub fn input(&self) -> Buttons {
+        self.mmu.controllers.pads[0]
+    }
+
+    pub fn set_input(&mut self, buttons: Buttons) {
+        self.mmu.controllers.pads[0] = buttons;
+    }
+
+    // How many times the CPU has been stepped. This is what --run-for and
+    // the trace count, so interrupts taken and WAI steps count too, as that
+    // keeps it going up by one with every step.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // How many instructions have actually run, leaving out the steps that
+    // took an interrupt or waited in WAI.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            steps: self.steps,
+            instructions: self.instructions,
+            cycles: self.cycles,
+            frames: self.frame(),
+            nmis: self.nmis,
+            irqs: self.irqs,
+            dma_bytes: self.dma_bytes,
+        }
+    }
+
+    // Copies the machine state, so that it can be restored later. The SPC
+    // trace and any debugging setup aren't carried over.
+    pub fn snapshot(&self) -> Emulator {
+        Emulator {
+            cpu: self.cpu.clone(),
+            mmu: self.mmu.snapshot(),
+
+            spc_trace: None,
+            battery: None,
+            battery_error: None,
+            hooks: Hooks::default(),
+
+            apu_clock: self.apu_clock.clone(),
+            apu_debt: self.apu_debt,
+
+            steps: self.steps,
+            instructions: self.instructions,
+            cycles: self.cycles,
+
+            nmis: self.nmis,
+            irqs: self.irqs,
+            dma_bytes: self.dma_bytes,
+        }
+    }
+
+    // Writes the battery save now if SRAM has changed, rather than waiting
+    // for the next frame.
+    pub fn flush_battery(&mut self) -> io::Result<()> {
+        match &mut self.battery {
+            Some(battery) => battery.flush(&mut self.mmu),
+            None => Ok(()),
+        }
+    }
+
+    // Why the battery save couldn't be written at the end of a frame, if it
+    // couldn't. Frames carry on regardless, and it's tried again after the
+    // interval.
+    pub fn take_battery_error(&mut self) -> Option<io::Error> {
+        self.battery_error.take()
+    }
+
+    // Takes on the machine state of another emulator, keeping this one's
+    // cartridge and debugging setup.
+    pub fn restore(&mut self, mut state: Emulator) {
+        std::mem::swap(&mut self.cpu, &mut state.cpu);
+        self.mmu.restore(&mut state.mmu);
+
+        self.apu_clock = state.apu_clock.clone();
+        self.apu_debt = state.apu_debt;
+
+        self.steps = state.steps;
+        self.instructions = state.instructions;
+        self.cycles = state.cycles;
+
+        self.nmis = state.nmis;
+        self.irqs = state.irqs;
+        self.dma_bytes = state.dma_bytes;
+    }
+}
+
+impl Drop for Emulator {
+    // There's no one left to report an error to here, so anything that
+    // cares should flush before dropping.
+    fn drop(&mut self) {
+        let _ = self.flush_battery();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::{Access, Watchpoint};
+
+    #[test]
+    fn apu_clock_stays_within_one_cycle() {
+        let mut clock = ClockRatio::new(APU_CLOCK_NUMERATOR, APU_CLOCK_DENOMINATOR);
+
+        let mut master = 0;
+        let mut apu = 0;
+
+        // A mix of the access speeds an instruction stream produces.
+        for &cycles in [6, 8, 12, 6, 6, 8, 14, 40, 6, 12]
+            .iter()
+            .cycle()
+            .take(100_000)
+        {
+            master += cycles;
+            apu += clock.advance(cycles);
+
+            let ideal = master as f64 * APU_CLOCK_NUMERATOR as f64 / APU_CLOCK_DENOMINATOR as f64;
+            assert!(
+                (ideal - apu as f64).abs() < 1.0,
+                "{apu} vs {ideal} after {master}"
+            );
+        }
+    }
+
+    #[test]
+    fn remainder_carries_over() {
+        let mut clock = ClockRatio::new(1, 3);
+
+        let apu: Vec<u64> = (0..6).map(|_| clock.advance(1)).collect();
+        assert_eq!(apu, [0, 0, 1, 0, 0, 1]);
+
+        assert_eq!(clock.advance(7), 2);
+        assert_eq!(clock.advance(2), 1);
+    }
+
+    #[test]
+    fn apu_cycles_per_frame() {
+        let mut clock = ClockRatio::new(APU_CLOCK_NUMERATOR, APU_CLOCK_DENOMINATOR);
+
+        // 262 lines of 1364 master cycles, at about 1.024MHz.
+        assert_eq!(clock.advance(262 * 1364), 17038);
+    }
+
+    // A LoROM with the code at $8000, where it starts running.
+    fn emulator(code: &[u8]) -> Emulator {
+        let mut rom = vec![0; 0x8000];
+        rom[..code.len()].copy_from_slice(code);
+        rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+        Emulator::new(rom, Some(MapMode::LoRom)).unwrap()
+    }
+
+    #[test]
+    fn small_roms_are_rejected() {
+        for len in [0, 0x200, 0x7FFF] {
+            assert!(Emulator::new(vec![0; len], None).is_err());
+        }
+
+        // The copier header doesn't count towards the size.
+        assert!(Emulator::new(vec![0; 0x8200], None).is_ok());
+    }
+
+    #[test]
+    fn watch_hits_belong_to_the_accessing_instruction() {
+        #[rustfmt::skip]
+        let mut emulator = emulator(&[
+            0x18,             // CLC
+            0xFB,             // XCE
+            0xE2, 0x20,       // SEP #$20
+            0xAD, 0x00, 0x01, // LDA $0100
+            0x8D, 0x01, 0x01, // STA $0101
+            0xEA,             // NOP
+            0x8D, 0x00, 0x01, // STA $0100
+            0x80, 0xFE,       // BRA *
+        ]);
+
+        // The ranges overlap at $0100, which is reported once per access.
+        emulator.mmu.watchpoints = vec![
+            Watchpoint {
+                start: 0x7E_0100,
+                end: 0x7E_0100,
+                read: true,
+                write: false,
+            },
+            Watchpoint {
+                start: 0x7E_0100,
+                end: 0x7E_0101,
+                read: false,
+                write: true,
+            },
+        ];
+
+        let mut hits = Vec::new();
+
+        for _ in 0..8 {
+            let pc = emulator.cpu.current_addr();
+            emulator.step_instruction();
+
+            for hit in emulator.mmu.take_watch_hits() {
+                hits.push((pc, hit.access, hit.addr));
+            }
+        }
+
+        // Bank 0 reaches WRAM through the low RAM mirror.
+        assert_eq!(
+            hits,
+            [
+                (0x8004, Access::Read, 0x7E_0100),
+                (0x8007, Access::Write, 0x7E_0101),
+                (0x800B, Access::Write, 0x7E_0100),
+            ]
+        );
+    }
+
+    #[test]
+    fn peeking_doesnt_trip_watchpoints() {
+        let mut emulator = emulator(&[0xEA, 0x80, 0xFE]);
+        emulator.mmu.watchpoints = vec![Watchpoint {
+            start: 0x7E_0000,
+            end: 0x7E_FFFF,
+            read: true,
+            write: true,
+        }];
+
+        emulator.mmu.peek_bytes(0x7E_0000, 0x100);
+        emulator.step_instruction();
+
+        assert!(emulator.mmu.take_watch_hits().is_empty());
+    }
+}
@@ -0,0 +1,342 @@
+use std::fmt::{self, Write};
+
+// Something the hardware did, as opposed to something the CPU ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    NmiAsserted,
+    IrqAsserted,
+
+    // One channel of a general purpose DMA. `b_addr` is the low byte of the
+    // B bus register it started at.
+    DmaTransfer { channel: u8, bytes: u32, b_addr: u8 },
+
+    // The HDMA channels rewinding to the start of their tables, at the
+    // start of the frame.
+    HdmaInit { channels: u8 },
+
+    AutoJoypadRead,
+    ApuPortWrite { port: u8, value: u8 },
+
+    // A write to VMDATAL/VMDATAH outside of vblank and forced blank, which
+    // the PPU drops. `vram_addr` is VMADD at the time.
+    VramWriteIgnored { vram_addr: u16 },
+}
+
+impl EventKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::NmiAsserted => "nmi",
+            EventKind::IrqAsserted => "irq",
+            EventKind::DmaTransfer { .. } => "dma",
+            EventKind::HdmaInit { .. } => "hdma_init",
+            EventKind::AutoJoypadRead => "auto_joypad_read",
+            EventKind::ApuPortWrite { .. } => "apu_port_write",
+            EventKind::VramWriteIgnored { .. } => "vram_write_ignored",
+        }
+    }
+}
+
+// e.g. `DMA ch2 512 bytes to 2118`
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EventKind::NmiAsserted => f.write_str("NMI"),
+            EventKind::IrqAsserted => f.write_str("IRQ"),
+            EventKind::DmaTransfer {
+                channel,
+                bytes,
+                b_addr,
+            } => write!(f, "DMA ch{} {} bytes to 21{:02X}", channel, bytes, b_addr),
+            EventKind::HdmaInit { channels } => write!(f, "HDMA init {:08b}", channels),
+            EventKind::AutoJoypadRead => f.write_str("auto joypad read"),
+            EventKind::ApuPortWrite { port, value } => {
+                write!(f, "APUIO{} = {:02X}", port, value)
+            }
+            EventKind::VramWriteIgnored { vram_addr } => {
+                write!(
+                    f,
+                    "VRAM write to {:04X} ignored outside blanking",
+                    vram_addr
+                )
+            }
+        }
+    }
+}
+
+// An event, along with when it happened. `instruction` is how many
+// instructions the CPU had run when it started the one that was going on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16,
+    pub instruction: u64,
+    pub kind: EventKind,
+}
+
+impl Event {
+    // e.g. `frame 12 line 225 dot  10 inst 40213 NMI`
+    pub fn format(&self, output: &mut String) {
+        let _ = write!(
+            output,
+            "frame {} line {:3} dot {:3} inst {} {}",
+            self.frame, self.scanline, self.dot, self.instruction, self.kind
+        );
+    }
+
+    pub fn format_json(&self, output: &mut String) {
+        let _ = write!(
+            output,
+            "{{\"frame\":{},\"scanline\":{},\"dot\":{},\"instruction\":{},\"event\":\"{}\"",
+            self.frame,
+            self.scanline,
+            self.dot,
+            self.instruction,
+            self.kind.name()
+        );
+
+        let _ = match self.kind {
+            EventKind::DmaTransfer {
+                channel,
+                bytes,
+                b_addr,
+            } => write!(
+                output,
+                ",\"channel\":{},\"bytes\":{},\"b_addr\":{}",
+                channel, bytes, b_addr
+            ),
+            EventKind::HdmaInit { channels } => write!(output, ",\"channels\":{}", channels),
+            EventKind::ApuPortWrite { port, value } => {
+                write!(output, ",\"port\":{},\"value\":{}", port, value)
+            }
+            EventKind::VramWriteIgnored { vram_addr } => {
+                write!(output, ",\"vram_addr\":{}", vram_addr)
+            }
+            EventKind::NmiAsserted | EventKind::IrqAsserted | EventKind::AutoJoypadRead => Ok(()),
+        };
+
+        output.push('}');
+    }
+}
+
+// The last few events. The space is all allocated up front, so that pushing
+// an event never allocates, and the oldest one is overwritten once it's
+// full.
+pub struct EventLog {
+    events: Vec<Event>,
+    capacity: usize,
+
+    // Where the next event goes, once the log is full.
+    next: usize,
+
+    // The instruction that's running, for stamping events with. The
+    // emulator keeps this up to date.
+    pub instruction: u64,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> EventLog {
+        let capacity = capacity.max(1);
+
+        EventLog {
+            events: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+            instruction: 0,
+        }
+    }
+
+    pub fn push(&mut self, frame: u64, scanline: u16, dot: u16, kind: EventKind) {
+        let event = Event {
+            frame,
+            scanline,
+            dot,
+            instruction: self.instruction,
+            kind,
+        };
+
+        if self.events.len() < self.capacity {
+            self.events.push(event);
+        } else {
+            self.events[self.next] = event;
+        }
+
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // Every event that's kept, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        let split = if self.events.len() < self.capacity {
+            0
+        } else {
+            self.next
+        };
+
+        self.events[split..].iter().chain(&self.events[..split])
+    }
+
+    // The last n events, oldest first.
+    pub fn recent(&self, n: usize) -> impl Iterator<Item = &Event> {
+        self.iter().skip(self.len().saturating_sub(n))
+    }
+
+    // One event per line, or with `json`, one JSON object per line.
+    pub fn dump(&self, json: bool) -> String {
+        let mut output = String::new();
+
+        for event in self.iter() {
+            if json {
+                event.format_json(&mut output);
+            } else {
+                event.format(&mut output);
+            }
+
+            output.push('\n');
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nmi(log: &mut EventLog, frame: u64) {
+        log.push(frame, 225, 10, EventKind::NmiAsserted);
+    }
+
+    #[test]
+    fn keeps_the_last_events() {
+        let mut log = EventLog::new(3);
+        assert!(log.is_empty());
+
+        let buffer = log.events.as_ptr();
+
+        for frame in 0..5 {
+            log.instruction = frame * 100;
+            nmi(&mut log, frame);
+        }
+
+        // The ring was allocated up front, and never grew.
+        assert_eq!(log.events.as_ptr(), buffer);
+        assert_eq!(log.events.capacity(), 3);
+
+        let frames: Vec<_> = log.iter().map(|event| event.frame).collect();
+        assert_eq!(frames, [2, 3, 4]);
+
+        let frames: Vec<_> = log.recent(2).map(|event| event.frame).collect();
+        assert_eq!(frames, [3, 4]);
+        assert_eq!(log.recent(10).count(), 3);
+
+        let instructions: Vec<_> = log.iter().map(|event| event.instruction).collect();
+        assert_eq!(instructions, [200, 300, 400]);
+    }
+
+    #[test]
+    fn zero_capacity_keeps_one() {
+        let mut log = EventLog::new(0);
+
+        nmi(&mut log, 1);
+        nmi(&mut log, 2);
+
+        assert_eq!(log.len(), 1);
+        assert_eq!(log.iter().next().unwrap().frame, 2);
+    }
+
+    fn every_kind() -> EventLog {
+        let mut log = EventLog::new(8);
+        log.instruction = 40213;
+
+        #[rustfmt::skip]
+        let kinds = [
+            EventKind::NmiAsserted,
+            EventKind::IrqAsserted,
+            EventKind::DmaTransfer { channel: 2, bytes: 512, b_addr: 0x18 },
+            EventKind::HdmaInit { channels: 0b1010 },
+            EventKind::AutoJoypadRead,
+            EventKind::ApuPortWrite { port: 1, value: 0xCC },
+            EventKind::VramWriteIgnored { vram_addr: 0x1234 },
+        ];
+
+        for kind in kinds {
+            log.push(12, 225, 10, kind);
+        }
+
+        log
+    }
+
+    #[test]
+    fn text() {
+        let dump = every_kind().dump(false);
+        let lines: Vec<_> = dump.lines().collect();
+
+        assert_eq!(
+            lines,
+            [
+                "frame 12 line 225 dot  10 inst 40213 NMI",
+                "frame 12 line 225 dot  10 inst 40213 IRQ",
+                "frame 12 line 225 dot  10 inst 40213 DMA ch2 512 bytes to 2118",
+                "frame 12 line 225 dot  10 inst 40213 HDMA init 00001010",
+                "frame 12 line 225 dot  10 inst 40213 auto joypad read",
+                "frame 12 line 225 dot  10 inst 40213 APUIO1 = CC",
+                "frame 12 line 225 dot  10 inst 40213 VRAM write to 1234 ignored outside blanking",
+            ]
+        );
+    }
+
+    #[test]
+    fn json() {
+        let dump = every_kind().dump(true);
+        let events: Vec<serde_json::Value> = dump
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let names: Vec<_> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+
+        assert_eq!(
+            names,
+            [
+                "nmi",
+                "irq",
+                "dma",
+                "hdma_init",
+                "auto_joypad_read",
+                "apu_port_write",
+                "vram_write_ignored"
+            ]
+        );
+
+        for event in &events {
+            assert_eq!(event["frame"], 12);
+            assert_eq!(event["scanline"], 225);
+            assert_eq!(event["dot"], 10);
+            assert_eq!(event["instruction"], 40213);
+        }
+
+        assert_eq!(events[0].as_object().unwrap().len(), 5);
+        assert_eq!(
+            events[2],
+            serde_json::json!({
+                "frame": 12, "scanline": 225, "dot": 10, "instruction": 40213,
+                "event": "dma", "channel": 2, "bytes": 512, "b_addr": 0x18,
+            })
+        );
+        assert_eq!(events[3]["channels"], 0b1010);
+        assert_eq!(events[5]["port"], 1);
+        assert_eq!(events[5]["value"], 0xCC);
+        assert_eq!(events[6]["vram_addr"], 0x1234);
+    }
+}
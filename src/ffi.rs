@@ -0,0 +1,219 @@
+// A C interface to the core, for embedding it in frontends that aren't
+// written in Rust. The header in include/snesemu.h is generated from this
+// file with cbindgen.
+//
+// Every function catches panics before they reach the caller, and reports
+// them as SNES_ERROR_PANIC. An emulator that has panicked may be left in an
+// inconsistent state, so the caller should destroy it.
+//
+// Null pointers are checked for, but otherwise the caller has to keep to the
+// usual rules: emulator pointers have to have come from snes_create and not
+// have been destroyed, and buffers have to be as long as the length passed
+// with them.
+#![allow(clippy::missing_safety_doc)]
+
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use crate::emulator::Emulator;
+use crate::input::Buttons;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::savestate;
+
+pub const SNES_OK: i32 = 0;
+pub const SNES_ERROR_NULL: i32 = -1;
+pub const SNES_ERROR_PANIC: i32 = -2;
+pub const SNES_ERROR_INVALID_ARGUMENT: i32 = -3;
+pub const SNES_ERROR_BUFFER_TOO_SMALL: i32 = -4;
+pub const SNES_ERROR_INVALID_STATE: i32 = -5;
+
+// An emulator, as far as C is concerned. It's only ever handled through a
+// pointer.
+pub struct SnesEmulator {
+    emulator: Emulator,
+
+    // Save states are serialized into this before being copied out, and
+    // it's reused to save allocating.
+    state: Vec<u8>,
+}
+
+// Runs f, turning a panic into an error code.
+fn guard(f: impl FnOnce() -> i32) -> i32 {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(SNES_ERROR_PANIC)
+}
+
+// Creates an emulator with a copy of the ROM, working out its mapping from
+// the header. Returns null if the ROM is too small to run or couldn't be
+// loaded.
+#[no_mangle]
+pub unsafe extern "C" fn snes_create(rom: *const u8, len: usize) -> *mut SnesEmulator {
+    if rom.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let rom = slice::from_raw_parts(rom, len).to_vec();
+
+    panic::catch_unwind(|| match Emulator::new(rom, None) {
+        Ok(emulator) => Box::into_raw(Box::new(SnesEmulator {
+            emulator,
+            state: Vec::new(),
+        })),
+        Err(_) => std::ptr::null_mut(),
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+// The emulator can't be used after this.
+#[no_mangle]
+pub unsafe extern "C" fn snes_destroy(snes: *mut SnesEmulator) {
+    if !snes.is_null() {
+        drop(Box::from_raw(snes));
+    }
+}
+
+// Runs until the PPU finishes a frame.
+#[no_mangle]
+pub unsafe extern "C" fn snes_run_frame(snes: *mut SnesEmulator) -> i32 {
+    let Some(snes) = snes.as_mut() else {
+        return SNES_ERROR_NULL;
+    };
+
+    guard(|| {
+        snes.emulator.run_frame();
+        SNES_OK
+    })
+}
+
+// The last frame, as width * height pixels of RGBA with 8 bits per
+// channel. The pointer stays valid until the next call that runs the
+// emulator or destroys it. Either size pointer can be null.
+#[no_mangle]
+pub unsafe extern "C" fn snes_framebuffer(
+    snes: *const SnesEmulator,
+    width: *mut u32,
+    height: *mut u32,
+) -> *const u8 {
+    let Some(snes) = snes.as_ref() else {
+        return std::ptr::null();
+    };
+
+    if let Some(width) = width.as_mut() {
+        *width = SCREEN_WIDTH as u32;
+    }
+
+    if let Some(height) = height.as_mut() {
+        *height = SCREEN_HEIGHT as u32;
+    }
+
+    snes.emulator.framebuffer().as_ptr()
+}
+
+// Sets the buttons held on a pad, numbered from 0, using the same bits as
+// the auto-read registers: B Y Select Start Up Down Left Right from the top
+// down, then A X L R. Pads 1 to 4 are only read when a multitap is plugged
+// into the second port.
+#[no_mangle]
+pub unsafe extern "C" fn snes_set_input(snes: *mut SnesEmulator, pad: u32, buttons: u16) -> i32 {
+    let Some(snes) = snes.as_mut() else {
+        return SNES_ERROR_NULL;
+    };
+
+    match snes.emulator.mmu.controllers.pads.get_mut(pad as usize) {
+        Some(pad) => {
+            *pad = Buttons::from_bits_truncate(buttons);
+            SNES_OK
+        }
+        None => SNES_ERROR_INVALID_ARGUMENT,
+    }
+}
+
+// Writes a save state into buf. The size of the state is always written to
+// `written`, so passing a null buffer with a length of zero finds out how
+// much space is needed, and SNES_ERROR_BUFFER_TOO_SMALL is returned if
+// there isn't enough.
+#[no_mangle]
+pub unsafe extern "C" fn snes_save_state(
+    snes: *mut SnesEmulator,
+    buf: *mut u8,
+    len: usize,
+    written: *mut usize,
+) -> i32 {
+    let Some(snes) = snes.as_mut() else {
+        return SNES_ERROR_NULL;
+    };
+
+    guard(|| {
+        snes.state.clear();
+
+        if savestate::save_to(&snes.emulator, &mut snes.state).is_err() {
+            return SNES_ERROR_INVALID_STATE;
+        }
+
+        if let Some(written) = written.as_mut() {
+            *written = snes.state.len();
+        }
+
+        if buf.is_null() || len < snes.state.len() {
+            return SNES_ERROR_BUFFER_TOO_SMALL;
+        }
+
+        slice::from_raw_parts_mut(buf, snes.state.len()).copy_from_slice(&snes.state);
+        SNES_OK
+    })
+}
+
+// Restores a save state taken with the same ROM. The emulator is left as it
+// was if the state can't be loaded.
+#[no_mangle]
+pub unsafe extern "C" fn snes_load_state(
+    snes: *mut SnesEmulator,
+    buf: *const u8,
+    len: usize,
+) -> i32 {
+    let Some(snes) = snes.as_mut() else {
+        return SNES_ERROR_NULL;
+    };
+
+    if buf.is_null() {
+        return SNES_ERROR_NULL;
+    }
+
+    let state = slice::from_raw_parts(buf, len);
+
+    guard(|| match savestate::load_from(&mut snes.emulator, state) {
+        Ok(()) => SNES_OK,
+        Err(_) => SNES_ERROR_INVALID_STATE,
+    })
+}
+
+// Reads a byte from the CPU's address space, without any of the side
+// effects a read from the CPU would have.
+#[no_mangle]
+pub unsafe extern "C" fn snes_read_memory(
+    snes: *const SnesEmulator,
+    addr: u32,
+    value: *mut u8,
+) -> i32 {
+    let (Some(snes), Some(value)) = (snes.as_ref(), value.as_mut()) else {
+        return SNES_ERROR_NULL;
+    };
+
+    guard(|| {
+        *value = snes.emulator.mmu.peek_u8(addr & 0xFF_FFFF);
+        SNES_OK
+    })
+}
+
+// Writes a byte to the CPU's address space, the same as the CPU would, for
+// things like cheats.
+#[no_mangle]
+pub unsafe extern "C" fn snes_write_memory(snes: *mut SnesEmulator, addr: u32, value: u8) -> i32 {
+    let Some(snes) = snes.as_mut() else {
+        return SNES_ERROR_NULL;
+    };
+
+    guard(|| {
+        snes.emulator.mmu.store_u8(addr & 0xFF_FFFF, value);
+        SNES_OK
+    })
+}
@@ -0,0 +1,37 @@
+#[cfg(any(feature = "audio", test))]
+pub mod audio;
+pub mod batch;
+pub mod battery;
+pub mod compare;
+pub mod determinism;
+pub mod frame_dump;
+pub mod frame_hash;
+pub mod idle;
+pub mod io_log;
+pub mod modes;
+pub mod movie;
+pub mod opcode_coverage;
+pub mod options;
+pub mod pacing;
+pub mod palette;
+pub mod profiler;
+pub mod rewind;
+pub mod rom_info;
+pub mod savestate;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod session;
+pub mod stack_guard;
+pub mod summary;
+pub mod trace;
+pub mod trace_filter;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod write_log;
+
+#[cfg(feature = "window")]
+pub mod window;
+
+// Save states use the same checksum to tell ROMs apart, so it lives in the
+// core.
+pub use crate::savestate::checksum;
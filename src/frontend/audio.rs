@@ -0,0 +1,365 @@
+// The buffering and resampling don't need an output device, so they're
+// built for the tests even without the audio feature.
+
+use std::collections::VecDeque;
+#[cfg(feature = "audio")]
+use std::error::Error;
+#[cfg(feature = "audio")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "audio")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "audio")]
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+
+// The DSP's native output rate.
+pub const SAMPLE_RATE: u32 = 32000;
+
+// About 100ms of audio - enough to ride out a slow frame without adding
+// noticeable latency.
+#[cfg(feature = "audio")]
+const BUFFER_CAPACITY: usize = SAMPLE_RATE as usize / 10;
+
+#[derive(Clone, Copy)]
+pub struct BufferStats {
+    pub queued: usize,
+    pub underruns: u64,
+    pub overruns: u64,
+}
+
+pub struct RingBuffer {
+    frames: VecDeque<[i16; 2]>,
+    capacity: usize,
+
+    underruns: u64,
+    overruns: u64,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> RingBuffer {
+        RingBuffer {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+
+            underruns: 0,
+            overruns: 0,
+        }
+    }
+
+    // Takes interleaved stereo samples. If the buffer is full, the new
+    // samples are dropped.
+    pub fn push(&mut self, samples: &[i16]) {
+        for frame in samples.chunks_exact(2) {
+            if self.frames.len() == self.capacity {
+                self.overruns += 1;
+                continue;
+            }
+
+            self.frames.push_back([frame[0], frame[1]]);
+        }
+    }
+
+    // If the buffer is empty, silence is returned instead.
+    pub fn pop(&mut self) -> [i16; 2] {
+        match self.frames.pop_front() {
+            Some(frame) => frame,
+            None => {
+                self.underruns += 1;
+                [0, 0]
+            }
+        }
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        BufferStats {
+            queued: self.frames.len(),
+            underruns: self.underruns,
+            overruns: self.overruns,
+        }
+    }
+}
+
+// Linearly interpolates between frames to convert from the DSP's rate to
+// the output device's.
+pub struct Resampler {
+    step: f64,
+    position: f64,
+    prev: [i16; 2],
+    next: [i16; 2],
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Resampler {
+        Resampler {
+            step: from_rate as f64 / to_rate as f64,
+            position: 0.0,
+            prev: [0; 2],
+            next: [0; 2],
+        }
+    }
+
+    pub fn next(&mut self, buffer: &mut RingBuffer) -> [f32; 2] {
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            self.prev = self.next;
+            self.next = buffer.pop();
+        }
+
+        let t = self.position as f32;
+        self.position += self.step;
+
+        [0, 1].map(|c| {
+            let sample = self.prev[c] as f32 + (self.next[c] as f32 - self.prev[c] as f32) * t;
+            sample / 32768.0
+        })
+    }
+}
+
+#[cfg(feature = "audio")]
+pub struct Audio {
+    // The stream stops playing when dropped.
+    _stream: Stream,
+    buffer: Arc<Mutex<RingBuffer>>,
+}
+
+#[cfg(feature = "audio")]
+impl Audio {
+    pub fn new() -> Result<Audio, Box<dyn Error>> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or("no audio output device available")?;
+
+        let supported = device.default_output_config()?;
+        let format = supported.sample_format();
+        let config: StreamConfig = supported.into();
+
+        let buffer = Arc::new(Mutex::new(RingBuffer::new(BUFFER_CAPACITY)));
+
+        let stream = match format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, buffer.clone())?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, buffer.clone())?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, buffer.clone())?,
+            _ => return Err(format!("unsupported sample format: {}", format).into()),
+        };
+
+        stream.play()?;
+
+        Ok(Audio {
+            _stream: stream,
+            buffer,
+        })
+    }
+
+    pub fn push(&self, samples: &[i16]) {
+        self.buffer.lock().unwrap().push(samples);
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        self.buffer.lock().unwrap().stats()
+    }
+}
+
+#[cfg(feature = "audio")]
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    buffer: Arc<Mutex<RingBuffer>>,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut resampler = Resampler::new(SAMPLE_RATE, config.sample_rate.0);
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let mut buffer = buffer.lock().unwrap();
+
+            for frame in data.chunks_mut(channels) {
+                let [left, right] = resampler.next(&mut buffer);
+
+                if let [sample] = frame {
+                    *sample = T::from_sample((left + right) / 2.0);
+                    continue;
+                }
+
+                // Any channels beyond the first two are left silent.
+                for (i, sample) in frame.iter_mut().enumerate() {
+                    *sample = T::from_sample(match i {
+                        0 => left,
+                        1 => right,
+                        _ => 0.0,
+                    });
+                }
+            }
+        },
+        |err| eprintln!("audio stream error: {}", err),
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spc::Spc700;
+
+    #[test]
+    fn underrun_plays_silence() {
+        let mut buffer = RingBuffer::new(4);
+        buffer.push(&[1, 2]);
+
+        assert_eq!(buffer.pop(), [1, 2]);
+        assert_eq!(buffer.pop(), [0, 0]);
+        assert_eq!(buffer.pop(), [0, 0]);
+
+        let stats = buffer.stats();
+        assert_eq!((stats.queued, stats.underruns, stats.overruns), (0, 2, 0));
+    }
+
+    // Once the buffer is full, the newest frames are the ones dropped.
+    #[test]
+    fn overrun_drops_new_frames() {
+        let mut buffer = RingBuffer::new(2);
+        buffer.push(&[1, 1, 2, 2, 3, 3, 4, 4]);
+
+        let stats = buffer.stats();
+        assert_eq!((stats.queued, stats.underruns, stats.overruns), (2, 0, 2));
+
+        assert_eq!(buffer.pop(), [1, 1]);
+        assert_eq!(buffer.pop(), [2, 2]);
+        assert_eq!(buffer.stats().underruns, 0);
+    }
+
+    // A ramp upsampled to 48kHz comes out as the same ramp, two thirds as
+    // steep. It starts two frames behind, as the resampler starts out
+    // between two frames of silence.
+    #[test]
+    fn resampler_ratio() {
+        let ramp: Vec<i16> = (0..320).flat_map(|i| [i * 100, -i * 100]).collect();
+
+        let mut buffer = RingBuffer::new(320);
+        buffer.push(&ramp);
+
+        let mut resampler = Resampler::new(SAMPLE_RATE, 48000);
+
+        for k in 0..480 {
+            let [left, right] = resampler.next(&mut buffer);
+            let expected = (k as f32 * 2.0 / 3.0 - 2.0).max(0.0) * 100.0;
+
+            assert!((left * 32768.0 - expected).abs() < 0.5, "{}: {}", k, left);
+            assert!((right * 32768.0 + expected).abs() < 0.5, "{}: {}", k, right);
+        }
+
+        let stats = buffer.stats();
+        assert_eq!((stats.queued, stats.underruns), (1, 0));
+    }
+
+    // Plays a looped square wave through the DSP, with the SPC running its
+    // IPL ROM, and checks the mixed output on its way to the device.
+    #[test]
+    fn square_wave_through_the_dsp() {
+        let mut spc = Spc700::new();
+
+        // Two BRR blocks with a shift of 10 and no filter, each holding 16
+        // samples of +4 and then -4, which decode to +/-4096. The second
+        // ends the sample and loops back to the first.
+        let blocks = [[0xA0, 0x44], [0xA3, 0xCC]];
+
+        for (i, [header, nibbles]) in blocks.into_iter().enumerate() {
+            let addr = 0x0200 + i as u16 * 9;
+            spc.store_u8(addr, header);
+
+            for offset in 1..9 {
+                spc.store_u8(addr + offset, nibbles);
+            }
+        }
+
+        // Sample 0 starts and loops at $0200.
+        for (addr, value) in (0x0300..).zip([0x00, 0x02, 0x00, 0x02]) {
+            spc.store_u8(addr, value);
+        }
+
+        let mut dsp = |reg: u8, value: u8| {
+            spc.store_u8(0x00F2, reg);
+            spc.store_u8(0x00F3, value);
+        };
+
+        // DIR, voice 0's volume and pitch (one BRR sample per output
+        // sample), the main volume, then KON.
+        for (reg, value) in [
+            (0x5D, 0x03),
+            (0x00, 0x40),
+            (0x01, 0x40),
+            (0x03, 0x10),
+            (0x0C, 0x7F),
+            (0x1C, 0x7F),
+            (0x4C, 0x01),
+        ] {
+            dsp(reg, value);
+        }
+
+        let mut cycles = 0;
+
+        while cycles < 32 * 320 {
+            cycles += spc.tick();
+        }
+
+        // One stereo frame for every 32 cycles.
+        let samples = spc.take_samples();
+        assert_eq!(samples.len() as u64, cycles / 32 * 2);
+
+        // 4096 at half volume, then at 127/128 of the main volume.
+        let amplitude = 2032;
+
+        for (i, frame) in samples.chunks_exact(2).take(320).enumerate() {
+            let expected = match i / 16 % 2 {
+                0 => amplitude,
+                _ => -amplitude,
+            };
+
+            assert_eq!(frame, [expected, expected], "frame {}", i);
+        }
+
+        // KOF cuts the voice off straight away, as there's no release yet.
+        spc.store_u8(0x00F2, 0x5C);
+        spc.store_u8(0x00F3, 0x01);
+
+        let mut cycles = 0;
+
+        while cycles < 32 * 128 {
+            cycles += spc.tick();
+        }
+
+        let silence = spc.take_samples();
+        assert!(silence.len() >= 256);
+        assert!(silence.iter().all(|&sample| sample == 0));
+
+        // The envelope of what reaches the device: the peak of each 2ms
+        // window of 48kHz output holds steady, then drops to nothing. The
+        // resampler runs two frames behind, so the wave's last frames are
+        // in the sixth window.
+        let mut buffer = RingBuffer::new(SAMPLE_RATE as usize);
+        buffer.push(&samples[..640]);
+        buffer.push(&silence[..256]);
+
+        let mut resampler = Resampler::new(SAMPLE_RATE, 48000);
+        let output: Vec<f32> = (0..672).map(|_| resampler.next(&mut buffer)[0]).collect();
+
+        let peaks: Vec<i16> = output
+            .chunks(96)
+            .map(|window| {
+                let peak = window
+                    .iter()
+                    .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+                (peak * 32768.0).round() as i16
+            })
+            .collect();
+
+        assert_eq!(
+            peaks,
+            [amplitude; 6].into_iter().chain([0]).collect::<Vec<_>>()
+        );
+    }
+}
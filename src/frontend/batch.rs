@@ -0,0 +1,334 @@
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::emulator::Emulator;
+use crate::mmu;
+
+// How many frames in a row have to take an NMI, at the end of a run, for
+// the game to count as sitting in its main loop.
+const STEADY_FRAMES: u64 = 10;
+
+// How many instructions run between checks of the time limit.
+const TIME_CHECK_INTERVAL: u64 = 4096;
+
+// How long each ROM gets to run.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub frames: Option<u64>,
+    pub instructions: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+// How a ROM's run ended, from worst to best.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Unreadable { message: String },
+    Panic { message: String, pc: Option<u32> },
+    UnknownOpcode { opcode: u8, pc: u32 },
+
+    // Waiting in WAI at the end, with no interrupt in sight.
+    Hung { pc: u32 },
+
+    // Ran to the limit without settling into taking an NMI every frame.
+    Limit,
+
+    // Ran to the limit, taking an NMI every frame at the end, which is what
+    // a game sitting in its main loop looks like.
+    Steady,
+}
+
+impl Outcome {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Unreadable { .. } => "unreadable",
+            Outcome::Panic { .. } => "panic",
+            Outcome::UnknownOpcode { .. } => "unknown_opcode",
+            Outcome::Hung { .. } => "hung",
+            Outcome::Limit => "limit",
+            Outcome::Steady => "steady",
+        }
+    }
+
+    pub fn pc(&self) -> Option<u32> {
+        match *self {
+            Outcome::Unreadable { .. } => None,
+            Outcome::Panic { pc, .. } => pc,
+            Outcome::UnknownOpcode { pc, .. } | Outcome::Hung { pc } => Some(pc),
+            Outcome::Limit | Outcome::Steady => None,
+        }
+    }
+
+    pub fn detail(&self) -> String {
+        match self {
+            Outcome::Unreadable { message } | Outcome::Panic { message, .. } => message.clone(),
+            Outcome::UnknownOpcode { opcode, .. } => format!("${:02X}", opcode),
+            Outcome::Hung { .. } | Outcome::Limit | Outcome::Steady => String::new(),
+        }
+    }
+}
+
+pub struct BatchResult {
+    pub path: PathBuf,
+    pub outcome: Outcome,
+    pub frames: u64,
+    pub instructions: u64,
+
+    // The registers the emulator doesn't handle that the ROM touched, with
+    // the mirrors folded into bank 0.
+    pub unmapped: BTreeSet<u16>,
+}
+
+// The .sfc and .smc files in a directory, in order of name. Subdirectories
+// aren't searched.
+pub fn find_roms(dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let is_rom = path.extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("sfc") || extension.eq_ignore_ascii_case("smc")
+        });
+
+        if is_rom && path.is_file() {
+            roms.push(path);
+        }
+    }
+
+    roms.sort();
+    Ok(roms)
+}
+
+// Runs a ROM in a fresh emulator until it hits one of the limits or
+// something goes wrong. A panic is caught and reported as the outcome, so
+// the caller should silence the panic hook to keep the output clean.
+pub fn run_rom(path: &Path, limits: Limits) -> BatchResult {
+    let mut emulator = None;
+    let mut unmapped = BTreeSet::new();
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| -> io::Result<Outcome> {
+        let rom = std::fs::read(path)?;
+        let emulator = emulator.insert(
+            Emulator::new(rom, None).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        );
+
+        emulator.mmu.log_io = true;
+        run(emulator, limits, &mut unmapped)
+    }));
+
+    let pc = emulator
+        .as_ref()
+        .map(|emulator| emulator.cpu.current_addr());
+
+    let outcome = match result {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => Outcome::Unreadable {
+            message: e.to_string(),
+        },
+        Err(payload) => Outcome::Panic {
+            message: panic_message(payload.as_ref()),
+            pc,
+        },
+    };
+
+    BatchResult {
+        path: path.to_path_buf(),
+        outcome,
+        frames: emulator.as_ref().map_or(0, |emulator| emulator.frame()),
+        instructions: emulator
+            .as_ref()
+            .map_or(0, |emulator| emulator.instructions()),
+        unmapped,
+    }
+}
+
+fn run(
+    emulator: &mut Emulator,
+    limits: Limits,
+    unmapped: &mut BTreeSet<u16>,
+) -> io::Result<Outcome> {
+    let start = Instant::now();
+
+    // The frame that the run of frames with NMIs started at.
+    let mut steady_since = 0;
+    let mut nmis = 0;
+
+    loop {
+        let frame = emulator.frame();
+
+        let done = limits.frames.is_some_and(|max| frame >= max)
+            || limits
+                .instructions
+                .is_some_and(|max| emulator.steps() >= max)
+            || limits.time.is_some_and(|max| {
+                emulator.steps().is_multiple_of(TIME_CHECK_INTERVAL) && start.elapsed() >= max
+            });
+
+        if done {
+            let steady = frame >= steady_since + STEADY_FRAMES;
+
+            return Ok(match emulator.cpu.waiting() {
+                true if !steady => Outcome::Hung {
+                    pc: emulator.cpu.current_addr(),
+                },
+                _ if steady => Outcome::Steady,
+                _ => Outcome::Limit,
+            });
+        }
+
+        let pc = emulator.cpu.current_addr();
+        let result = emulator.step_instruction();
+
+        for access in emulator.mmu.io_accesses() {
+            if mmu::unmapped_register(access.access, access.addr as u16) {
+                unmapped.insert(access.addr as u16);
+            }
+        }
+
+        if let Some(opcode) = result.unknown_opcode {
+            return Ok(Outcome::UnknownOpcode { opcode, pc });
+        }
+
+        if result.frame_complete {
+            let stats = emulator.stats();
+
+            if stats.nmis + stats.irqs == nmis {
+                steady_since = emulator.frame();
+            }
+
+            nmis = stats.nmis + stats.irqs;
+        }
+    }
+}
+
+fn panic_message(payload: &dyn Any) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".to_string(),
+        },
+    }
+}
+
+// Puts the worst outcomes first, then goes by path.
+pub fn sort_results(results: &mut [BatchResult]) {
+    results.sort_by(|a, b| {
+        outcome_rank(&a.outcome)
+            .cmp(&outcome_rank(&b.outcome))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+}
+
+fn outcome_rank(outcome: &Outcome) -> u8 {
+    match outcome {
+        Outcome::Unreadable { .. } => 0,
+        Outcome::Panic { .. } => 1,
+        Outcome::UnknownOpcode { .. } => 2,
+        Outcome::Hung { .. } => 3,
+        Outcome::Limit => 4,
+        Outcome::Steady => 5,
+    }
+}
+
+// One line per ROM, after a header. The unmapped registers are separated by
+// spaces within their column.
+pub fn batch_csv(results: &[BatchResult]) -> String {
+    let mut output = String::from("rom,outcome,pc,detail,frames,instructions,unmapped\n");
+
+    for result in results {
+        let registers: Vec<String> = result
+            .unmapped
+            .iter()
+            .map(|register| format!("{:04X}", register))
+            .collect();
+
+        let _ = writeln!(
+            output,
+            "{},{},{},{},{},{},{}",
+            csv_field(&result.path.display().to_string()),
+            result.outcome.name(),
+            result
+                .outcome
+                .pc()
+                .map_or(String::new(), |pc| format!("{:06X}", pc)),
+            csv_field(&result.outcome.detail()),
+            result.frames,
+            result.instructions,
+            registers.join(" ")
+        );
+    }
+
+    output
+}
+
+pub fn batch_json(results: &[BatchResult]) -> String {
+    let mut roms = Vec::new();
+
+    for result in results {
+        let mut rom = format!(
+            "{{\"rom\":{},\"outcome\":\"{}\"",
+            json_string(&result.path.display().to_string()),
+            result.outcome.name()
+        );
+
+        if let Some(pc) = result.outcome.pc() {
+            let _ = write!(rom, ",\"pc\":{}", pc);
+        }
+
+        match &result.outcome {
+            Outcome::Unreadable { message } | Outcome::Panic { message, .. } => {
+                let _ = write!(rom, ",\"message\":{}", json_string(message));
+            }
+            Outcome::UnknownOpcode { opcode, .. } => {
+                let _ = write!(rom, ",\"opcode\":{}", opcode);
+            }
+            _ => {}
+        }
+
+        let registers: Vec<String> = result.unmapped.iter().map(u16::to_string).collect();
+
+        let _ = write!(
+            rom,
+            ",\"frames\":{},\"instructions\":{},\"unmapped\":[{}]}}",
+            result.frames,
+            result.instructions,
+            registers.join(",")
+        );
+
+        roms.push(rom);
+    }
+
+    format!("{{\"roms\":[{}]}}", roms.join(","))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut output = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+
+    output.push('"');
+    output
+}
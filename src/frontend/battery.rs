@@ -0,0 +1,246 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::battery::Battery;
+use crate::mmu::Mmu;
+
+// Keeps a cartridge's battery-backed SRAM in a .srm file.
+//
+// Saves are written while the game runs rather than only at exit, as the
+// times that a save is most likely to be lost are when the emulator is
+// killed or panics. Games often write to SRAM every frame while saving, so
+// the writes are spaced out by at least the interval.
+pub struct BatteryFile {
+    path: PathBuf,
+    interval: Duration,
+    last_flush: Instant,
+}
+
+impl BatteryFile {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> BatteryFile {
+        BatteryFile {
+            path: path.into(),
+            interval,
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Loads the save into SRAM, if there is one yet.
+    pub fn load(&self, mmu: &mut Mmu) -> io::Result<()> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                mmu.load_sram(&data);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Battery for BatteryFile {
+    fn frame(&mut self, mmu: &mut Mmu) -> io::Result<()> {
+        if self.last_flush.elapsed() < self.interval {
+            return Ok(());
+        }
+
+        self.flush(mmu)
+    }
+
+    fn flush(&mut self, mmu: &mut Mmu) -> io::Result<()> {
+        self.last_flush = Instant::now();
+
+        if !mmu.sram_dirty() {
+            return Ok(());
+        }
+
+        write_atomic(&self.path, mmu.sram())?;
+        mmu.clear_sram_dirty();
+
+        Ok(())
+    }
+}
+
+// Writes to a temporary file alongside the real one, then renames it over
+// the top, so that crashing partway through can't leave a truncated save.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    let mut file = File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::emulator::Emulator;
+    use crate::mmu::MapMode;
+
+    // A LoROM cartridge with 2KB of SRAM, which spins at $8000.
+    fn cartridge() -> Vec<u8> {
+        let mut cartridge = vec![0; 0x8000];
+        cartridge[..2].copy_from_slice(&[0x80, 0xFE]);
+        cartridge[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+        cartridge[0x7FD6] = 0x02;
+        cartridge[0x7FD8] = 1;
+
+        cartridge
+    }
+
+    fn mmu() -> Mmu {
+        Mmu::new(cartridge(), Some(MapMode::LoRom))
+    }
+
+    fn save_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "snesemu-battery-{}-{}.srm",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn frames_flush_at_most_once_per_interval() {
+        let path = save_path("interval");
+        let mut mmu = mmu();
+        let mut battery = BatteryFile::new(&path, Duration::from_secs(3600));
+
+        mmu.store_u8(0x70_0000, 0x42);
+        battery.frame(&mut mmu).unwrap();
+        assert!(!path.exists());
+
+        // Once the interval's up, the next frame writes it, at the size of
+        // the chip.
+        battery.last_flush -= Duration::from_secs(3600);
+        battery.frame(&mut mmu).unwrap();
+
+        let save = fs::read(&path).unwrap();
+        assert_eq!((save.len(), save[0]), (0x800, 0x42));
+        assert!(!mmu.sram_dirty());
+
+        // And then the interval starts again.
+        mmu.store_u8(0x70_0000, 0x43);
+        battery.frame(&mut mmu).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[0], 0x42);
+
+        // Unless it's flushed by hand.
+        battery.flush(&mut mmu).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[0], 0x43);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_changed_sram_is_written() {
+        let path = save_path("dirty");
+        let mut mmu = mmu();
+        let mut battery = BatteryFile::new(&path, Duration::ZERO);
+
+        battery.frame(&mut mmu).unwrap();
+        assert!(!path.exists());
+
+        mmu.store_u8(0x70_0001, 0x42);
+        battery.frame(&mut mmu).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Nothing's changed since, so it isn't written again...
+        battery.frame(&mut mmu).unwrap();
+        assert!(!path.exists());
+
+        // ...until there's another write, even of the same value.
+        mmu.store_u8(0x70_0001, 0x42);
+        battery.frame(&mut mmu).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[1], 0x42);
+
+        // Loading it back doesn't count as a change.
+        let mut loaded = self::mmu();
+        battery.load(&mut loaded).unwrap();
+        assert_eq!(loaded.sram()[1], 0x42);
+        assert!(!loaded.sram_dirty());
+
+        fs::remove_file(&path).unwrap();
+
+        // A missing save is just an empty one.
+        battery.load(&mut loaded).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_replaces_the_whole_file() {
+        let path = save_path("atomic");
+        let temp = path.with_extension("srm.tmp");
+
+        // A longer save, and a stale temporary file from a crash.
+        fs::write(&path, [0xFF; 0x2000]).unwrap();
+        fs::write(&temp, b"partial").unwrap();
+
+        write_atomic(&path, &[0x12; 0x800]).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), [0x12; 0x800]);
+        assert!(!temp.exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_write_keeps_the_old_save() {
+        let path = save_path("failed");
+        let temp = path.with_extension("srm.tmp");
+
+        // The temporary file can't be created where there's a directory.
+        fs::write(&path, [0x34; 0x800]).unwrap();
+        fs::create_dir(&temp).unwrap();
+
+        assert!(write_atomic(&path, &[0x56; 0x800]).is_err());
+        assert_eq!(fs::read(&path).unwrap(), [0x34; 0x800]);
+
+        fs::remove_dir(&temp).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    // The emulator doesn't print anything itself, but hands the errors back.
+    #[test]
+    fn errors_are_returned() {
+        let path = save_path("missing").join("save.srm");
+
+        let mut emulator = Emulator::new(cartridge(), Some(MapMode::LoRom)).unwrap();
+        emulator.battery = Some(Box::new(BatteryFile::new(&path, Duration::ZERO)));
+        emulator.mmu.store_u8(0x70_0000, 0x42);
+
+        emulator.run_frame();
+        assert!(emulator.take_battery_error().is_some());
+        assert!(emulator.take_battery_error().is_none());
+
+        // SRAM is still waiting to be saved.
+        assert!(emulator.flush_battery().is_err());
+    }
+
+    #[test]
+    fn dropping_the_emulator_flushes() {
+        let path = save_path("drop");
+
+        let mut emulator = Emulator::new(cartridge(), Some(MapMode::LoRom)).unwrap();
+        emulator.battery = Some(Box::new(BatteryFile::new(&path, Duration::from_secs(3600))));
+        emulator.mmu.store_u8(0x70_07FF, 0x42);
+
+        // Neither a frame nor a snapshot writes it yet.
+        emulator.run_frame();
+        drop(emulator.snapshot());
+        assert!(!path.exists());
+
+        drop(emulator);
+        assert_eq!(fs::read(&path).unwrap()[0x7FF], 0x42);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
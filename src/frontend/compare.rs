@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use crate::cpu::CpuState;
+use crate::symbols::Symbols;
+use crate::trace::{bsnes_trace_entry, TraceRecord};
+
+// The registers from one line of a bsnes-plus trace. Anything that a line
+// doesn't have is left as None and isn't compared, and anything else on the
+// line (timing, disassembly, fields we don't model) is ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReferenceLine {
+    pub pc: u32,
+    pub a: Option<u16>,
+    pub x: Option<u16>,
+    pub y: Option<u16>,
+    pub sp: Option<u16>,
+    pub direct_page: Option<u16>,
+    pub data_bank: Option<u8>,
+    pub status: Option<u8>,
+}
+
+// Returns None for lines that don't start with an address, e.g. headers or
+// messages that the reference emulator mixed into its log.
+pub fn parse_reference_line(line: &str) -> Option<ReferenceLine> {
+    let mut fields = line.split_whitespace();
+
+    let pc = fields.next()?;
+
+    if pc.len() != 6 {
+        return None;
+    }
+
+    let mut reference = ReferenceLine {
+        pc: u32::from_str_radix(pc, 16).ok()?,
+        ..ReferenceLine::default()
+    };
+
+    for field in fields {
+        let register = |name: &str| {
+            field
+                .strip_prefix(name)
+                .and_then(|value| u16::from_str_radix(value, 16).ok())
+        };
+
+        if let Some(value) = register("A:") {
+            reference.a = Some(value);
+        } else if let Some(value) = register("X:") {
+            reference.x = Some(value);
+        } else if let Some(value) = register("Y:") {
+            reference.y = Some(value);
+        } else if let Some(value) = register("S:") {
+            reference.sp = Some(value);
+        } else if let Some(value) = register("DB:") {
+            reference.data_bank = Some(value as u8);
+        } else if let Some(value) = register("D:") {
+            reference.direct_page = Some(value);
+        } else if let Some(status) = parse_flags(field) {
+            reference.status = Some(status);
+        }
+    }
+
+    Some(reference)
+}
+
+// Flags are written as `nvmxdizc`, with set flags in upper case.
+fn parse_flags(field: &str) -> Option<u8> {
+    if field.len() != 8 {
+        return None;
+    }
+
+    field
+        .chars()
+        .zip("nvmxdizc".chars())
+        .enumerate()
+        .try_fold(0, |status, (i, (c, flag))| {
+            if c == flag {
+                Some(status)
+            } else if c == flag.to_ascii_uppercase() {
+                Some(status | 0x80 >> i)
+            } else {
+                None
+            }
+        })
+}
+
+// Where execution first went differently to the reference.
+pub struct Divergence {
+    pub instruction: u64,
+    pub line: usize,
+
+    // The name of the first register that differs, as bsnes-plus labels it,
+    // or PC.
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+
+    // Our lines for the instructions leading up to this one.
+    pub context: Vec<String>,
+}
+
+impl Divergence {
+    // Shows both lines one above the other, with the field that differs
+    // underlined in ours.
+    pub fn report(&self) -> String {
+        let mut output = format!(
+            "Diverged from the reference at instruction {} (line {}): {} differs\n",
+            self.instruction, self.line, self.field
+        );
+
+        for line in &self.context {
+            let _ = writeln!(output, "    {}", line);
+        }
+
+        let _ = writeln!(output, "  - {}", self.expected);
+        let _ = writeln!(output, "  + {}", self.actual);
+
+        let (start, len) = match self.field {
+            "PC" => (0, 6),
+            "P" => match self.actual.find(" V:") {
+                Some(end) => (end - 8, 8),
+                None => (0, 0),
+            },
+            field => match self.actual.find(&format!(" {}:", field)) {
+                Some(start) => {
+                    let start = start + field.len() + 2;
+                    let len = self.actual[start..]
+                        .find(' ')
+                        .unwrap_or(self.actual.len() - start);
+
+                    (start, len)
+                }
+                None => (0, 0),
+            },
+        };
+
+        if len > 0 {
+            let _ = writeln!(output, "    {}{}", " ".repeat(start), "^".repeat(len));
+        }
+
+        output
+    }
+}
+
+// Checks each instruction against a reference trace as the emulator runs.
+pub struct Comparer {
+    reference: Vec<(usize, ReferenceLine, String)>,
+    next: usize,
+
+    context: VecDeque<String>,
+    context_len: usize,
+}
+
+impl Comparer {
+    pub fn new(reference: &str, context_len: usize) -> Comparer {
+        let reference = reference
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                parse_reference_line(line).map(|parsed| (i + 1, parsed, line.trim_end().into()))
+            })
+            .collect();
+
+        Comparer {
+            reference,
+            next: 0,
+
+            context: VecDeque::new(),
+            context_len,
+        }
+    }
+
+    // How many instructions have matched so far.
+    pub fn matched(&self) -> usize {
+        self.next
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.reference.len()
+    }
+
+    // Takes the state from just before an instruction runs. Once the end of
+    // the reference has been reached, everything is accepted.
+    pub fn check(&mut self, record: &TraceRecord, symbols: &Symbols) -> Option<Divergence> {
+        let (line, reference, text) = self.reference.get(self.next)?;
+
+        let actual = bsnes_trace_entry(record, symbols);
+
+        if let Some(field) = first_difference(reference, &record.cpu) {
+            return Some(Divergence {
+                instruction: self.next as u64,
+                line: *line,
+                field,
+                expected: text.clone(),
+                actual,
+                context: self.context.iter().cloned().collect(),
+            });
+        }
+
+        self.next += 1;
+
+        if self.context_len > 0 {
+            if self.context.len() >= self.context_len {
+                self.context.pop_front();
+            }
+
+            self.context.push_back(actual);
+        }
+
+        None
+    }
+}
+
+fn first_difference(reference: &ReferenceLine, cpu: &CpuState) -> Option<&'static str> {
+    // bsnes-plus always shows M and X as set in emulation mode.
+    let status = if cpu.emulation {
+        cpu.status | 0x30
+    } else {
+        cpu.status
+    };
+
+    let differs = |expected: Option<u16>, actual: u16| expected.is_some_and(|e| e != actual);
+
+    if reference.pc != cpu.current_addr() {
+        Some("PC")
+    } else if differs(reference.a, cpu.a) {
+        Some("A")
+    } else if differs(reference.x, cpu.x) {
+        Some("X")
+    } else if differs(reference.y, cpu.y) {
+        Some("Y")
+    } else if differs(reference.sp, cpu.sp) {
+        Some("S")
+    } else if differs(reference.direct_page, cpu.direct_page) {
+        Some("D")
+    } else if differs(reference.data_bank.map(u16::from), cpu.data_bank as u16) {
+        Some("DB")
+    } else if differs(reference.status.map(u16::from), status as u16) {
+        Some("P")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{lorom, Asm};
+    use crate::emulator::Emulator;
+    use crate::mmu::MapMode;
+
+    // Records of the first dozen instructions of a short loop, to build
+    // references from.
+    fn records() -> Vec<TraceRecord> {
+        #[rustfmt::skip]
+        let code = Asm::at(0x8000)
+            .clc().xce()
+            .sep(0x20).rep(0x10)
+            .ldx_imm16(0x1234)
+            .label("loop")
+            .lda_imm8(0x7F)
+            .sta_abs_x(0x0100)
+            .inx()
+            .bra("loop")
+            .assemble()
+            .unwrap();
+
+        let mut emulator = Emulator::new(lorom(&code), Some(MapMode::LoRom)).unwrap();
+
+        (0..12).map(|_| emulator.step_traced().0).collect()
+    }
+
+    #[test]
+    fn reference_lines() {
+        let line = "008006 a9 7f       lda #$7f               A:0000 X:1234 Y:0000 S:01ff D:0000 DB:7e nvMxdizC V:  0 H:  72";
+
+        assert_eq!(
+            parse_reference_line(line),
+            Some(ReferenceLine {
+                pc: 0x00_8006,
+                a: Some(0x0000),
+                x: Some(0x1234),
+                y: Some(0x0000),
+                sp: Some(0x01FF),
+                direct_page: Some(0x0000),
+                data_bank: Some(0x7E),
+                status: Some(0x21),
+            })
+        );
+
+        // Only what's there is compared.
+        assert_eq!(
+            parse_reference_line("c08000 sei A:00ff"),
+            Some(ReferenceLine {
+                pc: 0xC0_8000,
+                a: Some(0x00FF),
+                ..ReferenceLine::default()
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_reference_lines() {
+        for line in [
+            "",
+            "Tracing started",
+            "8006 a9 7f",
+            "00800g clc",
+            "0080061 clc",
+        ] {
+            assert_eq!(parse_reference_line(line), None, "{:?}", line);
+        }
+
+        // Fields that don't parse are skipped, rather than the whole line.
+        assert_eq!(
+            parse_reference_line("008000 clc A:zz X:0001 nvmxdiz? DB:100"),
+            Some(ReferenceLine {
+                pc: 0x00_8000,
+                x: Some(0x0001),
+                data_bank: Some(0x00),
+                ..ReferenceLine::default()
+            })
+        );
+    }
+
+    #[test]
+    fn matching_reference() {
+        let records = records();
+        let symbols = Symbols::new();
+
+        let reference: Vec<String> = records
+            .iter()
+            .map(|record| bsnes_trace_entry(record, &symbols))
+            .collect();
+
+        let mut comparer = Comparer::new(&reference.join("\n"), 3);
+
+        for record in &records {
+            assert!(comparer.check(record, &symbols).is_none());
+        }
+
+        assert_eq!(comparer.matched(), records.len());
+        assert!(comparer.finished());
+    }
+
+    #[test]
+    fn first_divergence() {
+        let records = records();
+        let symbols = Symbols::new();
+
+        let mut reference: Vec<String> = records
+            .iter()
+            .map(|record| bsnes_trace_entry(record, &symbols))
+            .collect();
+
+        // The reference has a different A going into the second STA, and
+        // the PC is off after that too. Only the first difference counts.
+        assert!(reference[10].contains("sta") && reference[10].contains("A:007f"));
+        reference[10] = reference[10].replace("A:007f", "A:0080");
+        reference[11] = reference[11].replacen("00800e", "00800f", 1);
+        reference.insert(0, "Tracing started".into());
+
+        let mut comparer = Comparer::new(&reference.join("\n"), 3);
+
+        let divergence = records
+            .iter()
+            .find_map(|record| comparer.check(record, &symbols))
+            .unwrap();
+
+        // Lines are counted from 1, including the ones that were skipped.
+        assert_eq!(divergence.instruction, 10);
+        assert_eq!(divergence.line, 12);
+        assert_eq!(divergence.field, "A");
+        assert_eq!(divergence.expected, reference[11]);
+        assert_eq!(divergence.actual, bsnes_trace_entry(&records[10], &symbols));
+        assert_eq!(divergence.context, reference[8..11]);
+        assert_eq!(comparer.matched(), 10);
+        assert!(!comparer.finished());
+
+        // The differing field is underlined in our line.
+        let report = divergence.report();
+        let lines: Vec<&str> = report.lines().collect();
+        let column = lines[5].find("A:").unwrap() + 2;
+        assert_eq!(lines[6].find('^'), Some(column));
+        assert!(lines[6].ends_with("^^^^"));
+    }
+}
@@ -0,0 +1,74 @@
+use super::checksum;
+use super::movie::Player;
+use crate::cpu::CpuState;
+use crate::emulator::Emulator;
+
+// The state of the machine at the end of a frame (or at the end of the run),
+// reduced to something that's cheap to keep around and compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub instructions: u64,
+    pub frame: u64,
+    pub cpu: CpuState,
+    pub wram_hash: u64,
+}
+
+impl Checkpoint {
+    pub fn capture(emulator: &Emulator) -> Checkpoint {
+        Checkpoint {
+            instructions: emulator.steps(),
+            frame: emulator.frame(),
+            cpu: emulator.cpu.state(),
+            wram_hash: checksum(emulator.mmu.wram()),
+        }
+    }
+}
+
+// Runs until either limit is reached, taking a checkpoint at the end of
+// every frame and once more at the end. The limits count from the start of
+// the emulator, not from the start of this run, so that they line up with
+// a loaded save state. Input comes from the player if there is one.
+pub fn run_checkpoints(
+    emulator: &mut Emulator,
+    mut player: Option<&mut Player>,
+    max_instructions: Option<u64>,
+    max_frames: Option<u64>,
+) -> Vec<Checkpoint> {
+    let mut checkpoints = Vec::new();
+
+    let finished = |emulator: &Emulator| {
+        max_instructions.is_some_and(|max| emulator.steps() >= max)
+            || max_frames.is_some_and(|max| emulator.frame() >= max)
+    };
+
+    while !finished(emulator) {
+        if let Some(player) = &mut player {
+            let (buttons, _) = player.next(emulator.frame(), emulator.mmu.wram());
+            emulator.set_input(buttons);
+        }
+
+        while !finished(emulator) {
+            if emulator.step_instruction().frame_complete {
+                checkpoints.push(Checkpoint::capture(emulator));
+                break;
+            }
+        }
+    }
+
+    checkpoints.push(Checkpoint::capture(emulator));
+    checkpoints
+}
+
+// Finds the first checkpoint where two runs went differently. If one run
+// has fewer checkpoints than the other, the extra one counts as a
+// difference, with None for the missing side.
+pub fn first_mismatch(
+    first: &[Checkpoint],
+    second: &[Checkpoint],
+) -> Option<(Option<Checkpoint>, Option<Checkpoint>)> {
+    let len = first.len().max(second.len());
+
+    (0..len)
+        .map(|i| (first.get(i).copied(), second.get(i).copied()))
+        .find(|(first, second)| first != second)
+}
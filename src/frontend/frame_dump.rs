@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub struct FrameDumper {
+    dir: PathBuf,
+    interval: u64,
+}
+
+impl FrameDumper {
+    pub fn new(dir: impl Into<PathBuf>, interval: u64) -> io::Result<FrameDumper> {
+        if !cfg!(feature = "frame-dump") {
+            return Err(unsupported());
+        }
+
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(FrameDumper {
+            dir,
+            interval: interval.max(1),
+        })
+    }
+
+    pub fn dump(&self, frame: u64, framebuffer: &[u8]) -> io::Result<()> {
+        if !frame.is_multiple_of(self.interval) {
+            return Ok(());
+        }
+
+        self.write(frame, framebuffer)
+    }
+
+    // Writes the frame whether or not it's due, e.g. because it's one that
+    // didn't match its expected hash.
+    pub fn write(&self, frame: u64, framebuffer: &[u8]) -> io::Result<()> {
+        let file = File::create(frame_path(&self.dir, frame))?;
+
+        encode_png(
+            BufWriter::new(file),
+            framebuffer,
+            SCREEN_WIDTH as u32,
+            SCREEN_HEIGHT as u32,
+        )
+    }
+}
+
+// Writes a standalone RGBA image, e.g. from the PPU's debug views.
+pub fn write_png(path: impl AsRef<Path>, pixels: &[u8], width: u32, height: u32) -> io::Result<()> {
+    let file = File::create(path)?;
+    encode_png(BufWriter::new(file), pixels, width, height)
+}
+
+fn frame_path(dir: &Path, frame: u64) -> PathBuf {
+    // Zero-padded so that the directory sorts chronologically.
+    dir.join(format!("frame_{:08}.png", frame))
+}
+
+#[cfg(feature = "frame-dump")]
+pub fn encode_png<W: Write>(
+    writer: W,
+    framebuffer: &[u8],
+    width: u32,
+    height: u32,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(framebuffer)?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "frame-dump"))]
+pub fn encode_png<W: Write>(
+    _writer: W,
+    _framebuffer: &[u8],
+    _width: u32,
+    _height: u32,
+) -> io::Result<()> {
+    Err(unsupported())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "frame dumping requires the frame-dump feature",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_paths_sort_chronologically() {
+        let dir = Path::new("frames");
+        let mut paths: Vec<_> = [100, 9, 10_000, 10].map(|f| frame_path(dir, f)).into();
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            [9, 10, 100, 10_000].map(|f| frame_path(dir, f)).to_vec()
+        );
+        assert_eq!(frame_path(dir, 42), dir.join("frame_00000042.png"));
+    }
+
+    #[cfg(feature = "frame-dump")]
+    #[test]
+    fn png_round_trip() {
+        let framebuffer: Vec<u8> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+            .flat_map(|i| [i as u8, (i >> 8) as u8, (i * 7) as u8, 0xFF])
+            .collect();
+
+        let mut encoded = Vec::new();
+        encode_png(
+            &mut encoded,
+            &framebuffer,
+            SCREEN_WIDTH as u32,
+            SCREEN_HEIGHT as u32,
+        )
+        .unwrap();
+
+        let mut reader = png::Decoder::new(encoded.as_slice()).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+
+        assert_eq!((info.width, info.height), (256, 224));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(&decoded[..info.buffer_size()], framebuffer.as_slice());
+    }
+
+    #[cfg(feature = "frame-dump")]
+    #[test]
+    fn only_due_frames_are_dumped() {
+        let dir = std::env::temp_dir().join(format!("snesemu-frames-{}", std::process::id()));
+        let dumper = FrameDumper::new(&dir, 3).unwrap();
+        let framebuffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+
+        for frame in 0..7 {
+            dumper.dump(frame, &framebuffer).unwrap();
+        }
+
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            names,
+            [
+                "frame_00000000.png",
+                "frame_00000003.png",
+                "frame_00000006.png"
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+
+use super::checksum;
+use super::movie::Player;
+use crate::emulator::Emulator;
+
+// The hash of a finished frame. It's taken over the raw RGBA framebuffer,
+// so a change to the pixel format or the layout of the buffer changes every
+// hash, rather than slipping through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHash {
+    pub frame: u64,
+    pub hash: u64,
+}
+
+impl FrameHash {
+    pub fn capture(emulator: &Emulator) -> FrameHash {
+        FrameHash {
+            frame: emulator.frame(),
+            hash: checksum(emulator.framebuffer()),
+        }
+    }
+}
+
+// One line per frame, e.g. `12 9c2e4f0a1b3d5e67`, which is also what
+// parse_hashes reads back.
+impl fmt::Display for FrameHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:016x}", self.frame, self.hash)
+    }
+}
+
+// Runs until the emulator has finished the given frame, hashing each one on
+// the way. The callback gets the emulator while the frame is still in the
+// framebuffer, so that it can be dumped if it's wrong. Input comes from the
+// player if there is one.
+pub fn run_frame_hashes(
+    emulator: &mut Emulator,
+    mut player: Option<&mut Player>,
+    frames: u64,
+    mut on_frame: impl FnMut(&Emulator, FrameHash),
+) {
+    while emulator.frame() < frames {
+        if let Some(player) = &mut player {
+            let (buttons, _) = player.next(emulator.frame(), emulator.mmu.wram());
+            emulator.set_input(buttons);
+        }
+
+        emulator.run_frame();
+        on_frame(emulator, FrameHash::capture(emulator));
+    }
+}
+
+// Reads hashes in the format they're printed in. Blank lines and lines
+// starting with # are skipped, so that the files can be annotated.
+pub fn parse_hashes(text: &str) -> Result<Vec<FrameHash>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let parsed = line.split_once(' ').and_then(|(frame, hash)| {
+                Some(FrameHash {
+                    frame: frame.parse().ok()?,
+                    hash: u64::from_str_radix(hash.trim(), 16).ok()?,
+                })
+            });
+
+            parsed.ok_or_else(|| format!("line {}: expected <frame> <hash>, got {}", i + 1, line))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_it_prints() {
+        let hashes = [
+            FrameHash {
+                frame: 1,
+                hash: 0x0123_4567_89AB_CDEF,
+            },
+            FrameHash {
+                frame: 12,
+                hash: 0x9C2E_4F0A_1B3D_5E67,
+            },
+        ];
+
+        let text = format!("# a comment\n{}\n\n  {}\n", hashes[0], hashes[1]);
+        assert_eq!(text.lines().nth(1), Some("1 0123456789abcdef"));
+        assert_eq!(parse_hashes(&text), Ok(hashes.to_vec()));
+
+        assert_eq!(
+            parse_hashes("1 0123\n2 xyz"),
+            Err("line 2: expected <frame> <hash>, got 2 xyz".into())
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::fmt::Write;
+
+use crate::mmu::Access;
+
+// Loops with bodies longer than this are assumed to be doing real work.
+const MAX_BODY: usize = 32;
+
+// How many different addresses a loop can write to and still count as
+// idle, e.g. for a timeout counter.
+const MAX_WRITES: usize = 4;
+
+// What an idle loop seems to be waiting for, based on what it reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wait {
+    // Polling HVBJOY or RDNMI.
+    Vblank(u32),
+
+    // Polling one of the APU ports, usually waiting on the SPC700 to
+    // acknowledge a transfer.
+    Apu(u32),
+
+    // Polling a variable in RAM, usually one that the NMI handler sets.
+    Ram(u32),
+
+    // Not reading anything at all, so nothing is ever going to change.
+    Nothing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdleLoop {
+    pub start: u32,
+    pub end: u32,
+    pub iterations: u64,
+    pub wait: Wait,
+}
+
+impl IdleLoop {
+    pub fn describe(&self) -> String {
+        let mut output = format!(
+            "Idle loop at {:06X}-{:06X} for {} iterations: ",
+            self.start, self.end, self.iterations
+        );
+
+        let _ = match self.wait {
+            Wait::Vblank(addr) => write!(output, "waiting for vblank (polls {:06X})", addr),
+            Wait::Apu(addr) => write!(output, "waiting for the APU (polls {:06X})", addr),
+            Wait::Ram(addr) => write!(
+                output,
+                "waiting for {:06X} to change, probably from the NMI handler",
+                addr
+            ),
+            Wait::Nothing => write!(output, "not reading anything, so it may be stuck for good"),
+        };
+
+        output
+    }
+}
+
+// Spots the CPU going round the same short loop over and over.
+//
+// A loop starts when execution jumps backwards, and another iteration is
+// counted each time it jumps back to the same place. Its reads and writes
+// are collected along the way, and the loop is reported once if it keeps
+// going for long enough without writing anywhere much.
+pub struct IdleDetector {
+    threshold: u64,
+
+    start: Option<u32>,
+    end: u32,
+    body_len: usize,
+    iterations: u64,
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    reported: bool,
+
+    prev_pc: u32,
+}
+
+impl IdleDetector {
+    pub fn new(threshold: u64) -> IdleDetector {
+        IdleDetector {
+            threshold,
+
+            start: None,
+            end: 0,
+            body_len: 0,
+            iterations: 0,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            reported: false,
+
+            prev_pc: 0,
+        }
+    }
+
+    // Takes the address of an instruction that just ran, along with the
+    // memory it accessed. Returns the loop the first time it's been running
+    // for long enough.
+    pub fn record(&mut self, pc: u32, accesses: &[(Access, u32)]) -> Option<IdleLoop> {
+        let backwards = pc <= self.prev_pc && pc >> 16 == self.prev_pc >> 16;
+        self.prev_pc = pc;
+
+        if backwards {
+            if self.start == Some(pc) {
+                self.iterations += 1;
+            } else {
+                self.restart(Some(pc));
+            }
+
+            self.body_len = 0;
+        }
+
+        let start = self.start?;
+
+        self.body_len += 1;
+        self.end = self.end.max(pc);
+
+        for &(access, addr) in accesses {
+            let seen = match access {
+                Access::Read => &mut self.reads,
+                Access::Write => &mut self.writes,
+            };
+
+            if !seen.contains(&addr) {
+                seen.push(addr);
+            }
+        }
+
+        if self.body_len > MAX_BODY || self.writes.len() > MAX_WRITES {
+            self.restart(None);
+            return None;
+        }
+
+        if self.reported || self.iterations < self.threshold {
+            return None;
+        }
+
+        self.reported = true;
+
+        Some(IdleLoop {
+            start,
+            end: self.end,
+            iterations: self.iterations,
+            wait: self.classify(),
+        })
+    }
+
+    fn restart(&mut self, start: Option<u32>) {
+        self.start = start;
+        self.end = start.unwrap_or(0);
+        self.body_len = 0;
+        self.iterations = 0;
+        self.reads.clear();
+        self.writes.clear();
+        self.reported = false;
+    }
+
+    fn classify(&self) -> Wait {
+        // Leave out the loop's own instructions. The last one may have an
+        // operand, hence the extra bytes.
+        let code = self.start.unwrap_or(0)..=self.end + 3;
+
+        let mut ram = None;
+
+        for &addr in self.reads.iter().filter(|addr| !code.contains(addr)) {
+            let bank = (addr >> 16) as u8;
+            let offset = addr & 0xFFFF;
+            let system = matches!(bank, 0x00..=0x3F | 0x80..=0xBF);
+
+            match offset {
+                0x4210 | 0x4212 if system => return Wait::Vblank(addr),
+                0x2140..=0x2143 if system => return Wait::Apu(addr),
+                0x0000..=0x1FFF if system => ram = ram.or(Some(addr)),
+                _ if matches!(bank, 0x7E..=0x7F) => ram = ram.or(Some(addr)),
+                _ => {}
+            }
+        }
+
+        ram.map_or(Wait::Nothing, Wait::Ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{lorom, Asm};
+    use crate::emulator::Emulator;
+    use crate::mmu::MapMode;
+
+    // Runs the code from $8000 until the detector reports a loop, giving up
+    // after a while.
+    fn detect(code: Asm) -> Option<IdleLoop> {
+        let rom = lorom(&code.assemble().unwrap());
+        let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+        emulator.mmu.log_accesses = true;
+
+        let mut idle = IdleDetector::new(20);
+
+        for _ in 0..1000 {
+            let pc = emulator.cpu.current_addr();
+            emulator.step_instruction();
+
+            if let Some(idle_loop) = idle.record(pc, emulator.mmu.accesses()) {
+                return Some(idle_loop);
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn vblank_wait() {
+        // Waits for bit 7 of HVBJOY to be set.
+        let code = Asm::at(0x8000)
+            .label("wait")
+            .lda_abs(0x4212)
+            .cmp_imm8(0x80)
+            .bcc("wait");
+
+        assert_eq!(
+            detect(code),
+            Some(IdleLoop {
+                start: 0x00_8000,
+                end: 0x00_8005,
+                iterations: 20,
+                wait: Wait::Vblank(0x00_4212),
+            })
+        );
+    }
+
+    #[test]
+    fn apu_port_poll() {
+        // Waits for the SPC700 to echo back a byte that was never sent.
+        let code = Asm::at(0x8000)
+            .label("wait")
+            .lda_abs(0x2140)
+            .cmp_imm8(0xCC)
+            .bne("wait");
+
+        let idle_loop = detect(code).unwrap();
+        assert_eq!((idle_loop.start, idle_loop.end), (0x00_8000, 0x00_8005));
+        assert_eq!(idle_loop.wait, Wait::Apu(0x00_2140));
+    }
+
+    #[test]
+    fn ram_flag_spin() {
+        // Waits for an NMI handler that's never enabled to set $10, counting
+        // in $12 along the way.
+        let code = Asm::at(0x8000)
+            .label("wait")
+            .inx()
+            .stx_dp(0x12)
+            .lda_dp(0x10)
+            .beq("wait");
+
+        let idle_loop = detect(code).unwrap();
+        assert_eq!(idle_loop.wait, Wait::Ram(0x00_0010));
+        assert!(idle_loop
+            .describe()
+            .ends_with("waiting for 000010 to change, probably from the NMI handler"));
+    }
+
+    #[test]
+    fn loop_reading_nothing() {
+        let code = Asm::at(0x8000).label("spin").bra("spin");
+
+        let idle_loop = detect(code).unwrap();
+        assert_eq!((idle_loop.start, idle_loop.end), (0x00_8000, 0x00_8000));
+        assert_eq!(idle_loop.wait, Wait::Nothing);
+    }
+
+    #[test]
+    fn busy_loops_are_not_idle() {
+        // Fills memory, writing somewhere new every time around.
+        let code = Asm::at(0x8000)
+            .label("fill")
+            .inx()
+            .sta_abs_x(0x0100)
+            .bra("fill");
+
+        assert_eq!(detect(code), None);
+    }
+}
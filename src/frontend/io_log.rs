@@ -0,0 +1,339 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::mmu::{Access, IoAccess};
+
+// One access to a hardware register, along with where the CPU and the PPU
+// were when it happened.
+#[derive(Debug, Clone, Copy)]
+pub struct IoEntry {
+    pub access: Access,
+    pub addr: u32,
+    pub value: u8,
+    pub pc: u32,
+    pub frame: u64,
+    pub scanline: u16,
+}
+
+impl IoEntry {
+    pub fn new(access: &IoAccess, pc: u32, frame: u64, scanline: u16) -> IoEntry {
+        IoEntry {
+            access: access.access,
+            addr: access.addr,
+            value: access.value,
+            pc,
+            frame,
+            scanline,
+        }
+    }
+
+    // e.g. `frame 12 line 225 pc 008123 W 002100 INIDISP = 80`
+    pub fn format(&self, output: &mut String) {
+        let direction = match self.access {
+            Access::Read => 'R',
+            Access::Write => 'W',
+        };
+
+        let _ = write!(
+            output,
+            "frame {} line {:3} pc {:06X} {} {:06X} {:<8} = {:02X}",
+            self.frame,
+            self.scanline,
+            self.pc,
+            direction,
+            self.addr,
+            register_name(self.addr as u16).unwrap_or(""),
+            self.value
+        );
+    }
+}
+
+// Keeps the register accesses, either as the last few in memory or by
+// writing every one of them to a file.
+pub enum IoLog {
+    Ring(VecDeque<IoEntry>, usize),
+
+    // The line is reused for every entry, to save allocating.
+    Stream(BufWriter<File>, String),
+}
+
+impl IoLog {
+    pub fn ring(capacity: usize) -> IoLog {
+        IoLog::Ring(VecDeque::with_capacity(capacity), capacity.max(1))
+    }
+
+    pub fn create(path: impl AsRef<Path>) -> io::Result<IoLog> {
+        Ok(IoLog::Stream(
+            BufWriter::new(File::create(path)?),
+            String::new(),
+        ))
+    }
+
+    pub fn record(&mut self, entry: IoEntry) -> io::Result<()> {
+        match self {
+            IoLog::Ring(entries, capacity) => {
+                if entries.len() >= *capacity {
+                    entries.pop_front();
+                }
+
+                entries.push_back(entry);
+                Ok(())
+            }
+
+            IoLog::Stream(writer, line) => {
+                line.clear();
+                entry.format(line);
+                writeln!(writer, "{}", line)
+            }
+        }
+    }
+
+    // The last n entries, oldest first. Nothing is kept in stream mode.
+    pub fn recent(&self, n: usize) -> impl Iterator<Item = &IoEntry> {
+        let entries = match self {
+            IoLog::Ring(entries, _) => entries.range(entries.len().saturating_sub(n)..),
+            IoLog::Stream(..) => Default::default(),
+        };
+
+        entries
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            IoLog::Ring(..) => Ok(()),
+            IoLog::Stream(writer, _) => writer.flush(),
+        }
+    }
+}
+
+// The name of the register at an offset in the system banks.
+pub fn register_name(offset: u16) -> Option<&'static str> {
+    let name = match offset {
+        0x2100 => "INIDISP",
+        0x2101 => "OBSEL",
+        0x2102 => "OAMADDL",
+        0x2103 => "OAMADDH",
+        0x2104 => "OAMDATA",
+        0x2105 => "BGMODE",
+        0x2106 => "MOSAIC",
+        0x2107 => "BG1SC",
+        0x2108 => "BG2SC",
+        0x2109 => "BG3SC",
+        0x210A => "BG4SC",
+        0x210B => "BG12NBA",
+        0x210C => "BG34NBA",
+        0x210D => "BG1HOFS",
+        0x210E => "BG1VOFS",
+        0x210F => "BG2HOFS",
+        0x2110 => "BG2VOFS",
+        0x2111 => "BG3HOFS",
+        0x2112 => "BG3VOFS",
+        0x2113 => "BG4HOFS",
+        0x2114 => "BG4VOFS",
+        0x2115 => "VMAIN",
+        0x2116 => "VMADDL",
+        0x2117 => "VMADDH",
+        0x2118 => "VMDATAL",
+        0x2119 => "VMDATAH",
+        0x211A => "M7SEL",
+        0x211B => "M7A",
+        0x211C => "M7B",
+        0x211D => "M7C",
+        0x211E => "M7D",
+        0x211F => "M7X",
+        0x2120 => "M7Y",
+        0x2121 => "CGADD",
+        0x2122 => "CGDATA",
+        0x2123 => "W12SEL",
+        0x2124 => "W34SEL",
+        0x2125 => "WOBJSEL",
+        0x2126 => "WH0",
+        0x2127 => "WH1",
+        0x2128 => "WH2",
+        0x2129 => "WH3",
+        0x212A => "WBGLOG",
+        0x212B => "WOBJLOG",
+        0x212C => "TM",
+        0x212D => "TS",
+        0x212E => "TMW",
+        0x212F => "TSW",
+        0x2130 => "CGWSEL",
+        0x2131 => "CGADSUB",
+        0x2132 => "COLDATA",
+        0x2133 => "SETINI",
+        0x2134 => "MPYL",
+        0x2135 => "MPYM",
+        0x2136 => "MPYH",
+        0x2137 => "SLHV",
+        0x2138 => "RDOAM",
+        0x2139 => "RDVRAML",
+        0x213A => "RDVRAMH",
+        0x213B => "RDCGRAM",
+        0x213C => "OPHCT",
+        0x213D => "OPVCT",
+        0x213E => "STAT77",
+        0x213F => "STAT78",
+        0x2140..=0x217F => ["APUIO0", "APUIO1", "APUIO2", "APUIO3"][offset as usize & 3],
+        0x2180 => "WMDATA",
+        0x2181 => "WMADDL",
+        0x2182 => "WMADDM",
+        0x2183 => "WMADDH",
+        0x4016 => "JOYSER0",
+        0x4017 => "JOYSER1",
+        0x4200 => "NMITIMEN",
+        0x4201 => "WRIO",
+        0x4202 => "WRMPYA",
+        0x4203 => "WRMPYB",
+        0x4204 => "WRDIVL",
+        0x4205 => "WRDIVH",
+        0x4206 => "WRDIVB",
+        0x4207 => "HTIMEL",
+        0x4208 => "HTIMEH",
+        0x4209 => "VTIMEL",
+        0x420A => "VTIMEH",
+        0x420B => "MDMAEN",
+        0x420C => "HDMAEN",
+        0x420D => "MEMSEL",
+        0x4210 => "RDNMI",
+        0x4211 => "TIMEUP",
+        0x4212 => "HVBJOY",
+        0x4213 => "RDIO",
+        0x4214 => "RDDIVL",
+        0x4215 => "RDDIVH",
+        0x4216 => "RDMPYL",
+        0x4217 => "RDMPYH",
+        0x4218 => "JOY1L",
+        0x4219 => "JOY1H",
+        0x421A => "JOY2L",
+        0x421B => "JOY2H",
+        0x421C => "JOY3L",
+        0x421D => "JOY3H",
+        0x421E => "JOY4L",
+        0x421F => "JOY4H",
+        0x4300..=0x437F => match offset & 0x0F {
+            0x0 => "DMAPx",
+            0x1 => "BBADx",
+            0x2 => "A1TxL",
+            0x3 => "A1TxH",
+            0x4 => "A1Bx",
+            0x5 => "DASxL",
+            0x6 => "DASxH",
+            0x7 => "DASBx",
+            0x8 => "A2AxL",
+            0x9 => "A2AxH",
+            0xA => "NTRLx",
+            _ => "UNUSEDx",
+        },
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mmu::{MapMode, Mmu};
+
+    // Sets the screen up the way a boot sequence might, through the
+    // mirrors as well, with some accesses that aren't to registers mixed in.
+    fn scripted_accesses() -> Vec<IoAccess> {
+        let mut mmu = Mmu::new(vec![0; 0x8000], Some(MapMode::LoRom));
+        mmu.log_io = true;
+
+        mmu.store_u8(0x00_2100, 0x8F);
+        mmu.store_u8(0x7E_2100, 0x00);
+        mmu.store_u8(0x80_4200, 0x81);
+        mmu.store_u8(0x00_0010, 0x01);
+        mmu.read_u8(0x00_4210);
+        mmu.store_u8(0x00_4352, 0x34);
+        mmu.read_u8(0x00_8000);
+        mmu.store_u8(0x00_420B, 0x00);
+        mmu.store_u8(0x00_2142, 0xCC);
+        mmu.store_u8(0x40_2100, 0x0F);
+
+        mmu.io_accesses().to_vec()
+    }
+
+    #[test]
+    fn records_register_accesses() {
+        let accesses = scripted_accesses();
+
+        let recorded: Vec<_> = accesses
+            .iter()
+            .map(|a| (a.access, a.addr, a.value, register_name(a.addr as u16)))
+            .collect();
+
+        assert_eq!(
+            recorded,
+            [
+                (Access::Write, 0x00_2100, 0x8F, Some("INIDISP")),
+                (Access::Write, 0x80_4200, 0x81, Some("NMITIMEN")),
+                (Access::Read, 0x00_4210, recorded[2].2, Some("RDNMI")),
+                (Access::Write, 0x00_4352, 0x34, Some("A1TxL")),
+                (Access::Write, 0x00_420B, 0x00, Some("MDMAEN")),
+                (Access::Write, 0x00_2142, 0xCC, Some("APUIO2")),
+            ]
+        );
+
+        assert_eq!(register_name(0x4214), Some("RDDIVL"));
+        assert_eq!(register_name(0x4300), Some("DMAPx"));
+        assert_eq!(register_name(0x437B), Some("UNUSEDx"));
+        assert_eq!(register_name(0x2184), None);
+        assert_eq!(register_name(0x4000), None);
+    }
+
+    #[test]
+    fn ring_keeps_the_last_entries() {
+        let mut log = IoLog::ring(4);
+
+        for (i, access) in scripted_accesses().iter().enumerate() {
+            log.record(IoEntry::new(access, 0x8000 + i as u32, 3, i as u16))
+                .unwrap();
+        }
+
+        let kept: Vec<u32> = log.recent(10).map(|entry| entry.addr).collect();
+        assert_eq!(kept, [0x00_4210, 0x00_4352, 0x00_420B, 0x00_2142]);
+
+        let last: Vec<u32> = log.recent(1).map(|entry| entry.pc).collect();
+        assert_eq!(last, [0x8005]);
+
+        let mut line = String::new();
+        log.recent(4).next().unwrap().format(&mut line);
+        assert_eq!(
+            line,
+            format!(
+                "frame 3 line   2 pc 008002 R 004210 RDNMI    = {:02X}",
+                scripted_accesses()[2].value
+            )
+        );
+    }
+
+    #[test]
+    fn stream_writes_every_entry() {
+        let path = std::env::temp_dir().join(format!("snesemu-io-log-{}.log", std::process::id()));
+
+        let mut log = IoLog::create(&path).unwrap();
+
+        for access in &scripted_accesses()[..2] {
+            log.record(IoEntry::new(access, 0x00_8123, 12, 225))
+                .unwrap();
+        }
+
+        assert_eq!(log.recent(10).count(), 0);
+        log.flush().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            text,
+            "frame 12 line 225 pc 008123 W 002100 INIDISP  = 8F\n\
+             frame 12 line 225 pc 008123 W 804200 NMITIMEN = 81\n"
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+use std::panic;
+
+use crate::emulator::Emulator;
+use crate::frontend::batch::{batch_csv, batch_json, find_roms, run_rom, sort_results, Limits};
+use crate::frontend::determinism::{first_mismatch, run_checkpoints};
+use crate::frontend::frame_dump::FrameDumper;
+use crate::frontend::frame_hash::{parse_hashes, run_frame_hashes};
+use crate::frontend::movie::Player;
+use crate::frontend::opcode_coverage::{coverage_grid, coverage_json};
+use crate::frontend::options::Options;
+use crate::frontend::rom_info::{rom_info, rom_info_json};
+use crate::frontend::session::{self, SetupError};
+use crate::header::Header;
+use crate::mmu::{self, MapMode};
+
+pub fn print_opcodes(options: &Options, counts: Option<&[u64; 256]>) {
+    match options.json {
+        true => println!("{}", coverage_json(counts)),
+        false => println!("{}", coverage_grid(counts)),
+    }
+}
+
+pub fn print_rom_info(options: &Options) -> i32 {
+    let mut cartridge = match std::fs::read(&options.rom) {
+        Ok(cartridge) => cartridge,
+        Err(e) => {
+            eprintln!("error: couldn't read {}: {}", options.rom, e);
+            return 2;
+        }
+    };
+    let copier_header = mmu::strip_copier_header(&mut cartridge);
+    let map_mode = options
+        .map_mode
+        .unwrap_or_else(|| MapMode::detect(&cartridge));
+
+    let Some(header) = Header::parse(&cartridge, map_mode) else {
+        eprintln!("error: {} is too small to contain a header", options.rom);
+        return 1;
+    };
+
+    if options.json {
+        println!("{}", rom_info_json(&header, &cartridge, copier_header));
+    } else {
+        print!("{}", rom_info(&header, &cartridge, copier_header));
+    }
+
+    0
+}
+
+// Builds an emulator for a run from scratch, along with the movie it plays.
+fn start(options: &Options, rom: Vec<u8>) -> Result<(Emulator, Option<Player>), SetupError> {
+    let mut emulator = session::prepare(options, rom)?;
+    session::load_state(&mut emulator, options)?;
+
+    let player = session::open_movie(&emulator, options)?;
+
+    Ok((emulator, player))
+}
+
+// Runs the same headless run twice from scratch, and compares the machine
+// state at each frame. Returns the exit code.
+pub fn check_determinism(options: &Options) -> i32 {
+    let rom = match session::read_rom(options) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return e.exit_code();
+        }
+    };
+
+    let run = || {
+        let (mut emulator, mut player) = start(options, rom.clone())?;
+
+        Ok::<_, SetupError>(run_checkpoints(
+            &mut emulator,
+            player.as_mut(),
+            options.max_instructions,
+            options.max_frames,
+        ))
+    };
+
+    let (first, second) = match run().and_then(|first| Ok((first, run()?))) {
+        Ok(runs) => runs,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return e.exit_code();
+        }
+    };
+
+    let describe = |checkpoint: Option<_>| match checkpoint {
+        Some(checkpoint) => format!("{:?}", checkpoint),
+        None => "<run ended>".to_string(),
+    };
+
+    match first_mismatch(&first, &second) {
+        Some((expected, actual)) => {
+            eprintln!(
+                "Runs differed:
+  first:  {}
+  second: {}",
+                describe(expected),
+                describe(actual)
+            );
+            9
+        }
+        None => {
+            eprintln!(
+                "Runs matched at all {} checkpoints, with RAM starting as {}",
+                first.len(),
+                options.ram_init
+            );
+            0
+        }
+    }
+}
+
+// Runs each ROM in the directory in turn, printing how it went as it goes,
+// then prints the report.
+pub fn run_batch(options: &Options, dir: &str) -> i32 {
+    let roms = match find_roms(dir) {
+        Ok(roms) => roms,
+        Err(e) => {
+            eprintln!("error: couldn't read {}: {}", dir, e);
+            return 1;
+        }
+    };
+
+    let unlimited = options.max_frames.is_none()
+        && options.max_instructions.is_none()
+        && options.max_time.is_none();
+
+    let limits = Limits {
+        frames: if unlimited {
+            Some(600)
+        } else {
+            options.max_frames
+        },
+        instructions: options.max_instructions,
+        time: options.max_time,
+    };
+
+    // Panics are part of the report, so they shouldn't be printed as well.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut results = Vec::new();
+
+    for rom in &roms {
+        let result = run_rom(rom, limits);
+        let detail = result.outcome.detail();
+
+        match detail.is_empty() {
+            true => eprintln!("{}: {}", rom.display(), result.outcome.name()),
+            false => eprintln!("{}: {} {}", rom.display(), result.outcome.name(), detail),
+        }
+
+        results.push(result);
+    }
+
+    panic::set_hook(hook);
+
+    sort_results(&mut results);
+
+    match options.json {
+        true => println!("{}", batch_json(&results)),
+        false => print!("{}", batch_csv(&results)),
+    }
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+
+    for result in &results {
+        *counts.entry(result.outcome.name()).or_insert(0) += 1;
+    }
+
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(name, count)| format!("{} {}", count, name))
+        .collect();
+
+    eprintln!("Ran {} ROMs: {}", results.len(), counts.join(", "));
+
+    0
+}
+
+// Prints the hash of every frame, and with --frame-hash-expect, compares
+// them against the expected ones. Frames that don't match are written out
+// as they go, as the framebuffer only holds the latest one.
+pub fn check_frame_hashes(options: &Options, frames: u64) -> i32 {
+    let expected: HashMap<u64, u64> = match &options.frame_hash_expect {
+        Some(path) => {
+            let hashes = match std::fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| parse_hashes(&text))
+            {
+                Ok(hashes) => hashes,
+                Err(e) => {
+                    eprintln!("error: couldn't load {}: {}", path, e);
+                    return 1;
+                }
+            };
+
+            hashes.iter().map(|hash| (hash.frame, hash.hash)).collect()
+        }
+        None => HashMap::new(),
+    };
+
+    let frame_dumper = match &options.dump_frames {
+        Some(dir) => match FrameDumper::new(dir, 1) {
+            Ok(frame_dumper) => Some(frame_dumper),
+            Err(e) => {
+                eprintln!("error: couldn't create {}: {}", dir, e);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let (mut emulator, mut player) =
+        match session::read_rom(options).and_then(|rom| start(options, rom)) {
+            Ok(started) => started,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return e.exit_code();
+            }
+        };
+
+    let first = emulator.frame();
+    let mut mismatches = Vec::new();
+
+    run_frame_hashes(
+        &mut emulator,
+        player.as_mut(),
+        frames,
+        |emulator, actual| {
+            println!("{}", actual);
+
+            let Some(&hash) = expected.get(&actual.frame) else {
+                return;
+            };
+
+            if hash == actual.hash {
+                return;
+            }
+
+            mismatches.push((actual, hash));
+
+            if let Some(frame_dumper) = &frame_dumper {
+                if let Err(e) = frame_dumper.write(actual.frame, emulator.framebuffer()) {
+                    eprintln!("error: couldn't write frame {}: {}", actual.frame, e);
+                }
+            }
+        },
+    );
+
+    if options.frame_hash_expect.is_none() {
+        return 0;
+    }
+
+    let mut missing: Vec<u64> = expected
+        .keys()
+        .copied()
+        .filter(|&frame| frame <= first || frame > emulator.frame())
+        .collect();
+
+    missing.sort_unstable();
+
+    for (actual, hash) in &mismatches {
+        eprintln!(
+            "Frame {} hashed to {:016x}, expected {:016x}",
+            actual.frame, actual.hash, hash
+        );
+    }
+
+    for frame in &missing {
+        eprintln!("Frame {} has an expected hash, but wasn't run", frame);
+    }
+
+    if mismatches.is_empty() && missing.is_empty() {
+        eprintln!("All {} expected hashes matched", expected.len());
+        0
+    } else {
+        eprintln!(
+            "{} of {} expected hashes didn't match",
+            mismatches.len() + missing.len(),
+            expected.len()
+        );
+        11
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::checksum;
+use crate::input::Buttons;
+use crate::mmu::RamInit;
+
+const VERSION: u32 = 2;
+
+// How often a hash of WRAM is stored, so that playback can tell when it has
+// stopped matching the recording.
+const HASH_INTERVAL: u64 = 60;
+
+// Movies are plain text, so that they can be written or tweaked by hand:
+//
+//     snesemu-movie 2 rom=<checksum> ram=<what RAM starts with, e.g. random:1234>
+//     <frame> <port 1> <port 2> [<wram hash>]
+//
+// A line is only written when the input changes, or when a hash is due.
+// Everything other than the frame number is in hex.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    last_frame: Option<u64>,
+    last_buttons: Option<u16>,
+}
+
+impl Recorder {
+    pub fn create(
+        path: impl AsRef<Path>,
+        cartridge: &[u8],
+        ram_init: RamInit,
+    ) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "{}", header(cartridge, ram_init))?;
+
+        Ok(Recorder {
+            writer,
+            last_frame: None,
+            last_buttons: None,
+        })
+    }
+
+    // Records the input for the start of a frame. Calling this more than
+    // once for the same frame does nothing.
+    pub fn record(&mut self, frame: u64, buttons: Buttons, wram: &[u8]) -> io::Result<()> {
+        if self.last_frame == Some(frame) {
+            return Ok(());
+        }
+
+        self.last_frame = Some(frame);
+
+        let changed = self.last_buttons != Some(buttons.bits());
+        let hash_due = frame.is_multiple_of(HASH_INTERVAL);
+
+        if !changed && !hash_due {
+            return Ok(());
+        }
+
+        self.last_buttons = Some(buttons.bits());
+
+        // TODO: Port 2 isn't connected yet
+        write!(self.writer, "{} {:04x} {:04x}", frame, buttons.bits(), 0)?;
+
+        if hash_due {
+            write!(self.writer, " {:016x}", checksum(wram))?;
+        }
+
+        writeln!(self.writer)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+struct Entry {
+    frame: u64,
+    buttons: Buttons,
+    hash: Option<u64>,
+}
+
+pub struct Desync {
+    pub frame: u64,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+pub struct Player {
+    entries: Vec<Entry>,
+    next: usize,
+    buttons: Buttons,
+}
+
+impl Player {
+    // Movies only play back the same way from the same RAM, so the setting
+    // has to match the one the movie was recorded with.
+    pub fn open(path: impl AsRef<Path>, cartridge: &[u8], ram_init: RamInit) -> io::Result<Player> {
+        let text = std::fs::read_to_string(path)?;
+        let mut lines = text.lines();
+
+        let header = lines.next().unwrap_or_default();
+        let expected_header = self::header(cartridge, ram_init);
+
+        if !header.starts_with("snesemu-movie ") {
+            return Err(invalid("not a movie file".into()));
+        }
+
+        if header != expected_header {
+            return Err(invalid(format!(
+                "movie was recorded with different settings ({}, expected {})",
+                header, expected_header
+            )));
+        }
+
+        let mut entries = Vec::new();
+
+        for (i, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let entry = match fields.as_slice() {
+                [] => continue,
+                [frame, port1, _port2] => parse_entry(frame, port1, None),
+                [frame, port1, _port2, hash] => parse_entry(frame, port1, Some(hash)),
+                _ => None,
+            };
+
+            // The header is line 1.
+            entries.push(entry.ok_or_else(|| invalid(format!("invalid entry on line {}", i + 2)))?);
+        }
+
+        Ok(Player {
+            entries,
+            next: 0,
+            buttons: Buttons::empty(),
+        })
+    }
+
+    // Returns the input for the start of a frame, along with details of the
+    // desync if WRAM doesn't match what was recorded.
+    pub fn next(&mut self, frame: u64, wram: &[u8]) -> (Buttons, Option<Desync>) {
+        let mut desync = None;
+
+        while let Some(entry) = self.entries.get(self.next) {
+            if entry.frame > frame {
+                break;
+            }
+
+            self.next += 1;
+            self.buttons = entry.buttons;
+
+            if let Some(expected) = entry.hash {
+                let actual = checksum(wram);
+
+                if entry.frame == frame && expected != actual {
+                    desync = Some(Desync {
+                        frame,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        (self.buttons, desync)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.entries.len()
+    }
+}
+
+fn header(cartridge: &[u8], ram_init: RamInit) -> String {
+    format!(
+        "snesemu-movie {} rom={:016x} ram={}",
+        VERSION,
+        checksum(cartridge),
+        ram_init
+    )
+}
+
+fn parse_entry(frame: &str, port1: &str, hash: Option<&str>) -> Option<Entry> {
+    let hash = match hash {
+        Some(hash) => Some(u64::from_str_radix(hash, 16).ok()?),
+        None => None,
+    };
+
+    Some(Entry {
+        frame: frame.parse().ok()?,
+        buttons: Buttons::from_bits_truncate(u16::from_str_radix(port1, 16).ok()?),
+        hash,
+    })
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,267 @@
+use std::fmt::Write;
+
+use crate::disasm;
+use crate::inst::Instruction;
+
+// How much of an opcode the CPU emulates, going by the decode table, so
+// that this can't drift from what actually runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    Implemented,
+
+    // Runs, but with a known gap.
+    Partial(&'static str),
+
+    Missing,
+}
+
+impl Support {
+    fn name(self) -> &'static str {
+        match self {
+            Support::Implemented => "implemented",
+            Support::Partial(_) => "partial",
+            Support::Missing => "missing",
+        }
+    }
+}
+
+pub fn support(opcode: u8) -> Support {
+    let instruction = Instruction::from_opcode(opcode);
+
+    match (instruction, instruction.known_issue()) {
+        (Instruction::Unknown, _) => Support::Missing,
+        (_, Some(issue)) => Support::Partial(issue),
+        (_, None) => Support::Implemented,
+    }
+}
+
+// Opcodes that aren't implemented but were run, most often first.
+fn missing_hit(counts: &[u64; 256]) -> Vec<(u8, u64)> {
+    let mut hit: Vec<(u8, u64)> = (0..=255u8)
+        .filter(|&opcode| support(opcode) == Support::Missing && counts[opcode as usize] > 0)
+        .map(|opcode| (opcode, counts[opcode as usize]))
+        .collect();
+
+    hit.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    hit
+}
+
+// A 16x16 grid of the opcodes, with the high nibble down the side.
+// Implemented opcodes are in upper case, missing ones in lower case, and
+// partial ones are marked with a *. Given how often each opcode ran,
+// missing ones that ran are marked with a ! and listed underneath.
+pub fn coverage_grid(counts: Option<&[u64; 256]>) -> String {
+    let mut output = String::from("   ");
+
+    for low in 0..16 {
+        let _ = write!(output, "  x{:X}  ", low);
+    }
+
+    let mut partial = Vec::new();
+    let mut totals = [0; 3];
+
+    for opcode in 0..=255u8 {
+        if opcode % 16 == 0 {
+            let _ = write!(output, "\n{:X}x ", opcode >> 4);
+        }
+
+        let mnemonic = disasm::mnemonic(opcode);
+        let ran = counts.is_some_and(|counts| counts[opcode as usize] > 0);
+
+        let (text, marker) = match support(opcode) {
+            Support::Implemented => {
+                totals[0] += 1;
+                (mnemonic.to_uppercase(), ' ')
+            }
+            Support::Partial(issue) => {
+                totals[1] += 1;
+                partial.push((opcode, issue));
+                (mnemonic.to_uppercase(), '*')
+            }
+            Support::Missing => {
+                totals[2] += 1;
+                (mnemonic.to_string(), if ran { '!' } else { ' ' })
+            }
+        };
+
+        let _ = write!(output, "  {}{}", text, marker);
+    }
+
+    let _ = write!(
+        output,
+        "\n\nImplemented: {}, partial: {}, missing: {}",
+        totals[0], totals[1], totals[2]
+    );
+
+    for (opcode, issue) in partial {
+        let _ = write!(
+            output,
+            "\n  ${:02X} {}: {}",
+            opcode,
+            disasm::mnemonic(opcode).to_uppercase(),
+            issue
+        );
+    }
+
+    if let Some(counts) = counts {
+        let ran = counts.iter().filter(|&&count| count > 0).count();
+        let _ = write!(output, "\n\nOpcodes run: {} of 256", ran);
+
+        let hit = missing_hit(counts);
+
+        if !hit.is_empty() {
+            output.push_str("\nMissing opcodes this ROM hit:");
+
+            for (opcode, count) in hit {
+                let _ = write!(
+                    output,
+                    "\n  ${:02X} {:<4} {} times",
+                    opcode,
+                    disasm::mnemonic(opcode),
+                    count
+                );
+            }
+        }
+    }
+
+    output
+}
+
+// The same as the grid, as a single line of JSON. Counts are only included
+// when there are some.
+pub fn coverage_json(counts: Option<&[u64; 256]>) -> String {
+    let mut opcodes = String::new();
+
+    for opcode in 0..=255u8 {
+        if opcode > 0 {
+            opcodes.push(',');
+        }
+
+        let support = support(opcode);
+
+        let _ = write!(
+            opcodes,
+            "{{\"opcode\":{},\"mnemonic\":\"{}\",\"status\":\"{}\"",
+            opcode,
+            disasm::mnemonic(opcode),
+            support.name()
+        );
+
+        if let Support::Partial(issue) = support {
+            let _ = write!(opcodes, ",\"issue\":\"{}\"", issue);
+        }
+
+        if let Some(counts) = counts {
+            let _ = write!(opcodes, ",\"count\":{}", counts[opcode as usize]);
+        }
+
+        opcodes.push('}');
+    }
+
+    let mut output = format!("{{\"opcodes\":[{}]", opcodes);
+
+    if let Some(counts) = counts {
+        let hit: Vec<String> = missing_hit(counts)
+            .iter()
+            .map(|(opcode, count)| format!("{{\"opcode\":{},\"count\":{}}}", opcode, count))
+            .collect();
+
+        let _ = write!(output, ",\"missing_hit\":[{}]", hit.join(","));
+    }
+
+    output.push('}');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_opcodes() {
+        // LDA #imm, CLC, XBA, STA abs, JSR abs
+        for opcode in [0xA9, 0x18, 0xEB, 0x8D, 0x20] {
+            assert_eq!(support(opcode), Support::Implemented, "{:02X}", opcode);
+        }
+
+        // TCD, WDM, STP, BIT #imm, TRB dp
+        for opcode in [0x5B, 0x42, 0xDB, 0x89, 0x14] {
+            assert_eq!(support(opcode), Support::Missing, "{:02X}", opcode);
+        }
+
+        assert_eq!(
+            support(0x9A),
+            Support::Partial("doesn't keep SP in page 1 in emulation mode")
+        );
+        assert!(matches!(support(0x22), Support::Partial(_)));
+        assert!(matches!(support(0x6B), Support::Partial(_)));
+    }
+
+    #[test]
+    fn grid() {
+        let mut counts = [0; 256];
+        counts[0xA9] = 100;
+        counts[0x5B] = 3;
+        counts[0x42] = 7;
+
+        let grid = coverage_grid(Some(&counts));
+        let rows: Vec<&str> = grid.lines().collect();
+
+        // A header, then a row for each high nibble.
+        assert!(rows[0].starts_with("     x0    x1"), "{}", rows[0]);
+        assert!(rows[6].starts_with("5x "), "{}", rows[6]);
+        assert!(rows[6].contains(&format!("{}!", disasm::mnemonic(0x5B))));
+        assert!(rows[11].contains("LDA "), "{}", rows[11]);
+        assert!(rows[10].contains("TXS*"), "{}", rows[10]);
+
+        // Missing opcodes that ran are listed most often first.
+        let hit = grid.split("Missing opcodes this ROM hit:").nth(1).unwrap();
+        let hit: Vec<&str> = hit.lines().skip(1).map(str::trim).collect();
+        assert_eq!(hit.len(), 2);
+        assert!(hit[0].starts_with("$42 "), "{}", hit[0]);
+        assert!(hit[0].ends_with(" 7 times"), "{}", hit[0]);
+        assert!(hit[1].starts_with("$5B "), "{}", hit[1]);
+
+        assert!(grid.contains("Opcodes run: 3 of 256"));
+        assert!(!coverage_grid(None).contains("Opcodes run"));
+    }
+
+    #[test]
+    fn json() {
+        let mut counts = [0; 256];
+        counts[0xA9] = 100;
+        counts[0x5B] = 3;
+
+        let json: serde_json::Value = serde_json::from_str(&coverage_json(Some(&counts))).unwrap();
+        let opcodes = json["opcodes"].as_array().unwrap();
+        assert_eq!(opcodes.len(), 256);
+
+        assert_eq!(opcodes[0xA9]["status"], "implemented");
+        assert_eq!(opcodes[0xA9]["count"], 100);
+        assert_eq!(opcodes[0x5B]["status"], "missing");
+        assert_eq!(opcodes[0x9A]["status"], "partial");
+        assert!(opcodes[0x9A]["issue"].is_string());
+
+        assert_eq!(
+            json["missing_hit"],
+            serde_json::json!([{ "opcode": 0x5B, "count": 3 }])
+        );
+
+        // Without counts there's nothing about what ran.
+        let json: serde_json::Value = serde_json::from_str(&coverage_json(None)).unwrap();
+        assert!(json.get("missing_hit").is_none());
+        assert!(json["opcodes"][0].get("count").is_none());
+
+        // Everything adds up with the grid's totals.
+        let implemented = opcodes
+            .iter()
+            .filter(|opcode| opcode["status"] == "implemented")
+            .count();
+        let grid = coverage_grid(None);
+        assert!(
+            grid.contains(&format!("Implemented: {},", implemented)),
+            "{}",
+            grid
+        );
+    }
+}
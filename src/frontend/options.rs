@@ -0,0 +1,923 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cheat::Cheat;
+use crate::debugger::{self, Condition};
+use crate::frontend::stack_guard::GuardRange;
+use crate::input::Port2;
+use crate::mmu::{MapMode, RamInit, RomWritePolicy, Watchpoint};
+use crate::trace::TraceFormat;
+
+pub const USAGE: &str = "\
+usage: snesemu <rom> [options]
+       snesemu coverage [<rom> [options]] [--json]
+       snesemu batch <dir> [--run-for <n>[f]] [--max-seconds <n>] [--json]
+
+coverage prints a grid of which opcodes the CPU implements, partly implements or
+is missing. Given a ROM, it runs it first and adds which opcodes it ran and how
+often, listing the missing ones it hit, most frequent first (pair with
+--ignore-unknown to keep going past them). It's the same as --opcodes.
+
+batch runs every .sfc and .smc file in dir without interaction, for 600 frames
+unless --run-for or --max-seconds says otherwise, and prints a CSV report of how
+each run ended, worst first.
+
+options:
+    --info                    print the ROM's header and mapping details, then exit
+    --json                    print --info, the coverage grid or the batch report as JSON
+    --opcodes                 the same as the coverage subcommand
+    --log <path>              where to write the trace log, which is compressed if the
+                              name ends in .gz (default: output.log)
+    --max-instructions <n>    stop after executing n instructions
+    --max-seconds <n>         stop after running for n seconds, not counting time paused in
+                              the debugger
+    --run-until <bank:addr>   run without interaction until execution reaches addr, then
+                              print a JSON summary
+    --run-for <n>[f]          run without interaction for n instructions (or n frames
+                              with the f suffix), then print a JSON summary
+    --expect <bank:addr>=<value>
+                              after --run-until/--run-for, check that memory at addr holds
+                              value, in hex (can be repeated)
+    --state-at <n>            run without interaction until n instructions have run, write
+                              a save state to the --save-state path, then exit
+    --check-determinism       run the --run-for run twice, and check that the machine is
+                              in the same state at the end of every frame
+    --frame-hash <n>          run n frames without interaction and print a hash of each
+                              one's pixels, then exit
+    --frame-hash-expect <path>
+                              check the --frame-hash hashes against a file of them, and
+                              write the frames that differ to the --dump-frames directory
+    --map-mode <mode>         lorom, hirom or auto (default: auto)
+    --ram-init <fill>         what RAM holds at power on: zero, ff, 55aa, or random with
+                              an optional seed, e.g. random:1234 (default: zero)
+    --cheat <code>            apply a Game Genie (XXXX-XXXX) or Pro Action Replay
+                              (AAAAAAVV) code (can be repeated)
+    --multitap                plug a multitap into the second controller port
+    --rom-writes <policy>     what to do about writes to ROM: ignore, warn once for each
+                              instruction that does it, or strict to stop (default: warn)
+    --trace-mode <mode>       ring keeps the last instructions in memory, stream writes
+                              every instruction as it runs, off skips tracing for speed
+                              (default: ring)
+    --trace-len <n>           number of instructions kept in ring mode (default: 200)
+    --trace-format <format>   default, bsnes or json, which has one object per line (json
+                              needs the trace-json feature, default: default)
+    --trace-gzip              compress the trace log with gzip, whatever it's called
+    --trace-range <bank:addr>-<bank:addr>
+                              only log instructions inside the range, e.g. 80:0000-80:FFFF
+                              for a whole bank (can be repeated)
+    --trace-skip <bank:addr>  don't log the subroutine at addr, from the call to it up to
+                              its matching return (can be repeated)
+    --compare-log <path>      compare the finished log against a reference log and print
+                              the differences (either can be gzipped)
+    --compare <path>          check every instruction against a bsnes-plus trace, which
+                              can be gzipped, as it runs, and stop at the first one where
+                              the registers differ
+    --compare-context <n>     instructions shown before a --compare difference (default: 10)
+    --dump-frames <dir>       write frames to dir as PNGs
+    --dump-interval <n>       only dump every nth frame (default: 1)
+    --break <bank:addr>[ if <condition>]
+                              stop when execution reaches addr, optionally only when
+                              the condition holds, e.g. 'C3:0123 if A==0x4F' (can be
+                              repeated)
+    --watch <addr>[-end][:r|w|rw]
+                              report accesses to an address range (can be repeated)
+    --debug                   pause before execution and accept debugger commands
+    --tui                     the same as --debug, but with a full-screen debugger (needs
+                              the tui feature)
+    --spc-trace <path>        log every SPC700 instruction to path
+    --io-log <path>           log every access to the $21xx, $40xx, $42xx and $43xx
+                              registers to path
+    --io-ring <n>             keep the last n register accesses, for the debugger's io
+                              command
+    --events <n>              keep the last n hardware events (NMIs, DMAs, APU port writes
+                              and so on), for the debugger's ev command
+    --events-out <path>       write the kept events to path on exit, as JSON lines if the
+                              name ends in .json (keeps the last 4096 without --events)
+    --write-ring <n>          keep the last n writes to memory (4096 is plenty), for the
+                              debugger's writes command and for crash reports
+    --load-state <path>       restore a save state before running
+    --sram <path>             where to keep the battery save (default: the ROM's path with
+                              .srm, except in headless runs, which don't save by default)
+    --sram-interval <n>       write the battery save at most every n seconds while running
+                              (default: 2)
+    --no-sram                 don't load or write the battery save
+    --record <path>           record the controller input to a movie file
+    --play <path>             take the controller input from a movie file
+    --cdl <path>              log which bytes of the ROM are run as code or read as data,
+                              and write the log to path on exit
+    --ignore-unknown          skip over unknown opcodes instead of stopping, and list them
+                              on exit
+    --script <path>           run a Rhai script with hooks into the emulator (needs the
+                              scripting feature)
+    --symbols <path>          label addresses in the trace using a WLA-DX or bsnes-plus
+                              symbol file
+    --profile <path>          count how often each address and opcode runs, and write the
+                              hottest to path on exit
+    --idle-loops <n>          report short loops that repeat n times without writing much,
+                              along with what they seem to be waiting for
+    --stack-depth <n>         warn when the stack grows more than n bytes below where TXS
+                              put it
+    --stack-guard <addr>[-end]|dp
+                              warn when a push lands in a range of bank 0, or in the
+                              direct page (can be repeated)
+    --rewind <n>              keep n snapshots of the machine for the debugger's rewind
+                              command (each is about 300KB)
+    --rewind-interval <n>     take a rewind snapshot every n frames (default: 30)
+    --save-state <path>       write a save state on exit
+    --dump-ram <addr:len:path>
+                              write len bytes of memory from addr to path on exit, without
+                              triggering any I/O side effects (can be repeated)
+    --dump-palette <path>     write CGRAM to path on exit, as a 16x16 PNG with one pixel
+                              per color
+    --window                  display the output in a window
+    --audio                   play the audio output
+
+exit status:
+    0    finished normally, or reached the --run-until address or --state-at
+         instruction
+    1    couldn't read or write a file, such as the --record movie
+    2    invalid arguments
+    3    stopped at a breakpoint
+    4    hit the --run-for, --max-instructions or --max-seconds limit
+    5    stopped on an unknown opcode during a --run-until/--run-for run
+    6    an --expect check failed
+    7    the log didn't match --compare-log
+    8    execution diverged from --compare
+    9    the --check-determinism runs differed
+    10   wrote to ROM with --rom-writes strict
+    11   a frame didn't match --frame-hash-expect
+    130  stopped with Ctrl+C (which pauses instead with --debug)";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TraceMode {
+    Ring,
+    Stream,
+    Off,
+}
+
+// Everything the command line asks for.
+pub struct Options {
+    pub rom: String,
+    pub batch: Option<String>,
+    pub info: bool,
+    pub json: bool,
+    pub opcodes: bool,
+    pub log: String,
+    pub max_instructions: Option<u64>,
+    pub max_frames: Option<u64>,
+    pub max_time: Option<Duration>,
+    pub run_until: Option<u32>,
+    pub expectations: Vec<(u32, u8)>,
+    pub state_at: Option<u64>,
+    pub check_determinism: bool,
+    pub frame_hashes: Option<u64>,
+    pub frame_hash_expect: Option<String>,
+
+    // Set by the run-until/run-for options, which report their result as
+    // JSON and an exit code rather than through the logs.
+    pub headless: bool,
+
+    pub map_mode: Option<MapMode>,
+    pub ram_init: RamInit,
+    pub cheats: Vec<Cheat>,
+    pub multitap: bool,
+    pub rom_writes: RomWritePolicy,
+    pub breakpoints: HashMap<u32, Option<Condition>>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub trace_mode: TraceMode,
+    pub trace_format: TraceFormat,
+    pub trace_len: usize,
+    pub trace_gzip: bool,
+    pub trace_ranges: Vec<(u32, u32)>,
+    pub trace_skipped: HashSet<u32>,
+    pub compare_log: Option<String>,
+    pub compare: Option<String>,
+    pub compare_context: usize,
+    pub dump_frames: Option<String>,
+    pub dump_interval: u64,
+    pub spc_trace: Option<String>,
+    pub io_log: Option<String>,
+    pub io_ring: Option<usize>,
+    pub events: Option<usize>,
+    pub events_out: Option<String>,
+    pub write_ring: Option<usize>,
+    pub load_state: Option<String>,
+    pub sram: Option<String>,
+    pub sram_interval: u64,
+    pub no_sram: bool,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub rewind: Option<usize>,
+    pub profile: Option<String>,
+    pub idle_loops: Option<u64>,
+    pub stack_depth: Option<u16>,
+    pub stack_guards: Vec<GuardRange>,
+    pub cdl: Option<String>,
+    pub symbols: Option<String>,
+    pub script: Option<String>,
+    pub ignore_unknown: bool,
+    pub rewind_interval: u64,
+    pub save_state: Option<String>,
+    pub ram_dumps: Vec<(u32, usize, String)>,
+    pub dump_palette: Option<String>,
+    pub debug: bool,
+    pub tui: bool,
+    pub show_window: bool,
+    pub play_audio: bool,
+}
+
+impl Options {
+    // Parses the arguments, without the program name.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut rom = None;
+
+        let mut options = Options {
+            rom: String::new(),
+            batch: None,
+            info: false,
+            json: false,
+            opcodes: false,
+            log: "output.log".into(),
+            max_instructions: None,
+            max_time: None,
+            max_frames: None,
+            run_until: None,
+            expectations: Vec::new(),
+            state_at: None,
+            check_determinism: false,
+            frame_hashes: None,
+            frame_hash_expect: None,
+            headless: false,
+            map_mode: None,
+            ram_init: RamInit::Zero,
+            cheats: Vec::new(),
+            multitap: false,
+            rom_writes: RomWritePolicy::Warn,
+            breakpoints: HashMap::new(),
+            watchpoints: Vec::new(),
+            trace_mode: TraceMode::Ring,
+            trace_format: TraceFormat::Default,
+            trace_len: 200,
+            trace_gzip: false,
+            trace_ranges: Vec::new(),
+            trace_skipped: HashSet::new(),
+            compare_log: None,
+            compare: None,
+            compare_context: 10,
+            dump_frames: None,
+            dump_interval: 1,
+            spc_trace: None,
+            io_log: None,
+            io_ring: None,
+            events: None,
+            events_out: None,
+            write_ring: None,
+            load_state: None,
+            sram: None,
+            sram_interval: 2,
+            no_sram: false,
+            record: None,
+            play: None,
+            rewind: None,
+            profile: None,
+            idle_loops: None,
+            stack_depth: None,
+            stack_guards: Vec::new(),
+            cdl: None,
+            symbols: None,
+            script: None,
+            ignore_unknown: false,
+            rewind_interval: 30,
+            save_state: None,
+            ram_dumps: Vec::new(),
+            dump_palette: None,
+            debug: false,
+            tui: false,
+            show_window: false,
+            play_audio: false,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} expects a value", arg));
+
+            match arg.as_str() {
+                "--info" => options.info = true,
+                "--json" => options.json = true,
+                "--opcodes" => options.opcodes = true,
+                "--log" => options.log = value()?,
+                "--max-instructions" => {
+                    options.max_instructions = Some(parse_number(&arg, value()?)?)
+                }
+                "--max-seconds" => {
+                    let value = value()?;
+
+                    options.max_time = parse_number(&arg, value.clone())
+                        .ok()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .map(Some)
+                        .ok_or_else(|| {
+                            format!("{} expects a number of seconds, got {}", arg, value)
+                        })?;
+                }
+                "--run-until" => {
+                    options.run_until = Some(parse_address(&arg, value()?)?);
+                    options.headless = true;
+                }
+                "--run-for" => {
+                    let value = value()?;
+
+                    match value.strip_suffix('f') {
+                        Some(frames) => {
+                            options.max_frames = Some(parse_number(&arg, frames.into())?)
+                        }
+                        None => options.max_instructions = Some(parse_number(&arg, value)?),
+                    }
+
+                    options.headless = true;
+                }
+                "--state-at" => {
+                    let index = parse_number(&arg, value()?)?;
+
+                    options.state_at = Some(index);
+                    options.max_instructions = Some(index);
+                    options.headless = true;
+                }
+                "--check-determinism" => options.check_determinism = true,
+                "--frame-hash" => options.frame_hashes = Some(parse_number(&arg, value()?)?),
+                "--frame-hash-expect" => options.frame_hash_expect = Some(value()?),
+                "--expect" => options
+                    .expectations
+                    .push(parse_expectation(&arg, value()?)?),
+                "--map-mode" => {
+                    options.map_mode = match value()?.as_str() {
+                        "lorom" => Some(MapMode::LoRom),
+                        "hirom" => Some(MapMode::HiRom),
+                        "auto" => None,
+                        mode => return Err(format!("unknown map mode: {}", mode)),
+                    }
+                }
+                "--ram-init" => options.ram_init = parse_ram_init(&arg, value()?)?,
+                "--cheat" => options
+                    .cheats
+                    .push(Cheat::parse(&value()?).map_err(|e| format!("{}: {}", arg, e))?),
+                "--multitap" => options.multitap = true,
+                "--rom-writes" => {
+                    options.rom_writes = match value()?.as_str() {
+                        "ignore" => RomWritePolicy::Ignore,
+                        "warn" => RomWritePolicy::Warn,
+                        "strict" => RomWritePolicy::Strict,
+                        policy => return Err(format!("unknown ROM write policy: {}", policy)),
+                    }
+                }
+                "--break" => {
+                    let (addr, condition) = debugger::parse_breakpoint(&value()?)
+                        .map_err(|e| format!("{}: {}", arg, e))?;
+
+                    options.breakpoints.insert(addr, condition);
+                }
+                "--watch" => options.watchpoints.push(parse_watchpoint(&arg, value()?)?),
+                "--trace-mode" => {
+                    options.trace_mode = match value()?.as_str() {
+                        "ring" => TraceMode::Ring,
+                        "stream" => TraceMode::Stream,
+                        "off" => TraceMode::Off,
+                        mode => return Err(format!("unknown trace mode: {}", mode)),
+                    }
+                }
+                "--trace-format" => {
+                    options.trace_format = match value()?.as_str() {
+                        "default" => TraceFormat::Default,
+                        "bsnes" => TraceFormat::Bsnes,
+                        #[cfg(feature = "trace-json")]
+                        "json" => TraceFormat::Json,
+                        #[cfg(not(feature = "trace-json"))]
+                        "json" => {
+                            return Err("--trace-format json requires the trace-json feature".into())
+                        }
+                        format => return Err(format!("unknown trace format: {}", format)),
+                    }
+                }
+                "--trace-gzip" => options.trace_gzip = true,
+                "--trace-range" => options.trace_ranges.push(parse_range(&arg, &value()?)?),
+                "--trace-skip" => {
+                    options.trace_skipped.insert(parse_address(&arg, value()?)?);
+                }
+                "--compare-log" => options.compare_log = Some(value()?),
+                "--compare" => options.compare = Some(value()?),
+                "--compare-context" => options.compare_context = parse_number(&arg, value()?)?,
+                "--trace-len" => options.trace_len = parse_number(&arg, value()?)?,
+                "--dump-frames" => options.dump_frames = Some(value()?),
+                "--dump-interval" => options.dump_interval = parse_number(&arg, value()?)?,
+                "--spc-trace" => options.spc_trace = Some(value()?),
+                "--io-log" => options.io_log = Some(value()?),
+                "--io-ring" => options.io_ring = Some(parse_number(&arg, value()?)?),
+                "--events" => options.events = Some(parse_number(&arg, value()?)?),
+                "--events-out" => options.events_out = Some(value()?),
+                "--write-ring" => options.write_ring = Some(parse_number(&arg, value()?)?),
+                "--load-state" => options.load_state = Some(value()?),
+                "--sram" => options.sram = Some(value()?),
+                "--sram-interval" => options.sram_interval = parse_number(&arg, value()?)?,
+                "--no-sram" => options.no_sram = true,
+                "--record" => options.record = Some(value()?),
+                "--play" => options.play = Some(value()?),
+                "--cdl" => options.cdl = Some(value()?),
+                "--symbols" => options.symbols = Some(value()?),
+                "--script" => options.script = Some(value()?),
+                "--profile" => options.profile = Some(value()?),
+                "--idle-loops" => options.idle_loops = Some(parse_number(&arg, value()?)?),
+                "--stack-depth" => options.stack_depth = Some(parse_number(&arg, value()?)?),
+                "--stack-guard" => options
+                    .stack_guards
+                    .push(parse_stack_guard(&arg, value()?)?),
+                "--rewind" => options.rewind = Some(parse_number(&arg, value()?)?),
+                "--rewind-interval" => options.rewind_interval = parse_number(&arg, value()?)?,
+                "--save-state" => options.save_state = Some(value()?),
+                "--dump-ram" => options.ram_dumps.push(parse_ram_dump(&arg, value()?)?),
+                "--dump-palette" => options.dump_palette = Some(value()?),
+                "--ignore-unknown" => options.ignore_unknown = true,
+                "--debug" => options.debug = true,
+                "--tui" => {
+                    options.debug = true;
+                    options.tui = true;
+                }
+                "--window" => options.show_window = true,
+                "--audio" => options.play_audio = true,
+                "batch" if rom.is_none() && options.batch.is_none() => {
+                    options.batch = Some(value()?)
+                }
+                "coverage" if rom.is_none() && !options.opcodes && options.batch.is_none() => {
+                    options.opcodes = true
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+                _ if rom.is_none() => rom = Some(arg),
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+
+        if options.record.is_some() && options.play.is_some() {
+            return Err("--record and --play can't be used together".into());
+        }
+
+        if options.json && !options.info && !options.opcodes && options.batch.is_none() {
+            return Err("--json requires --info, coverage or batch".into());
+        }
+
+        if !options.expectations.is_empty() && !options.headless {
+            return Err("--expect requires --run-until or --run-for".into());
+        }
+
+        if options.script.is_some() && !cfg!(feature = "scripting") {
+            return Err("--script requires the scripting feature".into());
+        }
+
+        if options.tui && !cfg!(feature = "tui") {
+            return Err("--tui requires the tui feature".into());
+        }
+
+        if options.io_log.is_some() && options.io_ring.is_some() {
+            return Err("--io-log and --io-ring can't be used together".into());
+        }
+
+        if options.state_at.is_some() && options.save_state.is_none() {
+            return Err("--state-at requires --save-state".into());
+        }
+
+        if options.state_at.is_some()
+            && (options.run_until.is_some() || options.max_frames.is_some())
+        {
+            return Err("--state-at can't be used with --run-until or --run-for".into());
+        }
+
+        if options.check_determinism
+            && options.max_instructions.is_none()
+            && options.max_frames.is_none()
+        {
+            return Err("--check-determinism requires --run-for".into());
+        }
+
+        if options.check_determinism && options.record.is_some() {
+            return Err("--check-determinism can't be used with --record".into());
+        }
+
+        if options.frame_hash_expect.is_some() && options.frame_hashes.is_none() {
+            return Err("--frame-hash-expect requires --frame-hash".into());
+        }
+
+        if options.frame_hashes.is_some() && (options.headless || options.check_determinism) {
+            return Err(
+                "--frame-hash can't be used with --run-until, --run-for or --check-determinism"
+                    .into(),
+            );
+        }
+
+        if options.headless && options.debug {
+            return Err("--run-until and --run-for can't be used with --debug".into());
+        }
+
+        // Logs named like gzip files get compressed without having to ask.
+        if options.log.ends_with(".gz") {
+            options.trace_gzip = true;
+        }
+
+        if options.trace_gzip && !cfg!(feature = "trace-gzip") {
+            return Err("compressing the log requires the trace-gzip feature".into());
+        }
+
+        if options.show_window && !cfg!(feature = "window") {
+            return Err("--window requires the window feature".into());
+        }
+
+        if options.play_audio && !cfg!(feature = "audio") {
+            return Err("--audio requires the audio feature".into());
+        }
+
+        if (options.load_state.is_some() || options.save_state.is_some())
+            && !cfg!(feature = "savestate")
+        {
+            return Err("save states require the savestate feature".into());
+        }
+
+        // The coverage grid on its own doesn't need a ROM, and batch runs
+        // find their own.
+        options.rom = match rom {
+            Some(_) if options.batch.is_some() => {
+                return Err("batch takes a directory rather than a ROM".into())
+            }
+            Some(rom) => rom,
+            None if options.opcodes || options.batch.is_some() => String::new(),
+            None => return Err("no ROM path given".into()),
+        };
+
+        Ok(options)
+    }
+
+    pub fn sram_path(&self) -> Option<PathBuf> {
+        if self.no_sram {
+            None
+        } else if let Some(path) = &self.sram {
+            Some(path.into())
+        } else if self.headless {
+            None
+        } else {
+            Some(Path::new(&self.rom).with_extension("srm"))
+        }
+    }
+
+    pub fn port2(&self) -> Port2 {
+        if self.multitap {
+            Port2::Multitap
+        } else {
+            Port2::Joypad
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(arg: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", arg, value))
+}
+
+// Random RAM without a seed gets one from the clock, which is printed so
+// that the run can be repeated.
+fn parse_ram_init(arg: &str, value: String) -> Result<RamInit, String> {
+    let init = match value.as_str() {
+        "zero" => RamInit::Zero,
+        "ff" => RamInit::Ff,
+        "55aa" => RamInit::Pattern,
+        "random" => RamInit::Random(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64),
+        ),
+        _ => match value.strip_prefix("random:") {
+            Some(seed) => RamInit::Random(
+                seed.parse()
+                    .map_err(|_| format!("invalid seed for {}: {}", arg, seed))?,
+            ),
+            None => return Err(format!("invalid value for {}: {}", arg, value)),
+        },
+    };
+
+    Ok(init)
+}
+
+fn parse_address(arg: &str, value: String) -> Result<u32, String> {
+    debugger::parse_address(&value)
+        .ok_or_else(|| format!("{} expects an address like 00:8000, got {}", arg, value))
+}
+
+fn parse_expectation(arg: &str, value: String) -> Result<(u32, u8), String> {
+    let (addr, expected) = value
+        .split_once('=')
+        .ok_or_else(|| format!("{} expects addr=value, got {}", arg, value))?;
+
+    let expected = u8::from_str_radix(expected.trim_start_matches('$'), 16)
+        .map_err(|_| format!("{} expects a hex byte, got {}", arg, expected))?;
+
+    Ok((parse_address(arg, addr.into())?, expected))
+}
+
+fn parse_range(arg: &str, value: &str) -> Result<(u32, u32), String> {
+    match value.split_once('-') {
+        Some((start, end)) => Ok((
+            parse_address(arg, start.into())?,
+            parse_address(arg, end.into())?,
+        )),
+        None => {
+            let addr = parse_address(arg, value.into())?;
+            Ok((addr, addr))
+        }
+    }
+}
+
+// The stack is always in bank 0, so ranges elsewhere are only accepted
+// where they mirror it, i.e. the first 8KB of WRAM.
+fn parse_stack_guard(arg: &str, value: String) -> Result<GuardRange, String> {
+    if value == "dp" {
+        return Ok(GuardRange::DirectPage);
+    }
+
+    let (start, end) = parse_range(arg, &value)?;
+
+    let offset = |addr: u32| match (addr >> 16) as u8 {
+        0x00 => Some(addr as u16),
+        0x01..=0x3F | 0x7E | 0x80..=0xBF if addr & 0xFFFF < 0x2000 => Some(addr as u16),
+        _ => None,
+    };
+
+    match (offset(start), offset(end)) {
+        (Some(start), Some(end)) => Ok(GuardRange::Range(start, end)),
+        _ => Err(format!(
+            "{} expects a range in bank 0 or the first 8KB of WRAM, got {}",
+            arg, value
+        )),
+    }
+}
+
+// The address can be in bank:addr form, so the length and path are split
+// off from the right.
+fn parse_ram_dump(arg: &str, value: String) -> Result<(u32, usize, String), String> {
+    let mut parts = value.rsplitn(3, ':');
+
+    let (Some(path), Some(len), Some(addr)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!("{} expects addr:len:path, got {}", arg, value));
+    };
+
+    Ok((
+        parse_address(arg, addr.into())?,
+        parse_number(arg, len.into())?,
+        path.into(),
+    ))
+}
+
+fn parse_watchpoint(arg: &str, value: String) -> Result<Watchpoint, String> {
+    let (range, read, write) = match value.rsplit_once(':') {
+        Some((range, "r")) => (range, true, false),
+        Some((range, "w")) => (range, false, true),
+        Some((range, "rw")) => (range, true, true),
+        _ => (value.as_str(), true, true),
+    };
+
+    let (start, end) = parse_range(arg, range)?;
+
+    Ok(Watchpoint {
+        start,
+        end,
+        read,
+        write,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&["game.sfc"]).unwrap();
+
+        assert_eq!(options.rom, "game.sfc");
+        assert_eq!(options.log, "output.log");
+        assert_eq!(options.max_instructions, None);
+        assert_eq!(options.map_mode, None);
+        assert_eq!(options.trace_len, 200);
+        assert!(options.trace_mode == TraceMode::Ring);
+        assert!(!options.headless);
+    }
+
+    #[test]
+    fn flags_before_and_after_rom() {
+        let options = parse(&[
+            "--log",
+            "trace.log",
+            "game.sfc",
+            "--max-instructions",
+            "5000",
+            "--map-mode",
+            "hirom",
+            "--trace-len",
+            "64",
+        ])
+        .unwrap();
+
+        assert_eq!(options.rom, "game.sfc");
+        assert_eq!(options.log, "trace.log");
+        assert_eq!(options.max_instructions, Some(5000));
+        assert_eq!(options.map_mode, Some(MapMode::HiRom));
+        assert_eq!(options.trace_len, 64);
+    }
+
+    #[test]
+    fn map_modes() {
+        let map_mode = |value| parse(&["game.sfc", "--map-mode", value]).map(|o| o.map_mode);
+
+        assert_eq!(map_mode("lorom"), Ok(Some(MapMode::LoRom)));
+        assert_eq!(map_mode("hirom"), Ok(Some(MapMode::HiRom)));
+        assert_eq!(map_mode("auto"), Ok(None));
+        assert!(map_mode("exhirom").is_err());
+    }
+
+    #[test]
+    fn run_for_frames_or_instructions() {
+        let options = parse(&["game.sfc", "--run-for", "30f"]).unwrap();
+        assert_eq!(options.max_frames, Some(30));
+        assert!(options.headless);
+
+        let options = parse(&["game.sfc", "--run-for", "1000"]).unwrap();
+        assert_eq!(options.max_instructions, Some(1000));
+        assert!(options.headless);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |args: &[&str]| parse(args).err().unwrap();
+
+        assert_eq!(error(&[]), "no ROM path given");
+        assert_eq!(error(&["game.sfc", "--bogus"]), "unknown option: --bogus");
+        assert_eq!(error(&["a.sfc", "b.sfc"]), "unexpected argument: b.sfc");
+        assert_eq!(error(&["game.sfc", "--log"]), "--log expects a value");
+        assert_eq!(
+            error(&["game.sfc", "--max-instructions", "lots"]),
+            "--max-instructions expects a number, got lots"
+        );
+        assert_eq!(
+            error(&["game.sfc", "--expect", "7E:0000=01"]),
+            "--expect requires --run-until or --run-for"
+        );
+    }
+
+    #[test]
+    fn ram_init() {
+        let ram_init = |value| parse(&["game.sfc", "--ram-init", value]).map(|o| o.ram_init);
+
+        assert_eq!(parse(&["game.sfc"]).unwrap().ram_init, RamInit::Zero);
+        assert_eq!(ram_init("zero"), Ok(RamInit::Zero));
+        assert_eq!(ram_init("ff"), Ok(RamInit::Ff));
+        assert_eq!(ram_init("55aa"), Ok(RamInit::Pattern));
+        assert_eq!(ram_init("random:1234"), Ok(RamInit::Random(1234)));
+        assert!(matches!(ram_init("random"), Ok(RamInit::Random(_))));
+
+        assert_eq!(
+            ram_init("random:abc"),
+            Err("invalid seed for --ram-init: abc".into())
+        );
+        assert_eq!(
+            ram_init("aa55"),
+            Err("invalid value for --ram-init: aa55".into())
+        );
+
+        // Written the same way as it's given, for movies and reports.
+        for value in ["zero", "ff", "55aa", "random:1234"] {
+            assert_eq!(ram_init(value).unwrap().to_string(), value);
+        }
+    }
+
+    #[test]
+    fn stack_guards() {
+        let options = parse(&[
+            "game.sfc",
+            "--stack-depth",
+            "256",
+            "--stack-guard",
+            "dp",
+            "--stack-guard",
+            "7E:0000-7E:00FF",
+            "--stack-guard",
+            "00:1F00",
+        ])
+        .unwrap();
+
+        assert_eq!(options.stack_depth, Some(256));
+        assert_eq!(
+            options.stack_guards,
+            [
+                GuardRange::DirectPage,
+                GuardRange::Range(0x0000, 0x00FF),
+                GuardRange::Range(0x1F00, 0x1F00),
+            ]
+        );
+
+        assert_eq!(
+            parse(&["game.sfc", "--stack-guard", "7E:2000"])
+                .err()
+                .unwrap(),
+            "--stack-guard expects a range in bank 0 or the first 8KB of WRAM, got 7E:2000"
+        );
+    }
+
+    #[test]
+    fn coverage_and_batch_need_no_rom() {
+        assert_eq!(parse(&["--opcodes"]).unwrap().rom, "");
+        assert_eq!(parse(&["coverage", "--json"]).unwrap().rom, "");
+
+        let options = parse(&["coverage", "game.sfc"]).unwrap();
+        assert!(options.opcodes);
+        assert_eq!(options.rom, "game.sfc");
+        assert!(parse(&["game.sfc", "coverage"]).is_err());
+
+        let options = parse(&["batch", "roms"]).unwrap();
+        assert_eq!(options.batch.as_deref(), Some("roms"));
+        assert!(parse(&["batch", "roms", "game.sfc"]).is_err());
+    }
+
+    #[test]
+    fn sram_path_follows_rom() {
+        let options = parse(&["roms/game.sfc"]).unwrap();
+        assert_eq!(options.sram_path(), Some(PathBuf::from("roms/game.srm")));
+
+        let options = parse(&["roms/game.sfc", "--no-sram"]).unwrap();
+        assert_eq!(options.sram_path(), None);
+
+        // Headless runs don't save unless asked to.
+        let options = parse(&["roms/game.sfc", "--run-for", "10f"]).unwrap();
+        assert_eq!(options.sram_path(), None);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let options = parse(&[
+            "game.sfc",
+            "--watch",
+            "7E:0100",
+            "--watch",
+            "7E:0200-7E:02FF:w",
+            "--watch",
+            "00:2140:r",
+        ])
+        .unwrap();
+
+        let watchpoints: Vec<_> = options
+            .watchpoints
+            .iter()
+            .map(|w| (w.start, w.end, w.read, w.write))
+            .collect();
+
+        assert_eq!(
+            watchpoints,
+            [
+                (0x7E_0100, 0x7E_0100, true, true),
+                (0x7E_0200, 0x7E_02FF, false, true),
+                (0x00_2140, 0x00_2140, true, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn trace_filters() {
+        let options = parse(&[
+            "game.sfc",
+            "--trace-range",
+            "80:0000-80:FFFF",
+            "--trace-range",
+            "00:8000-00:80FF",
+            "--trace-skip",
+            "00:9000",
+            "--trace-skip",
+            "01:8000",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            options.trace_ranges,
+            [(0x80_0000, 0x80_FFFF), (0x00_8000, 0x00_80FF)]
+        );
+        assert_eq!(
+            options.trace_skipped,
+            [0x00_9000, 0x01_8000].into_iter().collect()
+        );
+
+        // A single address is a range of one.
+        let options = parse(&["game.sfc", "--trace-range", "00:8000"]).unwrap();
+        assert_eq!(options.trace_ranges, [(0x00_8000, 0x00_8000)]);
+
+        assert!(parse(&["game.sfc", "--trace-range", "00:8000-"]).is_err());
+        assert!(parse(&["game.sfc", "--trace-skip", "nowhere"]).is_err());
+    }
+}
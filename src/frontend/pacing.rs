@@ -0,0 +1,85 @@
+// Frame pacing for the window, kept apart from it so that it builds (and
+// can be tested) without the window feature.
+
+use std::time::{Duration, Instant};
+
+// Returns how long to sleep for, and the deadline for the frame after.
+//
+// Deadlines advance by a fixed amount rather than being measured from when
+// the sleep finished, so that oversleeping doesn't accumulate into drift. If
+// we've fallen more than a frame behind, we resync rather than trying to run
+// a burst of frames to catch up.
+pub fn pace(deadline: Instant, now: Instant, frame_time: Duration) -> (Duration, Instant) {
+    if now > deadline + frame_time {
+        (Duration::ZERO, now + frame_time)
+    } else {
+        (
+            deadline.saturating_duration_since(now),
+            deadline + frame_time,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    #[test]
+    fn sleeps_until_deadline() {
+        let start = Instant::now();
+        let deadline = start + FRAME;
+
+        let (sleep, next) = pace(deadline, start + Duration::from_millis(10), FRAME);
+
+        assert_eq!(sleep, Duration::from_millis(6));
+        assert_eq!(next, deadline + FRAME);
+    }
+
+    #[test]
+    fn late_frames_dont_sleep() {
+        let start = Instant::now();
+        let deadline = start + FRAME;
+
+        let (sleep, next) = pace(deadline, deadline + Duration::from_millis(5), FRAME);
+
+        assert_eq!(sleep, Duration::ZERO);
+        assert_eq!(next, deadline + FRAME);
+    }
+
+    // Deadlines move on by exactly a frame each time, however long each
+    // frame took, so that the rate doesn't drift.
+    #[test]
+    fn no_drift() {
+        let start = Instant::now();
+        let mut deadline = start + FRAME;
+
+        for frame in 1..=600u32 {
+            // Alternate between fast and slow frames, both within budget.
+            let work = Duration::from_millis(if frame % 2 == 0 { 2 } else { 15 });
+            let now = deadline - FRAME + work;
+
+            let (sleep, next) = pace(deadline, now, FRAME);
+            assert_eq!(now + sleep, deadline);
+
+            deadline = next;
+        }
+
+        assert_eq!(deadline, start + FRAME * 601);
+    }
+
+    // Falling more than a frame behind starts again from now, rather than
+    // running frames back to back to catch up.
+    #[test]
+    fn resyncs_when_far_behind() {
+        let start = Instant::now();
+        let deadline = start + FRAME;
+        let now = deadline + FRAME * 3;
+
+        let (sleep, next) = pace(deadline, now, FRAME);
+
+        assert_eq!(sleep, Duration::ZERO);
+        assert_eq!(next, now + FRAME);
+    }
+}
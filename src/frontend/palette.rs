@@ -0,0 +1,84 @@
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+use super::frame_dump::write_png;
+use crate::ppu::color::snes_to_rgb;
+
+// Lists CGRAM as 16 rows of 16 colors, one palette of a 4bpp layer per row,
+// as raw 15-bit BGR values.
+pub fn format_palette(cgram: &[u16]) -> String {
+    let mut output = String::new();
+
+    for (row, colors) in cgram.chunks(16).enumerate() {
+        let _ = write!(output, "{:02X}:", row * 16);
+
+        for color in colors {
+            let _ = write!(output, " {:04X}", color);
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+// Writes CGRAM as a 16x16 image with one pixel per color, laid out the same
+// way as the text version.
+pub fn write_palette_png(path: impl AsRef<Path>, cgram: &[u16]) -> io::Result<()> {
+    let pixels: Vec<u8> = cgram
+        .iter()
+        .flat_map(|&color| {
+            let [r, g, b] = snes_to_rgb(color);
+            [r, g, b, 0xFF]
+        })
+        .collect();
+
+    write_png(path, &pixels, 16, cgram.len() as u32 / 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cgram() -> Vec<u16> {
+        (0..256).map(|i| (i * 0x0421) as u16 & 0x7FFF).collect()
+    }
+
+    #[test]
+    fn text_has_a_row_per_palette() {
+        let text = format_palette(&cgram());
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 16);
+        assert_eq!(
+            lines[0],
+            "00: 0000 0421 0842 0C63 1084 14A5 18C6 1CE7 2108 2529 294A 2D6B 318C 35AD 39CE 3DEF"
+        );
+        assert_eq!(
+            lines[15],
+            "F0: 5EF0 6311 6732 6B53 6F74 7395 77B6 7BD7 7FF8 0419 083A 0C5B 107C 149D 18BE 1CDF"
+        );
+    }
+
+    #[cfg(feature = "frame-dump")]
+    #[test]
+    fn png_is_a_swatch_per_color() {
+        let path = std::env::temp_dir().join(format!("snesemu-palette-{}.png", std::process::id()));
+        write_palette_png(&path, &cgram()).unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut reader = png::Decoder::new(file).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((info.width, info.height), (16, 16));
+
+        // Row 1, column 15 is color 31, which is white.
+        let pixel = |x: usize, y: usize| &decoded[(y * 16 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), [0, 0, 0, 0xFF]);
+        assert_eq!(pixel(1, 0), [8, 8, 8, 0xFF]);
+        assert_eq!(pixel(15, 1), [255, 255, 255, 0xFF]);
+    }
+}
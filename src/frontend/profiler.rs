@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::debugger;
+
+#[derive(Clone, Copy, Default)]
+struct OpcodeStats {
+    count: u64,
+    time: Duration,
+}
+
+// Counts how often each address and opcode is executed.
+pub struct Profiler {
+    // Keyed by 24-bit address. The opcode is kept alongside the count, as the
+    // memory may hold something else by the time the report is written.
+    addrs: HashMap<u32, (u64, u8)>,
+    opcodes: [OpcodeStats; 256],
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            addrs: HashMap::new(),
+            opcodes: [OpcodeStats::default(); 256],
+            total: 0,
+        }
+    }
+
+    // The time includes catching the PPU and APU up, as how far they run
+    // depends on the instruction.
+    pub fn record(&mut self, addr: u32, opcode: u8, time: Duration) {
+        let entry = self.addrs.entry(addr).or_insert((0, opcode));
+        entry.0 += 1;
+        entry.1 = opcode;
+
+        let stats = &mut self.opcodes[opcode as usize];
+        stats.count += 1;
+        stats.time += time;
+
+        self.total += 1;
+    }
+
+    pub fn report(&self, top: usize) -> String {
+        let mut output = String::new();
+
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+
+        let mut addrs: Vec<_> = self.addrs.iter().collect();
+
+        // Ties are broken by address, so that the report is stable.
+        addrs.sort_by_key(|&(&addr, &(count, _))| (std::cmp::Reverse(count), addr));
+
+        let _ = writeln!(output, "Instructions executed: {}", self.total);
+        let _ = writeln!(output, "\nHot addresses:");
+
+        for (&addr, &(count, opcode)) in addrs.iter().take(top) {
+            let _ = writeln!(
+                output,
+                "{:>12} {:>6.2}%  {}",
+                count,
+                percent(count),
+                debugger::format_instruction(addr, opcode)
+            );
+        }
+
+        let mut opcodes: Vec<(usize, &OpcodeStats)> = self
+            .opcodes
+            .iter()
+            .enumerate()
+            .filter(|(_, stats)| stats.count > 0)
+            .collect();
+
+        opcodes.sort_by_key(|&(opcode, stats)| (std::cmp::Reverse(stats.count), opcode));
+
+        let _ = writeln!(output, "\nHot opcodes:");
+
+        for &(opcode, stats) in opcodes.iter().take(top) {
+            let _ = writeln!(
+                output,
+                "{:>12} {:>6.2}%  {:02X}  {:>10.3}ms  {:>8.1}ns each",
+                stats.count,
+                percent(stats.count),
+                opcode,
+                stats.time.as_secs_f64() * 1000.0,
+                stats.time.as_nanos() as f64 / stats.count as f64
+            );
+        }
+
+        output
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use crate::mmu::MapMode;
+
+    #[test]
+    fn loop_dominates() {
+        #[rustfmt::skip]
+        let code = [
+            0x18,             // CLC
+            0xFB,             // XCE
+            0xC2, 0x10,       // REP #$10
+            0xA2, 0x00, 0x00, // LDX #$0000
+            // loop:
+            0xE8,             // INX
+            0xE0, 0xE8, 0x03, // CPX #$03E8
+            0xD0, 0xFA,       // BNE loop
+            0xDB,             // STP
+        ];
+
+        let mut rom = vec![0; 0x8000];
+        rom[..code.len()].copy_from_slice(&code);
+        rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+        let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+        let mut profiler = Profiler::new();
+
+        while emulator.cpu.pc() != 0x800D {
+            let addr = emulator.cpu.current_addr();
+            profiler.record(addr, emulator.mmu.peek_u8(addr), Duration::ZERO);
+            emulator.step();
+        }
+
+        let report = profiler.report(3);
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(lines[0], "Instructions executed: 3004");
+
+        // The three instructions in the loop, in order of address, as they
+        // all ran 1000 times.
+        assert_eq!(
+            &lines[3..6],
+            [
+                "        1000  33.29%  [008007] E8 INX",
+                "        1000  33.29%  [008008] E0 CPX #imm",
+                "        1000  33.29%  [00800B] D0 BNE rel",
+            ]
+        );
+
+        // Likewise for the opcodes, which are in order of opcode.
+        assert_eq!(lines[7], "Hot opcodes:");
+
+        for (line, opcode) in lines[8..11].iter().zip(["D0", "E0", "E8"]) {
+            assert!(
+                line.starts_with(&format!("        1000  33.29%  {}", opcode)),
+                "{}",
+                line
+            );
+        }
+    }
+}
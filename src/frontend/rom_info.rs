@@ -0,0 +1,237 @@
+use std::fmt::Write;
+
+use crate::header::{self, Header, VECTOR_NAMES};
+use crate::mmu::MapMode;
+
+fn map_mode_name(map_mode: MapMode) -> &'static str {
+    match map_mode {
+        MapMode::LoRom => "LoROM",
+        MapMode::HiRom => "HiROM",
+    }
+}
+
+pub fn rom_info(header: &Header, cartridge: &[u8], copier_header: bool) -> String {
+    let mut output = String::new();
+
+    let _ = writeln!(output, "Title:         {}", header.title);
+    let _ = writeln!(
+        output,
+        "Map mode:      {} ({})",
+        map_mode_name(header.map_mode),
+        if header.fast_rom {
+            "FastROM"
+        } else {
+            "SlowROM"
+        }
+    );
+    let _ = writeln!(
+        output,
+        "Chip:          {} (${:02X})",
+        header.chip_name(),
+        header.chip
+    );
+    let _ = writeln!(
+        output,
+        "ROM size:      {} KiB (file: {} KiB)",
+        header.rom_size_kb(),
+        cartridge.len() / 1024
+    );
+    let _ = writeln!(output, "SRAM size:     {} KiB", header.sram_size_kb());
+    let _ = writeln!(
+        output,
+        "Region:        {} (${:02X})",
+        header.region_name(),
+        header.region
+    );
+    let _ = writeln!(output, "Version:       1.{}", header.version);
+    let _ = writeln!(
+        output,
+        "Checksum:      ${:04X}, complement ${:04X} ({}, computed ${:04X})",
+        header.checksum,
+        header.complement,
+        if header.checksum_valid(cartridge) {
+            "valid"
+        } else {
+            "invalid"
+        },
+        header::checksum(cartridge)
+    );
+    let _ = writeln!(
+        output,
+        "Copier header: {}",
+        if copier_header { "yes" } else { "no" }
+    );
+    let _ = writeln!(output, "Vectors:       native  emulation");
+
+    for (i, name) in VECTOR_NAMES.iter().enumerate() {
+        let _ = writeln!(
+            output,
+            "    {:<10} ${:04X}   ${:04X}",
+            name, header.native_vectors[i], header.emulation_vectors[i]
+        );
+    }
+
+    output
+}
+
+// The same information as a single line of JSON. Numbers are written as-is
+// rather than in hex, so that scripts don't have to parse them.
+pub fn rom_info_json(header: &Header, cartridge: &[u8], copier_header: bool) -> String {
+    let vectors = |vectors: &[u16; 6]| {
+        let fields: Vec<String> = VECTOR_NAMES
+            .iter()
+            .zip(vectors)
+            .map(|(name, addr)| format!("\"{}\":{}", name.to_ascii_lowercase(), addr))
+            .collect();
+
+        fields.join(",")
+    };
+
+    // Titles are always printable ASCII after parsing, so only quotes and
+    // backslashes need escaping.
+    let title = header.title.replace('\\', "\\\\").replace('"', "\\\"");
+
+    format!(
+        concat!(
+            "{{\"title\":\"{}\",\"map_mode\":\"{}\",\"fast_rom\":{},\"chip\":{},\"chip_name\":\"{}\",",
+            "\"rom_size_kb\":{},\"file_size\":{},\"sram_size_kb\":{},\"region\":{},\"region_name\":\"{}\",",
+            "\"version\":{},\"checksum\":{},\"complement\":{},\"computed_checksum\":{},\"checksum_valid\":{},",
+            "\"copier_header\":{},\"native_vectors\":{{{}}},\"emulation_vectors\":{{{}}}}}"
+        ),
+        title,
+        map_mode_name(header.map_mode),
+        header.fast_rom,
+        header.chip,
+        header.chip_name(),
+        header.rom_size_kb(),
+        cartridge.len(),
+        header.sram_size_kb(),
+        header.region,
+        header.region_name(),
+        header.version,
+        header.checksum,
+        header.complement,
+        header::checksum(cartridge),
+        header.checksum_valid(cartridge),
+        copier_header,
+        vectors(&header.native_vectors),
+        vectors(&header.emulation_vectors),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu;
+
+    // A blank image of the given size with a header filled in, and a valid
+    // checksum.
+    fn image(size: usize, map_mode: MapMode, title: &str, mode: u8, rom_size: u8) -> Vec<u8> {
+        let mut cartridge = vec![0; size];
+        let header = &mut cartridge[map_mode.header_offset()..][..0x40];
+
+        header[..0x15].fill(b' ');
+        header[..title.len()].copy_from_slice(title.as_bytes());
+        header[0x15] = mode;
+        header[0x16] = 0x02;
+        header[0x17] = rom_size;
+        header[0x18] = 0x03;
+        header[0x19] = 0x01;
+        header[0x1B] = 0x02;
+
+        let vectors = [
+            (0x24, [0x8100, 0x8104, 0x0000, 0x8108, 0x0000, 0x810C]),
+            (0x34, [0x8100, 0x0000, 0x0000, 0x8108, 0x8000, 0x810C]),
+        ];
+
+        for (base, table) in vectors {
+            for (i, addr) in table.into_iter().enumerate() {
+                header[base + i * 2..][..2].copy_from_slice(&u16::to_le_bytes(addr));
+            }
+        }
+
+        // The checksum and its complement always add up to the same bytes,
+        // so the sum can be taken with any pair of them in place.
+        header[0x1C..0x20].copy_from_slice(&[0xFF, 0xFF, 0x00, 0x00]);
+        let checksum = header::checksum(&cartridge);
+
+        let header = &mut cartridge[map_mode.header_offset()..][..0x40];
+        header[0x1C..0x1E].copy_from_slice(&(!checksum).to_le_bytes());
+        header[0x1E..0x20].copy_from_slice(&checksum.to_le_bytes());
+
+        cartridge
+    }
+
+    #[test]
+    fn lorom_image() {
+        let mut cartridge = vec![0; 0x200];
+        cartridge.extend(image(0x8000, MapMode::LoRom, "TEST LOROM", 0x20, 0x05));
+
+        let copier_header = mmu::strip_copier_header(&mut cartridge);
+        assert_eq!(MapMode::detect(&cartridge), MapMode::LoRom);
+
+        let header = Header::parse(&cartridge, MapMode::LoRom).unwrap();
+        let checksum = header::checksum(&cartridge);
+
+        let expected = format!(
+            concat!(
+                "Title:         TEST LOROM\n",
+                "Map mode:      LoROM (SlowROM)\n",
+                "Chip:          ROM + RAM + battery ($02)\n",
+                "ROM size:      32 KiB (file: 32 KiB)\n",
+                "SRAM size:     8 KiB\n",
+                "Region:        North America ($01)\n",
+                "Version:       1.2\n",
+                "Checksum:      ${:04X}, complement ${:04X} (valid, computed ${:04X})\n",
+                "Copier header: yes\n",
+                "Vectors:       native  emulation\n",
+                "    COP        $8100   $8100\n",
+                "    BRK        $8104   $0000\n",
+                "    ABORT      $0000   $0000\n",
+                "    NMI        $8108   $8108\n",
+                "    RESET      $0000   $8000\n",
+                "    IRQ        $810C   $810C\n",
+            ),
+            checksum, !checksum, checksum
+        );
+
+        assert_eq!(rom_info(&header, &cartridge, copier_header), expected);
+    }
+
+    #[test]
+    fn hirom_image() {
+        let mut cartridge = image(0x10000, MapMode::HiRom, "TEST \"HIROM\"", 0x31, 0x06);
+
+        let copier_header = mmu::strip_copier_header(&mut cartridge);
+        assert_eq!(MapMode::detect(&cartridge), MapMode::HiRom);
+
+        let header = Header::parse(&cartridge, MapMode::HiRom).unwrap();
+        let checksum = header::checksum(&cartridge);
+
+        let expected = format!(
+            concat!(
+                "{{\"title\":\"TEST \\\"HIROM\\\"\",\"map_mode\":\"HiROM\",\"fast_rom\":true,",
+                "\"chip\":2,\"chip_name\":\"ROM + RAM + battery\",\"rom_size_kb\":64,",
+                "\"file_size\":65536,\"sram_size_kb\":8,\"region\":1,\"region_name\":\"North America\",",
+                "\"version\":2,\"checksum\":{},\"complement\":{},\"computed_checksum\":{},",
+                "\"checksum_valid\":true,\"copier_header\":false,",
+                "\"native_vectors\":{{\"cop\":33024,\"brk\":33028,\"abort\":0,\"nmi\":33032,\"reset\":0,\"irq\":33036}},",
+                "\"emulation_vectors\":{{\"cop\":33024,\"brk\":0,\"abort\":0,\"nmi\":33032,\"reset\":32768,\"irq\":33036}}}}"
+            ),
+            checksum, !checksum, checksum
+        );
+
+        assert_eq!(rom_info_json(&header, &cartridge, copier_header), expected);
+    }
+
+    #[test]
+    fn corrupt_checksum() {
+        let mut cartridge = image(0x8000, MapMode::LoRom, "TEST", 0x20, 0x05);
+        cartridge[0] = 0xFF;
+
+        let header = Header::parse(&cartridge, MapMode::LoRom).unwrap();
+
+        assert!(rom_info(&header, &cartridge, false).contains("(invalid, computed"));
+        assert!(rom_info_json(&header, &cartridge, false).contains("\"checksum_valid\":false"));
+    }
+}
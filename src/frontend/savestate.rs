@@ -0,0 +1,19 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::emulator::Emulator;
+use crate::savestate::{load_from, save_to};
+
+// The state is put together in memory first, so that nothing is left
+// behind if it can't be saved.
+pub fn save(emulator: &Emulator, path: impl AsRef<Path>) -> io::Result<()> {
+    let mut state = Vec::new();
+    save_to(emulator, &mut state)?;
+
+    fs::write(path, state)
+}
+
+pub fn load(emulator: &mut Emulator, path: impl AsRef<Path>) -> io::Result<()> {
+    load_from(emulator, fs::read(path)?.as_slice())
+}
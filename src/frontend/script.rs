@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{CallFnOptions, Engine, FuncArgs, Scope, AST};
+
+use crate::cpu::Register;
+use crate::emulator::Emulator;
+
+// Something a script asked the frontend to do, which is picked up after the
+// instruction that it ran during.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    Break(u32),
+    ClearBreak(u32),
+    Pause,
+}
+
+// What the functions that scripts can call have access to. While a script
+// is running, the real emulator is swapped in here, and a spare one is left
+// in its place.
+struct Context {
+    emulator: Emulator,
+    requests: Vec<Request>,
+}
+
+// A Rhai script that drives the emulator through its hooks. Scripts can
+// define any of these, which are called at the same points as the hooks:
+//
+//   fn on_instruction(addr) { ... }
+//   fn on_frame(frame) { ... }
+//   fn on_write(addr, value) { ... }
+//
+// and can call read(addr), read16(addr), write(addr, value), reg(name),
+// set_reg(name, value), frame(), instructions(), break_at(addr),
+// clear_break(addr) and pause().
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    context: Rc<RefCell<Context>>,
+}
+
+impl Script {
+    pub fn load(path: impl AsRef<Path>, spare: Emulator) -> Result<Script, String> {
+        let context = Rc::new(RefCell::new(Context {
+            emulator: spare,
+            requests: Vec::new(),
+        }));
+
+        let engine = engine(&context);
+        let ast = engine
+            .compile_file(path.as_ref().into())
+            .map_err(|e| e.to_string())?;
+
+        Ok(Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+            context,
+        })
+    }
+
+    // Runs the script's top level, then registers hooks for the callbacks
+    // it defines. Only those are registered, as calling into the script for
+    // every instruction is slow.
+    pub fn attach(self, emulator: &mut Emulator) -> Result<Rc<RefCell<Script>>, String> {
+        let script = Rc::new(RefCell::new(self));
+
+        script.borrow_mut().run(emulator)?;
+
+        let defines = |name: &str| script.borrow().ast.iter_functions().any(|f| f.name == name);
+
+        if defines("on_instruction") {
+            let script = script.clone();
+
+            emulator.on_instruction(move |emulator, _, addr| {
+                script
+                    .borrow_mut()
+                    .call(emulator, "on_instruction", (addr as i64,));
+            });
+        }
+
+        if defines("on_frame") {
+            let script = script.clone();
+
+            emulator.on_frame(move |emulator| {
+                let frame = emulator.frame() as i64;
+                script.borrow_mut().call(emulator, "on_frame", (frame,));
+            });
+        }
+
+        if defines("on_write") {
+            let script = script.clone();
+
+            emulator.on_memory_write(move |emulator, addr, value| {
+                script
+                    .borrow_mut()
+                    .call(emulator, "on_write", (addr as i64, value as i64));
+            });
+        }
+
+        Ok(script)
+    }
+
+    pub fn take_requests(&mut self) -> Vec<Request> {
+        std::mem::take(&mut self.context.borrow_mut().requests)
+    }
+
+    fn run(&mut self, emulator: &mut Emulator) -> Result<(), String> {
+        self.swap(emulator);
+        let result = self.engine.run_ast_with_scope(&mut self.scope, &self.ast);
+        self.swap(emulator);
+
+        result.map_err(|e| e.to_string())
+    }
+
+    // A script error pauses execution, so that it doesn't get reported for
+    // every instruction.
+    fn call(&mut self, emulator: &mut Emulator, name: &str, args: impl FuncArgs) {
+        self.swap(emulator);
+        // The top level was run once when the script was attached, and
+        // shouldn't be run again for every call.
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options::<rhai::Dynamic>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            name,
+            args,
+        );
+        self.swap(emulator);
+
+        if let Err(e) = result {
+            eprintln!("Script error in {}: {}", name, e);
+            self.context.borrow_mut().requests.push(Request::Pause);
+        }
+    }
+
+    fn swap(&self, emulator: &mut Emulator) {
+        std::mem::swap(emulator, &mut self.context.borrow_mut().emulator);
+    }
+}
+
+fn engine(context: &Rc<RefCell<Context>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let addr = |addr: i64| (addr as u32) & 0xFF_FFFF;
+
+    let c = context.clone();
+    engine.register_fn("read", move |a: i64| {
+        c.borrow().emulator.mmu.peek_u8(addr(a)) as i64
+    });
+
+    let c = context.clone();
+    engine.register_fn("read16", move |a: i64| {
+        let mmu = &c.borrow().emulator.mmu;
+        u16::from_le_bytes([mmu.peek_u8(addr(a)), mmu.peek_u8(addr(a + 1))]) as i64
+    });
+
+    let c = context.clone();
+    engine.register_fn("write", move |a: i64, value: i64| {
+        c.borrow_mut().emulator.mmu.store_u8(addr(a), value as u8);
+    });
+
+    let c = context.clone();
+    engine.register_fn(
+        "reg",
+        move |name: &str| -> Result<i64, Box<rhai::EvalAltResult>> {
+            let state = c.borrow().emulator.cpu.state();
+
+            let value = match name.to_ascii_uppercase().as_str() {
+                "A" => state.a as u32,
+                "X" => state.x as u32,
+                "Y" => state.y as u32,
+                "D" => state.direct_page as u32,
+                "SP" => state.sp as u32,
+                "DB" => state.data_bank as u32,
+                "PB" => state.program_bank as u32,
+                "PC" => (state.program_bank as u32) << 16 | state.pc as u32,
+                "P" => state.status as u32,
+                "E" => state.emulation as u32,
+                _ => return Err(format!("unknown register: {}", name).into()),
+            };
+
+            Ok(value as i64)
+        },
+    );
+
+    let c = context.clone();
+    engine.register_fn(
+        "set_reg",
+        move |name: &str, value: i64| -> Result<(), Box<rhai::EvalAltResult>> {
+            let register = match name.to_ascii_uppercase().as_str() {
+                "A" => Register::A,
+                "X" => Register::X,
+                "Y" => Register::Y,
+                "D" => Register::D,
+                _ => return Err(format!("can't set register: {}", name).into()),
+            };
+
+            c.borrow_mut()
+                .emulator
+                .cpu
+                .set_register(register, value as u16);
+
+            Ok(())
+        },
+    );
+
+    let c = context.clone();
+    engine.register_fn("frame", move || c.borrow().emulator.frame() as i64);
+
+    let c = context.clone();
+    engine.register_fn("instructions", move || {
+        c.borrow().emulator.instructions() as i64
+    });
+
+    let c = context.clone();
+    engine.register_fn("break_at", move |a: i64| {
+        c.borrow_mut().requests.push(Request::Break(addr(a)));
+    });
+
+    let c = context.clone();
+    engine.register_fn("clear_break", move |a: i64| {
+        c.borrow_mut().requests.push(Request::ClearBreak(addr(a)));
+    });
+
+    let c = context.clone();
+    engine.register_fn("pause", move || {
+        c.borrow_mut().requests.push(Request::Pause);
+    });
+
+    engine
+}
@@ -0,0 +1,1274 @@
+use std::any::Any;
+#[cfg(feature = "scripting")]
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{self, Write};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::ops::ControlFlow;
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "scripting")]
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::cdl::CodeDataLog;
+use crate::cpu::Cpu;
+use crate::debugger::{self, Condition};
+use crate::disasm::{self, Disassembly};
+use crate::emulator::Emulator;
+use crate::events::EventLog;
+#[cfg(feature = "audio")]
+use crate::frontend::audio::Audio;
+use crate::frontend::battery::BatteryFile;
+use crate::frontend::compare::Comparer;
+use crate::frontend::frame_dump::FrameDumper;
+use crate::frontend::idle::IdleDetector;
+use crate::frontend::io_log::{IoEntry, IoLog};
+use crate::frontend::modes::print_opcodes;
+use crate::frontend::movie::{Player, Recorder};
+use crate::frontend::options::{Options, TraceMode};
+use crate::frontend::palette::write_palette_png;
+use crate::frontend::profiler::Profiler;
+use crate::frontend::rewind::Rewind;
+use crate::frontend::savestate;
+#[cfg(feature = "scripting")]
+use crate::frontend::script::{Request, Script};
+use crate::frontend::stack_guard::StackGuard;
+use crate::frontend::summary::{summary_json, Check};
+use crate::frontend::trace::{diff_logs, read_log, write_log, TraceWriter};
+use crate::frontend::trace_filter::TraceFilter;
+#[cfg(feature = "tui")]
+use crate::frontend::tui::Tui;
+#[cfg(feature = "window")]
+use crate::frontend::window::Window;
+use crate::frontend::write_log::{WriteEntry, WriteLog};
+use crate::mmu::{self, RomWritePolicy};
+use crate::ram_search::RamSearch;
+use crate::symbols::Symbols;
+use crate::trace::{trace_entry, unknown_opcode_banner, write_calls, TraceFormat, TraceRecord};
+
+mod prompt;
+
+// How many addresses and opcodes the profile written on exit lists.
+const PROFILE_TOP: usize = 20;
+
+// How many instructions are shown before and after an unknown opcode.
+const UNKNOWN_WINDOW: usize = 4;
+
+// How many of the writes to a crashing instruction are shown.
+const REPORTED_WRITES: usize = 8;
+
+// How many instructions run between checks of --max-seconds, as reading the
+// clock for every one would slow things down.
+const TIME_CHECK_INTERVAL: u64 = 4096;
+
+// Set by interrupt(), and picked up before the next instruction.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Asks the running session to stop (or pause, in the debugger) before the
+// next instruction, as Ctrl+C does. Returns true if the last request hasn't
+// been picked up yet, which means the session is stuck somewhere else.
+pub fn interrupt() -> bool {
+    INTERRUPTED.swap(true, Ordering::Relaxed)
+}
+
+// Why a run couldn't be set up. Problems with what was asked for get the
+// same exit status as a bad argument, and problems with files get 1.
+#[derive(Debug)]
+pub enum SetupError {
+    Argument(String),
+    File(String),
+}
+
+impl SetupError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SetupError::Argument(_) => 2,
+            SetupError::File(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetupError::Argument(message) | SetupError::File(message) => f.write_str(message),
+        }
+    }
+}
+
+// Reads the ROM being run. A ROM that's missing or too small to run is
+// treated the same as a bad argument.
+pub fn read_rom(options: &Options) -> Result<Vec<u8>, SetupError> {
+    let mut rom = std::fs::read(&options.rom)
+        .map_err(|e| SetupError::Argument(format!("couldn't read {}: {}", options.rom, e)))?;
+
+    // The size is checked without any copier header, which the Mmu would
+    // strip anyway.
+    mmu::strip_copier_header(&mut rom);
+
+    if rom.len() < mmu::MIN_ROM_SIZE {
+        return Err(SetupError::Argument(format!(
+            "{} is too small to be a ROM ({} bytes)",
+            options.rom,
+            rom.len()
+        )));
+    }
+
+    Ok(rom)
+}
+
+// Builds the emulator that every kind of run starts from: the ROM, with RAM
+// filled in, the second controller port connected and the cheats applied.
+pub fn prepare(options: &Options, rom: Vec<u8>) -> Result<Emulator, SetupError> {
+    let mut emulator = Emulator::new(rom, options.map_mode)
+        .map_err(|e| SetupError::Argument(format!("{} is {}", options.rom, e)))?;
+    emulator.mmu.controllers.port2 = options.port2();
+    emulator.mmu.init_ram(options.ram_init);
+
+    // Game Genie codes can only be checked against the mapping once the ROM
+    // is loaded.
+    for cheat in &options.cheats {
+        emulator
+            .mmu
+            .add_cheat(cheat.clone())
+            .map_err(|e| SetupError::Argument(format!("couldn't apply {}: {}", cheat.code, e)))?;
+    }
+
+    Ok(emulator)
+}
+
+// Restores the --load-state save state, if there is one.
+pub fn load_state(emulator: &mut Emulator, options: &Options) -> Result<(), SetupError> {
+    match &options.load_state {
+        Some(path) => savestate::load(emulator, path)
+            .map_err(|e| SetupError::File(format!("couldn't load {}: {}", path, e))),
+        None => Ok(()),
+    }
+}
+
+// Opens the --play movie, if there is one.
+pub fn open_movie(emulator: &Emulator, options: &Options) -> Result<Option<Player>, SetupError> {
+    options
+        .play
+        .as_ref()
+        .map(|path| {
+            Player::open(path, emulator.mmu.cartridge(), options.ram_init)
+                .map_err(|e| SetupError::File(format!("couldn't load {}: {}", path, e)))
+        })
+        .transpose()
+}
+
+// Reports a file that couldn't be opened, created or read.
+fn file_error<'a>(action: &'a str, path: &'a str) -> impl FnOnce(io::Error) -> SetupError + 'a {
+    move |e| SetupError::File(format!("couldn't {} {}: {}", action, path, e))
+}
+
+// Why execution stopped before the emulator was closed.
+pub enum Stop {
+    UnknownOpcode,
+    InstructionLimit,
+    FrameLimit,
+    TimeLimit,
+    Interrupted,
+    Target,
+    Breakpoint(u32),
+    Watchpoint,
+    Step,
+    Diverged,
+    RomWrite,
+    MovieError,
+    WriteError,
+}
+
+// Where to pause after stepping over a call or finishing one: the first
+// instruction at `addr` (or anywhere, if None) once the stack pointer is
+// back up to `sp`. A deeper call reaching the same address, through
+// recursion or an interrupt handler, still has its return address on the
+// stack, so it doesn't count.
+#[derive(Clone, Copy)]
+struct ReturnTarget {
+    addr: Option<u32>,
+    sp: u16,
+}
+
+impl ReturnTarget {
+    fn reached(self, cpu: &Cpu) -> bool {
+        self.addr.is_none_or(|addr| addr == cpu.current_addr()) && cpu.sp() >= self.sp
+    }
+}
+
+enum Trace {
+    Ring(VecDeque<TraceRecord>),
+
+    // The line is reused for every instruction, to save allocating.
+    Stream(TraceWriter, String),
+
+    Off,
+}
+
+// A run of the emulator from the command line, along with the debugger
+// state that can change while it's running.
+pub struct Session {
+    options: Options,
+    trace: Trace,
+
+    // Breakpoints with a condition only stop execution when it holds.
+    breakpoints: HashMap<u32, Option<Condition>>,
+
+    // How many instructions to run before pausing, or None to run freely.
+    steps: Option<u64>,
+
+    // Set when resuming from the debugger, so that a breakpoint on the
+    // current instruction doesn't immediately fire again.
+    resuming: bool,
+
+    // Where to pause after stepping over or finishing a subroutine call.
+    step_over: Option<ReturnTarget>,
+
+    rewind: Option<Rewind>,
+    profiler: Option<Profiler>,
+    io_log: Option<IoLog>,
+    write_log: Option<WriteLog>,
+    idle: Option<IdleDetector>,
+
+    // How many times each opcode has run, for --opcodes.
+    opcode_counts: Option<Box<[u64; 256]>>,
+
+    stack_guard: Option<StackGuard>,
+    ram_search: Option<RamSearch>,
+    symbols: Symbols,
+    #[cfg(feature = "scripting")]
+    script: Option<Rc<RefCell<Script>>>,
+    #[cfg(feature = "tui")]
+    tui: Option<Tui>,
+
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    frame_dumper: Option<FrameDumper>,
+    #[cfg(feature = "window")]
+    window: Option<Window>,
+    #[cfg(feature = "audio")]
+    audio: Option<Audio>,
+
+    // Time spent running frames, leaving out any time paused in the
+    // debugger, for working out the emulation speed.
+    run_time: Duration,
+
+    // When the frame being run started, which isn't in run_time yet.
+    frame_start: Instant,
+    trace_filter: Option<TraceFilter>,
+    comparer: Option<Comparer>,
+
+    // The --compare-log reference, read up front so that a bad path is
+    // caught before the run rather than after it.
+    expected_log: Option<String>,
+
+    // The addresses of the last few instructions, for showing what led up
+    // to an unknown opcode.
+    recent: VecDeque<u32>,
+
+    // The addresses of instructions that have written to ROM, so that each
+    // is only reported once.
+    rom_write_pcs: HashSet<u32>,
+
+    // How many times each unknown opcode has been hit, keyed by address.
+    unknown_opcodes: BTreeMap<(u32, u8), u64>,
+
+    // Reports of unknown opcodes, kept until the end of the run in ring
+    // mode. In stream mode they're written straight to the log.
+    banners: Vec<String>,
+
+    // In stream mode, the record for the instruction that's running. It's
+    // written once the instruction has run and its operand is known, or by
+    // the panic log if it never finishes.
+    unwritten: Option<TraceRecord>,
+}
+
+impl Session {
+    // Attaches everything the options ask for to an emulator from prepare().
+    pub fn new(options: Options, emulator: &mut Emulator) -> Result<Session, SetupError> {
+        let frame_dumper = options
+            .dump_frames
+            .as_ref()
+            .map(|dir| {
+                FrameDumper::new(dir, options.dump_interval).map_err(file_error("create", dir))
+            })
+            .transpose()?;
+
+        emulator.mmu.rom_write_policy = options.rom_writes;
+
+        if let Some(path) = options
+            .sram_path()
+            .filter(|_| !emulator.mmu.sram().is_empty())
+        {
+            let battery = BatteryFile::new(path, Duration::from_secs(options.sram_interval));
+
+            battery.load(&mut emulator.mmu).map_err(|e| {
+                SetupError::File(format!("couldn't load {}: {}", battery.path().display(), e))
+            })?;
+
+            emulator.battery = Some(Box::new(battery));
+        }
+
+        emulator.mmu.watchpoints = options.watchpoints.clone();
+
+        if let Some(path) = &options.spc_trace {
+            let file = File::create(path).map_err(file_error("create", path))?;
+            emulator.spc_trace = Some(Box::new(BufWriter::new(file)));
+        }
+
+        load_state(emulator, &options)?;
+
+        emulator.mmu.log_accesses = options.idle_loops.is_some();
+
+        let io_log = match (&options.io_log, options.io_ring) {
+            (Some(path), _) => Some(IoLog::create(path).map_err(file_error("create", path))?),
+            (None, Some(capacity)) => Some(IoLog::ring(capacity)),
+            (None, None) => None,
+        };
+
+        emulator.mmu.log_io = io_log.is_some();
+
+        if options.write_ring.is_some() {
+            emulator.mmu.log_writes = true;
+        }
+
+        if options.events.is_some() || options.events_out.is_some() {
+            emulator.mmu.events = Some(EventLog::new(options.events.unwrap_or(4096)));
+        }
+
+        if options.cdl.is_some() {
+            emulator.mmu.cdl = Some(CodeDataLog::new(emulator.mmu.cartridge().len()));
+        }
+
+        let recorder = options
+            .record
+            .as_ref()
+            .map(|path| {
+                Recorder::create(path, emulator.mmu.cartridge(), options.ram_init)
+                    .map_err(file_error("create", path))
+            })
+            .transpose()?;
+
+        let player = open_movie(emulator, &options)?;
+
+        let symbols = match &options.symbols {
+            Some(path) => Symbols::load(path).map_err(file_error("load", path))?,
+            None => Symbols::new(),
+        };
+
+        let comparer = match &options.compare {
+            Some(path) => {
+                let reference = read_log(path).map_err(file_error("load", path))?;
+                Some(Comparer::new(&reference, options.compare_context))
+            }
+            None => None,
+        };
+
+        let expected_log = options
+            .compare_log
+            .as_ref()
+            .map(|path| {
+                read_log(path)
+                    .map_err(|e| SetupError::Argument(format!("couldn't read {}: {}", path, e)))
+            })
+            .transpose()?;
+
+        let trace = match options.trace_mode {
+            TraceMode::Ring => Trace::Ring(VecDeque::new()),
+            TraceMode::Off => Trace::Off,
+            TraceMode::Stream => {
+                let writer = TraceWriter::create(&options.log, options.trace_gzip)
+                    .map_err(file_error("create", &options.log))?;
+                writer.install_panic_hook();
+
+                Trace::Stream(writer, String::new())
+            }
+        };
+
+        #[cfg(feature = "scripting")]
+        let script = options
+            .script
+            .as_ref()
+            .map(|path| {
+                Script::load(path, emulator.snapshot())
+                    .and_then(|script| script.attach(emulator))
+                    .map_err(|e| SetupError::File(format!("couldn't load {}: {}", path, e)))
+            })
+            .transpose()?;
+
+        #[cfg(feature = "tui")]
+        let tui = options
+            .tui
+            .then(|| {
+                Tui::new()
+                    .map_err(|e| SetupError::File(format!("couldn't start the debugger: {}", e)))
+            })
+            .transpose()?;
+
+        #[cfg(feature = "audio")]
+        let audio = options
+            .play_audio
+            .then(|| {
+                Audio::new()
+                    .map_err(|e| SetupError::File(format!("couldn't start the audio: {}", e)))
+            })
+            .transpose()?;
+
+        Ok(Session {
+            breakpoints: options.breakpoints.clone(),
+            steps: options.debug.then_some(0),
+            resuming: false,
+            step_over: None,
+
+            rewind: options
+                .rewind
+                .map(|capacity| Rewind::new(capacity, options.rewind_interval)),
+            profiler: options.profile.is_some().then(Profiler::new),
+            io_log,
+            write_log: options.write_ring.map(WriteLog::new),
+            idle: options.idle_loops.map(IdleDetector::new),
+            opcode_counts: options.opcodes.then(|| Box::new([0; 256])),
+            stack_guard: (options.stack_depth.is_some() || !options.stack_guards.is_empty()).then(
+                || {
+                    StackGuard::new(
+                        options.stack_depth,
+                        options.stack_guards.clone(),
+                        &emulator.cpu,
+                    )
+                },
+            ),
+            ram_search: None,
+            symbols,
+            #[cfg(feature = "scripting")]
+            script,
+            #[cfg(feature = "tui")]
+            tui,
+
+            recorder,
+            player,
+            frame_dumper,
+            #[cfg(feature = "window")]
+            window: options.show_window.then(|| Window::new("snesemu")),
+            #[cfg(feature = "audio")]
+            audio,
+
+            run_time: Duration::ZERO,
+            frame_start: Instant::now(),
+            trace_filter: Some(TraceFilter::new(
+                options.trace_ranges.clone(),
+                options.trace_skipped.clone(),
+            ))
+            .filter(|filter| !filter.is_empty()),
+            comparer,
+            expected_log,
+
+            recent: VecDeque::new(),
+            rom_write_pcs: HashSet::new(),
+            unknown_opcodes: BTreeMap::new(),
+            banners: Vec::new(),
+            unwritten: None,
+
+            options,
+            trace,
+        })
+    }
+
+    // Runs frames until something stops execution, or the window or the
+    // debugger is closed, in which case there's no reason.
+    pub fn run(&mut self, emulator: &mut Emulator) -> Option<Stop> {
+        loop {
+            if let ControlFlow::Break(stop) = self.run_frame(emulator) {
+                return stop;
+            }
+        }
+    }
+
+    // How many instructions have been reported for writing to ROM.
+    pub fn rom_write_warnings(&self) -> usize {
+        self.rom_write_pcs.len()
+    }
+
+    // Runs a frame, including any time spent paused in the debugger, then
+    // presents it.
+    pub fn run_frame(&mut self, emulator: &mut Emulator) -> ControlFlow<Option<Stop>> {
+        loop {
+            #[cfg(feature = "window")]
+            if let Some(window) = &self.window {
+                if !window.is_open() {
+                    return ControlFlow::Break(None);
+                }
+
+                emulator.set_input(window.buttons());
+            }
+
+            #[cfg(feature = "tui")]
+            if let Some(tui) = &self.tui {
+                if self.steps.is_none() && tui.interrupted().unwrap_or(false) {
+                    self.steps = Some(0);
+                }
+            }
+
+            let frame = emulator.frame();
+
+            if let Some(player) = &mut self.player {
+                let (buttons, desync) = player.next(frame, emulator.mmu.wram());
+                emulator.set_input(buttons);
+
+                if let Some(desync) = desync {
+                    eprintln!(
+                        "Movie desynced at frame {}: WRAM hash is {:016x}, expected {:016x}",
+                        desync.frame, desync.actual, desync.expected
+                    );
+                }
+            }
+
+            if let Some(recorder) = &mut self.recorder {
+                if let Err(e) = recorder.record(frame, emulator.input(), emulator.mmu.wram()) {
+                    eprintln!("error: couldn't write the movie: {}", e);
+
+                    // There's no point finishing a movie that's missing input.
+                    self.recorder = None;
+                    return ControlFlow::Break(Some(Stop::MovieError));
+                }
+            }
+
+            if let Some(rewind) = &mut self.rewind {
+                rewind.capture(emulator);
+            }
+
+            self.frame_start = Instant::now();
+
+            // If the emulator panics, the ring buffer would be lost along
+            // with the rest of the stack, so write it out before carrying on.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.run_instructions(emulator)));
+
+            self.run_time += self.frame_start.elapsed();
+
+            let result = result.unwrap_or_else(|payload| {
+                self.write_panic_log(emulator, payload.as_ref());
+
+                if let Err(e) = emulator.flush_battery() {
+                    self.report_battery_error(e);
+                }
+
+                panic::resume_unwind(payload);
+            });
+
+            match result {
+                Ok(()) => break,
+
+                Err(Stop::Breakpoint(_) | Stop::Watchpoint | Stop::RomWrite | Stop::Step)
+                    if self.options.debug =>
+                {
+                    if !self.debug_prompt(emulator) {
+                        return ControlFlow::Break(None);
+                    }
+
+                    // The frame isn't finished yet, so go straight back to it.
+                }
+
+                Err(reason) => return ControlFlow::Break(Some(reason)),
+            }
+        }
+
+        if let Some(e) = emulator.take_battery_error() {
+            self.report_battery_error(e);
+        }
+
+        // The DSP's output has to be drained every frame, even if it's not
+        // being played.
+        let samples = emulator.mmu.spc.take_samples();
+
+        #[cfg(feature = "audio")]
+        if let Some(audio) = &self.audio {
+            audio.push(&samples);
+        }
+
+        #[cfg(not(feature = "audio"))]
+        drop(samples);
+
+        if let Some(frame_dumper) = &self.frame_dumper {
+            frame_dumper
+                .dump(emulator.frame(), emulator.framebuffer())
+                .unwrap();
+        }
+
+        #[cfg(feature = "window")]
+        if let Some(window) = &mut self.window {
+            window.present(emulator.framebuffer());
+        }
+
+        if self
+            .options
+            .max_frames
+            .is_some_and(|max| emulator.frame() >= max)
+        {
+            return ControlFlow::Break(Some(Stop::FrameLimit));
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    // Runs until the PPU finishes a frame, or until something stops
+    // execution.
+    fn run_instructions(&mut self, emulator: &mut Emulator) -> Result<(), Stop> {
+        let options = &self.options;
+
+        loop {
+            if options
+                .max_instructions
+                .is_some_and(|max| emulator.steps() >= max)
+            {
+                return Err(Stop::InstructionLimit);
+            }
+
+            if let Some(limit) = options.max_time {
+                if emulator.steps().is_multiple_of(TIME_CHECK_INTERVAL)
+                    && self.run_time + self.frame_start.elapsed() >= limit
+                {
+                    return Err(Stop::TimeLimit);
+                }
+            }
+
+            // In the debugger, Ctrl+C pauses instead of stopping.
+            if INTERRUPTED.swap(false, Ordering::Relaxed) {
+                match options.debug {
+                    true => self.steps = Some(0),
+                    false => return Err(Stop::Interrupted),
+                }
+            }
+
+            let current_addr = emulator.cpu.current_addr();
+
+            if self.steps == Some(0) {
+                return Err(Stop::Step);
+            }
+
+            // The target can be the current instruction, when an interrupt
+            // was stepped over, so it's not checked when resuming from there.
+            if !self.resuming
+                && self
+                    .step_over
+                    .is_some_and(|target| target.reached(&emulator.cpu))
+            {
+                self.step_over = None;
+                return Err(Stop::Step);
+            }
+
+            if options.run_until == Some(current_addr) {
+                return Err(Stop::Target);
+            }
+
+            let breakpoint = self
+                .breakpoints
+                .get(&current_addr)
+                .is_some_and(|condition| {
+                    condition
+                        .as_ref()
+                        .is_none_or(|condition| condition.eval(&emulator.cpu, &emulator.mmu))
+                });
+
+            if !self.resuming && breakpoint {
+                return Err(Stop::Breakpoint(current_addr));
+            }
+
+            self.resuming = false;
+
+            let record = (!matches!(self.trace, Trace::Off)).then(|| emulator.trace_record());
+
+            let next = emulator.next_instruction();
+            let opcode = next.opcode;
+            let unknown = next.unknown();
+
+            if let Some(counts) = self.opcode_counts.as_mut().filter(|_| next.runs) {
+                counts[opcode as usize] += 1;
+            }
+
+            // Unknown opcodes and watchpoint hits are reported against the
+            // state from before the instruction, so that's needed even when
+            // nothing is being traced.
+            let record = record.or_else(|| {
+                (unknown || !emulator.mmu.watchpoints.is_empty() || self.comparer.is_some())
+                    .then(|| emulator.trace_record())
+            });
+
+            if let (Some(comparer), Some(record)) = (&mut self.comparer, &record) {
+                if let Some(divergence) = comparer.check(record, &self.symbols) {
+                    eprint!("{}", divergence.report());
+                    return Err(Stop::Diverged);
+                }
+            }
+
+            // Each unknown opcode is only reported the first time it's hit.
+            // The report has to be built before the instruction runs, as the
+            // CPU still moves past the opcode.
+            let report = unknown && !self.unknown_opcodes.contains_key(&(current_addr, opcode));
+
+            let banner = record.as_ref().filter(|_| report).map(|record| {
+                let window = unknown_opcode_window(emulator, &self.recent);
+                let mut banner = unknown_opcode_banner(
+                    record,
+                    &window,
+                    emulator.cpu.call_stack(),
+                    &self.symbols,
+                );
+
+                // Whatever wrote the bytes being run is usually the real bug.
+                if let Some(write_log) = &self.write_log {
+                    write_log.write_report(&mut banner, current_addr, 4, REPORTED_WRITES);
+                }
+
+                banner
+            });
+
+            if self.recent.len() >= UNKNOWN_WINDOW {
+                self.recent.pop_front();
+            }
+
+            self.recent.push_back(current_addr);
+
+            let before = record
+                .as_ref()
+                .filter(|_| !emulator.mmu.watchpoints.is_empty())
+                .cloned();
+
+            let logged = match &mut self.trace_filter {
+                Some(filter) => filter.should_log(current_addr, next.instruction()),
+                None => true,
+            };
+
+            match (&mut self.trace, record.filter(|_| logged)) {
+                (Trace::Ring(records), Some(record)) => {
+                    if records.len() >= options.trace_len.max(1) {
+                        records.pop_front();
+                    }
+
+                    records.push_back(record);
+                }
+
+                (Trace::Stream(..), Some(record)) => self.unwritten = Some(record),
+
+                _ => {}
+            }
+
+            let start = self.profiler.is_some().then(Instant::now);
+            let position = (emulator.frame(), emulator.mmu.ppu.scanline());
+            let index = emulator.steps();
+
+            let result = emulator.step_instruction();
+
+            // Where the operand was accessed is only known once the
+            // instruction has run, so it's filled in afterwards.
+            let operand = result.operand;
+
+            match &mut self.trace {
+                Trace::Ring(records) if logged => {
+                    if let Some(record) = records.back_mut() {
+                        record.operand = operand;
+                    }
+                }
+
+                Trace::Stream(writer, line) => {
+                    if let Some(mut record) = self.unwritten.take() {
+                        record.operand = operand;
+
+                        line.clear();
+                        options.trace_format.write(line, &record, &self.symbols);
+
+                        if let Err(e) = writer.write_line(line) {
+                            eprintln!("error: couldn't write {}: {}", options.log, e);
+                            return Err(Stop::WriteError);
+                        }
+                    }
+                }
+
+                _ => {}
+            }
+
+            if let (Some(profiler), Some(start)) = (&mut self.profiler, start) {
+                profiler.record(current_addr, opcode, start.elapsed());
+            }
+
+            if let Some(io_log) = &mut self.io_log {
+                let (frame, scanline) = position;
+
+                let result = emulator.mmu.io_accesses().iter().try_for_each(|access| {
+                    io_log.record(IoEntry::new(access, current_addr, frame, scanline))
+                });
+
+                // Nothing more can be added to the log, so there's no point
+                // going on without it.
+                if let Err(e) = result {
+                    eprintln!("error: couldn't write the I/O log: {}", e);
+                    self.io_log = None;
+                    return Err(Stop::WriteError);
+                }
+            }
+
+            if let Some(write_log) = &mut self.write_log {
+                for &(addr, value) in emulator.mmu.writes() {
+                    write_log.record(WriteEntry {
+                        addr,
+                        value,
+                        pc: current_addr,
+                        instruction: index,
+                    });
+                }
+            }
+
+            if let Some(steps) = &mut self.steps {
+                *steps -= 1;
+            }
+
+            #[cfg(feature = "scripting")]
+            if let Some(script) = &self.script {
+                for request in script.borrow_mut().take_requests() {
+                    match request {
+                        Request::Break(addr) => {
+                            self.breakpoints.insert(addr, None);
+                        }
+                        Request::ClearBreak(addr) => {
+                            self.breakpoints.remove(&addr);
+                        }
+                        Request::Pause => self.steps = Some(0),
+                    }
+                }
+            }
+
+            if let Some(idle) = &mut self.idle {
+                if let Some(idle_loop) = idle.record(current_addr, emulator.mmu.accesses()) {
+                    eprintln!("{}", idle_loop.describe());
+                }
+            }
+
+            if let Some(stack_guard) = &mut self.stack_guard {
+                for overflow in stack_guard.check(&emulator.cpu) {
+                    let mut report = format!(
+                        "{} at {:06X}\n    {}",
+                        overflow.describe(),
+                        current_addr,
+                        emulator.cpu.register_debug()
+                    );
+
+                    write_calls(&mut report, emulator.cpu.call_stack(), &self.symbols);
+                    eprintln!("{}", report);
+                }
+            }
+
+            if !emulator.mmu.rom_writes().is_empty() {
+                if self.rom_write_pcs.insert(current_addr) {
+                    let disassembly =
+                        disasm::disassemble_at(&emulator.cpu, &emulator.mmu, current_addr);
+
+                    for &(addr, value) in emulator.mmu.rom_writes() {
+                        eprintln!(
+                            "Write to ROM: {:06X} = {:02X} at {:06X}: {}",
+                            addr,
+                            value,
+                            current_addr,
+                            disassembly.text()
+                        );
+                    }
+                }
+
+                if emulator.mmu.rom_write_policy == RomWritePolicy::Strict {
+                    return Err(Stop::RomWrite);
+                }
+            }
+
+            if let Some(mut before) = before {
+                let hits = emulator.mmu.take_watch_hits();
+                before.operand = operand;
+
+                for hit in &hits {
+                    eprintln!(
+                        "Watchpoint: {:?} {:06X} = {:02X}\n{}",
+                        hit.access,
+                        hit.addr,
+                        hit.value,
+                        trace_entry(&before, &self.symbols)
+                    );
+                }
+
+                if options.debug && !hits.is_empty() {
+                    return Err(Stop::Watchpoint);
+                }
+            }
+
+            if let Some(banner) = banner {
+                eprintln!("{}", banner);
+
+                match &self.trace {
+                    Trace::Ring(_) | Trace::Off => self.banners.push(banner),
+                    Trace::Stream(writer, _) => {
+                        if let Err(e) = writer.write_line(&banner) {
+                            eprintln!("error: couldn't write {}: {}", options.log, e);
+                            return Err(Stop::WriteError);
+                        }
+                    }
+                }
+            }
+
+            if unknown {
+                *self
+                    .unknown_opcodes
+                    .entry((current_addr, opcode))
+                    .or_insert(0) += 1;
+
+                if !options.ignore_unknown {
+                    return Err(Stop::UnknownOpcode);
+                }
+
+                emulator.skip_operand(current_addr);
+            }
+
+            if result.frame_complete {
+                return Ok(());
+            }
+        }
+    }
+
+    // Writes out everything the options ask for at the end of a run, and
+    // works out the exit status from why it stopped.
+    pub fn finish(mut self, emulator: &mut Emulator, stop: Option<Stop>) -> i32 {
+        let options = &self.options;
+
+        // Give the terminal back before printing anything else.
+        #[cfg(feature = "tui")]
+        drop(self.tui.take());
+
+        if let Some(path) = &options.save_state {
+            if let Err(e) = savestate::save(emulator, path) {
+                eprintln!("error: couldn't save {}: {}", path, e);
+            }
+        }
+
+        if let Some(io_log) = &mut self.io_log {
+            if let Err(e) = io_log.flush() {
+                eprintln!("error: couldn't write the I/O log: {}", e);
+            }
+        }
+
+        if let (Some(path), Some(events)) = (&options.events_out, &emulator.mmu.events) {
+            if let Err(e) = std::fs::write(path, events.dump(path.ends_with(".json"))) {
+                eprintln!("error: couldn't write {}: {}", path, e);
+            }
+        }
+
+        if let Some(Stop::Breakpoint(addr)) = stop {
+            eprintln!("Breakpoint hit at {:06X}", addr);
+            eprintln!("{}", trace_entry(&emulator.trace_record(), &self.symbols));
+        }
+
+        eprint!(
+            "{}",
+            debugger::format_stats(&emulator.stats(), self.run_time)
+        );
+
+        if let Some(stack_guard) = &self.stack_guard {
+            eprintln!("Lowest SP:    {:04X}", stack_guard.lowest());
+        }
+
+        if let Some(counts) = &self.opcode_counts {
+            print_opcodes(options, Some(counts));
+        }
+
+        if let Some(comparer) = &self.comparer {
+            if !matches!(stop, Some(Stop::Diverged)) {
+                let matched = match comparer.finished() {
+                    true => "all",
+                    false => "the first",
+                };
+
+                eprintln!(
+                    "Matched {} {} instructions of the reference",
+                    matched,
+                    comparer.matched()
+                );
+            }
+        }
+
+        let mut output = String::new();
+
+        if let Trace::Ring(records) = &self.trace {
+            write_records(&mut output, records, options.trace_format, &self.symbols);
+        }
+
+        for banner in &self.banners {
+            let _ = writeln!(output, "{}", banner);
+        }
+
+        if options.ignore_unknown && !self.unknown_opcodes.is_empty() {
+            let mut skipped = String::from("Unknown opcodes:\n");
+
+            for (&(addr, opcode), count) in &self.unknown_opcodes {
+                let _ = writeln!(
+                    skipped,
+                    "    {:06X} {:02X} {:<4} x{}",
+                    addr,
+                    opcode,
+                    disasm::mnemonic(opcode),
+                    count
+                );
+            }
+
+            eprint!("{}", skipped);
+            output.push_str(&skipped);
+        }
+
+        let _ = writeln!(output, "PPU: {}", emulator.mmu.ppu.register_debug());
+        let _ = writeln!(output, "SPC: {}", emulator.mmu.spc.register_debug());
+
+        #[cfg(feature = "audio")]
+        if let Some(audio) = &self.audio {
+            let stats = audio.stats();
+
+            let _ = writeln!(
+                output,
+                "Audio: {} queued | {} underruns | {} overruns",
+                stats.queued, stats.underruns, stats.overruns
+            );
+        }
+
+        match &self.trace {
+            Trace::Ring(_) | Trace::Off => {
+                let _ = write_log(&options.log, options.trace_gzip, &output);
+            }
+            Trace::Stream(writer, _) => {
+                let _ = writer.write_line(output.trim_end());
+            }
+        }
+
+        // The log needs to be complete before it can be compared, and the
+        // caller is likely to exit, which skips destructors, so flush it now.
+        self.trace = Trace::Off;
+
+        let mut movie_written = true;
+
+        if let (Some(path), Some(recorder)) = (&options.record, self.recorder.take()) {
+            if let Err(e) = recorder.finish() {
+                eprintln!("error: couldn't write {}: {}", path, e);
+                movie_written = false;
+            }
+        }
+
+        if let (Some(path), Some(cdl)) = (&options.cdl, &emulator.mmu.cdl) {
+            if let Err(e) = cdl.save(path) {
+                eprintln!("error: couldn't write {}: {}", path, e);
+            }
+        }
+
+        if let (Some(path), Some(profiler)) = (&options.profile, &self.profiler) {
+            if let Err(e) = std::fs::write(path, profiler.report(PROFILE_TOP)) {
+                eprintln!("error: couldn't write {}: {}", path, e);
+            }
+        }
+
+        for (addr, len, path) in &options.ram_dumps {
+            if let Err(e) = std::fs::write(path, emulator.mmu.peek_bytes(*addr, *len)) {
+                eprintln!("error: couldn't write {}: {}", path, e);
+            }
+        }
+
+        if let Some(path) = &options.dump_palette {
+            if let Err(e) = write_palette_png(path, emulator.mmu.ppu.cgram()) {
+                eprintln!("error: couldn't write {}: {}", path, e);
+            }
+        }
+
+        let log_matches = match (&options.compare_log, &self.expected_log) {
+            (Some(path), Some(expected)) => match read_log(&options.log) {
+                Ok(actual) => match diff_logs(expected, &actual) {
+                    Some(diff) => {
+                        eprint!("Log differs from {}:\n{}", path, diff);
+                        false
+                    }
+                    None => true,
+                },
+                Err(e) => {
+                    eprintln!("error: couldn't read {}: {}", options.log, e);
+                    false
+                }
+            },
+            _ => true,
+        };
+
+        let checks: Vec<Check> = options
+            .expectations
+            .iter()
+            .map(|&(addr, expected)| Check {
+                addr,
+                expected,
+                actual: emulator.mmu.peek_u8(addr),
+            })
+            .collect();
+
+        for check in &checks {
+            eprintln!(
+                "{} {:06X} = {:02X} (expected {:02X})",
+                if check.passed() { "PASS" } else { "FAIL" },
+                check.addr,
+                check.actual,
+                check.expected
+            );
+        }
+
+        if options.headless {
+            let reason = match stop {
+                Some(Stop::UnknownOpcode) => "unknown_opcode",
+                Some(Stop::InstructionLimit) => "instruction_limit",
+                Some(Stop::FrameLimit) => "frame_limit",
+                Some(Stop::TimeLimit) => "time_limit",
+                Some(Stop::Interrupted) => "interrupted",
+                Some(Stop::Target) => "target",
+                Some(Stop::Breakpoint(_)) => "breakpoint",
+                Some(Stop::Watchpoint) => "watchpoint",
+                Some(Stop::Step) => "step",
+                Some(Stop::Diverged) => "diverged",
+                Some(Stop::RomWrite) => "rom_write",
+                Some(Stop::MovieError) => "movie_error",
+                Some(Stop::WriteError) => "write_error",
+                None => "closed",
+            };
+
+            let unknown_opcodes: Vec<(u32, u8, u64)> = self
+                .unknown_opcodes
+                .iter()
+                .map(|(&(addr, opcode), &count)| (addr, opcode, count))
+                .collect();
+
+            println!(
+                "{}",
+                summary_json(emulator, reason, &unknown_opcodes, &checks)
+            );
+        }
+
+        let code = match stop {
+            _ if !movie_written => 1,
+            _ if checks.iter().any(|check| !check.passed()) => 6,
+            _ if !log_matches => 7,
+
+            Some(Stop::Breakpoint(_)) => 3,
+            Some(Stop::Diverged) => 8,
+            Some(Stop::RomWrite) => 10,
+            Some(Stop::MovieError | Stop::WriteError) => 1,
+            // Reaching the instruction is the point of --state-at.
+            Some(Stop::InstructionLimit) if options.state_at.is_some() => 0,
+            Some(Stop::InstructionLimit | Stop::FrameLimit | Stop::TimeLimit) => 4,
+            Some(Stop::Interrupted) => 130,
+
+            // STP isn't implemented yet, so it ends up here too.
+            Some(Stop::UnknownOpcode) if options.headless => 5,
+
+            Some(Stop::UnknownOpcode | Stop::Target | Stop::Watchpoint | Stop::Step) | None => 0,
+        };
+
+        // Exiting skips the emulator's destructor, so write the battery save
+        // first.
+        if let Err(e) = emulator.flush_battery() {
+            self.report_battery_error(e);
+        }
+
+        code
+    }
+
+    // The game carries on when the battery save can't be written, so that
+    // the save can be retried, or made some other way.
+    fn report_battery_error(&self, error: io::Error) {
+        if let Some(path) = self.options.sram_path() {
+            eprintln!("error: couldn't save {}: {}", path.display(), error);
+        }
+    }
+
+    // Writes out the trace after a panic, followed by the instruction that
+    // was running when it happened. In stream mode, everything up to that
+    // point has already been written.
+    fn write_panic_log(&self, emulator: &Emulator, payload: &dyn Any) {
+        let options = &self.options;
+
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => "unknown panic".to_string(),
+            },
+        };
+
+        let mut banner = match self.recent.back() {
+            Some(addr) => format!("*** Panicked while executing {:06X}: {}", addr, message),
+            None => format!("*** Panicked: {}", message),
+        };
+
+        write_calls(&mut banner, emulator.cpu.call_stack(), &self.symbols);
+
+        if let (Some(write_log), Some(&addr)) = (&self.write_log, self.recent.back()) {
+            write_log.write_report(&mut banner, addr, 4, REPORTED_WRITES);
+        }
+
+        match &self.trace {
+            Trace::Ring(records) => {
+                let mut output = String::new();
+                write_records(&mut output, records, options.trace_format, &self.symbols);
+
+                let _ = writeln!(output, "{}", banner);
+                let _ = write_log(&options.log, options.trace_gzip, &output);
+            }
+
+            Trace::Stream(writer, _) => {
+                if let Some(record) = &self.unwritten {
+                    let mut line = String::new();
+                    options.trace_format.write(&mut line, record, &self.symbols);
+                    let _ = writer.write_line(&line);
+                }
+
+                let _ = writer.write_line(&banner);
+            }
+
+            Trace::Off => {
+                let _ = write_log(&options.log, options.trace_gzip, &format!("{}\n", banner));
+            }
+        }
+
+        eprintln!(
+            "{}\nThe trace up to this point was written to {}",
+            banner, options.log
+        );
+    }
+}
+
+fn write_records(
+    output: &mut String,
+    records: &VecDeque<TraceRecord>,
+    format: TraceFormat,
+    symbols: &Symbols,
+) {
+    for record in records {
+        format.write(output, record, symbols);
+        output.push('\n');
+    }
+}
+
+// Disassembles the instructions that ran just before the current one, then
+// the current one and a few after it.
+fn unknown_opcode_window(emulator: &mut Emulator, recent: &VecDeque<u32>) -> Vec<Disassembly> {
+    let cpu = &emulator.cpu;
+    let mut window: Vec<Disassembly> = recent
+        .iter()
+        .map(|&addr| disasm::disassemble_at(cpu, &emulator.mmu, addr))
+        .collect();
+
+    let mut addr = cpu.current_addr();
+
+    for _ in 0..=UNKNOWN_WINDOW {
+        let disassembly = disasm::disassemble_at(cpu, &emulator.mmu, addr);
+        let len = disassembly.bytes().len() as u16;
+
+        window.push(disassembly);
+        addr = (addr & 0xFF_0000) | (addr as u16).wrapping_add(len) as u32;
+    }
+
+    window
+}
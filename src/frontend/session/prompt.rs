@@ -0,0 +1,530 @@
+use std::fmt::Write;
+use std::io::Write as _;
+
+use crate::debugger::{self, Command};
+use crate::emulator::Emulator;
+use crate::frontend::frame_dump::write_png;
+use crate::frontend::io_log::IoLog;
+use crate::frontend::palette::{format_palette, write_palette_png};
+use crate::frontend::savestate;
+#[cfg(feature = "tui")]
+use crate::frontend::tui::{Tui, View};
+use crate::mmu::MapMode;
+use crate::ram_search::{self, Filter, RamSearch, Width};
+use crate::trace::trace_entry;
+
+use super::{ReturnTarget, Session};
+
+impl Session {
+    // Reads and runs debugger commands until execution should resume. Returns
+    // false if the user asked to quit.
+    pub(super) fn debug_prompt(&mut self, emulator: &mut Emulator) -> bool {
+        #[cfg(feature = "tui")]
+        if let Some(mut tui) = self.tui.take() {
+            let resume = self.tui_prompt(emulator, &mut tui);
+            self.tui = Some(tui);
+
+            return resume;
+        }
+
+        let next = emulator.cpu.peek_next(&emulator.mmu);
+
+        println!("{}", debugger::format_decoded(&next));
+
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+
+        loop {
+            print!("> ");
+            let _ = std::io::stdout().flush();
+
+            line.clear();
+
+            // Treat EOF the same as quitting.
+            if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+                return false;
+            }
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let command = match debugger::parse_command(&line) {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}\n{}", e, debugger::HELP);
+                    continue;
+                }
+            };
+
+            if let Some(resume) = self.run_command(emulator, command) {
+                return resume;
+            }
+        }
+    }
+
+    // Shows the TUI until one of its keys resumes execution. Returns false if
+    // the user asked to quit.
+    #[cfg(feature = "tui")]
+    fn tui_prompt(&mut self, emulator: &mut Emulator, tui: &mut Tui) -> bool {
+        loop {
+            let view = View {
+                emulator,
+                breakpoints: &self.breakpoints,
+                io_log: self.io_log.as_ref(),
+            };
+
+            let command = match tui.prompt(&view) {
+                Ok(command) => command,
+                Err(e) => {
+                    eprintln!("error: couldn't draw the debugger: {}", e);
+                    return false;
+                }
+            };
+
+            if let Some(resume) = self.run_command(emulator, command) {
+                return resume;
+            }
+        }
+    }
+
+    // Runs a single debugger command. Returns whether execution should resume,
+    // or None if the debugger should wait for another command.
+    fn run_command(&mut self, emulator: &mut Emulator, command: Command) -> Option<bool> {
+        match command {
+            Command::Step(n) => {
+                self.steps = Some(n);
+                self.step_over = None;
+                self.resuming = true;
+                return Some(true);
+            }
+
+            // Calls run until they return to the next instruction, and
+            // anything else is a single step. An interrupt that's about to be
+            // taken is stepped over the same way, running the handler until it
+            // returns to the current instruction.
+            Command::StepOver => {
+                let next = emulator.cpu.peek_next(&emulator.mmu);
+                let len = next.bytes().len() as u16;
+                let pc = next.disassembly.pc;
+                let sp = emulator.cpu.sp();
+
+                let addr = match next.disassembly.opcode() {
+                    _ if emulator.cpu.pending_interrupt().is_some() => Some(pc),
+
+                    // JSR, JSL and JSR (addr,X)
+                    0x20 | 0x22 | 0xFC => {
+                        Some((pc & 0xFF_0000) | (pc as u16).wrapping_add(len) as u32)
+                    }
+                    _ => None,
+                };
+
+                match addr {
+                    Some(addr) => {
+                        self.steps = None;
+                        self.step_over = Some(ReturnTarget {
+                            addr: Some(addr),
+                            sp,
+                        });
+                    }
+                    None => {
+                        self.steps = Some(1);
+                        self.step_over = None;
+                    }
+                }
+
+                self.resuming = true;
+                return Some(true);
+            }
+
+            // Runs until the innermost call on the call stack returns, by
+            // whatever means. Its frame is gone once the stack pointer is back
+            // to where it was before the call.
+            Command::Finish => {
+                let Some(frame) = emulator.cpu.call_stack().last() else {
+                    println!("Not inside a subroutine");
+                    return None;
+                };
+
+                self.steps = None;
+                self.step_over = Some(ReturnTarget {
+                    addr: None,
+                    sp: frame.sp,
+                });
+
+                self.resuming = true;
+                return Some(true);
+            }
+
+            Command::Continue => {
+                self.steps = None;
+                self.step_over = None;
+                self.resuming = true;
+                return Some(true);
+            }
+
+            Command::Registers => {
+                println!("{}", trace_entry(&emulator.trace_record(), &self.symbols));
+
+                // The record is for the next instruction, which hasn't run yet,
+                // so the last one's operand goes on a line of its own.
+                if let Some(operand) = emulator.cpu.operand() {
+                    println!("Last operand: {}", operand);
+                }
+            }
+
+            Command::Disassemble(None) => {
+                let next = emulator.cpu.peek_next(&emulator.mmu);
+
+                println!("{}", debugger::format_decoded(&next));
+            }
+
+            Command::Disassemble(Some(addr)) => {
+                let opcode = emulator.mmu.peek_u8(addr);
+
+                println!("{}", debugger::format_instruction(addr, opcode));
+            }
+
+            Command::Memory(addr, len) => {
+                let bytes = emulator.mmu.peek_bytes(addr, len);
+
+                print!("{}", debugger::hexdump(addr, &bytes));
+            }
+
+            Command::Break(addr, condition) => {
+                self.breakpoints.insert(addr, condition);
+            }
+
+            Command::ClearBreak(addr) => {
+                self.breakpoints.remove(&addr);
+            }
+
+            Command::Backtrace => match emulator.cpu.call_stack() {
+                [] => println!("Not inside a subroutine"),
+                frames => print!("{}", debugger::format_backtrace(frames, &self.symbols)),
+            },
+
+            Command::Profile(top) => match &self.profiler {
+                Some(profiler) => print!("{}", profiler.report(top)),
+                None => println!("Profiling requires --profile"),
+            },
+
+            Command::Io(n) => match &self.io_log {
+                Some(io_log @ IoLog::Ring(..)) => {
+                    let mut line = String::new();
+
+                    for entry in io_log.recent(n) {
+                        line.clear();
+                        entry.format(&mut line);
+                        println!("{}", line);
+                    }
+                }
+                _ => println!("Showing register accesses requires --io-ring"),
+            },
+
+            Command::Events(n) => match &emulator.mmu.events {
+                Some(events) => {
+                    let mut line = String::new();
+
+                    for event in events.recent(n) {
+                        line.clear();
+                        event.format(&mut line);
+                        println!("{}", line);
+                    }
+                }
+                None => println!("Showing hardware events requires --events"),
+            },
+
+            Command::Writes(addr, len) => match &self.write_log {
+                Some(write_log) => {
+                    let mut line = String::new();
+
+                    for entry in write_log.to_range(addr, len) {
+                        line.clear();
+                        entry.format(&mut line);
+                        println!("{}", line);
+                    }
+                }
+                None => println!("Showing writes requires --write-ring"),
+            },
+
+            Command::Palette(path) => {
+                print!("{}", format_palette(emulator.mmu.ppu.cgram()));
+
+                if let Some(path) = path {
+                    match write_palette_png(&path, emulator.mmu.ppu.cgram()) {
+                        Ok(()) => println!("Saved to {}", path),
+                        Err(e) => println!("couldn't save {}: {}", path, e),
+                    }
+                }
+            }
+
+            Command::Tiles(depth, palette, path) => {
+                let image = emulator.mmu.ppu.render_tile_sheet(depth, palette);
+
+                match write_png(
+                    &path,
+                    &image.pixels,
+                    image.width as u32,
+                    image.height as u32,
+                ) {
+                    Ok(()) => println!("Saved to {}", path),
+                    Err(e) => println!("couldn't save {}: {}", path, e),
+                }
+            }
+
+            Command::Tilemap(bg, path) => match emulator.mmu.ppu.render_tilemap(bg) {
+                Some(image) => {
+                    match write_png(
+                        &path,
+                        &image.pixels,
+                        image.width as u32,
+                        image.height as u32,
+                    ) {
+                        Ok(()) => println!("Saved to {}", path),
+                        Err(e) => println!("couldn't save {}: {}", path, e),
+                    }
+                }
+                None => println!("BG{} isn't a tiled layer in this mode", bg + 1),
+            },
+
+            Command::SearchStart(width) => {
+                let search = RamSearch::start(emulator.mmu.wram(), width);
+                println!("Searching {} addresses", search.candidates().len());
+
+                self.ram_search = Some(search);
+            }
+
+            Command::SearchFilter(filter) => match &mut self.ram_search {
+                Some(search) => match filter {
+                    Filter::Equal(value) if search.width() == Width::Byte && value > 0xFF => {
+                        println!("{:#X} doesn't fit in a byte, use search start 16", value)
+                    }
+                    _ => {
+                        let left = search.filter(emulator.mmu.wram(), filter);
+                        println!("Addresses left: {}", left);
+                    }
+                },
+                None => println!("No search in progress, use search start"),
+            },
+
+            Command::SearchList => match &self.ram_search {
+                Some(search) => {
+                    let wram = emulator.mmu.wram();
+                    let candidates = search.candidates();
+
+                    for &offset in candidates.iter().take(ram_search::LIST_LIMIT) {
+                        let addr = 0x7E_0000 + offset;
+
+                        let mut line = match search.width() {
+                            Width::Byte => format!(
+                                "{:06X}: {:02X} (was {:02X})",
+                                addr,
+                                search.read(wram, offset),
+                                search.previous(offset)
+                            ),
+                            Width::Word => format!(
+                                "{:06X}: {:04X} (was {:04X})",
+                                addr,
+                                search.read(wram, offset),
+                                search.previous(offset)
+                            ),
+                        };
+
+                        let last_write = self.write_log.as_ref().and_then(|write_log| {
+                            write_log
+                                .to_range(addr, search.width().bytes() as u32)
+                                .last()
+                        });
+
+                        if let Some(entry) = last_write {
+                            let _ = write!(line, ", last written by {:06X}", entry.pc);
+                        }
+
+                        println!("{}", line);
+                    }
+
+                    if candidates.len() > ram_search::LIST_LIMIT {
+                        println!("... and {} more", candidates.len() - ram_search::LIST_LIMIT);
+                    }
+                }
+                None => println!("No search in progress, use search start"),
+            },
+
+            Command::Cheats => {
+                if emulator.mmu.cheats().is_empty() {
+                    println!("No cheats, use cheat add");
+                }
+
+                for (i, cheat) in emulator.mmu.cheats().iter().enumerate() {
+                    println!("{}: {}", i, cheat);
+                }
+            }
+
+            Command::AddCheat(cheat) => {
+                let description = cheat.to_string();
+
+                match emulator.mmu.add_cheat(cheat) {
+                    Ok(()) => println!("{}: {}", emulator.mmu.cheats().len() - 1, description),
+                    Err(e) => println!("couldn't add {}: {}", description, e),
+                }
+            }
+
+            Command::EnableCheat(i, enabled) => {
+                if emulator.mmu.set_cheat_enabled(i, enabled) {
+                    println!("{}: {}", i, emulator.mmu.cheats()[i]);
+                } else {
+                    println!("No cheat {}", i);
+                }
+            }
+
+            Command::Stats => {
+                print!(
+                    "{}",
+                    debugger::format_stats(&emulator.stats(), self.run_time)
+                );
+            }
+
+            Command::Coverage => match &emulator.mmu.cdl {
+                Some(cdl) => {
+                    let bank_size = match emulator.mmu.map_mode() {
+                        MapMode::LoRom => 0x8000,
+                        MapMode::HiRom => 0x10000,
+                    };
+
+                    print!("{}", debugger::format_coverage(&cdl.coverage(bank_size)));
+                }
+                None => println!("Coverage requires --cdl"),
+            },
+
+            Command::Rewind => match self.rewind.as_mut().map(|r| r.rewind(emulator)) {
+                Some(Some(frame)) => {
+                    let next = emulator.cpu.peek_next(&emulator.mmu);
+
+                    println!("Rewound to frame {} ({} steps)", frame, emulator.steps());
+                    println!("{}", debugger::format_decoded(&next));
+                }
+                Some(None) => println!("No snapshots left to rewind to"),
+                None => println!("Rewinding requires --rewind"),
+            },
+
+            Command::SaveState(path) => match savestate::save(emulator, &path) {
+                Ok(()) => println!("Saved to {}", path),
+                Err(e) => println!("couldn't save {}: {}", path, e),
+            },
+
+            Command::Quit => return Some(false),
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{lorom, Asm};
+    use crate::frontend::options::Options;
+    use crate::frontend::session::{self, Stop};
+
+    // A headless session, without the debugger prompt, so that stepping
+    // stops the run instead of asking for another command.
+    fn session(rom: Vec<u8>) -> (Session, Emulator) {
+        let args = ["test.sfc", "--no-sram"].map(String::from);
+        let options = Options::parse(args.into_iter()).unwrap();
+
+        let mut emulator = session::prepare(&options, rom).unwrap();
+        let session = Session::new(options, &mut emulator).unwrap();
+
+        (session, emulator)
+    }
+
+    // Runs to the breakpoint at `addr`, then clears it.
+    fn run_to(session: &mut Session, emulator: &mut Emulator, addr: u32) {
+        session.run_command(emulator, Command::Break(addr, None));
+        let stop = session.run(emulator);
+        assert!(matches!(stop, Some(Stop::Breakpoint(a)) if a == addr));
+        session.run_command(emulator, Command::ClearBreak(addr));
+    }
+
+    // Resumes with the command and runs until it stops again.
+    fn resume(session: &mut Session, emulator: &mut Emulator, command: Command) {
+        assert_eq!(session.run_command(emulator, command), Some(true));
+        assert!(matches!(session.run(emulator), Some(Stop::Step)));
+    }
+
+    #[test]
+    fn next_steps_over_recursion() {
+        // Recurses three deep, counting the returns at $10.
+        #[rustfmt::skip]
+        let code = Asm::at(0x8000)
+            .clc().xce()
+            .ldx_imm8(3)
+            .jsr_to("recurse")
+            .label("spin")
+            .bra("spin")
+            .label("recurse")                  // $8009
+            .dex()
+            .beq("done")
+            .jsr_to("recurse")                 // $800C
+            .label("done")
+            .inc_dp(0x10)                      // $800F
+            .rts()
+            .assemble()
+            .unwrap();
+
+        let (mut session, mut emulator) = session(lorom(&code));
+
+        // The first time round, one call deep.
+        run_to(&mut session, &mut emulator, 0x00_800C);
+        assert_eq!(emulator.cpu.sp(), 0x01FD);
+
+        // The deeper calls return to the same place first, but with more on
+        // the stack.
+        resume(&mut session, &mut emulator, Command::StepOver);
+        assert_eq!(emulator.cpu.current_addr(), 0x00_800F);
+        assert_eq!(emulator.cpu.sp(), 0x01FD);
+        assert_eq!(emulator.mmu.peek_u8(0x7E_0010), 2);
+
+        // Anything other than a call is a single step.
+        resume(&mut session, &mut emulator, Command::StepOver);
+        assert_eq!(emulator.cpu.current_addr(), 0x00_8011);
+        assert_eq!(emulator.mmu.peek_u8(0x7E_0010), 3);
+    }
+
+    #[test]
+    fn finish_runs_through_an_nmi() {
+        #[rustfmt::skip]
+        let code = Asm::at(0x8000)
+            .clc().xce()
+            .lda_imm8(0x80)
+            .sta_abs(0x4200)
+            .jsr_to("routine")
+            .label("spin")                     // $800A
+            .bra("spin")
+            .label("routine")                  // $800C
+            .wai()
+            .rts()
+            .assemble()
+            .unwrap();
+
+        // The NMI handler counts at $10.
+        let mut rom = lorom(&code);
+        rom[0x7F00..0x7F03].copy_from_slice(&[0xE6, 0x10, 0x40]);
+        rom[0x7FEA..0x7FEC].copy_from_slice(&[0x00, 0xFF]);
+
+        let (mut session, mut emulator) = session(rom);
+
+        // Outside of any call, there's nothing to finish.
+        assert_eq!(session.run_command(&mut emulator, Command::Finish), None);
+
+        // The handler runs while the routine waits, and doesn't stop it.
+        run_to(&mut session, &mut emulator, 0x00_800C);
+        resume(&mut session, &mut emulator, Command::Finish);
+
+        assert_eq!(emulator.cpu.current_addr(), 0x00_800A);
+        assert_eq!(emulator.cpu.sp(), 0x01FF);
+        assert_eq!(emulator.mmu.peek_u8(0x7E_0010), 1);
+        assert!(emulator.cpu.call_stack().is_empty());
+    }
+}
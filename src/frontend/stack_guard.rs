@@ -0,0 +1,295 @@
+use crate::cpu::Cpu;
+
+// Memory that the stack shouldn't reach. The stack is always in bank 0, so
+// these are offsets into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardRange {
+    // The 256 bytes from D, wherever it points at the time.
+    DirectPage,
+    Range(u16, u16),
+}
+
+impl GuardRange {
+    fn bounds(self, cpu: &Cpu) -> (u16, u16) {
+        match self {
+            GuardRange::DirectPage => {
+                let start = cpu.state().direct_page;
+                (start, start.saturating_add(0xFF))
+            }
+            GuardRange::Range(start, end) => (start, end),
+        }
+    }
+}
+
+// Something the stack did that it shouldn't have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    // It grew further below where TXS last put it than the limit.
+    Depth { sp: u16, base: u16 },
+
+    // A push landed inside one of the guarded ranges.
+    Range { sp: u16, start: u16, end: u16 },
+
+    // SP left page 1 in emulation mode, which the hardware doesn't allow.
+    Page { sp: u16 },
+}
+
+impl Overflow {
+    pub fn describe(&self) -> String {
+        match *self {
+            Overflow::Depth { sp, base } => format!(
+                "Stack overflow: SP {:04X} is {} bytes below its base of {:04X}",
+                sp,
+                base.wrapping_sub(sp),
+                base
+            ),
+            Overflow::Range { sp, start, end } => format!(
+                "Stack overflow: SP {:04X} has pushed into {:04X}-{:04X}",
+                sp, start, end
+            ),
+            Overflow::Page { sp } => {
+                format!(
+                    "Stack overflow: SP {:04X} has left page 1 in emulation mode",
+                    sp
+                )
+            }
+        }
+    }
+}
+
+// Watches for the stack growing into memory that's being used for
+// something else, which usually shows up much later as variables going
+// wrong. Each problem is reported once, then not again until the stack has
+// shrunk back out of it.
+pub struct StackGuard {
+    depth: Option<u16>,
+    ranges: Vec<GuardRange>,
+
+    // The lowest SP seen since TXS last moved the stack.
+    lowest: u16,
+    base: u16,
+    prev_sp: u16,
+
+    too_deep: bool,
+    in_range: Vec<bool>,
+    off_page: bool,
+}
+
+impl StackGuard {
+    pub fn new(depth: Option<u16>, ranges: Vec<GuardRange>, cpu: &Cpu) -> StackGuard {
+        StackGuard {
+            depth,
+            in_range: vec![false; ranges.len()],
+            ranges,
+
+            lowest: cpu.sp(),
+            base: cpu.sp_base(),
+            prev_sp: cpu.sp(),
+
+            too_deep: false,
+            off_page: false,
+        }
+    }
+
+    pub fn lowest(&self) -> u16 {
+        self.lowest
+    }
+
+    // Takes the CPU after each instruction, returning anything that went
+    // wrong for the first time.
+    pub fn check(&mut self, cpu: &Cpu) -> Vec<Overflow> {
+        let mut overflows = Vec::new();
+        let sp = cpu.sp();
+        let prev_sp = std::mem::replace(&mut self.prev_sp, sp);
+
+        if cpu.sp_base() != self.base {
+            self.base = cpu.sp_base();
+            self.lowest = sp;
+            self.too_deep = false;
+        }
+
+        self.lowest = self.lowest.min(sp);
+
+        if let Some(depth) = self.depth {
+            let too_deep = sp < self.base && self.base - sp > depth;
+
+            if too_deep && !self.too_deep {
+                overflows.push(Overflow::Depth {
+                    sp,
+                    base: self.base,
+                });
+            }
+
+            self.too_deep = too_deep;
+        }
+
+        for (range, in_range) in self.ranges.iter().zip(&mut self.in_range) {
+            let (start, end) = range.bounds(cpu);
+
+            // Anything pushed by the instruction is between the two stack
+            // pointers.
+            let pushed = sp < prev_sp && sp < end && prev_sp >= start;
+
+            if pushed && !*in_range {
+                overflows.push(Overflow::Range { sp, start, end });
+            }
+
+            *in_range = sp < end && (pushed || *in_range);
+        }
+
+        let off_page = cpu.emulation() && sp >> 8 != 0x01;
+
+        if off_page && !self.off_page {
+            overflows.push(Overflow::Page { sp });
+        }
+
+        self.off_page = off_page;
+
+        overflows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::emulator::Emulator;
+    use crate::mmu::MapMode;
+
+    // Puts the stack at $0FFF and D at $0E00, then calls itself forever.
+    fn recursing() -> Emulator {
+        #[rustfmt::skip]
+        let code = [
+            0x18,             // CLC
+            0xFB,             // XCE
+            0xC2, 0x30,       // REP #$30
+            0xA2, 0xFF, 0x0F, // LDX #$0FFF
+            0x9A,             // TXS
+            0xA9, 0x00, 0x0E, // LDA #$0E00
+            0x48,             // PHA
+            0x2B,             // PLD
+            // recurse:
+            0x20, 0x0D, 0x80, // JSR recurse
+        ];
+
+        let mut rom = vec![0; 0x8000];
+        rom[..code.len()].copy_from_slice(&code);
+        rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+        let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+
+        for _ in 0..8 {
+            emulator.step();
+        }
+
+        emulator
+    }
+
+    // Makes the calls, returning what was reported along with how many
+    // calls deep it was.
+    fn recurse(
+        guard: &mut StackGuard,
+        emulator: &mut Emulator,
+        calls: usize,
+    ) -> Vec<(usize, Overflow)> {
+        let mut reported = Vec::new();
+
+        for call in 1..=calls {
+            emulator.step();
+
+            for overflow in guard.check(&emulator.cpu) {
+                reported.push((call, overflow));
+            }
+        }
+
+        reported
+    }
+
+    #[test]
+    fn warns_at_the_depth() {
+        let mut emulator = recursing();
+        let mut guard = StackGuard::new(Some(64), Vec::new(), &emulator.cpu);
+
+        // Each call pushes two bytes, so the 33rd is the first to go more
+        // than 64 below the base, and it's only reported the once.
+        let reported = recurse(&mut guard, &mut emulator, 100);
+
+        assert_eq!(
+            reported,
+            [(
+                33,
+                Overflow::Depth {
+                    sp: 0x0FBD,
+                    base: 0x0FFF
+                }
+            )]
+        );
+        assert_eq!(guard.lowest(), 0x0FFF - 200);
+        assert_eq!(
+            reported[0].1.describe(),
+            "Stack overflow: SP 0FBD is 66 bytes below its base of 0FFF"
+        );
+    }
+
+    #[test]
+    fn warns_on_pushes_into_ranges() {
+        let mut emulator = recursing();
+        let ranges = vec![GuardRange::Range(0x0F00, 0x0F7F), GuardRange::DirectPage];
+        let mut guard = StackGuard::new(None, ranges, &emulator.cpu);
+
+        let reported = recurse(&mut guard, &mut emulator, 200);
+
+        assert_eq!(
+            reported,
+            [
+                (
+                    65,
+                    Overflow::Range {
+                        sp: 0x0F7D,
+                        start: 0x0F00,
+                        end: 0x0F7F
+                    }
+                ),
+                (
+                    129,
+                    Overflow::Range {
+                        sp: 0x0EFD,
+                        start: 0x0E00,
+                        end: 0x0EFF
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_again_after_unwinding() {
+        let mut emulator = recursing();
+        let mut guard = StackGuard::new(Some(16), Vec::new(), &emulator.cpu);
+
+        assert_eq!(recurse(&mut guard, &mut emulator, 20).len(), 1);
+
+        // Back out of it, as if every call had returned.
+        emulator.cpu.set_sp(0x0FFF);
+        assert!(guard.check(&emulator.cpu).is_empty());
+
+        let reported = recurse(&mut guard, &mut emulator, 20);
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].0, 9);
+        assert_eq!(guard.lowest(), 0x0FFF - 40);
+    }
+
+    #[test]
+    fn warns_when_leaving_page_one() {
+        let mut emulator = recursing();
+        emulator.cpu.set_emulation(true);
+        let mut guard = StackGuard::new(None, Vec::new(), &emulator.cpu);
+
+        emulator.cpu.set_sp(0x0101);
+        assert!(guard.check(&emulator.cpu).is_empty());
+
+        emulator.cpu.set_sp(0x00FF);
+        assert_eq!(guard.check(&emulator.cpu), [Overflow::Page { sp: 0x00FF }]);
+        assert!(guard.check(&emulator.cpu).is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt::Write;
+
+use super::checksum;
+use crate::cpu::Register;
+use crate::emulator::Emulator;
+
+// A value that a headless run expects to find in memory once it finishes,
+// e.g. where a test ROM writes its results.
+pub struct Check {
+    pub addr: u32,
+    pub expected: u8,
+    pub actual: u8,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+// Describes the state of the machine at the end of a headless run, as a
+// single line of JSON.
+//
+// The WRAM hash is written as a hex string, as not every JSON parser can
+// hold a full 64-bit integer.
+pub fn summary_json(
+    emulator: &Emulator,
+    reason: &str,
+    unknown_opcodes: &[(u32, u8, u64)],
+    checks: &[Check],
+) -> String {
+    let cpu = &emulator.cpu;
+
+    let mut unknown = String::new();
+
+    for (i, (addr, opcode, count)) in unknown_opcodes.iter().enumerate() {
+        if i > 0 {
+            unknown.push(',');
+        }
+
+        let _ = write!(
+            unknown,
+            "{{\"addr\":{},\"opcode\":{},\"count\":{}}}",
+            addr, opcode, count
+        );
+    }
+
+    let mut checked = String::new();
+
+    for (i, check) in checks.iter().enumerate() {
+        if i > 0 {
+            checked.push(',');
+        }
+
+        let _ = write!(
+            checked,
+            "{{\"addr\":{},\"expected\":{},\"actual\":{},\"passed\":{}}}",
+            check.addr,
+            check.expected,
+            check.actual,
+            check.passed()
+        );
+    }
+
+    format!(
+        concat!(
+            "{{\"reason\":\"{}\",\"pc\":{},",
+            "\"registers\":{{\"a\":{},\"x\":{},\"y\":{},\"sp\":{},\"d\":{},\"db\":{},\"p\":{},\"e\":{}}},",
+            "\"wram_hash\":\"{:016x}\",\"steps\":{},\"instructions\":{},\"cycles\":{},\"frames\":{},\"unknown_opcodes\":[{}],\"checks\":[{}]}}"
+        ),
+        reason,
+        cpu.current_addr(),
+        cpu.get_register(Register::A),
+        cpu.get_register(Register::X),
+        cpu.get_register(Register::Y),
+        cpu.sp(),
+        cpu.get_register(Register::D),
+        cpu.data_bank(),
+        cpu.status(),
+        cpu.emulation(),
+        checksum(emulator.mmu.wram()),
+        emulator.steps(),
+        emulator.instructions(),
+        emulator.stats().cycles,
+        emulator.frame(),
+        unknown,
+        checked,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::MapMode;
+
+    #[test]
+    fn summary_fields() {
+        #[rustfmt::skip]
+        let code = [
+            0x18,             // CLC
+            0xFB,             // XCE
+            0xC2, 0x30,       // REP #$30
+            0xA9, 0x34, 0x12, // LDA #$1234
+            0xA2, 0x78, 0x56, // LDX #$5678
+            0x8D, 0x00, 0x01, // STA $0100
+            0xDB,             // STP
+        ];
+
+        let mut rom = vec![0; 0x8000];
+        rom[..code.len()].copy_from_slice(&code);
+        rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+        let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+
+        for _ in 0..6 {
+            emulator.step();
+        }
+
+        let checks = [
+            Check {
+                addr: 0x7E_0100,
+                expected: 0x34,
+                actual: emulator.mmu.peek_u8(0x7E_0100),
+            },
+            Check {
+                addr: 0x7E_0101,
+                expected: 0x00,
+                actual: emulator.mmu.peek_u8(0x7E_0101),
+            },
+        ];
+
+        let json = summary_json(&emulator, "unknown_opcode", &[(0x800D, 0xDB, 1)], &checks);
+
+        assert_eq!(
+            json,
+            format!(
+                concat!(
+                    "{{\"reason\":\"unknown_opcode\",\"pc\":32781,",
+                    "\"registers\":{{\"a\":4660,\"x\":22136,\"y\":0,\"sp\":511,\"d\":0,\"db\":0,\"p\":1,\"e\":false}},",
+                    "\"wram_hash\":\"{:016x}\",\"steps\":6,\"instructions\":6,\"cycles\":{},\"frames\":0,",
+                    "\"unknown_opcodes\":[{{\"addr\":32781,\"opcode\":219,\"count\":1}}],",
+                    "\"checks\":[{{\"addr\":8257792,\"expected\":52,\"actual\":52,\"passed\":true}},",
+                    "{{\"addr\":8257793,\"expected\":0,\"actual\":18,\"passed\":false}}]}}"
+                ),
+                checksum(emulator.mmu.wram()),
+                emulator.stats().cycles,
+            )
+        );
+    }
+}
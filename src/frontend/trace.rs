@@ -0,0 +1,249 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+type SharedWriter = Arc<Mutex<Option<Box<dyn Write + Send>>>>;
+
+// How much of the log is buffered before it's written out. Lines are only
+// around 100 bytes, so writing each one to the file, or through the
+// compressor, on its own would be slow.
+const BUFFER_SIZE: usize = 1 << 20;
+
+// Gzip files start with these bytes, which lets logs be read back without
+// having to know how they were written.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+// Writes trace lines to a file as execution proceeds, rather than keeping
+// them in memory until the end of the run.
+pub struct TraceWriter {
+    // This is shared with the panic hook, so that the log gets flushed even
+    // if the emulator crashes.
+    writer: SharedWriter,
+}
+
+impl TraceWriter {
+    pub fn create(path: impl AsRef<Path>, gzip: bool) -> io::Result<TraceWriter> {
+        TraceWriter::new(
+            BufWriter::with_capacity(BUFFER_SIZE, File::create(path)?),
+            gzip,
+        )
+    }
+
+    // The same as create, but writing to anything, for frontends that have
+    // nowhere to put a file.
+    pub fn new(writer: impl Write + Send + 'static, gzip: bool) -> io::Result<TraceWriter> {
+        let writer: Box<dyn Write + Send> = if gzip {
+            compress(writer)?
+        } else {
+            Box::new(writer)
+        };
+
+        Ok(TraceWriter {
+            writer: Arc::new(Mutex::new(Some(writer))),
+        })
+    }
+
+    pub fn write_line(&self, line: &str) -> io::Result<()> {
+        match &mut *self.writer.lock().unwrap() {
+            Some(writer) => writeln!(writer, "{}", line),
+            None => Ok(()),
+        }
+    }
+
+    pub fn write_str(&self, text: &str) -> io::Result<()> {
+        match &mut *self.writer.lock().unwrap() {
+            Some(writer) => writer.write_all(text.as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn install_panic_hook(&self) {
+        let writer = Arc::downgrade(&self.writer);
+        let previous = std::panic::take_hook();
+
+        std::panic::set_hook(Box::new(move |info| {
+            // If the panic happened mid-write, the lock will already be held,
+            // so don't wait for it.
+            if let Some(writer) = writer.upgrade() {
+                if let Ok(mut writer) = writer.try_lock() {
+                    if let Some(writer) = &mut *writer {
+                        let _ = writer.flush();
+                    }
+                }
+            }
+
+            previous(info);
+        }));
+    }
+}
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        // Dropping the inner writer (rather than just flushing it) lets the
+        // gzip encoder write its trailer.
+        if let Ok(mut writer) = self.writer.lock() {
+            if let Some(mut writer) = writer.take() {
+                let _ = writer.flush();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "trace-gzip")]
+fn compress(writer: impl Write + Send + 'static) -> io::Result<Box<dyn Write + Send>> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    // Buffer what goes into the encoder as well as what comes out of it, so
+    // that it compresses big blocks rather than single lines.
+    let encoder = GzEncoder::new(writer, Compression::fast());
+
+    Ok(Box::new(BufWriter::with_capacity(BUFFER_SIZE, encoder)))
+}
+
+#[cfg(not(feature = "trace-gzip"))]
+fn compress(_writer: impl Write + Send + 'static) -> io::Result<Box<dyn Write + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "compressed traces require the trace-gzip feature",
+    ))
+}
+
+// Writes a whole log at once, compressing it if asked to.
+pub fn write_log(path: impl AsRef<Path>, gzip: bool, text: &str) -> io::Result<()> {
+    TraceWriter::create(path, gzip)?.write_str(text)
+}
+
+// Reads a log back, whether or not it was compressed.
+pub fn read_log(path: impl AsRef<Path>) -> io::Result<String> {
+    let bytes = std::fs::read(path)?;
+
+    if bytes.starts_with(&GZIP_MAGIC) {
+        decompress(&bytes)
+    } else {
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(feature = "trace-gzip")]
+fn decompress(bytes: &[u8]) -> io::Result<String> {
+    use std::io::Read;
+
+    use flate2::read::MultiGzDecoder;
+
+    let mut text = String::new();
+    MultiGzDecoder::new(bytes).read_to_string(&mut text)?;
+
+    Ok(text)
+}
+
+#[cfg(not(feature = "trace-gzip"))]
+fn decompress(_bytes: &[u8]) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "compressed logs require the trace-gzip feature",
+    ))
+}
+
+// Compares a finished log against a reference one line by line. Returns a
+// description of the differences, or None if the logs match.
+pub fn diff_logs(expected: &str, actual: &str) -> Option<String> {
+    // Past this point, the rest of the log is usually just fallout from the
+    // first few differences.
+    const MAX_DIFFERENCES: usize = 10;
+
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+
+    let mut output = String::new();
+    let mut differences = 0;
+    let mut line = 0;
+
+    loop {
+        line += 1;
+
+        let (expected, actual) = match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => break,
+            (expected, actual) if expected == actual => continue,
+            (expected, actual) => (expected, actual),
+        };
+
+        differences += 1;
+
+        if differences <= MAX_DIFFERENCES {
+            output.push_str(&format!(
+                "line {}:\n  - {}\n  + {}\n",
+                line,
+                expected.unwrap_or("<end of log>"),
+                actual.unwrap_or("<end of log>")
+            ));
+        }
+    }
+
+    if differences > MAX_DIFFERENCES {
+        output.push_str(&format!(
+            "...and {} more differences\n",
+            differences - MAX_DIFFERENCES
+        ));
+    }
+
+    (differences > 0).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("snesemu-{}-{}.log", name, std::process::id()))
+    }
+
+    #[test]
+    fn stream_is_flushed_on_drop() {
+        let path = temp_path("stream");
+        let writer = TraceWriter::create(&path, false).unwrap();
+
+        for i in 0..5000 {
+            writer.write_line(&format!("line {}", i)).unwrap();
+        }
+
+        // Everything is still sitting in the buffer.
+        assert_eq!(std::fs::read(&path).unwrap().len(), 0);
+
+        drop(writer);
+
+        let log = read_log(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(log.lines().count(), 5000);
+        assert_eq!(log.lines().last(), Some("line 4999"));
+    }
+
+    #[cfg(feature = "trace-gzip")]
+    #[test]
+    fn gzip_round_trip() {
+        let path = temp_path("gzip");
+        let text: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+
+        write_log(&path, true, &text).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let log = read_log(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(bytes.starts_with(&GZIP_MAGIC));
+        assert!(bytes.len() < text.len());
+        assert_eq!(log, text);
+    }
+
+    #[test]
+    fn diff_reports_changed_and_missing_lines() {
+        assert_eq!(diff_logs("a\nb\n", "a\nb\n"), None);
+
+        assert_eq!(
+            diff_logs("a\nb\nc\n", "a\nx\n").unwrap(),
+            "line 2:\n  - b\n  + x\nline 3:\n  - c\n  + <end of log>\n"
+        );
+    }
+}
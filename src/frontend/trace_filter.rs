@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+
+use crate::inst::Instruction;
+
+// Decides which instructions make it into the trace. An instruction is
+// logged if its address is inside one of the ranges (or there are no
+// ranges), and it isn't inside one of the skipped subroutines.
+pub struct TraceFilter {
+    ranges: Vec<(u32, u32)>,
+    skipped: HashSet<u32>,
+
+    // How many calls deep execution is into a skipped subroutine, or 0 if
+    // it's outside of them.
+    depth: u32,
+
+    // Whether the last instruction was a call, as a skipped subroutine is
+    // only entered by calling it - running into its address by way of a
+    // branch or jump doesn't count.
+    after_call: bool,
+}
+
+impl TraceFilter {
+    pub fn new(ranges: Vec<(u32, u32)>, skipped: HashSet<u32>) -> TraceFilter {
+        TraceFilter {
+            ranges,
+            skipped,
+            depth: 0,
+            after_call: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty() && self.skipped.is_empty()
+    }
+
+    // This has to see every instruction that runs, whether it's logged or
+    // not, so that calls and returns are matched up.
+    pub fn should_log(&mut self, addr: u32, instruction: Instruction) -> bool {
+        if self.after_call && self.depth == 0 && self.skipped.contains(&addr) {
+            self.depth = 1;
+        }
+
+        let is_call = matches!(
+            instruction,
+            Instruction::JumpSubRoutineAbsolute | Instruction::JumpSubRoutineAbsoluteLong
+        );
+
+        let is_return = matches!(instruction, Instruction::Return | Instruction::ReturnLong);
+
+        self.after_call = is_call;
+
+        // The return out of a skipped subroutine is still part of it, so it's
+        // left out along with the rest.
+        if self.depth > 0 {
+            if is_call {
+                self.depth += 1;
+            } else if is_return {
+                self.depth -= 1;
+            }
+
+            return false;
+        }
+
+        self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Instruction::{
+        JumpAbsolute as Jmp, JumpSubRoutineAbsolute as Jsr, JumpSubRoutineAbsoluteLong as Jsl,
+        LoadAImmediate as Lda, Return as Rts, ReturnLong as Rtl,
+    };
+
+    // Which of the instructions, run in order, get logged.
+    fn logged(filter: &mut TraceFilter, run: &[(u32, Instruction)]) -> Vec<bool> {
+        run.iter()
+            .map(|&(addr, instruction)| filter.should_log(addr, instruction))
+            .collect()
+    }
+
+    fn skipping(addrs: &[u32]) -> TraceFilter {
+        TraceFilter::new(Vec::new(), addrs.iter().copied().collect())
+    }
+
+    #[test]
+    fn no_filters_log_everything() {
+        let mut filter = TraceFilter::new(Vec::new(), HashSet::new());
+        assert!(filter.is_empty());
+
+        let run = [(0x00_8000, Jsr), (0x00_9000, Rts), (0x7E_0000, Lda)];
+        assert_eq!(logged(&mut filter, &run), [true, true, true]);
+    }
+
+    #[test]
+    fn ranges() {
+        let mut filter = TraceFilter::new(
+            vec![(0x00_8000, 0x00_80FF), (0x80_0000, 0x80_FFFF)],
+            HashSet::new(),
+        );
+        assert!(!filter.is_empty());
+
+        // Both ends are inclusive.
+        let run = [
+            (0x00_7FFF, Lda),
+            (0x00_8000, Lda),
+            (0x00_80FF, Lda),
+            (0x00_8100, Lda),
+            (0x80_0000, Lda),
+            (0x80_FFFF, Lda),
+            (0x81_0000, Lda),
+        ];
+
+        assert_eq!(
+            logged(&mut filter, &run),
+            [false, true, true, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn skips_nested_and_long_calls() {
+        let mut filter = skipping(&[0x00_9000]);
+
+        let run = [
+            (0x00_8000, Jsr), // JSR $9000
+            (0x00_9000, Lda),
+            (0x00_9002, Jsr), // JSR $A000
+            (0x00_A000, Rts),
+            (0x00_9005, Jsl), // JSL $018000
+            (0x01_8000, Jsr), // JSR $A000
+            (0x00_A000, Rts),
+            (0x01_8003, Rtl),
+            (0x00_9009, Rts),
+            (0x00_8003, Lda),
+        ];
+
+        // Only the call itself and what comes after the matching return.
+        assert_eq!(
+            logged(&mut filter, &run),
+            [true, false, false, false, false, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn skips_long_subroutines() {
+        let mut filter = skipping(&[0x01_8000]);
+
+        let run = [
+            (0x00_8000, Jsl), // JSL $018000
+            (0x01_8000, Jsr), // JSR $8010
+            (0x01_8010, Rts),
+            (0x01_8003, Rtl),
+            (0x00_8004, Lda),
+        ];
+
+        assert_eq!(logged(&mut filter, &run), [true, false, false, false, true]);
+    }
+
+    #[test]
+    fn recursive_calls_count_as_nesting() {
+        let mut filter = skipping(&[0x00_9000]);
+
+        // The subroutine calls itself, and only the outer return ends it.
+        let run = [
+            (0x00_8000, Jsr), // JSR $9000
+            (0x00_9000, Jsr), // JSR $9000
+            (0x00_9000, Jsr), // JSR $9000
+            (0x00_9000, Rts),
+            (0x00_9003, Rts),
+            (0x00_9003, Rts),
+            (0x00_8003, Lda),
+        ];
+
+        assert_eq!(
+            logged(&mut filter, &run),
+            [true, false, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn jumps_into_skipped_subroutines_are_logged() {
+        let mut filter = skipping(&[0x00_9000]);
+
+        // Running into it without a call, then returning from whoever called
+        // this code, doesn't start or end a skip.
+        let run = [
+            (0x00_8000, Jmp), // JMP $9000
+            (0x00_9000, Lda),
+            (0x00_9002, Rts),
+            (0x00_8000, Jsr), // JSR $9000
+            (0x00_9000, Rts),
+            (0x00_8003, Lda),
+        ];
+
+        assert_eq!(
+            logged(&mut filter, &run),
+            [true, true, true, true, false, true]
+        );
+    }
+
+    #[test]
+    fn skips_apply_inside_ranges() {
+        let mut filter = TraceFilter::new(
+            vec![(0x00_8000, 0x00_FFFF)],
+            [0x00_9000].into_iter().collect(),
+        );
+
+        let run = [
+            (0x00_8000, Jsr), // JSR $9000
+            (0x00_9000, Rts),
+            (0x00_8003, Jsl), // JSL $018000
+            (0x01_8000, Rtl),
+            (0x00_8007, Lda),
+        ];
+
+        assert_eq!(logged(&mut filter, &run), [true, false, true, false, true]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use super::io_log::IoLog;
+use crate::debugger::{self, Command, Condition};
+use crate::disasm;
+use crate::emulator::Emulator;
+use crate::events::EventLog;
+use crate::trace;
+
+const KEYS: &str = "s step  n step over  f finish  c continue (any key pauses)  b breakpoint  \
+                    up/down move  [ ] { } scroll memory  q quit";
+
+// Where the memory panel starts, until it's scrolled.
+const MEMORY_START: u32 = 0x7E_0000;
+
+// A full-screen debugger, shown in place of the command prompt whenever
+// execution stops. It's built on the same commands, so the keys just pick
+// one for the frontend to run.
+pub struct Tui {
+    terminal: DefaultTerminal,
+    panels: Panels,
+}
+
+// Where the panels are scrolled to, kept apart from the terminal so that
+// they can be drawn while it's borrowed.
+struct Panels {
+    // The line of the disassembly that breakpoints are toggled at.
+    cursor: usize,
+
+    // Where the disassembly starts. This follows the PC until the cursor is
+    // moved past the bottom of the panel.
+    top: Option<u32>,
+
+    memory: u32,
+}
+
+// What the panels show besides the emulator itself, which the frontend
+// keeps track of.
+pub struct View<'a> {
+    pub emulator: &'a Emulator,
+    pub breakpoints: &'a HashMap<u32, Option<Condition>>,
+    pub io_log: Option<&'a IoLog>,
+}
+
+impl Tui {
+    // Takes over the terminal until the Tui is dropped.
+    pub fn new() -> io::Result<Tui> {
+        Ok(Tui {
+            terminal: ratatui::try_init()?,
+            panels: Panels {
+                cursor: 0,
+                top: None,
+                memory: MEMORY_START,
+            },
+        })
+    }
+
+    // Draws the current state and waits for a key that maps to a command.
+    pub fn prompt(&mut self, view: &View) -> io::Result<Command> {
+        let panels = &mut self.panels;
+
+        panels.cursor = 0;
+        panels.top = None;
+
+        loop {
+            let mut lines = Vec::new();
+            self.terminal
+                .draw(|frame| lines = panels.draw(frame, view))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            let cursor_addr = lines.get(panels.cursor).copied();
+
+            match key.code {
+                KeyCode::Char('s') => return Ok(Command::Step(1)),
+                KeyCode::Char('n') => return Ok(Command::StepOver),
+                KeyCode::Char('f') => return Ok(Command::Finish),
+                KeyCode::Char('c') => return Ok(Command::Continue),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(Command::Quit),
+
+                KeyCode::Char('b') => match cursor_addr {
+                    Some(addr) if view.breakpoints.contains_key(&addr) => {
+                        return Ok(Command::ClearBreak(addr))
+                    }
+                    Some(addr) => return Ok(Command::Break(addr, None)),
+                    None => {}
+                },
+
+                KeyCode::Up if panels.cursor > 0 => panels.cursor -= 1,
+
+                // Moving back past the top goes back to following the PC, as
+                // there's no telling where the instructions before it start.
+                KeyCode::Up => panels.top = None,
+
+                KeyCode::Down if panels.cursor + 1 < lines.len() => panels.cursor += 1,
+                KeyCode::Down => panels.top = lines.get(1).copied(),
+
+                KeyCode::Char('[') => panels.scroll_memory(-0x10),
+                KeyCode::Char(']') => panels.scroll_memory(0x10),
+                KeyCode::Char('{') => panels.scroll_memory(-0x100),
+                KeyCode::Char('}') => panels.scroll_memory(0x100),
+
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Tui {
+    // Checks, without waiting, whether a key was pressed while running, so
+    // that execution can be paused.
+    pub fn interrupted(&self) -> io::Result<bool> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+impl Panels {
+    fn scroll_memory(&mut self, offset: i32) {
+        self.memory = self.memory.wrapping_add_signed(offset) & 0xFF_FFFF;
+    }
+
+    // Returns the address of each line of the disassembly, for moving the
+    // cursor around.
+    fn draw(&self, frame: &mut Frame, view: &View) -> Vec<u32> {
+        let [main, bottom, keys] = Layout::vertical([
+            Constraint::Min(8),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let [code, side] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main);
+
+        let [registers, lower] =
+            Layout::vertical([Constraint::Length(5), Constraint::Min(3)]).areas(side);
+
+        let [stack, memory] =
+            Layout::horizontal([Constraint::Length(20), Constraint::Min(20)]).areas(lower);
+
+        let [io, events] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .areas(bottom);
+
+        let addrs = self.draw_disassembly(frame, code, view);
+
+        draw_registers(frame, registers, view.emulator);
+        draw_stack(frame, stack, view.emulator);
+        self.draw_memory(frame, memory, view.emulator);
+        draw_io(frame, io, view.io_log);
+        draw_events(frame, events, view.emulator.mmu.events.as_ref());
+
+        frame.render_widget(Line::raw(KEYS), keys);
+
+        addrs
+    }
+
+    fn draw_disassembly(&self, frame: &mut Frame, area: Rect, view: &View) -> Vec<u32> {
+        let cpu = &view.emulator.cpu;
+        let mmu = &view.emulator.mmu;
+        let pc = cpu.current_addr();
+
+        let mut addr = self.top.unwrap_or(pc);
+        let mut addrs = Vec::new();
+        let mut lines = Vec::new();
+
+        for i in 0..area.height.saturating_sub(2) as usize {
+            let disassembly = disasm::disassemble_at(cpu, mmu, addr);
+
+            let bytes: Vec<String> = disassembly
+                .bytes()
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+
+            let marker = match (addr == pc, view.breakpoints.contains_key(&addr)) {
+                (true, true) => "*>",
+                (true, false) => " >",
+                (false, true) => "* ",
+                (false, false) => "  ",
+            };
+
+            let text = format!(
+                "{} {:06X} {:<11} {}",
+                marker,
+                addr,
+                bytes.join(" "),
+                disassembly.text()
+            );
+
+            let style = match i == self.cursor {
+                true => Style::new().add_modifier(Modifier::REVERSED),
+                false => Style::new(),
+            };
+
+            lines.push(Line::styled(text, style));
+            addrs.push(addr);
+
+            let len = disassembly.bytes().len() as u16;
+            addr = (addr & 0xFF_0000) | (addr as u16).wrapping_add(len) as u32;
+        }
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Disassembly")),
+            area,
+        );
+
+        addrs
+    }
+
+    fn draw_memory(&self, frame: &mut Frame, area: Rect, emulator: &Emulator) {
+        let rows = area.height.saturating_sub(2) as usize;
+        let bytes = emulator.mmu.peek_bytes(self.memory, rows * 16);
+
+        frame.render_widget(
+            Paragraph::new(debugger::hexdump(self.memory, &bytes))
+                .block(Block::bordered().title("Memory")),
+            area,
+        );
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+fn draw_registers(frame: &mut Frame, area: Rect, emulator: &Emulator) {
+    let text = format!(
+        "{}\nframe {} line {} instructions {}",
+        emulator.cpu.register_debug(),
+        emulator.frame(),
+        emulator.mmu.ppu.scanline(),
+        emulator.instructions()
+    );
+
+    frame.render_widget(
+        Paragraph::new(text)
+            .wrap(Wrap { trim: true })
+            .block(Block::bordered().title("Registers")),
+        area,
+    );
+}
+
+// Lists what's on the stack from the top down, grouped the same way as in
+// the trace.
+fn draw_stack(frame: &mut Frame, area: Rect, emulator: &Emulator) {
+    let stack = emulator.cpu.stack(&emulator.mmu);
+
+    let mut lines: Vec<Line> = stack
+        .entries()
+        .iter()
+        .map(|(offset, entry)| {
+            let mut line = format!("{:04X}: ", stack.sp.wrapping_add(offset + 1));
+            trace::write_stack_entry(&mut line, entry);
+            Line::raw(line)
+        })
+        .collect();
+
+    if stack.truncated {
+        lines.push(Line::raw("..."));
+    }
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Stack")),
+        area,
+    );
+}
+
+fn draw_io(frame: &mut Frame, area: Rect, io_log: Option<&IoLog>) {
+    let rows = area.height.saturating_sub(2) as usize;
+
+    let lines: Vec<Line> = match io_log {
+        Some(io_log @ IoLog::Ring(..)) => io_log
+            .recent(rows)
+            .map(|entry| {
+                let mut line = String::new();
+                entry.format(&mut line);
+                Line::raw(line)
+            })
+            .collect(),
+        _ => vec![Line::raw("Showing register accesses requires --io-ring")],
+    };
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("I/O")),
+        area,
+    );
+}
+
+fn draw_events(frame: &mut Frame, area: Rect, events: Option<&EventLog>) {
+    let rows = area.height.saturating_sub(2) as usize;
+
+    let lines: Vec<Line> = match events {
+        Some(events) => events
+            .recent(rows)
+            .map(|event| {
+                let mut line = String::new();
+                event.format(&mut line);
+                Line::raw(line)
+            })
+            .collect(),
+        None => vec![Line::raw("Showing hardware events requires --events")],
+    };
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Events")),
+        area,
+    );
+}
@@ -0,0 +1,82 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use minifb::{Key, Scale, WindowOptions};
+
+use super::pacing::pace;
+use crate::input::Buttons;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const FRAME_TIME: Duration = Duration::from_nanos(16_639_267);
+
+const KEY_MAP: [(Key, Buttons); 12] = [
+    (Key::Up, Buttons::UP),
+    (Key::Down, Buttons::DOWN),
+    (Key::Left, Buttons::LEFT),
+    (Key::Right, Buttons::RIGHT),
+    (Key::Z, Buttons::B),
+    (Key::X, Buttons::A),
+    (Key::A, Buttons::Y),
+    (Key::S, Buttons::X),
+    (Key::Q, Buttons::L),
+    (Key::W, Buttons::R),
+    (Key::Enter, Buttons::START),
+    (Key::RightShift, Buttons::SELECT),
+];
+
+pub struct Window {
+    window: minifb::Window,
+    buffer: Vec<u32>,
+    deadline: Instant,
+}
+
+impl Window {
+    pub fn new(title: &str) -> Window {
+        let mut window = minifb::Window::new(
+            title,
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+            WindowOptions {
+                scale: Scale::X2,
+                ..WindowOptions::default()
+            },
+        )
+        .unwrap();
+
+        // We do our own frame pacing, so that the timing is driven by the
+        // emulated frame rate rather than the window's update rate.
+        window.set_target_fps(0);
+
+        Window {
+            window,
+            buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            deadline: Instant::now() + FRAME_TIME,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        KEY_MAP
+            .iter()
+            .filter(|(key, _)| self.window.is_key_down(*key))
+            .fold(Buttons::empty(), |buttons, (_, button)| buttons | *button)
+    }
+
+    pub fn present(&mut self, framebuffer: &[u8]) {
+        for (pixel, rgba) in self.buffer.iter_mut().zip(framebuffer.chunks_exact(4)) {
+            *pixel = u32::from_be_bytes([0, rgba[0], rgba[1], rgba[2]]);
+        }
+
+        self.window
+            .update_with_buffer(&self.buffer, SCREEN_WIDTH, SCREEN_HEIGHT)
+            .unwrap();
+
+        let (sleep, deadline) = pace(self.deadline, Instant::now(), FRAME_TIME);
+
+        thread::sleep(sleep);
+        self.deadline = deadline;
+    }
+}
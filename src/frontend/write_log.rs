@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+
+// One store to memory, along with the instruction that made it. Writes made
+// by DMA are put down to the instruction that started it.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteEntry {
+    pub addr: u32,
+    pub value: u8,
+    pub pc: u32,
+
+    // How many instructions had run before this one.
+    pub instruction: u64,
+}
+
+impl WriteEntry {
+    // e.g. `#18273 pc 008123 W 7E0010 = 5A`
+    pub fn format(&self, output: &mut String) {
+        let _ = write!(
+            output,
+            "#{} pc {:06X} W {:06X} = {:02X}",
+            self.instruction, self.pc, self.addr, self.value
+        );
+    }
+}
+
+// Keeps the last few writes to memory, for working out what put a value
+// somewhere after the fact.
+pub struct WriteLog {
+    entries: VecDeque<WriteEntry>,
+    capacity: usize,
+}
+
+impl WriteLog {
+    pub fn new(capacity: usize) -> WriteLog {
+        WriteLog {
+            entries: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record(&mut self, entry: WriteEntry) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+    }
+
+    // The writes still in the log that touched len bytes from start, oldest
+    // first. Writes through any of WRAM's mirrors count.
+    pub fn to_range(&self, start: u32, len: u32) -> impl Iterator<Item = &WriteEntry> {
+        let start = wram_addr(start);
+
+        self.entries
+            .iter()
+            .filter(move |entry| wram_addr(entry.addr).wrapping_sub(start) & 0xFF_FFFF < len)
+    }
+
+    // Lists the last n writes to a range, for crash reports.
+    pub fn write_report(&self, output: &mut String, start: u32, len: u32, n: usize) {
+        let writes: Vec<&WriteEntry> = self.to_range(start, len).collect();
+
+        if writes.is_empty() {
+            let _ = write!(
+                output,
+                "\n    No writes to {:06X}-{:06X} in the last {}",
+                start,
+                start.wrapping_add(len - 1) & 0xFF_FFFF,
+                self.entries.len()
+            );
+            return;
+        }
+
+        let _ = write!(
+            output,
+            "\n    Last writes to {:06X}-{:06X}:",
+            start,
+            start.wrapping_add(len - 1) & 0xFF_FFFF
+        );
+
+        for entry in &writes[writes.len().saturating_sub(n)..] {
+            output.push_str("\n      ");
+            entry.format(output);
+        }
+    }
+}
+
+// The first 8KB of WRAM shows up at the bottom of every system bank, so
+// writes there are looked up by where they land in bank 7E.
+fn wram_addr(addr: u32) -> u32 {
+    let bank = (addr >> 16) as u8;
+    let offset = addr & 0xFFFF;
+
+    match bank {
+        0x00..=0x3F | 0x80..=0xBF if offset < 0x2000 => 0x7E_0000 | offset,
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(instruction: u64, addr: u32, value: u8) -> WriteEntry {
+        WriteEntry {
+            addr,
+            value,
+            pc: 0x00_8000 + instruction as u32,
+            instruction,
+        }
+    }
+
+    fn instructions<'a>(writes: impl Iterator<Item = &'a WriteEntry>) -> Vec<u64> {
+        writes.map(|entry| entry.instruction).collect()
+    }
+
+    #[test]
+    fn keeps_the_last_writes() {
+        let mut log = WriteLog::new(16);
+
+        // Two passes over $10-$1F, and then a little of a third.
+        for i in 0..40 {
+            log.record(entry(i, 0x7E_0010 + (i as u32 % 16), i as u8));
+        }
+
+        assert_eq!(log.entries.len(), 16);
+        assert_eq!(
+            instructions(log.to_range(0x7E_0000, 0x1_0000)),
+            (24..40).collect::<Vec<_>>()
+        );
+
+        // $18-$1F were last written on the second pass, $10-$17 on the third.
+        assert_eq!(instructions(log.to_range(0x7E_0010, 1)), [32]);
+        assert_eq!(instructions(log.to_range(0x7E_001F, 1)), [31]);
+        assert_eq!(instructions(log.to_range(0x7E_0016, 4)), [24, 25, 38, 39]);
+        assert_eq!(log.to_range(0x7E_0020, 16).count(), 0);
+    }
+
+    #[test]
+    fn mirrors_and_wrapping() {
+        let mut log = WriteLog::new(8);
+
+        log.record(entry(0, 0x00_0100, 1));
+        log.record(entry(1, 0x80_1FFF, 2));
+        log.record(entry(2, 0x7E_0100, 3));
+        log.record(entry(3, 0x7F_0100, 4));
+        log.record(entry(4, 0x40_0100, 5));
+        log.record(entry(5, 0x00_2000, 6));
+        log.record(entry(6, 0xFF_FFFF, 7));
+
+        // The low 8KB is the same memory whichever bank it's written through.
+        assert_eq!(instructions(log.to_range(0x7E_0100, 1)), [0, 2]);
+        assert_eq!(instructions(log.to_range(0xBF_0100, 1)), [0, 2]);
+        assert_eq!(instructions(log.to_range(0x7E_1FFF, 1)), [1]);
+        assert_eq!(instructions(log.to_range(0x40_0100, 1)), [4]);
+
+        // A range off the end of the address space carries on from zero,
+        // where $2000 up isn't WRAM.
+        assert_eq!(instructions(log.to_range(0xFF_FFFF, 0x2001)), [6]);
+        assert_eq!(instructions(log.to_range(0xFF_FFFF, 0x2002)), [5, 6]);
+    }
+
+    #[test]
+    fn reports() {
+        let mut log = WriteLog::new(4);
+
+        for i in 0..6 {
+            log.record(entry(i, 0x7E_0010, i as u8 * 0x11));
+        }
+
+        let mut report = String::new();
+        log.write_report(&mut report, 0x7E_0010, 4, 2);
+        assert_eq!(
+            report,
+            "\n    Last writes to 7E0010-7E0013:\
+             \n      #4 pc 008004 W 7E0010 = 44\
+             \n      #5 pc 008005 W 7E0010 = 55"
+        );
+
+        let mut report = String::new();
+        log.write_report(&mut report, 0x7E_0020, 4, 2);
+        assert_eq!(report, "\n    No writes to 7E0020-7E0023 in the last 4");
+    }
+}
@@ -0,0 +1,149 @@
+use crate::mmu::MapMode;
+
+// The names of the interrupt vectors, in the order they appear in both the
+// native and emulation mode tables.
+pub const VECTOR_NAMES: [&str; 6] = ["COP", "BRK", "ABORT", "NMI", "RESET", "IRQ"];
+
+// The internal header that every cartridge has just below the vectors, at
+// the end of the first bank.
+pub struct Header {
+    pub title: String,
+    pub map_mode: MapMode,
+    pub fast_rom: bool,
+    pub chip: u8,
+    pub rom_size: u8,
+    pub sram_size: u8,
+    pub region: u8,
+    pub version: u8,
+    pub checksum: u16,
+    pub complement: u16,
+
+    // Some slots are unused on hardware (RESET in native mode and BRK in
+    // emulation mode), but they're read the same as the others.
+    pub native_vectors: [u16; 6],
+    pub emulation_vectors: [u16; 6],
+}
+
+impl Header {
+    pub fn parse(cartridge: &[u8], map_mode: MapMode) -> Option<Header> {
+        let header = cartridge.get(map_mode.header_offset()..map_mode.header_offset() + 0x40)?;
+
+        let read_u16 = |addr: usize| u16::from_le_bytes([header[addr], header[addr + 1]]);
+        let vectors = |base: usize| std::array::from_fn(|i| read_u16(base + i * 2));
+
+        let title = header[..0x15]
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { ' ' })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        Some(Header {
+            title,
+            map_mode,
+            fast_rom: header[0x15] & 0x10 != 0,
+            chip: header[0x16],
+            rom_size: header[0x17],
+            sram_size: header[0x18],
+            region: header[0x19],
+            version: header[0x1B],
+            complement: read_u16(0x1C),
+            checksum: read_u16(0x1E),
+
+            native_vectors: vectors(0x24),
+            emulation_vectors: vectors(0x34),
+        })
+    }
+
+    // The sizes are stored as a power of two number of kilobytes.
+    pub fn rom_size_kb(&self) -> u32 {
+        1u32.checked_shl(self.rom_size as u32).unwrap_or(0)
+    }
+
+    pub fn sram_size_kb(&self) -> u32 {
+        match self.sram_size {
+            0 => 0,
+            n => 1u32.checked_shl(n as u32).unwrap_or(0),
+        }
+    }
+
+    pub fn chip_name(&self) -> String {
+        let base = match self.chip & 0x0F {
+            0x0 => "ROM",
+            0x1 => "ROM + RAM",
+            0x2 => "ROM + RAM + battery",
+            0x3 => "ROM + coprocessor",
+            0x4 => "ROM + coprocessor + RAM",
+            0x5 => "ROM + coprocessor + RAM + battery",
+            0x6 => "ROM + coprocessor + battery",
+            _ => return "unknown".into(),
+        };
+
+        if self.chip & 0x0F < 0x3 {
+            return base.into();
+        }
+
+        let coprocessor = match self.chip >> 4 {
+            0x0 => "DSP",
+            0x1 => "GSU",
+            0x2 => "OBC1",
+            0x3 => "SA-1",
+            0x4 => "S-DD1",
+            0x5 => "S-RTC",
+            0xE => "other",
+            0xF => "custom",
+            _ => "unknown",
+        };
+
+        format!("{} ({})", base, coprocessor)
+    }
+
+    pub fn region_name(&self) -> &'static str {
+        match self.region {
+            0x00 => "Japan",
+            0x01 => "North America",
+            0x02 => "Europe",
+            0x03 => "Sweden",
+            0x04 => "Finland",
+            0x05 => "Denmark",
+            0x06 => "France",
+            0x07 => "Netherlands",
+            0x08 => "Spain",
+            0x09 => "Germany",
+            0x0A => "Italy",
+            0x0B => "China",
+            0x0C => "Indonesia",
+            0x0D => "South Korea",
+            0x0F => "Canada",
+            0x10 => "Brazil",
+            0x11 => "Australia",
+            _ => "unknown",
+        }
+    }
+
+    pub fn checksum_valid(&self, cartridge: &[u8]) -> bool {
+        self.checksum ^ self.complement == 0xFFFF && self.checksum == checksum(cartridge)
+    }
+}
+
+// Sums every byte of the ROM. If the size isn't a power of two, the remainder
+// is mirrored up to fill the next power of two, as it would be when mapped.
+pub fn checksum(cartridge: &[u8]) -> u16 {
+    let sum = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .fold(0u16, |sum, &b| sum.wrapping_add(b as u16))
+    };
+
+    if cartridge.is_empty() || cartridge.len().is_power_of_two() {
+        return sum(cartridge);
+    }
+
+    let base = 1 << cartridge.len().ilog2();
+    let (first, rest) = cartridge.split_at(base);
+
+    // TODO: This assumes the remainder is itself a power of two
+    let repeats = (base / rest.len()) as u16;
+
+    sum(first).wrapping_add(sum(rest).wrapping_mul(repeats))
+}
@@ -0,0 +1,294 @@
+use bitflags::bitflags;
+
+bitflags! {
+    // Laid out in the same order as the auto-read registers ($4218/$4219).
+    #[derive(Clone, Copy, Default)]
+    #[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Buttons: u16 {
+        const R      = 0b0000_0000_0001_0000;
+        const L      = 0b0000_0000_0010_0000;
+        const X      = 0b0000_0000_0100_0000;
+        const A      = 0b0000_0000_1000_0000;
+        const RIGHT  = 0b0000_0001_0000_0000;
+        const LEFT   = 0b0000_0010_0000_0000;
+        const DOWN   = 0b0000_0100_0000_0000;
+        const UP     = 0b0000_1000_0000_0000;
+        const START  = 0b0001_0000_0000_0000;
+        const SELECT = 0b0010_0000_0000_0000;
+        const Y      = 0b0100_0000_0000_0000;
+        const B      = 0b1000_0000_0000_0000;
+    }
+}
+
+// What's plugged into the second controller port. The first port always has
+// a standard joypad.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub enum Port2 {
+    #[default]
+    Joypad,
+
+    // A multitap with four pads, for pads 2 to 5. It connects two of them
+    // to the port's two data lines at a time, picked by bit 7 of WRIO:
+    // pads 2 and 3 while it's set, and pads 4 and 5 while it's clear.
+    Multitap,
+}
+
+// The controller ports, and the joypads plugged into them. Pad 1 is on the
+// first port, and the rest are on the second.
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Controllers {
+    pub pads: [Buttons; 5],
+    pub port2: Port2,
+
+    // The serial side of each pad. Writing 1 to bit 0 of $4016 copies the
+    // buttons into the shift registers, and then each read of $4016/$4017
+    // shifts the next button out, starting from B.
+    strobe: bool,
+    shift: [u16; 5],
+}
+
+impl Controllers {
+    pub fn new() -> Controllers {
+        Controllers {
+            pads: [Buttons::empty(); 5],
+            port2: Port2::Joypad,
+
+            strobe: false,
+            shift: [0; 5],
+        }
+    }
+
+    // JOYWR ($4016). The buttons are copied in for as long as the strobe is
+    // held, so they're latched again when it's released.
+    pub fn write_strobe(&mut self, value: u8) {
+        let was_strobe = self.strobe;
+        self.strobe = value & 1 != 0;
+
+        if self.strobe || was_strobe {
+            for (shift, pad) in self.shift.iter_mut().zip(&self.pads) {
+                *shift = pad.bits();
+            }
+        }
+    }
+
+    // JOYA ($4016). Bit 0 is the port's first data line, and bit 1 its
+    // second, which nothing on the first port uses.
+    pub fn read_port1(&mut self) -> u8 {
+        let value = self.peek_port1();
+        self.advance(0);
+
+        value
+    }
+
+    pub fn peek_port1(&self) -> u8 {
+        self.next_bit(0)
+    }
+
+    // JOYB ($4017). Bits 2-4 are always set. `io_bit` is bit 7 of WRIO.
+    pub fn read_port2(&mut self, io_bit: bool) -> u8 {
+        let value = self.peek_port2(io_bit);
+
+        for pad in self.port2_lines(io_bit).into_iter().flatten() {
+            self.advance(pad);
+        }
+
+        value
+    }
+
+    pub fn peek_port2(&self, io_bit: bool) -> u8 {
+        let mut value = 0x1C;
+
+        for (line, pad) in self.port2_lines(io_bit).into_iter().enumerate() {
+            if let Some(pad) = pad {
+                value |= self.next_bit(pad) << line;
+            }
+        }
+
+        // While the strobe is held, the multitap drives the second line
+        // high, which is how games detect it.
+        if self.port2 == Port2::Multitap && self.strobe {
+            value |= 0x02;
+        }
+
+        value
+    }
+
+    // What auto-read puts in JOY1-JOY4 ($4218-$421F). JOY1 and JOY2 come from
+    // each port's first data line, and JOY3 and JOY4 from their second. With
+    // the multitap, that means only pads 2 and 3 can be auto-read, and games
+    // read pads 4 and 5 by hand after clearing bit 7 of WRIO.
+    pub fn auto_read(&self, io_bit: bool) -> [u16; 4] {
+        let [joy2, joy4] = self
+            .port2_lines(io_bit)
+            .map(|pad| pad.map_or(0, |pad| self.pads[pad].bits()));
+
+        [self.pads[0].bits(), joy2, 0, joy4]
+    }
+
+    // The pads connected to the second port's two data lines.
+    fn port2_lines(&self, io_bit: bool) -> [Option<usize>; 2] {
+        match self.port2 {
+            Port2::Joypad => [Some(1), None],
+            Port2::Multitap if self.strobe => [Some(1), None],
+            Port2::Multitap if io_bit => [Some(1), Some(2)],
+            Port2::Multitap => [Some(3), Some(4)],
+        }
+    }
+
+    fn next_bit(&self, pad: usize) -> u8 {
+        let bits = if self.strobe {
+            self.pads[pad].bits()
+        } else {
+            self.shift[pad]
+        };
+
+        (bits >> 15) as u8
+    }
+
+    // Once all 16 buttons have been read, a standard pad keeps returning 1.
+    fn advance(&mut self, pad: usize) {
+        if !self.strobe {
+            self.shift[pad] = self.shift[pad] << 1 | 1;
+        }
+    }
+}
+
+impl Default for Controllers {
+    fn default() -> Controllers {
+        Controllers::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Strobes the pads, then reads 16 bits from a data line of a port, B
+    // first.
+    fn read_serially(
+        controllers: &mut Controllers,
+        mut read: impl FnMut(&mut Controllers) -> u8,
+    ) -> u16 {
+        controllers.write_strobe(1);
+        controllers.write_strobe(0);
+
+        (0..16).fold(0, |bits, _| bits << 1 | read(controllers) as u16)
+    }
+
+    fn multitap() -> Controllers {
+        let mut controllers = Controllers::new();
+        controllers.port2 = Port2::Multitap;
+        controllers.pads = [
+            Buttons::B,
+            Buttons::Y,
+            Buttons::SELECT,
+            Buttons::START,
+            Buttons::UP,
+        ];
+
+        controllers
+    }
+
+    #[test]
+    fn joypads_read_serially() {
+        let mut controllers = Controllers::new();
+        controllers.pads[0] = Buttons::B | Buttons::START;
+        controllers.pads[1] = Buttons::A;
+
+        let pad1 = read_serially(&mut controllers, |c| c.read_port1() & 1);
+        assert_eq!(pad1, (Buttons::B | Buttons::START).bits());
+
+        // Once the buttons run out, it keeps returning 1.
+        assert_eq!(controllers.read_port1(), 1);
+        assert_eq!(controllers.read_port1(), 1);
+
+        // Bits 2-4 of JOYB are always set.
+        let pad2 = read_serially(&mut controllers, |c| c.read_port2(true) & 1);
+        assert_eq!(pad2, Buttons::A.bits());
+        assert_eq!(controllers.peek_port2(true) & 0x1C, 0x1C);
+    }
+
+    #[test]
+    fn peeking_doesnt_shift() {
+        let mut controllers = Controllers::new();
+        controllers.pads[0] = Buttons::B;
+        controllers.write_strobe(1);
+        controllers.write_strobe(0);
+
+        assert_eq!(controllers.peek_port1(), 1);
+        assert_eq!(controllers.peek_port1(), 1);
+        assert_eq!(controllers.read_port1(), 1);
+        assert_eq!(controllers.read_port1(), 0);
+    }
+
+    #[test]
+    fn multitap_pads_by_io_bit() {
+        let mut controllers = multitap();
+
+        // Pads 2 and 3 are on the two data lines while bit 7 of WRIO is set...
+        let pad2 = read_serially(&mut controllers, |c| c.read_port2(true) & 1);
+        let pad3 = read_serially(&mut controllers, |c| c.read_port2(true) >> 1 & 1);
+        assert_eq!((pad2, pad3), (Buttons::Y.bits(), Buttons::SELECT.bits()));
+
+        // ...and pads 4 and 5 while it's clear.
+        let pad4 = read_serially(&mut controllers, |c| c.read_port2(false) & 1);
+        let pad5 = read_serially(&mut controllers, |c| c.read_port2(false) >> 1 & 1);
+        assert_eq!((pad4, pad5), (Buttons::START.bits(), Buttons::UP.bits()));
+
+        // Pad 1 isn't affected either way.
+        let pad1 = read_serially(&mut controllers, |c| c.read_port1() & 1);
+        assert_eq!(pad1, Buttons::B.bits());
+    }
+
+    #[test]
+    fn multitap_is_detected_while_strobed() {
+        let mut controllers = multitap();
+
+        controllers.write_strobe(1);
+        assert_eq!(controllers.peek_port2(true) & 0x02, 0x02);
+
+        controllers.write_strobe(0);
+        controllers.pads[2] = Buttons::empty();
+        assert_eq!(controllers.peek_port2(true) & 0x02, 0x00);
+
+        // A joypad leaves the second line low.
+        let mut controllers = Controllers::new();
+        controllers.write_strobe(1);
+        assert_eq!(controllers.peek_port2(true) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn auto_read_slots() {
+        let controllers = multitap();
+
+        // JOY3 is always empty, and JOY4 is pad 3 while bit 7 of WRIO is
+        // set, or pad 5 while it's clear.
+        assert_eq!(
+            controllers.auto_read(true),
+            [
+                Buttons::B.bits(),
+                Buttons::Y.bits(),
+                0,
+                Buttons::SELECT.bits()
+            ]
+        );
+        assert_eq!(
+            controllers.auto_read(false),
+            [
+                Buttons::B.bits(),
+                Buttons::START.bits(),
+                0,
+                Buttons::UP.bits()
+            ]
+        );
+
+        let mut joypad = multitap();
+        joypad.port2 = Port2::Joypad;
+        assert_eq!(
+            joypad.auto_read(true),
+            [Buttons::B.bits(), Buttons::Y.bits(), 0, 0]
+        );
+    }
+}
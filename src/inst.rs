@@ -1,3 +1,5 @@
+use std::fmt::{self, Write};
+
 #[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     Unknown,
@@ -36,6 +38,8 @@ pub enum Instruction {
     AddWithCarryDirectPage,
     AddWithCarryAbsoluteIndexedY,
     AddWithCarryDirectPageIndexedX,
+    SubtractWithCarryImmediate,
+    SubtractWithCarryDirectPage,
     IncrementDirectPage,
     IncrementA,
     IncrementX,
@@ -45,6 +49,9 @@ pub enum Instruction {
 
     // Shifts
     ShiftLeft,
+    ShiftRight,
+    RotateLeft,
+    RotateRight,
 
     // Transfer register to register
     MoveAX,
@@ -58,6 +65,14 @@ pub enum Instruction {
     BlockMoveNext,
 
     // Logic
+    AndImmediate,
+    AndDirectPage,
+    OrImmediate,
+    OrDirectPage,
+    ExclusiveOrImmediate,
+    ExclusiveOrDirectPage,
+    TestBitsDirectPage,
+    TestBitsAbsolute,
     CompareImmediate,
     CompareAbsolute,
     CompareDirectPage,
@@ -101,6 +116,7 @@ pub enum Instruction {
 
     // Change status flags
     ClearCarry,
+    ClearIrqDisable,
     SetIrqDisable,
     ResetFlags,
     SetFlags,
@@ -108,92 +124,772 @@ pub enum Instruction {
 
     // Interrupts
     Break,
+    ReturnFromInterrupt,
+    WaitForInterrupt,
 }
 
 impl Instruction {
-    pub fn from_opcode(opcode: u8) -> Instruction {
-        match opcode {
-            0x00 => Instruction::Break,
-            0x08 => Instruction::PushStatus,
-            0x0A => Instruction::ShiftLeft,
-            0x0B => Instruction::PushD,
-            0x18 => Instruction::ClearCarry,
-            0x1A => Instruction::IncrementA,
-            0x20 => Instruction::JumpSubRoutineAbsolute,
-            0x22 => Instruction::JumpSubRoutineAbsoluteLong,
-            0x28 => Instruction::PullStatus,
-            0x2B => Instruction::PullD,
-            0x4C => Instruction::JumpAbsolute,
-            0x48 => Instruction::PushA,
-            0x54 => Instruction::BlockMoveNext,
-            0x5A => Instruction::PushY,
-            0x60 => Instruction::Return,
-            0x64 => Instruction::StoreZeroDirectPage,
-            0x65 => Instruction::AddWithCarryDirectPage,
-            0x68 => Instruction::PullA,
-            0x69 => Instruction::AddWithCarryImmediate,
-            0x6B => Instruction::ReturnLong,
-            0x6D => Instruction::AddWithCarryAbsolute,
-            0x74 => Instruction::StoreZeroDirectPageIndexedX,
-            0x75 => Instruction::AddWithCarryDirectPageIndexedX,
-            0x78 => Instruction::SetIrqDisable,
-            0x79 => Instruction::AddWithCarryAbsoluteIndexedY,
-            0x7A => Instruction::PullY,
-            0x7B => Instruction::MoveDA,
-            0x80 => Instruction::BranchAlways,
-            0x84 => Instruction::StoreYDirectPage,
-            0x85 => Instruction::StoreADirectPage,
-            0x86 => Instruction::StoreXDirectPage,
-            0x88 => Instruction::DecrementY,
-            0x8B => Instruction::PushB,
-            0x8D => Instruction::StoreAAbsolute,
-            0x8E => Instruction::StoreXAbsolute,
-            0x90 => Instruction::BranchCarryClear,
-            0x95 => Instruction::StoreADirectPageIndexedX,
-            0x98 => Instruction::MoveYA,
-            0x99 => Instruction::StoreAAbsoluteIndexedY,
-            0x9A => Instruction::MoveXSP,
-            0x9C => Instruction::StoreZeroAbsolute,
-            0x9D => Instruction::StoreAAbsoluteIndexedX,
-            0x9E => Instruction::StoreZeroAbsoluteIndexedX,
-            0x9F => Instruction::StoreAAbsoluteLongIndexedX,
-            0xA0 => Instruction::LoadYImmediate,
-            0xA2 => Instruction::LoadXImmediate,
-            0xA4 => Instruction::LoadYDirectPage,
-            0xA5 => Instruction::LoadADirectPage,
-            0xA6 => Instruction::LoadXDirectPage,
-            0xA8 => Instruction::MoveAY,
-            0xA7 => Instruction::LoadADirectPageIndirectLong,
-            0xA9 => Instruction::LoadAImmediate,
-            0xAA => Instruction::MoveAX,
-            0xAB => Instruction::PullB,
-            0xAD => Instruction::LoadAAbsolute,
-            0xB0 => Instruction::BranchCarrySet,
-            0xB9 => Instruction::LoadAAbsoluteIndexedY,
-            0xBD => Instruction::LoadAAbsoluteIndexedX,
-            0xBF => Instruction::LoadAAbsoluteLongIndexedX,
-            0xC0 => Instruction::CompareYImmediate,
-            0xC2 => Instruction::ResetFlags,
-            0xC5 => Instruction::CompareDirectPage,
-            0xC8 => Instruction::IncrementY,
-            0xC9 => Instruction::CompareImmediate,
-            0xCA => Instruction::DecrementX,
-            0xCD => Instruction::CompareAbsolute,
-            0xD0 => Instruction::BranchNotEqual,
-            0xD5 => Instruction::CompareDirectPageIndexedX,
-            0xDA => Instruction::PushX,
-            0xDF => Instruction::CompareAbsoluteLongIndexedX,
-            0xE0 => Instruction::CompareXImmediate,
-            0xE2 => Instruction::SetFlags,
-            0xE6 => Instruction::IncrementDirectPage,
-            0xE8 => Instruction::IncrementX,
-            0xEB => Instruction::ExchangeBA,
-            0xF0 => Instruction::BranchEqual,
-            0xF4 => Instruction::PushAbsolute,
-            0xFA => Instruction::PullX,
-            0xFB => Instruction::ExchangeCE,
-
-            _ => Instruction::Unknown,
+    pub const fn from_opcode(opcode: u8) -> Instruction {
+        OPCODES[opcode as usize].instruction
+    }
+
+    // The mnemonic used by the official documentation, in lower case as the
+    // table has it, e.g. "lda".
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Instruction::Unknown => "???",
+            _ => OPCODES[FIRST_OPCODES[self as usize] as usize].mnemonic,
+        }
+    }
+
+    // A known gap in how the CPU runs the instruction, for the opcode
+    // coverage report. These match TODOs in the CPU, and should go when
+    // they're fixed.
+    pub fn known_issue(self) -> Option<&'static str> {
+        match self {
+            Instruction::MoveXSP => Some("doesn't keep SP in page 1 in emulation mode"),
+            Instruction::BlockMoveNext => Some("may not handle 8-bit index registers"),
+            Instruction::JumpSubRoutineAbsoluteLong | Instruction::ReturnLong => {
+                Some("the bank goes on the stack below the address, not above")
+            }
+            Instruction::IncrementDirectPage => Some("always 8-bit, whatever M is"),
+            _ => None,
+        }
+    }
+
+    // The addressing mode, in the same notation as the documentation's
+    // opcode tables (e.g. "abs,X"). Empty for implied instructions.
+    pub fn addressing_mode(self) -> &'static str {
+        match self {
+            Instruction::Unknown
+            | Instruction::IncrementX
+            | Instruction::IncrementY
+            | Instruction::DecrementX
+            | Instruction::DecrementY
+            | Instruction::MoveAX
+            | Instruction::MoveAY
+            | Instruction::MoveDA
+            | Instruction::MoveXSP
+            | Instruction::MoveYA
+            | Instruction::ExchangeBA
+            | Instruction::PushA
+            | Instruction::PushB
+            | Instruction::PushD
+            | Instruction::PushX
+            | Instruction::PushY
+            | Instruction::PushStatus
+            | Instruction::PullA
+            | Instruction::PullB
+            | Instruction::PullD
+            | Instruction::PullX
+            | Instruction::PullY
+            | Instruction::PullStatus
+            | Instruction::Return
+            | Instruction::ReturnLong
+            | Instruction::ClearCarry
+            | Instruction::ClearIrqDisable
+            | Instruction::SetIrqDisable
+            | Instruction::ExchangeCE
+            | Instruction::Break
+            | Instruction::ReturnFromInterrupt
+            | Instruction::WaitForInterrupt => "",
+            Instruction::LoadAImmediate
+            | Instruction::LoadXImmediate
+            | Instruction::LoadYImmediate
+            | Instruction::AddWithCarryImmediate
+            | Instruction::SubtractWithCarryImmediate
+            | Instruction::AndImmediate
+            | Instruction::OrImmediate
+            | Instruction::ExclusiveOrImmediate
+            | Instruction::CompareImmediate
+            | Instruction::CompareXImmediate
+            | Instruction::CompareYImmediate
+            | Instruction::ResetFlags
+            | Instruction::SetFlags => "#imm",
+            Instruction::LoadAAbsolute
+            | Instruction::StoreAAbsolute
+            | Instruction::StoreXAbsolute
+            | Instruction::StoreZeroAbsolute
+            | Instruction::AddWithCarryAbsolute
+            | Instruction::TestBitsAbsolute
+            | Instruction::CompareAbsolute
+            | Instruction::PushAbsolute
+            | Instruction::JumpAbsolute
+            | Instruction::JumpSubRoutineAbsolute => "abs",
+            Instruction::LoadADirectPage
+            | Instruction::LoadXDirectPage
+            | Instruction::LoadYDirectPage
+            | Instruction::StoreADirectPage
+            | Instruction::StoreXDirectPage
+            | Instruction::StoreYDirectPage
+            | Instruction::StoreZeroDirectPage
+            | Instruction::AddWithCarryDirectPage
+            | Instruction::SubtractWithCarryDirectPage
+            | Instruction::IncrementDirectPage
+            | Instruction::AndDirectPage
+            | Instruction::OrDirectPage
+            | Instruction::ExclusiveOrDirectPage
+            | Instruction::TestBitsDirectPage
+            | Instruction::CompareDirectPage => "dp",
+            Instruction::LoadADirectPageIndirectLong => "[dp]",
+            Instruction::LoadAAbsoluteIndexedX
+            | Instruction::StoreAAbsoluteIndexedX
+            | Instruction::StoreZeroAbsoluteIndexedX => "abs,X",
+            Instruction::LoadAAbsoluteLongIndexedX
+            | Instruction::StoreAAbsoluteLongIndexedX
+            | Instruction::CompareAbsoluteLongIndexedX => "long,X",
+            Instruction::LoadAAbsoluteIndexedY
+            | Instruction::StoreAAbsoluteIndexedY
+            | Instruction::AddWithCarryAbsoluteIndexedY => "abs,Y",
+            Instruction::StoreADirectPageIndexedX
+            | Instruction::StoreZeroDirectPageIndexedX
+            | Instruction::AddWithCarryDirectPageIndexedX
+            | Instruction::CompareDirectPageIndexedX => "dp,X",
+            Instruction::IncrementA
+            | Instruction::ShiftLeft
+            | Instruction::ShiftRight
+            | Instruction::RotateLeft
+            | Instruction::RotateRight => "A",
+            Instruction::BlockMoveNext => "src,dest",
+            Instruction::BranchCarryClear
+            | Instruction::BranchCarrySet
+            | Instruction::BranchNotEqual
+            | Instruction::BranchEqual
+            | Instruction::BranchAlways => "rel",
+            Instruction::JumpSubRoutineAbsoluteLong => "long",
+        }
+    }
+}
+
+// Written in upper case, the way the debugger and the trace show it.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.mnemonic().chars() {
+            f.write_char(c.to_ascii_uppercase())?;
         }
+
+        Ok(())
+    }
+}
+
+// How an instruction's operand is encoded. Immediates that follow the M and
+// X flags are sized when the instruction is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    Immediate8,
+    Immediate16,
+    ImmediateM,
+    ImmediateX,
+    Direct,
+    DirectX,
+    DirectY,
+    DirectIndirect,
+    DirectIndirectX,
+    DirectIndirectY,
+    DirectIndirectLong,
+    DirectIndirectLongY,
+    Absolute,
+    AbsoluteJump,
+    AbsoluteX,
+    AbsoluteY,
+    AbsoluteLong,
+    AbsoluteLongX,
+    AbsoluteIndirect,
+    AbsoluteIndirectX,
+    AbsoluteIndirectLong,
+    StackRelative,
+    StackRelativeIndirectY,
+    Relative,
+    RelativeLong,
+    BlockMove,
+}
+
+impl Mode {
+    // The number of bytes after the opcode, given whether A and the index
+    // registers are 8-bit.
+    pub fn operand_len(self, m8: bool, x8: bool) -> usize {
+        match self {
+            Mode::Implied | Mode::Accumulator => 0,
+            Mode::ImmediateM => 2 - m8 as usize,
+            Mode::ImmediateX => 2 - x8 as usize,
+            Mode::Immediate8
+            | Mode::Direct
+            | Mode::DirectX
+            | Mode::DirectY
+            | Mode::DirectIndirect
+            | Mode::DirectIndirectX
+            | Mode::DirectIndirectY
+            | Mode::DirectIndirectLong
+            | Mode::DirectIndirectLongY
+            | Mode::StackRelative
+            | Mode::StackRelativeIndirectY
+            | Mode::Relative => 1,
+            Mode::Immediate16
+            | Mode::Absolute
+            | Mode::AbsoluteJump
+            | Mode::AbsoluteX
+            | Mode::AbsoluteY
+            | Mode::AbsoluteIndirect
+            | Mode::AbsoluteIndirectX
+            | Mode::AbsoluteIndirectLong
+            | Mode::RelativeLong
+            | Mode::BlockMove => 2,
+            Mode::AbsoluteLong | Mode::AbsoluteLongX => 3,
+        }
+    }
+}
+
+// Everything known about an opcode, whether or not the CPU implements it.
+// Unimplemented opcodes decode to Instruction::Unknown, but still have their
+// real mnemonic and mode, so that they can be disassembled and skipped.
+#[derive(Debug, Clone, Copy)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    pub instruction: Instruction,
+}
+
+impl Opcode {
+    pub fn get(opcode: u8) -> &'static Opcode {
+        &OPCODES[opcode as usize]
+    }
+
+    // The length of the whole instruction, including the opcode.
+    pub fn len(&self, m8: bool, x8: bool) -> usize {
+        1 + self.mode.operand_len(m8, x8)
+    }
+}
+
+const fn op(mnemonic: &'static str, mode: Mode, instruction: Instruction) -> Opcode {
+    Opcode {
+        mnemonic,
+        mode,
+        instruction,
+    }
+}
+
+// Every opcode in order, so that decoding in the hot path is a single lookup.
+#[rustfmt::skip]
+static OPCODES: [Opcode; 256] = {
+    use self::Instruction::*;
+    use self::Mode::*;
+
+    [
+        // 0x
+        op("brk", Immediate8, Break),
+        op("ora", DirectIndirectX, Unknown),
+        op("cop", Immediate8, Unknown),
+        op("ora", StackRelative, Unknown),
+        op("tsb", Direct, Unknown),
+        op("ora", Direct, OrDirectPage),
+        op("asl", Direct, Unknown),
+        op("ora", DirectIndirectLong, Unknown),
+        op("php", Implied, PushStatus),
+        op("ora", ImmediateM, OrImmediate),
+        op("asl", Accumulator, ShiftLeft),
+        op("phd", Implied, PushD),
+        op("tsb", Absolute, Unknown),
+        op("ora", Absolute, Unknown),
+        op("asl", Absolute, Unknown),
+        op("ora", AbsoluteLong, Unknown),
+
+        // 1x
+        op("bpl", Relative, Unknown),
+        op("ora", DirectIndirectY, Unknown),
+        op("ora", DirectIndirect, Unknown),
+        op("ora", StackRelativeIndirectY, Unknown),
+        op("trb", Direct, Unknown),
+        op("ora", DirectX, Unknown),
+        op("asl", DirectX, Unknown),
+        op("ora", DirectIndirectLongY, Unknown),
+        op("clc", Implied, ClearCarry),
+        op("ora", AbsoluteY, Unknown),
+        op("inc", Accumulator, IncrementA),
+        op("tcs", Implied, Unknown),
+        op("trb", Absolute, Unknown),
+        op("ora", AbsoluteX, Unknown),
+        op("asl", AbsoluteX, Unknown),
+        op("ora", AbsoluteLongX, Unknown),
+
+        // 2x
+        op("jsr", AbsoluteJump, JumpSubRoutineAbsolute),
+        op("and", DirectIndirectX, Unknown),
+        op("jsl", AbsoluteLong, JumpSubRoutineAbsoluteLong),
+        op("and", StackRelative, Unknown),
+        op("bit", Direct, TestBitsDirectPage),
+        op("and", Direct, AndDirectPage),
+        op("rol", Direct, Unknown),
+        op("and", DirectIndirectLong, Unknown),
+        op("plp", Implied, PullStatus),
+        op("and", ImmediateM, AndImmediate),
+        op("rol", Accumulator, RotateLeft),
+        op("pld", Implied, PullD),
+        op("bit", Absolute, TestBitsAbsolute),
+        op("and", Absolute, Unknown),
+        op("rol", Absolute, Unknown),
+        op("and", AbsoluteLong, Unknown),
+
+        // 3x
+        op("bmi", Relative, Unknown),
+        op("and", DirectIndirectY, Unknown),
+        op("and", DirectIndirect, Unknown),
+        op("and", StackRelativeIndirectY, Unknown),
+        op("bit", DirectX, Unknown),
+        op("and", DirectX, Unknown),
+        op("rol", DirectX, Unknown),
+        op("and", DirectIndirectLongY, Unknown),
+        op("sec", Implied, Unknown),
+        op("and", AbsoluteY, Unknown),
+        op("dec", Accumulator, Unknown),
+        op("tsc", Implied, Unknown),
+        op("bit", AbsoluteX, Unknown),
+        op("and", AbsoluteX, Unknown),
+        op("rol", AbsoluteX, Unknown),
+        op("and", AbsoluteLongX, Unknown),
+
+        // 4x
+        op("rti", Implied, ReturnFromInterrupt),
+        op("eor", DirectIndirectX, Unknown),
+        op("wdm", Immediate8, Unknown),
+        op("eor", StackRelative, Unknown),
+        op("mvp", BlockMove, Unknown),
+        op("eor", Direct, ExclusiveOrDirectPage),
+        op("lsr", Direct, Unknown),
+        op("eor", DirectIndirectLong, Unknown),
+        op("pha", Implied, PushA),
+        op("eor", ImmediateM, ExclusiveOrImmediate),
+        op("lsr", Accumulator, ShiftRight),
+        op("phk", Implied, Unknown),
+        op("jmp", AbsoluteJump, JumpAbsolute),
+        op("eor", Absolute, Unknown),
+        op("lsr", Absolute, Unknown),
+        op("eor", AbsoluteLong, Unknown),
+
+        // 5x
+        op("bvc", Relative, Unknown),
+        op("eor", DirectIndirectY, Unknown),
+        op("eor", DirectIndirect, Unknown),
+        op("eor", StackRelativeIndirectY, Unknown),
+        op("mvn", BlockMove, BlockMoveNext),
+        op("eor", DirectX, Unknown),
+        op("lsr", DirectX, Unknown),
+        op("eor", DirectIndirectLongY, Unknown),
+        op("cli", Implied, ClearIrqDisable),
+        op("eor", AbsoluteY, Unknown),
+        op("phy", Implied, PushY),
+        op("tcd", Implied, Unknown),
+        op("jml", AbsoluteLong, Unknown),
+        op("eor", AbsoluteX, Unknown),
+        op("lsr", AbsoluteX, Unknown),
+        op("eor", AbsoluteLongX, Unknown),
+
+        // 6x
+        op("rts", Implied, Return),
+        op("adc", DirectIndirectX, Unknown),
+        op("per", RelativeLong, Unknown),
+        op("adc", StackRelative, Unknown),
+        op("stz", Direct, StoreZeroDirectPage),
+        op("adc", Direct, AddWithCarryDirectPage),
+        op("ror", Direct, Unknown),
+        op("adc", DirectIndirectLong, Unknown),
+        op("pla", Implied, PullA),
+        op("adc", ImmediateM, AddWithCarryImmediate),
+        op("ror", Accumulator, RotateRight),
+        op("rtl", Implied, ReturnLong),
+        op("jmp", AbsoluteIndirect, Unknown),
+        op("adc", Absolute, AddWithCarryAbsolute),
+        op("ror", Absolute, Unknown),
+        op("adc", AbsoluteLong, Unknown),
+
+        // 7x
+        op("bvs", Relative, Unknown),
+        op("adc", DirectIndirectY, Unknown),
+        op("adc", DirectIndirect, Unknown),
+        op("adc", StackRelativeIndirectY, Unknown),
+        op("stz", DirectX, StoreZeroDirectPageIndexedX),
+        op("adc", DirectX, AddWithCarryDirectPageIndexedX),
+        op("ror", DirectX, Unknown),
+        op("adc", DirectIndirectLongY, Unknown),
+        op("sei", Implied, SetIrqDisable),
+        op("adc", AbsoluteY, AddWithCarryAbsoluteIndexedY),
+        op("ply", Implied, PullY),
+        op("tdc", Implied, MoveDA),
+        op("jmp", AbsoluteIndirectX, Unknown),
+        op("adc", AbsoluteX, Unknown),
+        op("ror", AbsoluteX, Unknown),
+        op("adc", AbsoluteLongX, Unknown),
+
+        // 8x
+        op("bra", Relative, BranchAlways),
+        op("sta", DirectIndirectX, Unknown),
+        op("brl", RelativeLong, Unknown),
+        op("sta", StackRelative, Unknown),
+        op("sty", Direct, StoreYDirectPage),
+        op("sta", Direct, StoreADirectPage),
+        op("stx", Direct, StoreXDirectPage),
+        op("sta", DirectIndirectLong, Unknown),
+        op("dey", Implied, DecrementY),
+        op("bit", ImmediateM, Unknown),
+        op("txa", Implied, Unknown),
+        op("phb", Implied, PushB),
+        op("sty", Absolute, Unknown),
+        op("sta", Absolute, StoreAAbsolute),
+        op("stx", Absolute, StoreXAbsolute),
+        op("sta", AbsoluteLong, Unknown),
+
+        // 9x
+        op("bcc", Relative, BranchCarryClear),
+        op("sta", DirectIndirectY, Unknown),
+        op("sta", DirectIndirect, Unknown),
+        op("sta", StackRelativeIndirectY, Unknown),
+        op("sty", DirectX, Unknown),
+        op("sta", DirectX, StoreADirectPageIndexedX),
+        op("stx", DirectY, Unknown),
+        op("sta", DirectIndirectLongY, Unknown),
+        op("tya", Implied, MoveYA),
+        op("sta", AbsoluteY, StoreAAbsoluteIndexedY),
+        op("txs", Implied, MoveXSP),
+        op("txy", Implied, Unknown),
+        op("stz", Absolute, StoreZeroAbsolute),
+        op("sta", AbsoluteX, StoreAAbsoluteIndexedX),
+        op("stz", AbsoluteX, StoreZeroAbsoluteIndexedX),
+        op("sta", AbsoluteLongX, StoreAAbsoluteLongIndexedX),
+
+        // Ax
+        op("ldy", ImmediateX, LoadYImmediate),
+        op("lda", DirectIndirectX, Unknown),
+        op("ldx", ImmediateX, LoadXImmediate),
+        op("lda", StackRelative, Unknown),
+        op("ldy", Direct, LoadYDirectPage),
+        op("lda", Direct, LoadADirectPage),
+        op("ldx", Direct, LoadXDirectPage),
+        op("lda", DirectIndirectLong, LoadADirectPageIndirectLong),
+        op("tay", Implied, MoveAY),
+        op("lda", ImmediateM, LoadAImmediate),
+        op("tax", Implied, MoveAX),
+        op("plb", Implied, PullB),
+        op("ldy", Absolute, Unknown),
+        op("lda", Absolute, LoadAAbsolute),
+        op("ldx", Absolute, Unknown),
+        op("lda", AbsoluteLong, Unknown),
+
+        // Bx
+        op("bcs", Relative, BranchCarrySet),
+        op("lda", DirectIndirectY, Unknown),
+        op("lda", DirectIndirect, Unknown),
+        op("lda", StackRelativeIndirectY, Unknown),
+        op("ldy", DirectX, Unknown),
+        op("lda", DirectX, Unknown),
+        op("ldx", DirectY, Unknown),
+        op("lda", DirectIndirectLongY, Unknown),
+        op("clv", Implied, Unknown),
+        op("lda", AbsoluteY, LoadAAbsoluteIndexedY),
+        op("tsx", Implied, Unknown),
+        op("tyx", Implied, Unknown),
+        op("ldy", AbsoluteX, Unknown),
+        op("lda", AbsoluteX, LoadAAbsoluteIndexedX),
+        op("ldx", AbsoluteY, Unknown),
+        op("lda", AbsoluteLongX, LoadAAbsoluteLongIndexedX),
+
+        // Cx
+        op("cpy", ImmediateX, CompareYImmediate),
+        op("cmp", DirectIndirectX, Unknown),
+        op("rep", Immediate8, ResetFlags),
+        op("cmp", StackRelative, Unknown),
+        op("cpy", Direct, Unknown),
+        op("cmp", Direct, CompareDirectPage),
+        op("dec", Direct, Unknown),
+        op("cmp", DirectIndirectLong, Unknown),
+        op("iny", Implied, IncrementY),
+        op("cmp", ImmediateM, CompareImmediate),
+        op("dex", Implied, DecrementX),
+        op("wai", Implied, WaitForInterrupt),
+        op("cpy", Absolute, Unknown),
+        op("cmp", Absolute, CompareAbsolute),
+        op("dec", Absolute, Unknown),
+        op("cmp", AbsoluteLong, Unknown),
+
+        // Dx
+        op("bne", Relative, BranchNotEqual),
+        op("cmp", DirectIndirectY, Unknown),
+        op("cmp", DirectIndirect, Unknown),
+        op("cmp", StackRelativeIndirectY, Unknown),
+        op("pei", DirectIndirect, Unknown),
+        op("cmp", DirectX, CompareDirectPageIndexedX),
+        op("dec", DirectX, Unknown),
+        op("cmp", DirectIndirectLongY, Unknown),
+        op("cld", Implied, Unknown),
+        op("cmp", AbsoluteY, Unknown),
+        op("phx", Implied, PushX),
+        op("stp", Implied, Unknown),
+        op("jml", AbsoluteIndirectLong, Unknown),
+        op("cmp", AbsoluteX, Unknown),
+        op("dec", AbsoluteX, Unknown),
+        op("cmp", AbsoluteLongX, CompareAbsoluteLongIndexedX),
+
+        // Ex
+        op("cpx", ImmediateX, CompareXImmediate),
+        op("sbc", DirectIndirectX, Unknown),
+        op("sep", Immediate8, SetFlags),
+        op("sbc", StackRelative, Unknown),
+        op("cpx", Direct, Unknown),
+        op("sbc", Direct, SubtractWithCarryDirectPage),
+        op("inc", Direct, IncrementDirectPage),
+        op("sbc", DirectIndirectLong, Unknown),
+        op("inx", Implied, IncrementX),
+        op("sbc", ImmediateM, SubtractWithCarryImmediate),
+        op("nop", Implied, Unknown),
+        op("xba", Implied, ExchangeBA),
+        op("cpx", Absolute, Unknown),
+        op("sbc", Absolute, Unknown),
+        op("inc", Absolute, Unknown),
+        op("sbc", AbsoluteLong, Unknown),
+
+        // Fx
+        op("beq", Relative, BranchEqual),
+        op("sbc", DirectIndirectY, Unknown),
+        op("sbc", DirectIndirect, Unknown),
+        op("sbc", StackRelativeIndirectY, Unknown),
+        op("pea", Immediate16, PushAbsolute),
+        op("sbc", DirectX, Unknown),
+        op("inc", DirectX, Unknown),
+        op("sbc", DirectIndirectLongY, Unknown),
+        op("sed", Implied, Unknown),
+        op("sbc", AbsoluteY, Unknown),
+        op("plx", Implied, PullX),
+        op("xce", Implied, ExchangeCE),
+        op("jsr", AbsoluteIndirectX, Unknown),
+        op("sbc", AbsoluteX, Unknown),
+        op("inc", AbsoluteX, Unknown),
+        op("sbc", AbsoluteLongX, Unknown),
+    ]
+};
+
+// The first opcode in the table for each instruction, by its position in
+// the enum, so that an instruction can find its entry without a search.
+static FIRST_OPCODES: [u8; 256] = {
+    let mut first = [0; 256];
+    let mut opcode = 256;
+
+    while opcode > 0 {
+        opcode -= 1;
+        first[OPCODES[opcode].instruction as usize] = opcode as u8;
+    }
+
+    first
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The addressing mode notation each mode should print as.
+    fn notation(mode: Mode) -> &'static str {
+        match mode {
+            Mode::Implied => "",
+            Mode::Accumulator => "A",
+            Mode::Immediate8 | Mode::Immediate16 | Mode::ImmediateM | Mode::ImmediateX => "#imm",
+            Mode::Direct => "dp",
+            Mode::DirectX => "dp,X",
+            Mode::DirectY => "dp,Y",
+            Mode::DirectIndirect => "(dp)",
+            Mode::DirectIndirectX => "(dp,X)",
+            Mode::DirectIndirectY => "(dp),Y",
+            Mode::DirectIndirectLong => "[dp]",
+            Mode::DirectIndirectLongY => "[dp],Y",
+            Mode::Absolute | Mode::AbsoluteJump => "abs",
+            Mode::AbsoluteX => "abs,X",
+            Mode::AbsoluteY => "abs,Y",
+            Mode::AbsoluteLong => "long",
+            Mode::AbsoluteLongX => "long,X",
+            Mode::AbsoluteIndirect => "(abs)",
+            Mode::AbsoluteIndirectX => "(abs,X)",
+            Mode::AbsoluteIndirectLong => "[abs]",
+            Mode::StackRelative => "sr,S",
+            Mode::StackRelativeIndirectY => "(sr,S),Y",
+            Mode::Relative | Mode::RelativeLong => "rel",
+            Mode::BlockMove => "src,dest",
+        }
+    }
+
+    fn implemented() -> impl Iterator<Item = (u8, &'static Opcode)> {
+        (0..=255)
+            .map(|opcode| (opcode, Opcode::get(opcode)))
+            .filter(|(_, op)| !matches!(op.instruction, Instruction::Unknown))
+    }
+
+    #[test]
+    fn every_instruction_has_its_mnemonic() {
+        for (opcode, op) in implemented() {
+            let instruction = Instruction::from_opcode(opcode);
+
+            assert_eq!(
+                instruction.mnemonic(),
+                op.mnemonic,
+                "{:02X} {:?}",
+                opcode,
+                instruction
+            );
+            assert_eq!(instruction.to_string(), op.mnemonic.to_uppercase());
+        }
+
+        assert_eq!(Instruction::Unknown.mnemonic(), "???");
+        assert_eq!(Instruction::Unknown.to_string(), "???");
+    }
+
+    #[test]
+    fn every_instruction_has_its_addressing_mode() {
+        for (opcode, op) in implemented() {
+            let expected = match op.instruction {
+                // BRK's signature byte and PEA's operand aren't read as
+                // immediates, so they're written the way the docs do.
+                Instruction::Break => "",
+                Instruction::PushAbsolute => "abs",
+                _ => notation(op.mode),
+            };
+
+            assert_eq!(
+                op.instruction.addressing_mode(),
+                expected,
+                "{:02X} {:?}",
+                opcode,
+                op.instruction
+            );
+        }
+    }
+
+    // The opcode matrix from the 65816 data sheet, typed in separately from
+    // the table above, with each addressing mode written as notation() has
+    // it.
+    #[rustfmt::skip]
+    const REFERENCE: [[&str; 16]; 16] = [
+        ["brk #imm", "ora (dp,X)", "cop #imm", "ora sr,S", "tsb dp", "ora dp", "asl dp", "ora [dp]",
+         "php", "ora #imm", "asl A", "phd", "tsb abs", "ora abs", "asl abs", "ora long"],
+        ["bpl rel", "ora (dp),Y", "ora (dp)", "ora (sr,S),Y", "trb dp", "ora dp,X", "asl dp,X", "ora [dp],Y",
+         "clc", "ora abs,Y", "inc A", "tcs", "trb abs", "ora abs,X", "asl abs,X", "ora long,X"],
+        ["jsr abs", "and (dp,X)", "jsl long", "and sr,S", "bit dp", "and dp", "rol dp", "and [dp]",
+         "plp", "and #imm", "rol A", "pld", "bit abs", "and abs", "rol abs", "and long"],
+        ["bmi rel", "and (dp),Y", "and (dp)", "and (sr,S),Y", "bit dp,X", "and dp,X", "rol dp,X", "and [dp],Y",
+         "sec", "and abs,Y", "dec A", "tsc", "bit abs,X", "and abs,X", "rol abs,X", "and long,X"],
+        ["rti", "eor (dp,X)", "wdm #imm", "eor sr,S", "mvp src,dest", "eor dp", "lsr dp", "eor [dp]",
+         "pha", "eor #imm", "lsr A", "phk", "jmp abs", "eor abs", "lsr abs", "eor long"],
+        ["bvc rel", "eor (dp),Y", "eor (dp)", "eor (sr,S),Y", "mvn src,dest", "eor dp,X", "lsr dp,X", "eor [dp],Y",
+         "cli", "eor abs,Y", "phy", "tcd", "jml long", "eor abs,X", "lsr abs,X", "eor long,X"],
+        ["rts", "adc (dp,X)", "per rel", "adc sr,S", "stz dp", "adc dp", "ror dp", "adc [dp]",
+         "pla", "adc #imm", "ror A", "rtl", "jmp (abs)", "adc abs", "ror abs", "adc long"],
+        ["bvs rel", "adc (dp),Y", "adc (dp)", "adc (sr,S),Y", "stz dp,X", "adc dp,X", "ror dp,X", "adc [dp],Y",
+         "sei", "adc abs,Y", "ply", "tdc", "jmp (abs,X)", "adc abs,X", "ror abs,X", "adc long,X"],
+        ["bra rel", "sta (dp,X)", "brl rel", "sta sr,S", "sty dp", "sta dp", "stx dp", "sta [dp]",
+         "dey", "bit #imm", "txa", "phb", "sty abs", "sta abs", "stx abs", "sta long"],
+        ["bcc rel", "sta (dp),Y", "sta (dp)", "sta (sr,S),Y", "sty dp,X", "sta dp,X", "stx dp,Y", "sta [dp],Y",
+         "tya", "sta abs,Y", "txs", "txy", "stz abs", "sta abs,X", "stz abs,X", "sta long,X"],
+        ["ldy #imm", "lda (dp,X)", "ldx #imm", "lda sr,S", "ldy dp", "lda dp", "ldx dp", "lda [dp]",
+         "tay", "lda #imm", "tax", "plb", "ldy abs", "lda abs", "ldx abs", "lda long"],
+        ["bcs rel", "lda (dp),Y", "lda (dp)", "lda (sr,S),Y", "ldy dp,X", "lda dp,X", "ldx dp,Y", "lda [dp],Y",
+         "clv", "lda abs,Y", "tsx", "tyx", "ldy abs,X", "lda abs,X", "ldx abs,Y", "lda long,X"],
+        ["cpy #imm", "cmp (dp,X)", "rep #imm", "cmp sr,S", "cpy dp", "cmp dp", "dec dp", "cmp [dp]",
+         "iny", "cmp #imm", "dex", "wai", "cpy abs", "cmp abs", "dec abs", "cmp long"],
+        ["bne rel", "cmp (dp),Y", "cmp (dp)", "cmp (sr,S),Y", "pei (dp)", "cmp dp,X", "dec dp,X", "cmp [dp],Y",
+         "cld", "cmp abs,Y", "phx", "stp", "jml [abs]", "cmp abs,X", "dec abs,X", "cmp long,X"],
+        ["cpx #imm", "sbc (dp,X)", "sep #imm", "sbc sr,S", "cpx dp", "sbc dp", "inc dp", "sbc [dp]",
+         "inx", "sbc #imm", "nop", "xba", "cpx abs", "sbc abs", "inc abs", "sbc long"],
+        ["beq rel", "sbc (dp),Y", "sbc (dp)", "sbc (sr,S),Y", "pea abs", "sbc dp,X", "inc dp,X", "sbc [dp],Y",
+         "sed", "sbc abs,Y", "plx", "xce", "jsr (abs,X)", "sbc abs,X", "inc abs,X", "sbc long,X"],
+    ];
+
+    // The operand length the data sheet gives each entry, with immediates
+    // that follow M or X as 1 byte when that flag is set and 2 when not.
+    fn reference_len(entry: &str, m8: bool, x8: bool) -> usize {
+        let (mnemonic, notation) = entry.split_once(' ').unwrap_or((entry, ""));
+
+        match (mnemonic, notation) {
+            (_, "" | "A") => 0,
+            ("ldx" | "ldy" | "cpx" | "cpy", "#imm") => 2 - x8 as usize,
+            ("brk" | "cop" | "wdm" | "rep" | "sep", "#imm") => 1,
+            (_, "#imm") => 2 - m8 as usize,
+            ("brl" | "per", "rel") => 2,
+            (_, "src,dest") => 2,
+            (_, notation) if notation.starts_with("long") => 3,
+            (_, notation) if notation.contains("abs") => 2,
+            _ => 1,
+        }
+    }
+
+    #[test]
+    fn table_matches_the_data_sheet() {
+        for opcode in 0..=255u8 {
+            let op = Opcode::get(opcode);
+            let reference = REFERENCE[opcode as usize >> 4][opcode as usize & 0xF];
+
+            // PEA's operand is read as a 16-bit immediate, but the data
+            // sheet writes it as an address.
+            let entry = match (op.mode, notation(op.mode)) {
+                (Mode::Immediate16, _) => format!("{} abs", op.mnemonic),
+                (_, "") => op.mnemonic.to_string(),
+                (_, notation) => format!("{} {}", op.mnemonic, notation),
+            };
+            assert_eq!(entry, reference, "{:02X}", opcode);
+
+            for (m8, x8) in [(false, false), (false, true), (true, false), (true, true)] {
+                assert_eq!(
+                    op.len(m8, x8),
+                    1 + reference_len(reference, m8, x8),
+                    "{:02X} with m8 {} and x8 {}",
+                    opcode,
+                    m8,
+                    x8
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decoding_follows_the_table() {
+        for opcode in 0..=255u8 {
+            let op = Opcode::get(opcode);
+            let instruction = Instruction::from_opcode(opcode);
+
+            assert_eq!(
+                format!("{:?}", instruction),
+                format!("{:?}", op.instruction),
+                "{:02X}",
+                opcode
+            );
+            assert_eq!(
+                crate::disasm::mnemonic(opcode),
+                op.mnemonic,
+                "{:02X}",
+                opcode
+            );
+        }
+
+        // A few that the CPU doesn't run yet, which still decode to their
+        // real names and lengths.
+        for (opcode, entry, len) in [
+            (0x5B, "tcd", 1),
+            (0x42, "wdm #imm", 2),
+            (0xDC, "jml [abs]", 3),
+        ] {
+            assert!(matches!(
+                Instruction::from_opcode(opcode),
+                Instruction::Unknown
+            ));
+            assert_eq!(
+                REFERENCE[opcode as usize >> 4][opcode as usize & 0xF],
+                entry
+            );
+            assert_eq!(Opcode::get(opcode).len(true, true), len);
+        }
+    }
+
+    #[test]
+    fn display_and_debug() {
+        let instruction = Instruction::LoadAAbsoluteIndexedX;
+
+        assert_eq!(format!("{}", instruction), "LDA");
+        assert_eq!(format!("{:?}", instruction), "LoadAAbsoluteIndexedX");
+        assert_eq!(instruction.addressing_mode(), "abs,X");
     }
 }
@@ -0,0 +1,30 @@
+#[cfg(feature = "test-support")]
+pub mod asm;
+pub mod battery;
+pub mod bus;
+pub mod cdl;
+pub mod cheat;
+pub mod coprocessor;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod dma;
+pub mod emulator;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "frontend")]
+pub mod frontend;
+pub mod header;
+pub mod input;
+pub mod inst;
+pub mod mmu;
+pub mod ppu;
+pub mod ram_search;
+pub mod savestate;
+pub mod spc;
+pub mod stack;
+pub mod symbols;
+pub mod trace;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,57 +1,69 @@
-mod cpu;
-mod inst;
-mod mmu;
-
-use std::collections::VecDeque;
-use std::fmt::Write;
-
-use self::cpu::Cpu;
-use self::inst::Instruction;
-use self::mmu::Mmu;
+use snesemu::frontend::modes::{
+    check_determinism, check_frame_hashes, print_opcodes, print_rom_info, run_batch,
+};
+use snesemu::frontend::options::{Options, USAGE};
+use snesemu::frontend::session::{self, Session};
+use snesemu::mmu::RamInit;
 
 fn main() {
-    let rom = std::fs::read("ff2.sfc").unwrap();
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    let mut mmu = Mmu::new(rom);
-    let mut cpu = Cpu::new();
-    cpu.set_current_addr(mmu.reset_vector() as u32);
+    if options.info {
+        std::process::exit(print_rom_info(&options));
+    }
 
-    let mut snapshots = VecDeque::new();
+    if options.opcodes && options.rom.is_empty() {
+        print_opcodes(&options, None);
+        return;
+    }
 
-    loop {
-        if snapshots.len() >= 200 {
-            snapshots.pop_front();
-        }
+    if let Some(dir) = &options.batch {
+        std::process::exit(run_batch(&options, dir));
+    }
 
-        snapshots.push_back(cpu.clone());
+    if let RamInit::Random(_) = options.ram_init {
+        eprintln!("RAM starts as {}", options.ram_init);
+    }
 
-        let opcode = mmu.read_u8(cpu.current_addr());
-        let inst = Instruction::from_opcode(opcode);
+    if options.check_determinism {
+        std::process::exit(check_determinism(&options));
+    }
 
-        cpu.tick(&mut mmu);
+    if let Some(frames) = options.frame_hashes {
+        std::process::exit(check_frame_hashes(&options, frames));
+    }
 
-        if let Instruction::Unknown = inst {
-            break;
+    // The first Ctrl+C stops at the next instruction, so that the trace and
+    // everything else still gets written. If that doesn't work, because it's
+    // stuck somewhere other than running instructions, a second one gives up.
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = ctrlc::set_handler(|| {
+        if session::interrupt() {
+            std::process::exit(130);
         }
-    }
+    });
 
-    let mut output = String::new();
-
-    for snapshot in snapshots {
-        let current_addr = snapshot.current_addr();
-        let opcode = mmu.read_u8(current_addr);
-        let inst = Instruction::from_opcode(opcode);
-
-        let _ = writeln!(
-            output,
-            "[{:>06X}] {:02X} {:?}\n         {}\n         Stack: [{}]",
-            current_addr,
-            opcode,
-            inst,
-            snapshot.register_debug(),
-            snapshot.stack_debug(&mmu) // TODO: This isn't accurate for snapshots
-        );
-    }
+    let (mut emulator, mut session) = match session::read_rom(&options)
+        .and_then(|rom| session::prepare(&options, rom))
+        .and_then(|mut emulator| Session::new(options, &mut emulator).map(|s| (emulator, s)))
+    {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+
+    let stop = session.run(&mut emulator);
+    let code = session.finish(&mut emulator, stop);
 
-    let _ = std::fs::write("output.log", &output);
+    if code != 0 {
+        std::process::exit(code);
+    }
 }
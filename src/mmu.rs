@@ -1,28 +1,998 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::cdl::CodeDataLog;
+use crate::cheat::{Cheat, CheatKind};
+use crate::coprocessor::{Chip, Coprocessor};
+use crate::dma::{self, DmaChannel};
+use crate::events::{EventKind, EventLog};
+use crate::input::Controllers;
+use crate::ppu::Ppu;
+use crate::spc::Spc700;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub enum MapMode {
+    LoRom,
+    HiRom,
+}
+
+impl MapMode {
+    // Guesses the mapping by checking which of the two possible header
+    // locations looks the most plausible.
+    pub fn detect(cartridge: &[u8]) -> MapMode {
+        let lorom = header_score(cartridge, MapMode::LoRom.header_offset());
+        let hirom = header_score(cartridge, MapMode::HiRom.header_offset());
+
+        if hirom > lorom {
+            MapMode::HiRom
+        } else {
+            MapMode::LoRom
+        }
+    }
+
+    // Where the internal header is in the ROM image.
+    pub fn header_offset(self) -> usize {
+        match self {
+            MapMode::LoRom => 0x7FC0,
+            MapMode::HiRom => 0xFFC0,
+        }
+    }
+}
+
+// The smallest ROM that has room for a LoROM header and the vectors. Anything
+// smaller can't be run, as there's nowhere to reset to.
+pub const MIN_ROM_SIZE: usize = 0x8000;
+
+// Dumps from copier devices have a 512 byte header in front of the actual ROM
+// data. Returns true if one was removed.
+pub fn strip_copier_header(cartridge: &mut Vec<u8>) -> bool {
+    if cartridge.len() % 0x400 == 0x200 {
+        cartridge.drain(..0x200);
+        true
+    } else {
+        false
+    }
+}
+
+fn header_score(cartridge: &[u8], header: usize) -> i32 {
+    if cartridge.len() < header + 0x40 {
+        return i32::MIN;
+    }
+
+    let read_u16 = |addr: usize| u16::from_le_bytes([cartridge[addr], cartridge[addr + 1]]);
+
+    let map_mode = cartridge[header + 0x15];
+    let complement = read_u16(header + 0x1C);
+    let checksum = read_u16(header + 0x1E);
+    let reset_vector = read_u16(header + 0x3C);
+
+    let mut score = 0;
+
+    if checksum ^ complement == 0xFFFF {
+        score += 4;
+    }
+
+    // Bit 0 of the map mode byte is set for HiROM.
+    if map_mode & 0xE0 == 0x20 && (map_mode & 1 == 1) == (header == 0xFFC0) {
+        score += 2;
+    }
+
+    if reset_vector >= 0x8000 {
+        score += 1;
+    }
+
+    score
+}
+
+// The address space is split into 8KB pages, so that plain memory can be
+// read without going through the full address decoding.
+const PAGE_SHIFT: u32 = 13;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+const PAGE_COUNT: usize = 0x100_0000 >> PAGE_SHIFT;
+
+// The header stores the SRAM size as a power of two number of kilobytes,
+// with zero meaning there isn't any. Anything too big to be real is treated
+// as none too.
+fn sram_size(size: u8) -> usize {
+    match size {
+        1..=8 => 0x400 << size,
+        _ => 0,
+    }
+}
+
+// The 5A22 revision, as reported in the low bits of RDNMI.
+const CPU_VERSION: u8 = 2;
+
+#[derive(Debug, Clone, Copy)]
+enum Page {
+    // Offsets of the start of the page into WRAM or the cartridge.
+    Ram(usize),
+    Rom(usize),
+
+    // Anything else, including I/O registers and unmapped areas, goes
+    // through read_slow/store_slow.
+    Slow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Watchpoint {
+    pub start: u32,
+    pub end: u32,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: Access, addr: u32) -> bool {
+        let enabled = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+
+        enabled && (self.start..=self.end).contains(&addr)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchHit {
+    pub access: Access,
+    pub addr: u32,
+    pub value: u8,
+}
+
+// A read or write of one of the hardware registers, for the I/O log.
+#[derive(Debug, Clone, Copy)]
+pub struct IoAccess {
+    pub access: Access,
+    pub addr: u32,
+    pub value: u8,
+}
+
+// Whether an access to a register in banks $00-$3F is one the emulator
+// doesn't handle yet, so that it's ignored or reads as 0. This has to be
+// kept in step with read_slow, store_slow and the PPU's registers.
+pub fn unmapped_register(access: Access, offset: u16) -> bool {
+    match (access, offset) {
+        // The PPU's write-only registers read as open bus, which is right.
+        (_, 0x2100..=0x213F) => false,
+
+        // The APU ports, but not their mirrors, or the WRAM port.
+        (_, 0x2140..=0x2143) => false,
+        (_, 0x2144..=0x21FF) => true,
+
+        (Access::Read, 0x4016..=0x4017 | 0x4210 | 0x4213 | 0x4218..=0x421F) => false,
+        (Access::Write, 0x4016 | 0x4200..=0x4201 | 0x420B..=0x420D) => false,
+        (_, 0x4300..=0x437F) => false,
+        (_, 0x4000..=0x44FF) => true,
+
+        _ => false,
+    }
+}
+
+// What to do when something writes to an address that's mapped to ROM. The
+// write never has any effect, but it's usually a sign that an address was
+// worked out wrongly, either by the game or by the emulator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RomWritePolicy {
+    Ignore,
+    #[default]
+    Warn,
+    Strict,
+}
+
+// What RAM holds at power on. Real consoles come up with something close to
+// a repeating pattern that varies from one unit to the next, so a game that
+// reads RAM before writing it can behave differently depending on this.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RamInit {
+    #[default]
+    Zero,
+    Ff,
+
+    // 55 AA 55 AA ...
+    Pattern,
+
+    // Seeded, so that a run can be repeated.
+    Random(u64),
+}
+
+impl RamInit {
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInit::Zero => ram.fill(0x00),
+            RamInit::Ff => ram.fill(0xFF),
+            RamInit::Pattern => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if i % 2 == 0 { 0x55 } else { 0xAA };
+                }
+            }
+            RamInit::Random(seed) => {
+                let mut state = seed;
+
+                for chunk in ram.chunks_mut(8) {
+                    let bytes = splitmix64(&mut state).to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+// The same names as --ram-init takes, e.g. `55aa` or `random:1234`.
+impl fmt::Display for RamInit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RamInit::Zero => write!(f, "zero"),
+            RamInit::Ff => write!(f, "ff"),
+            RamInit::Pattern => write!(f, "55aa"),
+            RamInit::Random(seed) => write!(f, "random:{}", seed),
+        }
+    }
+}
+
+// A small, fast generator that's good enough for filling RAM with noise.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mmu {
-    cartridge: Vec<u8>,
+    // The ROM isn't part of save states, so that they stay small. It's
+    // shared so that copies of the machine state (e.g. for rewinding) don't
+    // have to copy it either.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    cartridge: Arc<[u8]>,
+    map_mode: MapMode,
     ram: Vec<u8>,
 
-    spc: [u8; 4],
+    // Exactly the size of the chip, which is mirrored throughout the SRAM
+    // region. Empty if the cartridge doesn't have any.
+    sram: Vec<u8>,
+
+    // Set by writes to SRAM, so that the battery save is only written when
+    // something has changed.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    sram_dirty: bool,
+
+    // The last value on the CPU's data bus, which is what's read back from
+    // addresses that nothing responds to.
+    open_bus: u8,
+
+    // Rebuilt from the map mode rather than saved.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pages: Box<[Page]>,
+
+    // The master cycles that an access to each page takes, or zero for the
+    // one page that mixes speeds. Rebuilt whenever MEMSEL changes.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    speeds: Box<[u8]>,
+
+    // MEMSEL. Bit 0 makes ROM in banks $80-$FF fast.
+    memsel: u8,
+
+    pub spc: Spc700,
+
+    // A chip on the cartridge, if there's one that's emulated.
+    pub coprocessor: Option<Chip>,
+
+    pub ppu: Ppu,
+    pub controllers: Controllers,
+
+    // WRIO, the CPU's programmable I/O port. Bit 7 is wired to the second
+    // controller port and to the PPU's counter latch.
+    wrio: u8,
+
+    // NMITIMEN, which enables interrupts and auto-read.
+    nmitimen: u8,
+
+    // The flag read from RDNMI, which is set at the start of vblank and
+    // cleared either by reading it or at the end of vblank.
+    nmi_flag: bool,
+
+    // The CPU's NMI input is the flag ANDed with the enable bit, and an NMI
+    // is raised when that goes from low to high. This is its level as of the
+    // last check.
+    nmi_line: bool,
+
+    dma: [DmaChannel; 8],
+
+    // MDMAEN, the channels to run once the current instruction finishes.
+    mdmaen: u8,
+
+    // HDMAEN, the channels that run HDMA during the frame.
+    hdmaen: u8,
+
+    // Debugging
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub watchpoints: Vec<Watchpoint>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    watch_hits: Vec<WatchHit>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub cdl: Option<CodeDataLog>,
+
+    // Writes to ROM since the list was last cleared, as (address, value).
+    // Nothing is kept if the policy is to ignore them.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub rom_write_policy: RomWritePolicy,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    rom_writes: Vec<(u32, u8)>,
+
+    // If set, every read and write is kept until the next instruction
+    // starts.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub log_accesses: bool,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    accesses: Vec<(Access, u32)>,
+
+    // If set, accesses to the hardware registers are kept, with their
+    // values, until the next instruction starts.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub log_io: bool,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    io_accesses: Vec<IoAccess>,
+
+    // If set, every write is kept with its value, until the next
+    // instruction starts.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub log_writes: bool,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    writes: Vec<(u32, u8)>,
+
+    // If set, hardware events like NMIs and DMAs are kept here.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    pub events: Option<EventLog>,
+
+    // Cheats are part of the setup rather than the state. The Game Genie
+    // ones are kept as patches to the ROM, by offset, so that they cover
+    // every mirror of the address.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    cheats: Vec<Cheat>,
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    rom_patches: HashMap<usize, u8>,
 }
 
 impl Mmu {
-    pub fn new(cartridge: Vec<u8>) -> Mmu {
+    pub fn new(mut cartridge: Vec<u8>, map_mode: Option<MapMode>) -> Mmu {
+        strip_copier_header(&mut cartridge);
+
+        let map_mode = map_mode.unwrap_or_else(|| MapMode::detect(&cartridge));
+
+        let sram_size = cartridge
+            .get(map_mode.header_offset() + 0x18)
+            .map_or(0, |&size| sram_size(size));
+
+        let coprocessor = Chip::detect(&cartridge, map_mode);
+
+        let mut mmu = Mmu {
+            cartridge: cartridge.into(),
+            map_mode,
+            ram: vec![0; 0x20000],
+            sram: vec![0; sram_size],
+            sram_dirty: false,
+            open_bus: 0,
+            pages: Box::default(),
+            speeds: Box::default(),
+            memsel: 0,
+
+            spc: Spc700::new(),
+
+            coprocessor,
+
+            ppu: Ppu::new(),
+            controllers: Controllers::new(),
+            wrio: 0xFF,
+            nmitimen: 0,
+            nmi_flag: false,
+            nmi_line: false,
+
+            dma: [DmaChannel::default(); 8],
+            mdmaen: 0,
+            hdmaen: 0,
+
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            cdl: None,
+
+            rom_write_policy: RomWritePolicy::default(),
+            rom_writes: Vec::new(),
+
+            log_accesses: false,
+            accesses: Vec::new(),
+
+            log_io: false,
+            io_accesses: Vec::new(),
+
+            log_writes: false,
+            writes: Vec::new(),
+
+            events: None,
+
+            cheats: Vec::new(),
+            rom_patches: HashMap::new(),
+        };
+
+        mmu.build_pages();
+        mmu
+    }
+
+    // This needs calling again if the mapping ever changes.
+    fn build_pages(&mut self) {
+        let mut pages = vec![Page::Slow; PAGE_COUNT];
+
+        for (i, page) in pages.iter_mut().enumerate() {
+            let addr = (i << PAGE_SHIFT) as u32;
+            let bank = (addr >> 16) as u8;
+            let offset = (addr & 0xFFFF) as u16;
+
+            *page = match bank {
+                0x00..=0x3F | 0x80..=0xBF if offset < 0x2000 => Page::Ram(0),
+                0x00..=0x3F | 0x80..=0xBF if offset < 0x8000 => Page::Slow,
+                0x7E..=0x7F => Page::Ram((addr & 0x1_FFFF) as usize),
+
+                // Coprocessors can be mapped over ROM.
+                _ if self
+                    .coprocessor
+                    .as_ref()
+                    .is_some_and(|chip| chip.maps(bank, offset)) =>
+                {
+                    Page::Slow
+                }
+
+                // The page can only be read directly if it isn't split by
+                // the mirroring of a ROM whose size isn't a multiple of 8KB,
+                // and if no cheats patch it.
+                _ => match self.rom_offset(bank, offset) {
+                    Some(index)
+                        if index + PAGE_SIZE <= self.cartridge.len()
+                            && !self.rom_patched(index, PAGE_SIZE) =>
+                    {
+                        Page::Rom(index)
+                    }
+                    _ => Page::Slow,
+                },
+            };
+        }
+
+        self.pages = pages.into();
+        self.build_speeds();
+    }
+
+    fn build_speeds(&mut self) {
+        self.speeds = (0..PAGE_COUNT)
+            .map(|i| {
+                let addr = (i << PAGE_SHIFT) as u32;
+
+                match ((addr >> 16) as u8, addr as u16) {
+                    // The joypad registers are slower than the rest.
+                    (0x00..=0x3F | 0x80..=0xBF, 0x4000..=0x5FFF) => 0,
+                    _ => self.speed(addr) as u8,
+                }
+            })
+            .collect();
+    }
+
+    // Copies the machine state, leaving out any debugging setup.
+    pub fn snapshot(&self) -> Mmu {
         Mmu {
-            cartridge,
-            ram: vec![0; 128000],
+            cartridge: self.cartridge.clone(),
+            map_mode: self.map_mode,
+            ram: self.ram.clone(),
+            sram: self.sram.clone(),
+            sram_dirty: false,
+            open_bus: self.open_bus,
+            pages: self.pages.clone(),
+            speeds: self.speeds.clone(),
+            memsel: self.memsel,
+
+            spc: self.spc.clone(),
+
+            coprocessor: self.coprocessor.clone(),
+
+            ppu: self.ppu.clone(),
+            controllers: self.controllers.clone(),
+            wrio: self.wrio,
+            nmitimen: self.nmitimen,
+            nmi_flag: self.nmi_flag,
+            nmi_line: self.nmi_line,
+
+            dma: self.dma,
+            mdmaen: self.mdmaen,
+            hdmaen: self.hdmaen,
+
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            cdl: None,
+
+            rom_write_policy: RomWritePolicy::default(),
+            rom_writes: Vec::new(),
+
+            log_accesses: false,
+            accesses: Vec::new(),
+
+            log_io: false,
+            io_accesses: Vec::new(),
+
+            log_writes: false,
+            writes: Vec::new(),
 
-            spc: [0xAA, 0xBB, 0x00, 0x00],
+            events: None,
+
+            cheats: Vec::new(),
+            rom_patches: HashMap::new(),
         }
     }
 
-    pub fn read_u8(&self, addr: u32) -> u8 {
-        // TODO: This is hardcoded to LoROM at the moment.
+    pub fn access_cycles(&self, addr: u32) -> u64 {
+        match self.speeds[(addr as usize >> PAGE_SHIFT) & (PAGE_COUNT - 1)] {
+            0 => self.speed(addr),
+            cycles => cycles as u64,
+        }
+    }
 
+    // The full version of access_cycles, for building the table. The
+    // header's FastROM bit only says that the game expects fast ROM, it's
+    // MEMSEL that actually enables it.
+    fn speed(&self, addr: u32) -> u64 {
         let bank = (addr >> 16) as u8;
         let offset = (addr & 0x00_FFFF) as u16;
+        let fast = self.memsel & 1 != 0;
 
         match bank {
-            0x00..=0x3F => {
+            0x00..=0x3F | 0x80..=0xBF => match offset {
+                0x2000..=0x3FFF => 6,
+                0x4000..=0x41FF => 12,
+                0x4200..=0x5FFF => 6,
+                0x8000..=0xFFFF if bank >= 0x80 && fast => 6,
+                _ => 8,
+            },
+
+            0xC0..=0xFF if fast => 6,
+
+            _ => 8,
+        }
+    }
+
+    pub fn read_u8(&mut self, addr: u32) -> u8 {
+        let value = self.read_mapped(addr);
+        self.open_bus = value;
+
+        if self.log_accesses {
+            self.accesses.push((Access::Read, addr));
+        }
+
+        if self.log_io {
+            self.log_io_access(Access::Read, addr, value);
+        }
+
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(Access::Read, addr, value);
+        }
+
+        value
+    }
+
+    pub fn store_u8(&mut self, addr: u32, value: u8) {
+        self.open_bus = value;
+
+        if self.log_accesses {
+            self.accesses.push((Access::Write, addr));
+        }
+
+        if self.log_io {
+            self.log_io_access(Access::Write, addr, value);
+        }
+
+        if self.log_writes {
+            self.writes.push((addr, value));
+        }
+
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(Access::Write, addr, value);
+        }
+
+        self.store_mapped(addr, value);
+    }
+
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    pub fn rom_writes(&self) -> &[(u32, u8)] {
+        &self.rom_writes
+    }
+
+    pub fn clear_rom_writes(&mut self) {
+        self.rom_writes.clear();
+    }
+
+    // Kept out of line, as it's almost never called and the store path is
+    // hot.
+    #[cold]
+    fn rom_write(&mut self, addr: u32, value: u8) {
+        if self.rom_write_policy != RomWritePolicy::Ignore {
+            self.rom_writes.push((addr, value));
+        }
+    }
+
+    // The accesses made since the log was last cleared, if log_accesses is
+    // set.
+    pub fn accesses(&self) -> &[(Access, u32)] {
+        &self.accesses
+    }
+
+    pub fn clear_accesses(&mut self) {
+        self.accesses.clear();
+    }
+
+    // The register accesses made since the log was last cleared, if log_io
+    // is set. DMA transfers show up here too.
+    pub fn io_accesses(&self) -> &[IoAccess] {
+        &self.io_accesses
+    }
+
+    pub fn clear_io_accesses(&mut self) {
+        self.io_accesses.clear();
+    }
+
+    // The writes made since the log was last cleared, if log_writes is set.
+    pub fn writes(&self) -> &[(u32, u8)] {
+        &self.writes
+    }
+
+    pub fn clear_writes(&mut self) {
+        self.writes.clear();
+    }
+
+    fn log_io_access(&mut self, access: Access, addr: u32, value: u8) {
+        let bank = (addr >> 16) as u8;
+        let offset = addr as u16;
+
+        if matches!(bank, 0x00..=0x3F | 0x80..=0xBF) && matches!(offset >> 8, 0x21 | 0x40..=0x44) {
+            self.io_accesses.push(IoAccess {
+                access,
+                addr,
+                value,
+            });
+        }
+    }
+
+    fn check_watchpoints(&mut self, access: Access, addr: u32, value: u8) {
+        // Low RAM is mirrored into the system banks, so accesses through the
+        // mirror should trip watchpoints set on the 7E bank address.
+        let bank = (addr >> 16) as u8;
+        let offset = addr & 0xFFFF;
+
+        let addr = match bank {
+            0x00..=0x3F | 0x80..=0xBF if offset < 0x2000 => 0x7E_0000 | offset,
+            _ => addr,
+        };
+
+        if self.watchpoints.iter().any(|w| w.matches(access, addr)) {
+            self.watch_hits.push(WatchHit {
+                access,
+                addr,
+                value,
+            });
+        }
+    }
+
+    // Reads memory without any of the side effects a read from the CPU
+    // would have, and without tripping watchpoints, so that the debugger can
+    // look at I/O registers without disturbing them.
+    pub fn peek_u8(&self, addr: u32) -> u8 {
+        let offset = addr as usize & (PAGE_SIZE - 1);
+
+        match self.pages[(addr as usize >> PAGE_SHIFT) & (PAGE_COUNT - 1)] {
+            Page::Ram(base) => self.ram[base + offset],
+            Page::Rom(base) => self.cartridge[base + offset],
+            Page::Slow => self.peek_slow(addr),
+        }
+    }
+
+    // Peeks a run of bytes, wrapping around at the end of the address space.
+    pub fn peek_bytes(&self, start: u32, len: usize) -> Vec<u8> {
+        (0..len as u32)
+            .map(|i| self.peek_u8(start.wrapping_add(i) & 0xFF_FFFF))
+            .collect()
+    }
+
+    fn read_mapped(&mut self, addr: u32) -> u8 {
+        let offset = addr as usize & (PAGE_SIZE - 1);
+
+        match self.pages[(addr as usize >> PAGE_SHIFT) & (PAGE_COUNT - 1)] {
+            Page::Ram(base) => self.ram[base + offset],
+            Page::Rom(base) => self.cartridge[base + offset],
+            Page::Slow => self.read_slow(addr),
+        }
+    }
+
+    fn read_slow(&mut self, addr: u32) -> u8 {
+        let bank = (addr >> 16) as u8;
+        let offset = (addr & 0x00_FFFF) as u16;
+
+        if let Some(chip) = self.coprocessor_mut(bank, offset) {
+            return chip.read(bank, offset);
+        }
+
+        match (bank, offset) {
+            // Reading some of the PPU's registers changes its state.
+            (0x00..=0x3F | 0x80..=0xBF, 0x2100..=0x213F) => self.ppu.read(offset),
+
+            // As does reading the controllers serially.
+            (0x00..=0x3F | 0x80..=0xBF, 0x4016) => self.controllers.read_port1(),
+            (0x00..=0x3F | 0x80..=0xBF, 0x4017) => self.controllers.read_port2(self.io_bit()),
+
+            // And acknowledging the NMI.
+            (0x00..=0x3F | 0x80..=0xBF, 0x4210) => {
+                let value = self.peek_slow(addr);
+                self.nmi_flag = false;
+
+                value
+            }
+
+            _ => self.peek_slow(addr),
+        }
+    }
+
+    // Runs the transfers that MDMAEN started, one channel after another.
+    // Returns how many master cycles they took, during which the CPU is
+    // paused, and how many bytes they moved.
+    pub fn run_dma(&mut self) -> (u64, u64) {
+        if self.mdmaen == 0 {
+            return (0, 0);
+        }
+
+        let channels = std::mem::take(&mut self.mdmaen);
+
+        let mut cycles = dma::CYCLES_PER_TRANSFER;
+        let mut bytes = 0;
+
+        for i in (0..8).filter(|i| channels & 1 << i != 0) {
+            let pattern = self.dma[i].pattern();
+            let len = self.dma[i].transfer_len();
+
+            for n in 0..len as usize {
+                let channel = self.dma[i];
+                let b_addr =
+                    0x2100 | channel.b_addr.wrapping_add(pattern[n % pattern.len()]) as u32;
+                let a_addr = channel.a_bus_addr();
+
+                if channel.b_to_a() {
+                    let value = self.read_u8(b_addr);
+                    self.store_u8(a_addr, value);
+                } else {
+                    let value = self.read_u8(a_addr);
+                    self.store_u8(b_addr, value);
+                }
+
+                self.dma[i].step_a_addr();
+            }
+
+            self.dma[i].count = 0;
+
+            self.push_event(EventKind::DmaTransfer {
+                channel: i as u8,
+                bytes: len,
+                b_addr: self.dma[i].b_addr,
+            });
+
+            cycles += dma::CYCLES_PER_CHANNEL + len as u64 * dma::CYCLES_PER_BYTE;
+            bytes += len as u64;
+        }
+
+        (cycles, bytes)
+    }
+
+    pub fn dma_channels(&self) -> &[DmaChannel; 8] {
+        &self.dma
+    }
+
+    // Runs the PPU a line at a time, so that HDMA can happen in between.
+    // HDMA pauses the CPU while the PPU carries on, so the PPU runs for
+    // however long that takes too. Returns whether a frame was completed,
+    // and the cycles that HDMA took.
+    pub fn step_ppu(&mut self, cycles: u64) -> (bool, u64) {
+        let mut frame_complete = false;
+        let mut remaining = cycles;
+        let mut hdma_cycles = 0;
+
+        while remaining > 0 {
+            let step = remaining.min(self.ppu.cycles_left_in_line());
+
+            frame_complete |= self.ppu.step(step);
+            remaining -= step;
+
+            if self.ppu.h_counter() != 0 || self.hdmaen == 0 {
+                continue;
+            }
+
+            // Each line is drawn at the end of the line after it, so the
+            // start of a line stands in for the hblank before it's drawn.
+            let cycles = match self.ppu.scanline() {
+                0 => self.init_hdma(),
+                line if line <= self.ppu.vblank_line() => self.run_hdma(),
+                _ => 0,
+            };
+
+            remaining += cycles;
+            hdma_cycles += cycles;
+        }
+
+        (frame_complete, hdma_cycles)
+    }
+
+    // Rewinds every enabled channel to the start of its table.
+    fn init_hdma(&mut self) -> u64 {
+        let channels = self.hdmaen;
+        let mut cycles = dma::HDMA_CYCLES_PER_LINE;
+
+        self.push_event(EventKind::HdmaInit { channels });
+
+        for i in (0..8).filter(|i| channels & 1 << i != 0) {
+            self.dma[i].table_addr = self.dma[i].a_addr;
+            self.dma[i].hdma_terminated = false;
+
+            cycles += dma::HDMA_CYCLES_PER_CHANNEL + self.load_hdma_entry(i);
+        }
+
+        cycles
+    }
+
+    fn run_hdma(&mut self) -> u64 {
+        let channels = (0..8)
+            .filter(|&i| self.hdmaen & 1 << i != 0 && !self.dma[i].hdma_terminated)
+            .fold(0u8, |channels, i| channels | 1 << i);
+
+        if channels == 0 {
+            return 0;
+        }
+
+        let mut cycles = dma::HDMA_CYCLES_PER_LINE;
+
+        for i in (0..8).filter(|i| channels & 1 << i != 0) {
+            cycles += dma::HDMA_CYCLES_PER_CHANNEL;
+
+            if self.dma[i].hdma_do_transfer {
+                for &offset in self.dma[i].pattern() {
+                    let channel = &mut self.dma[i];
+                    let b_addr = 0x2100 | channel.b_addr.wrapping_add(offset) as u32;
+
+                    let a_addr = if channel.indirect() {
+                        let addr = channel.indirect_bus_addr();
+                        channel.count = channel.count.wrapping_add(1);
+                        addr
+                    } else {
+                        let addr = channel.table_bus_addr();
+                        channel.table_addr = channel.table_addr.wrapping_add(1);
+                        addr
+                    };
+
+                    if channel.b_to_a() {
+                        let value = self.read_u8(b_addr);
+                        self.store_u8(a_addr, value);
+                    } else {
+                        let value = self.read_u8(a_addr);
+                        self.store_u8(b_addr, value);
+                    }
+
+                    cycles += dma::CYCLES_PER_BYTE;
+                }
+            }
+
+            // Bit 7 of the counter is the repeat flag, which makes the
+            // channel transfer on every line of the entry rather than just
+            // the first. As the whole byte counts down, $80 gives 128 lines
+            // without repeating, and $81-$FF give 1-127 lines with it.
+            let channel = &mut self.dma[i];
+            channel.line_counter = channel.line_counter.wrapping_sub(1);
+            channel.hdma_do_transfer = channel.line_counter & 0x80 != 0;
+
+            if channel.line_counter & 0x7F == 0 {
+                cycles += self.load_hdma_entry(i);
+            }
+        }
+
+        cycles
+    }
+
+    // Reads the next entry's line counter, and in indirect mode, the
+    // address of its data. A counter of zero ends the table for the rest of
+    // the frame.
+    fn load_hdma_entry(&mut self, i: usize) -> u64 {
+        let line_counter = self.read_u8(self.dma[i].table_bus_addr());
+
+        let channel = &mut self.dma[i];
+        channel.table_addr = channel.table_addr.wrapping_add(1);
+        channel.line_counter = line_counter;
+        channel.hdma_do_transfer = true;
+
+        if line_counter == 0 {
+            channel.hdma_terminated = true;
+            return 0;
+        }
+
+        if !channel.indirect() {
+            return 0;
+        }
+
+        let low = self.read_u8(self.dma[i].table_bus_addr());
+        self.dma[i].table_addr = self.dma[i].table_addr.wrapping_add(1);
+        let high = self.read_u8(self.dma[i].table_bus_addr());
+        self.dma[i].table_addr = self.dma[i].table_addr.wrapping_add(1);
+
+        self.dma[i].count = u16::from_le_bytes([low, high]);
+
+        dma::HDMA_CYCLES_PER_INDIRECT
+    }
+
+    pub fn start_vblank(&mut self) {
+        self.nmi_flag = true;
+
+        // Auto-read isn't emulated yet, as JOY1-JOY4 are read straight from
+        // the pads, but this is when it would start.
+        if self.nmitimen & 1 != 0 {
+            self.push_event(EventKind::AutoJoypadRead);
+        }
+    }
+
+    // Stamps an event with where the PPU is, if events are being kept.
+    pub fn push_event(&mut self, kind: EventKind) {
+        if let Some(events) = &mut self.events {
+            events.push(self.ppu.frame(), self.ppu.scanline(), self.ppu.dot(), kind);
+        }
+    }
+
+    pub fn end_vblank(&mut self) {
+        self.nmi_flag = false;
+    }
+
+    // Returns true if the NMI line has gone high since the last check, in
+    // which case the CPU should take an NMI. This has to be checked after
+    // anything that could change the flag or the enable bit, so that
+    // enabling NMI partway through vblank (while the flag is still set)
+    // raises one straight away.
+    pub fn take_nmi_edge(&mut self) -> bool {
+        let line = self.nmi_flag && self.nmitimen & 0x80 != 0;
+        let edge = line && !self.nmi_line;
+
+        self.nmi_line = line;
+        edge
+    }
+
+    fn io_bit(&self) -> bool {
+        self.wrio & 0x80 != 0
+    }
+
+    fn peek_slow(&self, addr: u32) -> u8 {
+        let bank = (addr >> 16) as u8;
+        let offset = (addr & 0x00_FFFF) as u16;
+
+        if let Some(chip) = self.coprocessor(bank, offset) {
+            return chip.peek(bank, offset);
+        }
+
+        if let Some(index) = self.sram_offset(bank, offset) {
+            return match self.sram.len() {
+                0 => self.open_bus,
+                len => self.sram[index & (len - 1)],
+            };
+        }
+
+        match bank {
+            0x00..=0x3F | 0x80..=0xBF => {
                 match offset {
                     // RAM
                     0x0000..=0x1FFF => self.ram[offset as usize],
@@ -31,10 +1001,10 @@ impl Mmu {
                     0x2000..=0x20FF => 0,
 
                     // PPU, APU, Hardware
-                    0x2100..=0x213F => 0,
+                    0x2100..=0x213F => self.ppu.peek(offset),
 
                     // APUIO
-                    0x2140..=0x2143 => self.spc[offset as usize - 0x2140],
+                    0x2140..=0x2143 => self.spc.read_port(offset as usize - 0x2140),
 
                     // PPU, APU, Hardware
                     0x2144..=0x21FF => 0,
@@ -45,12 +1015,41 @@ impl Mmu {
                     // DSP, SuperFX, Hardware
                     0x3000..=0x3FFF => 0,
 
+                    // JOYA, JOYB
+                    0x4016 => self.controllers.peek_port1(),
+                    0x4017 => self.controllers.peek_port2(self.io_bit()),
+
                     // Joypads
                     0x4000..=0x40FF => 0,
 
                     // Unused
                     0x4100..=0x41FF => 0,
 
+                    // RDNMI
+                    // TODO: Bits 4-6 are open bus
+                    0x4210 => (self.nmi_flag as u8) << 7 | CPU_VERSION,
+
+                    // RDIO
+                    0x4213 => self.wrio,
+
+                    // DMA channels
+                    0x4300..=0x437F => {
+                        self.dma[(offset as usize >> 4) & 7].read(offset as u8 & 0x0F)
+                    }
+
+                    // JOY1L-JOY4H
+                    // TODO: This should only update when auto-read runs
+                    0x4218..=0x421F => {
+                        let joy = self.controllers.auto_read(self.io_bit());
+                        let value = joy[(offset as usize - 0x4218) / 2];
+
+                        if offset & 1 == 0 {
+                            value as u8
+                        } else {
+                            (value >> 8) as u8
+                        }
+                    }
+
                     // DMA, PPU2, Hardware
                     0x4200..=0x44FF => 0,
 
@@ -61,26 +1060,46 @@ impl Mmu {
                     0x6000..=0x7FFF => 0,
 
                     // ROM
-                    0x8000..=0xFFFF => {
-                        self.cartridge[(offset - 0x8000) as usize + (bank as usize * 0x8000)]
-                    }
+                    0x8000..=0xFFFF => self.read_rom(bank, offset),
                 }
             }
 
-            0x7E => self.ram[offset as usize],
+            0x7E..=0x7F => self.ram[(addr & 0x1_FFFF) as usize],
 
-            _ => unimplemented!("bank {:02X} is unimplemented", bank),
+            _ => self.read_rom(bank, offset),
         }
     }
 
-    pub fn store_u8(&mut self, addr: u32, value: u8) {
-        // TODO: This is hardcoded to LoROM at the moment.
+    fn store_mapped(&mut self, addr: u32, value: u8) {
+        match self.pages[(addr as usize >> PAGE_SHIFT) & (PAGE_COUNT - 1)] {
+            Page::Ram(base) => self.ram[base + (addr as usize & (PAGE_SIZE - 1))] = value,
+            Page::Rom(_) => self.rom_write(addr, value),
+            Page::Slow => self.store_slow(addr, value),
+        }
+    }
 
+    fn store_slow(&mut self, addr: u32, value: u8) {
         let bank = (addr >> 16) as u8;
         let offset = (addr & 0x00_FFFF) as u16;
 
+        if let Some(chip) = self.coprocessor_mut(bank, offset) {
+            chip.write(bank, offset, value);
+            return;
+        }
+
+        if let Some(index) = self.sram_offset(bank, offset) {
+            let len = self.sram.len();
+
+            if len > 0 {
+                self.sram[index & (len - 1)] = value;
+                self.sram_dirty = true;
+            }
+
+            return;
+        }
+
         match bank {
-            0x00..=0x3F => {
+            0x00..=0x3F | 0x80..=0xBF => {
                 match offset {
                     // RAM
                     0x0000..=0x1FFF => self.ram[offset as usize] = value,
@@ -89,10 +1108,26 @@ impl Mmu {
                     0x2000..=0x20FF => {}
 
                     // PPU, APU, Hardware
-                    0x2100..=0x213F => {}
+                    0x2100..=0x213F => {
+                        self.ppu.write(offset, value);
+
+                        if self.ppu.take_vram_write_ignored() {
+                            self.push_event(EventKind::VramWriteIgnored {
+                                vram_addr: self.ppu.vram_addr(),
+                            });
+                        }
+                    }
 
                     // APUIO
-                    0x2140..=0x2143 => self.spc[offset as usize - 0x2140] = value,
+                    0x2140..=0x2143 => {
+                        let port = offset as usize - 0x2140;
+
+                        self.spc.write_port(port, value);
+                        self.push_event(EventKind::ApuPortWrite {
+                            port: port as u8,
+                            value,
+                        });
+                    }
 
                     // PPU, APU, Hardware
                     0x2144..=0x21FF => {}
@@ -103,14 +1138,47 @@ impl Mmu {
                     // DSP, SuperFX, Hardware
                     0x3000..=0x3FFF => {}
 
+                    // JOYWR
+                    0x4016 => self.controllers.write_strobe(value),
+
                     // Joypads
                     0x4000..=0x40FF => {}
 
                     // Unused
                     0x4100..=0x41FF => {}
 
+                    // NMITIMEN
+                    // TODO: Auto-read and the H/V IRQs
+                    0x4200 => self.nmitimen = value,
+
+                    // WRIO
+                    0x4201 => {
+                        // Pulling bit 7 low latches the PPU's counters, the
+                        // same as reading SLHV.
+                        if self.io_bit() && value & 0x80 == 0 {
+                            self.ppu.latch_counters();
+                        }
+
+                        self.wrio = value;
+                    }
+
+                    // MDMAEN
+                    0x420B => self.mdmaen = value,
+                    0x420C => self.hdmaen = value,
+
+                    // MEMSEL
+                    0x420D => {
+                        self.memsel = value & 1;
+                        self.build_speeds();
+                    }
+
+                    // DMA channels
+                    0x4300..=0x437F => {
+                        self.dma[(offset as usize >> 4) & 7].write(offset as u8 & 0x0F, value)
+                    }
+
                     // DMA, PPU2, Hardware
-                    0x4200..=0x44FF => {}
+                    0x4202..=0x44FF => {}
 
                     // Unused
                     0x4500..=0x5FFF => {}
@@ -119,41 +1187,861 @@ impl Mmu {
                     0x6000..=0x7FFF => {}
 
                     // ROM
-                    0x8000..=0xFFFF => {}
+                    0x8000..=0xFFFF => self.rom_write(addr, value),
                 }
             }
 
-            0x7E => self.ram[offset as usize] = value,
+            0x7E..=0x7F => self.ram[(addr & 0x1_FFFF) as usize] = value,
 
+            // ROM
             _ => {
-                // TODO: Implement rest of memory ranges
+                if self.rom_offset(bank, offset).is_some() {
+                    self.rom_write(addr, value);
+                }
+            }
+        }
+    }
+
+    // The coprocessor, if there is one and it responds at the address.
+    fn coprocessor(&self, bank: u8, offset: u16) -> Option<&Chip> {
+        self.coprocessor
+            .as_ref()
+            .filter(|chip| chip.maps(bank, offset))
+    }
+
+    fn coprocessor_mut(&mut self, bank: u8, offset: u16) -> Option<&mut Chip> {
+        self.coprocessor
+            .as_mut()
+            .filter(|chip| chip.maps(bank, offset))
+    }
+
+    // Where an address falls in the cartridge's SRAM region, before it's
+    // masked down to the size of the chip.
+    fn sram_offset(&self, bank: u8, offset: u16) -> Option<usize> {
+        match (self.map_mode, bank) {
+            (MapMode::LoRom, 0x70..=0x7D | 0xF0..=0xFF) if offset < 0x8000 => {
+                Some((bank & 0x0F) as usize * 0x8000 + offset as usize)
             }
+            (MapMode::HiRom, 0x20..=0x3F | 0xA0..=0xBF) if (0x6000..0x8000).contains(&offset) => {
+                Some((bank & 0x1F) as usize * 0x2000 + (offset - 0x6000) as usize)
+            }
+            _ => None,
         }
     }
 
-    pub fn read_u16(&self, addr: u32) -> u16 {
-        let byte0 = self.read_u8(addr);
-        let byte1 = self.read_u8(addr + 1);
+    pub fn map_mode(&self) -> MapMode {
+        self.map_mode
+    }
+
+    pub fn cartridge(&self) -> &[u8] {
+        &self.cartridge
+    }
+
+    pub fn wram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn sram(&self) -> &[u8] {
+        &self.sram
+    }
+
+    // Sets what WRAM and SRAM hold at power on. A battery save loaded
+    // afterwards replaces SRAM.
+    pub fn init_ram(&mut self, init: RamInit) {
+        init.fill(&mut self.ram);
+        init.fill(&mut self.sram);
+    }
+
+    // Copies in a battery save. Anything beyond the size of the chip is
+    // ignored, and a short save leaves the rest of SRAM as it was.
+    pub fn load_sram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.sram.len());
+        self.sram[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn sram_dirty(&self) -> bool {
+        self.sram_dirty
+    }
 
-        u16::from_le_bytes([byte0, byte1])
+    pub fn clear_sram_dirty(&mut self) {
+        self.sram_dirty = false;
     }
 
-    pub fn read_long(&self, addr: u32) -> u32 {
-        let byte0 = self.read_u8(addr);
-        let byte1 = self.read_u8(addr + 1);
-        let byte2 = self.read_u8(addr + 2);
+    // Replaces the memory state with one loaded from a save state, which
+    // doesn't include the cartridge or any debugging setup. The old state is
+    // swapped into `state`.
+    pub fn restore(&mut self, state: &mut Mmu) {
+        state.cartridge = self.cartridge.clone();
+        state.cheats = std::mem::take(&mut self.cheats);
+        state.rom_patches = std::mem::take(&mut self.rom_patches);
+        state.build_pages();
+        state.watchpoints = std::mem::take(&mut self.watchpoints);
+        state.cdl = self.cdl.take();
+        state.events = self.events.take();
+        state.log_accesses = self.log_accesses;
+        state.log_io = self.log_io;
+        state.log_writes = self.log_writes;
+        state.rom_write_policy = self.rom_write_policy;
 
-        u32::from_le_bytes([byte0, byte1, byte2, 0])
+        // What's plugged in is part of the setup rather than the state.
+        state.controllers.port2 = self.controllers.port2;
+
+        // SRAM is about to change underneath the battery save.
+        state.sram_dirty = true;
+
+        std::mem::swap(self, state);
     }
 
-    pub fn store_u16(&mut self, addr: u32, value: u16) {
-        let [byte0, byte1] = value.to_le_bytes();
+    // Records how the CPU accessed an address, if it's in ROM and a code/data
+    // log is being kept.
+    pub fn log_access(&mut self, addr: u32, flags: u8) {
+        if self.cdl.is_none() {
+            return;
+        }
+
+        let bank = (addr >> 16) as u8;
+        let offset = (addr & 0x00_FFFF) as u16;
+
+        let is_rom = match bank {
+            0x00..=0x3F | 0x80..=0xBF => offset >= 0x8000,
+            0x7E..=0x7F => false,
+            _ => true,
+        };
+
+        if !is_rom {
+            return;
+        }
 
-        self.store_u8(addr, byte0);
-        self.store_u8(addr + 1, byte1)
+        if let (Some(index), Some(cdl)) = (self.rom_offset(bank, offset), &mut self.cdl) {
+            cdl.mark(index, flags);
+        }
     }
 
     pub fn reset_vector(&self) -> u16 {
-        u16::from_le_bytes([self.cartridge[0x7FFC], self.cartridge[0x7FFD]])
+        u16::from_le_bytes([self.read_rom(0x00, 0xFFFC), self.read_rom(0x00, 0xFFFD)])
+    }
+
+    fn read_rom(&self, bank: u8, offset: u16) -> u8 {
+        // TODO: Open bus
+        match self.rom_offset(bank, offset) {
+            Some(index) => match self.rom_patches.get(&index) {
+                Some(&value) => value,
+                None => self.cartridge[index],
+            },
+            None => 0,
+        }
+    }
+
+    fn rom_patched(&self, start: usize, len: usize) -> bool {
+        self.rom_patches
+            .keys()
+            .any(|&index| (start..start + len).contains(&index))
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // Game Genie codes have to point at ROM, going by the current mapping.
+    pub fn add_cheat(&mut self, cheat: Cheat) -> Result<(), String> {
+        if cheat.kind == CheatKind::GameGenie
+            && self
+                .rom_offset((cheat.addr >> 16) as u8, cheat.addr as u16)
+                .is_none()
+        {
+            return Err(format!("{:06X} isn't in ROM", cheat.addr));
+        }
+
+        self.cheats.push(cheat);
+        self.apply_rom_cheats();
+
+        Ok(())
+    }
+
+    // Returns false if there's no cheat with that index.
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        let Some(cheat) = self.cheats.get_mut(index) else {
+            return false;
+        };
+
+        cheat.enabled = enabled;
+        self.apply_rom_cheats();
+
+        true
+    }
+
+    fn apply_rom_cheats(&mut self) {
+        self.rom_patches = self
+            .cheats
+            .iter()
+            .filter(|cheat| cheat.enabled && cheat.kind == CheatKind::GameGenie)
+            .filter_map(|cheat| {
+                let index = self.rom_offset((cheat.addr >> 16) as u8, cheat.addr as u16)?;
+                Some((index, cheat.value))
+            })
+            .collect();
+
+        self.build_pages();
+    }
+
+    // Pokes the Pro Action Replay values into RAM. This goes around the
+    // usual write path, so it doesn't trip watchpoints or show up in the
+    // logs. Addresses that aren't RAM are ignored.
+    pub fn apply_ram_cheats(&mut self) {
+        for i in 0..self.cheats.len() {
+            let Cheat {
+                kind, addr, value, ..
+            } = self.cheats[i];
+
+            if !self.cheats[i].enabled || kind != CheatKind::ProActionReplay {
+                continue;
+            }
+
+            let bank = (addr >> 16) as u8;
+            let offset = addr as u16;
+
+            if let Some(index) = self.sram_offset(bank, offset) {
+                if !self.sram.is_empty() {
+                    let len = self.sram.len();
+                    self.sram[index & (len - 1)] = value;
+                }
+
+                continue;
+            }
+
+            match (bank, offset) {
+                (0x7E..=0x7F, _) => self.ram[(addr & 0x1_FFFF) as usize] = value,
+                (0x00..=0x3F | 0x80..=0xBF, 0x0000..=0x1FFF) => self.ram[offset as usize] = value,
+                _ => {}
+            }
+        }
+    }
+
+    fn rom_offset(&self, bank: u8, offset: u16) -> Option<usize> {
+        let index = match self.map_mode {
+            MapMode::LoRom => {
+                if offset < 0x8000 {
+                    return None;
+                }
+
+                (bank & 0x7F) as usize * 0x8000 + (offset - 0x8000) as usize
+            }
+
+            MapMode::HiRom => {
+                // Banks $00-$3F and $80-$BF only map the upper half.
+                if bank & 0x7F < 0x40 && offset < 0x8000 {
+                    return None;
+                }
+
+                (bank & 0x3F) as usize * 0x10000 + offset as usize
+            }
+        };
+
+        // Smaller ROMs are mirrored to fill the address space.
+        Some(index % self.cartridge.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cheat::{Cheat, CheatKind};
+    use crate::input::{Buttons, Port2};
+
+    // Cartridges of each mapping in a few sizes, including ones that get
+    // mirrored and ones that aren't a multiple of a page.
+    const CARTRIDGES: &[(MapMode, usize)] = &[
+        (MapMode::LoRom, 0x8000),
+        (MapMode::LoRom, 0x9000),
+        (MapMode::LoRom, 0x30000),
+        (MapMode::LoRom, 0x10_0000),
+        (MapMode::HiRom, 0x10000),
+        (MapMode::HiRom, 0x18000),
+        (MapMode::HiRom, 0x31000),
+        (MapMode::HiRom, 0x40_0000),
+    ];
+
+    // A cartridge full of noise, with 8KB of SRAM and no coprocessor, and
+    // WRAM full of noise too. One byte of ROM is patched by a cheat, which has to be read
+    // through the slow path.
+    fn mmu(map_mode: MapMode, size: usize, seed: u64) -> Mmu {
+        let mut cartridge = vec![0; size];
+        RamInit::Random(seed).fill(&mut cartridge);
+        cartridge[map_mode.header_offset() + 0x16] = 0x02;
+        cartridge[map_mode.header_offset() + 0x18] = 3;
+
+        let mut mmu = Mmu::new(cartridge, Some(map_mode));
+        RamInit::Random(!seed).fill(&mut mmu.ram);
+
+        mmu.add_cheat(Cheat {
+            code: String::new(),
+            kind: CheatKind::GameGenie,
+            addr: 0xC0_8123,
+            value: 0x42,
+            enabled: true,
+        })
+        .unwrap();
+
+        mmu
+    }
+
+    #[test]
+    fn pages_match_slow_decoding() {
+        for (i, &(map_mode, size)) in CARTRIDGES.iter().enumerate() {
+            let mut mmu = mmu(map_mode, size, i as u64);
+            let mut state = i as u64;
+
+            // Both ends of every page, then random addresses.
+            let edges = (0..PAGE_COUNT as u32).flat_map(|page| {
+                let start = page << PAGE_SHIFT;
+                [start, start + PAGE_SIZE as u32 - 1]
+            });
+            let random: Vec<u32> = (0..100_000)
+                .map(|_| splitmix64(&mut state) as u32 & 0xFF_FFFF)
+                .collect();
+
+            for addr in edges.chain(random) {
+                let expected = mmu.peek_slow(addr);
+
+                assert_eq!(
+                    mmu.peek_u8(addr),
+                    expected,
+                    "{:?} {:X} peek {:06X}",
+                    map_mode,
+                    size,
+                    addr
+                );
+
+                // Reading I/O registers can have side effects, but those
+                // all go through the slow path either way.
+                if !matches!(mmu.pages[addr as usize >> PAGE_SHIFT], Page::Slow) {
+                    assert_eq!(
+                        mmu.read_mapped(addr),
+                        expected,
+                        "{:?} {:X} read {:06X}",
+                        map_mode,
+                        size,
+                        addr
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn page_stores_match_slow_decoding() {
+        for (i, &(map_mode, size)) in CARTRIDGES.iter().enumerate() {
+            let mut fast = mmu(map_mode, size, i as u64);
+            let mut slow = mmu(map_mode, size, i as u64);
+            let mut state = i as u64;
+
+            for _ in 0..100_000 {
+                let random = splitmix64(&mut state);
+                let addr = random as u32 & 0xFF_FFFF;
+                let value = (random >> 32) as u8;
+
+                // Writing to ROM goes through the same policy either way.
+                match fast.pages[addr as usize >> PAGE_SHIFT] {
+                    Page::Rom(_) => continue,
+                    _ => fast.store_mapped(addr, value),
+                }
+
+                slow.store_slow(addr, value);
+            }
+
+            assert!(fast.ram == slow.ram, "{:?} {:X} WRAM", map_mode, size);
+            assert!(fast.sram == slow.sram, "{:?} {:X} SRAM", map_mode, size);
+        }
+    }
+
+    #[test]
+    fn wrio_latches_counters_on_falling_edge() {
+        let mut mmu = mmu(MapMode::LoRom, 0x8000, 0);
+
+        // 100 lines and 50 dots in.
+        mmu.ppu.step(100 * 1364 + 50 * 4);
+
+        // WRIO starts with bit 7 set, so writing it set again does nothing.
+        mmu.store_u8(0x00_4201, 0xFF);
+        assert_eq!(mmu.read_u8(0x00_213F) & 0x40, 0);
+
+        // Pulling it low latches, the same as reading SLHV.
+        mmu.store_u8(0x00_4201, 0x7F);
+        mmu.ppu.step(1364);
+
+        assert_eq!(mmu.read_u8(0x00_213C), 50);
+        assert_eq!(mmu.read_u8(0x00_213C) & 0x01, 0);
+        assert_eq!(mmu.read_u8(0x00_213D), 100);
+        assert_eq!(mmu.read_u8(0x00_213D) & 0x01, 0);
+        assert_eq!(mmu.read_u8(0x00_213F) & 0x40, 0x40);
+
+        // Keeping it low doesn't latch again, until it's been raised.
+        mmu.store_u8(0x00_4201, 0x7F);
+        assert_eq!(mmu.read_u8(0x00_213F) & 0x40, 0);
+
+        mmu.store_u8(0x00_4201, 0xFF);
+        mmu.store_u8(0x00_4201, 0x00);
+        assert_eq!(mmu.read_u8(0x00_213F) & 0x40, 0x40);
+        assert_eq!(mmu.read_u8(0x00_213C), 50);
+        assert_eq!(mmu.read_u8(0x00_213D), 101);
+
+        // RDIO reads back what was written.
+        assert_eq!(mmu.read_u8(0x00_4213), 0x00);
+    }
+
+    #[test]
+    fn multitap_pads_through_registers() {
+        let mut mmu = mmu(MapMode::LoRom, 0x8000, 0);
+        mmu.controllers.port2 = Port2::Multitap;
+        mmu.controllers.pads[2] = Buttons::SELECT;
+        mmu.controllers.pads[3] = Buttons::START;
+
+        let joy = |mmu: &Mmu, addr| u16::from_le_bytes([mmu.peek_u8(addr), mmu.peek_u8(addr + 1)]);
+
+        // Pad 3 is auto-read into JOY4 while bit 7 of WRIO is set...
+        assert_eq!(joy(&mmu, 0x00_421E), Buttons::SELECT.bits());
+        assert_eq!(joy(&mmu, 0x00_421C), 0);
+
+        // ...and both of pad 3's and pad 4's lines can be read by hand.
+        let read = |mmu: &mut Mmu, line: u8| {
+            mmu.store_u8(0x00_4016, 1);
+            mmu.store_u8(0x00_4016, 0);
+
+            (0..16).fold(0, |bits, _| {
+                bits << 1 | (mmu.read_u8(0x00_4017) >> line & 1) as u16
+            })
+        };
+
+        assert_eq!(read(&mut mmu, 1), Buttons::SELECT.bits());
+
+        mmu.store_u8(0x00_4201, 0x00);
+        assert_eq!(read(&mut mmu, 0), Buttons::START.bits());
+        assert_eq!(joy(&mmu, 0x00_421A), Buttons::START.bits());
+    }
+
+    fn rdnmi(mmu: &mut Mmu) -> bool {
+        mmu.read_u8(0x00_4210) & 0x80 != 0
+    }
+
+    #[test]
+    fn nmi_enabled_before_vblank() {
+        let mut mmu = mmu(MapMode::LoRom, 0x8000, 0);
+        mmu.store_u8(0x00_4200, 0x80);
+        assert!(!mmu.take_nmi_edge());
+
+        // One NMI at the start of vblank, however often it's checked.
+        mmu.start_vblank();
+        assert!(mmu.take_nmi_edge());
+        assert!(!mmu.take_nmi_edge());
+
+        mmu.end_vblank();
+        assert!(!mmu.take_nmi_edge());
+        assert!(!rdnmi(&mut mmu));
+
+        // And another at the next one.
+        mmu.start_vblank();
+        assert!(mmu.take_nmi_edge());
+    }
+
+    #[test]
+    fn nmi_enabled_during_vblank() {
+        let mut mmu = mmu(MapMode::LoRom, 0x8000, 0);
+
+        mmu.start_vblank();
+        assert!(!mmu.take_nmi_edge());
+
+        // The flag is already set, so enabling fires one straight away.
+        mmu.store_u8(0x00_4200, 0x80);
+        assert!(mmu.take_nmi_edge());
+        assert!(!mmu.take_nmi_edge());
+
+        // Unless it's been read first.
+        let mut mmu = self::mmu(MapMode::LoRom, 0x8000, 0);
+        mmu.start_vblank();
+        assert!(rdnmi(&mut mmu));
+
+        mmu.store_u8(0x00_4200, 0x80);
+        assert!(!mmu.take_nmi_edge());
+    }
+
+    #[test]
+    fn rdnmi_clears_on_read() {
+        let mut mmu = mmu(MapMode::LoRom, 0x8000, 0);
+        mmu.store_u8(0x00_4200, 0x80);
+        mmu.start_vblank();
+        assert!(mmu.take_nmi_edge());
+
+        // Reading it in the handler clears it without raising another.
+        assert!(rdnmi(&mut mmu));
+        assert!(!rdnmi(&mut mmu));
+        assert!(!mmu.take_nmi_edge());
+
+        // Peeking leaves it set, and the CPU version in the low bits.
+        mmu.start_vblank();
+        assert_eq!(mmu.peek_u8(0x00_4210) & 0x80, 0x80);
+        assert_eq!(mmu.peek_u8(0x00_4210) & 0x80, 0x80);
+        assert_eq!(mmu.read_u8(0x00_4210) & 0x0F, CPU_VERSION);
+    }
+
+    #[test]
+    fn nmi_disabled_and_enabled_again_in_vblank() {
+        let mut mmu = mmu(MapMode::LoRom, 0x8000, 0);
+        mmu.store_u8(0x00_4200, 0x80);
+        mmu.start_vblank();
+        assert!(mmu.take_nmi_edge());
+
+        // Turning it off and on again while the flag is set is another edge.
+        mmu.store_u8(0x00_4200, 0x00);
+        assert!(!mmu.take_nmi_edge());
+        mmu.store_u8(0x00_4200, 0x80);
+        assert!(mmu.take_nmi_edge());
+
+        // But not once the flag's been read.
+        rdnmi(&mut mmu);
+        mmu.store_u8(0x00_4200, 0x00);
+        assert!(!mmu.take_nmi_edge());
+        mmu.store_u8(0x00_4200, 0x80);
+        assert!(!mmu.take_nmi_edge());
+    }
+
+    // An empty cartridge with the given SRAM size byte in its header.
+    fn sram_mmu(map_mode: MapMode, size: u8) -> Mmu {
+        let mut cartridge = vec![0; 0x10000];
+        cartridge[map_mode.header_offset() + 0x16] = 0x02;
+        cartridge[map_mode.header_offset() + 0x18] = size;
+
+        Mmu::new(cartridge, Some(map_mode))
+    }
+
+    #[test]
+    fn sram_is_the_size_of_the_chip() {
+        for (size, len) in [(0, 0), (1, 0x800), (3, 0x2000), (5, 0x8000), (9, 0)] {
+            assert_eq!(sram_mmu(MapMode::LoRom, size).sram().len(), len);
+        }
+    }
+
+    #[test]
+    fn small_sram_is_mirrored() {
+        // 2KB, written at the base of the region.
+        let mut mmu = sram_mmu(MapMode::LoRom, 1);
+        mmu.store_u8(0x70_0000, 0x5A);
+        mmu.store_u8(0x70_07FF, 0xA5);
+
+        for addr in [0x70_0800, 0x70_2000, 0x70_7800, 0x71_0000, 0xF0_2000] {
+            assert_eq!(mmu.read_u8(addr), 0x5A, "{:06X}", addr);
+        }
+
+        assert_eq!(mmu.read_u8(0x70_27FF), 0xA5);
+
+        // Writes through a mirror land in the same place.
+        mmu.store_u8(0x7D_4000, 0x11);
+        assert_eq!(mmu.sram()[0], 0x11);
+
+        // The same for HiROM, whose region is $6000-$7FFF of banks $20-$3F.
+        let mut mmu = sram_mmu(MapMode::HiRom, 1);
+        mmu.store_u8(0x20_6000, 0x5A);
+
+        for addr in [0x20_6800, 0x21_6000, 0xA0_7000, 0x3F_7800] {
+            assert_eq!(mmu.read_u8(addr), 0x5A, "{:06X}", addr);
+        }
+    }
+
+    #[test]
+    fn ram_init_patterns() {
+        let cases: [(RamInit, [u8; 4]); 3] = [
+            (RamInit::Zero, [0x00; 4]),
+            (RamInit::Ff, [0xFF; 4]),
+            (RamInit::Pattern, [0x55, 0xAA, 0x55, 0xAA]),
+        ];
+
+        for (init, start) in cases {
+            let mut mmu = sram_mmu(MapMode::LoRom, 3);
+            RamInit::Random(1).fill(&mut mmu.ram);
+            mmu.init_ram(init);
+
+            assert_eq!(mmu.wram()[..4], start, "{}", init);
+            assert_eq!(mmu.wram()[0x1_FFFC..], start[..], "{}", init);
+            assert_eq!(mmu.sram()[..4], start, "{}", init);
+            assert_eq!(mmu.read_u8(0x00_0001), start[1], "{}", init);
+        }
+
+        // A battery save replaces whatever SRAM started with.
+        let mut mmu = sram_mmu(MapMode::LoRom, 3);
+        mmu.init_ram(RamInit::Ff);
+        mmu.load_sram(&[0x12; 0x2000]);
+        assert!(mmu.sram().iter().all(|&byte| byte == 0x12));
+    }
+
+    #[test]
+    fn random_ram_follows_the_seed() {
+        let random = |seed| {
+            let mut mmu = sram_mmu(MapMode::LoRom, 3);
+            mmu.init_ram(RamInit::Random(seed));
+            (mmu.wram().to_vec(), mmu.sram().to_vec())
+        };
+
+        let (wram, sram) = random(1234);
+        assert_eq!((wram.clone(), sram.clone()), random(1234));
+        assert_ne!(wram, random(1235).0);
+
+        // Noisy, rather than something like a fill or a short cycle.
+        let zeros = wram.iter().filter(|&&byte| byte == 0).count();
+        assert!(zeros < wram.len() / 128, "{} zeros", zeros);
+        assert_ne!(wram[..0x2000], wram[0x2000..0x4000]);
+        assert!(sram.iter().any(|&byte| byte != sram[0]));
+    }
+
+    #[test]
+    fn game_genie_patches_rom_reads() {
+        for (map_mode, mirror) in [(MapMode::LoRom, 0x8C_8123), (MapMode::HiRom, 0xCC_8123)] {
+            let mut cartridge = vec![0; 0x10_0000];
+            RamInit::Random(7).fill(&mut cartridge);
+
+            let mut mmu = Mmu::new(cartridge, Some(map_mode));
+            let original = mmu.read_u8(0x0C_8123);
+            let next = mmu.read_u8(0x0C_8124);
+
+            // 0C:8123 = FF
+            mmu.add_cheat(Cheat::parse("3364-0D3D").unwrap()).unwrap();
+
+            for addr in [0x0C_8123, mirror] {
+                assert_eq!(mmu.read_u8(addr), 0xFF, "{:?} {:06X}", map_mode, addr);
+            }
+
+            assert_eq!(mmu.read_u8(0x0C_8124), next);
+            assert_eq!(mmu.peek_u8(0x0C_8123), 0xFF);
+
+            // Where the patch lands in the ROM depends on the mapping.
+            let index = match map_mode {
+                MapMode::LoRom => 0x0C * 0x8000 + 0x123,
+                MapMode::HiRom => 0x0C * 0x10000 + 0x8123,
+            };
+            assert_eq!(mmu.cartridge()[index], original);
+
+            assert!(mmu.set_cheat_enabled(0, false));
+            assert_eq!(mmu.read_u8(0x0C_8123), original);
+            assert!(!mmu.set_cheat_enabled(1, false));
+        }
+
+        // Somewhere that isn't ROM in the current mapping.
+        let mut mmu = sram_mmu(MapMode::LoRom, 0);
+        let cheat = Cheat {
+            addr: 0x00_1234,
+            ..Cheat::parse("DD62-6DA4").unwrap()
+        };
+        assert_eq!(mmu.add_cheat(cheat), Err("001234 isn't in ROM".into()));
+    }
+
+    #[test]
+    fn pro_action_replay_overwrites_ram() {
+        let mut mmu = sram_mmu(MapMode::LoRom, 3);
+        mmu.add_cheat(Cheat::parse("7E0DBE:FF").unwrap()).unwrap();
+        mmu.add_cheat(Cheat::parse("700010:5A").unwrap()).unwrap();
+
+        mmu.store_u8(0x7E_0DBE, 0x01);
+        assert_eq!(mmu.read_u8(0x00_0DBE), 0x01);
+
+        mmu.apply_ram_cheats();
+        assert_eq!(mmu.read_u8(0x00_0DBE), 0xFF);
+        assert_eq!(mmu.sram()[0x10], 0x5A);
+
+        mmu.set_cheat_enabled(0, false);
+        mmu.store_u8(0x7E_0DBE, 0x01);
+        mmu.apply_ram_cheats();
+        assert_eq!(mmu.read_u8(0x7E_0DBE), 0x01);
+    }
+
+    #[test]
+    fn dsp1_takes_over_rom() {
+        let mut cartridge = vec![0x5A; 0x8_0000];
+        cartridge[MapMode::LoRom.header_offset() + 0x16] = 0x03;
+
+        let mut mmu = Mmu::new(cartridge, Some(MapMode::LoRom));
+        assert!(mmu.coprocessor.is_some());
+
+        // The registers replace ROM in banks $30-$3F and their mirrors, and
+        // ROM is still there in the banks either side.
+        assert_eq!(mmu.read_u8(0x30_C000), 0x80);
+        assert_eq!(mmu.read_u8(0xBF_FFFF), 0x80);
+        assert_eq!(mmu.read_u8(0x2F_8000), 0x5A);
+        assert_eq!(mmu.read_u8(0x40_8000), 0x5A);
+
+        // 0.5 x 0.5, with the result read through a mirror.
+        for value in [0x00, 0x00, 0x40, 0x00, 0x40] {
+            mmu.store_u8(0x30_8000, value);
+        }
+
+        assert_eq!(mmu.peek_u8(0xB0_8000), 0x00);
+        assert_eq!(mmu.read_u8(0xB0_8000), 0x00);
+        assert_eq!(mmu.peek_u8(0x30_C000), 0x90);
+        assert_eq!(mmu.read_u8(0x30_8000), 0x20);
+    }
+
+    #[test]
+    fn no_sram_ignores_writes() {
+        let mut mmu = sram_mmu(MapMode::LoRom, 0);
+        mmu.store_u8(0x70_0000, 0x5A);
+        assert!(mmu.sram().is_empty());
+
+        // Reads give back whatever was last on the bus, rather than the
+        // write.
+        mmu.store_u8(0x7E_0000, 0x33);
+        assert_eq!(mmu.read_u8(0x7E_0000), 0x33);
+        assert_eq!(mmu.read_u8(0x70_0000), 0x33);
+    }
+
+    #[test]
+    fn rom_writes_are_recorded_and_dropped() {
+        let mut mmu = mmu(MapMode::LoRom, 0x8000, 0);
+        let before = mmu.peek_u8(0x00_8000);
+
+        mmu.store_u8(0x00_8000, !before);
+        mmu.store_u8(0x80_FFFF, 0x12);
+        mmu.store_u8(0x7E_0000, 0x34);
+
+        assert_eq!(mmu.peek_u8(0x00_8000), before);
+        assert_eq!(mmu.rom_writes(), [(0x00_8000, !before), (0x80_FFFF, 0x12)]);
+
+        mmu.clear_rom_writes();
+        assert!(mmu.rom_writes().is_empty());
+
+        mmu.rom_write_policy = RomWritePolicy::Ignore;
+        mmu.store_u8(0x00_8000, 0x56);
+        assert!(mmu.rom_writes().is_empty());
+    }
+
+    #[test]
+    fn access_speeds() {
+        let mut mmu = mmu(MapMode::HiRom, 0x10000, 0);
+
+        // (address, slow, with MEMSEL set)
+        let cases = [
+            (0x00_0000, 8, 8),
+            (0x00_2100, 6, 6),
+            (0x00_4016, 12, 12),
+            (0x00_4200, 6, 6),
+            (0x00_8000, 8, 8),
+            (0x40_0000, 8, 8),
+            (0x7E_0000, 8, 8),
+            (0x80_1FFF, 8, 8),
+            (0x80_8000, 8, 6),
+            (0xBF_FFFF, 8, 6),
+            (0xC0_0000, 8, 6),
+            (0xFF_FFFF, 8, 6),
+        ];
+
+        for memsel in [0, 1] {
+            mmu.store_u8(0x00_420D, memsel);
+
+            for (addr, slow, fast) in cases {
+                let expected = if memsel == 1 { fast } else { slow };
+                assert_eq!(mmu.access_cycles(addr), expected, "{:06X}", addr);
+            }
+        }
+    }
+
+    // Runs the PPU up to the start of the next frame, where HDMA loads the
+    // first entry of each table.
+    fn start_frame(mmu: &mut Mmu) {
+        loop {
+            mmu.step_ppu(mmu.ppu.cycles_left_in_line());
+
+            if mmu.ppu.scanline() == 0 {
+                return;
+            }
+        }
+    }
+
+    // Runs the PPU to the start of vblank, returning the register writes
+    // that HDMA made at the start of each line from line 1.
+    fn hdma_lines(mmu: &mut Mmu) -> Vec<Vec<(u32, u8)>> {
+        let mut lines = Vec::new();
+
+        while mmu.ppu.scanline() != mmu.ppu.vblank_line() {
+            mmu.clear_io_accesses();
+            mmu.step_ppu(mmu.ppu.cycles_left_in_line());
+
+            let writes = mmu.io_accesses().iter();
+            lines.push(writes.map(|access| (access.addr, access.value)).collect());
+        }
+
+        lines
+    }
+
+    #[test]
+    fn hdma_indirect_and_repeat() {
+        let mut mmu = mmu(MapMode::LoRom, 0x8000, 0);
+        mmu.log_io = true;
+
+        // Channel 0 is indirect, with entries for two lines without
+        // repeating, three lines repeating, one line repeating, then the end.
+        #[rustfmt::skip]
+        let indirect_table = [
+            0x02, 0x00, 0x20,
+            0x83, 0x10, 0x20,
+            0x81, 0x20, 0x20,
+            0x00,
+        ];
+
+        mmu.ram[0x1000..0x100A].copy_from_slice(&indirect_table);
+        mmu.ram[0x2000] = 0x11;
+        mmu.ram[0x2010..0x2013].copy_from_slice(&[0x21, 0x22, 0x23]);
+        mmu.ram[0x2020] = 0x31;
+
+        // Channel 1 is direct, and $80 is 128 lines without repeating.
+        mmu.ram[0x1100..0x1105].copy_from_slice(&[0x80, 0x44, 0x01, 0x55, 0x00]);
+
+        #[rustfmt::skip]
+        let registers = [
+            // Channel 0 writes to CGADD, from the table at $7E:1000, with its
+            // data in bank $7E.
+            (0x4300, 0x40), (0x4301, 0x21), (0x4307, 0x7E),
+            (0x4302, 0x00), (0x4303, 0x10), (0x4304, 0x7E),
+            // Channel 1 writes to CGDATA, from the table at $7E:1100.
+            (0x4310, 0x00), (0x4311, 0x22),
+            (0x4312, 0x00), (0x4313, 0x11), (0x4314, 0x7E),
+            (0x420C, 0x03),
+        ];
+
+        for (reg, value) in registers {
+            mmu.store_u8(reg, value);
+        }
+
+        for frame in 0..2 {
+            start_frame(&mut mmu);
+
+            // Only the first entries have been read.
+            let [indirect, direct, ..] = *mmu.dma_channels();
+            assert!(!indirect.hdma_terminated && !direct.hdma_terminated);
+            assert_eq!((indirect.table_addr, indirect.count), (0x1003, 0x2000));
+            assert_eq!(direct.table_addr, 0x1101);
+
+            let lines = hdma_lines(&mut mmu);
+            assert_eq!(lines.len(), mmu.ppu.vblank_line() as usize);
+
+            let expected = |line: usize| match line {
+                1 => vec![(0x2121, 0x11), (0x2122, 0x44)],
+                3 => vec![(0x2121, 0x21)],
+                4 => vec![(0x2121, 0x22)],
+                5 => vec![(0x2121, 0x23)],
+                6 => vec![(0x2121, 0x31)],
+                129 => vec![(0x2122, 0x55)],
+                _ => vec![],
+            };
+
+            for (line, writes) in (1..).zip(&lines) {
+                assert_eq!(*writes, expected(line), "frame {} line {}", frame, line);
+            }
+
+            // Both tables have ended, and each channel's state shows where.
+            let [indirect, direct, ..] = *mmu.dma_channels();
+
+            assert!(indirect.hdma_terminated && direct.hdma_terminated);
+            assert_eq!((indirect.table_addr, indirect.count), (0x100A, 0x2021));
+            assert_eq!(direct.table_addr, 0x1105);
+            assert_eq!(mmu.peek_u8(0x00_4308), 0x0A);
+            assert_eq!(mmu.peek_u8(0x00_4305), 0x21);
+        }
     }
 }
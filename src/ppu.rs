@@ -0,0 +1,1901 @@
+pub mod color;
+pub mod debug;
+mod mode7;
+mod obj;
+mod window;
+
+use self::obj::ObjPixel;
+use self::window::COLOR_WINDOW;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 224;
+
+const MASTER_CYCLES_PER_LINE: u64 = 1364;
+const LINES_PER_FRAME: u16 = 262;
+
+// How many lines are displayed, normally and with SETINI's overscan bit.
+const VISIBLE_LINES: u16 = 224;
+const OVERSCAN_LINES: u16 = 239;
+
+// Front-to-back ranks for each layer, where the highest rank wins. BG entries
+// are indexed by the tile's priority bit, OBJ entries by the sprite priority.
+struct Ranks {
+    bg: [[u8; 2]; 4],
+    obj: [u8; 4],
+}
+
+// Modes 0 and 1, front to back: S3 1H 2H S2 1L 2L S1 3H 4H S0 3L 4L.
+const MODE0_RANKS: Ranks = Ranks {
+    bg: [[7, 10], [6, 9], [1, 4], [0, 3]],
+    obj: [2, 5, 8, 11],
+};
+
+// Mode 1 with BGMODE bit 3 set, which brings high priority BG3 tiles in
+// front of everything, e.g. for status bars: 3H S3 1H 2H S2 1L 2L S1 S0 3L.
+const MODE1_BG3_RANKS: Ranks = Ranks {
+    bg: [[7, 10], [6, 9], [1, 12], [0, 0]],
+    obj: [2, 5, 8, 11],
+};
+
+// Modes 2 to 7, which have two layers at most: S3 1H S2 2H S1 1L S0 2L.
+// Mode 7's single layer always uses the low priority rank.
+const MODE2_RANKS: Ranks = Ranks {
+    bg: [[2, 6], [0, 4], [0, 0], [0, 0]],
+    obj: [1, 3, 5, 7],
+};
+
+#[derive(Clone, Copy)]
+enum Layer {
+    Bg(usize),
+    Obj,
+    Backdrop,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ppu {
+    vram: Vec<u16>,
+    cgram: Vec<u16>,
+    oam: Vec<u8>,
+    framebuffer: Vec<u8>,
+
+    // Background registers
+    bg_mode: u8,
+    bg_tilemap: [u8; 4],
+    bg_chr_base: [u8; 2],
+    bg_hofs: [u16; 4],
+    bg_vofs: [u16; 4],
+    bg_scroll_latch: u8,
+    mosaic: u8,
+    mosaic_start: usize,
+
+    // Mode 7 registers
+    m7_select: u8,
+    m7_matrix: [u16; 4],
+    m7_center: [u16; 2],
+    m7_hofs: u16,
+    m7_vofs: u16,
+    m7_latch: u8,
+
+    // Sprite registers
+    obj_select: u8,
+    range_over: bool,
+    time_over: bool,
+
+    // VRAM/CGRAM/OAM ports
+    vram_increment: u8,
+    vram_addr: u16,
+    vram_prefetch: u16,
+    cgram_addr: u8,
+    cgram_latch: Option<u8>,
+    cgram_read_high: bool,
+    oam_reload: u16,
+    oam_addr: u16,
+    oam_latch: u8,
+
+    // Display control
+    inidisp: u8,
+    setini: u8,
+
+    // Screen designation
+    main_screen: u8,
+    sub_screen: u8,
+
+    // Windows. The selects and logic are kept as the registers were
+    // written (W12SEL-WOBJSEL, WBGLOG-WOBJLOG), as are the edges (WH0-WH3).
+    window_select: [u8; 3],
+    window_edges: [u8; 4],
+    window_logic: [u8; 2],
+
+    // TMW and TSW, the layers that the windows mask on each screen.
+    main_window: u8,
+    sub_window: u8,
+
+    // Color math
+    color_math_select: u8,
+    color_math_control: u8,
+    fixed_color: u16,
+
+    // Timing
+    scanline: u16,
+    line_cycles: u64,
+    frame: u64,
+
+    // The number of visible lines, which is taken from SETINI at the start
+    // of each frame, so that changing it only moves vblank from the next
+    // one.
+    visible_lines: u16,
+
+    // Which field of an interlaced frame is being drawn. This flips every
+    // frame, interlaced or not.
+    field: bool,
+
+    // Counter latch
+    counter_latched: bool,
+    latched_h: u16,
+    latched_v: u16,
+    ophct_high: bool,
+    opvct_high: bool,
+
+    // Open bus. Each chip has its own, holding the last value it returned.
+    ppu1_bus: u8,
+    ppu2_bus: u8,
+
+    // Set when a VRAM write is dropped for happening outside of blanking,
+    // which is a classic symptom of a game (or emulator) bug, until the
+    // MMU picks it up for the event log.
+    #[cfg_attr(feature = "savestate", serde(skip))]
+    vram_write_ignored: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            vram: vec![0; 0x8000],
+            cgram: vec![0; 256],
+            oam: vec![0; 544],
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
+
+            bg_mode: 0,
+            bg_tilemap: [0; 4],
+            bg_chr_base: [0; 2],
+            bg_hofs: [0; 4],
+            bg_vofs: [0; 4],
+            bg_scroll_latch: 0,
+            mosaic: 0,
+            mosaic_start: 0,
+
+            m7_select: 0,
+            m7_matrix: [0; 4],
+            m7_center: [0; 2],
+            m7_hofs: 0,
+            m7_vofs: 0,
+            m7_latch: 0,
+
+            obj_select: 0,
+            range_over: false,
+            time_over: false,
+
+            vram_increment: 0,
+            vram_addr: 0,
+            vram_prefetch: 0,
+            cgram_addr: 0,
+            cgram_latch: None,
+            cgram_read_high: false,
+            oam_reload: 0,
+            oam_addr: 0,
+            oam_latch: 0,
+
+            inidisp: 0x80,
+            setini: 0,
+
+            main_screen: 0,
+            sub_screen: 0,
+
+            window_select: [0; 3],
+            window_edges: [0; 4],
+            window_logic: [0; 2],
+            main_window: 0,
+            sub_window: 0,
+
+            color_math_select: 0,
+            color_math_control: 0,
+            fixed_color: 0,
+
+            scanline: 0,
+            line_cycles: 0,
+            frame: 0,
+
+            visible_lines: VISIBLE_LINES,
+            field: false,
+
+            counter_latched: false,
+            latched_h: 0,
+            latched_v: 0,
+            ophct_high: false,
+            opvct_high: false,
+
+            ppu1_bus: 0,
+            ppu2_bus: 0,
+
+            vram_write_ignored: false,
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let value = self.peek(addr);
+
+        match addr {
+            // SLHV
+            0x2137 => self.latch_counters(),
+
+            // OAMDATAREAD
+            0x2138 => self.oam_addr = (self.oam_addr + 1) & 0x3FF,
+
+            // VMDATALREAD, VMDATAHREAD. Only reading the byte that VMAIN
+            // increments on moves the address along.
+            0x2139 | 0x213A if (addr == 0x213A) == (self.vram_increment & 0x80 != 0) => {
+                self.prefetch_vram();
+                self.increment_vram_addr();
+            }
+
+            // CGDATAREAD
+            0x213B => {
+                if self.cgram_read_high {
+                    self.cgram_addr = self.cgram_addr.wrapping_add(1);
+                }
+
+                self.cgram_read_high = !self.cgram_read_high;
+            }
+
+            // OPHCT, OPVCT
+            0x213C => self.ophct_high = !self.ophct_high,
+            0x213D => self.opvct_high = !self.opvct_high,
+
+            // STAT78
+            0x213F => {
+                self.counter_latched = false;
+                self.ophct_high = false;
+                self.opvct_high = false;
+            }
+
+            _ => {}
+        }
+
+        // Whatever a chip drives onto the bus stays there, and shows through
+        // the bits of its registers that it doesn't drive.
+        match addr {
+            0x2134..=0x2136 | 0x2138..=0x213A | 0x213E => self.ppu1_bus = value,
+            0x213B..=0x213D | 0x213F => self.ppu2_bus = value,
+            _ => {}
+        }
+
+        value
+    }
+
+    // What the next read of a register would return, without latching the
+    // counters or flipping the high/low byte toggles.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            // MPYL, MPYM, MPYH
+            0x2134..=0x2136 => {
+                let product =
+                    self.m7_matrix[0] as i16 as i32 * (self.m7_matrix[1] >> 8) as i8 as i32;
+
+                (product >> ((addr - 0x2134) * 8)) as u8
+            }
+
+            // SLHV
+            // TODO: This returns the CPU's open bus, which isn't tracked
+            0x2137 => 0,
+
+            // OAMDATAREAD
+            0x2138 => {
+                let addr = self.oam_addr as usize;
+
+                if addr >= 0x200 {
+                    self.oam[0x200 | (addr & 0x1F)]
+                } else {
+                    self.oam[addr]
+                }
+            }
+
+            // VMDATALREAD, VMDATAHREAD. Reads come from a buffer that's
+            // filled before the address moves on, so they lag a word behind.
+            0x2139 => self.vram_prefetch as u8,
+            0x213A => (self.vram_prefetch >> 8) as u8,
+
+            // CGDATAREAD
+            0x213B => {
+                let color = self.cgram[self.cgram_addr as usize];
+
+                if self.cgram_read_high {
+                    (color >> 8) as u8 & 0x7F | self.ppu2_bus & 0x80
+                } else {
+                    color as u8
+                }
+            }
+
+            // OPHCT
+            0x213C => {
+                if self.ophct_high {
+                    (self.latched_h >> 8) as u8 & 1 | self.ppu2_bus & 0xFE
+                } else {
+                    self.latched_h as u8
+                }
+            }
+
+            // OPVCT
+            0x213D => {
+                if self.opvct_high {
+                    (self.latched_v >> 8) as u8 & 1 | self.ppu2_bus & 0xFE
+                } else {
+                    self.latched_v as u8
+                }
+            }
+
+            // STAT77
+            0x213E => {
+                (self.time_over as u8) << 7
+                    | (self.range_over as u8) << 6
+                    | self.ppu1_bus & 0x10
+                    | 0x01
+            }
+
+            // STAT78
+            0x213F => {
+                (self.field as u8) << 7
+                    | (self.counter_latched as u8) << 6
+                    | self.ppu2_bus & 0x20
+                    | 0x03
+            }
+
+            // The write-only registers, which PPU1 leaves its last value on.
+            _ => self.ppu1_bus,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            // INIDISP
+            0x2100 => self.inidisp = value,
+
+            // OBSEL
+            0x2101 => self.obj_select = value,
+
+            // OAMADDL
+            0x2102 => {
+                self.oam_reload = (self.oam_reload & 0x100) | value as u16;
+                self.oam_addr = self.oam_reload << 1;
+            }
+
+            // OAMADDH
+            0x2103 => {
+                // TODO: Priority rotation (bit 7)
+                self.oam_reload = (self.oam_reload & 0xFF) | ((value & 1) as u16) << 8;
+                self.oam_addr = self.oam_reload << 1;
+            }
+
+            // OAMDATA
+            0x2104 => {
+                let addr = self.oam_addr as usize;
+
+                if addr >= 0x200 {
+                    // The high table is written directly (and mirrored).
+                    self.oam[0x200 | (addr & 0x1F)] = value;
+                } else if addr & 1 == 0 {
+                    self.oam_latch = value;
+                } else {
+                    self.oam[addr - 1] = self.oam_latch;
+                    self.oam[addr] = value;
+                }
+
+                self.oam_addr = (self.oam_addr + 1) & 0x3FF;
+            }
+
+            // BGMODE
+            0x2105 => self.bg_mode = value,
+
+            // MOSAIC
+            0x2106 => {
+                // The vertical block grid starts from the line where the
+                // register was written, rather than the top of the screen.
+                self.mosaic = value;
+                self.mosaic_start = self.scanline.saturating_sub(1) as usize;
+            }
+
+            // BG1SC - BG4SC
+            0x2107..=0x210A => self.bg_tilemap[addr as usize - 0x2107] = value,
+
+            // BG12NBA, BG34NBA
+            0x210B..=0x210C => self.bg_chr_base[addr as usize - 0x210B] = value,
+
+            // BG1HOFS (shared with M7HOFS)
+            0x210D => {
+                self.write_hofs(0, value);
+
+                self.m7_hofs = self.m7_write(value) & 0x1FFF;
+            }
+
+            // BG1VOFS (shared with M7VOFS)
+            0x210E => {
+                self.write_vofs(0, value);
+
+                self.m7_vofs = self.m7_write(value) & 0x1FFF;
+            }
+
+            // BG2HOFS - BG4VOFS
+            0x210F..=0x2114 => {
+                let bg = (addr as usize - 0x210D) / 2;
+
+                if addr & 1 == 1 {
+                    self.write_hofs(bg, value);
+                } else {
+                    self.write_vofs(bg, value);
+                }
+            }
+
+            // VMAIN
+            0x2115 => self.vram_increment = value,
+
+            // VMADDL
+            0x2116 => {
+                self.vram_addr = (self.vram_addr & 0xFF00) | value as u16;
+                self.prefetch_vram();
+            }
+
+            // VMADDH
+            0x2117 => {
+                self.vram_addr = (self.vram_addr & 0x00FF) | (value as u16) << 8;
+                self.prefetch_vram();
+            }
+
+            // VMDATAL
+            0x2118 => {
+                // Outside blanking the write is lost, but the address still
+                // moves on.
+                if self.in_blanking() {
+                    let addr = self.vram_word_addr();
+                    let word = &mut self.vram[addr];
+                    *word = (*word & 0xFF00) | value as u16;
+                } else {
+                    self.vram_write_ignored = true;
+                }
+
+                if self.vram_increment & 0x80 == 0 {
+                    self.increment_vram_addr();
+                }
+            }
+
+            // VMDATAH
+            0x2119 => {
+                if self.in_blanking() {
+                    let addr = self.vram_word_addr();
+                    let word = &mut self.vram[addr];
+                    *word = (*word & 0x00FF) | (value as u16) << 8;
+                } else {
+                    self.vram_write_ignored = true;
+                }
+
+                if self.vram_increment & 0x80 != 0 {
+                    self.increment_vram_addr();
+                }
+            }
+
+            // CGADD
+            0x2121 => {
+                self.cgram_addr = value;
+                self.cgram_latch = None;
+                self.cgram_read_high = false;
+            }
+
+            // CGDATA
+            0x2122 => match self.cgram_latch.take() {
+                None => self.cgram_latch = Some(value),
+                Some(low) => {
+                    self.cgram[self.cgram_addr as usize] = u16::from_le_bytes([low, value & 0x7F]);
+
+                    self.cgram_addr = self.cgram_addr.wrapping_add(1);
+                }
+            },
+
+            // M7SEL
+            0x211A => self.m7_select = value,
+
+            // M7A - M7D
+            0x211B..=0x211E => {
+                self.m7_matrix[addr as usize - 0x211B] = self.m7_write(value);
+            }
+
+            // M7X, M7Y
+            0x211F..=0x2120 => {
+                self.m7_center[addr as usize - 0x211F] = self.m7_write(value) & 0x1FFF;
+            }
+
+            // W12SEL, W34SEL, WOBJSEL
+            0x2123..=0x2125 => self.window_select[addr as usize - 0x2123] = value,
+
+            // WH0, WH1, WH2, WH3
+            0x2126..=0x2129 => self.window_edges[addr as usize - 0x2126] = value,
+
+            // WBGLOG, WOBJLOG
+            0x212A..=0x212B => self.window_logic[addr as usize - 0x212A] = value,
+
+            // TM
+            0x212C => self.main_screen = value,
+
+            // TS
+            0x212D => self.sub_screen = value,
+
+            // TMW
+            0x212E => self.main_window = value,
+
+            // TSW
+            0x212F => self.sub_window = value,
+
+            // CGWSEL
+            0x2130 => self.color_math_select = value,
+
+            // CGADSUB
+            0x2131 => self.color_math_control = value,
+
+            // COLDATA
+            0x2132 => {
+                let intensity = (value & 0x1F) as u16;
+
+                for channel in 0..3 {
+                    if value & (0x20 << channel) != 0 {
+                        self.fixed_color &= !(0x1F << (channel * 5));
+                        self.fixed_color |= intensity << (channel * 5);
+                    }
+                }
+            }
+
+            // SETINI
+            // TODO: Pseudo-hires and EXTBG
+            0x2133 => self.setini = value,
+
+            // The read-only registers
+            _ => {}
+        }
+    }
+
+    // The offsets are stored with all 16 bits, as the next horizontal write
+    // needs bits 0-2 of this one's high byte. Only 10 of them are used for
+    // rendering.
+    fn write_hofs(&mut self, bg: usize, value: u8) {
+        // The horizontal offsets only take bits 3-7 from the shared latch,
+        // and keep bits 0-2 from the register's previous high write.
+        self.bg_hofs[bg] =
+            (value as u16) << 8 | (self.bg_scroll_latch & !7) as u16 | (self.bg_hofs[bg] >> 8) & 7;
+
+        self.bg_scroll_latch = value;
+    }
+
+    fn write_vofs(&mut self, bg: usize, value: u8) {
+        self.bg_vofs[bg] = (value as u16) << 8 | self.bg_scroll_latch as u16;
+        self.bg_scroll_latch = value;
+    }
+
+    // Whether a VRAM write has been ignored since the last call.
+    pub fn take_vram_write_ignored(&mut self) -> bool {
+        std::mem::take(&mut self.vram_write_ignored)
+    }
+
+    pub fn vram_addr(&self) -> u16 {
+        self.vram_addr
+    }
+
+    fn m7_write(&mut self, value: u8) -> u16 {
+        let word = (value as u16) << 8 | self.m7_latch as u16;
+        self.m7_latch = value;
+
+        word
+    }
+
+    pub fn in_vblank(&self) -> bool {
+        self.scanline >= self.vblank_line()
+    }
+
+    fn in_blanking(&self) -> bool {
+        self.inidisp & 0x80 != 0 || self.in_vblank()
+    }
+
+    // The scanline that vblank starts on, which is the one after the last
+    // visible line is drawn.
+    pub fn vblank_line(&self) -> u16 {
+        self.visible_lines + 1
+    }
+
+    // SETINI's interlace bit. Interlaced frames take an extra line on every
+    // other field, but are otherwise drawn the same as progressive ones for
+    // now.
+    pub fn interlace(&self) -> bool {
+        self.setini & 0x01 != 0
+    }
+
+    // SETINI's OBJ interlace bit, which isn't drawn yet.
+    pub fn obj_interlace(&self) -> bool {
+        self.setini & 0x02 != 0
+    }
+
+    pub fn field(&self) -> bool {
+        self.field
+    }
+
+    // The word that the data ports access, after the address has been
+    // through the translation selected by VMAIN.
+    fn vram_word_addr(&self) -> usize {
+        let addr = match (self.vram_increment >> 2) & 0b11 {
+            0 => self.vram_addr,
+            1 => remap_2bpp(self.vram_addr),
+            2 => remap_4bpp(self.vram_addr),
+            _ => remap_8bpp(self.vram_addr),
+        };
+
+        (addr & 0x7FFF) as usize
+    }
+
+    fn prefetch_vram(&mut self) {
+        self.vram_prefetch = self.vram[self.vram_word_addr()];
+    }
+
+    fn increment_vram_addr(&mut self) {
+        let step = match self.vram_increment & 0b11 {
+            0 => 1,
+            1 => 32,
+            _ => 128,
+        };
+
+        self.vram_addr = self.vram_addr.wrapping_add(step);
+    }
+
+    pub fn step(&mut self, cycles: u64) -> bool {
+        let mut frame_complete = false;
+
+        self.line_cycles += cycles;
+
+        while self.line_cycles >= MASTER_CYCLES_PER_LINE {
+            self.line_cycles -= MASTER_CYCLES_PER_LINE;
+
+            // Line 0 is never displayed, so visible line N is drawn at the
+            // end of scanline N + 1.
+            // TODO: The framebuffer doesn't have room for the overscan lines
+            if (1..=SCREEN_HEIGHT as u16).contains(&self.scanline) {
+                self.render_scanline(self.scanline as usize - 1);
+            }
+
+            self.scanline += 1;
+
+            if self.scanline == self.vblank_line() {
+                self.oam_addr = self.oam_reload << 1;
+                self.frame += 1;
+
+                frame_complete = true;
+            }
+
+            // The first field of an interlaced frame has an extra line.
+            let lines = LINES_PER_FRAME + (self.interlace() && !self.field) as u16;
+
+            if self.scanline == lines {
+                self.scanline = 0;
+
+                self.visible_lines = match self.setini & 0x04 != 0 {
+                    true => OVERSCAN_LINES,
+                    false => VISIBLE_LINES,
+                };
+
+                self.field = !self.field;
+
+                self.range_over = false;
+                self.time_over = false;
+
+                self.mosaic_start = 0;
+            }
+        }
+
+        frame_complete
+    }
+
+    pub fn cycles_left_in_line(&self) -> u64 {
+        MASTER_CYCLES_PER_LINE - self.line_cycles
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    // The horizontal position in master cycles, which is what most debuggers
+    // show in their traces.
+    pub fn h_counter(&self) -> u16 {
+        self.line_cycles as u16
+    }
+
+    pub fn dot(&self) -> u16 {
+        // TODO: Account for the two long dots on each line
+        (self.line_cycles / 4) as u16
+    }
+
+    pub fn latch_counters(&mut self) {
+        self.latched_h = self.dot();
+        self.latched_v = self.scanline;
+        self.counter_latched = true;
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    pub fn cgram(&self) -> &[u16] {
+        &self.cgram
+    }
+
+    fn bg_depths(&self) -> [u8; 4] {
+        match self.bg_mode & 0b111 {
+            0 => [2, 2, 2, 2],
+            1 => [4, 4, 2, 0],
+            2 => [4, 4, 0, 0],
+            3 => [8, 4, 0, 0],
+            4 => [8, 2, 0, 0],
+            5 => [4, 2, 0, 0],
+            6 => [4, 0, 0, 0],
+            _ => [8, 0, 0, 0],
+        }
+    }
+
+    fn bg_scroll(&self, bg: usize) -> (u16, u16) {
+        if bg == 0 && self.bg_mode & 0b111 == 7 {
+            (self.m7_hofs, self.m7_vofs)
+        } else {
+            (self.bg_hofs[bg] & 0x3FF, self.bg_vofs[bg] & 0x3FF)
+        }
+    }
+
+    fn render_scanline(&mut self, y: usize) {
+        if self.inidisp & 0x80 != 0 {
+            let line = y * SCREEN_WIDTH * 4..(y + 1) * SCREEN_WIDTH * 4;
+
+            for pixel in self.framebuffer[line].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[0, 0, 0, 0xFF]);
+            }
+
+            return;
+        }
+
+        let depths = self.bg_depths();
+        let ranks = self.ranks();
+        let obj_line = self.render_obj_line(y);
+
+        for (x, obj) in obj_line.iter().enumerate() {
+            // The layers that a screen's windows cover are left off it.
+            let masks = self.window_masks(x);
+            let main_screen = self.main_screen & !(self.main_window & masks);
+            let sub_screen = self.sub_screen & !(self.sub_window & masks);
+
+            let (index, layer) = self
+                .composite(&depths, ranks, *obj, main_screen, x, y)
+                .unwrap_or((0, Layer::Backdrop));
+
+            let mut color = self.cgram[index as usize];
+
+            // CGWSEL picks where the main screen is forced to black, and
+            // where color math is allowed, by the color window.
+            let in_color_window = masks & COLOR_WINDOW != 0;
+
+            let black = match self.color_math_select >> 6 {
+                0 => false,
+                1 => !in_color_window,
+                2 => in_color_window,
+                _ => true,
+            };
+
+            let math = match (self.color_math_select >> 4) & 3 {
+                0 => true,
+                1 => in_color_window,
+                2 => !in_color_window,
+                _ => false,
+            };
+
+            if black {
+                color = 0;
+            }
+
+            if self.color_math_enabled(layer, index) && math {
+                let sub = if self.color_math_select & 0x02 != 0 {
+                    self.composite(&depths, ranks, *obj, sub_screen, x, y)
+                        .map(|(index, _)| self.cgram[index as usize])
+                } else {
+                    None
+                };
+
+                // Halving is skipped when a transparent sub screen pixel
+                // falls back to the fixed color.
+                let half = self.color_math_control & 0x40 != 0
+                    && (self.color_math_select & 0x02 == 0 || sub.is_some());
+
+                color = color::blend(
+                    color,
+                    sub.unwrap_or(self.fixed_color),
+                    self.color_math_control & 0x80 != 0,
+                    half,
+                );
+            }
+
+            let [r, g, b] = color::brightness(color::snes_to_rgb(color), self.inidisp & 0xF);
+
+            let i = (y * SCREEN_WIDTH + x) * 4;
+            self.framebuffer[i..i + 4].copy_from_slice(&[r, g, b, 0xFF]);
+        }
+    }
+
+    fn composite(
+        &self,
+        depths: &[u8; 4],
+        ranks: &Ranks,
+        obj: Option<ObjPixel>,
+        screen: u8,
+        x: usize,
+        y: usize,
+    ) -> Option<(u8, Layer)> {
+        let mut pixel = None;
+        let mut rank = None;
+
+        for (bg, &depth) in depths.iter().enumerate() {
+            if depth == 0 || screen & (1 << bg) == 0 {
+                continue;
+            }
+
+            if let Some((color, priority)) = self.bg_pixel(bg, depth, x, y) {
+                let bg_rank = ranks.bg[bg][priority as usize];
+
+                if rank < Some(bg_rank) {
+                    pixel = Some((color, Layer::Bg(bg)));
+                    rank = Some(bg_rank);
+                }
+            }
+        }
+
+        if let Some(obj) = obj {
+            if screen & 0x10 != 0 && rank < Some(ranks.obj[obj.priority as usize]) {
+                pixel = Some((obj.color, Layer::Obj));
+            }
+        }
+
+        pixel
+    }
+
+    fn color_math_enabled(&self, layer: Layer, index: u8) -> bool {
+        match layer {
+            Layer::Bg(bg) => self.color_math_control & (1 << bg) != 0,
+
+            // Only sprites using palettes 4-7 participate in color math.
+            Layer::Obj => self.color_math_control & 0x10 != 0 && index >= 192,
+
+            Layer::Backdrop => self.color_math_control & 0x20 != 0,
+        }
+    }
+
+    fn ranks(&self) -> &'static Ranks {
+        match self.bg_mode & 0b1111 {
+            0b0000 | 0b1000 | 0b0001 => &MODE0_RANKS,
+            0b1001 => &MODE1_BG3_RANKS,
+            _ => &MODE2_RANKS,
+        }
+    }
+
+    fn mosaic(&self, bg: usize, x: usize, y: usize) -> (usize, usize) {
+        if self.mosaic & (1 << bg) == 0 {
+            return (x, y);
+        }
+
+        let size = (self.mosaic >> 4) as usize + 1;
+        let start = self.mosaic_start.min(y);
+
+        (x - x % size, y - (y - start) % size)
+    }
+
+    fn bg_pixel(&self, bg: usize, depth: u8, x: usize, y: usize) -> Option<(u8, bool)> {
+        let (mosaic_x, y) = self.mosaic(bg, x, y);
+
+        if self.bg_mode & 0b111 == 7 {
+            // Mode 7 only applies the vertical part of the mosaic.
+            return self.mode7_pixel(x, y).map(|color| (color, false));
+        }
+
+        let x = mosaic_x;
+
+        let (hofs, vofs) = self.bg_scroll(bg);
+        let tile_size = self.tile_size(bg);
+
+        let px = (x as u16).wrapping_add(hofs) & 0x3FF;
+        let py = (y as u16).wrapping_add(vofs) & 0x3FF;
+
+        let entry = self.tilemap_entry(bg, (px / tile_size) & 63, (py / tile_size) & 63);
+        let color = self.entry_pixel(bg, depth, entry, px % tile_size, py % tile_size);
+
+        if color == 0 {
+            return None;
+        }
+
+        Some((color, entry & 0x2000 != 0))
+    }
+
+    fn tile_size(&self, bg: usize) -> u16 {
+        if self.bg_mode & (0x10 << bg) != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // The CGRAM index of a pixel in the tile that a tilemap entry points to,
+    // or zero if it's transparent. The position is within the whole tile,
+    // which may be 16x16, before flipping.
+    fn entry_pixel(&self, bg: usize, depth: u8, entry: u16, x: u16, y: u16) -> u8 {
+        let tile_size = self.tile_size(bg);
+
+        let mut fine_x = x;
+        let mut fine_y = y;
+
+        if entry & 0x4000 != 0 {
+            fine_x = tile_size - 1 - fine_x;
+        }
+
+        if entry & 0x8000 != 0 {
+            fine_y = tile_size - 1 - fine_y;
+        }
+
+        let tile = (entry & 0x3FF) + (fine_x / 8) + (fine_y / 8) * 16;
+
+        let tile_addr = self.chr_base(bg).wrapping_add(tile * depth as u16 * 4);
+
+        let color = self.tile_pixel(tile_addr, depth, fine_x % 8, fine_y % 8);
+
+        if color == 0 {
+            return 0;
+        }
+
+        let palette = ((entry >> 10) & 0b111) as u8;
+
+        match depth {
+            2 if self.bg_mode & 0b111 == 0 => bg as u8 * 32 + palette * 4 + color,
+            2 => palette * 4 + color,
+            4 => palette * 16 + color,
+            _ => color,
+        }
+    }
+
+    // The tilemap entry for a tile, counted in tiles from the top left of
+    // the whole (up to 64x64) map.
+    fn tilemap_entry(&self, bg: usize, tile_x: u16, tile_y: u16) -> u16 {
+        let tilemap = self.bg_tilemap[bg];
+        let mut entry_addr = (tilemap as u16 & 0xFC) << 8;
+
+        if tile_x >= 32 && tilemap & 1 != 0 {
+            entry_addr += 0x400;
+        }
+
+        if tile_y >= 32 && tilemap & 2 != 0 {
+            entry_addr += if tilemap & 1 != 0 { 0x800 } else { 0x400 };
+        }
+
+        entry_addr += (tile_y & 31) * 32 + (tile_x & 31);
+
+        self.vram[(entry_addr & 0x7FFF) as usize]
+    }
+
+    // The word address of a BG's first tile.
+    fn chr_base(&self, bg: usize) -> u16 {
+        (self.bg_chr_base[bg / 2] as u16 >> ((bg % 2) * 4) & 0xF) << 12
+    }
+
+    fn tile_pixel(&self, tile_addr: u16, depth: u8, x: u16, y: u16) -> u8 {
+        let mut color = 0;
+
+        for plane in 0..depth as u16 / 2 {
+            let word = self.vram[(tile_addr.wrapping_add(y + plane * 8) & 0x7FFF) as usize];
+
+            let low = (word >> (7 - x)) & 1;
+            let high = (word >> (15 - x)) & 1;
+
+            color |= ((low | high << 1) as u8) << (plane * 2);
+        }
+
+        color
+    }
+
+    pub fn register_debug(&self) -> String {
+        format!(
+            "Mode: {} | BG1: {:03X},{:03X} | BG2: {:03X},{:03X} | BG3: {:03X},{:03X} | BG4: {:03X},{:03X} | M7: {:04X},{:04X} | Line: {}",
+            self.bg_mode & 0b111,
+            self.bg_hofs[0] & 0x3FF,
+            self.bg_vofs[0] & 0x3FF,
+            self.bg_hofs[1] & 0x3FF,
+            self.bg_vofs[1] & 0x3FF,
+            self.bg_hofs[2] & 0x3FF,
+            self.bg_vofs[2] & 0x3FF,
+            self.bg_hofs[3] & 0x3FF,
+            self.bg_vofs[3] & 0x3FF,
+            self.m7_hofs,
+            self.m7_vofs,
+            self.scanline,
+        )
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Ppu {
+        Ppu::new()
+    }
+}
+
+// The VRAM address translations rotate the low bits of the word address
+// left by three, so that writing consecutive words fills one row of pixels
+// across several tiles, as a bitmap would be laid out:
+//
+//   2bpp: aaaaaaaaYYYxxxxx -> aaaaaaaaxxxxxYYY
+//   4bpp: aaaaaaaYYYxxxxxP -> aaaaaaaxxxxxPYYY
+//   8bpp: aaaaaaYYYxxxxxPP -> aaaaaaxxxxxPPYYY
+pub fn remap_2bpp(addr: u16) -> u16 {
+    (addr & 0xFF00) | (addr << 3 & 0x00F8) | (addr >> 5 & 0x0007)
+}
+
+pub fn remap_4bpp(addr: u16) -> u16 {
+    (addr & 0xFE00) | (addr << 3 & 0x01F8) | (addr >> 6 & 0x0007)
+}
+
+pub fn remap_8bpp(addr: u16) -> u16 {
+    (addr & 0xFC00) | (addr << 3 & 0x03F8) | (addr >> 7 & 0x0007)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes a register the way a game would, a byte at a time.
+    fn write_all(ppu: &mut Ppu, addr: u16, values: &[u8]) {
+        for &value in values {
+            ppu.write(addr, value);
+        }
+    }
+
+    #[test]
+    fn scroll_two_writes() {
+        let mut ppu = Ppu::new();
+
+        write_all(&mut ppu, 0x210F, &[0x23, 0x01]);
+        write_all(&mut ppu, 0x2110, &[0x45, 0x02]);
+
+        assert_eq!(ppu.bg_hofs[1], 0x123);
+        assert_eq!(ppu.bg_vofs[1], 0x245);
+    }
+
+    #[test]
+    fn scroll_is_ten_bits() {
+        let mut ppu = Ppu::new();
+
+        write_all(&mut ppu, 0x2111, &[0xFF, 0xFF]);
+        write_all(&mut ppu, 0x2112, &[0xFF, 0xFF]);
+
+        // The first write's high byte supplies bits 0-2 of the second.
+        assert_eq!(ppu.bg_scroll(2), (0x3FF, 0x3FF));
+    }
+
+    // Bit 2 of the low byte has to survive the high byte's write, or the
+    // scroll shakes by four pixels.
+    #[test]
+    fn hofs_keeps_bit_2() {
+        let mut ppu = Ppu::new();
+
+        write_all(&mut ppu, 0x210D, &[0x04, 0x00]);
+        assert_eq!(ppu.bg_scroll(0), (0x004, 0));
+
+        write_all(&mut ppu, 0x210F, &[0x0D, 0x01]);
+        assert_eq!(ppu.bg_scroll(1), (0x10D, 0));
+    }
+
+    // The latch is shared by all eight registers, so the low byte of one
+    // can end up in another.
+    #[test]
+    fn scroll_latch_is_shared() {
+        let mut ppu = Ppu::new();
+
+        ppu.write(0x2110, 0x34);
+        ppu.write(0x2114, 0x01);
+
+        assert_eq!(ppu.bg_vofs[3], 0x134);
+    }
+
+    // A single write to a horizontal offset takes bits 3-7 from the latch,
+    // and keeps bits 0-2 from the register's previous high byte.
+    #[test]
+    fn hofs_single_write() {
+        let mut ppu = Ppu::new();
+
+        write_all(&mut ppu, 0x210F, &[0x00, 0x03]);
+        assert_eq!(ppu.bg_hofs[1], 0x300);
+
+        ppu.write(0x2110, 0xAF);
+        ppu.write(0x210F, 0x01);
+
+        assert_eq!(ppu.bg_hofs[1], 0x1AB);
+    }
+
+    // A single write to a vertical offset takes the whole latch as its low
+    // byte.
+    #[test]
+    fn vofs_single_write() {
+        let mut ppu = Ppu::new();
+
+        ppu.write(0x210F, 0x5A);
+        ppu.write(0x2110, 0x02);
+
+        assert_eq!(ppu.bg_vofs[1], 0x25A);
+    }
+
+    // BG1's offsets double as mode 7's, which have a latch of their own and
+    // 13 bits.
+    #[test]
+    fn bg1_scroll_is_shared_with_mode7() {
+        let mut ppu = Ppu::new();
+
+        write_all(&mut ppu, 0x210D, &[0x34, 0x12]);
+        write_all(&mut ppu, 0x210E, &[0x78, 0xF6]);
+
+        assert_eq!(ppu.m7_hofs, 0x1234);
+        assert_eq!(ppu.m7_vofs, 0x1678);
+
+        ppu.bg_mode = 7;
+        assert_eq!(ppu.bg_scroll(0), (0x1234, 0x1678));
+
+        ppu.bg_mode = 1;
+        assert_eq!(ppu.bg_scroll(0), (0x234, 0x278));
+    }
+
+    // The first pixel of a rendered line, as RGB.
+    fn first_pixel(ppu: &mut Ppu) -> [u8; 3] {
+        ppu.render_scanline(0);
+        ppu.framebuffer[0..3].try_into().unwrap()
+    }
+
+    #[test]
+    fn forced_blank_is_black() {
+        let mut ppu = Ppu::new();
+        ppu.cgram[0] = 0x7FFF;
+
+        ppu.write(0x2100, 0x8F);
+        assert_eq!(first_pixel(&mut ppu), [0, 0, 0]);
+
+        ppu.write(0x2100, 0x0F);
+        assert_eq!(first_pixel(&mut ppu), [255, 255, 255]);
+    }
+
+    #[test]
+    fn brightness_scales_output() {
+        let mut ppu = Ppu::new();
+        ppu.cgram[0] = 0x7FFF;
+
+        ppu.write(0x2100, 0x07);
+        assert_eq!(first_pixel(&mut ppu), [127, 127, 127]);
+
+        ppu.write(0x2100, 0x00);
+        assert_eq!(first_pixel(&mut ppu), [15, 15, 15]);
+    }
+
+    // Shows a backdrop of `backdrop`, with COLDATA set to `fixed`.
+    fn math_ppu(backdrop: u16, fixed: u16) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(0x2100, 0x0F);
+        ppu.cgram[0] = backdrop;
+
+        for channel in 0..3 {
+            let intensity = (fixed >> (channel * 5)) as u8 & 0x1F;
+            ppu.write(0x2132, 0x20 << channel | intensity);
+        }
+
+        ppu
+    }
+
+    #[test]
+    fn coldata_sets_selected_channels() {
+        let mut ppu = Ppu::new();
+
+        ppu.write(0x2132, 0xE0 | 0x08);
+        assert_eq!(ppu.fixed_color, 0x2108);
+
+        ppu.write(0x2132, 0x40 | 0x1F);
+        assert_eq!(ppu.fixed_color, 0x23E8);
+    }
+
+    #[test]
+    fn math_only_on_enabled_layers() {
+        let mut ppu = math_ppu(0x0010, 0x0008);
+
+        ppu.write(0x2131, 0x00);
+        assert_eq!(first_pixel(&mut ppu), color::snes_to_rgb(0x0010));
+
+        ppu.write(0x2131, 0x20);
+        assert_eq!(first_pixel(&mut ppu), color::snes_to_rgb(0x0018));
+    }
+
+    #[test]
+    fn fixed_color_add_with_halve() {
+        // Red (16 + 8) / 2 = 12, green (4 + 8) / 2 = 6.
+        let mut ppu = math_ppu(0x0090, 0x0108);
+        ppu.write(0x2131, 0x60);
+
+        assert_eq!(first_pixel(&mut ppu), color::snes_to_rgb(0x00CC));
+    }
+
+    #[test]
+    fn fixed_color_subtract_clamps() {
+        // Red 4 - 10 clamps to 0, green 20 - 5 = 15.
+        let mut ppu = math_ppu(0x0284, 0x00AA);
+        ppu.write(0x2131, 0xA0);
+
+        assert_eq!(first_pixel(&mut ppu), color::snes_to_rgb(0x01E0));
+    }
+
+    #[test]
+    fn transparent_sub_screen_falls_back_unhalved() {
+        // With the sub screen selected but empty, the fixed color is used
+        // and halving is skipped.
+        let mut ppu = math_ppu(0x0010, 0x0008);
+        ppu.write(0x2130, 0x02);
+        ppu.write(0x2131, 0x60);
+
+        assert_eq!(first_pixel(&mut ppu), color::snes_to_rgb(0x0018));
+    }
+
+    #[test]
+    fn color_window_gates_math() {
+        let mut ppu = math_ppu(0x0010, 0x0008);
+        ppu.write(0x2131, 0x20);
+
+        // Math "never", then main screen always black with math on.
+        ppu.write(0x2130, 0x30);
+        assert_eq!(first_pixel(&mut ppu), color::snes_to_rgb(0x0010));
+
+        ppu.write(0x2130, 0xC0);
+        assert_eq!(first_pixel(&mut ppu), color::snes_to_rgb(0x0008));
+    }
+
+    // A PPU in the given mode where each of the layers is solid, and only
+    // those are on the main screen. Layers are named as in the priority
+    // tables: "2H" is BG2 with its high priority tiles, "S1" a sprite with
+    // priority 1.
+    fn layered_ppu(mode: u8, layers: &[&str]) -> (Ppu, Option<ObjPixel>) {
+        let mut ppu = Ppu::new();
+        ppu.write(0x2105, mode);
+
+        // Tile 1 is solid in color 1 at every depth, with all the character
+        // bases at 0.
+        for depth in [2, 4, 8] {
+            ppu.vram[depth * 4..depth * 4 + 8].fill(0x00FF);
+        }
+
+        let mut main_screen = 0;
+        let mut obj = None;
+
+        for layer in layers {
+            let (kind, priority) = layer.split_at(1);
+
+            if kind == "S" {
+                main_screen |= 0x10;
+                obj = Some(ObjPixel {
+                    color: 129,
+                    priority: priority.parse().unwrap(),
+                });
+            } else {
+                let bg = kind.parse::<usize>().unwrap() - 1;
+                let entry = 1 | ((priority == "H") as u16) << 13;
+
+                ppu.write(0x2107 + bg as u16, 0x40 + bg as u8 * 4);
+                ppu.vram[0x4000 + bg * 0x400..][..0x400].fill(entry);
+                main_screen |= 1 << bg;
+            }
+        }
+
+        ppu.write(0x212C, main_screen);
+        (ppu, obj)
+    }
+
+    // Which layer ends up in front, as "S" for a sprite or the BG's number.
+    fn front_layer(ppu: &Ppu, obj: Option<ObjPixel>, screen: u8) -> Option<String> {
+        let depths = ppu.bg_depths();
+
+        ppu.composite(&depths, ppu.ranks(), obj, screen, 0, 0)
+            .map(|(_, layer)| match layer {
+                Layer::Bg(bg) => (bg + 1).to_string(),
+                Layer::Obj => "S".to_string(),
+                Layer::Backdrop => unreachable!(),
+            })
+    }
+
+    #[test]
+    fn layers_in_priority_order() {
+        // Each mode's layers, front to back.
+        let orders: &[(u8, &[&str])] = &[
+            (
+                0x00,
+                &[
+                    "S3", "1H", "2H", "S2", "1L", "2L", "S1", "3H", "4H", "S0", "3L", "4L",
+                ],
+            ),
+            (
+                0x01,
+                &["S3", "1H", "2H", "S2", "1L", "2L", "S1", "3H", "S0", "3L"],
+            ),
+            (
+                0x09,
+                &["3H", "S3", "1H", "2H", "S2", "1L", "2L", "S1", "S0", "3L"],
+            ),
+            (0x03, &["S3", "1H", "S2", "2H", "S1", "1L", "S0", "2L"]),
+        ];
+
+        for &(mode, order) in orders {
+            for (i, front) in order.iter().enumerate() {
+                for back in &order[i + 1..] {
+                    // A layer can't be in front of itself.
+                    if front[..1] == back[..1] {
+                        continue;
+                    }
+
+                    let (ppu, obj) = layered_ppu(mode, &[front, back]);
+                    let layer = front_layer(&ppu, obj, ppu.main_screen);
+
+                    assert_eq!(
+                        layer.as_deref(),
+                        Some(&front[..1]),
+                        "mode {:02X}: {} over {}",
+                        mode,
+                        front,
+                        back
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn screens_mask_layers() {
+        let (mut ppu, obj) = layered_ppu(0x01, &["S3", "1H", "2L", "3H"]);
+
+        let layer = |ppu: &Ppu, screen| front_layer(ppu, obj, screen);
+
+        assert_eq!(layer(&ppu, 0x17).as_deref(), Some("S"));
+        assert_eq!(layer(&ppu, 0x07).as_deref(), Some("1"));
+        assert_eq!(layer(&ppu, 0x06).as_deref(), Some("2"));
+        assert_eq!(layer(&ppu, 0x04).as_deref(), Some("3"));
+        assert_eq!(layer(&ppu, 0x00), None);
+
+        // The sub screen has its own mask.
+        ppu.write(0x212D, 0x02);
+        assert_eq!(layer(&ppu, ppu.sub_screen).as_deref(), Some("2"));
+
+        // Mode 1's BG3 priority bit only matters for its high priority
+        // tiles.
+        ppu.write(0x2105, 0x09);
+        assert_eq!(layer(&ppu, 0x17).as_deref(), Some("3"));
+    }
+
+    // BG1 drawn in red over a blue backdrop, with window 1 at 64-191, and
+    // the colors across the line after rendering.
+    fn windowed_line(setup: impl Fn(&mut Ppu)) -> Vec<[u8; 3]> {
+        let (mut ppu, _) = layered_ppu(0x01, &["1H"]);
+        ppu.write(0x2100, 0x0F);
+        ppu.cgram[0] = 0x7C00;
+        ppu.cgram[1] = 0x001F;
+
+        ppu.write(0x2126, 64);
+        ppu.write(0x2127, 191);
+        setup(&mut ppu);
+
+        ppu.render_scanline(0);
+        ppu.framebuffer[..SCREEN_WIDTH * 4]
+            .chunks(4)
+            .map(|pixel| pixel[..3].try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn windows_clip_layers() {
+        let red = color::snes_to_rgb(0x001F);
+        let blue = color::snes_to_rgb(0x7C00);
+
+        // Without TMW, the window has nothing to mask.
+        let line = windowed_line(|ppu| ppu.write(0x2123, 0x02));
+        assert!(line.iter().all(|&pixel| pixel == red));
+
+        let line = windowed_line(|ppu| {
+            ppu.write(0x2123, 0x02);
+            ppu.write(0x212E, 0x01);
+        });
+
+        assert_eq!(
+            [line[63], line[64], line[191], line[192]],
+            [red, blue, blue, red]
+        );
+
+        // The sub screen's mask doesn't touch the main screen.
+        let line = windowed_line(|ppu| {
+            ppu.write(0x2123, 0x02);
+            ppu.write(0x212F, 0x01);
+        });
+
+        assert!(line.iter().all(|&pixel| pixel == red));
+    }
+
+    #[test]
+    fn color_window() {
+        let red = color::snes_to_rgb(0x001F);
+        let black = [0, 0, 0];
+
+        // CGWSEL clips to black outside the color window, so inverting
+        // window 1 for it swaps which part of the line goes black.
+        for (wobjsel, inside, outside) in [(0x20, red, black), (0x30, black, red)] {
+            let line = windowed_line(|ppu| {
+                ppu.write(0x2125, wobjsel);
+                ppu.write(0x2130, 0x40);
+            });
+
+            assert_eq!([line[10], line[100], line[250]], [outside, inside, outside]);
+        }
+
+        // Adding the fixed green only inside it.
+        let line = windowed_line(|ppu| {
+            ppu.write(0x2125, 0x20);
+            ppu.write(0x2130, 0x10);
+            ppu.write(0x2131, 0x01);
+            ppu.write(0x2132, 0x40 | 0x1F);
+        });
+
+        let yellow = color::snes_to_rgb(0x03FF);
+        assert_eq!([line[10], line[100], line[250]], [red, yellow, red]);
+    }
+
+    fn write_vram_word(ppu: &mut Ppu, addr: u16, value: u16) {
+        ppu.write(0x2115, 0x80);
+        ppu.write(0x2116, addr as u8);
+        ppu.write(0x2117, (addr >> 8) as u8);
+        ppu.write(0x2118, value as u8);
+        ppu.write(0x2119, (value >> 8) as u8);
+    }
+
+    #[test]
+    fn vram_writes_in_forced_blank() {
+        let mut ppu = Ppu::new();
+        ppu.scanline = 100;
+
+        write_vram_word(&mut ppu, 0x1000, 0xBEEF);
+
+        assert_eq!(ppu.vram[0x1000], 0xBEEF);
+        assert_eq!(ppu.vram_addr, 0x1001);
+        assert!(!ppu.take_vram_write_ignored());
+    }
+
+    #[test]
+    fn vram_writes_ignored_while_drawing() {
+        let mut ppu = Ppu::new();
+        ppu.write(0x2100, 0x0F);
+        ppu.scanline = 100;
+
+        write_vram_word(&mut ppu, 0x1000, 0xBEEF);
+
+        // The data is lost, but the address still increments.
+        assert_eq!(ppu.vram[0x1000], 0);
+        assert_eq!(ppu.vram_addr, 0x1001);
+        assert!(ppu.take_vram_write_ignored());
+        assert!(!ppu.take_vram_write_ignored());
+    }
+
+    #[test]
+    fn vram_writes_in_vblank() {
+        let mut ppu = Ppu::new();
+        ppu.write(0x2100, 0x0F);
+
+        ppu.scanline = ppu.vblank_line() - 1;
+        write_vram_word(&mut ppu, 0x1000, 0x1234);
+        assert_eq!(ppu.vram[0x1000], 0);
+        assert!(ppu.take_vram_write_ignored());
+
+        ppu.scanline = ppu.vblank_line();
+        write_vram_word(&mut ppu, 0x1000, 0x1234);
+        assert_eq!(ppu.vram[0x1000], 0x1234);
+        assert!(!ppu.take_vram_write_ignored());
+    }
+
+    // Steps a line at a time until the start of the next frame, and returns
+    // how many lines that took and the line that vblank started on.
+    fn run_frame(ppu: &mut Ppu) -> (u16, u16) {
+        let (mut lines, mut vblank) = (0, 0);
+
+        loop {
+            lines += 1;
+
+            if ppu.step(MASTER_CYCLES_PER_LINE) {
+                vblank = ppu.scanline;
+            }
+
+            if ppu.scanline == 0 {
+                return (lines, vblank);
+            }
+        }
+    }
+
+    #[test]
+    fn overscan_starts_with_the_next_frame() {
+        let mut ppu = Ppu::new();
+        run_frame(&mut ppu);
+
+        ppu.scanline = 100;
+        ppu.write(0x2133, 0x04);
+        assert_eq!(ppu.vblank_line(), 225);
+        assert_eq!(run_frame(&mut ppu), (162, 225));
+
+        assert_eq!(run_frame(&mut ppu), (262, 240));
+
+        ppu.write(0x2133, 0x00);
+        assert_eq!(run_frame(&mut ppu), (262, 240));
+        assert_eq!(run_frame(&mut ppu), (262, 225));
+    }
+
+    #[test]
+    fn interlace() {
+        let mut ppu = Ppu::new();
+        ppu.write(0x2133, 0x03);
+        assert!(ppu.interlace() && ppu.obj_interlace());
+
+        // The field flips every frame, and shows up in the top bit of
+        // STAT78. The first field is a line longer.
+        let mut fields = Vec::new();
+        let mut lengths = Vec::new();
+
+        for _ in 0..4 {
+            lengths.push(run_frame(&mut ppu).0);
+            fields.push(ppu.read(0x213F) >> 7);
+        }
+
+        assert_eq!(lengths, [263, 262, 263, 262]);
+        assert_eq!(fields, [1, 0, 1, 0]);
+
+        ppu.write(0x2133, 0x00);
+        assert!(!ppu.interlace() && !ppu.obj_interlace());
+        assert_eq!(run_frame(&mut ppu).0, 262);
+        assert_eq!(ppu.read(0x213F) >> 7, 1);
+    }
+
+    #[test]
+    fn remaps_rotate_the_low_bits() {
+        // (address, 2bpp, 4bpp, 8bpp)
+        let cases = [
+            (0x0001, 0x0008, 0x0008, 0x0008),
+            (0x0002, 0x0010, 0x0010, 0x0010),
+            (0x0010, 0x0080, 0x0080, 0x0080),
+            (0x0020, 0x0001, 0x0100, 0x0100),
+            (0x0040, 0x0002, 0x0001, 0x0200),
+            (0x0080, 0x0004, 0x0002, 0x0001),
+            (0x0100, 0x0100, 0x0004, 0x0002),
+            (0x0200, 0x0200, 0x0200, 0x0004),
+            (0x0400, 0x0400, 0x0400, 0x0400),
+            (0x1234, 0x12A1, 0x13A0, 0x11A4),
+        ];
+
+        for (addr, bpp2, bpp4, bpp8) in cases {
+            assert_eq!(remap_2bpp(addr), bpp2, "2bpp {:04X}", addr);
+            assert_eq!(remap_4bpp(addr), bpp4, "4bpp {:04X}", addr);
+            assert_eq!(remap_8bpp(addr), bpp8, "8bpp {:04X}", addr);
+        }
+
+        // Each is a shuffle of the bits, so no two addresses end up at the
+        // same word.
+        for remap in [remap_2bpp, remap_4bpp, remap_8bpp] {
+            let mut seen = vec![false; 0x10000];
+
+            for addr in 0..=0xFFFF {
+                assert!(!std::mem::replace(&mut seen[remap(addr) as usize], true));
+            }
+        }
+    }
+
+    #[test]
+    fn vram_upload_through_2bpp_remap() {
+        let mut ppu = Ppu::new();
+
+        // Increment after the high byte, by one word, with the 2bpp remap.
+        ppu.write(0x2115, 0x84);
+        ppu.write(0x2116, 0x00);
+        ppu.write(0x2117, 0x10);
+
+        for n in 0..33 {
+            ppu.write(0x2118, n);
+            ppu.write(0x2119, 0xA0);
+        }
+
+        // Consecutive words go down a column, eight words apart, and the
+        // 33rd starts the next row.
+        for n in 0..32 {
+            assert_eq!(ppu.vram[0x1000 + n * 8], 0xA000 | n as u16);
+        }
+
+        assert_eq!(ppu.vram[0x1001], 0xA020);
+        assert_eq!(ppu.vram[0x1002], 0);
+        assert_eq!(ppu.vram_addr, 0x1021);
+
+        // Reading goes through the same remap.
+        ppu.write(0x2116, 0x03);
+        assert_eq!(ppu.read(0x2139), 0x03);
+        assert_eq!(ppu.read(0x213A), 0xA0);
+    }
+
+    #[test]
+    fn vram_reads_lag_through_prefetch() {
+        let mut ppu = Ppu::new();
+        ppu.vram[0x2000] = 0x1111;
+        ppu.vram[0x2001] = 0x2222;
+        ppu.vram[0x2002] = 0x3333;
+
+        // Setting the address fills the buffer, and each read of the byte
+        // that increments refills it before the address moves on.
+        ppu.write(0x2115, 0x80);
+        write_all(&mut ppu, 0x2116, &[0x00]);
+        write_all(&mut ppu, 0x2117, &[0x20]);
+
+        let words: Vec<u16> = (0..3)
+            .map(|_| ppu.read(0x2139) as u16 | (ppu.read(0x213A) as u16) << 8)
+            .collect();
+        assert_eq!(words, [0x1111, 0x1111, 0x2222]);
+        assert_eq!(ppu.vram_addr, 0x2003);
+
+        // Reading the low byte doesn't increment with VMAIN bit 7 set.
+        assert_eq!(ppu.read(0x2139), 0x33);
+        assert_eq!(ppu.read(0x2139), 0x33);
+        assert_eq!(ppu.vram_addr, 0x2003);
+    }
+
+    // Runs the PPU forward by a number of lines and dots.
+    fn advance(ppu: &mut Ppu, lines: u64, dots: u64) {
+        ppu.step(lines * MASTER_CYCLES_PER_LINE + dots * 4);
+    }
+
+    #[test]
+    fn counters_latch_on_slhv_read() {
+        let mut ppu = Ppu::new();
+        advance(&mut ppu, 100, 50);
+
+        assert_eq!(ppu.read(0x213F) & 0x40, 0);
+
+        ppu.read(0x2137);
+
+        // The latch doesn't follow the counters once it's been taken.
+        advance(&mut ppu, 1, 0);
+
+        assert_eq!(ppu.read(0x213C), 50);
+        assert_eq!(ppu.read(0x213C) & 0x01, 0);
+        assert_eq!(ppu.read(0x213D), 100);
+        assert_eq!(ppu.read(0x213D) & 0x01, 0);
+    }
+
+    #[test]
+    fn counters_are_nine_bits() {
+        let mut ppu = Ppu::new();
+        advance(&mut ppu, 0x105, 0x12C);
+        ppu.read(0x2137);
+
+        // Only bit 0 of the high byte is driven; the rest is open bus.
+        assert_eq!(ppu.read(0x213C), 0x2C);
+        assert_eq!(ppu.read(0x213C) & 0x01, 0x01);
+        assert_eq!(ppu.read(0x213D), 0x05);
+        assert_eq!(ppu.read(0x213D) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn low_high_toggle_wraps() {
+        let mut ppu = Ppu::new();
+        advance(&mut ppu, 0x105, 0x12C);
+        ppu.read(0x2137);
+
+        let reads: Vec<u8> = (0..4).map(|_| ppu.read(0x213C) & 0x01).collect();
+        assert_eq!(reads, [0x00, 0x01, 0x00, 0x01]);
+
+        // Peeking leaves the toggle where it was.
+        assert_eq!(ppu.peek(0x213C), 0x2C);
+        assert_eq!(ppu.read(0x213C), 0x2C);
+    }
+
+    #[test]
+    fn stat78_read_resets_toggles_and_flag() {
+        let mut ppu = Ppu::new();
+        advance(&mut ppu, 0x105, 0x12C);
+        ppu.read(0x2137);
+
+        assert_eq!(ppu.read(0x213C), 0x2C);
+        assert_eq!(ppu.read(0x213D), 0x05);
+
+        assert_eq!(ppu.read(0x213F) & 0x40, 0x40);
+        assert_eq!(ppu.read(0x213F) & 0x40, 0);
+
+        // Both counters start again from the low byte.
+        assert_eq!(ppu.read(0x213C), 0x2C);
+        assert_eq!(ppu.read(0x213D), 0x05);
+    }
+
+    #[test]
+    fn write_only_registers_read_ppu1_bus() {
+        let mut ppu = Ppu::new();
+        ppu.oam[0] = 0xA5;
+
+        // OAMDATAREAD leaves its value on PPU1's bus.
+        assert_eq!(ppu.read(0x2138), 0xA5);
+
+        for addr in [0x2100, 0x2105, 0x2118, 0x2130, 0x2133] {
+            assert_eq!(ppu.read(addr), 0xA5, "{:04X}", addr);
+        }
+
+        // PPU2's registers don't change it.
+        ppu.cgram[0] = 0x1234;
+        assert_eq!(ppu.read(0x213B), 0x34);
+        assert_eq!(ppu.read(0x2100), 0xA5);
+
+        // MPYL does, and so does reading a write-only register itself.
+        assert_eq!(ppu.read(0x2134), 0x00);
+        assert_eq!(ppu.read(0x2100), 0x00);
+        assert_eq!(ppu.peek(0x2100), 0x00);
+    }
+
+    #[test]
+    fn undriven_bits_come_from_their_own_ppu() {
+        let mut ppu = Ppu::new();
+        advance(&mut ppu, 0x105, 0x12C);
+        ppu.read(0x2137);
+
+        // PPU1's bus is all 1s, and PPU2's all 0s, after reading CGRAM's
+        // low byte.
+        ppu.oam[0] = 0xFF;
+        ppu.read(0x2138);
+        ppu.read(0x213B);
+
+        assert_eq!(ppu.read(0x213E) & 0x10, 0x10);
+        assert_eq!(ppu.read(0x213F) & 0x20, 0x00);
+
+        // CGDATAREAD's high byte drives 7 bits, and the counters' 1 bit.
+        ppu.cgram[1] = 0x7FFF;
+        ppu.write(0x2121, 0x01);
+        assert_eq!(ppu.read(0x213B), 0xFF);
+        assert_eq!(ppu.read(0x213B), 0xFF);
+
+        assert_eq!(ppu.read(0x213C), 0x2C);
+        assert_eq!(ppu.read(0x213C), 0x2D);
+        assert_eq!(ppu.read(0x213D), 0x05);
+        assert_eq!(ppu.read(0x213D), 0x05);
+
+        // The last read left $05 on PPU2's bus, which has bit 5 clear, and
+        // STAT77 still sees PPU1's.
+        assert_eq!(ppu.read(0x213F) & 0x20, 0x00);
+        assert_eq!(ppu.read(0x213E) & 0x10, 0x10);
+    }
+
+    // A gradient tile, where each pixel has its own color.
+    fn gradient_pixel(x: usize, y: usize) -> u8 {
+        (1 + x % 8 + (y % 8) * 8) as u8
+    }
+
+    // Mode 3, with BG1 covered in the gradient tile and every CGRAM entry a
+    // different color.
+    fn mosaic_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(0x2100, 0x0F);
+        ppu.write(0x2105, 0x03);
+        ppu.write(0x210B, 0x01);
+        ppu.write(0x212C, 0x01);
+
+        ppu.vram[..0x400].fill(1);
+
+        for y in 0..8 {
+            for plane in 0..4 {
+                let mut word = 0;
+
+                for x in 0..8 {
+                    let color = gradient_pixel(x, y) as u16 >> (plane * 2);
+                    word |= (color & 1) << (7 - x) | (color >> 1 & 1) << (15 - x);
+                }
+
+                ppu.vram[0x1020 + y + plane * 8] = word;
+            }
+        }
+
+        for (i, color) in ppu.cgram.iter_mut().enumerate() {
+            *color = i as u16;
+        }
+
+        ppu
+    }
+
+    #[test]
+    fn mosaic_off() {
+        let ppu = mosaic_ppu();
+
+        for y in 0..8 {
+            for x in 0..16 {
+                assert_eq!(
+                    ppu.bg_pixel(0, 8, x, y),
+                    Some((gradient_pixel(x, y), false))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mosaic_blocks() {
+        let mut ppu = mosaic_ppu();
+        ppu.write(0x2106, 0x31);
+
+        for y in 0..16 {
+            ppu.render_scanline(y);
+
+            for x in 0..32 {
+                let expected = gradient_pixel(x & !3, y & !3);
+                let i = (y * SCREEN_WIDTH + x) * 4;
+
+                assert_eq!(
+                    ppu.framebuffer[i..i + 3],
+                    color::snes_to_rgb(expected as u16),
+                    "pixel {x},{y}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mosaic_is_per_bg() {
+        let mut ppu = mosaic_ppu();
+        ppu.write(0x2106, 0x32);
+
+        assert_eq!(
+            ppu.bg_pixel(0, 8, 3, 3),
+            Some((gradient_pixel(3, 3), false))
+        );
+    }
+
+    #[test]
+    fn mosaic_grid_starts_at_write_line() {
+        let mut ppu = mosaic_ppu();
+
+        // Written during scanline 11, which draws framebuffer row 10.
+        ppu.scanline = 11;
+        ppu.write(0x2106, 0x31);
+
+        for (y, block) in [(10, 10), (12, 10), (13, 10), (14, 14), (17, 14)] {
+            assert_eq!(
+                ppu.bg_pixel(0, 8, 0, y),
+                Some((gradient_pixel(0, block), false))
+            );
+        }
+    }
+
+    #[test]
+    fn mode7_mosaic_is_vertical_only() {
+        let mut ppu = Ppu::new();
+        ppu.bg_mode = 7;
+        ppu.m7_matrix = [0x100, 0, 0, 0x100];
+
+        // The whole Mode 7 map is tile 0, which holds the gradient.
+        for (i, word) in ppu.vram[..64].iter_mut().enumerate() {
+            *word = (gradient_pixel(i % 8, i / 8) as u16) << 8;
+        }
+
+        ppu.write(0x2106, 0x31);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                // The matrix uses the scanline, which is one below the row.
+                let expected = gradient_pixel(x, (y & !3) + 1);
+                assert_eq!(ppu.bg_pixel(0, 8, x, y), Some((expected, false)));
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Converts a 15-bit BGR color to 8-bit RGB. Each channel's top bits are
+// copied into the bottom, so that full intensity comes out as 255 rather
+// than 248.
+pub fn snes_to_rgb(color: u16) -> [u8; 3] {
+    channels(color).map(|c| c << 3 | c >> 2)
+}
+
+pub fn brightness(rgb: [u8; 3], brightness: u8) -> [u8; 3] {
+    rgb.map(|c| (c as u16 * (brightness as u16 + 1) / 16) as u8)
+}
+
+// Adds or subtracts two 15-bit colors channel by channel, clamping each
+// channel to the 5-bit range.
+pub fn blend(main: u16, sub: u16, subtract: bool, half: bool) -> u16 {
+    let main = channels(main);
+    let sub = channels(sub);
+
+    let mut result = [0; 3];
+
+    for i in 0..3 {
+        let value = if subtract {
+            main[i].saturating_sub(sub[i])
+        } else {
+            main[i] + sub[i]
+        };
+
+        result[i] = if half { value >> 1 } else { value.min(31) };
+    }
+
+    from_channels(result)
+}
+
+fn channels(color: u16) -> [u8; 3] {
+    [
+        (color & 0x1F) as u8,
+        ((color >> 5) & 0x1F) as u8,
+        ((color >> 10) & 0x1F) as u8,
+    ]
+}
+
+fn from_channels([r, g, b]: [u8; 3]) -> u16 {
+    r as u16 | (g as u16) << 5 | (b as u16) << 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_intensity() {
+        assert_eq!(snes_to_rgb(0x7FFF), [255, 255, 255]);
+        assert_eq!(snes_to_rgb(0x001F), [255, 0, 0]);
+        assert_eq!(snes_to_rgb(0x03E0), [0, 255, 0]);
+        assert_eq!(snes_to_rgb(0x7C00), [0, 0, 255]);
+        assert_eq!(snes_to_rgb(0x0010), [132, 0, 0]);
+    }
+
+    #[test]
+    fn black_and_gray() {
+        assert_eq!(snes_to_rgb(0x0000), [0, 0, 0]);
+
+        // 15 of 31 is just under half, and so is 123 of 255, where shifting
+        // alone would give 120.
+        assert_eq!(snes_to_rgb(0x3DEF), [123, 123, 123]);
+        assert_eq!(snes_to_rgb(0x4210), [132, 132, 132]);
+
+        // Every step is the same size, give or take rounding.
+        for c in 0..31u16 {
+            let [step, _, _] = snes_to_rgb(c + 1);
+            let [last, _, _] = snes_to_rgb(c);
+            assert!(matches!(step - last, 8 | 9), "{}", c);
+        }
+    }
+
+    // Brightness scales each channel by (brightness + 1) / 16.
+    #[test]
+    fn brightness_scaling() {
+        let white = [255, 255, 255];
+
+        assert_eq!(brightness(white, 15), [255, 255, 255]);
+        assert_eq!(brightness(white, 7), [127, 127, 127]);
+        assert_eq!(brightness(white, 0), [15, 15, 15]);
+        assert_eq!(brightness([200, 100, 16], 3), [50, 25, 4]);
+        assert_eq!(brightness([0, 0, 0], 15), [0, 0, 0]);
+    }
+
+    #[test]
+    fn blending() {
+        assert_eq!(blend(0x0010, 0x0008, false, false), 0x0018);
+        assert_eq!(blend(0x001F, 0x001F, false, false), 0x001F);
+        assert_eq!(blend(0x001F, 0x001F, false, true), 0x001F);
+        assert_eq!(blend(0x0008, 0x0010, true, false), 0x0000);
+        assert_eq!(blend(0x7FFF, 0x0421, true, false), 0x7BDE);
+    }
+
+    #[test]
+    fn add_with_halve() {
+        // (16 + 8) / 2 = 12, (4 + 9) / 2 = 6, (31 + 31) / 2 = 31
+        assert_eq!(blend(0x7C90, 0x7D28, false, true), 0x7CCC);
+        assert_eq!(blend(0x0000, 0x0001, false, true), 0x0000);
+    }
+
+    #[test]
+    fn subtract_clamps_to_zero() {
+        // 4 - 10 = 0, 20 - 5 = 15, 31 - 0 = 31
+        assert_eq!(blend(0x7E84, 0x00AA, true, false), 0x7DE0);
+        assert_eq!(blend(0x0000, 0x7FFF, true, false), 0x0000);
+
+        // Halving happens after clamping.
+        assert_eq!(blend(0x7E84, 0x00AA, true, true), 0x3CE0);
+    }
+}
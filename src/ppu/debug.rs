@@ -0,0 +1,201 @@
+use super::color::snes_to_rgb;
+use super::Ppu;
+
+// Tiles per row in the tile sheet.
+const SHEET_TILES: usize = 16;
+
+// An RGBA image, for viewing what's in VRAM outside of the normal frame.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: u16) {
+        let [r, g, b] = snes_to_rgb(color);
+        let i = (y * self.width + x) * 4;
+
+        self.pixels[i..i + 4].copy_from_slice(&[r, g, b, 0xFF]);
+    }
+}
+
+impl Ppu {
+    // Draws every tile in VRAM as if it had the given bit depth, 16 tiles to
+    // a row, using one palette of that depth's size for the colors.
+    pub fn render_tile_sheet(&self, depth: u8, palette: u8) -> Image {
+        let tile_words = depth as usize * 4;
+        let tiles = self.vram.len() / tile_words;
+
+        let mut image = Image::new(SHEET_TILES * 8, tiles.div_ceil(SHEET_TILES) * 8);
+
+        let base = match depth {
+            8 => 0,
+            _ => (palette as usize) << depth,
+        };
+
+        for tile in 0..tiles {
+            let tile_addr = (tile * tile_words) as u16;
+            let (tile_x, tile_y) = (tile % SHEET_TILES * 8, tile / SHEET_TILES * 8);
+
+            for y in 0..8 {
+                for x in 0..8 {
+                    let color = self.tile_pixel(tile_addr, depth, x, y) as usize;
+                    let color = self.cgram[(base + color) & 0xFF];
+
+                    image.set(tile_x + x as usize, tile_y + y as usize, color);
+                }
+            }
+        }
+
+        image
+    }
+
+    // Draws the whole of a BG's tilemap, with its current tile size,
+    // character base and screen base, but without scrolling. Transparent
+    // pixels show the backdrop. This is None if the BG doesn't exist in the
+    // current mode, or in mode 7, which uses its own format.
+    pub fn render_tilemap(&self, bg: usize) -> Option<Image> {
+        let depth = self.bg_depths()[bg];
+
+        if depth == 0 || self.bg_mode & 0b111 == 7 {
+            return None;
+        }
+
+        let tile_size = self.tile_size(bg) as usize;
+
+        let tilemap = self.bg_tilemap[bg];
+        let columns = if tilemap & 1 != 0 { 64 } else { 32 };
+        let rows = if tilemap & 2 != 0 { 64 } else { 32 };
+
+        let mut image = Image::new(columns * tile_size, rows * tile_size);
+
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let entry = self.tilemap_entry(bg, (x / tile_size) as u16, (y / tile_size) as u16);
+                let (fine_x, fine_y) = ((x % tile_size) as u16, (y % tile_size) as u16);
+
+                let index = self.entry_pixel(bg, depth, entry, fine_x, fine_y);
+                image.set(x, y, self.cgram[index as usize]);
+            }
+        }
+
+        Some(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 4bpp tile with colors 0-7 along the first row, 8-15 along the
+    // second, and the left half of the last row in color 15.
+    #[rustfmt::skip]
+    const TILE: [u16; 16] = [
+        // Planes 0 and 1
+        0x3355, 0x3355, 0, 0, 0, 0, 0, 0xF0F0,
+        // Planes 2 and 3
+        0x000F, 0xFF0F, 0, 0, 0, 0, 0, 0xF0F0,
+    ];
+
+    #[rustfmt::skip]
+    const PIXELS: [[u8; 8]; 8] = [
+        [0, 1, 2, 3, 4, 5, 6, 7],
+        [8, 9, 10, 11, 12, 13, 14, 15],
+        [0; 8],
+        [0; 8],
+        [0; 8],
+        [0; 8],
+        [0; 8],
+        [15, 15, 15, 15, 0, 0, 0, 0],
+    ];
+
+    // Tile 1 is the hand-made one, and palette 2 is 16 shades of gray, with
+    // a red backdrop.
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.vram[16..32].copy_from_slice(&TILE);
+
+        ppu.cgram[0] = 0x001F;
+        for c in 0..16 {
+            ppu.cgram[32 + c] = c as u16 * 0x0421;
+        }
+
+        ppu
+    }
+
+    fn pixel(image: &Image, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * image.width + x) * 4;
+        [image.pixels[i], image.pixels[i + 1], image.pixels[i + 2]]
+    }
+
+    #[test]
+    fn decodes_a_4bpp_tile() {
+        let ppu = ppu();
+
+        for (y, row) in PIXELS.iter().enumerate() {
+            for (x, &color) in row.iter().enumerate() {
+                assert_eq!(ppu.tile_pixel(16, 4, x as u16, y as u16), color, "{x},{y}");
+            }
+        }
+    }
+
+    #[test]
+    fn tile_sheet() {
+        let image = ppu().render_tile_sheet(4, 2);
+
+        // 2048 tiles, 16 to a row.
+        assert_eq!((image.width, image.height), (128, 1024));
+
+        for (y, row) in PIXELS.iter().enumerate() {
+            for (x, &color) in row.iter().enumerate() {
+                let gray = color as u16 * 0x0421;
+                assert_eq!(pixel(&image, 8 + x, y), snes_to_rgb(gray), "{x},{y}");
+            }
+        }
+
+        // Color 0 is drawn from the palette like any other.
+        assert_eq!(pixel(&image, 0, 0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn tilemap() {
+        let mut ppu = ppu();
+        ppu.write(0x2105, 0x01);
+        ppu.write(0x2107, 0x40);
+
+        // Tile 1 in palette 2, then again flipped horizontally.
+        ppu.vram[0x4000] = 0x0801;
+        ppu.vram[0x4001] = 0x4801;
+
+        let image = ppu.render_tilemap(0).unwrap();
+        assert_eq!((image.width, image.height), (256, 256));
+
+        let red = snes_to_rgb(0x001F);
+
+        for (y, row) in PIXELS.iter().enumerate() {
+            for (x, &color) in row.iter().enumerate() {
+                let expected = match color {
+                    0 => red,
+                    _ => snes_to_rgb(color as u16 * 0x0421),
+                };
+
+                assert_eq!(pixel(&image, x, y), expected, "{x},{y}");
+                assert_eq!(pixel(&image, 15 - x, y), expected, "flipped {x},{y}");
+            }
+        }
+
+        // BG4 doesn't exist in mode 1, and mode 7 has its own format.
+        assert!(ppu.render_tilemap(3).is_none());
+        ppu.write(0x2105, 0x07);
+        assert!(ppu.render_tilemap(0).is_none());
+    }
+}
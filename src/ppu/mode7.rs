@@ -0,0 +1,221 @@
+use super::Ppu;
+
+impl Ppu {
+    pub(super) fn mode7_pixel(&self, x: usize, y: usize) -> Option<u8> {
+        let (hofs, vofs) = self.bg_scroll(0);
+
+        // The matrix is evaluated using the scanline number, which is one
+        // ahead of the framebuffer row.
+        let mut screen_x = x as i32;
+        let mut screen_y = y as i32 + 1;
+
+        if self.m7_select & 0x01 != 0 {
+            screen_x = 255 - screen_x;
+        }
+
+        if self.m7_select & 0x02 != 0 {
+            screen_y = 255 - screen_y;
+        }
+
+        let (px, py) = mode7_transform(
+            self.m7_matrix.map(|n| n as i16),
+            self.m7_center.map(sign_extend_13),
+            [sign_extend_13(hofs), sign_extend_13(vofs)],
+            screen_x,
+            screen_y,
+        );
+
+        let outside = !(0..1024).contains(&px) || !(0..1024).contains(&py);
+
+        let tile = match self.m7_select >> 6 {
+            2 | 3 if outside => {
+                if self.m7_select >> 6 == 2 {
+                    return None;
+                }
+
+                0
+            }
+
+            _ => {
+                let tile_x = (px >> 3) & 127;
+                let tile_y = (py >> 3) & 127;
+
+                self.vram[(tile_y * 128 + tile_x) as usize] & 0xFF
+            }
+        };
+
+        let pixel = tile * 64 + ((py & 7) * 8 + (px & 7)) as u16;
+
+        // TODO: EXTBG
+        match (self.vram[pixel as usize] >> 8) as u8 {
+            0 => None,
+            color => Some(color),
+        }
+    }
+}
+
+pub fn sign_extend_13(value: u16) -> i32 {
+    ((value << 3) as i16 >> 3) as i32
+}
+
+// Clips a 14-bit intermediate to the signed 10-bit range the hardware keeps.
+fn clip(value: i32) -> i32 {
+    if value & 0x2000 != 0 {
+        value | !0x3FF
+    } else {
+        value & 0x3FF
+    }
+}
+
+// Maps a screen coordinate to a position on the 1024x1024 Mode 7 plane.
+//
+// The matrix entries are 8.8 fixed point, and the hardware drops the low six
+// bits of each of the per-line products before summing them.
+pub fn mode7_transform(
+    [a, b, c, d]: [i16; 4],
+    [center_x, center_y]: [i32; 2],
+    [hofs, vofs]: [i32; 2],
+    screen_x: i32,
+    screen_y: i32,
+) -> (i32, i32) {
+    let (a, b, c, d) = (a as i32, b as i32, c as i32, d as i32);
+
+    let dx = clip(hofs - center_x);
+    let dy = clip(vofs - center_y);
+
+    let origin_x = ((a * dx) & !63) + ((b * dy) & !63) + ((b * screen_y) & !63) + (center_x << 8);
+    let origin_y = ((c * dx) & !63) + ((d * dy) & !63) + ((d * screen_y) & !63) + (center_y << 8);
+
+    (
+        (origin_x + a * screen_x) >> 8,
+        (origin_y + c * screen_x) >> 8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: [i16; 4] = [0x100, 0, 0, 0x100];
+
+    #[test]
+    fn offsets_are_13_bit_signed() {
+        assert_eq!(sign_extend_13(0x0FFF), 4095);
+        assert_eq!(sign_extend_13(0x1000), -4096);
+        assert_eq!(sign_extend_13(0x1FFF), -1);
+
+        // Bits above the 13th are ignored.
+        assert_eq!(sign_extend_13(0xE005), 5);
+    }
+
+    #[test]
+    fn identity_matrix() {
+        let transform = |x, y| mode7_transform(IDENTITY, [0, 0], [0, 0], x, y);
+
+        assert_eq!(transform(0, 0), (0, 0));
+        assert_eq!(transform(10, 20), (10, 20));
+        assert_eq!(transform(255, 223), (255, 223));
+    }
+
+    #[test]
+    fn scrolling() {
+        assert_eq!(mode7_transform(IDENTITY, [0, 0], [16, 8], 10, 20), (26, 28));
+        assert_eq!(mode7_transform(IDENTITY, [0, 0], [-8, 0], 0, 0), (-8, 0));
+    }
+
+    #[test]
+    fn scroll_wraps_at_ten_bits() {
+        // The difference between the offset and the center is clipped to
+        // 10 bits, so scrolling by a whole plane is the same as not scrolling.
+        assert_eq!(
+            mode7_transform(IDENTITY, [0, 0], [1024, 0], 10, 20),
+            mode7_transform(IDENTITY, [0, 0], [0, 0], 10, 20),
+        );
+    }
+
+    #[test]
+    fn scaling_around_center() {
+        // A = D = 2.0 shows the plane at half size, anchored on the center.
+        let transform = |x, y| mode7_transform([0x200, 0, 0, 0x200], [128, 128], [0, 0], x, y);
+
+        assert_eq!(transform(128, 128), (128, 128));
+        assert_eq!(transform(129, 128), (130, 128));
+        assert_eq!(transform(128, 130), (128, 132));
+        assert_eq!(transform(0, 0), (-128, -128));
+    }
+
+    #[test]
+    fn rotation() {
+        // A quarter turn: B = 1.0, C = -1.0.
+        let transform = |x, y| mode7_transform([0, 0x100, -0x100, 0], [0, 0], [0, 0], x, y);
+
+        assert_eq!(transform(10, 20), (20, -10));
+        assert_eq!(transform(0, 5), (5, 0));
+    }
+
+    #[test]
+    fn fractional_steps() {
+        // A = 0.5 advances one texel every other pixel.
+        let transform = |x| mode7_transform([0x80, 0, 0, 0x100], [0, 0], [0, 0], x, 0).0;
+
+        assert_eq!(
+            (0..6).map(transform).collect::<Vec<_>>(),
+            [0, 0, 1, 1, 2, 2]
+        );
+    }
+
+    // Tile 0 is filled with color 1 and tile 1 with color 2. The top-right
+    // corner of the map holds tile 1, and everything else holds tile 0.
+    fn ppu(screen_over: u8) -> Ppu {
+        let mut ppu = Ppu::new();
+
+        ppu.bg_mode = 7;
+        ppu.m7_select = screen_over << 6;
+        ppu.m7_matrix = IDENTITY.map(|n| n as u16);
+
+        for (i, word) in ppu.vram[..128].iter_mut().enumerate() {
+            *word = if i < 64 { 0x0100 } else { 0x0200 };
+        }
+
+        ppu.vram[127] |= 0x01;
+
+        // Scroll 8 pixels left of the plane.
+        ppu.m7_hofs = 0x1FF8;
+
+        ppu
+    }
+
+    #[test]
+    fn screen_over_wraps() {
+        let ppu = ppu(0);
+
+        assert_eq!(ppu.mode7_pixel(0, 0), Some(2));
+        assert_eq!(ppu.mode7_pixel(8, 0), Some(1));
+    }
+
+    #[test]
+    fn screen_over_transparent() {
+        let ppu = ppu(2);
+
+        assert_eq!(ppu.mode7_pixel(0, 0), None);
+        assert_eq!(ppu.mode7_pixel(8, 0), Some(1));
+    }
+
+    #[test]
+    fn screen_over_tile_0() {
+        let ppu = ppu(3);
+
+        assert_eq!(ppu.mode7_pixel(0, 0), Some(1));
+        assert_eq!(ppu.mode7_pixel(8, 0), Some(1));
+    }
+
+    #[test]
+    fn screen_flip() {
+        let mut ppu = ppu(2);
+        ppu.m7_select |= 0x01;
+
+        // Flipped, the left edge of the screen is the right edge of the plane.
+        assert_eq!(ppu.mode7_pixel(255, 0), None);
+        assert_eq!(ppu.mode7_pixel(0, 0), Some(1));
+    }
+}
@@ -0,0 +1,316 @@
+use super::{Ppu, SCREEN_WIDTH};
+
+const SPRITE_SIZES: [[(u16, u16); 2]; 8] = [
+    [(8, 8), (16, 16)],
+    [(8, 8), (32, 32)],
+    [(8, 8), (64, 64)],
+    [(16, 16), (32, 32)],
+    [(16, 16), (64, 64)],
+    [(32, 32), (64, 64)],
+    [(16, 32), (32, 64)],
+    [(16, 32), (32, 32)],
+];
+
+const MAX_SPRITES_PER_LINE: usize = 32;
+const MAX_TILES_PER_LINE: usize = 34;
+
+#[derive(Clone, Copy)]
+pub struct ObjPixel {
+    pub color: u8,
+    pub priority: u8,
+}
+
+struct Sprite {
+    x: i16,
+    y: u8,
+    tile: u16,
+    attr: u8,
+    width: u16,
+    height: u16,
+}
+
+impl Ppu {
+    fn sprite(&self, index: usize) -> Sprite {
+        let entry = &self.oam[index * 4..index * 4 + 4];
+        let high = self.oam[0x200 + index / 4] >> ((index % 4) * 2);
+
+        let x = entry[0] as i16 | ((high & 1) as i16) << 8;
+        let (width, height) =
+            SPRITE_SIZES[(self.obj_select >> 5) as usize][(high >> 1) as usize & 1];
+
+        Sprite {
+            // X is a 9-bit signed value, so 256-511 are off the left edge.
+            x: if x >= 256 { x - 512 } else { x },
+            y: entry[1],
+            tile: entry[2] as u16 | ((entry[3] & 1) as u16) << 8,
+            attr: entry[3],
+            width,
+            height,
+        }
+    }
+
+    pub(super) fn render_obj_line(&mut self, y: usize) -> [Option<ObjPixel>; SCREEN_WIDTH] {
+        let mut line = [None; SCREEN_WIDTH];
+
+        // TODO: OAM priority rotation
+        let mut in_range = Vec::with_capacity(MAX_SPRITES_PER_LINE);
+
+        for index in 0..128 {
+            let sprite = self.sprite(index);
+
+            let row = (y as u8).wrapping_sub(sprite.y) as u16;
+
+            if row >= sprite.height || sprite.x <= -(sprite.width as i16) || sprite.x >= 256 {
+                continue;
+            }
+
+            if in_range.len() == MAX_SPRITES_PER_LINE {
+                self.range_over = true;
+                break;
+            }
+
+            in_range.push((sprite, row));
+        }
+
+        // Tiles are fetched starting from the last sprite in range, so when the
+        // tile limit is hit it's the lowest-numbered sprites that lose out.
+        let mut tiles = 0;
+
+        let name_base = (self.obj_select as u16 & 0b111) << 13;
+        let name_gap = (((self.obj_select as u16 >> 3) & 0b11) + 1) << 12;
+
+        'sprites: for (sprite, row) in in_range.iter().rev() {
+            let row = if sprite.attr & 0x80 != 0 {
+                sprite.height - 1 - row
+            } else {
+                *row
+            };
+
+            for column in 0..sprite.width / 8 {
+                let tile_x = sprite.x + column as i16 * 8;
+
+                if tile_x <= -8 || tile_x >= 256 {
+                    continue;
+                }
+
+                if tiles == MAX_TILES_PER_LINE {
+                    self.time_over = true;
+                    break 'sprites;
+                }
+
+                tiles += 1;
+
+                let column = if sprite.attr & 0x40 != 0 {
+                    sprite.width / 8 - 1 - column
+                } else {
+                    column
+                };
+
+                // Large sprites wrap within the 16x16 grid of tiles rather
+                // than carrying into the next row or name table.
+                let tile = (sprite.tile & 0x100)
+                    | ((sprite.tile + (row / 8) * 16) & 0xF0)
+                    | ((sprite.tile + column) & 0x0F);
+
+                let mut tile_addr = name_base.wrapping_add((tile & 0xFF) * 16);
+
+                if tile & 0x100 != 0 {
+                    tile_addr = tile_addr.wrapping_add(name_gap);
+                }
+
+                let palette = (sprite.attr >> 1) & 0b111;
+                let priority = (sprite.attr >> 4) & 0b11;
+
+                for fine_x in 0..8 {
+                    let x = tile_x + fine_x;
+
+                    if !(0..SCREEN_WIDTH as i16).contains(&x) {
+                        continue;
+                    }
+
+                    let fine_x = if sprite.attr & 0x40 != 0 {
+                        7 - fine_x
+                    } else {
+                        fine_x
+                    };
+
+                    let color = self.tile_pixel(tile_addr, 4, fine_x as u16, row % 8);
+
+                    if color != 0 {
+                        line[x as usize] = Some(ObjPixel {
+                            color: 128 + palette * 16 + color,
+                            priority,
+                        });
+                    }
+                }
+            }
+        }
+
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A PPU with every sprite moved below the screen, and 8x8/16x16 sprites
+    // with their tiles at the start of VRAM.
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+
+        for index in 0..128 {
+            ppu.oam[index * 4 + 1] = 0xF0;
+        }
+
+        ppu
+    }
+
+    // Fills a 4bpp tile with color 1 wherever `row` has a bit set, left to
+    // right, in every row for which `rows` has a bit set, top to bottom.
+    fn set_tile(ppu: &mut Ppu, tile: usize, rows: u8, row: u8) {
+        for y in 0..8 {
+            let bits = if rows & (0x80 >> y) != 0 { row } else { 0 };
+            ppu.vram[tile * 16 + y] = bits as u16;
+        }
+    }
+
+    fn place(ppu: &mut Ppu, index: usize, x: i16, y: u8, attr: u8, large: bool) {
+        let x = x as u16 & 0x1FF;
+
+        ppu.oam[index * 4] = x as u8;
+        ppu.oam[index * 4 + 1] = y;
+        ppu.oam[index * 4 + 2] = 0;
+        ppu.oam[index * 4 + 3] = attr;
+
+        let shift = (index % 4) * 2;
+        let high = &mut ppu.oam[0x200 + index / 4];
+        *high &= !(0b11 << shift);
+        *high |= ((x >> 8) as u8 | (large as u8) << 1) << shift;
+    }
+
+    fn covered(line: &[Option<ObjPixel>]) -> Vec<usize> {
+        (0..SCREEN_WIDTH).filter(|&x| line[x].is_some()).collect()
+    }
+
+    #[test]
+    fn sprite_at_position() {
+        let mut ppu = ppu();
+        set_tile(&mut ppu, 0, 0xFF, 0xFF);
+        place(&mut ppu, 0, 10, 20, 0b0010_0110, false);
+
+        assert!(covered(&ppu.render_obj_line(19)).is_empty());
+        assert_eq!(
+            covered(&ppu.render_obj_line(20)),
+            (10..18).collect::<Vec<_>>()
+        );
+        assert!(covered(&ppu.render_obj_line(28)).is_empty());
+
+        // Palette 3 and priority 2, from the attributes.
+        let pixel = ppu.render_obj_line(27)[17].unwrap();
+        assert_eq!(pixel.color, 128 + 3 * 16 + 1);
+        assert_eq!(pixel.priority, 2);
+    }
+
+    // Setting X's high bit makes it negative, so the sprite hangs off the
+    // left edge.
+    #[test]
+    fn sprite_straddling_left_edge() {
+        let mut ppu = ppu();
+        set_tile(&mut ppu, 0, 0xFF, 0xFF);
+        place(&mut ppu, 0, -3, 40, 0, false);
+
+        assert_eq!(ppu.oam[0], 0xFD);
+        assert_eq!(ppu.oam[0x200] & 1, 1);
+        assert_eq!(covered(&ppu.render_obj_line(40)), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn sprite_flips() {
+        let mut ppu = ppu();
+
+        // Only the top left pixel is set.
+        set_tile(&mut ppu, 0, 0x80, 0x80);
+
+        place(&mut ppu, 0, 0, 0, 0, false);
+        place(&mut ppu, 1, 20, 0, 0x40, false);
+        place(&mut ppu, 2, 40, 0, 0x80, false);
+        place(&mut ppu, 3, 60, 0, 0xC0, false);
+
+        assert_eq!(covered(&ppu.render_obj_line(0)), vec![0, 27]);
+        assert_eq!(covered(&ppu.render_obj_line(7)), vec![40, 67]);
+    }
+
+    // Lower numbered sprites are drawn in front of higher numbered ones.
+    #[test]
+    fn lower_sprites_in_front() {
+        let mut ppu = ppu();
+        set_tile(&mut ppu, 0, 0xFF, 0xFF);
+        place(&mut ppu, 0, 0, 0, 0x02, false);
+        place(&mut ppu, 1, 4, 0, 0x04, false);
+
+        let line = ppu.render_obj_line(0);
+        assert_eq!(line[5].unwrap().color, 128 + 16 + 1);
+        assert_eq!(line[9].unwrap().color, 128 + 2 * 16 + 1);
+    }
+
+    #[test]
+    fn range_over() {
+        let mut ppu = ppu();
+        set_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        for index in 0..32 {
+            place(&mut ppu, index, index as i16 * 8, 0, 0, false);
+        }
+
+        ppu.render_obj_line(0);
+        assert!(!ppu.range_over);
+
+        // The 33rd sprite on the line is dropped.
+        place(&mut ppu, 32, 0, 0, 0, false);
+
+        ppu.render_obj_line(0);
+        assert!(ppu.range_over);
+        assert!(!ppu.time_over);
+        assert_eq!(ppu.peek(0x213E) & 0xC0, 0x40);
+    }
+
+    #[test]
+    fn time_over() {
+        let mut ppu = ppu();
+        set_tile(&mut ppu, 0, 0xFF, 0xFF);
+
+        // 17 16x16 sprites take 34 tiles, which is as many as there's time
+        // to fetch.
+        for index in 0..17 {
+            place(&mut ppu, index, index as i16 * 8, 0, 0, true);
+        }
+
+        ppu.render_obj_line(0);
+        assert!(!ppu.time_over);
+
+        place(&mut ppu, 17, 200, 0, 0, true);
+
+        let line = ppu.render_obj_line(0);
+        assert!(ppu.time_over);
+        assert!(!ppu.range_over);
+        assert_eq!(ppu.peek(0x213E) & 0xC0, 0x80);
+
+        // Tiles are fetched from the last sprite back, so it's sprite 0's
+        // that get dropped.
+        assert!(line[0].is_none());
+    }
+
+    // Tiles that are off the screen don't count towards the limit.
+    #[test]
+    fn offscreen_tiles_are_not_fetched() {
+        let mut ppu = ppu();
+
+        for index in 0..20 {
+            place(&mut ppu, index, -8, 0, 0, true);
+        }
+
+        ppu.render_obj_line(0);
+        assert!(!ppu.time_over);
+    }
+}
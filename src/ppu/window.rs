@@ -0,0 +1,149 @@
+use super::Ppu;
+
+// The bit in the window masks for the color window, after BG1-4 and OBJ.
+pub const COLOR_WINDOW: u8 = 0x20;
+
+impl Ppu {
+    // Which of the areas that windows apply to are masked at x, with a bit
+    // each for BG1-4, OBJ and the color window, in that order.
+    pub(super) fn window_masks(&self, x: usize) -> u8 {
+        let edges = [
+            (self.window_edges[0], self.window_edges[1]),
+            (self.window_edges[2], self.window_edges[3]),
+        ];
+
+        let logic = u16::from_le_bytes(self.window_logic);
+        let mut masks = 0;
+
+        for area in 0..6 {
+            let select = self.window_select[area / 2] >> ((area % 2) * 4) & 0xF;
+            let logic = (logic >> (area * 2)) as u8 & 3;
+
+            if masked(select, logic, edges, x as u8) {
+                masks |= 1 << area;
+            }
+        }
+
+        masks
+    }
+}
+
+// Whether an area is masked at x. The select bits are the area's nibble of
+// W12SEL/W34SEL/WOBJSEL: bit 1 enables window 1 and bit 0 inverts it, then
+// bits 3 and 2 do the same for window 2. When both are enabled, they're
+// combined with the logic from WBGLOG/WOBJLOG (OR, AND, XOR or XNOR).
+//
+// Each window covers left to right inclusive, so it's empty if left is past
+// right, and inverting that covers the whole line.
+pub fn masked(select: u8, logic: u8, edges: [(u8, u8); 2], x: u8) -> bool {
+    let window = |n: usize| {
+        let (left, right) = edges[n];
+        let enabled = select & (2 << (n * 2)) != 0;
+        let inverted = select & (1 << (n * 2)) != 0;
+
+        enabled.then_some((left..=right).contains(&x) != inverted)
+    };
+
+    match (window(0), window(1)) {
+        (None, None) => false,
+        (Some(one), None) => one,
+        (None, Some(two)) => two,
+        (Some(one), Some(two)) => match logic & 3 {
+            0 => one || two,
+            1 => one && two,
+            2 => one != two,
+            _ => one == two,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Window 1 covers 0-15 and window 2 covers 10-25.
+    const EDGES: [(u8, u8); 2] = [(0, 15), (10, 25)];
+
+    // Whether each of x = 5 (window 1 only), 12 (both), 20 (window 2 only)
+    // and 30 (neither) is masked.
+    fn at_each(select: u8, logic: u8, edges: [(u8, u8); 2]) -> [bool; 4] {
+        [5, 12, 20, 30].map(|x| masked(select, logic, edges, x))
+    }
+
+    #[test]
+    fn disabled() {
+        for logic in 0..4 {
+            assert_eq!(at_each(0b0000, logic, EDGES), [false; 4]);
+            assert_eq!(at_each(0b0101, logic, EDGES), [false; 4]);
+        }
+    }
+
+    #[test]
+    fn one_window() {
+        // The logic only applies when both are on.
+        for logic in 0..4 {
+            assert_eq!(at_each(0b0010, logic, EDGES), [true, true, false, false]);
+            assert_eq!(at_each(0b0011, logic, EDGES), [false, false, true, true]);
+            assert_eq!(at_each(0b1000, logic, EDGES), [false, true, true, false]);
+            assert_eq!(at_each(0b1100, logic, EDGES), [true, false, false, true]);
+        }
+
+        // The edges are inclusive.
+        let edges = [(10, 20), (0, 0)];
+        let covered: Vec<bool> = [9, 10, 20, 21]
+            .iter()
+            .map(|&x| masked(0b0010, 0, edges, x))
+            .collect();
+
+        assert_eq!(covered, [false, true, true, false]);
+    }
+
+    #[test]
+    fn logic() {
+        assert_eq!(at_each(0b1010, 0, EDGES), [true, true, true, false]);
+        assert_eq!(at_each(0b1010, 1, EDGES), [false, true, false, false]);
+        assert_eq!(at_each(0b1010, 2, EDGES), [true, false, true, false]);
+        assert_eq!(at_each(0b1010, 3, EDGES), [false, true, false, true]);
+
+        // With window 1 inverted, it covers 20 and 30 instead.
+        assert_eq!(at_each(0b1011, 1, EDGES), [false, false, true, false]);
+        assert_eq!(at_each(0b1011, 2, EDGES), [false, true, false, true]);
+    }
+
+    #[test]
+    fn left_past_right() {
+        let edges = [(20, 10), (10, 25)];
+
+        for x in [0, 10, 15, 20, 255] {
+            assert!(!masked(0b0010, 0, edges, x));
+            assert!(masked(0b0011, 0, edges, x));
+        }
+
+        // An empty window changes nothing when ORed, and everything when
+        // ANDed.
+        assert_eq!(at_each(0b1010, 0, edges), at_each(0b1000, 0, edges));
+        assert_eq!(at_each(0b1010, 1, edges), [false; 4]);
+    }
+
+    #[test]
+    fn areas() {
+        let mut ppu = Ppu::new();
+
+        // WH0-WH3: window 1 is 10-20, window 2 is 30-40.
+        for (i, edge) in [10, 20, 30, 40].into_iter().enumerate() {
+            ppu.write(0x2126 + i as u16, edge);
+        }
+
+        // BG1 uses window 1, BG2 window 2, BG4 both ANDed (so nothing), OBJ
+        // window 2 inverted and the color window both ORed.
+        ppu.write(0x2123, 0x82);
+        ppu.write(0x2124, 0xA0);
+        ppu.write(0x2125, 0xAC);
+        ppu.write(0x212A, 0x40);
+        ppu.write(0x212B, 0x00);
+
+        assert_eq!(ppu.window_masks(15), 0b11_0001);
+        assert_eq!(ppu.window_masks(35), 0b10_0010);
+        assert_eq!(ppu.window_masks(50), 0b1_0000);
+    }
+}
@@ -0,0 +1,173 @@
+// How many candidates `search list` shows, as the first few passes can
+// leave tens of thousands.
+pub const LIST_LIMIT: usize = 32;
+
+// Whether to treat WRAM as bytes or as little-endian words. Words can start
+// at any address, not just even ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Word,
+}
+
+impl Width {
+    pub fn bytes(self) -> usize {
+        match self {
+            Width::Byte => 1,
+            Width::Word => 2,
+        }
+    }
+}
+
+// A way of narrowing down the candidates. Everything but Equal compares
+// against the value at the last pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Equal(u16),
+    Greater,
+    Less,
+    Changed,
+    Unchanged,
+}
+
+impl Filter {
+    fn keeps(self, before: u16, after: u16) -> bool {
+        match self {
+            Filter::Equal(value) => after == value,
+            Filter::Greater => after > before,
+            Filter::Less => after < before,
+            Filter::Changed => after != before,
+            Filter::Unchanged => after == before,
+        }
+    }
+}
+
+// The classic cheat search: start with every address in WRAM, then keep
+// throwing out the ones that didn't change the way the variable being
+// looked for should have.
+pub struct RamSearch {
+    width: Width,
+
+    // WRAM as it was at the last pass.
+    snapshot: Vec<u8>,
+
+    // Offsets into WRAM, in order.
+    candidates: Vec<u32>,
+}
+
+impl RamSearch {
+    pub fn start(wram: &[u8], width: Width) -> RamSearch {
+        let candidates = (0..=wram.len() - width.bytes()).map(|offset| offset as u32);
+
+        RamSearch {
+            width,
+            snapshot: wram.to_vec(),
+            candidates: candidates.collect(),
+        }
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    pub fn candidates(&self) -> &[u32] {
+        &self.candidates
+    }
+
+    // The value at an offset, in the search's width.
+    pub fn read(&self, wram: &[u8], offset: u32) -> u16 {
+        let offset = offset as usize;
+
+        match self.width {
+            Width::Byte => wram[offset] as u16,
+            Width::Word => u16::from_le_bytes([wram[offset], wram[offset + 1]]),
+        }
+    }
+
+    // The value an offset had at the last pass.
+    pub fn previous(&self, offset: u32) -> u16 {
+        self.read(&self.snapshot, offset)
+    }
+
+    // Keeps the candidates that pass the filter, then takes a new snapshot
+    // for the next pass to compare against. Returns how many are left.
+    pub fn filter(&mut self, wram: &[u8], filter: Filter) -> usize {
+        let mut candidates = std::mem::take(&mut self.candidates);
+
+        candidates.retain(|&offset| filter.keeps(self.previous(offset), self.read(wram, offset)));
+
+        self.candidates = candidates;
+        self.snapshot.copy_from_slice(wram);
+
+        self.candidates.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 bytes of RAM, with a few set to start with.
+    fn ram(values: &[(usize, u8)]) -> Vec<u8> {
+        let mut ram = vec![0; 16];
+
+        for &(offset, value) in values {
+            ram[offset] = value;
+        }
+
+        ram
+    }
+
+    #[test]
+    fn byte_passes() {
+        let before = ram(&[(2, 5), (3, 5), (7, 9), (12, 1)]);
+        let mut search = RamSearch::start(&before, Width::Byte);
+        assert_eq!(search.candidates().len(), 16);
+
+        // 2 and 7 go up, 3 goes down, 12 stays the same.
+        let after = ram(&[(2, 6), (3, 4), (7, 10), (12, 1)]);
+        assert_eq!(search.filter(&after, Filter::Changed), 3);
+        assert_eq!(search.candidates(), [2, 3, 7]);
+
+        // Each pass compares against the one before it, not the start.
+        let after = ram(&[(2, 7), (3, 4), (7, 10), (12, 1)]);
+        assert_eq!(search.filter(&after, Filter::Greater), 1);
+        assert_eq!(search.candidates(), [2]);
+        assert_eq!(search.previous(2), 7);
+
+        let mut search = RamSearch::start(&before, Width::Byte);
+        assert_eq!(search.filter(&before, Filter::Unchanged), 16);
+        assert_eq!(search.filter(&ram(&[(3, 4), (12, 0)]), Filter::Less), 4);
+        assert_eq!(search.candidates(), [2, 3, 7, 12]);
+        assert_eq!(search.filter(&ram(&[(3, 4)]), Filter::Equal(4)), 1);
+        assert_eq!(search.candidates(), [3]);
+    }
+
+    #[test]
+    fn word_passes() {
+        // A word at 4 counting up past $FF, which the bytes alone would
+        // lose track of.
+        let mut search = RamSearch::start(&ram(&[(4, 0xFF)]), Width::Word);
+        assert_eq!(search.candidates().len(), 15);
+        assert_eq!(search.previous(4), 0x00FF);
+
+        let after = ram(&[(5, 0x01)]);
+        assert_eq!(search.read(&after, 4), 0x0100);
+
+        search.filter(&after, Filter::Greater);
+        assert_eq!(search.candidates(), [4, 5]);
+
+        // Words start at odd addresses too.
+        search.filter(&after, Filter::Equal(0x0001));
+        assert_eq!(search.candidates(), [5]);
+        assert_eq!(search.width(), Width::Word);
+    }
+
+    #[test]
+    fn nothing_left() {
+        let mut search = RamSearch::start(&ram(&[]), Width::Byte);
+        assert_eq!(search.filter(&ram(&[]), Filter::Changed), 0);
+        assert_eq!(search.filter(&ram(&[(0, 1)]), Filter::Changed), 0);
+        assert!(search.candidates().is_empty());
+    }
+}
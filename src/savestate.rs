@@ -0,0 +1,211 @@
+use std::io::{self, Read, Write};
+
+use crate::emulator::Emulator;
+
+const MAGIC: &[u8; 8] = b"SNESSAVE";
+
+// Bump this whenever the layout of any of the saved structs changes, as
+// old states won't deserialize correctly afterwards.
+const VERSION: u32 = 17;
+
+// FNV-1a - this is only used to tell whether two blocks of memory match,
+// so it doesn't need to be resistant to tampering.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+// A state starts with a fixed header, followed by the serialized machine
+// state:
+//
+// 0x00  magic
+// 0x08  format version (u32, little endian)
+// 0x0C  checksum of the ROM the state was taken from (u64, little endian)
+//
+// The core only deals in writers and readers, and it's up to the frontend
+// where states are kept.
+pub fn save_to(emulator: &Emulator, mut writer: impl Write) -> io::Result<()> {
+    if !cfg!(feature = "savestate") {
+        return Err(unsupported());
+    }
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&checksum(emulator.mmu.cartridge()).to_le_bytes())?;
+
+    serialize(writer, emulator)
+}
+
+pub fn load_from(emulator: &mut Emulator, mut reader: impl Read) -> io::Result<()> {
+    if !cfg!(feature = "savestate") {
+        return Err(unsupported());
+    }
+
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(invalid("not a save state"));
+    }
+
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+
+    if version != VERSION {
+        return Err(invalid(format!(
+            "save state is version {}, expected {}",
+            version, VERSION
+        )));
+    }
+
+    let mut rom_checksum = [0; 8];
+    reader.read_exact(&mut rom_checksum)?;
+
+    if u64::from_le_bytes(rom_checksum) != checksum(emulator.mmu.cartridge()) {
+        return Err(invalid("save state was taken with a different ROM"));
+    }
+
+    emulator.restore(deserialize(&mut reader)?);
+
+    Ok(())
+}
+
+#[cfg(feature = "savestate")]
+fn serialize<W: Write>(writer: W, emulator: &Emulator) -> io::Result<()> {
+    bincode::serialize_into(writer, emulator).map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "savestate"))]
+fn serialize<W: Write>(_writer: W, _emulator: &Emulator) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(feature = "savestate")]
+fn deserialize<R: Read>(reader: R) -> io::Result<Emulator> {
+    bincode::deserialize_from(reader).map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(not(feature = "savestate"))]
+fn deserialize<R: Read>(_reader: R) -> io::Result<Emulator> {
+    Err(unsupported())
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "save states require the savestate feature",
+    )
+}
+
+#[cfg(all(test, feature = "savestate"))]
+mod tests {
+    use super::*;
+    use crate::mmu::MapMode;
+    use crate::symbols::Symbols;
+    use crate::trace::bsnes_trace_entry;
+
+    // Counts up through WRAM forever, with an NMI handler that counts frames,
+    // so that the CPU, RAM and PPU all have state worth saving.
+    fn emulator(marker: u8) -> Emulator {
+        #[rustfmt::skip]
+        let code = [
+            0x18,             // CLC
+            0xFB,             // XCE
+            0xC2, 0x30,       // REP #$30
+            0xA9, 0x80, 0x00, // LDA #$0080
+            0x8D, 0x00, 0x42, // STA $4200
+            0xA2, 0x00, 0x00, // LDX #$0000
+            // loop:
+            0x1A,             // INC
+            0x9D, 0x00, 0x01, // STA $0100,X
+            0xE8,             // INX
+            0xE8,             // INX
+            0xE0, 0x00, 0x01, // CPX #$0100
+            0xD0, 0xF5,       // BNE loop
+            0xA2, 0x00, 0x00, // LDX #$0000
+            0x80, 0xF0,       // BRA loop
+        ];
+
+        let mut rom = vec![0; 0x8000];
+        rom[..code.len()].copy_from_slice(&code);
+
+        // INC $10, RTI
+        rom[0x40..0x43].copy_from_slice(&[0xE6, 0x10, 0x40]);
+        rom[0x7FEA..0x7FEC].copy_from_slice(&[0x40, 0x80]);
+        rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+        // Tells ROMs apart without changing the code.
+        rom[0x1000] = marker;
+
+        Emulator::new(rom, Some(MapMode::LoRom)).unwrap()
+    }
+
+    // The call stack is only there for debugging, and isn't saved, so the
+    // trace is in a format that leaves it out.
+    fn trace(emulator: &mut Emulator, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| bsnes_trace_entry(&emulator.step_traced().0, &Symbols::new()))
+            .collect()
+    }
+
+    #[test]
+    fn restored_run_matches_straight_run() {
+        let mut straight = emulator(0);
+
+        // Past the first few NMIs.
+        while straight.mmu.peek_u8(0x10) < 3 {
+            straight.step();
+        }
+
+        let mut state = Vec::new();
+        save_to(&straight, &mut state).unwrap();
+
+        let expected = trace(&mut straight, 50_000);
+
+        let mut restored = emulator(0);
+        load_from(&mut restored, state.as_slice()).unwrap();
+
+        let actual = trace(&mut restored, 50_000);
+
+        assert!(expected == actual, "traces differ after restoring");
+        assert_eq!(restored.mmu.wram(), straight.mmu.wram());
+        assert_eq!(restored.stats(), straight.stats());
+    }
+
+    #[test]
+    fn rejects_other_versions_and_roms() {
+        let mut state = Vec::new();
+        save_to(&emulator(0), &mut state).unwrap();
+
+        let error = |state: &[u8], marker| {
+            load_from(&mut emulator(marker), state)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error(&state, 1),
+            "save state was taken with a different ROM"
+        );
+
+        let mut old = state.clone();
+        old[8..12].copy_from_slice(&(VERSION - 1).to_le_bytes());
+        assert_eq!(
+            error(&old, 0),
+            format!(
+                "save state is version {}, expected {}",
+                VERSION - 1,
+                VERSION
+            )
+        );
+
+        assert_eq!(error(b"SNESSAVX", 0), "not a save state");
+        assert_eq!(error(&state[..16], 0), "failed to fill whole buffer");
+    }
+}
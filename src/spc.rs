@@ -0,0 +1,1140 @@
+mod brr;
+mod disasm;
+mod dsp;
+mod timer;
+
+use bitflags::bitflags;
+
+use self::dsp::Dsp;
+use self::timer::{Timer, FAST_PERIOD, SLOW_PERIOD};
+
+const IPL_ROM: [u8; 64] = [
+    0xCD, 0xEF, 0xBD, 0xE8, 0x00, 0xC6, 0x1D, 0xD0, 0xFC, 0x8F, 0xAA, 0xF4, 0x8F, 0xBB, 0xF5, 0x78,
+    0xCC, 0xF4, 0xD0, 0xFB, 0x2F, 0x19, 0xEB, 0xF4, 0xD0, 0xFC, 0x7E, 0xF4, 0xD0, 0x0B, 0xE4, 0xF5,
+    0xCB, 0xF4, 0xD7, 0x00, 0xFC, 0xD0, 0xF3, 0xAB, 0x01, 0x10, 0xEF, 0x7E, 0xF4, 0x10, 0xEB, 0xBA,
+    0xF6, 0xDA, 0x00, 0xBA, 0xF4, 0xC4, 0xF4, 0xDD, 0x5D, 0xD0, 0xDB, 0x1F, 0x00, 0x00, 0xC0, 0xFF,
+];
+
+// Base cycle counts for each opcode. Conditional branches take two more
+// cycles when the branch is taken.
+#[rustfmt::skip]
+const CYCLES: [u8; 256] = [
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 4, 6, 8,
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 6, 5, 2, 2, 4, 6,
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 4, 5, 4,
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 6, 5, 2, 2, 3, 8,
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 4, 6, 6,
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 4, 5, 2, 2, 4, 3,
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 4, 5, 5,
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 3, 6,
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 2, 4, 5,
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 12, 5,
+    3, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 2, 4, 4,
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 3, 4,
+    3, 8, 4, 5, 4, 5, 4, 7, 2, 5, 6, 4, 5, 2, 4, 9,
+    2, 8, 4, 5, 5, 6, 6, 7, 4, 5, 5, 5, 2, 2, 6, 3,
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 4, 5, 3, 4, 3, 4, 3,
+    2, 8, 4, 5, 4, 5, 5, 6, 3, 4, 5, 4, 2, 2, 4, 3,
+];
+
+bitflags! {
+    #[derive(Clone, Copy)]
+    #[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+    pub struct SpcFlags: u8 {
+        const CARRY       = 0b00000001;
+        const ZERO        = 0b00000010;
+        const INTERRUPT   = 0b00000100;
+        const HALF_CARRY  = 0b00001000;
+        const BREAK       = 0b00010000;
+        const DIRECT_PAGE = 0b00100000;
+        const OVERFLOW    = 0b01000000;
+        const NEGATIVE    = 0b10000000;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Immediate,
+    DirectPage,
+    DirectPageX,
+    DirectPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    IndirectX,
+    IndexedIndirect,
+    IndirectIndexed,
+}
+
+#[derive(Clone, Copy)]
+enum AluOp {
+    Or,
+    And,
+    Eor,
+    Cmp,
+    Adc,
+    Sbc,
+}
+
+#[derive(Clone, Copy)]
+enum ShiftOp {
+    Asl,
+    Rol,
+    Lsr,
+    Ror,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spc700 {
+    // Registers
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    pc: u16,
+    psw: SpcFlags,
+
+    ram: Vec<u8>,
+
+    // I/O
+    control: u8,
+    cpu_ports: [u8; 4],
+    apu_ports: [u8; 4],
+
+    dsp_addr: u8,
+    dsp: Dsp,
+
+    timers: [Timer; 3],
+
+    stopped: bool,
+    cycles: u64,
+}
+
+impl Spc700 {
+    pub fn new() -> Spc700 {
+        Spc700 {
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0xEF,
+            pc: 0xFFC0,
+            psw: SpcFlags::empty(),
+
+            ram: vec![0; 0x10000],
+
+            control: 0x80,
+            cpu_ports: [0; 4],
+            apu_ports: [0; 4],
+
+            dsp_addr: 0,
+            dsp: Dsp::new(),
+
+            timers: [
+                Timer::new(SLOW_PERIOD),
+                Timer::new(SLOW_PERIOD),
+                Timer::new(FAST_PERIOD),
+            ],
+
+            stopped: false,
+            cycles: 0,
+        }
+    }
+
+    // The S-CPU's side of the ports at $2140-$2143.
+    pub fn read_port(&self, port: usize) -> u8 {
+        self.apu_ports[port]
+    }
+
+    pub fn write_port(&mut self, port: usize, value: u8) {
+        self.cpu_ports[port] = value;
+    }
+
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.dsp.take_samples()
+    }
+
+    pub fn read_u8(&mut self, addr: u16) -> u8 {
+        match addr {
+            // DSPADDR, DSPDATA
+            0x00F2 => self.dsp_addr,
+            0x00F3 => self.dsp.read(self.dsp_addr),
+
+            0x00F4..=0x00F7 => self.cpu_ports[addr as usize - 0xF4],
+
+            // T0OUT, T1OUT, T2OUT
+            0x00FD..=0x00FF => self.timers[addr as usize - 0xFD].read_counter(),
+
+            0xFFC0..=0xFFFF if self.control & 0x80 != 0 => IPL_ROM[addr as usize - 0xFFC0],
+
+            _ => self.ram[addr as usize],
+        }
+    }
+
+    // Reads memory without triggering any I/O side effects, for debugging.
+    fn peek_u8(&self, addr: u16) -> u8 {
+        match addr {
+            0x00FD..=0x00FF => self.timers[addr as usize - 0xFD].counter(),
+            0xFFC0..=0xFFFF if self.control & 0x80 != 0 => IPL_ROM[addr as usize - 0xFFC0],
+            _ => self.ram[addr as usize],
+        }
+    }
+
+    pub fn store_u8(&mut self, addr: u16, value: u8) {
+        match addr {
+            // CONTROL
+            0x00F1 => {
+                if value & 0x10 != 0 {
+                    self.cpu_ports[0] = 0;
+                    self.cpu_ports[1] = 0;
+                }
+
+                if value & 0x20 != 0 {
+                    self.cpu_ports[2] = 0;
+                    self.cpu_ports[3] = 0;
+                }
+
+                for (i, timer) in self.timers.iter_mut().enumerate() {
+                    timer.set_enabled(value & 1 << i != 0);
+                }
+
+                self.control = value;
+            }
+
+            // DSPADDR, DSPDATA
+            0x00F2 => self.dsp_addr = value,
+            0x00F3 => self.dsp.write(self.dsp_addr, value),
+
+            0x00F4..=0x00F7 => self.apu_ports[addr as usize - 0xF4] = value,
+
+            // T0TARGET, T1TARGET, T2TARGET
+            0x00FA..=0x00FC => self.timers[addr as usize - 0xFA].set_target(value),
+
+            // Writes to the IPL region always go to the underlying RAM.
+            _ => self.ram[addr as usize] = value,
+        }
+    }
+
+    fn read_u16_dp(&mut self, addr: u8) -> u16 {
+        let low = self.read_u8(self.dp(addr));
+        let high = self.read_u8(self.dp(addr.wrapping_add(1)));
+
+        u16::from_le_bytes([low, high])
+    }
+
+    fn store_u16_dp(&mut self, addr: u8, value: u16) {
+        let [low, high] = value.to_le_bytes();
+
+        self.store_u8(self.dp(addr), low);
+        self.store_u8(self.dp(addr.wrapping_add(1)), high);
+    }
+
+    fn fetch_u8(&mut self) -> u8 {
+        let value = self.read_u8(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+
+        value
+    }
+
+    fn fetch_u16(&mut self) -> u16 {
+        let low = self.fetch_u8();
+        let high = self.fetch_u8();
+
+        u16::from_le_bytes([low, high])
+    }
+
+    fn dp(&self, addr: u8) -> u16 {
+        if self.psw.contains(SpcFlags::DIRECT_PAGE) {
+            0x100 | addr as u16
+        } else {
+            addr as u16
+        }
+    }
+
+    fn fetch_addr(&mut self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Immediate => {
+                let addr = self.pc;
+                self.pc = self.pc.wrapping_add(1);
+
+                addr
+            }
+
+            Operand::DirectPage => {
+                let addr = self.fetch_u8();
+                self.dp(addr)
+            }
+
+            Operand::DirectPageX => {
+                let addr = self.fetch_u8().wrapping_add(self.x);
+                self.dp(addr)
+            }
+
+            Operand::DirectPageY => {
+                let addr = self.fetch_u8().wrapping_add(self.y);
+                self.dp(addr)
+            }
+
+            Operand::Absolute => self.fetch_u16(),
+            Operand::AbsoluteX => self.fetch_u16().wrapping_add(self.x as u16),
+            Operand::AbsoluteY => self.fetch_u16().wrapping_add(self.y as u16),
+            Operand::IndirectX => self.dp(self.x),
+
+            Operand::IndexedIndirect => {
+                let ptr = self.fetch_u8().wrapping_add(self.x);
+                self.read_u16_dp(ptr)
+            }
+
+            Operand::IndirectIndexed => {
+                let ptr = self.fetch_u8();
+                self.read_u16_dp(ptr).wrapping_add(self.y as u16)
+            }
+        }
+    }
+
+    fn push_u8(&mut self, value: u8) {
+        self.store_u8(0x100 | self.sp as u16, value);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn push_u16(&mut self, value: u16) {
+        let [low, high] = value.to_le_bytes();
+
+        self.push_u8(high);
+        self.push_u8(low);
+    }
+
+    fn pull_u8(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.read_u8(0x100 | self.sp as u16)
+    }
+
+    fn pull_u16(&mut self) -> u16 {
+        let low = self.pull_u8();
+        let high = self.pull_u8();
+
+        u16::from_le_bytes([low, high])
+    }
+
+    fn set_nz(&mut self, value: u8) {
+        self.psw.set(SpcFlags::NEGATIVE, value & 0x80 != 0);
+        self.psw.set(SpcFlags::ZERO, value == 0);
+    }
+
+    fn set_nz16(&mut self, value: u16) {
+        self.psw.set(SpcFlags::NEGATIVE, value & 0x8000 != 0);
+        self.psw.set(SpcFlags::ZERO, value == 0);
+    }
+
+    fn ya(&self) -> u16 {
+        u16::from_le_bytes([self.a, self.y])
+    }
+
+    fn set_ya(&mut self, value: u16) {
+        [self.a, self.y] = value.to_le_bytes();
+    }
+
+    pub fn tick(&mut self) -> u64 {
+        // SLEEP/STOP halt the processor until reset, but time still passes
+        // for the DSP.
+        let cycles = if self.stopped { 2 } else { self.execute() };
+
+        self.dsp.step(cycles, &self.ram);
+
+        for timer in &mut self.timers {
+            timer.step(cycles);
+        }
+
+        cycles
+    }
+
+    fn execute(&mut self) -> u64 {
+        let start_cycles = self.cycles;
+
+        let opcode = self.fetch_u8();
+        self.cycles += CYCLES[opcode as usize] as u64;
+
+        match opcode {
+            // ALU operations, which follow a regular pattern across the
+            // opcode map.
+            0x04..=0x09
+            | 0x14..=0x19
+            | 0x24..=0x29
+            | 0x34..=0x39
+            | 0x44..=0x49
+            | 0x54..=0x59
+            | 0x64..=0x69
+            | 0x74..=0x79
+            | 0x84..=0x89
+            | 0x94..=0x99
+            | 0xA4..=0xA9
+            | 0xB4..=0xB9 => self.alu_group(opcode),
+
+            // Shifts and rotates
+            0x0B => self.shift_memory(ShiftOp::Asl, Operand::DirectPage),
+            0x0C => self.shift_memory(ShiftOp::Asl, Operand::Absolute),
+            0x1B => self.shift_memory(ShiftOp::Asl, Operand::DirectPageX),
+            0x1C => self.a = self.shift(ShiftOp::Asl, self.a),
+            0x2B => self.shift_memory(ShiftOp::Rol, Operand::DirectPage),
+            0x2C => self.shift_memory(ShiftOp::Rol, Operand::Absolute),
+            0x3B => self.shift_memory(ShiftOp::Rol, Operand::DirectPageX),
+            0x3C => self.a = self.shift(ShiftOp::Rol, self.a),
+            0x4B => self.shift_memory(ShiftOp::Lsr, Operand::DirectPage),
+            0x4C => self.shift_memory(ShiftOp::Lsr, Operand::Absolute),
+            0x5B => self.shift_memory(ShiftOp::Lsr, Operand::DirectPageX),
+            0x5C => self.a = self.shift(ShiftOp::Lsr, self.a),
+            0x6B => self.shift_memory(ShiftOp::Ror, Operand::DirectPage),
+            0x6C => self.shift_memory(ShiftOp::Ror, Operand::Absolute),
+            0x7B => self.shift_memory(ShiftOp::Ror, Operand::DirectPageX),
+            0x7C => self.a = self.shift(ShiftOp::Ror, self.a),
+
+            // Increment and decrement
+            0x8B => self.inc_dec_memory(Operand::DirectPage, -1),
+            0x8C => self.inc_dec_memory(Operand::Absolute, -1),
+            0x9B => self.inc_dec_memory(Operand::DirectPageX, -1),
+            0xAB => self.inc_dec_memory(Operand::DirectPage, 1),
+            0xAC => self.inc_dec_memory(Operand::Absolute, 1),
+            0xBB => self.inc_dec_memory(Operand::DirectPageX, 1),
+            0x9C => {
+                self.a = self.a.wrapping_sub(1);
+                self.set_nz(self.a);
+            }
+            0xBC => {
+                self.a = self.a.wrapping_add(1);
+                self.set_nz(self.a);
+            }
+            0x1D => {
+                self.x = self.x.wrapping_sub(1);
+                self.set_nz(self.x);
+            }
+            0x3D => {
+                self.x = self.x.wrapping_add(1);
+                self.set_nz(self.x);
+            }
+            0xDC => {
+                self.y = self.y.wrapping_sub(1);
+                self.set_nz(self.y);
+            }
+            0xFC => {
+                self.y = self.y.wrapping_add(1);
+                self.set_nz(self.y);
+            }
+
+            // Loads into A
+            0xE8 => self.a = self.load(Operand::Immediate),
+            0xE6 => self.a = self.load(Operand::IndirectX),
+            0xE4 => self.a = self.load(Operand::DirectPage),
+            0xF4 => self.a = self.load(Operand::DirectPageX),
+            0xE5 => self.a = self.load(Operand::Absolute),
+            0xF5 => self.a = self.load(Operand::AbsoluteX),
+            0xF6 => self.a = self.load(Operand::AbsoluteY),
+            0xE7 => self.a = self.load(Operand::IndexedIndirect),
+            0xF7 => self.a = self.load(Operand::IndirectIndexed),
+            0xBF => {
+                self.a = self.load(Operand::IndirectX);
+                self.x = self.x.wrapping_add(1);
+            }
+
+            // Loads into X and Y
+            0xCD => self.x = self.load(Operand::Immediate),
+            0xF8 => self.x = self.load(Operand::DirectPage),
+            0xF9 => self.x = self.load(Operand::DirectPageY),
+            0xE9 => self.x = self.load(Operand::Absolute),
+            0x8D => self.y = self.load(Operand::Immediate),
+            0xEB => self.y = self.load(Operand::DirectPage),
+            0xFB => self.y = self.load(Operand::DirectPageX),
+            0xEC => self.y = self.load(Operand::Absolute),
+
+            // Stores from A
+            0xC6 => self.store(Operand::IndirectX, self.a),
+            0xC4 => self.store(Operand::DirectPage, self.a),
+            0xD4 => self.store(Operand::DirectPageX, self.a),
+            0xC5 => self.store(Operand::Absolute, self.a),
+            0xD5 => self.store(Operand::AbsoluteX, self.a),
+            0xD6 => self.store(Operand::AbsoluteY, self.a),
+            0xC7 => self.store(Operand::IndexedIndirect, self.a),
+            0xD7 => self.store(Operand::IndirectIndexed, self.a),
+            0xAF => {
+                self.store(Operand::IndirectX, self.a);
+                self.x = self.x.wrapping_add(1);
+            }
+
+            // Stores from X and Y
+            0xD8 => self.store(Operand::DirectPage, self.x),
+            0xD9 => self.store(Operand::DirectPageY, self.x),
+            0xC9 => self.store(Operand::Absolute, self.x),
+            0xCB => self.store(Operand::DirectPage, self.y),
+            0xDB => self.store(Operand::DirectPageX, self.y),
+            0xCC => self.store(Operand::Absolute, self.y),
+
+            // Memory to memory
+            0xFA => {
+                let src = self.fetch_addr(Operand::DirectPage);
+                let value = self.read_u8(src);
+                let dest = self.fetch_addr(Operand::DirectPage);
+
+                self.store_u8(dest, value);
+            }
+            0x8F => {
+                let value = self.fetch_u8();
+                let dest = self.fetch_addr(Operand::DirectPage);
+
+                self.store_u8(dest, value);
+            }
+
+            // Register transfers
+            0x7D => {
+                self.a = self.x;
+                self.set_nz(self.a);
+            }
+            0xDD => {
+                self.a = self.y;
+                self.set_nz(self.a);
+            }
+            0x5D => {
+                self.x = self.a;
+                self.set_nz(self.x);
+            }
+            0xFD => {
+                self.y = self.a;
+                self.set_nz(self.y);
+            }
+            0x9D => {
+                self.x = self.sp;
+                self.set_nz(self.x);
+            }
+            0xBD => self.sp = self.x,
+
+            // Compare X and Y
+            0xC8 => self.compare_register(self.x, Operand::Immediate),
+            0x3E => self.compare_register(self.x, Operand::DirectPage),
+            0x1E => self.compare_register(self.x, Operand::Absolute),
+            0xAD => self.compare_register(self.y, Operand::Immediate),
+            0x7E => self.compare_register(self.y, Operand::DirectPage),
+            0x5E => self.compare_register(self.y, Operand::Absolute),
+
+            // 16-bit operations
+            0xBA => {
+                let addr = self.fetch_u8();
+                let value = self.read_u16_dp(addr);
+
+                self.set_ya(value);
+                self.set_nz16(value);
+            }
+            0xDA => {
+                let addr = self.fetch_u8();
+                self.store_u16_dp(addr, self.ya());
+            }
+            0x3A | 0x1A => {
+                let addr = self.fetch_u8();
+                let value = self.read_u16_dp(addr);
+
+                let result = if opcode == 0x3A {
+                    value.wrapping_add(1)
+                } else {
+                    value.wrapping_sub(1)
+                };
+
+                self.store_u16_dp(addr, result);
+                self.set_nz16(result);
+            }
+            0x7A | 0x9A => {
+                let addr = self.fetch_u8();
+                let value = self.read_u16_dp(addr);
+
+                let result = if opcode == 0x7A {
+                    self.psw.remove(SpcFlags::CARRY);
+                    self.adc16(self.ya(), value)
+                } else {
+                    self.psw.insert(SpcFlags::CARRY);
+                    self.adc16(self.ya(), !value)
+                };
+
+                self.set_ya(result);
+            }
+            0x5A => {
+                let addr = self.fetch_u8();
+                let value = self.read_u16_dp(addr);
+                let result = self.ya().wrapping_sub(value);
+
+                self.psw.set(SpcFlags::CARRY, self.ya() >= value);
+                self.set_nz16(result);
+            }
+
+            // Multiply and divide
+            0xCF => {
+                let result = self.y as u16 * self.a as u16;
+
+                self.set_ya(result);
+                self.set_nz(self.y);
+            }
+            0x9E => self.divide(),
+
+            // Decimal adjust
+            0xDF => {
+                if self.psw.contains(SpcFlags::CARRY) || self.a > 0x99 {
+                    self.a = self.a.wrapping_add(0x60);
+                    self.psw.insert(SpcFlags::CARRY);
+                }
+
+                if self.psw.contains(SpcFlags::HALF_CARRY) || self.a & 0x0F > 0x09 {
+                    self.a = self.a.wrapping_add(0x06);
+                }
+
+                self.set_nz(self.a);
+            }
+            0xBE => {
+                if !self.psw.contains(SpcFlags::CARRY) || self.a > 0x99 {
+                    self.a = self.a.wrapping_sub(0x60);
+                    self.psw.remove(SpcFlags::CARRY);
+                }
+
+                if !self.psw.contains(SpcFlags::HALF_CARRY) || self.a & 0x0F > 0x09 {
+                    self.a = self.a.wrapping_sub(0x06);
+                }
+
+                self.set_nz(self.a);
+            }
+
+            // Branches
+            0x2F => self.branch(true),
+            0xF0 => self.branch(self.psw.contains(SpcFlags::ZERO)),
+            0xD0 => self.branch(!self.psw.contains(SpcFlags::ZERO)),
+            0xB0 => self.branch(self.psw.contains(SpcFlags::CARRY)),
+            0x90 => self.branch(!self.psw.contains(SpcFlags::CARRY)),
+            0x70 => self.branch(self.psw.contains(SpcFlags::OVERFLOW)),
+            0x50 => self.branch(!self.psw.contains(SpcFlags::OVERFLOW)),
+            0x30 => self.branch(self.psw.contains(SpcFlags::NEGATIVE)),
+            0x10 => self.branch(!self.psw.contains(SpcFlags::NEGATIVE)),
+
+            // BBS, BBC
+            0x03 | 0x13 | 0x23 | 0x33 | 0x43 | 0x53 | 0x63 | 0x73 | 0x83 | 0x93 | 0xA3 | 0xB3
+            | 0xC3 | 0xD3 | 0xE3 | 0xF3 => {
+                let addr = self.fetch_addr(Operand::DirectPage);
+                let value = self.read_u8(addr);
+                let bit = value & (1 << (opcode >> 5)) != 0;
+
+                self.branch(bit == (opcode & 0x10 == 0));
+            }
+
+            // CBNE
+            0x2E | 0xDE => {
+                let operand = if opcode == 0x2E {
+                    Operand::DirectPage
+                } else {
+                    Operand::DirectPageX
+                };
+
+                let addr = self.fetch_addr(operand);
+                let value = self.read_u8(addr);
+
+                self.branch(self.a != value);
+            }
+
+            // DBNZ
+            0x6E => {
+                let addr = self.fetch_addr(Operand::DirectPage);
+                let value = self.read_u8(addr).wrapping_sub(1);
+
+                self.store_u8(addr, value);
+                self.branch(value != 0);
+            }
+            0xFE => {
+                self.y = self.y.wrapping_sub(1);
+                self.branch(self.y != 0);
+            }
+
+            // Jumps and calls
+            0x5F => self.pc = self.fetch_u16(),
+            0x1F => {
+                let ptr = self.fetch_u16().wrapping_add(self.x as u16);
+                let low = self.read_u8(ptr);
+                let high = self.read_u8(ptr.wrapping_add(1));
+
+                self.pc = u16::from_le_bytes([low, high]);
+            }
+            0x3F => {
+                let addr = self.fetch_u16();
+
+                self.push_u16(self.pc);
+                self.pc = addr;
+            }
+            0x4F => {
+                let addr = self.fetch_u8();
+
+                self.push_u16(self.pc);
+                self.pc = 0xFF00 | addr as u16;
+            }
+            0x01 | 0x11 | 0x21 | 0x31 | 0x41 | 0x51 | 0x61 | 0x71 | 0x81 | 0x91 | 0xA1 | 0xB1
+            | 0xC1 | 0xD1 | 0xE1 | 0xF1 => {
+                let vector = 0xFFDE - (opcode >> 4) as u16 * 2;
+                let low = self.read_u8(vector);
+                let high = self.read_u8(vector + 1);
+
+                self.push_u16(self.pc);
+                self.pc = u16::from_le_bytes([low, high]);
+            }
+            0x0F => {
+                let low = self.read_u8(0xFFDE);
+                let high = self.read_u8(0xFFDF);
+
+                self.push_u16(self.pc);
+                self.push_u8(self.psw.bits());
+
+                self.psw.insert(SpcFlags::BREAK);
+                self.psw.remove(SpcFlags::INTERRUPT);
+                self.pc = u16::from_le_bytes([low, high]);
+            }
+            0x6F => self.pc = self.pull_u16(),
+            0x7F => {
+                self.psw = SpcFlags::from_bits_retain(self.pull_u8());
+                self.pc = self.pull_u16();
+            }
+
+            // Stack
+            0x2D => self.push_u8(self.a),
+            0x4D => self.push_u8(self.x),
+            0x6D => self.push_u8(self.y),
+            0x0D => self.push_u8(self.psw.bits()),
+            0xAE => self.a = self.pull_u8(),
+            0xCE => self.x = self.pull_u8(),
+            0xEE => self.y = self.pull_u8(),
+            0x8E => self.psw = SpcFlags::from_bits_retain(self.pull_u8()),
+
+            // SET1, CLR1
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x82 | 0x92 | 0xA2 | 0xB2
+            | 0xC2 | 0xD2 | 0xE2 | 0xF2 => {
+                let addr = self.fetch_addr(Operand::DirectPage);
+                let value = self.read_u8(addr);
+                let mask = 1 << (opcode >> 5);
+
+                if opcode & 0x10 == 0 {
+                    self.store_u8(addr, value | mask);
+                } else {
+                    self.store_u8(addr, value & !mask);
+                }
+            }
+
+            // TSET1, TCLR1
+            0x0E | 0x4E => {
+                let addr = self.fetch_u16();
+                let value = self.read_u8(addr);
+
+                self.set_nz(self.a.wrapping_sub(value));
+
+                if opcode == 0x0E {
+                    self.store_u8(addr, value | self.a);
+                } else {
+                    self.store_u8(addr, value & !self.a);
+                }
+            }
+
+            // Carry bit operations
+            0x0A | 0x2A | 0x4A | 0x6A | 0x8A | 0xAA => {
+                let (addr, bit) = self.fetch_bit_addr();
+                let value = self.read_u8(addr) & (1 << bit) != 0;
+                let carry = self.psw.contains(SpcFlags::CARRY);
+
+                let result = match opcode {
+                    0x0A => carry | value,
+                    0x2A => carry | !value,
+                    0x4A => carry & value,
+                    0x6A => carry & !value,
+                    0x8A => carry ^ value,
+                    _ => value,
+                };
+
+                self.psw.set(SpcFlags::CARRY, result);
+            }
+            0xCA => {
+                let (addr, bit) = self.fetch_bit_addr();
+                let value = self.read_u8(addr) & !(1 << bit);
+                let carry = self.psw.contains(SpcFlags::CARRY) as u8;
+
+                self.store_u8(addr, value | carry << bit);
+            }
+            0xEA => {
+                let (addr, bit) = self.fetch_bit_addr();
+                let value = self.read_u8(addr);
+
+                self.store_u8(addr, value ^ (1 << bit));
+            }
+
+            // Flags
+            0x60 => self.psw.remove(SpcFlags::CARRY),
+            0x80 => self.psw.insert(SpcFlags::CARRY),
+            0xED => self.psw.toggle(SpcFlags::CARRY),
+            0xE0 => self.psw.remove(SpcFlags::OVERFLOW | SpcFlags::HALF_CARRY),
+            0x20 => self.psw.remove(SpcFlags::DIRECT_PAGE),
+            0x40 => self.psw.insert(SpcFlags::DIRECT_PAGE),
+            0xA0 => self.psw.insert(SpcFlags::INTERRUPT),
+            0xC0 => self.psw.remove(SpcFlags::INTERRUPT),
+
+            // Misc
+            0x00 => {}
+            0x9F => {
+                self.a = self.a.rotate_left(4);
+                self.set_nz(self.a);
+            }
+            0xEF | 0xFF => self.stopped = true,
+        }
+
+        self.cycles - start_cycles
+    }
+
+    fn alu_group(&mut self, opcode: u8) {
+        let op = match opcode >> 5 {
+            0 => AluOp::Or,
+            1 => AluOp::And,
+            2 => AluOp::Eor,
+            3 => AluOp::Cmp,
+            4 => AluOp::Adc,
+            _ => AluOp::Sbc,
+        };
+
+        let high_half = opcode & 0x10 != 0;
+
+        match (opcode & 0x0F, high_half) {
+            (0x09, false) => {
+                let src = self.fetch_addr(Operand::DirectPage);
+                let rhs = self.read_u8(src);
+                let dest = self.fetch_addr(Operand::DirectPage);
+
+                self.alu_memory(op, dest, rhs);
+            }
+
+            (0x08, true) => {
+                let rhs = self.fetch_u8();
+                let dest = self.fetch_addr(Operand::DirectPage);
+
+                self.alu_memory(op, dest, rhs);
+            }
+
+            (0x09, true) => {
+                let rhs = self.read_u8(self.dp(self.y));
+                let dest = self.dp(self.x);
+
+                self.alu_memory(op, dest, rhs);
+            }
+
+            (mode, high_half) => {
+                let operand = match (mode, high_half) {
+                    (0x04, false) => Operand::DirectPage,
+                    (0x05, false) => Operand::Absolute,
+                    (0x06, false) => Operand::IndirectX,
+                    (0x07, false) => Operand::IndexedIndirect,
+                    (0x08, false) => Operand::Immediate,
+                    (0x04, true) => Operand::DirectPageX,
+                    (0x05, true) => Operand::AbsoluteX,
+                    (0x06, true) => Operand::AbsoluteY,
+                    _ => Operand::IndirectIndexed,
+                };
+
+                let addr = self.fetch_addr(operand);
+                let rhs = self.read_u8(addr);
+
+                if let Some(result) = self.alu(op, self.a, rhs) {
+                    self.a = result;
+                }
+            }
+        }
+    }
+
+    fn alu_memory(&mut self, op: AluOp, addr: u16, rhs: u8) {
+        let lhs = self.read_u8(addr);
+
+        if let Some(result) = self.alu(op, lhs, rhs) {
+            self.store_u8(addr, result);
+        }
+    }
+
+    // Returns None for comparisons, which only update the flags.
+    fn alu(&mut self, op: AluOp, lhs: u8, rhs: u8) -> Option<u8> {
+        let result = match op {
+            AluOp::Or => lhs | rhs,
+            AluOp::And => lhs & rhs,
+            AluOp::Eor => lhs ^ rhs,
+            AluOp::Adc => self.adc(lhs, rhs),
+            AluOp::Sbc => self.adc(lhs, !rhs),
+            AluOp::Cmp => {
+                self.psw.set(SpcFlags::CARRY, lhs >= rhs);
+                self.set_nz(lhs.wrapping_sub(rhs));
+
+                return None;
+            }
+        };
+
+        self.set_nz(result);
+
+        Some(result)
+    }
+
+    fn adc(&mut self, lhs: u8, rhs: u8) -> u8 {
+        let carry = self.psw.contains(SpcFlags::CARRY) as u16;
+        let result = lhs as u16 + rhs as u16 + carry;
+
+        self.psw.set(SpcFlags::CARRY, result > 0xFF);
+        self.psw.set(
+            SpcFlags::HALF_CARRY,
+            (lhs & 0x0F) as u16 + (rhs & 0x0F) as u16 + carry > 0x0F,
+        );
+        self.psw.set(
+            SpcFlags::OVERFLOW,
+            !(lhs ^ rhs) & (lhs ^ result as u8) & 0x80 != 0,
+        );
+
+        result as u8
+    }
+
+    fn adc16(&mut self, lhs: u16, rhs: u16) -> u16 {
+        // The 16-bit add is performed as two 8-bit adds, so H comes from the
+        // high byte.
+        let [lhs_low, lhs_high] = lhs.to_le_bytes();
+        let [rhs_low, rhs_high] = rhs.to_le_bytes();
+
+        let low = self.adc(lhs_low, rhs_low);
+        let high = self.adc(lhs_high, rhs_high);
+
+        let result = u16::from_le_bytes([low, high]);
+        self.set_nz16(result);
+
+        result
+    }
+
+    fn shift(&mut self, op: ShiftOp, value: u8) -> u8 {
+        let carry = self.psw.contains(SpcFlags::CARRY) as u8;
+
+        let (result, carry_out) = match op {
+            ShiftOp::Asl => (value << 1, value & 0x80 != 0),
+            ShiftOp::Rol => (value << 1 | carry, value & 0x80 != 0),
+            ShiftOp::Lsr => (value >> 1, value & 1 != 0),
+            ShiftOp::Ror => (value >> 1 | carry << 7, value & 1 != 0),
+        };
+
+        self.psw.set(SpcFlags::CARRY, carry_out);
+        self.set_nz(result);
+
+        result
+    }
+
+    fn shift_memory(&mut self, op: ShiftOp, operand: Operand) {
+        let addr = self.fetch_addr(operand);
+        let value = self.read_u8(addr);
+        let result = self.shift(op, value);
+
+        self.store_u8(addr, result);
+    }
+
+    fn inc_dec_memory(&mut self, operand: Operand, amount: i8) {
+        let addr = self.fetch_addr(operand);
+        let value = self.read_u8(addr).wrapping_add_signed(amount);
+
+        self.store_u8(addr, value);
+        self.set_nz(value);
+    }
+
+    fn load(&mut self, operand: Operand) -> u8 {
+        let addr = self.fetch_addr(operand);
+        let value = self.read_u8(addr);
+
+        self.set_nz(value);
+
+        value
+    }
+
+    fn store(&mut self, operand: Operand, value: u8) {
+        let addr = self.fetch_addr(operand);
+        self.store_u8(addr, value);
+    }
+
+    fn compare_register(&mut self, lhs: u8, operand: Operand) {
+        let addr = self.fetch_addr(operand);
+        let rhs = self.read_u8(addr);
+
+        self.alu(AluOp::Cmp, lhs, rhs);
+    }
+
+    fn fetch_bit_addr(&mut self) -> (u16, u8) {
+        let operand = self.fetch_u16();
+
+        (operand & 0x1FFF, (operand >> 13) as u8)
+    }
+
+    fn divide(&mut self) {
+        // This follows the hardware's algorithm, which gives well-defined
+        // (if odd) results when the quotient doesn't fit in 8 bits.
+        let ya = self.ya() as u32;
+        let x = self.x as u32;
+
+        self.psw
+            .set(SpcFlags::HALF_CARRY, (self.y & 0x0F) >= (self.x & 0x0F));
+        self.psw.set(SpcFlags::OVERFLOW, self.y >= self.x);
+
+        if (self.y as u32) < x << 1 {
+            self.a = (ya / x) as u8;
+            self.y = (ya % x) as u8;
+        } else {
+            self.a = (255 - (ya - (x << 9)) / (256 - x)) as u8;
+            self.y = (x + (ya - (x << 9)) % (256 - x)) as u8;
+        }
+
+        self.set_nz(self.a);
+    }
+
+    fn branch(&mut self, should_branch: bool) {
+        let offset = self.fetch_u8() as i8;
+
+        if should_branch {
+            self.pc = self.pc.wrapping_add_signed(offset as i16);
+            self.cycles += 2;
+        }
+    }
+
+    pub fn register_debug(&self) -> String {
+        format!(
+            "A: {:02X} | X: {:02X} | Y: {:02X} | SP: {:02X} | PC: {:04X} | PSW: {:02X} | In: {:02X} {:02X} {:02X} {:02X} | Out: {:02X} {:02X} {:02X} {:02X}",
+            self.a,
+            self.x,
+            self.y,
+            self.sp,
+            self.pc,
+            self.psw.bits(),
+            self.cpu_ports[0],
+            self.cpu_ports[1],
+            self.cpu_ports[2],
+            self.cpu_ports[3],
+            self.apu_ports[0],
+            self.apu_ports[1],
+            self.apu_ports[2],
+            self.apu_ports[3],
+        )
+    }
+
+    pub fn trace_line(&self) -> String {
+        let bytes = [0, 1, 2].map(|i| self.peek_u8(self.pc.wrapping_add(i)));
+        let (text, len) = disasm::disassemble(bytes, self.pc);
+
+        let hex: Vec<String> = bytes[..len].iter().map(|b| format!("{:02X}", b)).collect();
+
+        format!(
+            "[{:04X}] {:<8} {:<20} {}",
+            self.pc,
+            hex.join(" "),
+            text,
+            self.register_debug()
+        )
+    }
+}
+
+impl Default for Spc700 {
+    fn default() -> Spc700 {
+        Spc700::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs the SPC until a condition holds, failing if it takes longer than
+    // the IPL ROM plausibly could.
+    fn run_until(spc: &mut Spc700, condition: impl Fn(&Spc700) -> bool) {
+        for _ in 0..100_000 {
+            if condition(spc) {
+                return;
+            }
+
+            spc.tick();
+        }
+
+        panic!("timed out at {}", spc.trace_line());
+    }
+
+    #[test]
+    fn ipl_signals_ready() {
+        let mut spc = Spc700::new();
+
+        run_until(&mut spc, |spc| {
+            spc.read_port(0) == 0xAA && spc.read_port(1) == 0xBB
+        });
+    }
+
+    // The boot protocol: announce the destination with $CC, send each byte
+    // with an incrementing counter, then finish by sending the entry point.
+    #[test]
+    fn ipl_uploads_and_runs_program() {
+        // MOV A, #$42; MOV $F7, A; BRA -2
+        let program = [0xE8, 0x42, 0xC4, 0xF7, 0x2F, 0xFE];
+
+        let mut spc = Spc700::new();
+        run_until(&mut spc, |spc| {
+            spc.read_port(0) == 0xAA && spc.read_port(1) == 0xBB
+        });
+
+        spc.write_port(2, 0x00);
+        spc.write_port(3, 0x02);
+        spc.write_port(1, 0x01);
+        spc.write_port(0, 0xCC);
+        run_until(&mut spc, |spc| spc.read_port(0) == 0xCC);
+
+        for (i, &byte) in program.iter().enumerate() {
+            spc.write_port(1, byte);
+            spc.write_port(0, i as u8);
+            run_until(&mut spc, |spc| spc.read_port(0) == i as u8);
+        }
+
+        let end = program.len() as u8 + 1;
+
+        spc.write_port(2, 0x00);
+        spc.write_port(3, 0x02);
+        spc.write_port(1, 0x00);
+        spc.write_port(0, end);
+        run_until(&mut spc, |spc| spc.read_port(0) == end);
+
+        // Each byte is echoed just before it's stored, so the last one is
+        // only certain to have landed once the final command is answered.
+        assert_eq!(spc.ram[0x0200..0x0206], program);
+
+        run_until(&mut spc, |spc| spc.read_port(3) == 0x42);
+    }
+
+    #[test]
+    fn timers_through_registers() {
+        let mut spc = Spc700::new();
+
+        // Timer 0 counts every two 8kHz ticks, and timer 2 every four 64kHz
+        // ones. Timer 1 has a target but isn't enabled.
+        spc.store_u8(0x00FA, 2);
+        spc.store_u8(0x00FB, 1);
+        spc.store_u8(0x00FC, 4);
+        spc.store_u8(0x00F1, 0x05);
+
+        // A stopped SPC takes two cycles a tick, which makes the count
+        // exact: 1280 cycles is 10 slow ticks and 80 fast ones.
+        spc.stopped = true;
+        for _ in 0..640 {
+            assert_eq!(spc.tick(), 2);
+        }
+
+        assert_eq!(spc.peek_u8(0x00FD), 5);
+        assert_eq!(spc.peek_u8(0x00FE), 0);
+        assert_eq!(spc.peek_u8(0x00FF), 4);
+
+        // Reading clears them, but peeking doesn't.
+        assert_eq!(spc.read_u8(0x00FD), 5);
+        assert_eq!(spc.read_u8(0x00FD), 0);
+        assert_eq!(spc.read_u8(0x00FF), 4);
+        assert_eq!(spc.peek_u8(0x00FF), 0);
+        assert_eq!(spc.read_u8(0x00FE), 0);
+
+        // Writing CONTROL again with a timer still enabled doesn't restart
+        // it.
+        spc.tick();
+        spc.store_u8(0x00F1, 0x01);
+        for _ in 0..127 {
+            spc.tick();
+        }
+
+        assert_eq!(spc.read_u8(0x00FD), 1);
+    }
+}
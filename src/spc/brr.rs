@@ -0,0 +1,160 @@
+pub const BLOCK_SIZE: usize = 9;
+pub const SAMPLES_PER_BLOCK: usize = 16;
+
+pub struct BrrHeader {
+    pub shift: u8,
+    pub filter: u8,
+    pub looped: bool,
+    pub end: bool,
+}
+
+impl BrrHeader {
+    pub fn new(value: u8) -> BrrHeader {
+        BrrHeader {
+            shift: value >> 4,
+            filter: (value >> 2) & 0b11,
+            looped: value & 0b10 != 0,
+            end: value & 0b01 != 0,
+        }
+    }
+}
+
+// Decodes a 9 byte BRR block into 16 samples. `history` holds the last two
+// decoded samples (most recent first), and is carried over between blocks
+// so that the filters can predict from them.
+pub fn decode_block(block: &[u8], history: &mut [i16; 2]) -> [i16; SAMPLES_PER_BLOCK] {
+    let header = BrrHeader::new(block[0]);
+    let mut samples = [0; SAMPLES_PER_BLOCK];
+
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let byte = block[1 + i / 2];
+        let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+
+        // Sign extend the nibble to 4 bits.
+        let mut s = ((nibble << 4) as i8 >> 4) as i32;
+
+        // Shift values above 12 are invalid, and the hardware produces
+        // either 0 or -2048 for them.
+        if header.shift <= 12 {
+            s = (s << header.shift) >> 1;
+        } else if s < 0 {
+            s = -2048;
+        } else {
+            s = 0;
+        }
+
+        // The stored samples are 15-bit, so the history needs to be scaled
+        // back down before being used.
+        let p1 = (history[0] >> 1) as i32;
+        let p2 = (history[1] >> 1) as i32;
+
+        s += match header.filter {
+            0 => 0,
+            1 => p1 + (-p1 >> 4),
+            2 => (p1 << 1) + ((-p1 * 3) >> 5) - p2 + (p2 >> 4),
+            _ => (p1 << 1) + ((-p1 * 13) >> 6) - p2 + ((p2 * 3) >> 4),
+        };
+
+        let s = (s.clamp(i16::MIN as i32, i16::MAX as i32) as i16).wrapping_shl(1);
+
+        history[1] = history[0];
+        history[0] = s;
+
+        *sample = s;
+    }
+
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A block with the given header, whose nibbles are all `nibble`.
+    fn block(header: u8, nibble: u8) -> [u8; BLOCK_SIZE] {
+        let mut block = [nibble << 4 | nibble; BLOCK_SIZE];
+        block[0] = header;
+        block
+    }
+
+    #[test]
+    fn header_fields() {
+        let header = BrrHeader::new(0xC7);
+
+        assert_eq!(header.shift, 12);
+        assert_eq!(header.filter, 1);
+        assert!(header.looped);
+        assert!(header.end);
+    }
+
+    #[test]
+    fn high_nibble_first() {
+        let mut block = [0; BLOCK_SIZE];
+        block[0] = 0xC0;
+        block[1] = 0x7F;
+
+        let samples = decode_block(&block, &mut [0; 2]);
+
+        assert_eq!(samples[..3], [28672, -4096, 0]);
+    }
+
+    #[test]
+    fn shift_range() {
+        assert_eq!(decode_block(&block(0xC0, 0x7), &mut [0; 2])[0], 28672);
+        assert_eq!(decode_block(&block(0xC0, 0x8), &mut [0; 2])[0], -32768);
+        assert_eq!(decode_block(&block(0x00, 0x1), &mut [0; 2])[0], 0);
+        assert_eq!(decode_block(&block(0x00, 0x2), &mut [0; 2])[0], 2);
+        assert_eq!(decode_block(&block(0x40, 0x3), &mut [0; 2])[0], 48);
+    }
+
+    // Shifts of 13-15 decode to 0 or -2048 depending on the sign.
+    #[test]
+    fn invalid_shift() {
+        assert_eq!(decode_block(&block(0xD0, 0x1), &mut [0; 2])[0], 0);
+        assert_eq!(decode_block(&block(0xF0, 0x7), &mut [0; 2])[0], 0);
+        assert_eq!(decode_block(&block(0xD0, 0xF), &mut [0; 2])[0], -4096);
+    }
+
+    #[test]
+    fn filter_1() {
+        let mut history = [2000, 0];
+        let samples = decode_block(&block(0xC4, 0), &mut history);
+
+        assert_eq!(samples[..2], [1874, 1756]);
+        assert_eq!(history, [samples[15], samples[14]]);
+    }
+
+    #[test]
+    fn filter_2() {
+        let samples = decode_block(&block(0xC8, 0), &mut [2000, 1000]);
+
+        assert_eq!(samples[0], 2874);
+    }
+
+    #[test]
+    fn filter_3() {
+        let samples = decode_block(&block(0xCC, 0), &mut [2000, 1000]);
+
+        assert_eq!(samples[0], 2778);
+    }
+
+    // The sum is clamped to 16 bits, but the doubling afterwards wraps.
+    #[test]
+    fn clamp_then_wrap() {
+        let samples = decode_block(&block(0xC8, 0x7), &mut [32766, 0]);
+
+        assert_eq!(samples[0], -2);
+    }
+
+    #[test]
+    fn history_carries_between_blocks() {
+        let mut history = [0; 2];
+
+        let first = decode_block(&block(0xC0, 0x1), &mut history);
+        let second = decode_block(&block(0x04, 0x0), &mut history);
+
+        // Filter 1 decays the last sample by 1/16 each step.
+        assert_eq!(first[15], 4096);
+        assert_eq!(second[0], 3840);
+    }
+}
@@ -0,0 +1,181 @@
+// Operand placeholders in the templates below:
+//
+// {i1}  immediate from byte 1
+// {d1}  direct page address from byte 1 ({d2} for byte 2)
+// {u1}  upper page address from byte 1
+// {r1}  relative branch target from byte 1 ({r2} for byte 2)
+// {a}   absolute address from bytes 1-2
+// {mb}  13-bit absolute address and bit number from bytes 1-2
+#[rustfmt::skip]
+const TEMPLATES: [&str; 256] = [
+    "NOP", "TCALL 0", "SET1 {d1}.0", "BBS {d1}.0, {r2}",
+    "OR A, {d1}", "OR A, {a}", "OR A, (X)", "OR A, [{d1}+X]",
+    "OR A, {i1}", "OR {d2}, {d1}", "OR1 C, {mb}", "ASL {d1}",
+    "ASL {a}", "PUSH PSW", "TSET1 {a}", "BRK",
+    "BPL {r1}", "TCALL 1", "CLR1 {d1}.0", "BBC {d1}.0, {r2}",
+    "OR A, {d1}+X", "OR A, {a}+X", "OR A, {a}+Y", "OR A, [{d1}]+Y",
+    "OR {d2}, {i1}", "OR (X), (Y)", "DECW {d1}", "ASL {d1}+X",
+    "ASL A", "DEC X", "CMP X, {a}", "JMP [{a}+X]",
+    "CLRP", "TCALL 2", "SET1 {d1}.1", "BBS {d1}.1, {r2}",
+    "AND A, {d1}", "AND A, {a}", "AND A, (X)", "AND A, [{d1}+X]",
+    "AND A, {i1}", "AND {d2}, {d1}", "OR1 C, /{mb}", "ROL {d1}",
+    "ROL {a}", "PUSH A", "CBNE {d1}, {r2}", "BRA {r1}",
+    "BMI {r1}", "TCALL 3", "CLR1 {d1}.1", "BBC {d1}.1, {r2}",
+    "AND A, {d1}+X", "AND A, {a}+X", "AND A, {a}+Y", "AND A, [{d1}]+Y",
+    "AND {d2}, {i1}", "AND (X), (Y)", "INCW {d1}", "ROL {d1}+X",
+    "ROL A", "INC X", "CMP X, {d1}", "CALL {a}",
+    "SETP", "TCALL 4", "SET1 {d1}.2", "BBS {d1}.2, {r2}",
+    "EOR A, {d1}", "EOR A, {a}", "EOR A, (X)", "EOR A, [{d1}+X]",
+    "EOR A, {i1}", "EOR {d2}, {d1}", "AND1 C, {mb}", "LSR {d1}",
+    "LSR {a}", "PUSH X", "TCLR1 {a}", "PCALL {u1}",
+    "BVC {r1}", "TCALL 5", "CLR1 {d1}.2", "BBC {d1}.2, {r2}",
+    "EOR A, {d1}+X", "EOR A, {a}+X", "EOR A, {a}+Y", "EOR A, [{d1}]+Y",
+    "EOR {d2}, {i1}", "EOR (X), (Y)", "CMPW YA, {d1}", "LSR {d1}+X",
+    "LSR A", "MOV X, A", "CMP Y, {a}", "JMP {a}",
+    "CLRC", "TCALL 6", "SET1 {d1}.3", "BBS {d1}.3, {r2}",
+    "CMP A, {d1}", "CMP A, {a}", "CMP A, (X)", "CMP A, [{d1}+X]",
+    "CMP A, {i1}", "CMP {d2}, {d1}", "AND1 C, /{mb}", "ROR {d1}",
+    "ROR {a}", "PUSH Y", "DBNZ {d1}, {r2}", "RET",
+    "BVS {r1}", "TCALL 7", "CLR1 {d1}.3", "BBC {d1}.3, {r2}",
+    "CMP A, {d1}+X", "CMP A, {a}+X", "CMP A, {a}+Y", "CMP A, [{d1}]+Y",
+    "CMP {d2}, {i1}", "CMP (X), (Y)", "ADDW YA, {d1}", "ROR {d1}+X",
+    "ROR A", "MOV A, X", "CMP Y, {d1}", "RETI",
+    "SETC", "TCALL 8", "SET1 {d1}.4", "BBS {d1}.4, {r2}",
+    "ADC A, {d1}", "ADC A, {a}", "ADC A, (X)", "ADC A, [{d1}+X]",
+    "ADC A, {i1}", "ADC {d2}, {d1}", "EOR1 C, {mb}", "DEC {d1}",
+    "DEC {a}", "MOV Y, {i1}", "POP PSW", "MOV {d2}, {i1}",
+    "BCC {r1}", "TCALL 9", "CLR1 {d1}.4", "BBC {d1}.4, {r2}",
+    "ADC A, {d1}+X", "ADC A, {a}+X", "ADC A, {a}+Y", "ADC A, [{d1}]+Y",
+    "ADC {d2}, {i1}", "ADC (X), (Y)", "SUBW YA, {d1}", "DEC {d1}+X",
+    "DEC A", "MOV X, SP", "DIV YA, X", "XCN A",
+    "EI", "TCALL 10", "SET1 {d1}.5", "BBS {d1}.5, {r2}",
+    "SBC A, {d1}", "SBC A, {a}", "SBC A, (X)", "SBC A, [{d1}+X]",
+    "SBC A, {i1}", "SBC {d2}, {d1}", "MOV1 C, {mb}", "INC {d1}",
+    "INC {a}", "CMP Y, {i1}", "POP A", "MOV (X)+, A",
+    "BCS {r1}", "TCALL 11", "CLR1 {d1}.5", "BBC {d1}.5, {r2}",
+    "SBC A, {d1}+X", "SBC A, {a}+X", "SBC A, {a}+Y", "SBC A, [{d1}]+Y",
+    "SBC {d2}, {i1}", "SBC (X), (Y)", "MOVW YA, {d1}", "INC {d1}+X",
+    "INC A", "MOV SP, X", "DAS A", "MOV A, (X)+",
+    "DI", "TCALL 12", "SET1 {d1}.6", "BBS {d1}.6, {r2}",
+    "MOV {d1}, A", "MOV {a}, A", "MOV (X), A", "MOV [{d1}+X], A",
+    "CMP X, {i1}", "MOV {a}, X", "MOV1 {mb}, C", "MOV {d1}, Y",
+    "MOV {a}, Y", "MOV X, {i1}", "POP X", "MUL YA",
+    "BNE {r1}", "TCALL 13", "CLR1 {d1}.6", "BBC {d1}.6, {r2}",
+    "MOV {d1}+X, A", "MOV {a}+X, A", "MOV {a}+Y, A", "MOV [{d1}]+Y, A",
+    "MOV {d1}, X", "MOV {d1}+Y, X", "MOVW {d1}, YA", "MOV {d1}+X, Y",
+    "DEC Y", "MOV A, Y", "CBNE {d1}+X, {r2}", "DAA A",
+    "CLRV", "TCALL 14", "SET1 {d1}.7", "BBS {d1}.7, {r2}",
+    "MOV A, {d1}", "MOV A, {a}", "MOV A, (X)", "MOV A, [{d1}+X]",
+    "MOV A, {i1}", "MOV X, {a}", "NOT1 {mb}", "MOV Y, {d1}",
+    "MOV Y, {a}", "NOTC", "POP Y", "SLEEP",
+    "BEQ {r1}", "TCALL 15", "CLR1 {d1}.7", "BBC {d1}.7, {r2}",
+    "MOV A, {d1}+X", "MOV A, {a}+X", "MOV A, {a}+Y", "MOV A, [{d1}]+Y",
+    "MOV X, {d1}", "MOV X, {d1}+Y", "MOV {d2}, {d1}", "MOV Y, {d1}+X",
+    "INC Y", "MOV Y, A", "DBNZ Y, {r1}", "STOP",
+];
+
+// Disassembles the instruction at `pc`, returning the text and the number of
+// bytes it occupies.
+pub fn disassemble(bytes: [u8; 3], pc: u16) -> (String, usize) {
+    let template = TEMPLATES[bytes[0] as usize];
+
+    let word = u16::from_le_bytes([bytes[1], bytes[2]]);
+    let branch = |offset: u8, len: u16| {
+        pc.wrapping_add(len)
+            .wrapping_add_signed(offset as i8 as i16)
+    };
+
+    let mut text = String::new();
+    let mut len = 1;
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}').unwrap();
+        text.push_str(&rest[..start]);
+
+        let (operand, operand_len) = match &rest[start + 1..end] {
+            "i1" => (format!("#${:02X}", bytes[1]), 2),
+            "d1" => (format!("${:02X}", bytes[1]), 2),
+            "d2" => (format!("${:02X}", bytes[2]), 3),
+            "u1" => (format!("$FF{:02X}", bytes[1]), 2),
+            "r1" => (format!("${:04X}", branch(bytes[1], 2)), 2),
+            "r2" => (format!("${:04X}", branch(bytes[2], 3)), 3),
+            "a" => (format!("!${:04X}", word), 3),
+            "mb" => (format!("${:04X}.{}", word & 0x1FFF, word >> 13), 3),
+            other => unreachable!("unknown operand {}", other),
+        };
+
+        text.push_str(&operand);
+        len = len.max(operand_len);
+        rest = &rest[end + 1..];
+    }
+
+    text.push_str(rest);
+
+    (text, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spc::Spc700;
+
+    #[test]
+    fn operands() {
+        let cases: &[(&[u8], u16, &str)] = &[
+            (&[0x00], 0x0200, "NOP"),
+            (&[0xE8, 0x42], 0x0200, "MOV A, #$42"),
+            (&[0xE4, 0xF4], 0x0200, "MOV A, $F4"),
+            (&[0xF4, 0x10], 0x0200, "MOV A, $10+X"),
+            (&[0xE5, 0x34, 0x12], 0x0200, "MOV A, !$1234"),
+            (&[0xF6, 0x34, 0x12], 0x0200, "MOV A, !$1234+Y"),
+            (&[0xE7, 0x20], 0x0200, "MOV A, [$20+X]"),
+            (&[0xF7, 0x20], 0x0200, "MOV A, [$20]+Y"),
+            (&[0xFA, 0xF4, 0x10], 0x0200, "MOV $10, $F4"),
+            (&[0x8F, 0xAA, 0xF4], 0x0200, "MOV $F4, #$AA"),
+            (&[0x4F, 0xC0], 0x0200, "PCALL $FFC0"),
+            (&[0x2F, 0xFE], 0xFFC0, "BRA $FFC0"),
+            (&[0xD0, 0x10], 0x0200, "BNE $0212"),
+            (&[0x03, 0x20, 0xFD], 0x0200, "BBS $20.0, $0200"),
+            (&[0xFE, 0xFC], 0x0200, "DBNZ Y, $01FE"),
+            (&[0x6E, 0x20, 0x05], 0x0200, "DBNZ $20, $0208"),
+            (&[0xAA, 0x34, 0xF2], 0x0200, "MOV1 C, $1234.7"),
+            (&[0x3F, 0x00, 0x08], 0x0200, "CALL !$0800"),
+            (&[0x1F, 0x00, 0x08], 0x0200, "JMP [!$0800+X]"),
+            (&[0xC1], 0x0200, "TCALL 12"),
+            (&[0xBA, 0x30], 0x0200, "MOVW YA, $30"),
+            (&[0xAF], 0x0200, "MOV (X)+, A"),
+        ];
+
+        for &(bytes, pc, expected) in cases {
+            let mut padded = [0; 3];
+            padded[..bytes.len()].copy_from_slice(bytes);
+
+            assert_eq!(disassemble(padded, pc), (expected.to_string(), bytes.len()));
+        }
+    }
+
+    // With all-zero operands every branch lands on the next instruction,
+    // so apart from jumps and calls, running an opcode should move the PC
+    // by exactly the length the disassembler reports.
+    #[test]
+    fn lengths_match_execution() {
+        let jumps = [0x0F, 0x1F, 0x3F, 0x4F, 0x5F, 0x6F, 0x7F];
+
+        for opcode in 0..=255u8 {
+            let (text, len) = disassemble([opcode, 0, 0], 0x0200);
+
+            if jumps.contains(&opcode) || opcode & 0x0F == 0x01 {
+                continue;
+            }
+
+            let mut spc = Spc700::new();
+            spc.pc = 0x0200;
+            spc.ram[0x0200] = opcode;
+
+            spc.tick();
+
+            assert_eq!(spc.pc, 0x0200 + len as u16, "{opcode:02X} {text}");
+        }
+    }
+}
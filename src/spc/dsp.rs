@@ -0,0 +1,287 @@
+use super::brr::{self, BrrHeader, BLOCK_SIZE, SAMPLES_PER_BLOCK};
+
+// The DSP outputs a sample every 32 SPC700 cycles, for a rate of 32kHz.
+const CYCLES_PER_SAMPLE: u64 = 32;
+
+// Per-voice registers, offset from $x0 for voice x.
+const VOICE_VOL_LEFT: usize = 0x0;
+const VOICE_VOL_RIGHT: usize = 0x1;
+const VOICE_PITCH_LOW: usize = 0x2;
+const VOICE_PITCH_HIGH: usize = 0x3;
+const VOICE_SRCN: usize = 0x4;
+const VOICE_ENVX: usize = 0x8;
+const VOICE_OUTX: usize = 0x9;
+
+// Global registers
+const MVOL_LEFT: usize = 0x0C;
+const MVOL_RIGHT: usize = 0x1C;
+const FLG: usize = 0x6C;
+const KON: usize = 0x4C;
+const KOF: usize = 0x5C;
+const ENDX: usize = 0x7C;
+const DIR: usize = 0x5D;
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+struct Voice {
+    active: bool,
+
+    // BRR decoding
+    block_addr: u16,
+    buffer: [i16; SAMPLES_PER_BLOCK],
+    history: [i16; 2],
+    position: usize,
+    pitch_counter: u32,
+
+    output: i16,
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dsp {
+    regs: Vec<u8>,
+    voices: [Voice; 8],
+
+    // KON is latched until the next sample boundary.
+    key_on: u8,
+
+    // Interleaved stereo output, waiting to be taken by the frontend.
+    samples: Vec<i16>,
+
+    cycles: u64,
+}
+
+impl Dsp {
+    pub fn new() -> Dsp {
+        Dsp {
+            regs: vec![0; 128],
+            voices: [Voice::default(); 8],
+
+            key_on: 0,
+
+            samples: Vec::new(),
+
+            cycles: 0,
+        }
+    }
+
+    pub fn read(&self, addr: u8) -> u8 {
+        // $80-$FF are read-only mirrors of $00-$7F.
+        self.regs[addr as usize & 0x7F]
+    }
+
+    pub fn write(&mut self, addr: u8, value: u8) {
+        let addr = addr as usize;
+
+        match addr {
+            0x80..=0xFF => {}
+
+            KON => {
+                self.key_on |= value;
+                self.regs[addr] = value;
+            }
+
+            // Any write to ENDX clears it.
+            ENDX => self.regs[addr] = 0,
+
+            _ => self.regs[addr] = value,
+        }
+    }
+
+    pub fn step(&mut self, cycles: u64, ram: &[u8]) {
+        self.cycles += cycles;
+
+        while self.cycles >= CYCLES_PER_SAMPLE {
+            self.cycles -= CYCLES_PER_SAMPLE;
+            self.run_sample(ram);
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    fn run_sample(&mut self, ram: &[u8]) {
+        let mut left = 0;
+        let mut right = 0;
+
+        for voice in 0..8 {
+            let bit = 1 << voice;
+
+            if self.key_on & bit != 0 {
+                self.start_voice(voice, ram);
+            } else if self.regs[KOF] & bit != 0 {
+                // TODO: This should enter the release phase of the envelope
+                self.voices[voice].active = false;
+                self.voices[voice].output = 0;
+            }
+
+            if self.voices[voice].active {
+                self.advance_voice(voice, ram);
+            }
+
+            let base = voice << 4;
+            let output = self.voices[voice].output;
+
+            // TODO: Envelopes
+            self.regs[base | VOICE_ENVX] = if self.voices[voice].active { 0x7F } else { 0 };
+            self.regs[base | VOICE_OUTX] = (output >> 8) as u8;
+
+            // TODO: Pitch modulation, noise and echo
+            left = mix(left, output, self.regs[base | VOICE_VOL_LEFT]);
+            right = mix(right, output, self.regs[base | VOICE_VOL_RIGHT]);
+        }
+
+        self.key_on = 0;
+
+        // FLG bit 6 mutes the output.
+        if self.regs[FLG] & 0x40 != 0 {
+            self.samples.extend([0, 0]);
+        } else {
+            self.samples.extend([
+                mix(0, left, self.regs[MVOL_LEFT]),
+                mix(0, right, self.regs[MVOL_RIGHT]),
+            ]);
+        }
+    }
+
+    fn sample_dir_entry(&self, voice: usize, ram: &[u8]) -> (u16, u16) {
+        let srcn = self.regs[(voice << 4) | VOICE_SRCN] as u16;
+        let entry = ((self.regs[DIR] as u16) << 8).wrapping_add(srcn * 4) as usize;
+
+        let read_u16 =
+            |addr: usize| u16::from_le_bytes([ram[addr & 0xFFFF], ram[(addr + 1) & 0xFFFF]]);
+
+        (read_u16(entry), read_u16(entry + 2))
+    }
+
+    fn start_voice(&mut self, voice: usize, ram: &[u8]) {
+        let (start, _) = self.sample_dir_entry(voice, ram);
+
+        self.regs[ENDX] &= !(1 << voice);
+
+        let v = &mut self.voices[voice];
+
+        v.active = true;
+        v.block_addr = start;
+        v.history = [0; 2];
+        v.position = 0;
+        v.pitch_counter = 0;
+        v.buffer = brr::decode_block(&block(ram, start), &mut v.history);
+    }
+
+    fn advance_voice(&mut self, voice: usize, ram: &[u8]) {
+        let base = voice << 4;
+        let pitch = u16::from_le_bytes([
+            self.regs[base | VOICE_PITCH_LOW],
+            self.regs[base | VOICE_PITCH_HIGH],
+        ]) & 0x3FFF;
+
+        // TODO: Gaussian interpolation
+        self.voices[voice].output = self.voices[voice].buffer[self.voices[voice].position];
+        self.voices[voice].pitch_counter += pitch as u32;
+
+        while self.voices[voice].pitch_counter >= 0x1000 {
+            self.voices[voice].pitch_counter -= 0x1000;
+            self.voices[voice].position += 1;
+
+            if self.voices[voice].position < SAMPLES_PER_BLOCK {
+                continue;
+            }
+
+            let header = BrrHeader::new(ram[self.voices[voice].block_addr as usize]);
+
+            let next_addr = if header.end {
+                self.regs[ENDX] |= 1 << voice;
+
+                if !header.looped {
+                    self.voices[voice].active = false;
+                    self.voices[voice].output = 0;
+                    return;
+                }
+
+                self.sample_dir_entry(voice, ram).1
+            } else {
+                self.voices[voice]
+                    .block_addr
+                    .wrapping_add(BLOCK_SIZE as u16)
+            };
+
+            let v = &mut self.voices[voice];
+
+            v.block_addr = next_addr;
+            v.position = 0;
+            v.buffer = brr::decode_block(&block(ram, next_addr), &mut v.history);
+        }
+    }
+}
+
+// Adds a sample scaled by a signed volume register to the running total,
+// clamping as the hardware does after each voice.
+fn mix(total: i16, sample: i16, volume: u8) -> i16 {
+    let scaled = (sample as i32 * volume as i8 as i32) >> 7;
+
+    (total as i32 + scaled).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+fn block(ram: &[u8], addr: u16) -> [u8; BLOCK_SIZE] {
+    let mut block = [0; BLOCK_SIZE];
+
+    for (i, byte) in block.iter_mut().enumerate() {
+        *byte = ram[addr.wrapping_add(i as u16) as usize];
+    }
+
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upper_half_is_read_only_mirror() {
+        let mut dsp = Dsp::new();
+
+        dsp.write(0x0C, 0x7F);
+        dsp.write(0x8C, 0x12);
+
+        assert_eq!(dsp.read(0x0C), 0x7F);
+        assert_eq!(dsp.read(0x8C), 0x7F);
+    }
+
+    #[test]
+    fn endx_write_clears() {
+        let mut dsp = Dsp::new();
+        dsp.regs[ENDX] = 0xFF;
+
+        dsp.write(ENDX as u8, 0x12);
+
+        assert_eq!(dsp.read(ENDX as u8), 0);
+    }
+
+    // One block of a sample with the end flag set and no loop, played back
+    // at one BRR sample per output sample.
+    #[test]
+    fn one_shot_sample() {
+        let mut ram = vec![0; 0x10000];
+        ram[0x0100..0x0104].copy_from_slice(&[0x00, 0x02, 0x00, 0x02]);
+        ram[0x0200] = 0xC1;
+        ram[0x0201..0x0209].fill(0x11);
+
+        let mut dsp = Dsp::new();
+        dsp.write(DIR as u8, 0x01);
+        dsp.write(VOICE_PITCH_HIGH as u8, 0x10);
+        dsp.write(KON as u8, 0x01);
+
+        dsp.step(CYCLES_PER_SAMPLE, &ram);
+
+        assert_eq!(dsp.read(VOICE_ENVX as u8), 0x7F);
+        assert_eq!(dsp.read(VOICE_OUTX as u8), 0x10);
+        assert_eq!(dsp.read(ENDX as u8), 0);
+
+        dsp.step(CYCLES_PER_SAMPLE * 16, &ram);
+
+        assert_eq!(dsp.read(VOICE_ENVX as u8), 0);
+        assert_eq!(dsp.read(ENDX as u8), 0x01);
+    }
+}
@@ -0,0 +1,151 @@
+// SPC700 cycles per tick of timers 0 and 1 (8kHz), and of timer 2 (64kHz).
+pub const SLOW_PERIOD: u64 = 128;
+pub const FAST_PERIOD: u64 = 16;
+
+// One of the three timers. Each tick counts towards the target, and on
+// reaching it, the 4-bit counter that the driver reads goes up by one.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "savestate", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timer {
+    period: u64,
+
+    // Cycles since the last tick. This keeps running while the timer is
+    // disabled.
+    divider: u64,
+
+    enabled: bool,
+
+    // TnTARGET. Zero counts as 256.
+    target: u8,
+
+    // Ticks since the counter last went up.
+    ticks: u8,
+
+    // TnOUT
+    counter: u8,
+}
+
+impl Timer {
+    pub fn new(period: u64) -> Timer {
+        Timer {
+            period,
+            divider: 0,
+            enabled: false,
+            target: 0,
+            ticks: 0,
+            counter: 0,
+        }
+    }
+
+    pub fn step(&mut self, cycles: u64) {
+        self.divider += cycles;
+
+        while self.divider >= self.period {
+            self.divider -= self.period;
+
+            if !self.enabled {
+                continue;
+            }
+
+            self.ticks = self.ticks.wrapping_add(1);
+
+            if self.ticks == self.target {
+                self.ticks = 0;
+                self.counter = (self.counter + 1) & 0xF;
+            }
+        }
+    }
+
+    // Enabling a timer that was off restarts its count.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            self.ticks = 0;
+            self.counter = 0;
+        }
+
+        self.enabled = enabled;
+    }
+
+    pub fn set_target(&mut self, target: u8) {
+        self.target = target;
+    }
+
+    pub fn counter(&self) -> u8 {
+        self.counter
+    }
+
+    // Reading the counter clears it.
+    pub fn read_counter(&mut self) -> u8 {
+        std::mem::take(&mut self.counter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(period: u64, target: u8) -> Timer {
+        let mut timer = Timer::new(period);
+        timer.set_target(target);
+        timer.set_enabled(true);
+        timer
+    }
+
+    #[test]
+    fn counts_up_at_the_target() {
+        let mut timer = timer(SLOW_PERIOD, 4);
+
+        timer.step(SLOW_PERIOD * 4 * 3 - 1);
+        assert_eq!(timer.counter(), 2);
+
+        timer.step(1);
+        assert_eq!(timer.counter(), 3);
+
+        // Reading clears the counter, but not the ticks towards the next.
+        assert_eq!(timer.read_counter(), 3);
+        assert_eq!(timer.read_counter(), 0);
+
+        timer.step(SLOW_PERIOD * 4);
+        assert_eq!(timer.read_counter(), 1);
+    }
+
+    #[test]
+    fn counter_is_four_bits() {
+        let mut timer = timer(FAST_PERIOD, 1);
+
+        timer.step(FAST_PERIOD * 17);
+        assert_eq!(timer.counter(), 1);
+    }
+
+    #[test]
+    fn zero_target_is_256() {
+        let mut timer = timer(FAST_PERIOD, 0);
+
+        timer.step(FAST_PERIOD * 255);
+        assert_eq!(timer.counter(), 0);
+
+        timer.step(FAST_PERIOD);
+        assert_eq!(timer.counter(), 1);
+    }
+
+    #[test]
+    fn disabled_timers_stop_counting() {
+        let mut timer = timer(SLOW_PERIOD, 1);
+        timer.step(SLOW_PERIOD * 3);
+
+        // Disabling keeps the counter, and re-enabling clears it.
+        timer.set_enabled(false);
+        timer.step(SLOW_PERIOD * 3);
+        assert_eq!(timer.counter(), 3);
+
+        timer.set_enabled(true);
+        assert_eq!(timer.counter(), 0);
+
+        // The divider kept running, so the first tick comes early.
+        timer.step(100);
+        timer.set_enabled(false);
+        timer.set_enabled(true);
+        timer.step(SLOW_PERIOD - 100);
+        assert_eq!(timer.counter(), 1);
+    }
+}
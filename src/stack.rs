@@ -0,0 +1,223 @@
+use crate::bus::Bus;
+use crate::cpu::{CallFrame, CallKind};
+
+// The most of the stack that gets captured. Games rarely have more than
+// this much live at once, and it keeps capturing the stack for every
+// traced instruction cheap.
+pub const STACK_LIMIT: u16 = 64;
+
+// A return address found on the stack, `offset` bytes above the top. For
+// interrupts this covers the pushed status as well.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReturnSlot {
+    pub offset: u16,
+    pub kind: CallKind,
+    pub returns_to: u32,
+
+    // Whether there's a frame on the call stack for it, rather than it
+    // just looking like a return address.
+    pub confirmed: bool,
+}
+
+impl ReturnSlot {
+    // The offset just past it.
+    pub fn end(&self) -> u16 {
+        self.offset + self.kind.pushed()
+    }
+}
+
+// How a run of bytes on the stack is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackEntry {
+    Byte(u8),
+    Word(u16),
+    Return(ReturnSlot),
+}
+
+// The top of the stack, from SP+1 upwards, along with any return addresses
+// that could be picked out of it.
+#[derive(Clone, Debug, Default)]
+pub struct Stack {
+    pub sp: u16,
+    pub bytes: Vec<u8>,
+
+    // In order of offset, and never overlapping.
+    pub returns: Vec<ReturnSlot>,
+
+    // Whether there was more on the stack than was captured.
+    pub truncated: bool,
+}
+
+impl Stack {
+    pub fn new(sp: u16, bytes: Vec<u8>, truncated: bool) -> Stack {
+        Stack {
+            sp,
+            bytes,
+            returns: Vec::new(),
+            truncated,
+        }
+    }
+
+    // Marks the return addresses of the calls still in progress, then
+    // anything else that points just after a JSR or JSL in ROM. The guesses
+    // use the current program bank for JSR, as that's all there is to go on.
+    pub fn find_returns(&mut self, mmu: &impl Bus, frames: &[CallFrame], program_bank: u8) {
+        for frame in frames {
+            let len = frame.kind.pushed();
+            let start = frame.sp.wrapping_sub(len - 1);
+            let offset = start.wrapping_sub(self.sp.wrapping_add(1));
+
+            if offset as usize + len as usize > self.bytes.len() {
+                continue;
+            }
+
+            let bytes = &self.bytes[offset as usize..][..len as usize];
+
+            let returns_to = match frame.kind {
+                CallKind::Subroutine => {
+                    let addr = u16::from_le_bytes([bytes[0], bytes[1]]).wrapping_add(1);
+                    (frame.caller & 0xFF_0000) | addr as u32
+                }
+                CallKind::Long => long_return(bytes),
+                CallKind::Interrupt { emulation } => {
+                    let bank = if emulation { 0 } else { bytes[3] };
+                    (bank as u32) << 16 | u16::from_le_bytes([bytes[1], bytes[2]]) as u32
+                }
+            };
+
+            self.returns.push(ReturnSlot {
+                offset,
+                kind: frame.kind,
+                returns_to,
+                confirmed: true,
+            });
+        }
+
+        let mut confirmed = std::mem::take(&mut self.returns);
+        confirmed.sort_by_key(|slot| slot.offset);
+
+        let mut confirmed = confirmed.into_iter().peekable();
+        let mut offset = 0;
+
+        while (offset as usize) < self.bytes.len() {
+            // A frame that overlaps the one before it is left out.
+            if let Some(slot) = confirmed.next_if(|slot| slot.offset <= offset) {
+                if slot.offset == offset {
+                    offset = slot.end();
+                    self.returns.push(slot);
+                }
+
+                continue;
+            }
+
+            // Guesses can't run into a confirmed frame.
+            let room = match confirmed.peek() {
+                Some(slot) => slot.offset,
+                None => self.bytes.len() as u16,
+            } - offset;
+
+            match self.guess_return(mmu, offset, room, program_bank) {
+                Some(slot) => {
+                    offset = slot.end();
+                    self.returns.push(slot);
+                }
+                None => offset += 1,
+            }
+        }
+    }
+
+    fn guess_return(
+        &self,
+        mmu: &impl Bus,
+        offset: u16,
+        room: u16,
+        program_bank: u8,
+    ) -> Option<ReturnSlot> {
+        let bytes = &self.bytes[offset as usize..];
+
+        // JSR and JSL push the address of their last byte.
+        if room >= 3 {
+            let returns_to = long_return(bytes);
+            let call = (returns_to & 0xFF_0000) | (returns_to as u16).wrapping_sub(4) as u32;
+
+            if in_rom(call) && mmu.peek_u8(call) == 0x22 {
+                return Some(ReturnSlot {
+                    offset,
+                    kind: CallKind::Long,
+                    returns_to,
+                    confirmed: false,
+                });
+            }
+        }
+
+        if room >= 2 {
+            let addr = u16::from_le_bytes([bytes[0], bytes[1]]).wrapping_add(1);
+            let returns_to = (program_bank as u32) << 16 | addr as u32;
+            let call = (program_bank as u32) << 16 | addr.wrapping_sub(3) as u32;
+
+            if in_rom(call) && matches!(mmu.peek_u8(call), 0x20 | 0xFC) {
+                return Some(ReturnSlot {
+                    offset,
+                    kind: CallKind::Subroutine,
+                    returns_to,
+                    confirmed: false,
+                });
+            }
+        }
+
+        None
+    }
+
+    // Groups the bytes into 16-bit words from the top down, breaking off
+    // for return addresses. A byte left over before one, or at the end, is
+    // shown on its own.
+    pub fn entries(&self) -> Vec<(u16, StackEntry)> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        let ends = self.returns.iter().map(Some).chain(std::iter::once(None));
+
+        for slot in ends {
+            let end = slot.map_or(self.bytes.len() as u16, |slot| slot.offset);
+
+            while offset + 2 <= end {
+                let i = offset as usize;
+                let word = u16::from_le_bytes([self.bytes[i], self.bytes[i + 1]]);
+                entries.push((offset, StackEntry::Word(word)));
+                offset += 2;
+            }
+
+            if offset < end {
+                entries.push((offset, StackEntry::Byte(self.bytes[offset as usize])));
+            }
+
+            if let Some(slot) = slot {
+                entries.push((slot.offset, StackEntry::Return(*slot)));
+                offset = slot.end();
+            }
+        }
+
+        entries
+    }
+}
+
+// What JSL pushed: the address of its last byte, with the bank on top. This
+// follows the order the CPU pushes them in, which is the reverse of the
+// real thing (see the TODO on JSL).
+fn long_return(bytes: &[u8]) -> u32 {
+    let addr = u16::from_le_bytes([bytes[1], bytes[2]]).wrapping_add(1);
+    (bytes[0] as u32) << 16 | addr as u32
+}
+
+// Whether an address is somewhere that cartridges put ROM, going by the
+// usual LoROM and HiROM layouts.
+fn in_rom(addr: u32) -> bool {
+    let bank = (addr >> 16) as u8;
+    let offset = addr as u16;
+
+    match bank {
+        0x7E | 0x7F => false,
+        0x40..=0x7D | 0xC0..=0xFF => true,
+        _ => offset >= 0x8000,
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::debugger::parse_address;
+
+// Labels for addresses in the 24-bit address space, loaded from a symbol
+// file. Two formats are understood, which only really differ in their
+// section names:
+//
+//     WLA-DX:      [labels]
+//                  c3:0123 DrawMenuBox
+//
+//     bsnes-plus:  #SNES65816
+//                  [symbol]
+//                  c3:0123 DrawMenuBox any 1
+//
+// Anything after the label is ignored, as are any other sections (e.g.
+// WLA-DX's [definitions]). Addresses without a colon are also accepted, as
+// some tools write them that way.
+#[derive(Default)]
+pub struct Symbols {
+    labels: BTreeMap<u32, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Symbols> {
+        Symbols::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Symbols> {
+        let mut symbols = Symbols::new();
+
+        // Files that don't have any sections are treated as a plain list of
+        // labels.
+        let mut in_labels = true;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = match line.split_once(';') {
+                Some((line, _comment)) => line.trim(),
+                None => line.trim(),
+            };
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let section = line.trim_matches(|c| c == '[' || c == ']');
+                in_labels = section.eq_ignore_ascii_case("labels")
+                    || section.eq_ignore_ascii_case("symbol");
+
+                continue;
+            }
+
+            if !in_labels {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+
+            let (addr, name) = match (fields.next().and_then(parse_address), fields.next()) {
+                (Some(addr), Some(name)) => (addr, name),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid label on line {}", i + 1),
+                    ))
+                }
+            };
+
+            // If an address has more than one label, the first one wins.
+            symbols
+                .labels
+                .entry(addr)
+                .or_insert_with(|| name.to_string());
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn label(&self, addr: u32) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    // Finds the closest label at or below an address, along with how far
+    // past it the address is. Labels in other banks don't count.
+    pub fn nearest(&self, addr: u32) -> Option<(&str, u32)> {
+        let (&label_addr, name) = self.labels.range(..=addr).next_back()?;
+
+        if label_addr >> 16 != addr >> 16 {
+            return None;
+        }
+
+        Some((name, addr - label_addr))
+    }
+
+    // Describes an address relative to the closest label, e.g. "Reset+$12".
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        match self.nearest(addr)? {
+            (name, 0) => Some(name.to_string()),
+            (name, offset) => Some(format!("{}+${:X}", name, offset)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wla_dx() {
+        let symbols = Symbols::parse(
+            "; WLA-DX symbolic information\n\
+             [labels]\n\
+             00:8000 Reset\n\
+             00:8000 Start ; a second label for the same address\n\
+             C3:0123 DrawMenuBox\n\
+             7F:2000 Buffer\n\
+             \n\
+             [definitions]\n\
+             00000010 SOME_CONSTANT\n",
+        )
+        .unwrap();
+
+        assert_eq!(symbols.label(0x00_8000), Some("Reset"));
+        assert_eq!(symbols.label(0xC3_0123), Some("DrawMenuBox"));
+        assert_eq!(symbols.label(0x7F_2000), Some("Buffer"));
+
+        // Definitions aren't addresses.
+        assert_eq!(symbols.label(0x00_0010), None);
+    }
+
+    #[test]
+    fn bsnes_plus() {
+        let symbols = Symbols::parse(
+            "#SNES65816\n\
+             \n\
+             [SYMBOL]\n\
+             c3:0123 DrawMenuBox any 1\n\
+             7e:0100 Counter any 2\n\
+             ff:fff0 Top any 1\n\
+             \n\
+             [COMMENT]\n\
+             c3:0123 Draws the menu box\n",
+        )
+        .unwrap();
+
+        assert_eq!(symbols.label(0xC3_0123), Some("DrawMenuBox"));
+        assert_eq!(symbols.label(0x7E_0100), Some("Counter"));
+        assert_eq!(symbols.label(0xFF_FFF0), Some("Top"));
+    }
+
+    #[test]
+    fn plain_list() {
+        let symbols = Symbols::parse("C08000 Main\n808000 MainMirror\n").unwrap();
+
+        assert_eq!(symbols.label(0xC0_8000), Some("Main"));
+        assert_eq!(symbols.label(0x80_8000), Some("MainMirror"));
+    }
+
+    #[test]
+    fn nearest_label_below() {
+        let symbols =
+            Symbols::parse("[labels]\nC3:0100 First\nC3:0123 DrawMenuBox\nC4:0000 NextBank\n")
+                .unwrap();
+
+        assert_eq!(symbols.nearest(0xC3_0123), Some(("DrawMenuBox", 0)));
+        assert_eq!(symbols.nearest(0xC3_0150), Some(("DrawMenuBox", 0x2D)));
+        assert_eq!(symbols.nearest(0xC3_0122), Some(("First", 0x22)));
+        assert_eq!(
+            symbols.describe(0xC3_FFFF).as_deref(),
+            Some("DrawMenuBox+$FEDC")
+        );
+
+        // Labels in other banks don't count.
+        assert_eq!(symbols.nearest(0xC3_00FF), None);
+        assert_eq!(symbols.nearest(0xC2_FFFF), None);
+        assert_eq!(symbols.nearest(0xC5_0000), None);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let error = Symbols::parse("[labels]\n00:8000 Reset\nnonsense\n")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "invalid label on line 3");
+    }
+}
@@ -0,0 +1,720 @@
+use std::fmt::Write as _;
+
+use crate::cpu::{CallFrame, CallKind, Cpu, CpuState, Operand};
+use crate::debugger;
+use crate::disasm::{self, Disassembly};
+use crate::inst::Instruction;
+use crate::mmu::Mmu;
+use crate::stack::{Stack, StackEntry};
+use crate::symbols::Symbols;
+
+// The state of the machine from just before an instruction ran. Everything
+// the log needs is captured at the same time, as memory has usually changed
+// (or the code has modified itself) by the time the log gets written.
+//
+// One of these is captured for every instruction, so it only holds what the
+// log needs (the full machine state is only copied for rewinding), and the
+// disassembly's text isn't generated until the log is written.
+// benches/trace.rs measures how long capturing one takes.
+#[derive(Clone)]
+pub struct TraceRecord {
+    pub cpu: CpuState,
+    pub instruction: Instruction,
+    pub disassembly: Disassembly,
+    pub stack: Stack,
+    pub cycles: u64,
+
+    pub frame: u64,
+    pub scanline: u16,
+    pub h_counter: u16,
+
+    // Where the instruction accessed its operand. This can only be known
+    // once it's run, so it's None until the record is filled in afterwards.
+    pub operand: Option<Operand>,
+}
+
+impl TraceRecord {
+    pub fn capture(cpu: &Cpu, mmu: &Mmu) -> TraceRecord {
+        let next = cpu.peek_next(mmu);
+
+        TraceRecord {
+            cpu: cpu.state(),
+            instruction: next.instruction,
+            disassembly: next.disassembly,
+            stack: cpu.stack(mmu),
+            cycles: cpu.cycles(),
+
+            frame: mmu.ppu.frame(),
+            scanline: mmu.ppu.scanline(),
+            h_counter: mmu.ppu.h_counter(),
+
+            operand: None,
+        }
+    }
+}
+
+// Pads the text written since start out to a minimum width, the same as
+// {:<width} would.
+fn pad(output: &mut String, start: usize, width: usize) {
+    while output.len() - start < width {
+        output.push(' ');
+    }
+}
+
+fn write_bytes(output: &mut String, bytes: &[u8], lowercase: bool) {
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            output.push(' ');
+        }
+
+        let _ = if lowercase {
+            write!(output, "{:02x}", byte)
+        } else {
+            write!(output, "{:02X}", byte)
+        };
+    }
+}
+
+// Lists the stack from the top down, as words where there's no telling
+// what was pushed. Return addresses show where they go back to, with a ?
+// on the ones that no frame on the call stack accounts for, and `rti` for
+// interrupts.
+pub fn write_stack(output: &mut String, stack: &Stack) {
+    for (i, (_, entry)) in stack.entries().into_iter().enumerate() {
+        if i > 0 {
+            output.push_str(", ");
+        }
+
+        write_stack_entry(output, &entry);
+    }
+
+    if stack.truncated {
+        output.push_str(if stack.bytes.is_empty() {
+            "..."
+        } else {
+            ", ..."
+        });
+    }
+}
+
+pub fn write_stack_entry(output: &mut String, entry: &StackEntry) {
+    let _ = match entry {
+        StackEntry::Byte(byte) => write!(output, "{:02X}", byte),
+        StackEntry::Word(word) => write!(output, "{:04X}", word),
+        StackEntry::Return(slot) => write!(
+            output,
+            "{}{} {:06X}",
+            match slot.kind {
+                CallKind::Interrupt { .. } => "rti",
+                _ => "ret",
+            },
+            if slot.confirmed { "" } else { "?" },
+            slot.returns_to
+        ),
+    };
+}
+
+// Names the target of an instruction, if there's a label for it.
+fn write_target_label(output: &mut String, record: &TraceRecord, symbols: &Symbols) {
+    if let Some(label) = record
+        .disassembly
+        .effective_addr
+        .and_then(|addr| symbols.label(addr))
+    {
+        let _ = write!(output, " ({})", label);
+    }
+}
+
+pub fn trace_entry(record: &TraceRecord, symbols: &Symbols) -> String {
+    let mut output = String::new();
+    write_trace_entry(&mut output, record, symbols);
+
+    output
+}
+
+// The same as trace_entry, but appended to an existing buffer, so that the
+// allocation can be reused from one instruction to the next.
+pub fn write_trace_entry(output: &mut String, record: &TraceRecord, symbols: &Symbols) {
+    let pc = record.cpu.current_addr();
+
+    let _ = write!(output, "[{:>06X}] ", pc);
+
+    let start = output.len();
+    write_bytes(output, record.disassembly.bytes(), false);
+    pad(output, start, 11);
+
+    let _ = write!(output, " {}", record.instruction);
+
+    let mode = record.instruction.addressing_mode();
+
+    if !mode.is_empty() {
+        let _ = write!(output, " {}", mode);
+    }
+
+    if let Some(addr) = record.disassembly.effective_addr {
+        let _ = write!(output, " [{:06X}]", addr);
+    }
+
+    write_target_label(output, record, symbols);
+
+    if let Some(location) = symbols.describe(pc) {
+        let _ = write!(output, " ; {}", location);
+    }
+
+    output.push_str("\n         ");
+    let _ = record.cpu.register_debug_to(output);
+    let _ = write!(output, " | Cycles: {}", record.cycles);
+
+    if let Some(operand) = record.operand {
+        let _ = write!(output, " | Operand: {}", operand);
+    }
+
+    output.push_str("\n         Stack: [");
+    write_stack(output, &record.stack);
+    output.push(']');
+}
+
+// Appends the calls in progress to a crash report, if there are any.
+pub fn write_calls(output: &mut String, call_stack: &[CallFrame], symbols: &Symbols) {
+    if call_stack.is_empty() {
+        return;
+    }
+
+    output.push_str("\n    Calls:");
+
+    for line in debugger::format_backtrace(call_stack, symbols).lines() {
+        let _ = write!(output, "\n      {}", line);
+    }
+}
+
+// Describes an opcode that the CPU doesn't implement, along with the state
+// of the machine and the instructions around it. The window should include
+// the instruction at the current address.
+pub fn unknown_opcode_banner(
+    record: &TraceRecord,
+    window: &[Disassembly],
+    call_stack: &[CallFrame],
+    symbols: &Symbols,
+) -> String {
+    let pc = record.cpu.current_addr();
+    let opcode = record.disassembly.opcode();
+
+    let location = match symbols.describe(pc) {
+        Some(location) => format!(" ({})", location),
+        None => String::new(),
+    };
+
+    let mut output = format!(
+        "*** Unknown opcode ${:02X} ({}) at {:06X}{}\n    ",
+        opcode,
+        disasm::mnemonic(opcode),
+        pc,
+        location,
+    );
+
+    let _ = record.cpu.register_debug_to(&mut output);
+    let _ = write!(output, " | Cycles: {}\n    Stack: [", record.cycles);
+    write_stack(&mut output, &record.stack);
+    output.push(']');
+
+    write_calls(&mut output, call_stack, symbols);
+
+    for disassembly in window {
+        let marker = if disassembly.pc == pc { ">" } else { " " };
+        let _ = write!(output, "\n  {} {:06X} ", marker, disassembly.pc);
+
+        let start = output.len();
+        write_bytes(&mut output, disassembly.bytes(), false);
+        pad(&mut output, start, 11);
+
+        output.push(' ');
+        disassembly.write_text(&mut output);
+    }
+
+    output
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Default,
+
+    // Matches the layout of bsnes-plus, so that logs can be diffed against
+    // it line by line.
+    Bsnes,
+
+    // One JSON object per line, for analysing with other tools.
+    #[cfg(feature = "trace-json")]
+    Json,
+}
+
+impl TraceFormat {
+    pub fn format(self, record: &TraceRecord, symbols: &Symbols) -> String {
+        let mut output = String::new();
+        self.write(&mut output, record, symbols);
+
+        output
+    }
+
+    pub fn write(self, output: &mut String, record: &TraceRecord, symbols: &Symbols) {
+        match self {
+            TraceFormat::Default => write_trace_entry(output, record, symbols),
+            TraceFormat::Bsnes => write_bsnes_trace_entry(output, record, symbols),
+            #[cfg(feature = "trace-json")]
+            TraceFormat::Json => write_json_trace_entry(output, record),
+        }
+    }
+}
+
+pub fn bsnes_trace_entry(record: &TraceRecord, symbols: &Symbols) -> String {
+    let mut output = String::new();
+    write_bsnes_trace_entry(&mut output, record, symbols);
+
+    output
+}
+
+pub fn write_bsnes_trace_entry(output: &mut String, record: &TraceRecord, symbols: &Symbols) {
+    let cpu = &record.cpu;
+    let disassembly = &record.disassembly;
+
+    let _ = write!(output, "{:06x} ", cpu.current_addr());
+
+    let start = output.len();
+    write_bytes(output, disassembly.bytes(), true);
+    pad(output, start, 11);
+    output.push(' ');
+
+    // The operand the instruction really accessed is left out, as
+    // bsnes-plus has no such column and the lines have to match it. The
+    // predicted effective address is usually the same anyway.
+    //
+    // Labels come after the effective address, so that logs without any
+    // symbols loaded still line up with bsnes-plus.
+    let start = output.len();
+    disassembly.write_text(output);
+
+    if let Some(addr) = disassembly.effective_addr {
+        pad(output, start, 14);
+        let _ = write!(output, "[{:06x}]", addr);
+        write_target_label(output, record, symbols);
+    }
+
+    pad(output, start, 22);
+
+    let _ = write!(
+        output,
+        " A:{:04x} X:{:04x} Y:{:04x} S:{:04x} D:{:04x} DB:{:02x} ",
+        cpu.a, cpu.x, cpu.y, cpu.sp, cpu.direct_page, cpu.data_bank,
+    );
+
+    // In emulation mode, M and X always read as set.
+    let mut status = cpu.status;
+
+    if cpu.emulation {
+        status |= 0x30;
+    }
+
+    for (i, c) in "nvmxdizc".chars().enumerate() {
+        if status & (0x80 >> i) != 0 {
+            output.push(c.to_ascii_uppercase());
+        } else {
+            output.push(c);
+        }
+    }
+
+    let _ = write!(output, " V:{:3} H:{:4}", record.scanline, record.h_counter);
+}
+
+#[cfg(feature = "trace-json")]
+#[derive(serde::Serialize)]
+struct JsonEntry<'a> {
+    pc: u32,
+    bytes: &'a [u8],
+    mnemonic: &'static str,
+    text: String,
+    a: u16,
+    x: u16,
+    y: u16,
+    sp: u16,
+    d: u16,
+    db: u8,
+    pb: u8,
+    flags: JsonFlags,
+    cycles: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effective_addr: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operand_addr: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operand_access: Option<&'static str>,
+    frame: u64,
+    scanline: u16,
+    h_counter: u16,
+}
+
+#[cfg(feature = "trace-json")]
+#[derive(serde::Serialize)]
+struct JsonFlags {
+    n: bool,
+    v: bool,
+    m: bool,
+    x: bool,
+    d: bool,
+    i: bool,
+    z: bool,
+    c: bool,
+    e: bool,
+}
+
+// Writes a record as a single JSON object, with every number as a plain
+// integer rather than hex:
+//
+//   pc              the instruction's 24-bit address
+//   bytes           the instruction's bytes, opcode first
+//   mnemonic        e.g. "lda"
+//   text            the whole instruction, e.g. "lda $10,x"
+//   a x y sp d      registers, before the instruction ran
+//   db pb           data and program banks
+//   flags           n v m x d i z c as booleans, and e for emulation mode
+//   cycles          CPU cycles run before this instruction
+//   effective_addr  the address the instruction will access, and left out
+//                   when that can't be worked out ahead of time
+//   operand_addr operand_access
+//                   the address the operand was accessed at when the
+//                   instruction ran, and "read", "write" or "modify", both
+//                   left out for instructions without a memory operand
+//   frame scanline h_counter
+//                   where the PPU was
+//
+// e.g. {"pc":32768,"bytes":[169,1],"mnemonic":"lda","text":"lda #$01",...}
+//
+// Anything else written to the log, such as reports of unknown opcodes and
+// the state of the machine at the end, is plain text, so skip lines that
+// don't start with `{`.
+#[cfg(feature = "trace-json")]
+pub fn write_json_trace_entry(output: &mut String, record: &TraceRecord) {
+    use crate::cpu::Flags;
+
+    let cpu = &record.cpu;
+
+    // In emulation mode, M and X always read as set.
+    let status = match cpu.emulation {
+        true => cpu.status | 0x30,
+        false => cpu.status,
+    };
+
+    let flag = |flag: Flags| status & flag.bits() != 0;
+
+    let entry = JsonEntry {
+        pc: cpu.current_addr(),
+        bytes: record.disassembly.bytes(),
+        mnemonic: disasm::mnemonic(record.disassembly.opcode()),
+        text: record.disassembly.text(),
+        a: cpu.a,
+        x: cpu.x,
+        y: cpu.y,
+        sp: cpu.sp,
+        d: cpu.direct_page,
+        db: cpu.data_bank,
+        pb: cpu.program_bank,
+        flags: JsonFlags {
+            n: flag(Flags::NEGATIVE),
+            v: flag(Flags::OVERFLOW),
+            m: flag(Flags::MEMORY_SELECT),
+            x: flag(Flags::INDEX_REGISTER),
+            d: flag(Flags::DECIMAL_MODE),
+            i: flag(Flags::IRQ_DISABLE),
+            z: flag(Flags::ZERO),
+            c: flag(Flags::CARRY),
+            e: cpu.emulation,
+        },
+        cycles: record.cycles,
+        effective_addr: record.disassembly.effective_addr,
+        operand_addr: record.operand.map(|operand| operand.addr),
+        operand_access: record.operand.map(|operand| operand.access.name()),
+        frame: record.frame,
+        scanline: record.scanline,
+        h_counter: record.h_counter,
+    };
+
+    // Serializing plain numbers, strings and booleans can't fail.
+    output.push_str(&serde_json::to_string(&entry).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{lorom, Asm};
+    use crate::emulator::Emulator;
+    use crate::mmu::MapMode;
+
+    // Runs the code from $8000 in a LoROM, capturing a record before each
+    // instruction.
+    fn records(code: &[u8], count: usize) -> Vec<TraceRecord> {
+        let mut rom = vec![0; 0x8000];
+        rom[..code.len()].copy_from_slice(code);
+        rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+        let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+
+        (0..count).map(|_| emulator.step_traced().0).collect()
+    }
+
+    #[test]
+    fn bsnes_lines() {
+        #[rustfmt::skip]
+        let records = records(&[
+            0x18,             // CLC
+            0xFB,             // XCE
+            0xE2, 0x20,       // SEP #$20
+            0xC2, 0x10,       // REP #$10
+            0xA9, 0x7F,       // LDA #$7F
+            0xA2, 0x34, 0x12, // LDX #$1234
+            0x8D, 0x00, 0x21, // STA $2100
+            0xA5, 0x10,       // LDA $10
+            0x9D, 0x00, 0x01, // STA $0100,X
+            0xD0, 0xF0,       // BNE $8005
+            0x20, 0x00, 0x90, // JSR $9000
+        ], 11);
+
+        let lines: Vec<String> = records
+            .iter()
+            .map(|record| bsnes_trace_entry(record, &Symbols::new()))
+            .collect();
+
+        // M and X read as set in emulation mode, whatever the status byte
+        // holds.
+        assert_eq!(
+            lines[0],
+            "008000 18          clc                    A:0000 X:0000 Y:0000 S:01ff D:0000 DB:00 nvMXdizc V:  0 H:   0"
+        );
+        assert_eq!(
+            lines[4],
+            "008006 a9 7f       lda #$7f               A:0000 X:0000 Y:0000 S:01ff D:0000 DB:00 nvMxdizC V:  0 H:  72"
+        );
+        assert_eq!(
+            lines[5],
+            "008008 a2 34 12    ldx #$1234             A:007f X:0000 Y:0000 S:01ff D:0000 DB:00 nvMxdizC V:  0 H:  94"
+        );
+        assert_eq!(
+            lines[6],
+            "00800b 8d 00 21    sta $2100     [002100] A:007f X:1234 Y:0000 S:01ff D:0000 DB:00 nvMxdizC V:  0 H: 124"
+        );
+        assert_eq!(
+            lines[8],
+            "008010 9d 00 01    sta $0100,x   [001334] A:0000 X:1234 Y:0000 S:01ff D:0000 DB:00 nvMxdiZC V:  0 H: 190"
+        );
+        assert_eq!(
+            lines[9],
+            "008013 d0 f0       bne $8005     [008005] A:0000 X:1234 Y:0000 S:01ff D:0000 DB:00 nvMxdiZC V:  0 H: 228"
+        );
+        assert_eq!(
+            lines[10],
+            "008015 20 00 90    jsr $9000     [009000] A:0000 X:1234 Y:0000 S:01ff D:0000 DB:00 nvMxdiZC V:  0 H: 250"
+        );
+    }
+
+    #[cfg(feature = "trace-json")]
+    #[test]
+    fn json_lines_parse_back() {
+        #[rustfmt::skip]
+        let records = records(&[
+            0x18,             // CLC
+            0xFB,             // XCE
+            0xC2, 0x10,       // REP #$10
+            0xA9, 0x7F,       // LDA #$7F
+            0xA2, 0x34, 0x12, // LDX #$1234
+            0x9D, 0x00, 0x01, // STA $0100,X
+        ], 6);
+
+        let entries: Vec<serde_json::Value> = records
+            .iter()
+            .map(|record| {
+                let mut line = String::new();
+                write_json_trace_entry(&mut line, record);
+
+                assert!(line.starts_with('{') && !line.contains('\n'), "{}", line);
+                serde_json::from_str(&line).unwrap()
+            })
+            .collect();
+
+        let clc = &entries[0];
+        assert_eq!(clc["pc"], 0x8000);
+        assert_eq!(clc["bytes"], serde_json::json!([0x18]));
+        assert_eq!(clc["mnemonic"], "clc");
+        assert_eq!(clc["cycles"], 0);
+        assert_eq!((&clc["frame"], &clc["scanline"]), (&0.into(), &0.into()));
+        assert!(clc.get("effective_addr").is_none());
+        assert!(clc.get("operand_access").is_none());
+
+        // M and X read as set in emulation mode.
+        let flags = |entry: &serde_json::Value| {
+            ["n", "v", "m", "x", "d", "i", "z", "c", "e"]
+                .map(|flag| entry["flags"][flag].as_bool().unwrap())
+        };
+        assert_eq!(
+            flags(clc),
+            [false, false, true, true, false, false, false, false, true]
+        );
+
+        let sta = &entries[5];
+        assert_eq!(sta["pc"], 0x8009);
+        assert_eq!(sta["bytes"], serde_json::json!([0x9D, 0x00, 0x01]));
+        assert_eq!(sta["text"], "sta $0100,x");
+        assert_eq!((&sta["a"], &sta["x"]), (&0x7F.into(), &0x1234.into()));
+        assert_eq!(sta["effective_addr"], 0x1334);
+        assert_eq!(sta["operand_addr"], 0x1334);
+        assert_eq!(sta["operand_access"], "write");
+        assert_eq!(sta["h_counter"], records[5].h_counter);
+        assert!(sta["cycles"].as_u64().unwrap() > 0);
+        assert_eq!(
+            flags(sta),
+            [false, false, true, false, false, false, false, true, false]
+        );
+    }
+
+    #[test]
+    fn labels() {
+        #[rustfmt::skip]
+        let records = records(&[
+            0x20, 0x00, 0x90, // JSR $9000
+        ], 1);
+
+        let symbols = Symbols::parse("[labels]\n00:8000 Reset\n00:9000 DrawMenuBox\n").unwrap();
+
+        assert!(trace_entry(&records[0], &symbols)
+            .starts_with("[008000] 20 00 90    JSR abs [009000] (DrawMenuBox) ; Reset\n"));
+        assert!(bsnes_trace_entry(&records[0], &symbols)
+            .starts_with("008000 20 00 90    jsr $9000     [009000] (DrawMenuBox)"));
+    }
+
+    #[test]
+    fn stack_is_captured_when_the_record_is() {
+        #[rustfmt::skip]
+        let records = records(&[
+            0x18,             // CLC
+            0xFB,             // XCE
+            0xC2, 0x30,       // REP #$30
+            0xA9, 0x34, 0x12, // LDA #$1234
+            0x48,             // PHA
+            0xA9, 0xCD, 0xAB, // LDA #$ABCD
+            0x8D, 0xFE, 0x01, // STA $01FE
+            0xEA,             // NOP
+        ], 8);
+
+        // By the time the log is written, the pushed word has been
+        // overwritten, but the record from before that still has it.
+        assert_eq!(records[5].stack.bytes, [0x34, 0x12]);
+        assert_eq!(records[7].stack.bytes, [0xCD, 0xAB]);
+
+        let symbols = Symbols::new();
+        assert!(trace_entry(&records[5], &symbols).ends_with("Stack: [1234]"));
+        assert!(trace_entry(&records[7], &symbols).ends_with("Stack: [ABCD]"));
+    }
+
+    #[test]
+    fn records_replay_from_a_snapshot() {
+        // Pushes and pops around a subroutine call, so that the records have
+        // something on the stack.
+        #[rustfmt::skip]
+        let code = Asm::at(0x8000)
+            .clc().xce()
+            .rep(0x30)
+            .label("loop")
+            .inx()
+            .phx()
+            .jsr_to("store")
+            .plx()
+            .bra("loop")
+            .label("store")
+            .lda_imm16(0x1234)
+            .sta_abs_x(0x0100)
+            .rts()
+            .assemble()
+            .unwrap();
+
+        let mut emulator = Emulator::new(lorom(&code), Some(MapMode::LoRom)).unwrap();
+
+        for _ in 0..100 {
+            emulator.step_instruction();
+        }
+
+        let snapshot = emulator.snapshot();
+        let symbols = Symbols::new();
+
+        let run = |emulator: &mut Emulator| -> Vec<String> {
+            (0..200)
+                .map(|_| {
+                    let state = emulator.cpu.state();
+                    let cycles = emulator.cpu.cycles();
+
+                    let (record, _) = emulator.step_traced();
+
+                    // The record holds the state from before the
+                    // instruction ran.
+                    assert_eq!((record.cpu, record.cycles), (state, cycles));
+
+                    trace_entry(&record, &symbols)
+                })
+                .collect()
+        };
+
+        let first = run(&mut emulator);
+        assert!(first.iter().any(|line| line.contains("ret 00")));
+
+        // Records captured after restoring the full machine state log the
+        // same as the first time around.
+        emulator.restore(snapshot);
+        assert_eq!(run(&mut emulator), first);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        // Copies a loop into RAM that replaces its first instruction, INC,
+        // with INX the first time around.
+        #[rustfmt::skip]
+        let ram_code = [
+            0x1A,             // INC
+            0xA9, 0xE8,       // LDA #$E8
+            0x8D, 0x00, 0x02, // STA $0200
+            0x80, 0xF8,       // BRA $0200
+        ];
+
+        let mut code = vec![0xE2, 0x20]; // SEP #$20
+
+        for (i, byte) in ram_code.into_iter().enumerate() {
+            // LDA #byte, STA $0200+i
+            code.extend([0xA9, byte, 0x8D, i as u8, 0x02]);
+        }
+
+        code.extend([0x4C, 0x00, 0x02]); // JMP $0200
+
+        let records = records(&code, 1 + 16 + 1 + 5);
+        let executed = &records[18..];
+
+        let symbols = Symbols::new();
+        let lines: Vec<String> = executed
+            .iter()
+            .map(|record| trace_entry(record, &symbols))
+            .collect();
+
+        assert!(
+            lines[0].starts_with("[000200] 1A          INC"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[4].starts_with("[000200] E8          INX"),
+            "{}",
+            lines[4]
+        );
+
+        // The effective address is worked out ahead of time, and the
+        // operand is where the instruction really wrote.
+        assert!(
+            lines[2].starts_with("[000203] 8D 00 02    STA abs [000200]"),
+            "{}",
+            lines[2]
+        );
+        assert!(lines[2].contains("| Operand: write 000200"), "{}", lines[2]);
+    }
+}
@@ -0,0 +1,70 @@
+// A JavaScript interface to the core, for running it in a browser:
+//
+//   const snes = new Snes(romBytes);
+//   snes.set_input(buttons);
+//   snes.run_frame();
+//   context.putImageData(new ImageData(snes.framebuffer(), Snes.width(), Snes.height()), 0, 0);
+//
+// The command line frontend deals in files, and has no place in a browser,
+// so build it without the default features:
+//
+//   wasm-pack build -- --no-default-features --features wasm
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+
+use crate::emulator::Emulator;
+use crate::input::Buttons;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[wasm_bindgen]
+pub struct Snes {
+    emulator: Emulator,
+}
+
+#[wasm_bindgen]
+impl Snes {
+    // Loads a ROM, working out its mapping from the header. The error is
+    // thrown as a string on the JavaScript side.
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8]) -> Result<Snes, String> {
+        Ok(Snes {
+            emulator: Emulator::new(rom.to_vec(), None)?,
+        })
+    }
+
+    pub fn run_frame(&mut self) {
+        self.emulator.run_frame();
+    }
+
+    // The last frame as RGBA, which comes out as a Uint8ClampedArray, ready
+    // to be put in an ImageData.
+    pub fn framebuffer(&self) -> Clamped<Vec<u8>> {
+        Clamped(self.emulator.framebuffer().to_vec())
+    }
+
+    pub fn width() -> u32 {
+        SCREEN_WIDTH as u32
+    }
+
+    pub fn height() -> u32 {
+        SCREEN_HEIGHT as u32
+    }
+
+    // Sets the buttons held on pad 1, using the same bits as the auto-read
+    // registers.
+    pub fn set_input(&mut self, buttons: u16) {
+        self.emulator
+            .set_input(Buttons::from_bits_truncate(buttons));
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.emulator.frame()
+    }
+
+    // Reads a byte from the CPU's address space, without any of the side
+    // effects a read by the CPU would have.
+    pub fn peek(&self, addr: u32) -> u8 {
+        self.emulator.mmu.peek_u8(addr)
+    }
+}
@@ -0,0 +1,246 @@
+// Runs a directory of tiny synthetic ROMs through the batch runner, each
+// written to end its run in a different way, and checks the report.
+
+#![cfg(feature = "frontend")]
+
+use std::path::{Path, PathBuf};
+
+use snesemu::asm::{lorom, Asm};
+use snesemu::frontend::batch::{
+    batch_csv, batch_json, find_roms, run_rom, sort_results, Limits, Outcome,
+};
+
+const LIMITS: Limits = Limits {
+    frames: Some(30),
+    instructions: None,
+    time: None,
+};
+
+fn start() -> Asm {
+    Asm::at(0x8000).clc().xce().sep(0x20)
+}
+
+// Spins with interrupts off, after touching an unmapped register.
+fn limit() -> Vec<u8> {
+    let code = start()
+        .sta_abs(0x4100)
+        .label("spin")
+        .bra("spin")
+        .assemble()
+        .unwrap();
+
+    lorom(&code)
+}
+
+// Turns on the NMI and spins, with an RTI at $FF00 to take it.
+fn steady() -> Vec<u8> {
+    let code = start()
+        .lda_imm8(0x80)
+        .sta_abs(0x4200)
+        .label("spin")
+        .bra("spin")
+        .assemble()
+        .unwrap();
+
+    let mut rom = lorom(&code);
+    rom[0x7F00] = 0x40;
+    rom[0x7FEA..0x7FEC].copy_from_slice(&[0x00, 0xFF]);
+    rom
+}
+
+// Waits for an interrupt that never comes, so the run ends waiting just
+// after the WAI.
+fn hung() -> Vec<u8> {
+    lorom(&start().sei().wai().assemble().unwrap())
+}
+
+// TCD isn't implemented.
+fn unknown_opcode() -> Vec<u8> {
+    lorom(&start().byte(0x5B).assemble().unwrap())
+}
+
+// Sends the DSP-1 a command it doesn't emulate, which panics.
+fn panics() -> Vec<u8> {
+    let code = start()
+        .ldx_imm8(0x00)
+        .lda_imm8(0x01)
+        .sta_long_x(0x30_8000)
+        .assemble()
+        .unwrap();
+
+    let mut rom = lorom(&code);
+    rom[0x7FD6] = 0x03;
+    rom
+}
+
+fn rom_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("snesemu-batch-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("nested.sfc")).unwrap();
+
+    std::fs::write(dir.join("limit.sfc"), limit()).unwrap();
+    std::fs::write(dir.join("steady.SMC"), steady()).unwrap();
+    std::fs::write(dir.join("hung.smc"), hung()).unwrap();
+    std::fs::write(dir.join("unknown.sfc"), unknown_opcode()).unwrap();
+
+    std::fs::write(dir.join("panic.sfc"), panics()).unwrap();
+
+    // Too small to have anywhere to reset to.
+    std::fs::write(dir.join("empty.sfc"), []).unwrap();
+
+    std::fs::write(dir.join("notes.txt"), "not a ROM").unwrap();
+    dir
+}
+
+fn names(paths: impl IntoIterator<Item = PathBuf>) -> Vec<String> {
+    paths
+        .into_iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn finds_roms() {
+    let dir = rom_dir();
+    let roms = find_roms(&dir).unwrap();
+
+    assert_eq!(
+        names(roms),
+        [
+            "empty.sfc",
+            "hung.smc",
+            "limit.sfc",
+            "panic.sfc",
+            "steady.SMC",
+            "unknown.sfc"
+        ]
+    );
+    assert!(find_roms(dir.join("missing")).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn outcomes() {
+    let dir = rom_dir();
+
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let mut results: Vec<_> = find_roms(&dir)
+        .unwrap()
+        .iter()
+        .map(|rom| run_rom(rom, LIMITS))
+        .collect();
+
+    results.push(run_rom(&dir.join("missing.sfc"), LIMITS));
+    std::panic::set_hook(hook);
+
+    sort_results(&mut results);
+
+    let outcomes: Vec<_> = results.iter().map(|result| &result.outcome).collect();
+    let names = names(results.iter().map(|result| result.path.clone()));
+
+    assert_eq!(
+        names,
+        [
+            "empty.sfc",
+            "missing.sfc",
+            "panic.sfc",
+            "unknown.sfc",
+            "hung.smc",
+            "limit.sfc",
+            "steady.SMC"
+        ]
+    );
+
+    assert!(matches!(outcomes[0], Outcome::Unreadable { .. }));
+    assert!(matches!(outcomes[1], Outcome::Unreadable { .. }));
+    assert!(matches!(outcomes[2], Outcome::Panic { pc: Some(_), .. }));
+    assert_eq!(
+        *outcomes[3],
+        Outcome::UnknownOpcode {
+            opcode: 0x5B,
+            pc: 0x00_8004
+        }
+    );
+    assert_eq!(*outcomes[4], Outcome::Hung { pc: 0x00_8006 });
+    assert_eq!(*outcomes[5], Outcome::Limit);
+    assert_eq!(*outcomes[6], Outcome::Steady);
+
+    // Only the limit ROM touches an unmapped register, and the ones that
+    // reached the limit ran for all of it.
+    let limit = &results[5];
+    assert_eq!(limit.unmapped.iter().copied().collect::<Vec<_>>(), [0x4100]);
+    assert!(results
+        .iter()
+        .filter(|result| result.path != limit.path)
+        .all(|result| result.unmapped.is_empty()));
+
+    assert_eq!(results[5].frames, 30);
+    assert_eq!(results[6].frames, 30);
+    assert_eq!(results[0].instructions, 0);
+    assert_eq!(results[1].instructions, 0);
+
+    check_csv(&batch_csv(&results));
+    check_json(&batch_json(&results), &dir);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn check_csv(csv: &str) {
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "rom,outcome,pc,detail,frames,instructions,unmapped"
+    );
+    assert_eq!(lines.len(), 8);
+
+    let columns: Vec<Vec<&str>> = lines[1..]
+        .iter()
+        .map(|line| line.rsplitn(6, ',').collect())
+        .collect();
+
+    // The columns after the path, last first.
+    assert_eq!(columns[2][3], "DSP-1 command $01 isn't emulated");
+    assert_eq!(columns[3][..5], ["", "4", "0", "$5B", "008004"]);
+    assert_eq!(columns[4][4], "008006");
+    assert_eq!(columns[5][0], "4100");
+    assert!(columns[5][5].ends_with(",limit"));
+    assert!(columns[6][5].ends_with(",steady"));
+}
+
+fn check_json(json: &str, dir: &Path) {
+    let json: serde_json::Value = serde_json::from_str(json).unwrap();
+    let roms = json["roms"].as_array().unwrap();
+    assert_eq!(roms.len(), 7);
+
+    let outcomes: Vec<_> = roms
+        .iter()
+        .map(|rom| rom["outcome"].as_str().unwrap())
+        .collect();
+
+    assert_eq!(
+        outcomes,
+        [
+            "unreadable",
+            "unreadable",
+            "panic",
+            "unknown_opcode",
+            "hung",
+            "limit",
+            "steady"
+        ]
+    );
+
+    assert_eq!(roms[0]["message"], "too small to be a ROM (0 bytes)");
+    assert!(roms[1]["message"].is_string());
+    assert!(roms[2]["message"].is_string());
+    assert_eq!(roms[3]["opcode"], 0x5B);
+    assert_eq!(roms[3]["pc"], 0x00_8004);
+    assert_eq!(roms[5]["unmapped"], serde_json::json!([0x4100]));
+    assert_eq!(
+        roms[6]["rom"],
+        dir.join("steady.SMC").display().to_string().as_str()
+    );
+}
@@ -0,0 +1,809 @@
+// Runs single instructions on the CPU through its public interface only, on
+// a flat bus with nothing else attached.
+
+use snesemu::asm::Asm;
+use snesemu::bus::FlatBus;
+use snesemu::cpu::{CallFrame, CallKind, Cpu, CpuState, Flags, Operand, OperandAccess, Register};
+use snesemu::mmu::RamInit;
+
+// A CPU in native mode with the given status, about to run the code at
+// $00:8000.
+fn cpu(status: u8, a: u16, code: &[u8]) -> (Cpu, FlatBus) {
+    let mut cpu = Cpu::new();
+    let mut bus = FlatBus::new();
+
+    cpu.set_state(&CpuState {
+        a,
+        pc: 0x8000,
+        sp: 0x01FF,
+        status,
+        ..CpuState::default()
+    });
+
+    bus.load(0x00_8000, code);
+
+    (cpu, bus)
+}
+
+// Starts a test program, to be run from $8000.
+fn asm() -> Asm {
+    Asm::at(0x8000)
+}
+
+#[test]
+fn exchange_b_and_a() {
+    let (mut cpu, mut bus) = cpu(0x00, 0x12F0, &asm().xba().assemble().unwrap());
+    cpu.tick(&mut bus);
+
+    // The flags come from the new low byte, even with a 16-bit accumulator.
+    assert_eq!(cpu.state().a, 0xF012);
+    assert_eq!(cpu.b(), 0xF0);
+    assert!(!cpu.flag(Flags::NEGATIVE) && !cpu.flag(Flags::ZERO));
+    assert_eq!(cpu.pc(), 0x8001);
+}
+
+#[test]
+fn eight_bit_loads_keep_b() {
+    let code = asm().lda_imm8(0x80).assemble().unwrap();
+    let (mut cpu, mut bus) = cpu(Flags::MEMORY_SELECT.bits(), 0xAB00, &code);
+    cpu.tick(&mut bus);
+
+    assert_eq!(cpu.get_register(Register::A), 0xAB80);
+    assert!(cpu.is_eight_bit_mode(Register::A));
+    assert!(cpu.flag(Flags::NEGATIVE));
+    assert_eq!(cpu.pc(), 0x8002);
+}
+
+#[test]
+fn push_and_pull() {
+    let (mut cpu, mut bus) = cpu(0x00, 0x1234, &asm().pha().plx().assemble().unwrap());
+
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.sp(), 0x01FD);
+    assert_eq!(bus.memory()[0x01FE..0x0200], [0x34, 0x12]);
+
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.sp(), 0x01FF);
+    assert_eq!(cpu.get_register(Register::X), 0x1234);
+}
+
+#[test]
+fn load_state_enforces_emulation_mode() {
+    let state = CpuState {
+        x: 0x1234,
+        y: 0x5678,
+        sp: 0x1234,
+        emulation: true,
+        ..CpuState::default()
+    };
+
+    // set_state takes the registers as they are...
+    let mut cpu = Cpu::new();
+    cpu.set_state(&state);
+    assert_eq!(cpu.state(), state);
+
+    // ...while load_state makes them something the hardware could hold.
+    cpu.load_state(&state);
+
+    let loaded = cpu.save_state();
+    assert_eq!((loaded.x, loaded.y, loaded.sp), (0x34, 0x78, 0x0134));
+    assert!(cpu.flag(Flags::MEMORY_SELECT) && cpu.flag(Flags::INDEX_REGISTER));
+
+    // Which then round trips exactly.
+    let mut copy = Cpu::new();
+    copy.load_state(&loaded);
+    assert_eq!(copy.save_state(), loaded);
+
+    assert!(format!("{:?}", copy).contains("emulation: true"));
+}
+
+// A state with every register random, which the hardware mightn't be able
+// to hold.
+fn random_state(seed: u64) -> CpuState {
+    let mut bytes = [0; 16];
+    RamInit::Random(seed).fill(&mut bytes);
+
+    let word = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+
+    CpuState {
+        a: word(0),
+        x: word(2),
+        y: word(4),
+        pc: word(6),
+        sp: word(8),
+        direct_page: word(10),
+        program_bank: bytes[12],
+        data_bank: bytes[13],
+        status: bytes[14],
+        emulation: bytes[15] & 1 == 1,
+    }
+}
+
+#[test]
+fn random_states_round_trip() {
+    for seed in 0..1000 {
+        let state = random_state(seed);
+
+        let mut cpu = Cpu::new();
+        cpu.load_state(&state);
+        let loaded = cpu.save_state();
+
+        // Only what the mode can't hold changes.
+        let x8 = state.emulation || state.status & Flags::INDEX_REGISTER.bits() != 0;
+        let index = |value: u16| if x8 { value & 0xFF } else { value };
+
+        let expected = CpuState {
+            x: index(state.x),
+            y: index(state.y),
+            sp: if state.emulation {
+                0x0100 | (state.sp & 0xFF)
+            } else {
+                state.sp
+            },
+            status: if state.emulation {
+                state.status | 0x30
+            } else {
+                state.status
+            },
+            ..state
+        };
+
+        assert_eq!(loaded, expected, "seed {}", seed);
+
+        // What comes out loads back exactly.
+        let mut copy = Cpu::new();
+        copy.load_state(&loaded);
+        assert_eq!(copy.save_state(), loaded, "seed {}", seed);
+    }
+}
+
+#[test]
+fn eight_bit_index_state_drops_high_bytes() {
+    // Native mode, with X set.
+    let mut cpu = Cpu::new();
+    cpu.load_state(&CpuState {
+        x: 0x1234,
+        y: 0x5678,
+        sp: 0x1FFF,
+        status: Flags::INDEX_REGISTER.bits(),
+        ..CpuState::default()
+    });
+
+    let loaded = cpu.save_state();
+    assert_eq!((loaded.x, loaded.y, loaded.sp), (0x34, 0x78, 0x1FFF));
+    assert!(!cpu.flag(Flags::MEMORY_SELECT));
+}
+
+#[test]
+fn pull_status_takes_every_flag() {
+    // N and Z are both set on the stack, which no ALU result could do.
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &asm().plp().assemble().unwrap());
+    bus.load(0x00_0200, &[0x82]);
+    cpu.tick(&mut bus);
+
+    assert_eq!(cpu.state().status, 0x82);
+    assert!(cpu.flag(Flags::NEGATIVE) && cpu.flag(Flags::ZERO));
+}
+
+#[test]
+fn exchange_carry_and_emulation() {
+    // In native mode with the carry clear.
+    let (mut native, mut bus) = cpu(0x00, 0x0000, &asm().xce().assemble().unwrap());
+
+    // Swapping two clear bits leaves both of them clear.
+    native.tick(&mut bus);
+    assert!(!native.flag(Flags::CARRY) && !native.state().emulation);
+
+    // Into emulation mode, then back out.
+    let code = asm().sep(0x01).xce().clc().xce().assemble().unwrap();
+    let (mut round_trip, mut bus) = cpu(0x00, 0x0000, &code);
+
+    round_trip.tick(&mut bus);
+    round_trip.tick(&mut bus);
+    assert!(!round_trip.flag(Flags::CARRY) && round_trip.state().emulation);
+
+    round_trip.tick(&mut bus);
+    round_trip.tick(&mut bus);
+    assert!(round_trip.flag(Flags::CARRY) && !round_trip.state().emulation);
+
+    // Leaving emulation mode doesn't clear M and X.
+    assert!(round_trip.is_eight_bit_mode(Register::A));
+    assert!(round_trip.is_eight_bit_mode(Register::X));
+}
+
+#[test]
+fn eight_bit_index_registers_drop_the_high_byte() {
+    let code = asm()
+        .ldx_imm16(0x1234)
+        .sep(0x10)
+        .rep(0x10)
+        .assemble()
+        .unwrap();
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &code);
+
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.get_register(Register::X), 0x34);
+
+    // It's gone, not just hidden.
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.get_register(Register::X), 0x0034);
+}
+
+// The same, but in emulation mode, with the IRQ/BRK vector pointing at
+// $9000 and the NMI vector at $9100.
+fn emulation_cpu(status: u8, code: &[u8]) -> (Cpu, FlatBus) {
+    let (mut cpu, mut bus) = cpu(status, 0x0000, code);
+
+    cpu.set_state(&CpuState {
+        emulation: true,
+        ..cpu.state()
+    });
+
+    bus.load(0x00_FFFA, &[0x00, 0x91]);
+    bus.load(0x00_FFFE, &[0x00, 0x90]);
+
+    (cpu, bus)
+}
+
+#[test]
+fn emulation_mode_break() {
+    // Bit 5 and the B flag are both clear in the live status.
+    let (mut cpu, mut bus) = emulation_cpu(0xC1, &asm().brk(0x42).assemble().unwrap());
+    cpu.tick(&mut bus);
+
+    // The status goes on top of the address after the signature byte, with
+    // B and bit 5 set, and no program bank below them.
+    assert_eq!(cpu.sp(), 0x01FC);
+    assert_eq!(bus.memory()[0x01FC..0x0200], [0x00, 0xF1, 0x02, 0x80]);
+
+    assert_eq!((cpu.program_bank(), cpu.pc()), (0x00, 0x9000));
+    assert!(cpu.flag(Flags::IRQ_DISABLE));
+}
+
+#[test]
+fn emulation_mode_irq() {
+    // The X flag is bit 4 of the live status, which has to be cleared in
+    // the pushed copy so that the handler doesn't take it for a BRK.
+    let (mut cpu, mut bus) = emulation_cpu(0xD9, &asm().inx().assemble().unwrap());
+    cpu.set_irq(true);
+    cpu.tick(&mut bus);
+
+    // The same vector as BRK, and the address of the instruction that
+    // didn't run. D is cleared in the live status, but not the pushed one.
+    assert_eq!(cpu.sp(), 0x01FC);
+    assert_eq!(bus.memory()[0x01FC..0x0200], [0x00, 0xE9, 0x00, 0x80]);
+
+    assert_eq!((cpu.program_bank(), cpu.pc()), (0x00, 0x9000));
+    assert!(cpu.flag(Flags::IRQ_DISABLE) && !cpu.flag(Flags::DECIMAL_MODE));
+}
+
+#[test]
+fn emulation_mode_nmi() {
+    let (mut cpu, mut bus) = emulation_cpu(0x00, &asm().inx().assemble().unwrap());
+    cpu.raise_nmi();
+    cpu.tick(&mut bus);
+
+    assert_eq!(bus.memory()[0x01FC..0x0200], [0x00, 0x20, 0x00, 0x80]);
+    assert_eq!(cpu.pc(), 0x9100);
+}
+
+#[test]
+fn native_mode_break() {
+    // From bank $12.
+    let (mut cpu, mut bus) = cpu(0xC1, 0x0000, &[]);
+    cpu.set_state(&CpuState {
+        program_bank: 0x12,
+        ..cpu.state()
+    });
+    bus.load(0x12_8000, &asm().brk(0x42).assemble().unwrap());
+    bus.load(0x00_FFE6, &[0x00, 0xA0]);
+
+    cpu.tick(&mut bus);
+
+    // Native mode pushes the program bank, and the status as it is.
+    assert_eq!(cpu.sp(), 0x01FB);
+    assert_eq!(bus.memory()[0x01FC..0x0200], [0xC1, 0x02, 0x80, 0x12]);
+    assert_eq!((cpu.program_bank(), cpu.pc()), (0x00, 0xA000));
+}
+
+// A native mode CPU with I set, whose IRQ handler at $9000 starts with
+// the given code.
+fn masked_cpu(code: &[u8], handler: &[u8]) -> (Cpu, FlatBus) {
+    let (cpu, mut bus) = cpu(Flags::IRQ_DISABLE.bits(), 0x0000, code);
+
+    bus.load(0x00_FFEE, &[0x00, 0x90]);
+    bus.load(0x00_9000, handler);
+
+    (cpu, bus)
+}
+
+#[test]
+fn irq_is_taken_straight_after_cli() {
+    let (mut cpu, mut bus) = masked_cpu(&asm().cli().inx().assemble().unwrap(), &[]);
+    cpu.set_irq(true);
+
+    // Held off while I is set...
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x8001);
+
+    // ...then taken before the next instruction, without a delay.
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x9000);
+    assert_eq!(bus.memory()[0x01FC..0x0200], [0x00, 0x01, 0x80, 0x00]);
+    assert_eq!(cpu.get_register(Register::X), 0);
+}
+
+#[test]
+fn irq_is_taken_after_plp_and_rti_clear_i() {
+    // PLP, with a clear status on the stack.
+    let (mut cpu, mut bus) = masked_cpu(&asm().plp().assemble().unwrap(), &[]);
+    bus.load(0x00_0200, &[0x00]);
+    cpu.set_irq(true);
+
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x9000);
+
+    // RTI, back to $8123 with a clear status.
+    let (mut cpu, mut bus) = masked_cpu(&asm().rti().assemble().unwrap(), &[]);
+    bus.load(0x00_0200, &[0x00, 0x23, 0x81, 0x00]);
+    cpu.set_irq(true);
+
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x8123);
+
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x9000);
+}
+
+#[test]
+fn irq_dropped_before_cli_is_never_taken() {
+    let code = asm().inx().cli().inx().assemble().unwrap();
+    let (mut cpu, mut bus) = masked_cpu(&code, &[]);
+
+    cpu.set_irq(true);
+    cpu.tick(&mut bus);
+
+    cpu.set_irq(false);
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+
+    assert_eq!(cpu.pc(), 0x8003);
+    assert_eq!(cpu.get_register(Register::X), 2);
+    assert_eq!(cpu.sp(), 0x01FF);
+}
+
+#[test]
+fn sei_blocks_a_pending_irq() {
+    // I is clear to start with.
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &asm().sei().inx().assemble().unwrap());
+    bus.load(0x00_FFEE, &[0x00, 0x90]);
+
+    cpu.tick(&mut bus);
+    cpu.set_irq(true);
+    cpu.tick(&mut bus);
+
+    assert_eq!(cpu.pc(), 0x8002);
+    assert!(cpu.pending_interrupt().is_none());
+}
+
+#[test]
+fn nested_irqs_wait_for_the_handler_to_clear_i() {
+    let handler = Asm::at(0x9000).inx().cli().inx().assemble().unwrap();
+    let (mut cpu, mut bus) = masked_cpu(&asm().cli().assemble().unwrap(), &handler);
+    cpu.set_irq(true);
+
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+    assert_eq!((cpu.pc(), cpu.sp()), (0x9000, 0x01FB));
+
+    // Taking the IRQ set I, so the handler runs even though the line is
+    // still asserted...
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x9001);
+    assert_eq!(cpu.get_register(Register::X), 1);
+
+    // ...until it clears I itself, when the IRQ is taken again on top.
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+    assert_eq!((cpu.pc(), cpu.sp()), (0x9000, 0x01F7));
+    assert_eq!(bus.memory()[0x01F9..0x01FB], [0x02, 0x90]);
+    assert_eq!(cpu.call_stack().len(), 2);
+}
+
+#[test]
+fn wai_wakes_and_vectors_on_nmi() {
+    // I is set, which doesn't matter to an NMI.
+    let (mut cpu, mut bus) = masked_cpu(&asm().wai().inx().assemble().unwrap(), &[]);
+    bus.load(0x00_FFEA, &[0x00, 0x91]);
+
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+    assert!(cpu.waiting());
+    assert_eq!(cpu.pc(), 0x8001);
+
+    cpu.raise_nmi();
+    cpu.tick(&mut bus);
+
+    assert!(!cpu.waiting());
+    assert_eq!(cpu.pc(), 0x9100);
+    assert_eq!(bus.memory()[0x01FD..0x01FF], [0x01, 0x80]);
+}
+
+#[test]
+fn wai_wakes_and_vectors_on_unmasked_irq() {
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &asm().wai().inx().assemble().unwrap());
+    bus.load(0x00_FFEE, &[0x00, 0x90]);
+
+    cpu.tick(&mut bus);
+    assert!(cpu.waiting());
+
+    cpu.set_irq(true);
+    cpu.tick(&mut bus);
+
+    assert!(!cpu.waiting());
+    assert_eq!(cpu.pc(), 0x9000);
+    assert_eq!(cpu.get_register(Register::X), 0);
+}
+
+#[test]
+fn wai_wakes_and_carries_on_with_masked_irq() {
+    let (mut cpu, mut bus) = masked_cpu(&asm().wai().inx().assemble().unwrap(), &[]);
+
+    cpu.tick(&mut bus);
+    assert!(cpu.waiting());
+
+    // The IRQ isn't taken, and the instruction after WAI runs instead.
+    cpu.set_irq(true);
+    cpu.tick(&mut bus);
+
+    assert!(!cpu.waiting());
+    assert_eq!(cpu.pc(), 0x8002);
+    assert_eq!(cpu.get_register(Register::X), 1);
+    assert_eq!(cpu.sp(), 0x01FF);
+}
+
+#[test]
+fn eight_bit_accumulator_keeps_b() {
+    // Each runs with an 8-bit A and the carry clear, with Y holding $1234
+    // and $5A on top of the stack.
+    #[rustfmt::skip]
+    let cases: &[(&[u8], u16, u16)] = &[
+        (&[0xA9, 0x12], 0xAB00, 0xAB12), // LDA #$12
+        (&[0x1A],       0xABFF, 0xAB00), // INC
+        (&[0x69, 0x01], 0xABFF, 0xAB00), // ADC #$01
+        (&[0xE9, 0x01], 0xAB05, 0xAB03), // SBC #$01
+        (&[0x0A],       0xAB81, 0xAB02), // ASL
+        (&[0x4A],       0xAB01, 0xAB00), // LSR
+        (&[0x2A],       0xAB80, 0xAB00), // ROL
+        (&[0x6A],       0xAB01, 0xAB00), // ROR
+        (&[0x29, 0x0F], 0xABF0, 0xAB00), // AND #$0F
+        (&[0x09, 0x0F], 0xABF0, 0xABFF), // ORA #$0F
+        (&[0x49, 0xFF], 0xABF0, 0xAB0F), // EOR #$FF
+        (&[0x98],       0xAB00, 0xAB34), // TYA
+        (&[0x68],       0xAB00, 0xAB5A), // PLA
+    ];
+
+    for &(code, before, after) in cases {
+        let (mut cpu, mut bus) = cpu(Flags::MEMORY_SELECT.bits(), before, code);
+        cpu.set_register(Register::Y, 0x1234);
+        bus.load(0x00_0200, &[0x5A]);
+
+        cpu.tick(&mut bus);
+
+        assert_eq!(
+            cpu.get_register(Register::A),
+            after,
+            "{:02X} with A = {:04X}",
+            code[0],
+            before
+        );
+        assert_eq!(cpu.b(), 0xAB);
+    }
+}
+
+#[test]
+fn sixteen_bit_accumulator_spans_both_bytes() {
+    // The same operations carry from A into B.
+    #[rustfmt::skip]
+    let cases: &[(&[u8], u16, u16)] = &[
+        (&[0x1A],             0x00FF, 0x0100), // INC
+        (&[0x69, 0x01, 0x00], 0x00FF, 0x0100), // ADC #$0001
+        (&[0xE9, 0x00, 0x00], 0x0100, 0x00FF), // SBC #$0000
+        (&[0x0A],             0x0080, 0x0100), // ASL
+        (&[0x4A],             0x0100, 0x0080), // LSR
+        (&[0x98],             0x0000, 0x1234), // TYA
+        (&[0x7B],             0xFFFF, 0x0000), // TDC
+    ];
+
+    for &(code, before, after) in cases {
+        let (mut cpu, mut bus) = cpu(0x00, before, code);
+        cpu.set_register(Register::Y, 0x1234);
+
+        cpu.tick(&mut bus);
+
+        assert_eq!(
+            cpu.get_register(Register::A),
+            after,
+            "{:02X} with A = {:04X}",
+            code[0],
+            before
+        );
+        assert_eq!(cpu.b(), (after >> 8) as u8);
+    }
+
+    // TDC copies all 16 bits even with an 8-bit A.
+    let (mut cpu, mut bus) = cpu(Flags::MEMORY_SELECT.bits(), 0xFFFF, &[0x7B]);
+    cpu.set_register(Register::D, 0x1234);
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.get_register(Register::A), 0x1234);
+}
+
+#[test]
+fn long_calls_across_banks() {
+    // Nothing's mapped, so any bank will do.
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &asm().jsl(0x34_5678).assemble().unwrap());
+    bus.load(0x34_5678, &Asm::at(0x34_5678).rtl().assemble().unwrap());
+
+    // Three bytes go on the stack, the bank and the address of the JSL's
+    // last byte.
+    cpu.tick(&mut bus);
+    assert_eq!(
+        (cpu.program_bank(), cpu.pc(), cpu.sp()),
+        (0x34, 0x5678, 0x01FC)
+    );
+    assert_eq!(cpu.call_stack().len(), 1);
+
+    cpu.tick(&mut bus);
+    assert_eq!(
+        (cpu.program_bank(), cpu.pc(), cpu.sp()),
+        (0x00, 0x8004, 0x01FF)
+    );
+    assert!(cpu.call_stack().is_empty());
+}
+
+#[test]
+fn block_move_between_banks() {
+    // Moving 3 bytes from $12:1000 to $7F:2000.
+    let code = asm().mvn(0x12, 0x7F).assemble().unwrap();
+    let (mut cpu, mut bus) = cpu(0x00, 0x0002, &code);
+    cpu.set_register(Register::X, 0x1000);
+    cpu.set_register(Register::Y, 0x2000);
+    bus.load(0x12_1000, &[0x11, 0x22, 0x33, 0x44]);
+
+    cpu.tick(&mut bus);
+
+    assert_eq!(bus.memory()[0x7F_2000..0x7F_2004], [0x11, 0x22, 0x33, 0x00]);
+    assert_eq!(cpu.get_register(Register::A), 0xFFFF);
+    assert_eq!(
+        (cpu.get_register(Register::X), cpu.get_register(Register::Y)),
+        (0x1003, 0x2003)
+    );
+    assert_eq!((cpu.data_bank(), cpu.pc()), (0x7F, 0x8003));
+}
+
+#[test]
+fn operand_addresses() {
+    use OperandAccess::*;
+
+    // Each runs with a 16-bit A and index registers, D = $1F00, DBR = $7E,
+    // X = $0010 and Y = $0020. The pointer at $1F40 is $12:3456.
+    #[rustfmt::skip]
+    let cases: Vec<(Asm, u32, OperandAccess)> = vec![
+        (asm().sta_abs(0x2000),           0x7E_2000, Write),
+        (asm().sta_abs_x(0x2000),         0x7E_2010, Write),
+        (asm().sta_abs_y(0x2000),         0x7E_2020, Write),
+        // Indexing carries into the next bank.
+        (asm().sta_abs_x(0xFFF8),         0x7F_0008, Write),
+        (asm().lda_abs_y(0xFFF0),         0x7F_0010, Read),
+        (asm().sta_long_x(0x12_3400),     0x12_3410, Write),
+        (asm().lda_long_x(0xFF_FFF8),     0x00_0008, Read),
+        // The direct page is always in bank 0, whatever DBR is.
+        (asm().sta_dp(0x40),              0x00_1F40, Write),
+        (asm().sta_dp_x(0x40),            0x00_1F50, Write),
+        (asm().stz_dp_x(0xF8),            0x00_2008, Write),
+        (asm().lda_dp_indirect_long(0x40), 0x12_3456, Read),
+        (asm().inc_dp(0x40),              0x00_1F40, Modify),
+        (asm().cmp_abs(0x2000),           0x7E_2000, Read),
+    ];
+
+    for (code, addr, access) in cases {
+        let code = code.assemble().unwrap();
+        let (mut cpu, mut bus) = cpu(0x00, 0x0000, &code);
+        cpu.set_state(&CpuState {
+            x: 0x0010,
+            y: 0x0020,
+            direct_page: 0x1F00,
+            data_bank: 0x7E,
+            ..cpu.state()
+        });
+        bus.load(0x00_1F40, &[0x56, 0x34, 0x12]);
+
+        cpu.tick(&mut bus);
+
+        let expected = Operand { addr, access };
+        assert_eq!(cpu.operand(), Some(expected), "{:02X}", code[0]);
+    }
+}
+
+#[test]
+fn direct_page_wraps_in_bank_0() {
+    let code = asm().sta_dp_x(0xF0).sta_dp(0x20).assemble().unwrap();
+    let (mut cpu, mut bus) = cpu(0x00, 0x1234, &code);
+    cpu.set_state(&CpuState {
+        x: 0x0020,
+        direct_page: 0xFFF0,
+        data_bank: 0x7E,
+        ..cpu.state()
+    });
+
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.operand().unwrap().addr, 0x00_0100);
+    assert_eq!(bus.memory()[0x00_0100..0x00_0102], [0x34, 0x12]);
+
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.operand().unwrap().addr, 0x00_0010);
+}
+
+#[test]
+fn no_operand() {
+    // Immediates and implied instructions don't touch memory through an
+    // operand, and the last instruction's doesn't carry over.
+    let code = asm()
+        .sta_abs(0x2000)
+        .lda_imm16(0x1234)
+        .inc_a()
+        .assemble()
+        .unwrap();
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &code);
+
+    cpu.tick(&mut bus);
+    assert!(cpu.operand().is_some());
+
+    for _ in 0..2 {
+        cpu.tick(&mut bus);
+        assert_eq!(cpu.operand(), None);
+    }
+}
+
+#[test]
+fn nested_calls() {
+    #[rustfmt::skip]
+    let code = asm()
+        .jsr_to("outer")
+        .label("spin")
+        .bra("spin")
+        .label("outer")            // $8005
+        .jsl(0x01_9000)
+        .rts()
+        .assemble()
+        .unwrap();
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &code);
+    bus.load(
+        0x01_9000,
+        &Asm::at(0x01_9000).inx().rtl().assemble().unwrap(),
+    );
+
+    let outer = CallFrame {
+        caller: 0x00_8000,
+        target: 0x00_8005,
+        sp: 0x01FF,
+        kind: CallKind::Subroutine,
+    };
+    let inner = CallFrame {
+        caller: 0x00_8005,
+        target: 0x01_9000,
+        sp: 0x01FD,
+        kind: CallKind::Long,
+    };
+
+    // Outermost first.
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.call_stack(), [outer, inner]);
+
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.call_stack(), [outer, inner]);
+
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.call_stack(), [outer]);
+
+    cpu.tick(&mut bus);
+    assert!(cpu.call_stack().is_empty());
+    assert_eq!(cpu.pc(), 0x8003);
+}
+
+#[test]
+fn rts_dispatch_stays_in_the_caller() {
+    // Pushes the address before `handler` and returns to it, the way jump
+    // tables are often done.
+    #[rustfmt::skip]
+    let code = asm()
+        .jsr_to("dispatch")
+        .label("spin")
+        .bra("spin")
+        .label("dispatch")         // $8005
+        .pea(0x8008)
+        .rts()
+        .label("handler")          // $8009
+        .inx()
+        .rts()
+        .assemble()
+        .unwrap();
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &code);
+
+    let frame = CallFrame {
+        caller: 0x00_8000,
+        target: 0x00_8005,
+        sp: 0x01FF,
+        kind: CallKind::Subroutine,
+    };
+
+    for _ in 0..3 {
+        cpu.tick(&mut bus);
+    }
+
+    // The RTS only returned from what PEA pushed, so the handler still
+    // runs as part of the JSR, and no call is made up for it.
+    assert_eq!(cpu.pc(), 0x8009);
+    assert_eq!(cpu.call_stack(), [frame]);
+
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x8003);
+    assert!(cpu.call_stack().is_empty());
+}
+
+#[test]
+fn interrupt_during_a_subroutine() {
+    #[rustfmt::skip]
+    let code = asm()
+        .jsr_to("sub")
+        .label("spin")
+        .bra("spin")
+        .label("sub")              // $8005
+        .inx()
+        .rts()
+        .assemble()
+        .unwrap();
+    let (mut cpu, mut bus) = cpu(0x00, 0x0000, &code);
+    bus.load(0x00_FFEE, &[0x00, 0x90]);
+    bus.load(0x00_9000, &Asm::at(0x9000).rti().assemble().unwrap());
+
+    let sub = CallFrame {
+        caller: 0x00_8000,
+        target: 0x00_8005,
+        sp: 0x01FF,
+        kind: CallKind::Subroutine,
+    };
+
+    cpu.tick(&mut bus);
+    cpu.set_irq(true);
+    cpu.tick(&mut bus);
+
+    // The interrupt comes from the instruction that didn't get to run, and
+    // sits on top of the subroutine.
+    assert_eq!(
+        cpu.call_stack(),
+        [
+            sub,
+            CallFrame {
+                caller: 0x00_8005,
+                target: 0x00_9000,
+                sp: 0x01FD,
+                kind: CallKind::Interrupt { emulation: false },
+            }
+        ]
+    );
+
+    cpu.set_irq(false);
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x8005);
+    assert_eq!(cpu.call_stack(), [sub]);
+
+    cpu.tick(&mut bus);
+    cpu.tick(&mut bus);
+    assert_eq!(cpu.pc(), 0x8003);
+    assert!(cpu.call_stack().is_empty());
+}
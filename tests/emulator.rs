@@ -0,0 +1,597 @@
+// Drives the emulator a frame at a time through its public API, the way an
+// embedder would.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use snesemu::cpu::Interrupt;
+use snesemu::emulator::{Emulator, Stats};
+use snesemu::events::{EventKind, EventLog};
+use snesemu::mmu::MapMode;
+
+// Copies 32 bytes to VRAM by DMA, writes to the APU, turns on HDMA, NMIs and
+// auto-joypad reading, then spins. The NMI handler just returns.
+fn events_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x80,       // LDA #$80
+        0x8D, 0x00, 0x21, // STA $2100
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x00, 0x43, // STA $4300
+        0xA9, 0x18,       // LDA #$18
+        0x8D, 0x01, 0x43, // STA $4301
+        0x9C, 0x02, 0x43, // STZ $4302
+        0xA9, 0x80,       // LDA #$80
+        0x8D, 0x03, 0x43, // STA $4303
+        0x9C, 0x04, 0x43, // STZ $4304
+        0xA9, 0x20,       // LDA #$20
+        0x8D, 0x05, 0x43, // STA $4305
+        0x9C, 0x06, 0x43, // STZ $4306
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x0B, 0x42, // STA $420B
+        0xA9, 0x5A,       // LDA #$5A
+        0x8D, 0x40, 0x21, // STA $2140
+        0xA9, 0x02,       // LDA #$02
+        0x8D, 0x0C, 0x42, // STA $420C
+        0xA9, 0x81,       // LDA #$81
+        0x8D, 0x00, 0x42, // STA $4200
+        0x80, 0xFE,       // BRA *
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+
+    // The NMI handler is an RTI, and the HDMA table at $00:0000 ends
+    // straight away.
+    rom[0x80] = 0x40;
+    rom[0x7FEA..0x7FEC].copy_from_slice(&[0x80, 0x80]);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+#[test]
+fn run_frame_events() {
+    let mut emulator = Emulator::new(events_rom(), Some(MapMode::LoRom)).unwrap();
+    emulator.mmu.events = Some(EventLog::new(64));
+
+    for frame in 1..=3 {
+        assert!(emulator.run_frame() > 0);
+        assert_eq!(emulator.frame(), frame);
+    }
+
+    let events: Vec<(u64, u16, EventKind)> = emulator
+        .mmu
+        .events
+        .as_ref()
+        .unwrap()
+        .iter()
+        .map(|event| (event.frame, event.scanline, event.kind))
+        .collect();
+
+    let dma = EventKind::DmaTransfer {
+        channel: 0,
+        bytes: 32,
+        b_addr: 0x18,
+    };
+    let apu = EventKind::ApuPortWrite {
+        port: 0,
+        value: 0x5A,
+    };
+    let hdma = EventKind::HdmaInit { channels: 0b10 };
+    let joypad = EventKind::AutoJoypadRead;
+    let nmi = EventKind::NmiAsserted;
+
+    // Each frame ends at the start of vblank, which is when the frame count
+    // goes up, and HDMA only starts at the top of the next one.
+    #[rustfmt::skip]
+    let expected = [
+        (0, 0, dma), (0, 0, apu),
+        (1, 225, joypad), (1, 225, nmi),
+        (1, 0, hdma),
+        (2, 225, joypad), (2, 225, nmi),
+        (2, 0, hdma),
+        (3, 225, joypad), (3, 225, nmi),
+    ];
+
+    assert_eq!(events, expected);
+
+    // The last NMI is only taken when the next frame starts running.
+    assert_eq!(emulator.stats().nmis, 2);
+}
+
+// The same run a step at a time, where each NMI is reported by the step that
+// took it.
+#[test]
+fn step_reports_interrupts() {
+    let mut emulator = Emulator::new(events_rom(), Some(MapMode::LoRom)).unwrap();
+    let mut taken = Vec::new();
+
+    while emulator.frame() < 3 {
+        let result = emulator.step_instruction();
+
+        if let Some(interrupt) = result.interrupt {
+            assert_eq!(result.unknown_opcode, None);
+            taken.push((emulator.frame(), interrupt));
+        }
+    }
+
+    assert_eq!(taken, [(1, Interrupt::Nmi), (2, Interrupt::Nmi)]);
+    assert_eq!(emulator.stats().nmis, 2);
+}
+
+// The DMA and the APU write belong to the instructions that made them, and
+// the NMI to whichever one was running when vblank started.
+#[test]
+fn events_are_stamped() {
+    let mut emulator = Emulator::new(events_rom(), Some(MapMode::LoRom)).unwrap();
+    emulator.mmu.events = Some(EventLog::new(64));
+    emulator.run_frame();
+
+    let events = emulator.mmu.events.as_ref().unwrap();
+    let stamps: Vec<(u64, u16, u64)> = events
+        .iter()
+        .map(|event| (event.frame, event.dot, event.instruction))
+        .collect();
+
+    // 17 instructions come before the STA $420B, and two more before the
+    // STA $2140. The frame ends in the instruction that was running when
+    // vblank started.
+    let last = emulator.steps() - 1;
+    assert_eq!(stamps[0].2, 17);
+    assert_eq!(stamps[1].2, 19);
+    assert!(stamps[0].1 < stamps[1].1);
+    assert_eq!(stamps[2..], [(1, 1, last), (1, 1, last)]);
+}
+
+// Turns on NMIs and spins, with an RTI at $8080 to take them.
+fn nmi_rom() -> Vec<u8> {
+    // BRA *
+    idle_rom(&[0x80, 0xFE])
+}
+
+// Turns on NMIs, then runs the idle loop forever. The NMI handler just
+// returns.
+fn idle_rom(idle: &[u8]) -> Vec<u8> {
+    #[rustfmt::skip]
+    let setup = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x80,       // LDA #$80
+        0x8D, 0x00, 0x42, // STA $4200
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..setup.len()].copy_from_slice(&setup);
+    rom[setup.len()..setup.len() + idle.len()].copy_from_slice(idle);
+    rom[0x80] = 0x40;
+    rom[0x7FEA..0x7FEC].copy_from_slice(&[0x80, 0x80]);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+// Runs for three frames, counting the steps that ran an instruction and
+// the ones that didn't.
+fn count_steps(rom: Vec<u8>) -> (Stats, u64, u64, u64) {
+    let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+    let (mut ran, mut other, mut cycles) = (0, 0, 0);
+
+    while emulator.frame() < 3 {
+        if emulator.next_instruction().runs {
+            ran += 1;
+        } else {
+            other += 1;
+        }
+
+        cycles += emulator.step_instruction().cycles;
+    }
+
+    (emulator.stats(), ran, other, cycles)
+}
+
+#[test]
+fn stats_count_every_step() {
+    let (stats, ran, interrupts, cycles) = count_steps(nmi_rom());
+
+    // The third frame's NMI hasn't been taken yet.
+    assert_eq!((stats.frames, stats.nmis, stats.irqs), (3, 2, 0));
+    assert_eq!(interrupts, 2);
+
+    // Taking an interrupt is a step, but not an instruction.
+    assert_eq!(stats.steps, ran + interrupts);
+    assert_eq!(stats.instructions, ran);
+    assert_eq!(stats.cycles, cycles);
+    assert_eq!(stats.dma_bytes, 0);
+
+    // The same run counts the same again.
+    assert_eq!(count_steps(nmi_rom()).0, stats);
+}
+
+#[test]
+fn waiting_isnt_counted_as_instructions() {
+    // WAI, BRA back to it
+    let (stats, ran, other, _) = count_steps(idle_rom(&[0xCB, 0x80, 0xFD]));
+
+    // Five to set up, then WAI, the NMI's RTI and the BRA for each of the
+    // two NMIs, and the WAI still waiting for the third.
+    assert_eq!(stats.instructions, ran);
+    assert_eq!(ran, 5 + 2 * 3 + 1);
+    assert_eq!(stats.steps, ran + other);
+    assert!(other > stats.nmis, "{other} steps waited");
+}
+
+// Runs until the given line, once the frame count has reached the given
+// frame, then writes SETINI.
+fn write_setini(emulator: &mut Emulator, frame: u64, line: u16, value: u8) {
+    while emulator.frame() < frame || emulator.mmu.ppu.scanline() != line {
+        emulator.step_instruction();
+    }
+
+    emulator.mmu.store_u8(0x00_2133, value);
+}
+
+#[test]
+fn overscan_moves_nmi_from_the_next_frame() {
+    let mut emulator = Emulator::new(nmi_rom(), Some(MapMode::LoRom)).unwrap();
+    emulator.mmu.events = Some(EventLog::new(64));
+
+    // Overscan goes on partway through the second frame, and off partway
+    // through the fourth.
+    write_setini(&mut emulator, 1, 100, 0x04);
+    assert_eq!(emulator.mmu.ppu.vblank_line(), 225);
+
+    write_setini(&mut emulator, 3, 100, 0x00);
+    assert_eq!(emulator.mmu.ppu.vblank_line(), 240);
+
+    while emulator.frame() < 5 {
+        emulator.run_frame();
+    }
+
+    let nmis: Vec<(u64, u16)> = emulator
+        .mmu
+        .events
+        .as_ref()
+        .unwrap()
+        .iter()
+        .filter(|event| event.kind == EventKind::NmiAsserted)
+        .map(|event| (event.frame, event.scanline))
+        .collect();
+
+    // Each NMI is stamped with the frame count it bumped, so the second
+    // frame's is frame 2, and only the third moves.
+    assert_eq!(nmis, [(1, 225), (2, 225), (3, 240), (4, 240), (5, 225)]);
+}
+
+// Reads the VRAM prefetch and RDNMI, both of which change when they're
+// read, and keeps what they gave in $10-$12.
+fn reads_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x12,       // LDA #$12
+        0xAD, 0x39, 0x21, // LDA $2139
+        0x85, 0x10,       // STA $10
+        0xAD, 0x3A, 0x21, // LDA $213A
+        0x85, 0x11,       // STA $11
+        0xAD, 0x10, 0x42, // LDA $4210
+        0x85, 0x12,       // STA $12
+        0xC2, 0x20,       // REP #$20
+        0xA9, 0x34, 0x12, // LDA #$1234
+        0x80, 0xFE,       // BRA *
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+#[test]
+fn peeking_changes_nothing() {
+    let mut peeked = Emulator::new(reads_rom(), Some(MapMode::LoRom)).unwrap();
+    let mut plain = Emulator::new(reads_rom(), Some(MapMode::LoRom)).unwrap();
+
+    let mut decoded = Vec::new();
+
+    for _ in 0..14 {
+        let state = peeked.cpu.state();
+        let cycles = peeked.cpu.cycles();
+
+        let next = peeked.cpu.peek_next(&peeked.mmu);
+
+        for _ in 0..3 {
+            let again = peeked.cpu.peek_next(&peeked.mmu);
+            assert_eq!(again.bytes(), next.bytes());
+            assert_eq!(again.effective_addr(), next.effective_addr());
+        }
+
+        assert_eq!(peeked.cpu.state(), state);
+        assert_eq!(peeked.cpu.cycles(), cycles);
+
+        decoded.push((next.instruction.mnemonic(), next.bytes().to_vec()));
+
+        peeked.step();
+        plain.step();
+
+        assert_eq!(peeked.cpu.state(), plain.cpu.state());
+        assert_eq!(peeked.cpu.cycles(), plain.cpu.cycles());
+    }
+
+    // The immediates are sized by the M flag at the time.
+    assert_eq!(decoded[3], ("lda", vec![0xA9, 0x12]));
+    assert_eq!(decoded[11], ("lda", vec![0xA9, 0x34, 0x12]));
+
+    // The reads happened once each, so the next ones carry on from there
+    // the same way.
+    assert_eq!(
+        peeked.mmu.peek_bytes(0x7E_0010, 3),
+        plain.mmu.peek_bytes(0x7E_0010, 3)
+    );
+
+    for addr in [0x00_2139, 0x00_213A, 0x00_4210] {
+        assert_eq!(peeked.mmu.read_u8(addr), plain.mmu.read_u8(addr));
+    }
+}
+
+// DMAs `count` bytes from WRAM to VRAM (0 being 64KB), then spins. The
+// write to MDMAEN is at $8025.
+fn dma_rom(count: u16) -> Vec<u8> {
+    let [low, high] = count.to_le_bytes();
+
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x00, 0x43, // STA $4300
+        0xA9, 0x18,       // LDA #$18
+        0x8D, 0x01, 0x43, // STA $4301
+        0x9C, 0x02, 0x43, // STZ $4302
+        0x9C, 0x03, 0x43, // STZ $4303
+        0xA9, 0x7E,       // LDA #$7E
+        0x8D, 0x04, 0x43, // STA $4304
+        0xA9, low,        // LDA #low
+        0x8D, 0x05, 0x43, // STA $4305
+        0xA9, high,       // LDA #high
+        0x8D, 0x06, 0x43, // STA $4306
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x0B, 0x42, // STA $420B
+        0x80, 0xFE,       // BRA *
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+// Runs the write to MDMAEN, returning how many master cycles it took and
+// how many whole lines the PPU moved on in that time.
+fn run_dma(count: u16) -> (u64, u64) {
+    let mut emulator = Emulator::new(dma_rom(count), Some(MapMode::LoRom)).unwrap();
+
+    while emulator.cpu.pc() != 0x8025 {
+        emulator.step();
+    }
+
+    let line = |emulator: &Emulator| emulator.frame() * 262 + emulator.mmu.ppu.scanline() as u64;
+
+    let (before, dot, cycles) = (
+        line(&emulator),
+        emulator.mmu.ppu.h_counter() as u64,
+        emulator.cpu.cycles(),
+    );
+
+    emulator.step();
+
+    let taken = emulator.cpu.cycles() - cycles;
+    let lines = line(&emulator) - before;
+
+    // The PPU ran for exactly as long as the CPU was held up.
+    assert_eq!(lines, (dot + taken) / 1364);
+    assert_eq!(emulator.mmu.ppu.h_counter() as u64, (dot + taken) % 1364);
+
+    (taken, lines)
+}
+
+#[test]
+fn dma_takes_time() {
+    let (one_byte, _) = run_dma(1);
+    let (full, lines) = run_dma(0);
+
+    // 8 master cycles a byte, so 64KB is over a frame and a half.
+    assert_eq!(full - one_byte, 0xFFFF * 8);
+    assert!((384..=385).contains(&lines), "{} lines", lines);
+}
+
+// Sets MEMSEL, then calls the same loop at $8040 through bank $00 and then
+// through its mirror in bank $80.
+fn speed_rom(memsel: u8) -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,                   // CLC
+        0xFB,                   // XCE
+        0xC2, 0x10,             // REP #$10
+        0xE2, 0x20,             // SEP #$20
+        0xA9, memsel,           // LDA #memsel
+        0x8D, 0x0D, 0x42,       // STA $420D
+        0x22, 0x40, 0x80, 0x00, // JSL $008040
+        0x22, 0x40, 0x80, 0x80, // JSL $808040
+        0x80, 0xFE,             // BRA *
+    ];
+
+    #[rustfmt::skip]
+    let subroutine = [
+        0xA2, 0x10, 0x00,       // LDX #$0010
+        // loop:
+        0xCA,                   // DEX
+        0xD0, 0xFD,             // BNE loop
+        0x6B,                   // RTL
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x40..0x40 + subroutine.len()].copy_from_slice(&subroutine);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+// The master cycles each of the two calls took, from the JSL to the
+// instruction after it.
+fn call_cycles(memsel: u8) -> (u64, u64) {
+    let mut emulator = Emulator::new(speed_rom(memsel), Some(MapMode::LoRom)).unwrap();
+
+    let mut run_to = |pc: u16| {
+        while emulator.cpu.current_addr() != pc as u32 {
+            emulator.step();
+        }
+
+        emulator.cpu.cycles()
+    };
+
+    let start = run_to(0x800B);
+    let middle = run_to(0x800F);
+    let end = run_to(0x8013);
+
+    (middle - start, end - middle)
+}
+
+#[test]
+fn fast_rom_needs_memsel() {
+    let (slow, mirror) = call_cycles(0x00);
+    assert_eq!(slow, mirror);
+
+    // With MEMSEL set, each of the 52 bytes of ROM read in bank $80 takes 6
+    // master cycles instead of 8. The stack is in WRAM either way.
+    let (slow, fast) = call_cycles(0x01);
+    assert_eq!(slow - fast, 52 * 2);
+}
+
+// Copies $10 plus one into $11, over and over.
+fn copy_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        // loop:
+        0xA5, 0x10,       // LDA $10
+        0x1A,             // INC
+        0x85, 0x11,       // STA $11
+        0x80, 0xF9,       // BRA loop
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+#[test]
+fn hooks_fire_in_order() {
+    let mut emulator = Emulator::new(copy_rom(), Some(MapMode::LoRom)).unwrap();
+    let calls = Rc::new(RefCell::new(Vec::new()));
+
+    for name in ["first", "second"] {
+        let calls = calls.clone();
+        emulator.on_instruction(move |_, _, addr| {
+            calls.borrow_mut().push(format!("{name} {addr:06X}"))
+        });
+    }
+
+    let writes = calls.clone();
+    emulator.on_memory_write(move |_, addr, value| {
+        writes
+            .borrow_mut()
+            .push(format!("write {addr:06X}={value:02X}"));
+    });
+
+    for _ in 0..5 {
+        emulator.step();
+    }
+
+    assert_eq!(
+        *calls.borrow(),
+        [
+            "first 008000",
+            "second 008000",
+            "first 008001",
+            "second 008001",
+            "first 008002",
+            "second 008002",
+            "first 008004",
+            "second 008004",
+            "first 008006",
+            "second 008006",
+        ]
+    );
+
+    calls.borrow_mut().clear();
+    emulator.step();
+    assert_eq!(
+        *calls.borrow(),
+        ["first 008007", "second 008007", "write 000011=01"]
+    );
+}
+
+#[test]
+fn hooks_poke_ram_between_instructions() {
+    let mut emulator = Emulator::new(copy_rom(), Some(MapMode::LoRom)).unwrap();
+
+    // Puts a new value in $10 each time round the loop, just before the
+    // LDA reads it.
+    let mut next: u8 = 0x20;
+    emulator.on_instruction(move |emulator, state, addr| {
+        if addr == 0x8004 {
+            assert!(state.status & 0x20 != 0);
+            emulator.mmu.store_u8(0x7E_0010, next);
+            next = next.wrapping_add(1);
+        }
+    });
+
+    let stored = Rc::new(RefCell::new(Vec::new()));
+    let writes = stored.clone();
+    emulator.on_memory_write(move |emulator, addr, value| {
+        // Hooks can read memory as it is after the write.
+        assert_eq!(emulator.mmu.peek_u8(addr), value);
+        writes.borrow_mut().push(value);
+    });
+
+    let frames = Rc::new(RefCell::new(0));
+    let counter = frames.clone();
+    emulator.on_frame(move |emulator| {
+        *counter.borrow_mut() += 1;
+        assert_eq!(emulator.frame(), *counter.borrow());
+    });
+
+    emulator.run_frame();
+    assert_eq!(*frames.borrow(), 1);
+
+    let stored = stored.borrow();
+    assert!(stored.len() > 100);
+
+    // Every store is of the poked value plus one, so none of them saw a
+    // stale $10.
+    for (i, &value) in stored.iter().enumerate() {
+        assert_eq!(value, (0x21 + i) as u8);
+    }
+
+    // Once they're cleared, nothing changes $10 but the program.
+    emulator.clear_hooks();
+    let poked = emulator.mmu.peek_u8(0x7E_0010);
+    emulator.run_frame();
+    assert_eq!(emulator.mmu.peek_u8(0x7E_0010), poked);
+    assert_eq!(emulator.mmu.peek_u8(0x7E_0011), poked.wrapping_add(1));
+}
@@ -0,0 +1,169 @@
+// Drives the core through its C interface: first from Rust, for the error
+// handling, then by building examples/ffi.c against the shared library and
+// running it, to check that the header and the ABI agree.
+//
+// The C half needs a C compiler as `cc`, and is skipped without one.
+
+#![cfg(feature = "ffi")]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+
+use snesemu::ffi::*;
+
+// Stores the pad's high byte at $10 every time round a loop.
+fn rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x00, 0x42, // STA $4200
+        // loop:
+        0xAD, 0x19, 0x42, // LDA $4219
+        0x85, 0x10,       // STA $10
+        0x80, 0xF9,       // BRA loop
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+#[test]
+fn error_codes() {
+    let rom = rom();
+
+    unsafe {
+        assert!(snes_create(ptr::null(), 0).is_null());
+        assert!(snes_create(rom.as_ptr(), 0).is_null());
+        assert!(snes_create(rom.as_ptr(), 0x4000).is_null());
+
+        assert_eq!(snes_run_frame(ptr::null_mut()), SNES_ERROR_NULL);
+        assert!(snes_framebuffer(ptr::null(), ptr::null_mut(), ptr::null_mut()).is_null());
+
+        let snes = snes_create(rom.as_ptr(), rom.len());
+        assert!(!snes.is_null());
+
+        assert_eq!(snes_set_input(snes, 0, 0x1000), SNES_OK);
+        assert_eq!(snes_set_input(snes, 5, 0x1000), SNES_ERROR_INVALID_ARGUMENT);
+
+        for _ in 0..2 {
+            assert_eq!(snes_run_frame(snes), SNES_OK);
+        }
+
+        let mut value = 0;
+        assert_eq!(snes_read_memory(snes, 0x7E_0010, &mut value), SNES_OK);
+        assert_eq!(value, 0x10);
+        assert_eq!(
+            snes_read_memory(snes, 0x7E_0010, ptr::null_mut()),
+            SNES_ERROR_NULL
+        );
+
+        let (mut width, mut height) = (0, 0);
+        assert!(!snes_framebuffer(snes, &mut width, &mut height).is_null());
+        assert_eq!((width, height), (256, 224));
+
+        // Asking for the size of a state, then giving too little room.
+        let mut len = 0;
+        let result = snes_save_state(snes, ptr::null_mut(), 0, &mut len);
+        assert_eq!(result, SNES_ERROR_BUFFER_TOO_SMALL);
+
+        let mut state = vec![0; len];
+        let result = snes_save_state(snes, state.as_mut_ptr(), len - 1, &mut len);
+        assert_eq!(result, SNES_ERROR_BUFFER_TOO_SMALL);
+        assert_eq!(
+            snes_save_state(snes, state.as_mut_ptr(), len, &mut len),
+            SNES_OK
+        );
+
+        // A bad state leaves the emulator as it was, and a good one
+        // restores it.
+        assert_eq!(snes_write_memory(snes, 0x7E_0020, 0x5A), SNES_OK);
+        let garbage = [0xFF; 16];
+        let result = snes_load_state(snes, garbage.as_ptr(), garbage.len());
+        assert_eq!(result, SNES_ERROR_INVALID_STATE);
+        assert_eq!(snes_read_memory(snes, 0x7E_0020, &mut value), SNES_OK);
+        assert_eq!(value, 0x5A);
+
+        assert_eq!(snes_load_state(snes, state.as_ptr(), len), SNES_OK);
+        assert_eq!(snes_read_memory(snes, 0x7E_0020, &mut value), SNES_OK);
+        assert_eq!(value, 0x00);
+
+        snes_destroy(snes);
+        snes_destroy(ptr::null_mut());
+    }
+}
+
+// Builds the shared library on its own, as the tests only link against the
+// rlib. It gets a target directory of its own so as not to wait on the one
+// running the tests.
+fn build_library(target_dir: &Path) -> PathBuf {
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--quiet", "--lib", "--features", "ffi"])
+        .arg("--manifest-path")
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(target_dir)
+        .status()
+        .unwrap();
+
+    assert!(status.success(), "couldn't build the library");
+    target_dir.join("debug")
+}
+
+#[test]
+fn c_program_runs() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("no C compiler, skipping the C test");
+        return;
+    }
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let lib_dir = build_library(&dir);
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    let program = dir.join("ffi");
+    let status = Command::new("cc")
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(root.join("examples/ffi.c"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .args(["-lsnesemu", "-o"])
+        .arg(&program)
+        .status()
+        .unwrap();
+
+    assert!(status.success(), "couldn't compile examples/ffi.c");
+
+    let rom = dir.join("ffi.sfc");
+    std::fs::write(&rom, self::rom()).unwrap();
+
+    let output = Command::new(&program)
+        .arg(&rom)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Forced blank, so the screen is black.
+    assert!(
+        stdout.contains("frame: 256x224, first pixel 000000"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("restored 7E0000 = 00"), "{}", stdout);
+}
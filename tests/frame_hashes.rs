@@ -0,0 +1,293 @@
+// Renders a few synthetic scenes and compares the hash of every frame
+// against the ones in tests/frame_hashes, so that changes to what the PPU
+// draws don't go unnoticed. When a scene is meant to look different, set
+// UPDATE_FRAME_HASHES=1 to write the new hashes out instead, and look at the
+// frames before committing them.
+//
+// A frame that doesn't match is written out as a PNG when the frame-dump
+// feature is on, and the failure says where.
+//
+// Real ROMs can be checked the same way: set SNES_TEST_ROMS to a directory,
+// and each ROM in it with a `.hashes` file next to it (e.g. game.sfc and
+// game.sfc.hashes, in the format --frame-hash prints) is run for as many
+// frames as the file covers.
+
+#![cfg(feature = "frontend")]
+
+use std::path::{Path, PathBuf};
+
+use snesemu::asm::{lorom, Asm};
+use snesemu::emulator::Emulator;
+use snesemu::frontend::frame_hash::{parse_hashes, run_frame_hashes, FrameHash};
+use snesemu::frontend::options::Options;
+use snesemu::frontend::session;
+use snesemu::mmu::MapMode;
+
+// How many frames each scene runs for.
+const FRAMES: u64 = 8;
+
+// Turns the screen on, then changes the backdrop color at the start of
+// every vblank.
+fn backdrop() -> Vec<u8> {
+    let code = Asm::at(0x8000)
+        .clc()
+        .xce()
+        .sep(0x20)
+        .lda_imm8(0x0F)
+        .sta_abs(0x2100)
+        .label("wait")
+        .lda_abs(0x4210)
+        .asl_a()
+        .bcc("wait")
+        .inc_dp(0x10)
+        .stz_abs(0x2121)
+        .lda_dp(0x10)
+        .sta_abs(0x2122)
+        .sta_abs(0x2122)
+        .bra("wait")
+        .assemble()
+        .unwrap();
+
+    lorom(&code)
+}
+
+// A mode 0 BG1 of two tiles in two palettes, scrolling diagonally by a
+// pixel a frame.
+fn scrolling_bg() -> Vec<u8> {
+    #[rustfmt::skip]
+    let tile = [
+        0x81, 0x00, 0x42, 0xFF, 0x24, 0x00, 0x18, 0xFF,
+        0x18, 0x00, 0x24, 0xFF, 0x42, 0x00, 0x81, 0xFF,
+    ];
+
+    let colors: Vec<u8> = [
+        0x0000, 0x001F, 0x03E0, 0x7C00, 0x0000, 0x7FFF, 0x56B5, 0x294A,
+    ]
+    .iter()
+    .flat_map(|color: &u16| color.to_le_bytes())
+    .collect();
+
+    let code = Asm::at(0x8000)
+        .clc()
+        .xce()
+        .rep(0x10)
+        .sep(0x20)
+        // Forced blank, mode 0, BG1's tilemap at $0400 and tiles at $0000.
+        .lda_imm8(0x80)
+        .sta_abs(0x2100)
+        .stz_abs(0x2105)
+        .lda_imm8(0x04)
+        .sta_abs(0x2107)
+        .stz_abs(0x210B)
+        .lda_imm8(0x80)
+        .sta_abs(0x2115)
+        // Tile 1, from $8200.
+        .ldx_imm16(0x0008)
+        .stx_abs(0x2116)
+        .ldx_imm16(0x0000)
+        .label("tile")
+        .lda_abs_x(0x8200)
+        .sta_abs(0x2118)
+        .inx()
+        .lda_abs_x(0x8200)
+        .sta_abs(0x2119)
+        .inx()
+        .cpx_imm16(0x0010)
+        .bne("tile")
+        // The tilemap, in runs of three that don't line up with the rows.
+        .ldx_imm16(0x0400)
+        .stx_abs(0x2116)
+        .rep(0x20)
+        .ldy_imm16(342)
+        .label("map")
+        .lda_imm16(0x0001)
+        .sta_abs(0x2118)
+        .lda_imm16(0x0401)
+        .sta_abs(0x2118)
+        .stz_abs(0x2118)
+        .dey()
+        .bne("map")
+        .sep(0x20)
+        // Two palettes, from $8210.
+        .stz_abs(0x2121)
+        .ldx_imm16(0x0000)
+        .label("palette")
+        .lda_abs_x(0x8210)
+        .sta_abs(0x2122)
+        .inx()
+        .cpx_imm16(0x0010)
+        .bne("palette")
+        .lda_imm8(0x01)
+        .sta_abs(0x212C)
+        .lda_imm8(0x0F)
+        .sta_abs(0x2100)
+        // Scroll at the start of every vblank.
+        .label("wait")
+        .lda_abs(0x4210)
+        .asl_a()
+        .bcc("wait")
+        .inc_dp(0x10)
+        .lda_dp(0x10)
+        .sta_abs(0x210D)
+        .stz_abs(0x210D)
+        .sta_abs(0x210E)
+        .stz_abs(0x210E)
+        .bra("wait")
+        .assemble()
+        .unwrap();
+
+    let mut rom = lorom(&code);
+    rom[0x0200..0x0210].copy_from_slice(&tile);
+    rom[0x0210..0x0220].copy_from_slice(&colors);
+
+    rom
+}
+
+// Writes a frame that didn't match out for looking at, returning where it
+// went, or how to get it.
+fn dump_mismatch(name: &str, emulator: &Emulator, frame: u64) -> String {
+    if !cfg!(feature = "frame-dump") {
+        return "build with the frame-dump feature to write it out".into();
+    }
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("frame_hashes")
+        .join(name);
+
+    let dumper = snesemu::frontend::frame_dump::FrameDumper::new(&dir, 1).unwrap();
+    dumper.write(frame, emulator.framebuffer()).unwrap();
+
+    format!("written to {}", dir.display())
+}
+
+// Runs the emulator for as many frames as are expected, failing on the
+// first one that doesn't match.
+fn check(name: &str, mut emulator: Emulator, expected: &[FrameHash]) {
+    let frames = expected.iter().map(|hash| hash.frame).max().unwrap_or(0);
+    let mut mismatch = None;
+
+    run_frame_hashes(&mut emulator, None, frames, |emulator, actual| {
+        let Some(expected) = expected.iter().find(|hash| hash.frame == actual.frame) else {
+            return;
+        };
+
+        if mismatch.is_none() && expected.hash != actual.hash {
+            let dumped = dump_mismatch(name, emulator, actual.frame);
+            mismatch = Some(format!(
+                "{}: frame {} hashed to {:016x}, expected {:016x} ({})",
+                name, actual.frame, actual.hash, expected.hash, dumped
+            ));
+        }
+    });
+
+    if let Some(mismatch) = mismatch {
+        panic!("{}", mismatch);
+    }
+}
+
+fn check_scene(name: &str, rom: Vec<u8>) {
+    let path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "tests",
+        "frame_hashes",
+        &format!("{}.txt", name),
+    ]
+    .iter()
+    .collect();
+
+    let emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+
+    if std::env::var_os("UPDATE_FRAME_HASHES").is_some() {
+        let mut emulator = emulator;
+        let mut text = format!("# {}, written by tests/frame_hashes.rs\n", name);
+
+        run_frame_hashes(&mut emulator, None, FRAMES, |_, hash| {
+            text.push_str(&format!("{}\n", hash));
+        });
+
+        std::fs::write(&path, text).unwrap();
+        return;
+    }
+
+    let text = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("couldn't read {}: {}", path.display(), e));
+    let expected = parse_hashes(&text).unwrap();
+
+    assert_eq!(expected.len() as u64, FRAMES, "{}", path.display());
+    check(name, emulator, &expected);
+}
+
+#[test]
+fn backdrop_scene() {
+    check_scene("backdrop", backdrop());
+}
+
+#[test]
+fn scrolling_bg_scene() {
+    check_scene("scrolling_bg", scrolling_bg());
+}
+
+// The scenes have to draw something that changes, or the hashes would
+// catch nothing.
+#[test]
+fn scenes_change_every_frame() {
+    for rom in [backdrop(), scrolling_bg()] {
+        let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+        let mut hashes = Vec::new();
+
+        run_frame_hashes(&mut emulator, None, FRAMES, |_, hash| {
+            hashes.push(hash.hash)
+        });
+
+        // The first frame is drawn before the first vblank, so both start
+        // the same way as the second.
+        hashes.dedup();
+        assert!(hashes.len() as u64 >= FRAMES - 1, "{:x?}", hashes);
+    }
+}
+
+// Every ROM under the directory with a .hashes file next to it.
+fn hashed_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            hashed_roms(&path, roms);
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "hashes")
+        {
+            roms.push(path.with_extension(""));
+        }
+    }
+}
+
+#[test]
+fn test_roms() {
+    let Some(dir) = std::env::var_os("SNES_TEST_ROMS") else {
+        eprintln!("SNES_TEST_ROMS isn't set, skipping the frame hashes of test ROMs");
+        return;
+    };
+
+    let mut roms = Vec::new();
+    hashed_roms(Path::new(&dir), &mut roms);
+
+    for rom in roms {
+        let hashes = std::fs::read_to_string(rom.with_extension(format!(
+            "{}.hashes",
+            rom.extension().unwrap().to_string_lossy()
+        )))
+        .unwrap();
+        let expected = parse_hashes(&hashes)
+            .unwrap_or_else(|e| panic!("bad hashes for {}: {}", rom.display(), e));
+
+        let args = [rom.to_str().unwrap(), "--no-sram"];
+        let options = Options::parse(args.into_iter().map(String::from)).unwrap();
+        let emulator = session::read_rom(&options)
+            .and_then(|data| session::prepare(&options, data))
+            .unwrap_or_else(|e| panic!("couldn't start {}: {}", rom.display(), e));
+
+        let name = rom.file_stem().unwrap().to_string_lossy();
+        check(&name, emulator, &expected);
+    }
+}
@@ -0,0 +1,174 @@
+// Runs small hand-assembled programs through the real Cpu and Mmu, and
+// compares their traces against the golden ones in tests/golden. When an
+// instruction's behaviour is meant to change, set UPDATE_GOLDEN=1 to write
+// the new traces out instead, and check the diff before committing them.
+
+#![cfg(feature = "frontend")]
+
+use std::path::PathBuf;
+
+use snesemu::emulator::Emulator;
+use snesemu::frontend::trace::diff_logs;
+use snesemu::mmu::MapMode;
+use snesemu::symbols::Symbols;
+use snesemu::trace::trace_entry;
+
+// Anything that runs longer than this has got stuck.
+const MAX_INSTRUCTIONS: usize = 1000;
+
+// Runs the program from $8000 until it reaches `end`, tracing every
+// instruction up to there.
+fn trace(code: &[u8], end: u16) -> String {
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    let mut emulator = Emulator::new(rom, Some(MapMode::LoRom)).unwrap();
+    let symbols = Symbols::new();
+
+    let mut output = String::new();
+
+    for _ in 0..MAX_INSTRUCTIONS {
+        if emulator.cpu.pc() == end {
+            return output;
+        }
+
+        let (record, _) = emulator.step_traced();
+
+        output.push_str(&trace_entry(&record, &symbols));
+        output.push('\n');
+    }
+
+    panic!("didn't reach ${:04X}:\n{}", end, output);
+}
+
+fn check(name: &str, actual: &str) {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+        .iter()
+        .collect();
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("couldn't read {}: {}", path.display(), e));
+
+    if let Some(diff) = diff_logs(&expected, actual) {
+        panic!("{} differs:\n{}", name, diff);
+    }
+}
+
+#[test]
+fn arithmetic() {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x7F,       // LDA #$7F
+        0x69, 0x01,       // ADC #$01
+        0x69, 0x80,       // ADC #$80
+        0xC9, 0x00,       // CMP #$00
+        0xE2, 0x01,       // SEP #$01
+        0x69, 0x10,       // ADC #$10
+        0x0A,             // ASL
+        0xEB,             // XBA
+        0xC2, 0x21,       // REP #$21
+        0xA9, 0xFF, 0xFF, // LDA #$FFFF
+        0x1A,             // INC
+        0xA9, 0x00, 0x40, // LDA #$4000
+        0x0A,             // ASL
+        0xC9, 0x00, 0x80, // CMP #$8000
+        0x69, 0x00, 0x80, // ADC #$8000
+        0x80, 0xFE,       // BRA *
+    ];
+
+    check("arithmetic.log", &trace(&code, 0x8022));
+}
+
+#[test]
+fn stack() {
+    #[rustfmt::skip]
+    let code = [
+        0x18,                   // CLC
+        0xFB,                   // XCE
+        0xC2, 0x30,             // REP #$30
+        0xA9, 0x34, 0x12,       // LDA #$1234
+        0xA2, 0x78, 0x56,       // LDX #$5678
+        0x48,                   // PHA
+        0xDA,                   // PHX
+        0x0B,                   // PHD
+        0xF4, 0xCD, 0xAB,       // PEA $ABCD
+        0x20, 0x30, 0x80,       // JSR $8030
+        0x22, 0x40, 0x80, 0x00, // JSL $008040
+        0x7A,                   // PLY
+        0x2B,                   // PLD
+        0x68,                   // PLA
+        0xFA,                   // PLX
+        0x80, 0xFE,             // BRA *
+    ];
+
+    #[rustfmt::skip]
+    let subroutine = [
+        0x8B,                   // PHB
+        0xE2, 0x20,             // SEP #$20
+        0xA9, 0x7E,             // LDA #$7E
+        0x48,                   // PHA
+        0xAB,                   // PLB
+        0xAB,                   // PLB
+        0xC2, 0x20,             // REP #$20
+        0x60,                   // RTS
+    ];
+
+    #[rustfmt::skip]
+    let long_subroutine = [
+        0x20, 0x30, 0x80,       // JSR $8030
+        0x6B,                   // RTL
+    ];
+
+    let mut program = vec![0; 0x50];
+    program[..code.len()].copy_from_slice(&code);
+    program[0x30..0x30 + subroutine.len()].copy_from_slice(&subroutine);
+    program[0x40..0x40 + long_subroutine.len()].copy_from_slice(&long_subroutine);
+
+    check("stack.log", &trace(&program, 0x801B));
+}
+
+#[test]
+fn indexed() {
+    #[rustfmt::skip]
+    let code = [
+        0x18,                   // CLC
+        0xFB,                   // XCE
+        0xE2, 0x20,             // SEP #$20
+        0xC2, 0x10,             // REP #$10
+        0xA2, 0x02, 0x00,       // LDX #$0002
+        0xA0, 0x03, 0x00,       // LDY #$0003
+        0xA9, 0x11,             // LDA #$11
+        0x9D, 0x00, 0x01,       // STA $0100,X
+        0xA9, 0x22,             // LDA #$22
+        0x99, 0x00, 0x01,       // STA $0100,Y
+        0x95, 0x10,             // STA $10,X
+        0xBD, 0x01, 0x01,       // LDA $0101,X
+        0xB9, 0xFF, 0x00,       // LDA $00FF,Y
+        0xBF, 0x00, 0x01, 0x7E, // LDA $7E0100,X
+        0xDF, 0x01, 0x01, 0x7E, // CMP $7E0101,X
+        0x64, 0x20,             // STZ $20
+        0xA9, 0x01,             // LDA #$01
+        0x85, 0x21,             // STA $21
+        0xA9, 0x7E,             // LDA #$7E
+        0x85, 0x22,             // STA $22
+        0xA7, 0x20,             // LDA [$20]
+        0x9E, 0xFE, 0x00,       // STZ $00FE,X
+        0xC2, 0x20,             // REP #$20
+        0xA9, 0x03, 0x00,       // LDA #$0003
+        0xA2, 0x00, 0x01,       // LDX #$0100
+        0xA0, 0x00, 0x02,       // LDY #$0200
+        0x54, 0x7E, 0x7E,       // MVN $7E,$7E
+        0x80, 0xFE,             // BRA *
+    ];
+
+    check("indexed.log", &trace(&code, 0x8043));
+}
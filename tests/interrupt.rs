@@ -0,0 +1,67 @@
+// Ctrl+C sets a flag for the whole process, so this gets a test binary of
+// its own rather than risk stopping some other test's session.
+
+#![cfg(feature = "frontend")]
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use snesemu::frontend::options::Options;
+use snesemu::frontend::session::{self, Session, Stop};
+
+// Counts up in $10 forever.
+fn rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        // loop:
+        0xE6, 0x10,       // INC $10
+        0x80, 0xFC,       // BRA loop
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+#[test]
+fn stops_between_instructions() {
+    let log = std::env::temp_dir().join(format!("snesemu-interrupt-{}.log", std::process::id()));
+    let args = ["interrupt.sfc", "--no-sram", "--log", log.to_str().unwrap()];
+    let options = Options::parse(args.into_iter().map(String::from)).unwrap();
+
+    let mut emulator = session::prepare(&options, rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    // As if Ctrl+C was pressed while the 100th instruction was running.
+    let count = Rc::new(Cell::new(0));
+    emulator.on_instruction({
+        let count = count.clone();
+
+        move |_, _, _| {
+            count.set(count.get() + 1);
+
+            if count.get() == 100 {
+                assert!(!session::interrupt());
+            }
+        }
+    });
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::Interrupted)));
+    assert_eq!(session.finish(&mut emulator, stop), 130);
+
+    // Two instructions to set up, then INC and BRA by turns.
+    assert_eq!(emulator.steps(), 100);
+    assert_eq!(count.get(), 100);
+    assert_eq!(emulator.mmu.peek_u8(0x7E_0010), 49);
+    assert_eq!(emulator.cpu.state().pc, 0x8002);
+
+    // The trace was still written.
+    let text = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+    assert!(text.lines().any(|line| line.contains("INC")), "{}", text);
+}
@@ -0,0 +1,57 @@
+// Runs a program through the library alone, as another frontend would.
+
+use snesemu::cpu::Register;
+use snesemu::emulator::Emulator;
+
+// Fills $0100-$0163 with the words 1 to 50, then spins, in a 32KB LoROM
+// that starts running at $8000.
+fn counting_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xC2, 0x30,       // REP #$30
+        0xA9, 0x00, 0x00, // LDA #$0000
+        0xA2, 0x00, 0x00, // LDX #$0000
+        // loop:
+        0x1A,             // INC
+        0x9D, 0x00, 0x01, // STA $0100,X
+        0xE8,             // INX
+        0xE8,             // INX
+        0xE0, 0x64, 0x00, // CPX #$0064
+        0xD0, 0xF5,       // BNE loop
+        // done:
+        0x80, 0xFE,       // BRA done
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+#[test]
+fn steps_a_program() {
+    let mut emulator = Emulator::new(counting_rom(), None).unwrap();
+
+    // 5 instructions of setup, 6 for each of the 50 times round the loop,
+    // then a few more spinning at the end.
+    for _ in 0..310 {
+        emulator.step();
+    }
+
+    assert_eq!(emulator.cpu.get_register(Register::A), 50);
+    assert_eq!(emulator.cpu.get_register(Register::X), 100);
+    assert!(!emulator.cpu.is_eight_bit_mode(Register::A));
+    assert_eq!(emulator.cpu.current_addr(), 0x00_8015);
+
+    for n in 1..=50 {
+        let addr = 0x7E_0100 + (n as u32 - 1) * 2;
+
+        assert_eq!(emulator.mmu.read_u8(addr), n);
+        assert_eq!(emulator.mmu.read_u8(addr + 1), 0);
+    }
+
+    assert_eq!(emulator.mmu.read_u8(0x7E_0164), 0);
+}
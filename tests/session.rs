@@ -0,0 +1,678 @@
+#![cfg(feature = "frontend")]
+
+use std::ops::ControlFlow;
+
+use snesemu::frontend::checksum;
+use snesemu::frontend::options::Options;
+use snesemu::frontend::session::{self, Session, Stop};
+use snesemu::input::Buttons;
+
+// A 32KB LoROM with each block of code at its address in bank 0, which
+// starts running at $8000.
+fn lorom(blocks: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+
+    for &(addr, code) in blocks {
+        let start = addr as usize - 0x8000;
+        rom[start..start + code.len()].copy_from_slice(code);
+    }
+
+    // The reset vector, in the emulation mode vectors.
+    rom[0x7FFC] = 0x00;
+    rom[0x7FFD] = 0x80;
+
+    rom
+}
+
+// Fills $0100-$0163 with the words 1 to 50, then spins.
+fn counting_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xC2, 0x30,       // REP #$30
+        0xA9, 0x00, 0x00, // LDA #$0000
+        0xA2, 0x00, 0x00, // LDX #$0000
+        // loop:
+        0x1A,             // INC
+        0x9D, 0x00, 0x01, // STA $0100,X
+        0xE8,             // INX
+        0xE8,             // INX
+        0xE0, 0x64, 0x00, // CPX #$0064
+        0xD0, 0xF5,       // BNE loop
+        // done:
+        0x80, 0xFE,       // BRA done
+    ];
+
+    lorom(&[(0x8000, &code)])
+}
+
+// Each test writes its trace log to a file of its own.
+fn options(name: &str, args: &[&str]) -> Options {
+    let log = std::env::temp_dir().join(format!("snesemu-{}-{}.log", name, std::process::id()));
+    let log = log.to_str().unwrap();
+
+    let args = ["counting.sfc", "--log", log, "--no-sram"]
+        .into_iter()
+        .chain(args.iter().copied())
+        .map(String::from);
+
+    Options::parse(args).unwrap()
+}
+
+#[test]
+fn runs_until_target() {
+    let options = options(
+        "target",
+        &[
+            "--run-until",
+            "00:8015",
+            "--expect",
+            "7E:0100=01",
+            "--expect",
+            "7E:0162=32",
+        ],
+    );
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::Target)));
+
+    let state = emulator.cpu.state();
+    assert_eq!((state.a, state.x), (0x32, 0x64));
+    assert!(!state.emulation);
+
+    let words: Vec<u8> = (1..=50).flat_map(|n: u8| [n, 0]).collect();
+    assert_eq!(emulator.mmu.peek_bytes(0x7E_0100, 100), words);
+    assert_eq!(emulator.mmu.peek_u8(0x7E_0164), 0);
+
+    assert_eq!(session.finish(&mut emulator, stop), 0);
+}
+
+#[test]
+fn instruction_limit() {
+    let options = options("limit", &["--run-for", "100", "--expect", "7E:0100=02"]);
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::InstructionLimit)));
+    assert_eq!(emulator.steps(), 100);
+
+    // The --expect check failing takes priority over the limit.
+    assert_eq!(session.finish(&mut emulator, stop), 6);
+}
+
+#[test]
+fn missing_files_are_setup_errors() {
+    let options = options("symbols", &["--symbols", "/nonexistent/snesemu.sym"]);
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+
+    match Session::new(options, &mut emulator) {
+        Err(e) => assert_eq!(e.exit_code(), 1),
+        Ok(_) => panic!("loaded a missing symbol file"),
+    }
+}
+
+// The --compare-log reference is only needed at the end, but a bad one is
+// caught before the run, like a bad argument.
+#[test]
+fn missing_compare_log_is_rejected() {
+    let options = options(
+        "compare-log",
+        &["--compare-log", "/nonexistent/snesemu.log"],
+    );
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+
+    match Session::new(options, &mut emulator) {
+        Err(e) => assert_eq!(e.exit_code(), 2),
+        Ok(_) => panic!("accepted a missing reference log"),
+    }
+}
+
+#[test]
+fn rejects_missing_and_undersized_roms() {
+    let path = std::env::temp_dir().join(format!("snesemu-small-{}.sfc", std::process::id()));
+
+    let mut options = options("small", &[]);
+    options.rom = path.to_str().unwrap().into();
+
+    let exit_code = |options| session::read_rom(options).err().map(|e| e.exit_code());
+
+    assert_eq!(exit_code(&options), Some(2));
+
+    // 0x7E00 bytes is a copier header on top of 0x7C00 bytes of ROM.
+    for size in [0, 0x200, 0x7E00, 0x7FFF] {
+        std::fs::write(&path, vec![0; size]).unwrap();
+        assert_eq!(exit_code(&options), Some(2), "{size} bytes");
+    }
+
+    // A copier header on top of a full bank is fine, and gets stripped.
+    std::fs::write(&path, vec![0; 0x8200]).unwrap();
+    assert_eq!(session::read_rom(&options).unwrap().len(), 0x8000);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn streamed_trace_has_every_instruction() {
+    let log = std::env::temp_dir().join(format!("snesemu-streamed-{}.log", std::process::id()));
+
+    let mut options = options("streamed", &["--trace-mode", "stream", "--run-for", "3000"]);
+    options.log = log.to_str().unwrap().into();
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert_eq!(session.finish(&mut emulator, stop), 4);
+
+    let text = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+
+    // Each instruction starts with its address, and the PPU and SPC state
+    // are written at the end.
+    let instructions: Vec<&str> = text.lines().filter(|line| line.starts_with('[')).collect();
+
+    assert_eq!(instructions.len(), 3000);
+    assert!(instructions[0].contains("CLC"), "{}", instructions[0]);
+    assert!(instructions[2999].contains("BRA"), "{}", instructions[2999]);
+
+    let end: Vec<&str> = text.lines().rev().take(2).collect();
+    assert!(end[1].starts_with("PPU: ") && end[0].starts_with("SPC: "));
+}
+
+// With tracing off, nothing is captured for the instructions, so the log
+// only has the PPU and SPC state written at the end.
+#[test]
+fn trace_off_logs_no_instructions() {
+    let log = std::env::temp_dir().join(format!("snesemu-off-{}.log", std::process::id()));
+
+    let mut options = options("off", &["--trace-mode", "off", "--run-for", "3000"]);
+    options.log = log.to_str().unwrap().into();
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert_eq!(session.finish(&mut emulator, stop), 4);
+    assert_eq!(emulator.steps(), 3000);
+
+    let text = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", text);
+    assert!(lines[0].starts_with("PPU: ") && lines[1].starts_with("SPC: "));
+}
+
+// Calls a subroutine at $8010, then spins.
+fn call_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0x20, 0x10, 0x80, // JSR $8010
+        0x80, 0xFE,       // BRA *
+    ];
+
+    lorom(&[(0x8000, &code), (0x8010, &[0x60])])
+}
+
+// Runs to the end, returning the breakpoint it stopped at, if any, and the
+// exit status.
+fn run(options: Options, rom: Vec<u8>) -> (Option<u32>, i32) {
+    let mut emulator = session::prepare(&options, rom).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    let stopped_at = match stop {
+        Some(Stop::Breakpoint(addr)) => Some(addr),
+        _ => None,
+    };
+
+    (stopped_at, session.finish(&mut emulator, stop))
+}
+
+#[test]
+fn breakpoint_on_call_target() {
+    let options = options("call", &["--run-for", "100", "--break", "00:8010"]);
+
+    assert_eq!(run(options, call_rom()), (Some(0x8010), 3));
+}
+
+#[test]
+fn breakpoint_on_operand_never_fires() {
+    // $8003 is the low byte of the JSR's operand, which is never executed.
+    let options = options("operand", &["--run-for", "100", "--break", "00:8003"]);
+
+    assert_eq!(run(options, call_rom()), (None, 4));
+}
+
+#[test]
+fn breakpoint_on_interrupt_handler() {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x80,       // LDA #$80
+        0x8D, 0x00, 0x42, // STA $4200
+        0x80, 0xFE,       // BRA *
+    ];
+
+    // The native mode NMI vector points at an RTI.
+    let mut rom = lorom(&[(0x8000, &code), (0x8020, &[0x40])]);
+    rom[0x7FEA] = 0x20;
+    rom[0x7FEB] = 0x80;
+
+    let options = options("nmi", &["--run-for", "2f", "--break", "00:8020"]);
+
+    assert_eq!(run(options, rom), (Some(0x8020), 3));
+}
+
+#[test]
+fn stops_on_unknown_opcode() {
+    // STP isn't implemented, so it's an unknown opcode too.
+    let rom = lorom(&[(0x8000, &[0xEA, 0xDB])]);
+
+    let stopped = options("unknown", &["--run-for", "100"]);
+    assert_eq!(run(stopped, rom.clone()), (None, 5));
+
+    // Skipping it runs on to the limit instead.
+    let skipped = options("skipped", &["--run-for", "100", "--ignore-unknown"]);
+    assert_eq!(run(skipped, rom), (None, 4));
+}
+
+// Adds up the high byte of the first pad's auto-read buttons in $10, over
+// and over.
+fn input_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x00, 0x42, // STA $4200
+        // loop:
+        0xAD, 0x19, 0x42, // LDA $4219
+        0x18,             // CLC
+        0x65, 0x10,       // ADC $10
+        0x85, 0x10,       // STA $10
+        0x80, 0xF6,       // BRA loop
+    ];
+
+    lorom(&[(0x8000, &code)])
+}
+
+// Runs frame by frame, pressing whatever the script gives for each frame,
+// and returns a hash of WRAM at the end.
+fn run_with_input(options: Options, script: impl Fn(u64) -> Buttons) -> u64 {
+    let mut emulator = session::prepare(&options, input_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = loop {
+        emulator.set_input(script(emulator.frame()));
+
+        if let ControlFlow::Break(stop) = session.run_frame(&mut emulator) {
+            break stop;
+        }
+    };
+
+    assert!(matches!(stop, Some(Stop::FrameLimit)));
+    session.finish(&mut emulator, stop);
+
+    checksum(emulator.mmu.wram())
+}
+
+#[test]
+fn movie_plays_back_recorded_input() {
+    let movie = std::env::temp_dir().join(format!("snesemu-movie-{}.txt", std::process::id()));
+    let movie = movie.to_str().unwrap();
+
+    let script = |frame: u64| match frame % 20 {
+        0..=4 => Buttons::B,
+        10..=14 => Buttons::UP | Buttons::START,
+        _ => Buttons::empty(),
+    };
+
+    let recording = options("record", &["--run-for", "60f", "--record", movie]);
+    let recorded = run_with_input(recording, script);
+
+    // The host's input is ignored while a movie plays.
+    let playback = options("play", &["--run-for", "60f", "--play", movie]);
+    let played = run_with_input(playback, |_| Buttons::Y);
+
+    let without = options("without", &["--run-for", "60f"]);
+    let unscripted = run_with_input(without, |_| Buttons::empty());
+
+    std::fs::remove_file(movie).unwrap();
+
+    assert_eq!(played, recorded);
+    assert_ne!(unscripted, recorded);
+}
+
+// /dev/full takes the movie's header, but fails when it's flushed.
+#[cfg(target_os = "linux")]
+#[test]
+fn unwritable_movie_fails_the_run() {
+    let options = options("full-movie", &["--run-for", "10f", "--record", "/dev/full"]);
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::FrameLimit)));
+    assert_eq!(session.finish(&mut emulator, stop), 1);
+}
+
+// /dev/full fails as soon as the I/O log's buffer fills up, which stops the
+// run rather than panicking mid-frame.
+#[cfg(target_os = "linux")]
+#[test]
+fn unwritable_io_log_stops_the_run() {
+    #[rustfmt::skip]
+    let code = [
+        0xAD, 0x12, 0x42, // LDA $4212
+        0x80, 0xFB,       // BRA -5
+    ];
+
+    let options = options(
+        "full-io-log",
+        &["--run-for", "10f", "--io-log", "/dev/full"],
+    );
+
+    let mut emulator = session::prepare(&options, lorom(&[(0x8000, &code)])).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::WriteError)));
+    assert!(emulator.frame() < 10);
+    assert_eq!(session.finish(&mut emulator, stop), 1);
+}
+
+// The same for a streamed trace, once its buffer fills up.
+#[cfg(target_os = "linux")]
+#[test]
+fn unwritable_trace_stops_the_run() {
+    let options = options(
+        "full-trace",
+        &[
+            "--run-for",
+            "60f",
+            "--trace-mode",
+            "stream",
+            "--log",
+            "/dev/full",
+        ],
+    );
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::WriteError)));
+    assert_eq!(session.finish(&mut emulator, stop), 1);
+}
+
+#[test]
+fn ignore_unknown_skips_operands() {
+    // None of these are implemented, and each has a different length. The
+    // immediates depend on the M flag, the same as the implemented ones.
+    #[rustfmt::skip]
+    let code = [
+        0x18,                   // CLC
+        0xFB,                   // XCE
+        0xE2, 0x20,             // SEP #$20
+        0x89, 0xFF,             // BIT #$FF
+        0xC2, 0x20,             // REP #$20
+        0x89, 0xFF, 0xFF,       // BIT #$FFFF
+        0x0F, 0x00, 0x00, 0x7E, // ORA $7E0000
+        0x14, 0x10,             // TRB $10
+        0x42, 0x00,             // WDM #$00
+        0x1B,                   // TCS
+        0x80, 0xFE,             // BRA *
+    ];
+
+    let rom = lorom(&[(0x8000, &code)]);
+
+    // Any wrong length would land in the middle of an instruction, and
+    // never reach the BRA.
+    let options = options(
+        "skip",
+        &["--run-for", "100", "--ignore-unknown", "--break", "00:8014"],
+    );
+
+    assert_eq!(run(options, rom), (Some(0x8014), 3));
+
+    // The banner names them from the full table, even though they don't run.
+    let names = [0x89, 0x0F, 0x14, 0x42, 0x1B].map(snesemu::disasm::mnemonic);
+    assert_eq!(names, ["bit", "ora", "trb", "wdm", "tcs"]);
+}
+
+// Writes to ROM from two places, one of them over and over.
+fn rom_writing_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0x8D, 0x00, 0x90, // STA $9000
+        // loop:
+        0x8D, 0x00, 0x80, // STA $8000
+        0x80, 0xFB,       // BRA loop
+    ];
+
+    lorom(&[(0x8000, &code)])
+}
+
+#[test]
+fn warns_once_per_rom_writer() {
+    let options = options("rom-writes", &["--run-for", "100"]);
+
+    let mut emulator = session::prepare(&options, rom_writing_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::InstructionLimit)));
+    assert_eq!(session.rom_write_warnings(), 2);
+    assert_eq!(emulator.mmu.peek_u8(0x00_8000), 0x18);
+
+    session.finish(&mut emulator, stop);
+}
+
+#[test]
+fn rom_write_policies() {
+    let ignored = options(
+        "rom-ignored",
+        &["--run-for", "100", "--rom-writes", "ignore"],
+    );
+    let mut emulator = session::prepare(&ignored, rom_writing_rom()).unwrap();
+    let mut session = Session::new(ignored, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert_eq!(session.rom_write_warnings(), 0);
+    assert_eq!(session.finish(&mut emulator, stop), 4);
+
+    let strict = options(
+        "rom-strict",
+        &["--run-for", "100", "--rom-writes", "strict"],
+    );
+    let mut emulator = session::prepare(&strict, rom_writing_rom()).unwrap();
+    let mut session = Session::new(strict, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::RomWrite)));
+    assert_eq!(emulator.steps(), 4);
+    assert_eq!(session.finish(&mut emulator, stop), 10);
+}
+
+#[cfg(feature = "trace-gzip")]
+#[test]
+fn compressed_stream_matches_plain() {
+    use snesemu::frontend::trace::read_log;
+
+    let dir = std::env::temp_dir();
+    let plain = dir.join(format!("snesemu-plain-{}.log", std::process::id()));
+    let compressed = dir.join(format!("snesemu-compressed-{}.log.gz", std::process::id()));
+
+    let mut first = options("plain", &["--trace-mode", "stream", "--run-for", "500"]);
+    first.log = plain.to_str().unwrap().into();
+
+    let mut emulator = session::prepare(&first, counting_rom()).unwrap();
+    let mut session = Session::new(first, &mut emulator).unwrap();
+    let stop = session.run(&mut emulator);
+    assert_eq!(session.finish(&mut emulator, stop), 4);
+
+    // Compressed because of the name, and compared against the plain log
+    // on the way out.
+    let second = options(
+        "compressed",
+        &[
+            "--trace-mode",
+            "stream",
+            "--run-for",
+            "500",
+            "--compare-log",
+            plain.to_str().unwrap(),
+            "--log",
+            compressed.to_str().unwrap(),
+        ],
+    );
+    assert!(second.trace_gzip);
+
+    let mut emulator = session::prepare(&second, counting_rom()).unwrap();
+    let mut session = Session::new(second, &mut emulator).unwrap();
+    let stop = session.run(&mut emulator);
+    assert_eq!(session.finish(&mut emulator, stop), 4);
+
+    let bytes = std::fs::read(&compressed).unwrap();
+    assert_eq!(bytes[..2], [0x1F, 0x8B]);
+
+    let expected = std::fs::read_to_string(&plain).unwrap();
+    assert_eq!(read_log(&compressed).unwrap(), expected);
+    assert_eq!(read_log(&plain).unwrap(), expected);
+    assert!(expected.lines().count() > 500);
+
+    std::fs::remove_file(&plain).unwrap();
+    std::fs::remove_file(&compressed).unwrap();
+}
+
+#[test]
+fn random_ram_repeats_with_the_seed() {
+    let run = |name, init| {
+        let options = options(name, &["--run-for", "30f", "--ram-init", init]);
+        run_with_input(options, |_| Buttons::A)
+    };
+
+    let first = run("seed-first", "random:1234");
+    assert_eq!(run("seed-second", "random:1234"), first);
+    assert_ne!(run("seed-other", "random:1235"), first);
+    assert_ne!(run("seed-zero", "zero"), first);
+}
+
+#[test]
+fn movies_record_ram_init() {
+    let movie = std::env::temp_dir().join(format!("snesemu-ram-movie-{}.txt", std::process::id()));
+    let movie = movie.to_str().unwrap();
+
+    let recording = options(
+        "ram-record",
+        &["--run-for", "10f", "--ram-init", "55aa", "--record", movie],
+    );
+    run_with_input(recording, |_| Buttons::empty());
+
+    let header = std::fs::read_to_string(movie).unwrap();
+    assert!(header.starts_with("snesemu-movie 2 rom="), "{}", header);
+    assert!(
+        header.lines().next().unwrap().ends_with(" ram=55aa"),
+        "{}",
+        header
+    );
+
+    // Playing it back from different RAM would go its own way.
+    let playback = options("ram-play", &["--run-for", "10f", "--play", movie]);
+    let emulator = session::prepare(&playback, input_rom()).unwrap();
+    let error = session::open_movie(&emulator, &playback).err().unwrap();
+    assert_eq!(error.exit_code(), 1);
+
+    let playback = options(
+        "ram-play",
+        &["--run-for", "10f", "--ram-init", "55aa", "--play", movie],
+    );
+    let emulator = session::prepare(&playback, input_rom()).unwrap();
+    assert!(session::open_movie(&emulator, &playback).unwrap().is_some());
+
+    std::fs::remove_file(movie).unwrap();
+}
+
+#[test]
+fn instruction_limit_leaves_consistent_state() {
+    let log = std::env::temp_dir().join(format!("snesemu-max-{}.log", std::process::id()));
+    let state = log.with_extension("state");
+
+    // Five instructions of setup, then ten times round the six in the loop.
+    let mut args = vec!["--trace-mode", "stream", "--max-instructions", "65"];
+
+    if cfg!(feature = "savestate") {
+        args.extend(["--save-state", state.to_str().unwrap()]);
+    }
+
+    let mut options = options("max", &args);
+    options.log = log.to_str().unwrap().into();
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::InstructionLimit)));
+    assert_eq!(session.finish(&mut emulator, stop), 4);
+
+    assert_eq!(emulator.steps(), 65);
+    let cpu = emulator.cpu.state();
+    assert_eq!((cpu.a, cpu.x, cpu.pc), (10, 20, 0x800A));
+
+    let words: Vec<u8> = (1..=10).flat_map(|n: u8| [n, 0]).collect();
+    assert_eq!(emulator.mmu.peek_bytes(0x7E_0100, 22)[..20], words);
+    assert_eq!(emulator.mmu.peek_u8(0x7E_0114), 0);
+
+    // The trace stops at the same instruction.
+    let text = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+
+    let instructions: Vec<&str> = text.lines().filter(|line| line.starts_with('[')).collect();
+    assert_eq!(instructions.len(), 65);
+    assert!(instructions[64].contains("BNE"), "{}", instructions[64]);
+
+    // As does the save state.
+    #[cfg(feature = "savestate")]
+    {
+        let mut restored = snesemu::emulator::Emulator::new(counting_rom(), None).unwrap();
+        snesemu::frontend::savestate::load(&mut restored, &state).unwrap();
+        std::fs::remove_file(&state).unwrap();
+
+        assert_eq!(restored.cpu.state(), emulator.cpu.state());
+        assert_eq!(restored.steps(), 65);
+        assert_eq!(restored.mmu.wram(), emulator.mmu.wram());
+    }
+}
+
+#[test]
+fn time_limit() {
+    let options = options("seconds", &["--max-seconds", "0.05"]);
+
+    let mut emulator = session::prepare(&options, counting_rom()).unwrap();
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(matches!(stop, Some(Stop::TimeLimit)));
+    assert_eq!(session.finish(&mut emulator, stop), 4);
+
+    // The limit is only looked at every so often.
+    assert_eq!(emulator.steps() % 4096, 0);
+}
@@ -0,0 +1,165 @@
+// Runs the CPU against single-step tests in the format of the TomHarte
+// 65816 suite (https://github.com/SingleStepTests/65816). Each test gives
+// the registers and memory before and after one instruction, and the CPU
+// runs on a flat 16MB bus, as the suite expects.
+//
+// The suite itself is too big to check in, so tests/single_step has a few
+// cases in the same format for the opcodes that are implemented, which run
+// by default. To run the whole suite, point SINGLE_STEP_TESTS at its v1
+// directory and run the ignored test:
+//
+//     SINGLE_STEP_TESTS=path/to/65816/v1 cargo test --release -- --ignored
+//
+// Bus cycles aren't compared, as the CPU doesn't time each access yet.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use snesemu::bus::{Bus, FlatBus};
+use snesemu::cpu::{Cpu, CpuState};
+
+#[derive(Deserialize)]
+struct Test {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    s: u16,
+    p: u8,
+    a: u16,
+    x: u16,
+    y: u16,
+    dbr: u8,
+    d: u16,
+    pbr: u8,
+    e: u8,
+    ram: Vec<(u32, u8)>,
+}
+
+impl State {
+    fn registers(&self) -> CpuState {
+        CpuState {
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            pc: self.pc,
+            sp: self.s,
+            direct_page: self.d,
+            program_bank: self.pbr,
+            data_bank: self.dbr,
+            status: self.p,
+            emulation: self.e != 0,
+        }
+    }
+}
+
+// Runs one test, returning what differed from the expected state.
+fn run(test: &Test) -> Vec<String> {
+    let mut cpu = Cpu::new();
+    let mut bus = FlatBus::new();
+
+    cpu.set_state(&test.initial.registers());
+
+    for &(addr, value) in &test.initial.ram {
+        bus.store_u8(addr, value);
+    }
+
+    cpu.tick(&mut bus);
+
+    let mut diffs = Vec::new();
+
+    let (actual, expected) = (cpu.state(), test.expected.registers());
+
+    if actual != expected {
+        diffs.push(format!("expected {:?}, got {:?}", expected, actual));
+    }
+
+    for &(addr, value) in &test.expected.ram {
+        let actual = bus.peek_u8(addr);
+
+        if actual != value {
+            diffs.push(format!(
+                "expected {:02X} at {:06X}, got {:02X}",
+                value, addr, actual
+            ));
+        }
+    }
+
+    diffs
+}
+
+// Runs every test in every file, and reports the failures grouped by the
+// opcode and mode the file is for, e.g. `a9.n`. Returns how many failed.
+fn run_files(files: &[PathBuf]) -> usize {
+    let mut failures: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut total = 0;
+
+    for path in files {
+        let text = std::fs::read_to_string(path).unwrap();
+        let tests: Vec<Test> = serde_json::from_str(&text)
+            .unwrap_or_else(|e| panic!("couldn't parse {}: {}", path.display(), e));
+
+        let group = path.file_stem().unwrap().to_string_lossy().into_owned();
+
+        for test in &tests {
+            let diffs = run(test);
+
+            if !diffs.is_empty() {
+                let failed = failures.entry(group.clone()).or_default();
+                failed.push(format!("{}: {}", test.name, diffs.join("; ")));
+            }
+        }
+
+        total += tests.len();
+    }
+
+    let failed: usize = failures.values().map(Vec::len).sum();
+
+    for (group, failed) in &failures {
+        eprintln!("{}: {} failed", group, failed.len());
+
+        // The first few are enough to see what's wrong.
+        for failure in failed.iter().take(3) {
+            eprintln!("    {}", failure);
+        }
+    }
+
+    eprintln!("{} of {} tests passed", total - failed, total);
+
+    failed
+}
+
+fn json_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("couldn't read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+
+    files.sort();
+    files
+}
+
+#[test]
+fn supported_opcodes() {
+    let files = json_files(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/single_step"));
+    assert!(!files.is_empty());
+
+    assert_eq!(run_files(&files), 0);
+}
+
+#[test]
+#[ignore]
+fn full_suite() {
+    let dir = std::env::var("SINGLE_STEP_TESTS")
+        .expect("SINGLE_STEP_TESTS should be the path to the suite's v1 directory");
+
+    assert_eq!(run_files(&json_files(Path::new(&dir))), 0);
+}
@@ -0,0 +1,159 @@
+// Runs test ROMs that leave their results in memory, such as PeterLemon's
+// CPU tests (https://github.com/PeterLemon/SNES), headless for a number of
+// frames, then checks each sub-test's result through the Mmu.
+//
+// The ROMs aren't checked in. To run them, set SNES_TEST_ROMS to the
+// directory they're in; without it, they're skipped. Where each ROM keeps
+// its results comes from its source, so it's read from a file next to the
+// ROM with the same name plus `.expect`, with one sub-test per line:
+//
+//     <name> <bank:addr>=<value>
+//
+// e.g. for the ADC test, CPUTest/CPU/ADC/CPUADC.sfc.expect might have
+//
+//     adc8-binary 7E:0010=01
+//     adc16-decimal 7E:0011=01
+//
+// which is the same address and value format as --expect.
+
+#![cfg(feature = "frontend")]
+
+use std::path::Path;
+
+use snesemu::frontend::options::Options;
+use snesemu::frontend::session::{self, Session, Stop};
+
+// The ROMs to run, relative to SNES_TEST_ROMS, and how many frames they
+// need to finish.
+const TEST_ROMS: &[(&str, u32)] = &[("CPUTest/CPU/ADC/CPUADC.sfc", 60)];
+
+// Whether a sub-test passed, by name.
+type Results = Vec<(String, bool)>;
+
+// Runs the ROM for the given number of frames, then checks each sub-test.
+fn run_test_rom(rom: &Path, frames: u32, subtests: &[(&str, &str)]) -> Results {
+    let log = std::env::temp_dir().join(format!(
+        "snesemu-test-rom-{}-{}.log",
+        rom.file_stem().unwrap().to_string_lossy(),
+        std::process::id()
+    ));
+
+    let mut args = vec![
+        rom.to_str().unwrap().to_string(),
+        "--run-for".into(),
+        format!("{}f", frames),
+        "--log".into(),
+        log.to_str().unwrap().into(),
+        "--trace-mode".into(),
+        "off".into(),
+        "--no-sram".into(),
+    ];
+
+    for (_, expectation) in subtests {
+        args.extend(["--expect".into(), expectation.to_string()]);
+    }
+
+    let options = Options::parse(args.into_iter())
+        .unwrap_or_else(|e| panic!("bad expectation for {}: {}", rom.display(), e));
+    let expectations = options.expectations.clone();
+
+    let mut emulator = session::read_rom(&options)
+        .and_then(|rom| session::prepare(&options, rom))
+        .unwrap_or_else(|e| panic!("couldn't start {}: {}", rom.display(), e));
+    let mut session = Session::new(options, &mut emulator).unwrap();
+
+    let stop = session.run(&mut emulator);
+    assert!(
+        matches!(stop, Some(Stop::FrameLimit)),
+        "{} didn't run for {} frames",
+        rom.display(),
+        frames
+    );
+
+    let results = subtests
+        .iter()
+        .zip(&expectations)
+        .map(|(&(name, _), &(addr, expected))| {
+            (name.to_string(), emulator.mmu.peek_u8(addr) == expected)
+        })
+        .collect();
+
+    session.finish(&mut emulator, stop);
+    let _ = std::fs::remove_file(&log);
+
+    results
+}
+
+#[test]
+fn test_roms() {
+    let Some(dir) = std::env::var_os("SNES_TEST_ROMS") else {
+        eprintln!("SNES_TEST_ROMS isn't set, skipping the test ROMs");
+        return;
+    };
+
+    let mut failed = Vec::new();
+
+    for &(path, frames) in TEST_ROMS {
+        let rom = Path::new(&dir).join(path);
+
+        let expect_path = format!("{}.expect", rom.display());
+        let text = std::fs::read_to_string(&expect_path)
+            .unwrap_or_else(|e| panic!("couldn't read {}: {}", expect_path, e));
+
+        let subtests: Vec<(&str, &str)> = text
+            .lines()
+            .filter_map(|line| line.trim().split_once(' '))
+            .collect();
+
+        for (name, passed) in run_test_rom(&rom, frames, &subtests) {
+            eprintln!(
+                "{} {}: {}",
+                if passed { "PASS" } else { "FAIL" },
+                path,
+                name
+            );
+
+            if !passed {
+                failed.push(format!("{}: {}", path, name));
+            }
+        }
+    }
+
+    assert!(failed.is_empty(), "failed: {}", failed.join(", "));
+}
+
+// The harness itself, on a ROM that passes one sub-test and fails another.
+#[test]
+fn reports_each_subtest() {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x10, 0x00, // STA $0010
+        0xA9, 0xFF,       // LDA #$FF
+        0x8D, 0x11, 0x00, // STA $0011
+        0x80, 0xFE,       // BRA *
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    let path = std::env::temp_dir().join(format!("snesemu-subtests-{}.sfc", std::process::id()));
+    std::fs::write(&path, rom).unwrap();
+
+    let results = run_test_rom(
+        &path,
+        2,
+        &[("first", "7E:0010=01"), ("second", "7E:0011=01")],
+    );
+
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        results,
+        [("first".to_string(), true), ("second".to_string(), false)]
+    );
+}
@@ -0,0 +1,25 @@
+// The panic hook is shared by the whole process, so this gets a test binary
+// of its own rather than swap it out while other tests are panicking.
+
+#![cfg(feature = "frontend")]
+
+use snesemu::frontend::trace::{read_log, TraceWriter};
+
+#[test]
+fn stream_is_flushed_on_panic() {
+    let path = std::env::temp_dir().join(format!("snesemu-panic-{}.log", std::process::id()));
+    let writer = TraceWriter::create(&path, false).unwrap();
+
+    writer.install_panic_hook();
+    writer.write_line("before the panic").unwrap();
+
+    let result = std::panic::catch_unwind(|| panic!("emulator crashed"));
+    assert!(result.is_err());
+
+    // The writer is still alive, so only the hook can have flushed it.
+    let log = read_log(&path).unwrap();
+    drop(writer);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(log, "before the panic\n");
+}
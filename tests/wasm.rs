@@ -0,0 +1,72 @@
+// Runs a few frames through the browser-facing API. Natively these are plain
+// tests, and under wasm-pack they run in wasm32:
+//
+//     wasm-pack test --node -- --features wasm
+
+#![cfg(feature = "wasm")]
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::wasm_bindgen_test as test;
+
+use snesemu::wasm::Snes;
+
+// Turns the screen on with the backdrop in pure blue, then copies the first
+// pad's high byte to $10 over and over.
+fn rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let code = [
+        0x18,             // CLC
+        0xFB,             // XCE
+        0xE2, 0x20,       // SEP #$20
+        0x9C, 0x21, 0x21, // STZ $2121
+        0x9C, 0x22, 0x21, // STZ $2122
+        0xA9, 0x7C,       // LDA #$7C
+        0x8D, 0x22, 0x21, // STA $2122
+        0xA9, 0x0F,       // LDA #$0F
+        0x8D, 0x00, 0x21, // STA $2100
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x00, 0x42, // STA $4200
+        // loop:
+        0xAD, 0x19, 0x42, // LDA $4219
+        0x85, 0x10,       // STA $10
+        0x80, 0xF9,       // BRA loop
+    ];
+
+    let mut rom = vec![0; 0x8000];
+    rom[..code.len()].copy_from_slice(&code);
+    rom[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+
+    rom
+}
+
+#[test]
+fn rejects_small_roms() {
+    assert!(Snes::new(&[]).is_err());
+    assert!(Snes::new(&rom()[..0x4000]).is_err());
+}
+
+#[test]
+fn runs_frames() {
+    let mut snes = Snes::new(&rom()).unwrap();
+    snes.set_input(0x1000);
+
+    for frame in 1..=3 {
+        snes.run_frame();
+        assert_eq!(snes.frame(), frame);
+    }
+
+    // The auto-read has picked up Start, in the high byte.
+    assert_eq!(snes.peek(0x7E_0010), 0x10);
+
+    let framebuffer = snes.framebuffer().0;
+    assert_eq!(
+        framebuffer.len(),
+        (Snes::width() * Snes::height() * 4) as usize
+    );
+    assert_eq!((Snes::width(), Snes::height()), (256, 224));
+
+    // Every pixel is the backdrop.
+    for pixel in framebuffer.chunks_exact(4) {
+        assert_eq!(pixel, [0, 0, 255, 255]);
+    }
+}